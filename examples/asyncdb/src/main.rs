@@ -13,6 +13,42 @@ async fn main() {
     let r = adb.get("Hello".as_bytes().to_owned()).await;
     assert_eq!(r, Ok(Some("World".as_bytes().to_owned())));
 
+    // Iterators return their entries in chunks.
+    adb.put("Hi".as_bytes().to_owned(), "There".as_bytes().to_owned())
+        .await
+        .expect("put()");
+    let iter = adb.new_iter().await.expect("new_iter()");
+    let entries = adb.next_many(iter, 10).await.expect("next_many()");
+    assert_eq!(
+        entries
+            .iter()
+            .map(|(k, _)| k.as_slice())
+            .collect::<Vec<_>>(),
+        vec!["Hello".as_bytes(), "Hi".as_bytes()]
+    );
+    assert!(adb
+        .next_many(iter, 10)
+        .await
+        .expect("next_many()")
+        .is_empty());
+
+    // They can be moved, and go backwards too.
+    adb.seek(iter, "Hi".as_bytes().to_owned())
+        .await
+        .expect("seek()");
+    let entries = adb.prev_many(iter, 10).await.expect("prev_many()");
+    assert_eq!(
+        entries
+            .iter()
+            .map(|(k, _)| k.as_slice())
+            .collect::<Vec<_>>(),
+        vec!["Hello".as_bytes()]
+    );
+    adb.seek_to_last(iter).await.expect("seek_to_last()");
+    let entries = adb.prev_many(iter, 1).await.expect("prev_many()");
+    assert_eq!(entries[0].0, "Hi".as_bytes());
+    adb.drop_iter(iter).await.expect("drop_iter()");
+
     let snapshot = adb.get_snapshot().await.expect("get_snapshot()");
 
     adb.delete("Hello".as_bytes().to_owned())
@@ -1,6 +1,6 @@
 extern crate rusty_leveldb;
 
-use rusty_leveldb::{compressor, CompressorId, LdbIterator, Options, DB};
+use rusty_leveldb::{compressor, verify_wal, CompressorId, LdbIterator, Options, DB};
 
 use std::env::args;
 use std::io::{self, Write};
@@ -8,14 +8,15 @@ use std::iter::FromIterator;
 
 fn get(db: &mut DB, k: &str) {
     match db.get(k.as_bytes()) {
-        Some(v) => {
+        Ok(Some(v)) => {
             if let Ok(s) = String::from_utf8(v.clone()) {
                 eprintln!("{} => {}", k, s);
             } else {
                 eprintln!("{} => {:?}", k, v);
             }
         }
-        None => eprintln!("{} => <not found>", k),
+        Ok(None) => eprintln!("{} => <not found>", k),
+        Err(e) => eprintln!("{} => error: {}", k, e),
     }
 }
 
@@ -46,15 +47,42 @@ fn compact(db: &mut DB, from: &str, to: &str) {
     db.compact_range(from.as_bytes(), to.as_bytes()).unwrap();
 }
 
+fn verify_log(path: &str) {
+    let report = verify_wal(path, &Options::default()).unwrap();
+    println!(
+        "{}: {} bytes, {} records ({} bytes) in {} fragments",
+        path, report.file_size, report.records, report.record_bytes, report.fragments
+    );
+    for d in &report.damaged {
+        println!(
+            "damaged: offset {}, {} bytes dropped: {}",
+            d.offset, d.bytes, d.reason
+        );
+    }
+    if report.truncated_tail {
+        println!("log ends with an incomplete record");
+    }
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     let args = Vec::from_iter(args());
 
     if args.len() < 2 {
         panic!(
-            "Usage: {} [get|put/set|delete|iter|compact] [key|from] [val|to]",
+            "Usage: {} [get|put/set|delete|iter|compact|verify-wal] [key|from|file] [val|to]",
             args[0]
         );
     }
+    if args[1] == "verify-wal" {
+        if args.len() < 3 {
+            panic!("Usage: {} verify-wal file", args[0]);
+        }
+        verify_log(&args[2]);
+        return;
+    }
 
     let mut opt = Options::default();
     opt.reuse_logs = false;
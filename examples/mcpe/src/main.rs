@@ -65,6 +65,6 @@ fn main() {
     let opt = mcpe_options(compression_level);
     let mut db = DB::open(path, opt).unwrap();
     db.put(b"~local_player", b"NBT data goes here").unwrap();
-    let value = db.get(b"~local_player").unwrap();
+    let value = db.get(b"~local_player").unwrap().unwrap();
     assert_eq!(&value, b"NBT data goes here")
 }
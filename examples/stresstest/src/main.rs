@@ -34,7 +34,7 @@ fn read(db: &mut DB, n: usize) -> usize {
     for _ in 0..n {
         let k = gen_string(KEY_LEN);
 
-        if let Some(_) = db.get(k.as_bytes()) {
+        if let Ok(Some(_)) = db.get(k.as_bytes()) {
             succ += 1;
         }
     }
@@ -7,7 +7,7 @@ use std::path::Path;
 
 fn update_count(w: &str, db: &mut leveldb::DB) -> Option<()> {
     let mut count: usize = 0;
-    if let Some(v) = db.get(w.as_bytes()) {
+    if let Some(v) = db.get(w.as_bytes()).ok()? {
         let s = String::from_utf8(v).unwrap();
         count = usize::from_str_radix(&s, 10).unwrap();
     }
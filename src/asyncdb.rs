@@ -1,30 +1,197 @@
+use std::cmp::Ordering;
 use std::collections::hash_map::HashMap;
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::iter::FromIterator;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use crate::{Options, Result, Status, StatusCode, WriteBatch, DB};
+use crate::db_iter::DBIterator;
+use crate::key_types::ValueType;
+use crate::memory_usage::MemoryUsage;
+use crate::metrics;
+use crate::types::current_key_val;
+use crate::{
+    Histogram, KeyPresence, LdbIterator, MutexDB, Options, ReadOptions, Result, Status, StatusCode,
+    WriteBatch, DB,
+};
 
+use futures_core::Stream;
 use tokio::sync::mpsc;
-use tokio::sync::oneshot;
-use tokio::task::{spawn_blocking, JoinHandle};
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+use tokio::sync::{oneshot, watch};
 
 const CHANNEL_BUFFER_SIZE: usize = 32;
+/// How often the database thread checks for new requests while sync writes wait for their group
+/// commit.
+const GROUP_COMMIT_POLL_INTERVAL: Duration = Duration::from_micros(100);
 
 #[derive(Clone, Copy)]
 pub struct SnapshotRef(usize);
 
+/// IteratorRef refers to an iterator kept by the database thread (see `AsyncDB::new_iter()`).
+#[derive(Clone, Copy)]
+pub struct IteratorRef(usize);
+
+/// AsyncDbOptions configures the queue of requests to the database thread (see
+/// `AsyncDB::with_options()`).
+#[derive(Clone, Copy, Debug)]
+pub struct AsyncDbOptions {
+    /// How many requests can be queued before senders have to wait. None makes the queue
+    /// unbounded: Requests are never delayed, but the queue can grow without limit if the
+    /// database can't keep up.
+    pub buffer_size: Option<usize>,
+    /// How long a request waits for space in a full queue before failing with
+    /// `StatusCode::WouldBlock`. None waits indefinitely. Timeouts are measured by the
+    /// `BlockingSpawner` (see `BlockingSpawner::sleep()`).
+    pub send_timeout: Option<Duration>,
+    /// How long a request waits for its response, including the time it is queued, before
+    /// failing with `StatusCode::AsyncError`, e.g. while the database thread is busy with a long
+    /// compaction. The request isn't withdrawn: It may still be carried out later. None waits
+    /// indefinitely. Like send_timeout, this is measured by the `BlockingSpawner`.
+    pub request_timeout: Option<Duration>,
+    /// Whether `get()` and `multi_get()` are served by a reader thread besides the database
+    /// thread. It shares the database through a lock (see `MutexDB`), and takes reads from a
+    /// queue of its own, so reads don't wait behind writes queued before them, only for the
+    /// operation that holds the lock. Reads may then overtake writes that were sent, but not yet
+    /// answered. This doesn't make reads run concurrently with each other or with writes, as the
+    /// database can only be used by one thread at a time. If false, all requests are handled by
+    /// the database thread in the order they're sent.
+    pub read_thread: bool,
+}
+
+impl Default for AsyncDbOptions {
+    fn default() -> AsyncDbOptions {
+        AsyncDbOptions {
+            buffer_size: Some(CHANNEL_BUFFER_SIZE),
+            send_timeout: None,
+            request_timeout: None,
+            read_thread: false,
+        }
+    }
+}
+
 /// A request sent to the database thread.
 enum Request {
     Close,
-    Put { key: Vec<u8>, val: Vec<u8> },
-    Delete { key: Vec<u8> },
-    Write { batch: WriteBatch, sync: bool },
+    Put {
+        key: Vec<u8>,
+        val: Vec<u8>,
+    },
+    TryPut {
+        key: Vec<u8>,
+        val: Vec<u8>,
+        /// Bounds the time spent on compactions before the write, including the time the request
+        /// was queued (see `DB::put_timeout()`).
+        timeout: Duration,
+    },
+    PutMany {
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    Write {
+        batch: WriteBatch,
+        sync: bool,
+    },
     Flush,
-    GetAt { snapshot: SnapshotRef, key: Vec<u8> },
-    Get { key: Vec<u8> },
+    FlushMemtable,
+    WaitForCompaction,
+    GetAt {
+        snapshot: SnapshotRef,
+        key: Vec<u8>,
+        ropt: Box<ReadOptions>,
+    },
+    Get {
+        key: Vec<u8>,
+        ropt: Box<ReadOptions>,
+    },
+    MultiGet {
+        keys: Vec<Vec<u8>>,
+    },
+    ContainsKey {
+        key: Vec<u8>,
+    },
+    Update {
+        key: Vec<u8>,
+        f: UpdateFn,
+    },
+    CompareAndSwap {
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    },
     GetSnapshot,
-    DropSnapshot { snapshot: SnapshotRef },
-    CompactRange { from: Vec<u8>, to: Vec<u8> },
+    DropSnapshot {
+        snapshot: SnapshotRef,
+    },
+    CompactRange {
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+    CompactAll,
+    Checkpoint {
+        dir: PathBuf,
+    },
+    DeletePrefix {
+        prefix: Vec<u8>,
+    },
+    DeleteRange {
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+    Subscribe {
+        prefix: Vec<u8>,
+        send: mpsc::UnboundedSender<WriteEvent>,
+    },
+    ApproximateSizes {
+        ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    },
+    GetProperty {
+        name: String,
+    },
+    MemoryUsage,
+    NewIter {
+        snapshot: Option<SnapshotRef>,
+    },
+    NextMany {
+        iter: IteratorRef,
+        n: usize,
+        /// Entries at or after this key are left out.
+        to: Option<Vec<u8>>,
+    },
+    PrevMany {
+        iter: IteratorRef,
+        n: usize,
+    },
+    Seek {
+        iter: IteratorRef,
+        to: SeekTarget,
+    },
+    DropIter {
+        iter: IteratorRef,
+    },
+    PauseBackgroundWork,
+    ContinueBackgroundWork,
+    /// Sent periodically to wake the database thread for time-based work.
+    Tick,
+}
+
+/// UpdateFn computes the new value of a key from its current one (see `AsyncDB::update()`).
+type UpdateFn = Box<dyn FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>> + Send>;
+
+/// Where `Request::Seek` moves an iterator to.
+enum SeekTarget {
+    First,
+    Last,
+    Key(Vec<u8>),
 }
 
 /// A response received from the database thread.
@@ -32,40 +199,793 @@ enum Response {
     OK,
     Error(Status),
     Value(Option<Vec<u8>>),
+    Values(Vec<Option<Vec<u8>>>),
     Snapshot(SnapshotRef),
+    Iterator(IteratorRef),
+    Entries(Vec<(Vec<u8>, Vec<u8>)>),
+    Sizes(Vec<u64>),
+    Property(Option<String>),
+    MemoryUsage(MemoryUsage),
+    Presence(KeyPresence),
+    Swapped(bool),
+}
+
+impl Request {
+    /// name identifies the type of request in `AsyncMetrics::latencies`.
+    fn name(&self) -> &'static str {
+        match self {
+            Request::Close => "close",
+            Request::Put { .. } => "put",
+            Request::TryPut { .. } => "try_put",
+            Request::PutMany { .. } => "put_many",
+            Request::Delete { .. } => "delete",
+            Request::Write { .. } => "write",
+            Request::Flush => "flush",
+            Request::FlushMemtable => "flush_memtable",
+            Request::WaitForCompaction => "wait_for_compaction",
+            Request::GetAt { .. } => "get_at",
+            Request::Get { .. } => "get",
+            Request::MultiGet { .. } => "multi_get",
+            Request::ContainsKey { .. } => "contains_key",
+            Request::Update { .. } => "update",
+            Request::CompareAndSwap { .. } => "compare_and_swap",
+            Request::GetSnapshot => "get_snapshot",
+            Request::DropSnapshot { .. } => "drop_snapshot",
+            Request::CompactRange { .. } => "compact_range",
+            Request::CompactAll => "compact_all",
+            Request::Checkpoint { .. } => "checkpoint",
+            Request::DeletePrefix { .. } => "delete_prefix",
+            Request::DeleteRange { .. } => "delete_range",
+            Request::Subscribe { .. } => "subscribe",
+            Request::ApproximateSizes { .. } => "approximate_sizes",
+            Request::GetProperty { .. } => "get_property",
+            Request::MemoryUsage => "memory_usage",
+            Request::NewIter { .. } => "new_iter",
+            Request::NextMany { .. } => "next_many",
+            Request::PrevMany { .. } => "prev_many",
+            Request::Seek { .. } => "seek",
+            Request::DropIter { .. } => "drop_iter",
+            Request::PauseBackgroundWork => "pause_background_work",
+            Request::ContinueBackgroundWork => "continue_background_work",
+            Request::Tick => "tick",
+        }
+    }
 }
 
 /// Contains both a request and a back-channel for the reply.
 struct Message {
     req: Request,
-    resp_channel: oneshot::Sender<Response>,
+    resp_channel: Responder,
+    queued: Instant,
+}
+
+impl Message {
+    /// detached returns a message for a request whose response is of no interest.
+    fn detached(req: Request) -> Message {
+        let (ch, _) = oneshot::channel();
+        Message {
+            req,
+            resp_channel: Responder { ch, metrics: None },
+            queued: Instant::now(),
+        }
+    }
+}
+
+/// Responder is the back-channel of a request. It counts the responses that the sender of the
+/// request no longer waited for.
+struct Responder {
+    ch: oneshot::Sender<Response>,
+    /// None if the response is of no interest.
+    metrics: Option<Arc<Mutex<AsyncMetrics>>>,
+}
+
+impl Responder {
+    fn send(self, r: Response) -> std::result::Result<(), Response> {
+        let result = self.ch.send(r);
+        if let (Err(_), Some(m)) = (&result, self.metrics) {
+            m.lock().unwrap().dropped_responses += 1;
+        }
+        result
+    }
+}
+
+/// AsyncMetrics describes the requests handled by the database thread and the reader thread
+/// (see `AsyncDB::metrics()`), e.g. to find out whether the thread is a bottleneck: Then requests
+/// wait in the queue for longer than they take to handle.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AsyncMetrics {
+    /// The number of requests that were queued when the database thread last took one.
+    pub queue_depth: usize,
+    pub max_queue_depth: usize,
+    /// The number of requests handled so far.
+    pub requests: u64,
+    /// How long requests waited in the queue, in microseconds.
+    pub queue_wait: Histogram,
+    /// How long the database thread took to handle requests, by type of request (e.g. `get`),
+    /// in microseconds.
+    pub latencies: BTreeMap<&'static str, Histogram>,
+    /// The number of responses that were discarded because their receiver was gone, e.g. after
+    /// a request timed out (see `AsyncDbOptions::request_timeout`) or its future was dropped.
+    pub dropped_responses: u64,
+}
+
+impl AsyncMetrics {
+    fn record(&mut self, req: &'static str, queue_depth: usize, queued: Instant, start: Instant) {
+        self.queue_depth = queue_depth;
+        self.max_queue_depth = self.max_queue_depth.max(queue_depth);
+        self.requests += 1;
+        self.queue_wait
+            .add(start.duration_since(queued).as_micros() as u64);
+        self.latencies
+            .entry(req)
+            .or_default()
+            .add(start.elapsed().as_micros() as u64);
+    }
+}
+
+/// AsyncSnapshot is a snapshot of an `AsyncDB` (see `AsyncDB::snapshot()`), which is dropped
+/// in the database thread once the guard is dropped. This is best-effort: The drop request is
+/// queued without waiting, or, if the queue is full, from a task spawned on the current tokio
+/// runtime. Outside of a runtime, the snapshot is kept until the database is closed.
+pub struct AsyncSnapshot {
+    db: AsyncDB,
+    snapshot: SnapshotRef,
+}
+
+impl AsyncSnapshot {
+    /// get_at returns the value of key at the snapshot.
+    pub async fn get_at(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.db.get_at(self.snapshot, key).await
+    }
+
+    /// snapshot_ref returns the reference to the snapshot, e.g. for `AsyncDB::new_iter_at()`.
+    /// It is valid as long as the guard is.
+    pub fn snapshot_ref(&self) -> SnapshotRef {
+        self.snapshot
+    }
+}
+
+impl Drop for AsyncSnapshot {
+    fn drop(&mut self) {
+        self.db.send.send_from_drop(Request::DropSnapshot {
+            snapshot: self.snapshot,
+        });
+    }
+}
+
+/// The number of entries `Scan` fetches from the database thread at a time.
+const SCAN_CHUNK_SIZE: usize = 128;
+
+type ChunkFuture =
+    Pin<Box<dyn Future<Output = Result<(IteratorRef, Vec<(Vec<u8>, Vec<u8>)>)>> + Send>>;
+
+/// Scan is the stream of entries returned by `AsyncDB::scan()`. It fetches the entries from an
+/// iterator kept by the database thread in chunks, while it is polled, and drops the iterator
+/// when it is dropped (best-effort, like `AsyncSnapshot`).
+pub struct Scan {
+    db: AsyncDB,
+    from: Vec<u8>,
+    to: Option<Vec<u8>>,
+    iter: Option<IteratorRef>,
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    pending: Option<ChunkFuture>,
+    done: bool,
+}
+
+impl Scan {
+    fn fetch(&self) -> ChunkFuture {
+        let db = self.db.clone();
+        let (iter, from, to) = (self.iter, self.from.clone(), self.to.clone());
+        Box::pin(async move {
+            let iter = match iter {
+                Some(iter) => iter,
+                None => {
+                    let iter = db.new_iter().await?;
+                    db.seek(iter, from).await?;
+                    iter
+                }
+            };
+            Ok((iter, db.next_chunk(iter, SCAN_CHUNK_SIZE, to).await?))
+        })
+    }
+}
+
+impl Stream for Scan {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let scan = self.get_mut();
+        loop {
+            if let Some(entry) = scan.entries.pop_front() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+            if scan.done {
+                return Poll::Ready(None);
+            }
+            let fetch = match scan.pending.as_mut() {
+                Some(fetch) => fetch,
+                None => scan.pending.insert(scan.fetch()),
+            };
+            let r = match fetch.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(r) => r,
+            };
+            scan.pending = None;
+            match r {
+                Ok((iter, entries)) => {
+                    scan.iter = Some(iter);
+                    scan.done = entries.len() < SCAN_CHUNK_SIZE;
+                    scan.entries.extend(entries);
+                }
+                Err(e) => {
+                    scan.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Scan {
+    fn drop(&mut self) {
+        if let Some(iter) = self.iter {
+            self.db.send.send_from_drop(Request::DropIter { iter });
+        }
+    }
+}
+
+/// WriteEvent is a write applied to the database, as reported to subscribers (see
+/// `AsyncDB::subscribe()`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteEvent {
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    /// All keys starting with prefix were deleted (see `AsyncDB::delete_prefix()`).
+    DeletePrefix {
+        prefix: Vec<u8>,
+    },
+    /// All keys in [from; to) were deleted (see `AsyncDB::delete_range()`).
+    DeleteRange {
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+}
+
+impl WriteEvent {
+    /// matches returns true if the event may affect keys starting with prefix.
+    fn matches(&self, prefix: &[u8]) -> bool {
+        match self {
+            WriteEvent::Put { key, .. } | WriteEvent::Delete { key } => key.starts_with(prefix),
+            WriteEvent::DeletePrefix { prefix: p } => {
+                p.starts_with(prefix) || prefix.starts_with(p)
+            }
+            WriteEvent::DeleteRange { from, to } => {
+                (from.as_slice() < prefix || from.starts_with(prefix)) && to.as_slice() > prefix
+            }
+        }
+    }
+}
+
+/// Subscription is the stream of write events returned by `AsyncDB::subscribe()`. It ends when
+/// the database is closed.
+pub struct Subscription {
+    recv: mpsc::UnboundedReceiver<WriteEvent>,
+}
+
+impl Stream for Subscription {
+    type Item = WriteEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().recv.poll_recv(cx)
+    }
+}
+
+/// A subscription, as kept by the database thread.
+struct Subscriber {
+    prefix: Vec<u8>,
+    send: mpsc::UnboundedSender<WriteEvent>,
+}
+
+/// publish sends the events to the subscribers whose prefix they match, and forgets the
+/// subscribers whose `Subscription` was dropped.
+fn publish<I: IntoIterator<Item = WriteEvent>>(subscribers: &mut Vec<Subscriber>, events: I) {
+    subscribers.retain(|s| !s.send.is_closed());
+    for ev in events {
+        for s in subscribers.iter().filter(|s| ev.matches(&s.prefix)) {
+            s.send.send(ev.clone()).ok();
+        }
+    }
+}
+
+/// batch_events returns the events of a write batch.
+fn batch_events(batch: &WriteBatch) -> Vec<WriteEvent> {
+    batch
+        .records()
+        .map(|(typ, k, v)| match typ {
+            ValueType::TypeValue => WriteEvent::Put {
+                key: k.to_vec(),
+                value: v.to_vec(),
+            },
+            ValueType::TypeDeletion => WriteEvent::Delete { key: k.to_vec() },
+            ValueType::TypeRangeDeletion => WriteEvent::DeleteRange {
+                from: k.to_vec(),
+                to: v.to_vec(),
+            },
+        })
+        .collect()
+}
+
+/// AsyncWriteBatch collects puts and deletes, to be written atomically with `commit()`. It can
+/// also be collected from an iterator of keys and values, None meaning a delete.
+///
+/// ```no_run
+/// # async fn f(db: rusty_leveldb::AsyncDB) -> rusty_leveldb::Result<()> {
+/// use rusty_leveldb::AsyncWriteBatch;
+///
+/// let mut batch = AsyncWriteBatch::new();
+/// batch.put(b"a", b"1").delete(b"b");
+/// batch.commit(&db, true).await?;
+///
+/// let batch: AsyncWriteBatch = vec![(b"c", Some(b"2")), (b"d", None)].into_iter().collect();
+/// batch.commit(&db, false).await
+/// # }
+/// ```
+pub struct AsyncWriteBatch {
+    batch: WriteBatch,
+}
+
+impl AsyncWriteBatch {
+    pub fn new() -> AsyncWriteBatch {
+        AsyncWriteBatch {
+            batch: WriteBatch::new(),
+        }
+    }
+
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, val: V) -> &mut Self {
+        self.batch.put(key.as_ref(), val.as_ref());
+        self
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> &mut Self {
+        self.batch.delete(key.as_ref());
+        self
+    }
+
+    /// delete_range deletes all keys in [from; to) (see `DB::delete_range()`).
+    pub fn delete_range<K: AsRef<[u8]>>(&mut self, from: K, to: K) -> &mut Self {
+        self.batch.delete_range(from.as_ref(), to.as_ref());
+        self
+    }
+
+    /// len returns the number of puts, deletes and range deletions in the batch.
+    pub fn len(&self) -> usize {
+        self.batch.count() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// commit writes the batch to db in one request (see `AsyncDB::write()`).
+    pub async fn commit(self, db: &AsyncDB, sync: bool) -> Result<()> {
+        db.write(self.batch, sync).await
+    }
+}
+
+impl Default for AsyncWriteBatch {
+    fn default() -> AsyncWriteBatch {
+        AsyncWriteBatch::new()
+    }
+}
+
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> Extend<(K, Option<V>)> for AsyncWriteBatch {
+    fn extend<I: IntoIterator<Item = (K, Option<V>)>>(&mut self, entries: I) {
+        for (k, v) in entries {
+            match v {
+                Some(v) => self.put(k, v),
+                None => self.delete(k),
+            };
+        }
+    }
+}
+
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> FromIterator<(K, Option<V>)> for AsyncWriteBatch {
+    fn from_iter<I: IntoIterator<Item = (K, Option<V>)>>(entries: I) -> AsyncWriteBatch {
+        let mut batch = AsyncWriteBatch::new();
+        batch.extend(entries);
+        batch
+    }
+}
+
+impl<K, V, I> From<I> for AsyncWriteBatch
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+    I: Iterator<Item = (K, Option<V>)>,
+{
+    fn from(entries: I) -> AsyncWriteBatch {
+        entries.collect()
+    }
+}
+
+impl From<AsyncWriteBatch> for WriteBatch {
+    fn from(batch: AsyncWriteBatch) -> WriteBatch {
+        batch.batch
+    }
+}
+
+/// The sending side of the request queue, which is bounded or not (see
+/// `AsyncDbOptions::buffer_size`).
+#[derive(Clone)]
+enum RequestSender {
+    Bounded(mpsc::Sender<Message>),
+    Unbounded(mpsc::UnboundedSender<Message>),
+}
+
+enum WeakRequestSender {
+    Bounded(mpsc::WeakSender<Message>),
+    Unbounded(mpsc::WeakUnboundedSender<Message>),
+}
+
+enum RequestReceiver {
+    Bounded(mpsc::Receiver<Message>),
+    Unbounded(mpsc::UnboundedReceiver<Message>),
+}
+
+fn request_queue(buffer_size: Option<usize>) -> (RequestSender, RequestReceiver) {
+    match buffer_size {
+        Some(n) => {
+            let (send, recv) = mpsc::channel(n);
+            (RequestSender::Bounded(send), RequestReceiver::Bounded(recv))
+        }
+        None => {
+            let (send, recv) = mpsc::unbounded_channel();
+            (
+                RequestSender::Unbounded(send),
+                RequestReceiver::Unbounded(recv),
+            )
+        }
+    }
+}
+
+impl RequestSender {
+    /// send waits for space in the queue, until sleep completes, if given.
+    async fn send(&self, m: Message, sleep: Option<Sleep>) -> Result<()> {
+        let r = match (self, sleep) {
+            (RequestSender::Unbounded(send), _) => send.send(m).map_err(|e| e.to_string()),
+            (RequestSender::Bounded(send), None) => send.send(m).await.map_err(|e| e.to_string()),
+            (RequestSender::Bounded(send), Some(sleep)) => {
+                match timeout(sleep, send.send(m)).await {
+                    Some(r) => r.map_err(|e| e.to_string()),
+                    None => {
+                        return Err(Status::new(
+                            StatusCode::WouldBlock,
+                            "AsyncDB request queue is full",
+                        ))
+                    }
+                }
+            }
+        };
+        r.map_err(|e| Status {
+            code: StatusCode::AsyncError,
+            err: e,
+        })
+    }
+
+    /// send_from_drop sends a request whose response is of no interest, without waiting, for
+    /// use in `Drop` implementations. If the queue is full, the request is sent from a task
+    /// spawned on the current tokio runtime, if any.
+    fn send_from_drop(&self, req: Request) {
+        let message = Message::detached(req);
+        if let Err(TrySendError::Full(message)) = self.try_send(message) {
+            if let Ok(rt) = tokio::runtime::Handle::try_current() {
+                let send = self.clone();
+                rt.spawn(async move { send.send(message, None).await });
+            }
+        }
+    }
+
+    fn try_send(&self, m: Message) -> std::result::Result<(), TrySendError<Message>> {
+        match self {
+            RequestSender::Bounded(send) => send.try_send(m),
+            RequestSender::Unbounded(send) => send.send(m).map_err(|e| TrySendError::Closed(e.0)),
+        }
+    }
+
+    fn downgrade(&self) -> WeakRequestSender {
+        match self {
+            RequestSender::Bounded(send) => WeakRequestSender::Bounded(send.downgrade()),
+            RequestSender::Unbounded(send) => WeakRequestSender::Unbounded(send.downgrade()),
+        }
+    }
+}
+
+impl WeakRequestSender {
+    fn upgrade(&self) -> Option<RequestSender> {
+        match self {
+            WeakRequestSender::Bounded(send) => send.upgrade().map(RequestSender::Bounded),
+            WeakRequestSender::Unbounded(send) => send.upgrade().map(RequestSender::Unbounded),
+        }
+    }
+}
+
+impl RequestReceiver {
+    fn try_recv(&mut self) -> std::result::Result<Message, TryRecvError> {
+        match self {
+            RequestReceiver::Bounded(recv) => recv.try_recv(),
+            RequestReceiver::Unbounded(recv) => recv.try_recv(),
+        }
+    }
+
+    fn blocking_recv(&mut self) -> Option<Message> {
+        match self {
+            RequestReceiver::Bounded(recv) => recv.blocking_recv(),
+            RequestReceiver::Unbounded(recv) => recv.blocking_recv(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            RequestReceiver::Bounded(recv) => recv.len(),
+            RequestReceiver::Unbounded(recv) => recv.len(),
+        }
+    }
+
+    fn close(&mut self) {
+        match self {
+            RequestReceiver::Bounded(recv) => recv.close(),
+            RequestReceiver::Unbounded(recv) => recv.close(),
+        }
+    }
+}
+
+/// Sleep is a future that completes once a timeout has passed (see `BlockingSpawner::sleep()`).
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// BlockingSpawner provides what an `AsyncDB` needs from the runtime it is used in (see
+/// `AsyncDB::with_spawner()`): The database thread, and a timer. The channels used to talk to
+/// the database thread work with any executor, so this is all that's needed to use an `AsyncDB`
+/// outside of tokio.
+pub trait BlockingSpawner {
+    /// spawn_blocking runs f, which blocks until the database is closed, on a thread of its own
+    /// or one from a pool for blocking tasks.
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>);
+    /// sleep returns a future that completes after d. It is used for
+    /// `AsyncDbOptions::send_timeout` and `request_timeout`.
+    fn sleep(&self, d: Duration) -> Sleep;
+}
+
+/// TokioSpawner runs the database thread with `tokio::task::spawn_blocking()`, and uses tokio's
+/// timer. It needs to be used from within a tokio runtime, with the time driver enabled if
+/// timeouts are used.
+pub struct TokioSpawner;
+
+impl BlockingSpawner for TokioSpawner {
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        tokio::task::spawn_blocking(f);
+    }
+
+    fn sleep(&self, d: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(d))
+    }
+}
+
+/// ThreadSpawner runs the database thread on a new OS thread, and measures timeouts on a timer
+/// thread shared by all databases. It works with any executor.
+pub struct ThreadSpawner;
+
+impl BlockingSpawner for ThreadSpawner {
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        std::thread::spawn(f);
+    }
+
+    fn sleep(&self, d: Duration) -> Sleep {
+        let (send, recv) = oneshot::channel();
+        // If the timer thread is gone, the sleep never completes, like a timeout of None.
+        let _ = timer_thread()
+            .lock()
+            .unwrap()
+            .send((Instant::now() + d, send));
+        Box::pin(async move {
+            if recv.await.is_err() {
+                std::future::pending::<()>().await
+            }
+        })
+    }
+}
+
+type TimerQueue = std::sync::mpsc::Sender<(Instant, oneshot::Sender<()>)>;
+
+/// timer_thread returns the queue of the thread that completes the sleeps of `ThreadSpawner`,
+/// starting the thread on first use.
+fn timer_thread() -> &'static Mutex<TimerQueue> {
+    static TIMER: OnceLock<Mutex<TimerQueue>> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let (send, recv) = std::sync::mpsc::channel();
+        std::thread::spawn(move || run_timer(recv));
+        Mutex::new(send)
+    })
+}
+
+/// run_timer completes each sleep received from recv once its deadline has passed.
+fn run_timer(recv: std::sync::mpsc::Receiver<(Instant, oneshot::Sender<()>)>) {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    // Keyed by deadline and arrival, as deadlines may coincide.
+    let mut sleeps = BTreeMap::new();
+    let mut arrival = 0usize;
+    loop {
+        let now = Instant::now();
+        while let Some(entry) = sleeps.first_entry() {
+            let &(deadline, _) = entry.key();
+            if deadline > now {
+                break;
+            }
+            let done: oneshot::Sender<()> = entry.remove();
+            let _ = done.send(());
+        }
+        let next = match sleeps.keys().next() {
+            Some(&(deadline, _)) => recv.recv_timeout(deadline - now),
+            None => recv.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match next {
+            Ok((deadline, done)) => {
+                sleeps.insert((deadline, arrival), done);
+                arrival += 1;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
 }
 
-/// `AsyncDB` makes it easy to use LevelDB in a tokio runtime.
-/// The methods follow very closely the main API (see `DB` type). Iteration is not yet implemented.
+/// timeout is like `tokio::time::timeout()`, with sleep as the timer: It returns None if sleep
+/// completes before f.
+async fn timeout<F: Future>(sleep: Sleep, f: F) -> Option<F::Output> {
+    let mut sleep = sleep;
+    let mut f = std::pin::pin!(f);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(r) = f.as_mut().poll(cx) {
+            return Poll::Ready(Some(r));
+        }
+        sleep.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
+
+/// `AsyncDB` makes it easy to use LevelDB in a tokio runtime, or, with a different
+/// `BlockingSpawner`, in other async runtimes.
+/// The methods follow very closely the main API (see `DB` type). Iterators live in the database
+/// thread, and return their entries in chunks, in either direction (see `AsyncDB::next_many()`
+/// and `AsyncDB::prev_many()`).
+///
+/// Outside of tokio, an `AsyncSnapshot` dropped while the request queue is full isn't released
+/// until the database is closed.
 ///
-/// TODO: Make it work in other runtimes as well. This is a matter of adapting the blocking thread
-/// mechanism as well as the channel types.
+/// Once the last handle (including those held by snapshot guards and scans) is dropped, the
+/// database is closed in the background. Call `close()` to wait for it to be closed.
 #[derive(Clone)]
 pub struct AsyncDB {
-    jh: Arc<JoinHandle<()>>,
-    send: mpsc::Sender<Message>,
+    send: RequestSender,
+    /// The queue of the reader thread, if any (see `AsyncDbOptions::read_thread`).
+    read_send: Option<RequestSender>,
+    spawner: Arc<dyn BlockingSpawner + Send + Sync>,
+    send_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    /// Changes once the database thread has finished (see `AsyncDB::close()`).
+    done: watch::Receiver<()>,
+    metrics: Arc<Mutex<AsyncMetrics>>,
+    _closer: Arc<Closer>,
+}
+
+/// Closer sends `Request::Close` once the last `AsyncDB` handle is dropped.
+struct Closer(RequestSender);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.0.send_from_drop(Request::Close);
+    }
 }
 
 impl AsyncDB {
     /// Create a new or open an existing database.
     pub fn new<P: AsRef<Path>>(name: P, opts: Options) -> Result<AsyncDB> {
-        let db = DB::open(name, opts)?;
-        let (send, recv) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-        let jh = spawn_blocking(move || AsyncDB::run_server(db, recv));
+        AsyncDB::with_options(name, opts, AsyncDbOptions::default())
+    }
+
+    /// with_options is like `new()`, with a request queue configured by async_opts.
+    pub fn with_options<P: AsRef<Path>>(
+        name: P,
+        opts: Options,
+        async_opts: AsyncDbOptions,
+    ) -> Result<AsyncDB> {
+        AsyncDB::with_spawner(name, opts, async_opts, TokioSpawner)
+    }
+
+    /// with_spawner is like `with_options()`, but starts the database thread and measures
+    /// timeouts with spawner instead of tokio.
+    pub fn with_spawner<P: AsRef<Path>, S: BlockingSpawner + Send + Sync + 'static>(
+        name: P,
+        opts: Options,
+        async_opts: AsyncDbOptions,
+        spawner: S,
+    ) -> Result<AsyncDB> {
+        let tick_interval = [
+            opts.log_sync_interval,
+            opts.idle_compaction_delay,
+            opts.auto_snapshot_interval,
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied();
+        let group_commit_delay = opts.group_commit_delay;
+        let db = MutexDB::open(name, opts)?;
+        let (send, recv) = request_queue(async_opts.buffer_size);
+        let (done_send, done) = watch::channel(());
+        let metrics = Arc::new(Mutex::new(AsyncMetrics::default()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let read_send = if async_opts.read_thread {
+            let (read_send, read_recv) = request_queue(async_opts.buffer_size);
+            let db = db.clone();
+            let (closed, stats) = (closed.clone(), metrics.clone());
+            spawner.spawn_blocking(Box::new(move || {
+                AsyncDB::run_reader(db, read_recv, closed, stats)
+            }));
+            Some(read_send)
+        } else {
+            None
+        };
+        let stats = metrics.clone();
+        spawner.spawn_blocking(Box::new(move || {
+            AsyncDB::run_server(db, recv, group_commit_delay, closed, stats);
+            // The database has been closed at this point, or dropped unless the reader thread is
+            // still finishing.
+            drop(done_send);
+        }));
+        if let Some(interval) = tick_interval {
+            let send = send.downgrade();
+            std::thread::spawn(move || AsyncDB::run_ticker(send, interval));
+        }
         Ok(AsyncDB {
-            jh: Arc::new(jh),
+            _closer: Arc::new(Closer(send.clone())),
             send,
+            read_send,
+            spawner: Arc::new(spawner),
+            send_timeout: async_opts.send_timeout,
+            request_timeout: async_opts.request_timeout,
+            done,
+            metrics,
         })
     }
 
+    /// with_timeout returns a handle to the same database whose requests time out after timeout
+    /// instead (see `AsyncDbOptions::request_timeout`), e.g. for a single request:
+    /// `db.with_timeout(Some(Duration::from_secs(1))).get(key)`.
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> AsyncDB {
+        AsyncDB {
+            request_timeout: timeout,
+            ..self.clone()
+        }
+    }
+
+    /// metrics returns a snapshot of the metrics of the database thread. It is updated after
+    /// each request is answered, and doesn't wait for the database thread.
+    pub fn metrics(&self) -> AsyncMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    /// close closes the database (see `DB::close()`) and waits for the database thread to
+    /// finish. Requests sent afterwards, from this or other handles, fail.
     pub async fn close(&self) -> Result<()> {
         let r = self.process_request(Request::Close).await?;
+        let mut done = self.done.clone();
+        while done.changed().await.is_ok() {}
         match r {
             Response::OK => Ok(()),
             Response::Error(s) => Err(s),
@@ -87,8 +1007,14 @@ impl AsyncDB {
             }),
         }
     }
-    pub async fn delete(&self, key: Vec<u8>) -> Result<()> {
-        let r = self.process_request(Request::Delete { key }).await?;
+    /// put_many adds many entries in batches (see `DB::put_many()`), sending them to the database
+    /// thread in a single request.
+    pub async fn put_many<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(
+        &self,
+        pairs: I,
+    ) -> Result<()> {
+        let pairs = pairs.into_iter().collect();
+        let r = self.process_request(Request::PutMany { pairs }).await?;
         match r {
             Response::OK => Ok(()),
             Response::Error(s) => Err(s),
@@ -98,8 +1024,16 @@ impl AsyncDB {
             }),
         }
     }
-    pub async fn write(&self, batch: WriteBatch, sync: bool) -> Result<()> {
-        let r = self.process_request(Request::Write { batch, sync }).await?;
+    /// Like `put()`, but fails with `StatusCode::WouldBlock` instead of waiting if the request
+    /// queue is full or the write would stall on a memtable compaction.
+    pub async fn try_put(&self, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        let r = self
+            .try_process_request(Request::TryPut {
+                key,
+                val,
+                timeout: Duration::ZERO,
+            })
+            .await?;
         match r {
             Response::OK => Ok(()),
             Response::Error(s) => Err(s),
@@ -109,8 +1043,14 @@ impl AsyncDB {
             }),
         }
     }
-    pub async fn flush(&self) -> Result<()> {
-        let r = self.process_request(Request::Flush).await?;
+    /// put_timeout is the bounded-wait form of `put()`: It fails with `StatusCode::WouldBlock` if
+    /// the request queue stays full for timeout. What is left of timeout once the request is
+    /// taken from the queue bounds the compactions that the write waits for (see
+    /// `DB::write_timeout()`).
+    pub async fn put_timeout(&self, key: Vec<u8>, val: Vec<u8>, timeout: Duration) -> Result<()> {
+        let r = self
+            .send_request(Request::TryPut { key, val, timeout }, Some(timeout))
+            .await?;
         match r {
             Response::OK => Ok(()),
             Response::Error(s) => Err(s),
@@ -120,10 +1060,10 @@ impl AsyncDB {
             }),
         }
     }
-    pub async fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        let r = self.process_request(Request::Get { key }).await?;
+    pub async fn delete(&self, key: Vec<u8>) -> Result<()> {
+        let r = self.process_request(Request::Delete { key }).await?;
         match r {
-            Response::Value(v) => Ok(v),
+            Response::OK => Ok(()),
             Response::Error(s) => Err(s),
             _ => Err(Status {
                 code: StatusCode::AsyncError,
@@ -131,10 +1071,16 @@ impl AsyncDB {
             }),
         }
     }
-    pub async fn get_at(&self, snapshot: SnapshotRef, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        let r = self
-            .process_request(Request::GetAt { snapshot, key })
-            .await?;
+    /// update replaces the value of key by what f returns for the current value (None meaning
+    /// that the key doesn't exist, or is to be deleted), and returns the new value. It is done
+    /// atomically: No other request is handled in between. f runs on the database thread, so it
+    /// should be quick.
+    pub async fn update<F>(&self, key: Vec<u8>, f: F) -> Result<Option<Vec<u8>>>
+    where
+        F: FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>> + Send + 'static,
+    {
+        let f = Box::new(f);
+        let r = self.process_request(Request::Update { key, f }).await?;
         match r {
             Response::Value(v) => Ok(v),
             Response::Error(s) => Err(s),
@@ -144,34 +1090,495 @@ impl AsyncDB {
             }),
         }
     }
-    pub async fn get_snapshot(&self) -> Result<SnapshotRef> {
-        let r = self.process_request(Request::GetSnapshot).await?;
+    /// compare_and_swap sets the value of key to new (deleting it for None) if its current value
+    /// is expected (None if it doesn't exist), atomically. It returns whether the value was
+    /// replaced.
+    pub async fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        let r = self
+            .process_request(Request::CompareAndSwap { key, expected, new })
+            .await?;
         match r {
-            Response::Snapshot(sr) => Ok(sr),
+            Response::Swapped(swapped) => Ok(swapped),
+            Response::Error(s) => Err(s),
             _ => Err(Status {
                 code: StatusCode::AsyncError,
                 err: "Wrong response type in AsyncDB.".to_string(),
             }),
         }
     }
-    /// As snapshots returned by `AsyncDB::get_snapshot()` are sort-of "weak references" to an
-    /// actual snapshot, they need to be dropped explicitly.
-    pub async fn drop_snapshot(&self, snapshot: SnapshotRef) -> Result<()> {
-        let r = self
-            .process_request(Request::DropSnapshot { snapshot })
-            .await?;
+    pub async fn write(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        let r = self.process_request(Request::Write { batch, sync }).await?;
         match r {
             Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
             _ => Err(Status {
                 code: StatusCode::AsyncError,
                 err: "Wrong response type in AsyncDB.".to_string(),
             }),
         }
     }
-    pub async fn compact_range(&self, from: Vec<u8>, to: Vec<u8>) -> Result<()> {
-        let r = self
-            .process_request(Request::CompactRange { from, to })
-            .await?;
+    pub async fn flush(&self) -> Result<()> {
+        let r = self.process_request(Request::Flush).await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    pub async fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.get_with(key, ReadOptions::default()).await
+    }
+    /// Like `get()`, but reads table blocks as ropt says (see `DB::get_with()`).
+    pub async fn get_with(&self, key: Vec<u8>, ropt: ReadOptions) -> Result<Option<Vec<u8>>> {
+        let r = self
+            .process_request(Request::Get {
+                key,
+                ropt: Box::new(ropt),
+            })
+            .await?;
+        match r {
+            Response::Value(v) => Ok(v),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// Like `get()`, but fails with `StatusCode::WouldBlock` instead of waiting if the request
+    /// queue is full.
+    pub async fn try_get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let r = self
+            .try_process_request(Request::Get {
+                key,
+                ropt: Box::default(),
+            })
+            .await?;
+        match r {
+            Response::Value(v) => Ok(v),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// get_timeout is the bounded-wait form of `get()`: It fails with `StatusCode::WouldBlock` if
+    /// there is no response within timeout, e.g. because the request queue is full or the
+    /// database thread is busy. The request isn't withdrawn then.
+    pub async fn get_timeout(&self, key: Vec<u8>, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let req = Request::Get {
+            key,
+            ropt: Box::default(),
+        };
+        let r = match self::timeout(
+            self.spawner.sleep(timeout),
+            self.send_request(req, Some(timeout)),
+        )
+        .await
+        {
+            Some(r) => r?,
+            None => {
+                return Err(Status::new(
+                    StatusCode::WouldBlock,
+                    &format!("AsyncDB request got no response within {:?}", timeout),
+                ))
+            }
+        };
+        match r {
+            Response::Value(v) => Ok(v),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// multi_get returns the values of keys, in the same order. All keys are looked up in a
+    /// single request, saving a round trip to the database thread for each key.
+    pub async fn multi_get(&self, keys: Vec<Vec<u8>>) -> Result<Vec<Option<Vec<u8>>>> {
+        let r = self.process_request(Request::MultiGet { keys }).await?;
+        match r {
+            Response::Values(vs) => Ok(vs),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// contains_key checks whether key exists without reading its value, e.g. for
+    /// deduplication (see `DB::key_may_exist()`).
+    pub async fn contains_key(&self, key: Vec<u8>) -> Result<KeyPresence> {
+        let r = self.process_request(Request::ContainsKey { key }).await?;
+        match r {
+            Response::Presence(p) => Ok(p),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    pub async fn get_at(&self, snapshot: SnapshotRef, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.get_at_with(snapshot, key, ReadOptions::default())
+            .await
+    }
+    /// Like `get_at()`, but reads table blocks as ropt says (see `DB::get_with()`).
+    pub async fn get_at_with(
+        &self,
+        snapshot: SnapshotRef,
+        key: Vec<u8>,
+        ropt: ReadOptions,
+    ) -> Result<Option<Vec<u8>>> {
+        let r = self
+            .process_request(Request::GetAt {
+                snapshot,
+                key,
+                ropt: Box::new(ropt),
+            })
+            .await?;
+        match r {
+            Response::Value(v) => Ok(v),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    pub async fn get_snapshot(&self) -> Result<SnapshotRef> {
+        let r = self.process_request(Request::GetSnapshot).await?;
+        match r {
+            Response::Snapshot(sr) => Ok(sr),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// snapshot returns a snapshot that is dropped together with the returned guard, unlike
+    /// those returned by `get_snapshot()`.
+    pub async fn snapshot(&self) -> Result<AsyncSnapshot> {
+        Ok(AsyncSnapshot {
+            snapshot: self.get_snapshot().await?,
+            db: self.clone(),
+        })
+    }
+    /// As snapshots returned by `AsyncDB::get_snapshot()` are sort-of "weak references" to an
+    /// actual snapshot, they need to be dropped explicitly.
+    pub async fn drop_snapshot(&self, snapshot: SnapshotRef) -> Result<()> {
+        let r = self
+            .process_request(Request::DropSnapshot { snapshot })
+            .await?;
+        match r {
+            Response::OK => Ok(()),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// approximate_sizes returns an estimate of the bytes on disk taken by each of the ranges
+    /// (see `DB::approximate_sizes()`).
+    pub async fn approximate_sizes(&self, ranges: Vec<(Vec<u8>, Vec<u8>)>) -> Result<Vec<u64>> {
+        let r = self
+            .process_request(Request::ApproximateSizes { ranges })
+            .await?;
+        match r {
+            Response::Sizes(sizes) => Ok(sizes),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// get_property returns the value of a property of the database's internal state, e.g. the
+    /// number of files on a level (see `DB::get_property()`).
+    pub async fn get_property(&self, name: String) -> Result<Option<String>> {
+        let r = self.process_request(Request::GetProperty { name }).await?;
+        match r {
+            Response::Property(value) => Ok(value),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// memory_usage returns the memory used by the database and its block cache (see
+    /// `DB::memory_usage()`).
+    pub async fn memory_usage(&self) -> Result<MemoryUsage> {
+        let r = self.process_request(Request::MemoryUsage).await?;
+        match r {
+            Response::MemoryUsage(usage) => Ok(usage),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    pub async fn compact_range(&self, from: Vec<u8>, to: Vec<u8>) -> Result<()> {
+        let r = self
+            .process_request(Request::CompactRange { from, to })
+            .await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// compact_all compacts the whole key space (see `DB::compact_all()`).
+    pub async fn compact_all(&self) -> Result<()> {
+        let r = self.process_request(Request::CompactAll).await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// checkpoint creates a copy of the database in dir (see `DB::checkpoint()`).
+    pub async fn checkpoint<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let r = self
+            .process_request(Request::Checkpoint {
+                dir: dir.as_ref().to_path_buf(),
+            })
+            .await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// See `DB::pause_background_work()`.
+    pub async fn pause_background_work(&self) -> Result<()> {
+        let r = self.process_request(Request::PauseBackgroundWork).await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// See `DB::continue_background_work()`.
+    pub async fn continue_background_work(&self) -> Result<()> {
+        let r = self
+            .process_request(Request::ContinueBackgroundWork)
+            .await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// subscribe returns a stream of the writes to keys starting with prefix, from now on, in the
+    /// order they are applied. Events are included once the write is visible to reads (before a
+    /// sync write is acknowledged); failed writes are left out. The events are buffered for the
+    /// subscriber without limit, so the stream should be polled regularly, or dropped.
+    pub async fn subscribe(&self, prefix: Vec<u8>) -> Result<Subscription> {
+        let (send, recv) = mpsc::unbounded_channel();
+        let r = self
+            .process_request(Request::Subscribe { prefix, send })
+            .await?;
+        match r {
+            Response::OK => Ok(Subscription { recv }),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// See `DB::delete_range()`.
+    pub async fn delete_range(&self, from: Vec<u8>, to: Vec<u8>) -> Result<()> {
+        let r = self
+            .process_request(Request::DeleteRange { from, to })
+            .await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// See `DB::delete_prefix()`.
+    pub async fn delete_prefix(&self, prefix: Vec<u8>) -> Result<()> {
+        let r = self
+            .process_request(Request::DeletePrefix { prefix })
+            .await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// flush_memtable is like `flush()`, but returns only once the memtable has been written to a
+    /// table file. See `DB::flush_memtable()`.
+    pub async fn flush_memtable(&self) -> Result<()> {
+        let r = self.process_request(Request::FlushMemtable).await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// wait_for_compaction returns once no compaction is pending, running the pending ones
+    /// first. Compactions deferred by the `CompactionScheduler` or paused by
+    /// `pause_background_work()` are not waited for.
+    pub async fn wait_for_compaction(&self) -> Result<()> {
+        let r = self.process_request(Request::WaitForCompaction).await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+
+    /// new_iter creates an iterator over the current state of the database, which is kept by
+    /// the database thread. Like snapshots, iterators need to be dropped explicitly (see
+    /// `AsyncDB::drop_iter()`).
+    pub async fn new_iter(&self) -> Result<IteratorRef> {
+        self.new_iter_with(None).await
+    }
+    /// new_iter_at creates an iterator over the state of the database at the snapshot. The
+    /// iterator keeps the state around, even after the snapshot is dropped.
+    pub async fn new_iter_at(&self, snapshot: SnapshotRef) -> Result<IteratorRef> {
+        self.new_iter_with(Some(snapshot)).await
+    }
+    async fn new_iter_with(&self, snapshot: Option<SnapshotRef>) -> Result<IteratorRef> {
+        let r = self.process_request(Request::NewIter { snapshot }).await?;
+        match r {
+            Response::Iterator(ir) => Ok(ir),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// next_many advances the iterator by up to n entries and returns them. Fewer than n entries
+    /// are returned only at the end of the iteration. Fetching many entries at a time saves a
+    /// round trip to the database thread for each one, which would make scans much slower than
+    /// with a `DB`.
+    pub async fn next_many(&self, iter: IteratorRef, n: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.next_chunk(iter, n, None).await
+    }
+    async fn next_chunk(
+        &self,
+        iter: IteratorRef,
+        n: usize,
+        to: Option<Vec<u8>>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let r = self
+            .process_request(Request::NextMany { iter, n, to })
+            .await?;
+        match r {
+            Response::Entries(entries) => Ok(entries),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// prev_many moves the iterator backwards by up to n entries and returns them, in descending
+    /// order. Like with `next_many()`, the direction can be switched at any time: `next_many()`
+    /// returns the entries just returned by `prev_many()` again, in ascending order.
+    pub async fn prev_many(&self, iter: IteratorRef, n: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let r = self.process_request(Request::PrevMany { iter, n }).await?;
+        match r {
+            Response::Entries(entries) => Ok(entries),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// seek moves the iterator to just before the first entry with a key at or after key:
+    /// `next_many()` continues with that entry, and `prev_many()` with the one before it.
+    pub async fn seek(&self, iter: IteratorRef, key: Vec<u8>) -> Result<()> {
+        self.seek_iter(iter, SeekTarget::Key(key)).await
+    }
+    /// seek_to_first moves the iterator to before the first entry, as if it was new.
+    pub async fn seek_to_first(&self, iter: IteratorRef) -> Result<()> {
+        self.seek_iter(iter, SeekTarget::First).await
+    }
+    /// seek_to_last moves the iterator to after the last entry, so that `prev_many()` returns
+    /// the last entries.
+    pub async fn seek_to_last(&self, iter: IteratorRef) -> Result<()> {
+        self.seek_iter(iter, SeekTarget::Last).await
+    }
+    async fn seek_iter(&self, iter: IteratorRef, to: SeekTarget) -> Result<()> {
+        let r = self.process_request(Request::Seek { iter, to }).await?;
+        match r {
+            Response::OK => Ok(()),
+            Response::Error(s) => Err(s),
+            _ => Err(Status {
+                code: StatusCode::AsyncError,
+                err: "Wrong response type in AsyncDB.".to_string(),
+            }),
+        }
+    }
+    /// scan returns a stream of the entries with keys from from (inclusive) to to (exclusive), or
+    /// to the end if to is None. Unlike with `new_iter()`, the iterator behind it is released
+    /// when the stream is dropped.
+    pub fn scan(&self, from: Vec<u8>, to: Option<Vec<u8>>) -> Scan {
+        Scan {
+            db: self.clone(),
+            from,
+            to,
+            iter: None,
+            entries: VecDeque::new(),
+            pending: None,
+            done: false,
+        }
+    }
+    /// drop_iter releases an iterator returned by `AsyncDB::new_iter()`.
+    pub async fn drop_iter(&self, iter: IteratorRef) -> Result<()> {
+        let r = self.process_request(Request::DropIter { iter }).await?;
         match r {
             Response::OK => Ok(()),
             Response::Error(s) => Err(s),
@@ -183,58 +1590,358 @@ impl AsyncDB {
     }
 
     async fn process_request(&self, req: Request) -> Result<Response> {
-        let (tx, rx) = oneshot::channel();
+        self.send_request(req, self.send_timeout).await
+    }
+
+    /// send_request is like process_request(), but waits for space in the request queue for
+    /// send_timeout instead of `AsyncDbOptions::send_timeout`.
+    async fn send_request(&self, req: Request, send_timeout: Option<Duration>) -> Result<Response> {
+        self.with_deadline(async {
+            let send = self.queue(&req);
+            let (m, rx) = self.message(req);
+            let sleep = send_timeout.map(|t| self.spawner.sleep(t));
+            send.send(m, sleep).await?;
+            let resp = rx.await;
+            match resp {
+                Err(e) => Err(Status {
+                    code: StatusCode::AsyncError,
+                    err: e.to_string(),
+                }),
+                Ok(r) => Ok(r),
+            }
+        })
+        .await
+    }
+
+    /// try_process_request is like process_request(), but doesn't wait for space in the request
+    /// queue.
+    async fn try_process_request(&self, req: Request) -> Result<Response> {
+        let send = self.queue(&req);
+        let (m, rx) = self.message(req);
+        match send.try_send(m) {
+            Err(TrySendError::Full(_)) => {
+                return Err(Status::new(
+                    StatusCode::WouldBlock,
+                    "AsyncDB request queue is full",
+                ))
+            }
+            Err(e) => {
+                return Err(Status {
+                    code: StatusCode::AsyncError,
+                    err: e.to_string(),
+                })
+            }
+            Ok(()) => {}
+        }
+        self.with_deadline(async {
+            match rx.await {
+                Err(e) => Err(Status {
+                    code: StatusCode::AsyncError,
+                    err: e.to_string(),
+                }),
+                Ok(r) => Ok(r),
+            }
+        })
+        .await
+    }
+
+    /// queue returns the queue that req is sent to: Reads go to the reader thread, if there is
+    /// one (see `AsyncDbOptions::read_thread`).
+    fn queue(&self, req: &Request) -> &RequestSender {
+        match (&self.read_send, req) {
+            (Some(send), Request::Get { .. } | Request::MultiGet { .. }) => send,
+            _ => &self.send,
+        }
+    }
+
+    fn message(&self, req: Request) -> (Message, oneshot::Receiver<Response>) {
+        let (ch, rx) = oneshot::channel();
         let m = Message {
             req,
-            resp_channel: tx,
+            resp_channel: Responder {
+                ch,
+                metrics: Some(self.metrics.clone()),
+            },
+            queued: Instant::now(),
         };
-        if let Err(e) = self.send.send(m).await {
-            return Err(Status {
-                code: StatusCode::AsyncError,
-                err: e.to_string(),
-            });
+        (m, rx)
+    }
+
+    /// with_deadline fails with `StatusCode::AsyncError` if request doesn't finish within the
+    /// request timeout.
+    async fn with_deadline<F: Future<Output = Result<Response>>>(
+        &self,
+        request: F,
+    ) -> Result<Response> {
+        let t = match self.request_timeout {
+            None => return request.await,
+            Some(t) => t,
+        };
+        match timeout(self.spawner.sleep(t), request).await {
+            Some(r) => r,
+            None => Err(Status::new(
+                StatusCode::AsyncError,
+                &format!("AsyncDB request timed out after {:?}", t),
+            )),
         }
-        let resp = rx.await;
-        match resp {
-            Err(e) => Err(Status {
-                code: StatusCode::AsyncError,
-                err: e.to_string(),
-            }),
-            Ok(r) => Ok(r),
+    }
+
+    /// next_message waits for the next request. While no requests are queued, pending
+    /// compactions are done step by step (see `DB::compact_step()`), so that deferred compactions
+    /// (see `Options::defer_compactions`) run with lower priority than requests. The same goes
+    /// for idle compactions (see `DB::compact_idle()`). While sync writes wait for their group
+    /// commit, no compactions are started, and `Next::Commit` is returned once the group's
+    /// deadline passes.
+    /// The database is only locked while compacting, not while waiting.
+    fn next_message(db: &MutexDB, recv: &mut RequestReceiver, group: &GroupCommit) -> Next {
+        loop {
+            match recv.try_recv() {
+                Ok(message) => return Next::Message(message),
+                Err(TryRecvError::Disconnected) => return Next::Closed,
+                Err(TryRecvError::Empty) => {}
+            }
+            if let Some(deadline) = group.deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Next::Commit;
+                }
+                std::thread::sleep((deadline - now).min(GROUP_COMMIT_POLL_INTERVAL));
+                continue;
+            }
+            // Level compactions are done in steps, so that requests, and the memtable compactions
+            // writes wait for, are served in between. A failed compaction will be retried (and
+            // its error returned) on the next write or tick.
+            let compacted = db.lock().unwrap().compact_step().unwrap_or(false);
+            if !compacted {
+                return match recv.blocking_recv() {
+                    Some(message) => Next::Message(message),
+                    None => Next::Closed,
+                };
+            }
+        }
+    }
+
+    /// run_ticker periodically wakes the database thread, which syncs the log (see
+    /// `Options::log_sync_interval`) and starts idle compactions (see
+    /// `Options::idle_compaction_delay`) and automatic snapshots (see
+    /// `Options::auto_snapshot_interval`) if they're due. It returns once all AsyncDB handles are
+    /// dropped.
+    fn run_ticker(send: WeakRequestSender, interval: Duration) {
+        loop {
+            std::thread::sleep(interval);
+            let send = match send.upgrade() {
+                Some(send) => send,
+                None => return,
+            };
+            let message = Message::detached(Request::Tick);
+            // If requests are queued, the database thread is awake anyway.
+            if let Err(TrySendError::Closed(_)) = send.try_send(message) {
+                return;
+            }
+        }
+    }
+
+    /// run_reader serves the reads sent to the reader thread (see `AsyncDbOptions::read_thread`)
+    /// until all handles are dropped. Once the database is closed, reads fail.
+    fn run_reader(
+        db: MutexDB,
+        mut recv: RequestReceiver,
+        closed: Arc<AtomicBool>,
+        stats: Arc<Mutex<AsyncMetrics>>,
+    ) {
+        loop {
+            let (message, queue_depth) = match recv.blocking_recv() {
+                Some(message) => (message, recv.len()),
+                None => return,
+            };
+            let (name, queued, start) = (message.req.name(), message.queued, Instant::now());
+            if closed.load(atomic::Ordering::SeqCst) {
+                send_response(
+                    message.resp_channel,
+                    Err(Status::new(StatusCode::AsyncError, "AsyncDB is closed")),
+                );
+                continue;
+            }
+            match message.req {
+                Request::Get { key, ropt } => match db.get_with(&key, &ropt) {
+                    Ok(v) => {
+                        message.resp_channel.send(Response::Value(v)).ok();
+                    }
+                    Err(e) => send_response(message.resp_channel, Err(e)),
+                },
+                Request::MultiGet { keys } => match db.multi_get(&keys) {
+                    Ok(vs) => {
+                        message.resp_channel.send(Response::Values(vs)).ok();
+                    }
+                    Err(e) => send_response(message.resp_channel, Err(e)),
+                },
+                _ => send_response(
+                    message.resp_channel,
+                    Err(Status::new(
+                        StatusCode::AsyncError,
+                        "Request sent to a reader thread: this is a bug",
+                    )),
+                ),
+            }
+            stats
+                .lock()
+                .unwrap()
+                .record(name, queue_depth, queued, start);
         }
     }
 
-    fn run_server(mut db: DB, mut recv: mpsc::Receiver<Message>) {
+    /// run_server handles the requests of the database thread. Each request is handled with the
+    /// database locked, so that the reader thread (see `AsyncDbOptions::read_thread`) doesn't use
+    /// it concurrently. Snapshots and iterators refer to the database's internals, so they are
+    /// also only touched with the lock held.
+    fn run_server(
+        shared: MutexDB,
+        mut recv: RequestReceiver,
+        group_commit_delay: Option<Duration>,
+        closed: Arc<AtomicBool>,
+        stats: Arc<Mutex<AsyncMetrics>>,
+    ) {
         let mut snapshots = HashMap::new();
         let mut snapshot_counter: usize = 0;
+        let mut iterators = HashMap::new();
+        let mut iterator_counter: usize = 0;
+        let mut subscribers = vec![];
+        let mut group = GroupCommit {
+            delay: group_commit_delay,
+            deadline: None,
+            waiting: vec![],
+        };
 
-        while let Some(message) = recv.blocking_recv() {
+        loop {
+            let message = match AsyncDB::next_message(&shared, &mut recv, &group) {
+                Next::Message(message) => {
+                    metrics::async_queue_depth(recv.len());
+                    message
+                }
+                Next::Commit => {
+                    group.commit(&mut shared.lock().unwrap());
+                    continue;
+                }
+                Next::Closed => {
+                    let mut db = shared.lock().unwrap();
+                    group.commit(&mut db);
+                    iterators.clear();
+                    snapshots.clear();
+                    return;
+                }
+            };
+            let (name, queue_depth, start) = (message.req.name(), recv.len(), Instant::now());
+            let queued = message.queued;
+            let mut db = shared.lock().unwrap();
             match message.req {
                 Request::Close => {
-                    message.resp_channel.send(Response::OK).ok();
+                    group.commit(&mut db);
                     recv.close();
+                    closed.store(true, atomic::Ordering::SeqCst);
+                    iterators.clear();
+                    snapshots.clear();
+                    send_response(message.resp_channel, db.close());
                     return;
                 }
                 Request::Put { key, val } => {
                     let ok = db.put(&key, &val);
+                    if ok.is_ok() {
+                        publish(&mut subscribers, [WriteEvent::Put { key, value: val }]);
+                    }
+                    send_response(message.resp_channel, ok);
+                }
+                Request::TryPut { key, val, timeout } => {
+                    let ok = db.put_timeout(&key, &val, timeout.saturating_sub(queued.elapsed()));
+                    if ok.is_ok() {
+                        publish(&mut subscribers, [WriteEvent::Put { key, value: val }]);
+                    }
+                    send_response(message.resp_channel, ok);
+                }
+                Request::PutMany { pairs } => {
+                    let events: Vec<_> = if subscribers.is_empty() {
+                        vec![]
+                    } else {
+                        pairs
+                            .iter()
+                            .map(|(k, v)| WriteEvent::Put {
+                                key: k.clone(),
+                                value: v.clone(),
+                            })
+                            .collect()
+                    };
+                    let ok = db.put_many(pairs);
+                    if ok.is_ok() {
+                        publish(&mut subscribers, events);
+                    }
                     send_response(message.resp_channel, ok);
                 }
                 Request::Delete { key } => {
                     let ok = db.delete(&key);
+                    if ok.is_ok() {
+                        publish(&mut subscribers, [WriteEvent::Delete { key }]);
+                    }
                     send_response(message.resp_channel, ok);
                 }
                 Request::Write { batch, sync } => {
-                    let ok = db.write(batch, sync);
-                    send_response(message.resp_channel, ok);
+                    let events = if subscribers.is_empty() {
+                        vec![]
+                    } else {
+                        batch_events(&batch)
+                    };
+                    // Sync writes wait for their group commit, if enabled.
+                    let group_sync = sync && group.delay.is_some();
+                    let ok = db.write(batch, sync && !group_sync);
+                    if ok.is_ok() {
+                        publish(&mut subscribers, events);
+                    }
+                    match ok {
+                        Ok(()) if group_sync => group.add(message.resp_channel),
+                        ok => send_response(message.resp_channel, ok),
+                    }
+                }
+                Request::Subscribe { prefix, send } => {
+                    subscribers.push(Subscriber { prefix, send });
+                    send_response(message.resp_channel, Ok(()));
                 }
                 Request::Flush => {
+                    group.commit(&mut db);
                     let ok = db.flush();
                     send_response(message.resp_channel, ok);
                 }
-                Request::GetAt { snapshot, key } => {
+                Request::FlushMemtable => {
+                    group.commit(&mut db);
+                    let ok = db.flush_memtable();
+                    send_response(message.resp_channel, ok);
+                }
+                Request::WaitForCompaction => {
+                    let mut ok = Ok(());
+                    while ok.is_ok() && db.needs_compaction() {
+                        ok = db.compact_pending();
+                    }
+                    send_response(message.resp_channel, ok);
+                }
+                Request::PauseBackgroundWork => {
+                    db.pause_background_work();
+                    send_response(message.resp_channel, Ok(()));
+                }
+                Request::ContinueBackgroundWork => {
+                    let ok = db.continue_background_work();
+                    send_response(message.resp_channel, ok);
+                }
+                Request::Tick => {
+                    let ok = db
+                        .sync_log_if_due()
+                        .and_then(|_| db.auto_snapshot_if_due().map(|_| ()));
+                    send_response(message.resp_channel, ok);
+                }
+                Request::GetAt {
+                    snapshot,
+                    key,
+                    ropt,
+                } => {
                     let snapshot_id = snapshot.0;
                     if let Some(snapshot) = snapshots.get(&snapshot_id) {
-                        let ok = db.get_at(&snapshot, &key);
+                        let ok = db.get_at_with(snapshot, &key, &ropt);
                         match ok {
                             Err(e) => {
                                 message.resp_channel.send(Response::Error(e)).ok();
@@ -253,9 +1960,55 @@ impl AsyncDB {
                             .ok();
                     }
                 }
-                Request::Get { key } => {
-                    let r = db.get(&key);
-                    message.resp_channel.send(Response::Value(r)).ok();
+                Request::Get { key, ropt } => match db.get_with(&key, &ropt) {
+                    Ok(v) => {
+                        message.resp_channel.send(Response::Value(v)).ok();
+                    }
+                    Err(e) => send_response(message.resp_channel, Err(e)),
+                },
+                Request::MultiGet { keys } => match db.multi_get(&keys) {
+                    Ok(vs) => {
+                        message.resp_channel.send(Response::Values(vs)).ok();
+                    }
+                    Err(e) => send_response(message.resp_channel, Err(e)),
+                },
+                Request::ContainsKey { key } => match db.key_may_exist(&key) {
+                    Ok(p) => {
+                        message.resp_channel.send(Response::Presence(p)).ok();
+                    }
+                    Err(e) => send_response(message.resp_channel, Err(e)),
+                },
+                Request::Update { key, f } => {
+                    let r = db.get(&key).and_then(|old| {
+                        let existed = old.is_some();
+                        let new =
+                            panic::catch_unwind(AssertUnwindSafe(|| f(old))).map_err(|_| {
+                                Status::new(StatusCode::AsyncError, "update function panicked")
+                            })?;
+                        replace_value(&mut db, &mut subscribers, key, existed, new.clone())?;
+                        Ok(new)
+                    });
+                    match r {
+                        Ok(v) => {
+                            message.resp_channel.send(Response::Value(v)).ok();
+                        }
+                        Err(e) => send_response(message.resp_channel, Err(e)),
+                    }
+                }
+                Request::CompareAndSwap { key, expected, new } => {
+                    let r = db.get(&key).and_then(|old| {
+                        if old != expected {
+                            return Ok(false);
+                        }
+                        replace_value(&mut db, &mut subscribers, key, old.is_some(), new)?;
+                        Ok(true)
+                    });
+                    match r {
+                        Ok(swapped) => {
+                            message.resp_channel.send(Response::Swapped(swapped)).ok();
+                        }
+                        Err(e) => send_response(message.resp_channel, Err(e)),
+                    }
                 }
                 Request::GetSnapshot => {
                     snapshots.insert(snapshot_counter, db.get_snapshot());
@@ -271,15 +2024,1052 @@ impl AsyncDB {
                     let ok = db.compact_range(&from, &to);
                     send_response(message.resp_channel, ok);
                 }
+                Request::CompactAll => {
+                    let ok = db.compact_all();
+                    send_response(message.resp_channel, ok);
+                }
+                Request::Checkpoint { dir } => {
+                    let ok = db.checkpoint(dir);
+                    send_response(message.resp_channel, ok);
+                }
+                Request::DeletePrefix { prefix } => {
+                    let ok = db.delete_prefix(&prefix);
+                    if ok.is_ok() {
+                        publish(&mut subscribers, [WriteEvent::DeletePrefix { prefix }]);
+                    }
+                    send_response(message.resp_channel, ok);
+                }
+                Request::DeleteRange { from, to } => {
+                    let ok = db.delete_range(&from, &to);
+                    if ok.is_ok() && from < to {
+                        publish(&mut subscribers, [WriteEvent::DeleteRange { from, to }]);
+                    }
+                    send_response(message.resp_channel, ok);
+                }
+                Request::ApproximateSizes { ranges } => {
+                    let sizes = db.approximate_sizes(&ranges);
+                    message.resp_channel.send(Response::Sizes(sizes)).ok();
+                }
+                Request::GetProperty { name } => {
+                    let value = db.get_property(&name);
+                    message.resp_channel.send(Response::Property(value)).ok();
+                }
+                Request::MemoryUsage => {
+                    let usage = db.memory_usage();
+                    message.resp_channel.send(Response::MemoryUsage(usage)).ok();
+                }
+                Request::NewIter { snapshot } => {
+                    let iter = match snapshot {
+                        None => db.new_iter(),
+                        Some(snapshot) => match snapshots.get(&snapshot.0) {
+                            Some(ss) => db.new_iter_at(ss.clone()),
+                            None => Err(Status {
+                                code: StatusCode::AsyncError,
+                                err: "Unknown snapshot reference: this is a bug".to_string(),
+                            }),
+                        },
+                    };
+                    match iter {
+                        Ok(iter) => {
+                            iterators.insert(iterator_counter, ServerIter::new(iter));
+                            let iref = IteratorRef(iterator_counter);
+                            iterator_counter += 1;
+                            message.resp_channel.send(Response::Iterator(iref)).ok();
+                        }
+                        Err(e) => send_response(message.resp_channel, Err(e)),
+                    }
+                }
+                Request::NextMany { iter, n, to } => {
+                    let resp = match iterators.get_mut(&iter.0) {
+                        Some(iter) => {
+                            let mut entries = iter.next_many(n);
+                            if let Some(to) = to {
+                                let cmp = db.user_cmp();
+                                if let Some(end) = entries
+                                    .iter()
+                                    .position(|(k, _)| cmp.cmp(k, &to) != Ordering::Less)
+                                {
+                                    entries.truncate(end);
+                                }
+                            }
+                            Response::Entries(entries)
+                        }
+                        None => unknown_iterator(),
+                    };
+                    message.resp_channel.send(resp).ok();
+                }
+                Request::PrevMany { iter, n } => {
+                    let resp = match iterators.get_mut(&iter.0) {
+                        Some(iter) => Response::Entries(iter.prev_many(n)),
+                        None => unknown_iterator(),
+                    };
+                    message.resp_channel.send(resp).ok();
+                }
+                Request::Seek { iter, to } => {
+                    let resp = match iterators.get_mut(&iter.0) {
+                        Some(iter) => {
+                            iter.seek(to);
+                            Response::OK
+                        }
+                        None => unknown_iterator(),
+                    };
+                    message.resp_channel.send(resp).ok();
+                }
+                Request::DropIter { iter } => {
+                    iterators.remove(&iter.0);
+                    send_response(message.resp_channel, Ok(()));
+                }
+            }
+            stats
+                .lock()
+                .unwrap()
+                .record(name, queue_depth, queued, start);
+        }
+    }
+}
+
+/// replace_value writes the new value of key, or deletes it if it existed, for
+/// `Request::Update` and `Request::CompareAndSwap`.
+fn replace_value(
+    db: &mut DB,
+    subscribers: &mut Vec<Subscriber>,
+    key: Vec<u8>,
+    existed: bool,
+    new: Option<Vec<u8>>,
+) -> Result<()> {
+    match new {
+        Some(value) => {
+            db.put(&key, &value)?;
+            publish(subscribers, [WriteEvent::Put { key, value }]);
+        }
+        None if existed => {
+            db.delete(&key)?;
+            publish(subscribers, [WriteEvent::Delete { key }]);
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+fn unknown_iterator() -> Response {
+    Response::Error(Status {
+        code: StatusCode::AsyncError,
+        err: "Unknown iterator reference: this is a bug".to_string(),
+    })
+}
+
+/// The position of an iterator kept by the database thread. Positions are between entries:
+/// `next_many()` returns the entries after it, `prev_many()` those before it.
+enum IterPos {
+    /// Before the first entry.
+    Start,
+    /// Just before the entry the `DBIterator` is at.
+    Before,
+    /// Just after the entry the `DBIterator` is at.
+    After,
+    /// After the last entry, whose key is kept if it is known. An exhausted `DBIterator` would
+    /// restart at the first entry, so it doesn't tell where it stopped.
+    End(Option<Vec<u8>>),
+}
+
+/// ServerIter is an iterator kept by the database thread for an `IteratorRef`.
+struct ServerIter {
+    iter: DBIterator,
+    pos: IterPos,
+}
+
+impl ServerIter {
+    fn new(iter: DBIterator) -> ServerIter {
+        ServerIter {
+            iter,
+            pos: IterPos::Start,
+        }
+    }
+
+    fn seek(&mut self, to: SeekTarget) {
+        self.pos = match to {
+            SeekTarget::First => IterPos::Start,
+            SeekTarget::Last => IterPos::End(None),
+            SeekTarget::Key(key) => {
+                self.iter.seek(&key);
+                if self.iter.valid() {
+                    IterPos::Before
+                } else {
+                    IterPos::End(None)
+                }
+            }
+        };
+    }
+
+    fn next_many(&mut self, n: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        while entries.len() < n {
+            match self.pos {
+                IterPos::Start => {
+                    self.iter.seek_to_first();
+                    self.pos = IterPos::Before;
+                }
+                IterPos::Before => match current_key_val(&self.iter) {
+                    Some(e) => {
+                        entries.push(e);
+                        self.pos = IterPos::After;
+                    }
+                    None => self.pos = IterPos::End(None),
+                },
+                IterPos::After => {
+                    if self.iter.advance() {
+                        entries.extend(current_key_val(&self.iter));
+                    } else {
+                        self.pos = IterPos::End(entries.last().map(|(k, _)| k.clone()));
+                    }
+                }
+                IterPos::End(_) => break,
+            }
+        }
+        entries
+    }
+
+    fn prev_many(&mut self, n: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = vec![];
+        while entries.len() < n {
+            match &mut self.pos {
+                IterPos::Start => break,
+                IterPos::End(last) => {
+                    match last.take() {
+                        Some(k) => self.iter.seek(&k),
+                        None => self.iter.seek_to_last(),
+                    }
+                    self.pos = if self.iter.valid() {
+                        IterPos::After
+                    } else {
+                        IterPos::Start
+                    };
+                }
+                IterPos::After => match current_key_val(&self.iter) {
+                    Some(e) => {
+                        entries.push(e);
+                        self.pos = IterPos::Before;
+                    }
+                    None => self.pos = IterPos::Start,
+                },
+                IterPos::Before => {
+                    if self.iter.prev() {
+                        entries.extend(current_key_val(&self.iter));
+                    } else {
+                        self.pos = IterPos::Start;
+                    }
+                }
             }
         }
+        entries
+    }
+}
+
+/// The result of waiting for a request in the database thread.
+enum Next {
+    Message(Message),
+    /// The deadline of the current group commit has passed.
+    Commit,
+    Closed,
+}
+
+/// GroupCommit collects the sync writes that wait for a shared sync of the log (see
+/// `Options::group_commit_delay`).
+struct GroupCommit {
+    delay: Option<Duration>,
+    deadline: Option<Instant>,
+    waiting: Vec<Responder>,
+}
+
+impl GroupCommit {
+    fn add(&mut self, ch: Responder) {
+        if self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + self.delay.unwrap_or_default());
+        }
+        self.waiting.push(ch);
+    }
+
+    /// commit syncs the log and acknowledges all waiting writes.
+    fn commit(&mut self, db: &mut DB) {
+        self.deadline = None;
+        if self.waiting.is_empty() {
+            return;
+        }
+        let r = db.commit_group(self.waiting.len());
+        for ch in self.waiting.drain(..) {
+            send_response(ch, r.clone());
+        }
     }
 }
 
-fn send_response(ch: oneshot::Sender<Response>, result: Result<()>) {
+fn send_response(ch: Responder, result: Result<()>) {
     if let Err(e) = result {
         ch.send(Response::Error(e)).ok();
     } else {
         ch.send(Response::OK).ok();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::types::NUM_LEVELS;
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    async fn next<S: Stream + Unpin>(s: &mut S) -> Option<S::Item> {
+        std::future::poll_fn(|cx| Pin::new(&mut *s).poll_next(cx)).await
+    }
+
+    async fn collect(mut scan: Scan) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries = vec![];
+        while let Some(entry) = next(&mut scan).await {
+            entries.push(entry.unwrap());
+        }
+        entries
+    }
+
+    /// settled_metrics returns the metrics once the requests sent so far have been recorded.
+    async fn settled_metrics(db: &AsyncDB) -> AsyncMetrics {
+        // Requests are recorded after they are answered, but before the next one is taken.
+        db.get(b"settle".to_vec()).await.unwrap();
+        db.metrics()
+    }
+
+    fn count(m: &AsyncMetrics, req: &str) -> u64 {
+        m.latencies.get(req).map_or(0, |h| h.count())
+    }
+
+    fn incr(v: Option<Vec<u8>>) -> Vec<u8> {
+        let n: u32 = v.map_or(0, |v| String::from_utf8(v).unwrap().parse().unwrap());
+        (n + 1).to_string().into_bytes()
+    }
+
+    /// slow_update keeps the database thread busy for d.
+    async fn slow_update(db: &AsyncDB, d: Duration) -> Result<Option<Vec<u8>>> {
+        db.update(b"slow".to_vec(), move |v| {
+            std::thread::sleep(d);
+            v
+        })
+        .await
+    }
+
+    #[test]
+    fn test_asyncdb_update_atomic() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let db = db.clone();
+                    tokio::spawn(async move {
+                        for _ in 0..25 {
+                            db.update(b"upd".to_vec(), |v| Some(incr(v))).await.unwrap();
+                            // Compare and swap, retried until no other task got in between.
+                            loop {
+                                let old = db.get(b"cas".to_vec()).await.unwrap();
+                                let new = Some(incr(old.clone()));
+                                if db
+                                    .compare_and_swap(b"cas".to_vec(), old, new)
+                                    .await
+                                    .unwrap()
+                                {
+                                    break;
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for t in tasks {
+                t.await.unwrap();
+            }
+            assert_eq!(
+                Some(b"200".to_vec()),
+                db.get(b"upd".to_vec()).await.unwrap()
+            );
+            assert_eq!(
+                Some(b"200".to_vec()),
+                db.get(b"cas".to_vec()).await.unwrap()
+            );
+
+            assert!(db
+                .compare_and_swap(b"new".to_vec(), None, Some(b"1".to_vec()))
+                .await
+                .unwrap());
+            assert!(!db
+                .compare_and_swap(b"new".to_vec(), None, Some(b"2".to_vec()))
+                .await
+                .unwrap());
+            assert!(db
+                .compare_and_swap(b"new".to_vec(), Some(b"1".to_vec()), None)
+                .await
+                .unwrap());
+            assert_eq!(None, db.get(b"new".to_vec()).await.unwrap());
+            assert_eq!(None, db.update(b"new".to_vec(), |_| None).await.unwrap());
+
+            // A panicking update function leaves the value alone, and the database usable.
+            let r = db
+                .update(b"upd".to_vec(), |_| panic!("update failed"))
+                .await;
+            assert_eq!(StatusCode::AsyncError, r.unwrap_err().code);
+            assert_eq!(
+                Some(b"200".to_vec()),
+                db.get(b"upd".to_vec()).await.unwrap()
+            );
+            db.put(b"upd".to_vec(), b"0".to_vec()).await.unwrap();
+            assert_eq!(
+                Some(b"1".to_vec()),
+                db.update(b"upd".to_vec(), |v| Some(incr(v))).await.unwrap()
+            );
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_subscribe() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let mut a = db.subscribe(b"a".to_vec()).await.unwrap();
+            let mut all = db.subscribe(vec![]).await.unwrap();
+
+            db.put(b"a1".to_vec(), b"1".to_vec()).await.unwrap();
+            db.put(b"b1".to_vec(), b"2".to_vec()).await.unwrap();
+            db.delete(b"a1".to_vec()).await.unwrap();
+            let mut batch = AsyncWriteBatch::new();
+            batch.put(b"a2", b"3").delete(b"b1");
+            batch.commit(&db, false).await.unwrap();
+            db.delete_prefix(b"a".to_vec()).await.unwrap();
+            db.delete_range(b"b".to_vec(), b"c".to_vec()).await.unwrap();
+            assert!(db
+                .compare_and_swap(b"a3".to_vec(), None, Some(b"4".to_vec()))
+                .await
+                .unwrap());
+            // Neither a failed compare and swap nor an update without change is reported.
+            assert!(!db
+                .compare_and_swap(b"a3".to_vec(), None, Some(b"5".to_vec()))
+                .await
+                .unwrap());
+            db.update(b"a4".to_vec(), |_| None).await.unwrap();
+
+            let put = |k: &[u8], v: &[u8]| WriteEvent::Put {
+                key: k.to_vec(),
+                value: v.to_vec(),
+            };
+            let delete = |k: &[u8]| WriteEvent::Delete { key: k.to_vec() };
+            let delete_prefix = WriteEvent::DeletePrefix {
+                prefix: b"a".to_vec(),
+            };
+            let delete_range = WriteEvent::DeleteRange {
+                from: b"b".to_vec(),
+                to: b"c".to_vec(),
+            };
+            for ev in [
+                put(b"a1", b"1"),
+                delete(b"a1"),
+                put(b"a2", b"3"),
+                delete_prefix.clone(),
+                put(b"a3", b"4"),
+            ] {
+                assert_eq!(Some(ev), next(&mut a).await);
+            }
+            for ev in [
+                put(b"a1", b"1"),
+                put(b"b1", b"2"),
+                delete(b"a1"),
+                put(b"a2", b"3"),
+                delete(b"b1"),
+                delete_prefix,
+                delete_range,
+                put(b"a3", b"4"),
+            ] {
+                assert_eq!(Some(ev), next(&mut all).await);
+            }
+
+            // Dropped subscriptions are forgotten, and the others end with the database.
+            drop(all);
+            db.put(b"a5".to_vec(), b"6".to_vec()).await.unwrap();
+            db.close().await.unwrap();
+            assert_eq!(Some(put(b"a5", b"6")), next(&mut a).await);
+            assert_eq!(None, next(&mut a).await);
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_queues() {
+        block_on(async {
+            let async_opts = AsyncDbOptions {
+                buffer_size: Some(1),
+                send_timeout: Some(Duration::from_millis(20)),
+                ..AsyncDbOptions::default()
+            };
+            let db = AsyncDB::with_options("db", options::for_test(), async_opts).unwrap();
+            let slow = tokio::spawn({
+                let db = db.clone();
+                async move { slow_update(&db, Duration::from_millis(300)).await }
+            });
+            // Wait for the database thread to take the update.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let queued = tokio::spawn({
+                let db = db.clone();
+                async move { db.put(b"a".to_vec(), b"1".to_vec()).await }
+            });
+            tokio::task::yield_now().await;
+            let r = db.get(b"a".to_vec()).await;
+            assert_eq!(StatusCode::WouldBlock, r.unwrap_err().code);
+            let r = db.try_put(b"b".to_vec(), b"2".to_vec()).await;
+            assert_eq!(StatusCode::WouldBlock, r.unwrap_err().code);
+            slow.await.unwrap().unwrap();
+            queued.await.unwrap().unwrap();
+            assert_eq!(Some(b"1".to_vec()), db.get(b"a".to_vec()).await.unwrap());
+            db.close().await.unwrap();
+
+            // Requests are never delayed by an unbounded queue.
+            let async_opts = AsyncDbOptions {
+                buffer_size: None,
+                send_timeout: Some(Duration::from_millis(20)),
+                ..AsyncDbOptions::default()
+            };
+            let db = AsyncDB::with_options("db2", options::for_test(), async_opts).unwrap();
+            let slow = tokio::spawn({
+                let db = db.clone();
+                async move { slow_update(&db, Duration::from_millis(300)).await }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let puts: Vec<_> = (0..10u8)
+                .map(|i| {
+                    let db = db.clone();
+                    tokio::spawn(async move { db.put(vec![i], vec![i]).await })
+                })
+                .collect();
+            db.try_put(b"b".to_vec(), b"2".to_vec()).await.unwrap();
+            for p in puts {
+                p.await.unwrap().unwrap();
+            }
+            slow.await.unwrap().unwrap();
+            assert_eq!(Some(vec![9]), db.get(vec![9]).await.unwrap());
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_put_get_timeout() {
+        block_on(async {
+            let async_opts = AsyncDbOptions {
+                buffer_size: Some(1),
+                ..AsyncDbOptions::default()
+            };
+            let db = AsyncDB::with_options("db", options::for_test(), async_opts).unwrap();
+            let slow = tokio::spawn({
+                let db = db.clone();
+                async move { slow_update(&db, Duration::from_millis(300)).await }
+            });
+            // Wait for the database thread to take the update, then fill the queue.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let queued = tokio::spawn({
+                let db = db.clone();
+                async move { db.put(b"a".to_vec(), b"1".to_vec()).await }
+            });
+            tokio::task::yield_now().await;
+            let t = Duration::from_millis(20);
+            let r = db.get_timeout(b"a".to_vec(), t).await;
+            assert_eq!(StatusCode::WouldBlock, r.unwrap_err().code);
+            let r = db.put_timeout(b"b".to_vec(), b"2".to_vec(), t).await;
+            assert_eq!(StatusCode::WouldBlock, r.unwrap_err().code);
+            slow.await.unwrap().unwrap();
+            queued.await.unwrap().unwrap();
+
+            let t = Duration::from_secs(10);
+            db.put_timeout(b"b".to_vec(), b"2".to_vec(), t)
+                .await
+                .unwrap();
+            assert_eq!(
+                Some(b"1".to_vec()),
+                db.get_timeout(b"a".to_vec(), t).await.unwrap()
+            );
+            assert_eq!(
+                Some(b"2".to_vec()),
+                db.get_timeout(b"b".to_vec(), t).await.unwrap()
+            );
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_request_timeout() {
+        block_on(async {
+            let async_opts = AsyncDbOptions {
+                request_timeout: Some(Duration::from_millis(20)),
+                ..AsyncDbOptions::default()
+            };
+            let db = AsyncDB::with_options("db", options::for_test(), async_opts).unwrap();
+            let r = slow_update(&db, Duration::from_millis(100)).await;
+            let e = r.unwrap_err();
+            assert_eq!(StatusCode::AsyncError, e.code);
+            assert!(e.err.contains("timed out"));
+
+            // The update is still carried out, but its response is dropped.
+            let untimed = db.with_timeout(None);
+            assert!(slow_update(&untimed, Duration::from_millis(50))
+                .await
+                .is_ok());
+            assert_eq!(1, settled_metrics(&untimed).await.dropped_responses);
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_scan() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let key = |i: usize| format!("key{:04}", i).into_bytes();
+            db.put_many((0..300).map(|i| (key(i), key(i))))
+                .await
+                .unwrap();
+
+            let before = count(&settled_metrics(&db).await, "next_many");
+            let all = collect(db.scan(vec![], None)).await;
+            assert!((0..300).map(|i| (key(i), key(i))).eq(all));
+            // Two full chunks and the rest.
+            let m = settled_metrics(&db).await;
+            assert_eq!(before + 3, count(&m, "next_many"));
+
+            // Two full chunks; the third is empty.
+            let entries = collect(db.scan(key(10), Some(key(10 + 2 * SCAN_CHUNK_SIZE)))).await;
+            assert_eq!(2 * SCAN_CHUNK_SIZE, entries.len());
+            assert_eq!(key(10), entries[0].0);
+            assert_eq!(key(9 + 2 * SCAN_CHUNK_SIZE), entries.last().unwrap().0);
+            let m2 = settled_metrics(&db).await;
+            assert_eq!(count(&m, "next_many") + 3, count(&m2, "next_many"));
+
+            let entries = collect(db.scan(key(100), Some(key(150)))).await;
+            assert!((100..150).map(|i| (key(i), key(i))).eq(entries));
+            assert!(collect(db.scan(key(300), None)).await.is_empty());
+            assert!(collect(db.scan(key(20), Some(key(10)))).await.is_empty());
+
+            // Each scan's iterator was dropped with it, also if it wasn't exhausted. A scan
+            // that was never polled has no iterator.
+            let mut scan = db.scan(vec![], None);
+            assert_eq!(Some(key(0)), next(&mut scan).await.map(|e| e.unwrap().0));
+            drop(scan);
+            drop(db.scan(vec![], None));
+            let m = settled_metrics(&db).await;
+            assert_eq!(6, count(&m, "new_iter"));
+            assert_eq!(6, count(&m, "drop_iter"));
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_snapshot() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            let snapshot = db.snapshot().await.unwrap();
+            db.put(b"a".to_vec(), b"2".to_vec()).await.unwrap();
+            db.put(b"b".to_vec(), b"3".to_vec()).await.unwrap();
+
+            assert_eq!(
+                Some(b"1".to_vec()),
+                snapshot.get_at(b"a".to_vec()).await.unwrap()
+            );
+            assert_eq!(None, snapshot.get_at(b"b".to_vec()).await.unwrap());
+            assert_eq!(Some(b"2".to_vec()), db.get(b"a".to_vec()).await.unwrap());
+            let iter = db.new_iter_at(snapshot.snapshot_ref()).await.unwrap();
+            assert_eq!(
+                vec![(b"a".to_vec(), b"1".to_vec())],
+                db.next_many(iter, 10).await.unwrap()
+            );
+            db.drop_iter(iter).await.unwrap();
+
+            drop(snapshot);
+            assert_eq!(1, count(&settled_metrics(&db).await, "drop_snapshot"));
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_iter_seek() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let key = |i: usize| format!("key{:02}", i).into_bytes();
+            let entries =
+                |r: std::ops::Range<usize>| -> Vec<_> { r.map(|i| (key(i), key(i))).collect() };
+            // Half of the entries are in a table, the other half in the memtable.
+            db.put_many((0..10).map(|i| (key(2 * i), key(2 * i))))
+                .await
+                .unwrap();
+            db.flush_memtable().await.unwrap();
+            db.put_many((0..10).map(|i| (key(2 * i + 1), key(2 * i + 1))))
+                .await
+                .unwrap();
+            let rev = |mut v: Vec<_>| {
+                v.reverse();
+                v
+            };
+
+            let iter = db.new_iter().await.unwrap();
+            db.seek_to_last(iter).await.unwrap();
+            assert_eq!(rev(entries(17..20)), db.prev_many(iter, 3).await.unwrap());
+            // Switching direction returns the entries that prev_many() went over.
+            assert_eq!(entries(17..20), db.next_many(iter, 5).await.unwrap());
+            assert!(db.next_many(iter, 5).await.unwrap().is_empty());
+            assert_eq!(rev(entries(18..20)), db.prev_many(iter, 2).await.unwrap());
+
+            db.seek(iter, key(5)).await.unwrap();
+            assert_eq!(entries(5..7), db.next_many(iter, 2).await.unwrap());
+            assert_eq!(rev(entries(5..7)), db.prev_many(iter, 2).await.unwrap());
+            assert_eq!(rev(entries(0..5)), db.prev_many(iter, 10).await.unwrap());
+            assert!(db.prev_many(iter, 10).await.unwrap().is_empty());
+            assert_eq!(entries(0..2), db.next_many(iter, 2).await.unwrap());
+
+            db.seek(iter, key(10)).await.unwrap();
+            assert_eq!(rev(entries(7..10)), db.prev_many(iter, 3).await.unwrap());
+            // Past the last entry, prev_many() starts at the last one.
+            db.seek(iter, b"z".to_vec()).await.unwrap();
+            assert!(db.next_many(iter, 1).await.unwrap().is_empty());
+            assert_eq!(rev(entries(19..20)), db.prev_many(iter, 1).await.unwrap());
+
+            db.seek_to_first(iter).await.unwrap();
+            assert!(db.prev_many(iter, 1).await.unwrap().is_empty());
+            assert_eq!(entries(0..20), db.next_many(iter, 30).await.unwrap());
+            assert_eq!(rev(entries(0..20)), db.prev_many(iter, 30).await.unwrap());
+            db.drop_iter(iter).await.unwrap();
+
+            // An empty database.
+            let db2 = AsyncDB::new("db2", options::for_test()).unwrap();
+            let iter = db2.new_iter().await.unwrap();
+            db2.seek_to_last(iter).await.unwrap();
+            assert!(db2.prev_many(iter, 1).await.unwrap().is_empty());
+            assert!(db2.next_many(iter, 1).await.unwrap().is_empty());
+            db2.close().await.unwrap();
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_approximate_sizes() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let key = |i: usize| format!("key{:04}", i).into_bytes();
+            db.put_many((0..1000).map(|i| (key(i), vec![b'x'; 100])))
+                .await
+                .unwrap();
+            // The memtable isn't included.
+            let ranges = vec![(key(0), key(1000))];
+            assert_eq!(vec![0], db.approximate_sizes(ranges.clone()).await.unwrap());
+
+            db.flush_memtable().await.unwrap();
+            let sizes = db
+                .approximate_sizes(vec![
+                    (key(0), key(1000)),
+                    (key(0), key(500)),
+                    (b"x".to_vec(), b"y".to_vec()),
+                ])
+                .await
+                .unwrap();
+            assert_eq!(3, sizes.len());
+            assert!(sizes[0] > 0);
+            assert!(sizes[1] <= sizes[0]);
+            assert_eq!(0, sizes[2]);
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_get_with() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            db.flush_memtable().await.unwrap();
+            let snapshot = db.snapshot().await.unwrap();
+            db.put(b"a".to_vec(), b"2".to_vec()).await.unwrap();
+
+            let ropt = ReadOptions {
+                verify_checksums: true,
+                fill_cache: false,
+                ..ReadOptions::default()
+            };
+            assert_eq!(
+                Some(b"2".to_vec()),
+                db.get_with(b"a".to_vec(), ropt.clone()).await.unwrap()
+            );
+            // The value at the snapshot is read from the table.
+            assert_eq!(
+                Some(b"1".to_vec()),
+                db.get_at_with(snapshot.snapshot_ref(), b"a".to_vec(), ropt.clone())
+                    .await
+                    .unwrap()
+            );
+            assert_eq!(
+                None,
+                db.get_at_with(snapshot.snapshot_ref(), b"b".to_vec(), ropt)
+                    .await
+                    .unwrap()
+            );
+            drop(snapshot);
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_get_property() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let files = |db: AsyncDB| async move {
+                let mut n = 0;
+                for l in 0..NUM_LEVELS {
+                    let name = format!("leveldb.num-files-at-level{}", l);
+                    let v = db.get_property(name).await.unwrap().unwrap();
+                    n += v.parse::<usize>().unwrap();
+                }
+                n
+            };
+            assert_eq!(0, files(db.clone()).await);
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            db.flush_memtable().await.unwrap();
+            assert_eq!(1, files(db.clone()).await);
+
+            let stats = db.get_property("leveldb.stats".to_string()).await.unwrap();
+            assert!(stats.unwrap().contains("Compactions"));
+            assert_eq!(
+                None,
+                db.get_property("leveldb.unknown".to_string())
+                    .await
+                    .unwrap()
+            );
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_compact_all() {
+        block_on(async {
+            let mut opt = options::for_test();
+            opt.write_buffer_size = 4096;
+            let db = AsyncDB::new("db", opt).unwrap();
+            let key = |i: usize| format!("key{:04}", i).into_bytes();
+            db.put_many((0..500).map(|i| (key(i), key(i))))
+                .await
+                .unwrap();
+            for i in (0..500).step_by(2) {
+                db.delete(key(i)).await.unwrap();
+            }
+            db.flush_memtable().await.unwrap();
+            let before = db
+                .approximate_sizes(vec![(key(0), key(500))])
+                .await
+                .unwrap();
+
+            db.compact_all().await.unwrap();
+            let level0 = db
+                .get_property("leveldb.num-files-at-level0".to_string())
+                .await
+                .unwrap();
+            assert_eq!(Some("0".to_string()), level0);
+            // The deleted entries are gone.
+            let after = db
+                .approximate_sizes(vec![(key(0), key(500))])
+                .await
+                .unwrap();
+            assert!(after[0] < before[0]);
+            assert_eq!(None, db.get(key(0)).await.unwrap());
+            assert_eq!(Some(key(1)), db.get(key(1)).await.unwrap());
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_contains_key() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            db.put(b"b".to_vec(), b"2".to_vec()).await.unwrap();
+            db.delete(b"b".to_vec()).await.unwrap();
+            assert_eq!(
+                KeyPresence::Present,
+                db.contains_key(b"a".to_vec()).await.unwrap()
+            );
+            assert_eq!(
+                KeyPresence::Absent,
+                db.contains_key(b"b".to_vec()).await.unwrap()
+            );
+            assert_eq!(
+                KeyPresence::Absent,
+                db.contains_key(b"c".to_vec()).await.unwrap()
+            );
+
+            // Once the key is in a table, its value isn't read.
+            db.flush_memtable().await.unwrap();
+            assert_eq!(
+                KeyPresence::MayExist,
+                db.contains_key(b"a".to_vec()).await.unwrap()
+            );
+            assert_eq!(
+                KeyPresence::Absent,
+                db.contains_key(b"z".to_vec()).await.unwrap()
+            );
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_wait_for_compaction() {
+        block_on(async {
+            let mut opt = options::for_test();
+            opt.write_buffer_size = 4096;
+            opt.target_file_size_base = 1024;
+            opt.defer_compactions = true;
+            let db = AsyncDB::new("db", opt).unwrap();
+            let files = |db: AsyncDB, l: usize| async move {
+                let name = format!("leveldb.num-files-at-level{}", l);
+                let v = db.get_property(name).await.unwrap();
+                v.unwrap().parse::<usize>().unwrap()
+            };
+            // Each batch fills the memtable, and the level compactions are deferred: They only
+            // run step by step between requests.
+            let key = |i: usize| format!("key{:04}", i % 500).into_bytes();
+            for i in 0..20 {
+                db.put_many((i * 100..i * 100 + 100).map(|j| (key(j), vec![b'x'; 50])))
+                    .await
+                    .unwrap();
+            }
+
+            db.wait_for_compaction().await.unwrap();
+            // Level 0 is compacted from four files on.
+            assert!(files(db.clone(), 0).await < 4);
+            let mut deeper = 0;
+            for l in 1..NUM_LEVELS {
+                deeper += files(db.clone(), l).await;
+            }
+            assert!(deeper > 0);
+            assert_eq!(Some(vec![b'x'; 50]), db.get(key(0)).await.unwrap());
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_close() {
+        block_on(async {
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let other = db.clone();
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            other.close().await.unwrap();
+            let r = db.get(b"a".to_vec()).await;
+            assert_eq!(StatusCode::AsyncError, r.unwrap_err().code);
+            assert!(db.put(b"a".to_vec(), b"2".to_vec()).await.is_err());
+            assert!(db.close().await.is_err());
+
+            // Snapshot guards keep the database open; once the last handle is dropped, it is
+            // closed in the background.
+            let db = AsyncDB::new("db", options::for_test()).unwrap();
+            let mut done = db.done.clone();
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+            let snapshot = db.snapshot().await.unwrap();
+            drop(db);
+            assert_eq!(
+                Some(b"1".to_vec()),
+                snapshot.get_at(b"a".to_vec()).await.unwrap()
+            );
+            assert!(!done.has_changed().unwrap());
+            drop(snapshot);
+            let closed = tokio::time::timeout(Duration::from_secs(5), async {
+                while done.changed().await.is_ok() {}
+            });
+            assert!(closed.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_thread_spawner_timeouts() {
+        // Without tokio's time driver, which ThreadSpawner doesn't need.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let async_opts = AsyncDbOptions {
+                buffer_size: Some(1),
+                send_timeout: Some(Duration::from_millis(20)),
+                ..AsyncDbOptions::default()
+            };
+            let db = AsyncDB::with_spawner("db", options::for_test(), async_opts, ThreadSpawner)
+                .unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).await.unwrap();
+
+            let r = slow_update(
+                &db.with_timeout(Some(Duration::from_millis(20))),
+                Duration::from_millis(200),
+            )
+            .await;
+            assert_eq!(StatusCode::AsyncError, r.unwrap_err().code);
+            // The timed out update is still being carried out, so this fills the queue.
+            let queued = tokio::spawn({
+                let db = db.clone();
+                async move { slow_update(&db, Duration::from_millis(0)).await }
+            });
+            tokio::task::yield_now().await;
+            let r = db.get(b"a".to_vec()).await;
+            assert_eq!(StatusCode::WouldBlock, r.unwrap_err().code);
+            assert!(queued.await.unwrap().is_ok());
+
+            assert_eq!(Some(b"1".to_vec()), db.get(b"a".to_vec()).await.unwrap());
+            db.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_asyncdb_read_thread() {
+        block_on(async {
+            let async_opts = AsyncDbOptions {
+                read_thread: true,
+                ..AsyncDbOptions::default()
+            };
+            let db = AsyncDB::with_spawner("db", options::for_test(), async_opts, ThreadSpawner)
+                .unwrap();
+            let keys: Vec<_> = (0..100)
+                .map(|i| format!("key{:03}", i).into_bytes())
+                .collect();
+            db.put_many(keys.iter().map(|k| (k.clone(), k.clone())))
+                .await
+                .unwrap();
+
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    let (db, keys) = (db.clone(), keys.clone());
+                    tokio::spawn(async move {
+                        // The writes below race with these reads, so either value can be seen.
+                        let seen = |k: &Vec<u8>, v: Option<Vec<u8>>| {
+                            v.as_ref() == Some(k) || v.as_deref() == Some(b"new".as_slice())
+                        };
+                        for k in keys.iter() {
+                            assert!(seen(k, db.get(k.clone()).await.unwrap()));
+                        }
+                        let vals = db.multi_get(keys.clone()).await.unwrap();
+                        assert!(keys.iter().zip(vals).all(|(k, v)| seen(k, v)));
+                    })
+                })
+                .collect();
+            for k in keys.iter() {
+                db.put(k.clone(), b"new".to_vec()).await.unwrap();
+                // Reads sent after a write was answered see it.
+                assert_eq!(Some(b"new".to_vec()), db.get(k.clone()).await.unwrap());
+            }
+            db.flush_memtable().await.unwrap();
+            for r in readers {
+                r.await.unwrap();
+            }
+            let vals = db.multi_get(keys.clone()).await.unwrap();
+            assert!(vals.iter().all(|v| v.as_deref() == Some(b"new".as_slice())));
+
+            db.close().await.unwrap();
+            let r = db.get(keys[0].clone()).await;
+            assert_eq!(StatusCode::AsyncError, r.unwrap_err().code);
+        });
+    }
+}
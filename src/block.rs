@@ -3,6 +3,7 @@ use std::cmp::Ordering;
 use std::rc::Rc;
 
 use crate::options::Options;
+use crate::pinned_slice::PinnedSlice;
 use crate::types::LdbIterator;
 
 use integer_encoding::FixedInt;
@@ -10,6 +11,10 @@ use integer_encoding::VarInt;
 
 pub type BlockContents = Vec<u8>;
 
+/// Set in N_RESTARTS if the block's values are prefix-encoded like its keys. The original format
+/// can't contain that many restarts, so blocks without the flag are read as before.
+pub const VALUE_DELTA_FLAG: u32 = 1 << 31;
+
 /// A Block is an immutable ordered set of key/value entries.
 ///
 /// The structure internally looks like follows:
@@ -30,6 +35,11 @@ pub type BlockContents = Vec<u8>;
 /// A RESTART is a fixed u32 pointing to the beginning of an ENTRY.
 ///
 /// N_RESTARTS contains the number of restarts.
+///
+/// If `VALUE_DELTA_FLAG` is set in N_RESTARTS (see `Options::value_delta_encoding`), an ENTRY
+/// consists of four varints, SHARED, NON_SHARED, VAL_SHARED, VALSIZE, a KEY and a VALUE, where
+/// VAL_SHARED denotes how many bytes the entry's value shares with the previous one, and VALUE
+/// only contains the remaining VALSIZE bytes. Values are stored in full at restart points.
 #[derive(Clone)]
 pub struct Block {
     block: Rc<BlockContents>,
@@ -43,11 +53,14 @@ impl Block {
     /// the memory occupied by the block isn't, either)
     pub fn iter(&self) -> BlockIter {
         let restarts = u32::decode_fixed(&self.block[self.block.len() - 4..]);
+        let delta_values = restarts & VALUE_DELTA_FLAG != 0;
+        let restarts = restarts & !VALUE_DELTA_FLAG;
         let restart_offset = self.block.len() - 4 - 4 * restarts as usize;
 
         BlockIter {
             block: self.block.clone(),
             opt: self.opt.clone(),
+            delta_values,
 
             offset: 0,
             restarts_off: restart_offset,
@@ -56,6 +69,7 @@ impl Block {
 
             key: Vec::new(),
             val_offset: 0,
+            val: Vec::new(),
         }
     }
 
@@ -63,6 +77,17 @@ impl Block {
         self.block.clone()
     }
 
+    /// size returns the size of the block's contents in bytes.
+    pub fn size(&self) -> usize {
+        self.block.len()
+    }
+
+    /// pinned returns true if the contents are also referenced by other blocks or iterators, and
+    /// so can't be released with this block.
+    pub fn pinned(&self) -> bool {
+        Rc::strong_count(&self.block) > 1
+    }
+
     pub fn new(opt: Options, contents: BlockContents) -> Block {
         assert!(contents.len() > 4);
         Block {
@@ -79,6 +104,8 @@ pub struct BlockIter {
     /// TODO: Maybe (probably...) this needs an Arc.
     block: Rc<BlockContents>,
     opt: Options,
+    /// Whether values are prefix-encoded (see `VALUE_DELTA_FLAG`).
+    delta_values: bool,
     /// offset of restarts area within the block.
     restarts_off: usize,
 
@@ -93,12 +120,14 @@ pub struct BlockIter {
     key: Vec<u8>,
     /// Offset of the current value within the block.
     val_offset: usize,
+    /// The current full value, if values are prefix-encoded.
+    val: Vec<u8>,
 }
 
 impl BlockIter {
     /// Return the number of restarts in this block.
     fn number_restarts(&self) -> usize {
-        u32::decode_fixed(&self.block[self.block.len() - 4..]) as usize
+        (u32::decode_fixed(&self.block[self.block.len() - 4..]) & !VALUE_DELTA_FLAG) as usize
     }
 
     /// Seek to restart point `ix`. After the seek, current() will return the entry at that restart
@@ -131,7 +160,8 @@ impl BlockIter {
     /// Returns SHARED, NON_SHARED, VALSIZE and [length of length spec] from the current position,
     /// where 'length spec' is the length of the three values in the entry header, as described
     /// above.
-    /// Advances self.offset to the beginning of the next entry.
+    /// Advances self.offset to the beginning of the next entry. For prefix-encoded values, the
+    /// current value is assembled, too.
     fn parse_entry_and_advance(&mut self) -> Option<(usize, usize, usize, usize)> {
         let mut i = 0;
         let (shared, sharedlen) = usize::decode_var(&self.block[self.offset..])?;
@@ -140,12 +170,25 @@ impl BlockIter {
         let (non_shared, non_sharedlen) = usize::decode_var(&self.block[self.offset + i..])?;
         i += non_sharedlen;
 
+        let mut val_shared = 0;
+        if self.delta_values {
+            let (n, n_len) = usize::decode_var(&self.block[self.offset + i..])?;
+            val_shared = n;
+            i += n_len;
+        }
+
         let (valsize, valsizelen) = usize::decode_var(&self.block[self.offset + i..])?;
         i += valsizelen;
 
         self.val_offset = self.offset + i + non_shared;
         self.offset = self.val_offset + valsize;
 
+        if self.delta_values {
+            self.val.truncate(val_shared);
+            self.val
+                .extend_from_slice(&self.block[self.val_offset..self.offset]);
+        }
+
         Some((shared, non_shared, valsize, i))
     }
 
@@ -162,23 +205,18 @@ impl BlockIter {
             .extend_from_slice(&self.block[off..off + non_shared]);
     }
 
-    pub fn seek_to_last(&mut self) -> Option<()> {
-        if self.number_restarts() > 0 {
-            let num_restarts = self.number_restarts();
-            self.seek_to_restart_point(num_restarts - 1)?;
-        } else {
-            self.reset();
-        }
-
-        // Stop at last entry, before the iterator becomes invalid.
-        //
-        // We're checking the position before calling advance; if a restart point points to the
-        // last entry, calling advance() will directly reset the iterator.
-        while self.offset < self.restarts_off {
-            self.advance();
+    /// current_pinned returns the current key, and the current value without copying it, unless
+    /// values are prefix-encoded.
+    pub(crate) fn current_pinned(&self) -> Option<(Vec<u8>, PinnedSlice)> {
+        if !self.valid() {
+            return None;
         }
-        assert!(self.valid());
-        Some(())
+        let val = if self.delta_values {
+            PinnedSlice::from_vec(self.val.clone())
+        } else {
+            PinnedSlice::from_block(self.block.clone(), self.val_offset..self.offset)
+        };
+        Some((self.key.clone(), val))
     }
 }
 
@@ -213,11 +251,38 @@ impl LdbIterator for BlockIter {
         }
     }
 
+    fn seek_to_last(&mut self) {
+        if self.restarts_off == 0 {
+            // An empty block.
+            self.reset();
+            return;
+        }
+        if self.number_restarts() > 0 {
+            let num_restarts = self.number_restarts();
+            if self.seek_to_restart_point(num_restarts - 1).is_none() {
+                self.reset();
+                return;
+            }
+        } else {
+            self.reset();
+        }
+
+        // Stop at last entry, before the iterator becomes invalid.
+        //
+        // We're checking the position before calling advance; if a restart point points to the
+        // last entry, calling advance() will directly reset the iterator.
+        while self.offset < self.restarts_off {
+            self.advance();
+        }
+        assert!(self.valid());
+    }
+
     fn reset(&mut self) {
         self.offset = 0;
         self.val_offset = 0;
         self.current_restart_ix = 0;
         self.key.clear();
+        self.val.clear();
     }
 
     fn prev(&mut self) -> bool {
@@ -301,7 +366,11 @@ impl LdbIterator for BlockIter {
             key.clear();
             val.clear();
             key.extend_from_slice(&self.key);
-            val.extend_from_slice(&self.block[self.val_offset..self.offset]);
+            if self.delta_values {
+                val.extend_from_slice(&self.val);
+            } else {
+                val.extend_from_slice(&self.block[self.val_offset..self.offset]);
+            }
             true
         } else {
             false
@@ -386,6 +455,52 @@ mod tests {
         assert_eq!(i, data.len());
     }
 
+    #[test]
+    fn test_block_value_delta_encoding() {
+        let mut o = options::for_test();
+        o.block_restart_interval = 3;
+        o.value_delta_encoding = true;
+        let data: Vec<(Vec<u8>, Vec<u8>)> = (0..10)
+            .map(|i| {
+                (
+                    format!("key{:02}", i).into_bytes(),
+                    format!("{{\"row\": 1234, \"version\": {}}}", 10 - i).into_bytes(),
+                )
+            })
+            .collect();
+        let mut builder = BlockBuilder::new_data_block(o.clone());
+        let mut plain = BlockBuilder::new(o.clone());
+        for (k, v) in data.iter() {
+            builder.add(k, v);
+            plain.add(k, v);
+        }
+        let block_contents = builder.finish();
+        assert!(block_contents.len() < plain.finish().len() * 3 / 4);
+        let block = Block::new(o.clone(), block_contents);
+
+        let mut iter = block.iter();
+        let entries: Vec<_> = LdbIteratorIter::wrap(&mut iter).collect();
+        assert_eq!(data, entries);
+
+        // Backwards, and from a seek to an entry between restart points.
+        iter.seek_to_last();
+        for i in (0..data.len()).rev() {
+            assert_eq!(Some(data[i].clone()), current_key_val(&iter));
+            iter.prev();
+        }
+        assert!(!iter.valid());
+        iter.seek(b"key05");
+        assert_eq!(Some(data[5].clone()), current_key_val(&iter));
+        iter.prev();
+        assert_eq!(Some(data[4].clone()), current_key_val(&iter));
+
+        let mut builder = BlockBuilder::new_data_block(o.clone());
+        for (k, v) in data.iter().take(4) {
+            builder.add(k, v);
+        }
+        test_iterator_properties(Block::new(o, builder.finish()).iter());
+    }
+
     #[test]
     fn test_block_iterate_reverse() {
         let mut o = options::for_test();
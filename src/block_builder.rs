@@ -1,6 +1,6 @@
 use std::cmp::Ordering;
 
-use crate::block::BlockContents;
+use crate::block::{BlockContents, VALUE_DELTA_FLAG};
 use crate::options::Options;
 
 use integer_encoding::{FixedIntWriter, VarIntWriter};
@@ -13,6 +13,8 @@ pub struct BlockBuilder {
     restarts: Vec<u32>,
 
     last_key: Vec<u8>,
+    // Only maintained if values are prefix-encoded.
+    last_val: Option<Vec<u8>>,
     restart_counter: usize,
     counter: usize,
 }
@@ -27,11 +29,23 @@ impl BlockBuilder {
             opt: o,
             restarts,
             last_key: Vec::new(),
+            last_val: None,
             restart_counter: 0,
             counter: 0,
         }
     }
 
+    /// new_data_block returns a builder for a table's data block, which prefix-encodes values if
+    /// `Options::value_delta_encoding` is set.
+    pub fn new_data_block(o: Options) -> BlockBuilder {
+        let delta = o.value_delta_encoding;
+        let mut b = BlockBuilder::new(o);
+        if delta {
+            b.last_val = Some(Vec::new());
+        }
+        b
+    }
+
     pub fn entries(&self) -> usize {
         self.counter
     }
@@ -48,6 +62,9 @@ impl BlockBuilder {
         self.buffer.clear();
         self.restarts.clear();
         self.last_key.clear();
+        if let Some(ref mut v) = self.last_val {
+            v.clear();
+        }
         self.restart_counter = 0;
         self.counter = 0;
     }
@@ -74,6 +91,9 @@ impl BlockBuilder {
         } else {
             self.restarts.push(self.buffer.len() as u32);
             self.last_key.resize(0, 0);
+            if let Some(ref mut v) = self.last_val {
+                v.clear();
+            }
             self.restart_counter = 0;
         }
 
@@ -85,11 +105,24 @@ impl BlockBuilder {
         self.buffer
             .write_varint(non_shared)
             .expect("write to buffer failed");
+        let mut val_shared = 0;
+        if let Some(ref mut last_val) = self.last_val {
+            val_shared = last_val
+                .iter()
+                .zip(val.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            self.buffer
+                .write_varint(val_shared)
+                .expect("write to buffer failed");
+            last_val.clear();
+            last_val.extend_from_slice(val);
+        }
         self.buffer
-            .write_varint(val.len())
+            .write_varint(val.len() - val_shared)
             .expect("write to buffer failed");
         self.buffer.extend_from_slice(&key[shared..]);
-        self.buffer.extend_from_slice(val);
+        self.buffer.extend_from_slice(&val[val_shared..]);
 
         // Update key
         self.last_key.resize(shared, 0);
@@ -110,8 +143,12 @@ impl BlockBuilder {
         }
 
         // 2. Append N_RESTARTS
+        let mut n_restarts = self.restarts.len() as u32;
+        if self.last_val.is_some() {
+            n_restarts |= VALUE_DELTA_FLAG;
+        }
         self.buffer
-            .write_fixedint(self.restarts.len() as u32)
+            .write_fixedint(n_restarts)
             .expect("write to buffer failed");
 
         // done
@@ -192,14 +192,15 @@ impl<T> Cache<T> {
         self.cap
     }
 
-    /// Insert a new element into the cache. The returned `CacheHandle` can be used for further
-    /// operations on that element.
+    /// Insert a new element into the cache.
     /// If the capacity has been reached, the least recently used element is removed from the
-    /// cache.
-    pub fn insert(&mut self, key: &CacheKey, elem: T) {
+    /// cache and returned.
+    pub fn insert(&mut self, key: &CacheKey, elem: T) -> Option<(CacheKey, T)> {
+        let mut evicted = None;
         if self.list.count() >= self.cap {
             if let Some(removed_key) = self.list.remove_last() {
-                assert!(self.map.remove(&removed_key).is_some());
+                let (removed, _) = self.map.remove(&removed_key).unwrap();
+                evicted = Some((removed_key, removed));
             } else {
                 panic!("could not remove_last(); bug!");
             }
@@ -207,6 +208,7 @@ impl<T> Cache<T> {
 
         let lru_handle = self.list.insert(*key);
         self.map.insert(*key, (elem, lru_handle));
+        evicted
     }
 
     /// Retrieve an element from the cache.
@@ -221,6 +223,17 @@ impl<T> Cache<T> {
         }
     }
 
+    /// Whether the cache contains an element for key. Unlike `get()`, this doesn't count as a
+    /// use of the element.
+    pub fn contains(&self, key: &CacheKey) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// values returns the cached elements, in no particular order. This doesn't count as a use.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.map.values().map(|(elem, _)| elem)
+    }
+
     /// Remove an element from the cache (for invalidation).
     pub fn remove(&mut self, key: &CacheKey) -> Option<T> {
         match self.map.remove(key) {
@@ -282,9 +295,9 @@ mod tests {
 
         cache.insert(&h_123, 123);
         cache.insert(&h_332, 332);
-        cache.insert(&h_521, 521);
-        cache.insert(&h_372, 372);
-        cache.insert(&h_899, 899);
+        assert_eq!(None, cache.insert(&h_521, 521));
+        assert_eq!(Some((h_123, 123)), cache.insert(&h_372, 372));
+        assert_eq!(Some((h_332, 332)), cache.insert(&h_899, 899));
 
         assert_eq!(cache.count(), 3);
 
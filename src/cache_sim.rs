@@ -0,0 +1,246 @@
+//! Block cache simulation: Record the block accesses of a workload (see `Options::block_trace`),
+//! then replay them against caches of different sizes to find out how large the block cache
+//! needs to be.
+//!
+//! ```
+//! use rusty_leveldb::cache_sim::{self, BlockTrace, CachePolicy};
+//! use std::cell::RefCell;
+//! use std::rc::Rc;
+//!
+//! let mut opt = rusty_leveldb::in_memory();
+//! let trace = Rc::new(RefCell::new(BlockTrace::new(1_000_000)));
+//! opt.block_trace = Some(trace.clone());
+//!
+//! let mut db = rusty_leveldb::DB::open("traced", opt).unwrap();
+//! db.put(b"key", b"value").unwrap();
+//! db.flush_memtable().unwrap();
+//! for _ in 0..10 {
+//!     db.get(b"key").unwrap();
+//! }
+//!
+//! let results = cache_sim::simulate(&trace.borrow(), CachePolicy::Lru, &[0, 1 << 20]);
+//! assert_eq!(0.0, results[0].hit_rate());
+//! assert!(results[1].hit_rate() > 0.5);
+//! ```
+
+use crate::error::{err, Result, StatusCode};
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+
+/// The encoded size of a `BlockAccess`.
+const ACCESS_SIZE: usize = 20;
+
+/// BlockAccess is a read of a block, through the block cache.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockAccess {
+    /// Identifies the open table the block belongs to. A table that is opened again gets a new
+    /// id, like it does in the block cache.
+    pub table: u64,
+    pub offset: u64,
+    /// The size of the block's (uncompressed) contents.
+    pub size: u32,
+}
+
+/// BlockTrace records the block accesses of the databases using it (see `Options::block_trace`),
+/// up to a limit.
+pub struct BlockTrace {
+    accesses: Vec<BlockAccess>,
+    limit: usize,
+}
+
+impl BlockTrace {
+    /// new creates a trace that records up to limit accesses; later ones are dropped.
+    pub fn new(limit: usize) -> BlockTrace {
+        BlockTrace {
+            accesses: vec![],
+            limit,
+        }
+    }
+
+    pub fn record(&mut self, access: BlockAccess) {
+        if self.accesses.len() < self.limit {
+            self.accesses.push(access);
+        }
+    }
+
+    pub fn accesses(&self) -> &[BlockAccess] {
+        &self.accesses
+    }
+
+    /// is_full returns true once the trace has recorded as many accesses as it can.
+    pub fn is_full(&self) -> bool {
+        self.accesses.len() >= self.limit
+    }
+
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+    }
+
+    /// encode serializes the trace, e.g. to simulate it later or on another machine.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.accesses.len() * ACCESS_SIZE);
+        for a in &self.accesses {
+            buf.extend_from_slice(&a.table.to_le_bytes());
+            buf.extend_from_slice(&a.offset.to_le_bytes());
+            buf.extend_from_slice(&a.size.to_le_bytes());
+        }
+        buf
+    }
+
+    /// decode reads a trace serialized by `encode()`.
+    pub fn decode(buf: &[u8]) -> Result<BlockTrace> {
+        let chunks = buf.chunks_exact(ACCESS_SIZE);
+        if !chunks.remainder().is_empty() {
+            return err(StatusCode::Corruption, "block trace has a partial entry");
+        }
+        let accesses: Vec<_> = chunks
+            .map(|c| BlockAccess {
+                table: u64::from_le_bytes(c[0..8].try_into().unwrap()),
+                offset: u64::from_le_bytes(c[8..16].try_into().unwrap()),
+                size: u32::from_le_bytes(c[16..20].try_into().unwrap()),
+            })
+            .collect();
+        Ok(BlockTrace {
+            limit: accesses.len(),
+            accesses,
+        })
+    }
+}
+
+/// CachePolicy determines which block a simulated cache evicts when it is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CachePolicy {
+    /// Evict the least recently used block, like the block cache does.
+    Lru,
+    /// Evict the block that was inserted first.
+    Fifo,
+}
+
+/// SimulatedCache is the result of replaying a trace against a cache of `capacity` bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimulatedCache {
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// The bytes of blocks read because they weren't in the cache.
+    pub miss_bytes: u64,
+}
+
+impl SimulatedCache {
+    /// hit_rate returns the fraction of accesses that found their block in the cache, or 0 for
+    /// an empty trace.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// simulate replays the trace against caches of each of the capacities (in bytes of block
+/// contents) with the given eviction policy, starting out empty.
+pub fn simulate(
+    trace: &BlockTrace,
+    policy: CachePolicy,
+    capacities: &[usize],
+) -> Vec<SimulatedCache> {
+    capacities
+        .iter()
+        .map(|&capacity| simulate_one(trace.accesses(), policy, capacity))
+        .collect()
+}
+
+fn simulate_one(accesses: &[BlockAccess], policy: CachePolicy, capacity: usize) -> SimulatedCache {
+    let mut result = SimulatedCache {
+        capacity,
+        hits: 0,
+        misses: 0,
+        miss_bytes: 0,
+    };
+    // Maps blocks to their size and position in the eviction order; order maps positions back.
+    let mut blocks: HashMap<(u64, u64), (usize, u64)> = HashMap::new();
+    let mut order: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    let mut used = 0;
+
+    for (tick, a) in accesses.iter().enumerate() {
+        let tick = tick as u64;
+        let key = (a.table, a.offset);
+        if let Some((_, pos)) = blocks.get_mut(&key) {
+            result.hits += 1;
+            if policy == CachePolicy::Lru {
+                order.remove(pos);
+                order.insert(tick, key);
+                *pos = tick;
+            }
+            continue;
+        }
+        result.misses += 1;
+        result.miss_bytes += a.size as u64;
+        let size = a.size as usize;
+        if size > capacity {
+            continue;
+        }
+        while used + size > capacity {
+            let (_, evicted) = order.pop_first().unwrap();
+            used -= blocks.remove(&evicted).unwrap().0;
+        }
+        blocks.insert(key, (size, tick));
+        order.insert(tick, key);
+        used += size;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(table: u64, offset: u64) -> BlockAccess {
+        BlockAccess {
+            table,
+            offset,
+            size: 100,
+        }
+    }
+
+    #[test]
+    fn test_cache_sim_simulate() {
+        let mut trace = BlockTrace::new(1000);
+        // A working set of three blocks, with a scan over other blocks in between.
+        for i in 0..10 {
+            for b in 0..3 {
+                trace.record(access(1, b));
+            }
+            trace.record(access(2, i));
+        }
+        assert_eq!(40, trace.accesses().len());
+
+        let lru = simulate(&trace, CachePolicy::Lru, &[0, 300, 400, 10000]);
+        assert_eq!(
+            vec![(0, 40), (0, 40), (27, 13), (27, 13)],
+            lru.iter().map(|r| (r.hits, r.misses)).collect::<Vec<_>>()
+        );
+        assert_eq!(1300, lru[3].miss_bytes);
+        assert_eq!(27. / 40., lru[3].hit_rate());
+
+        // FIFO evicts the working set too, as it is inserted before the scanned blocks.
+        let fifo = simulate(&trace, CachePolicy::Fifo, &[400]);
+        assert!(fifo[0].hits < lru[2].hits);
+
+        let decoded = BlockTrace::decode(&trace.encode()).unwrap();
+        assert_eq!(trace.accesses(), decoded.accesses());
+        assert!(decoded.is_full());
+        assert!(BlockTrace::decode(&[0; 7]).is_err());
+
+        let mut small = BlockTrace::new(2);
+        for i in 0..3 {
+            small.record(access(1, i));
+        }
+        assert_eq!(2, small.accesses().len());
+        small.clear();
+        assert_eq!(0., simulate(&small, CachePolicy::Lru, &[100])[0].hit_rate());
+    }
+}
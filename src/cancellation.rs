@@ -0,0 +1,42 @@
+use crate::error::{err, Result, StatusCode};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// CancellationToken aborts long-running operations that accept one, such as
+/// `DB::verify_integrity()` or `DB::compact_range_with_progress()` (see
+/// `CompactionProgress::with_cancellation()`). Operations check the token between steps, and
+/// return `StatusCode::Incomplete` once it is cancelled, leaving the database consistent.
+///
+/// Clones share the same state and can be sent to other threads, so one token can cancel all
+/// maintenance work during shutdown.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// check returns an error if the token is cancelled.
+    pub(crate) fn check(&self, operation: &str) -> Result<()> {
+        if self.is_cancelled() {
+            err(StatusCode::Incomplete, &format!("{} cancelled", operation))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// check_cancelled is `CancellationToken::check()` for optional tokens.
+pub(crate) fn check_cancelled(token: Option<&CancellationToken>, operation: &str) -> Result<()> {
+    token.map_or(Ok(()), |t| t.check(operation))
+}
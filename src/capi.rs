@@ -0,0 +1,767 @@
+//! A C API following LevelDB's `include/leveldb/c.h` (enabled by the `capi` feature), so that
+//! programs and bindings written against the C++ implementation can use this one.
+//!
+//! Supported are databases, options, reads and writes, write batches, iterators, snapshots,
+//! properties and approximate sizes.
+//! Options that take C++ objects (comparators, filter policies, caches, environments and info
+//! logs) are not.
+//!
+//! The usual C API rules apply: Objects are freed with the matching `_destroy` function; values
+//! returned by `leveldb_get()` and `leveldb_property_value()` and error messages are freed with
+//! `leveldb_free()`. When an error occurs, `*errptr` is set to a message (a previous message is
+//! freed first); it is left alone otherwise. Keys returned by iterators stay valid until the
+//! iterator is moved.
+
+#![allow(
+    non_camel_case_types,
+    non_upper_case_globals,
+    clippy::missing_safety_doc
+)]
+
+use crate::compressor::{CompressorId, NoneCompressor, SnappyCompressor};
+use crate::error::Status;
+use crate::options::{Options, ReadOptions};
+use crate::snapshot::Snapshot;
+use crate::{DBIterator, LdbIterator, WriteBatch, DB};
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uchar, c_void};
+use std::ptr;
+use std::slice;
+
+pub struct leveldb_t {
+    db: DB,
+}
+
+pub struct leveldb_options_t {
+    opt: Options,
+}
+
+pub struct leveldb_readoptions_t {
+    snapshot: Option<Snapshot>,
+    ropt: ReadOptions,
+}
+
+pub struct leveldb_writeoptions_t {
+    sync: bool,
+}
+
+pub struct leveldb_writebatch_t {
+    batch: WriteBatch,
+}
+
+pub struct leveldb_snapshot_t {
+    snapshot: Snapshot,
+}
+
+pub struct leveldb_iterator_t {
+    iter: DBIterator,
+    // The current entry, as iterators only copy it out.
+    key: Vec<u8>,
+    val: Vec<u8>,
+    valid: bool,
+}
+
+pub const leveldb_no_compression: c_int = 0;
+pub const leveldb_snappy_compression: c_int = 1;
+
+unsafe fn save_error(errptr: *mut *mut c_char, e: Status) {
+    if errptr.is_null() {
+        return;
+    }
+    if !(*errptr).is_null() {
+        libc::free(*errptr as *mut c_void);
+    }
+    let msg = CString::new(e.err.replace('\0', "")).unwrap_or_default();
+    *errptr = libc::strdup(msg.as_ptr());
+}
+
+unsafe fn bytes<'a>(p: *const c_char, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+    slice::from_raw_parts(p as *const u8, len)
+}
+
+/// copy_out returns a copy of b allocated with malloc(), to be freed with `leveldb_free()`.
+unsafe fn copy_out(b: &[u8]) -> *mut c_char {
+    let p = libc::malloc(b.len().max(1)) as *mut c_char;
+    if !p.is_null() {
+        ptr::copy_nonoverlapping(b.as_ptr() as *const c_char, p, b.len());
+    }
+    p
+}
+
+// DB //
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_open(
+    options: *const leveldb_options_t,
+    name: *const c_char,
+    errptr: *mut *mut c_char,
+) -> *mut leveldb_t {
+    let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+    match DB::open(name, (*options).opt.clone()) {
+        Ok(db) => Box::into_raw(Box::new(leveldb_t { db })),
+        Err(e) => {
+            save_error(errptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_close(db: *mut leveldb_t) {
+    drop(Box::from_raw(db));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_put(
+    db: *mut leveldb_t,
+    options: *const leveldb_writeoptions_t,
+    key: *const c_char,
+    keylen: usize,
+    val: *const c_char,
+    vallen: usize,
+    errptr: *mut *mut c_char,
+) {
+    let mut batch = WriteBatch::new();
+    batch.put(bytes(key, keylen), bytes(val, vallen));
+    if let Err(e) = (*db).db.write(batch, (*options).sync) {
+        save_error(errptr, e);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_delete(
+    db: *mut leveldb_t,
+    options: *const leveldb_writeoptions_t,
+    key: *const c_char,
+    keylen: usize,
+    errptr: *mut *mut c_char,
+) {
+    let mut batch = WriteBatch::new();
+    batch.delete(bytes(key, keylen));
+    if let Err(e) = (*db).db.write(batch, (*options).sync) {
+        save_error(errptr, e);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_write(
+    db: *mut leveldb_t,
+    options: *const leveldb_writeoptions_t,
+    batch: *mut leveldb_writebatch_t,
+    errptr: *mut *mut c_char,
+) {
+    // The caller keeps the batch, e.g. to clear and reuse it.
+    let mut copy = WriteBatch::new();
+    append(&mut copy, &(*batch).batch);
+    if let Err(e) = (*db).db.write(copy, (*options).sync) {
+        save_error(errptr, e);
+    }
+}
+
+/// leveldb_get returns the value of key, or NULL if it isn't found. The value is not
+/// NUL-terminated.
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_get(
+    db: *mut leveldb_t,
+    options: *const leveldb_readoptions_t,
+    key: *const c_char,
+    keylen: usize,
+    vallen: *mut usize,
+    errptr: *mut *mut c_char,
+) -> *mut c_char {
+    let key = bytes(key, keylen);
+    let r = match &(*options).snapshot {
+        Some(s) => (*db).db.get_at_with(s, key, &(*options).ropt),
+        None => (*db).db.get_with(key, &(*options).ropt),
+    };
+    match r {
+        Ok(Some(v)) => {
+            *vallen = v.len();
+            copy_out(&v)
+        }
+        Ok(None) => {
+            *vallen = 0;
+            ptr::null_mut()
+        }
+        Err(e) => {
+            *vallen = 0;
+            save_error(errptr, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// leveldb_property_value returns the value of a property (see `DB::get_property()`) as a
+/// NUL-terminated string, or NULL if there is no such property.
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_property_value(
+    db: *mut leveldb_t,
+    propname: *const c_char,
+) -> *mut c_char {
+    let value = CStr::from_ptr(propname)
+        .to_str()
+        .ok()
+        .and_then(|name| (*db).db.get_property(name))
+        .and_then(|v| CString::new(v).ok());
+    match value {
+        Some(v) => libc::strdup(v.as_ptr()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// leveldb_approximate_sizes stores the approximate bytes on disk taken by each of the ranges
+/// [start_key; limit_key) in sizes (see `DB::approximate_sizes()`).
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_approximate_sizes(
+    db: *mut leveldb_t,
+    num_ranges: c_int,
+    range_start_key: *const *const c_char,
+    range_start_key_len: *const usize,
+    range_limit_key: *const *const c_char,
+    range_limit_key_len: *const usize,
+    sizes: *mut u64,
+) {
+    let n = num_ranges.max(0) as usize;
+    let ranges: Vec<_> = (0..n)
+        .map(|i| {
+            (
+                bytes(*range_start_key.add(i), *range_start_key_len.add(i)),
+                bytes(*range_limit_key.add(i), *range_limit_key_len.add(i)),
+            )
+        })
+        .collect();
+    for (i, size) in (*db).db.approximate_sizes(&ranges).into_iter().enumerate() {
+        *sizes.add(i) = size;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_create_snapshot(db: *mut leveldb_t) -> *const leveldb_snapshot_t {
+    let snapshot = (*db).db.get_snapshot();
+    Box::into_raw(Box::new(leveldb_snapshot_t { snapshot }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_release_snapshot(
+    _db: *mut leveldb_t,
+    snapshot: *const leveldb_snapshot_t,
+) {
+    drop(Box::from_raw(snapshot as *mut leveldb_snapshot_t));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_free(ptr: *mut c_void) {
+    libc::free(ptr);
+}
+
+#[no_mangle]
+pub extern "C" fn leveldb_major_version() -> c_int {
+    1
+}
+
+#[no_mangle]
+pub extern "C" fn leveldb_minor_version() -> c_int {
+    23
+}
+
+// ITERATORS //
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_create_iterator(
+    db: *mut leveldb_t,
+    options: *const leveldb_readoptions_t,
+) -> *mut leveldb_iterator_t {
+    let ropt = &(*options).ropt;
+    let r = match &(*options).snapshot {
+        Some(s) => (*db).db.new_iter_at_with(s.clone(), ropt),
+        None => (*db).db.new_iter_with(ropt),
+    };
+    // The C API has no way to report this error.
+    let iter = match r {
+        Ok(iter) => iter,
+        Err(_) => return ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(leveldb_iterator_t {
+        iter,
+        key: vec![],
+        val: vec![],
+        valid: false,
+    }))
+}
+
+impl leveldb_iterator_t {
+    fn load(&mut self) {
+        self.valid = self.iter.current(&mut self.key, &mut self.val);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_destroy(iter: *mut leveldb_iterator_t) {
+    drop(Box::from_raw(iter));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_valid(iter: *const leveldb_iterator_t) -> c_uchar {
+    (*iter).valid as c_uchar
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_seek_to_first(iter: *mut leveldb_iterator_t) {
+    let it = &mut *iter;
+    it.iter.reset();
+    it.iter.advance();
+    it.load();
+}
+
+/// leveldb_iter_seek_to_last moves to the last entry.
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_seek_to_last(iter: *mut leveldb_iterator_t) {
+    let it = &mut *iter;
+    it.iter.seek_to_last();
+    it.load();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_seek(
+    iter: *mut leveldb_iterator_t,
+    k: *const c_char,
+    klen: usize,
+) {
+    let it = &mut *iter;
+    it.iter.seek(bytes(k, klen));
+    it.load();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_next(iter: *mut leveldb_iterator_t) {
+    let it = &mut *iter;
+    it.iter.advance();
+    it.load();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_prev(iter: *mut leveldb_iterator_t) {
+    let it = &mut *iter;
+    it.iter.prev();
+    it.load();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_key(
+    iter: *const leveldb_iterator_t,
+    klen: *mut usize,
+) -> *const c_char {
+    *klen = (*iter).key.len();
+    (*iter).key.as_ptr() as *const c_char
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_value(
+    iter: *const leveldb_iterator_t,
+    vlen: *mut usize,
+) -> *const c_char {
+    *vlen = (*iter).val.len();
+    (*iter).val.as_ptr() as *const c_char
+}
+
+/// leveldb_iter_get_error never reports an error: Entries that can't be read are skipped.
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_iter_get_error(
+    _iter: *const leveldb_iterator_t,
+    _errptr: *mut *mut c_char,
+) {
+}
+
+// WRITE BATCHES //
+
+#[no_mangle]
+pub extern "C" fn leveldb_writebatch_create() -> *mut leveldb_writebatch_t {
+    Box::into_raw(Box::new(leveldb_writebatch_t {
+        batch: WriteBatch::new(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writebatch_destroy(b: *mut leveldb_writebatch_t) {
+    drop(Box::from_raw(b));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writebatch_clear(b: *mut leveldb_writebatch_t) {
+    (*b).batch.clear();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writebatch_put(
+    b: *mut leveldb_writebatch_t,
+    key: *const c_char,
+    klen: usize,
+    val: *const c_char,
+    vlen: usize,
+) {
+    (*b).batch.put(bytes(key, klen), bytes(val, vlen));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writebatch_delete(
+    b: *mut leveldb_writebatch_t,
+    key: *const c_char,
+    klen: usize,
+) {
+    (*b).batch.delete(bytes(key, klen));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writebatch_iterate(
+    b: *const leveldb_writebatch_t,
+    state: *mut c_void,
+    put: extern "C" fn(*mut c_void, *const c_char, usize, *const c_char, usize),
+    deleted: extern "C" fn(*mut c_void, *const c_char, usize),
+) {
+    for (k, v) in (*b).batch.iter() {
+        let kp = k.as_ptr() as *const c_char;
+        match v {
+            Some(v) => put(state, kp, k.len(), v.as_ptr() as *const c_char, v.len()),
+            None => deleted(state, kp, k.len()),
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writebatch_append(
+    destination: *mut leveldb_writebatch_t,
+    source: *const leveldb_writebatch_t,
+) {
+    append(&mut (*destination).batch, &(*source).batch);
+}
+
+fn append(destination: &mut WriteBatch, source: &WriteBatch) {
+    for (k, v) in source.iter() {
+        match v {
+            Some(v) => destination.put(k, v),
+            None => destination.delete(k),
+        }
+    }
+}
+
+// OPTIONS //
+
+#[no_mangle]
+pub extern "C" fn leveldb_options_create() -> *mut leveldb_options_t {
+    Box::into_raw(Box::new(leveldb_options_t {
+        opt: Options::default(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_destroy(options: *mut leveldb_options_t) {
+    drop(Box::from_raw(options));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_create_if_missing(
+    options: *mut leveldb_options_t,
+    v: c_uchar,
+) {
+    (*options).opt.create_if_missing = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_error_if_exists(
+    options: *mut leveldb_options_t,
+    v: c_uchar,
+) {
+    (*options).opt.error_if_exists = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_paranoid_checks(
+    options: *mut leveldb_options_t,
+    v: c_uchar,
+) {
+    (*options).opt.paranoid_checks = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_write_buffer_size(
+    options: *mut leveldb_options_t,
+    size: usize,
+) {
+    (*options).opt.write_buffer_size = size;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_max_open_files(
+    options: *mut leveldb_options_t,
+    n: c_int,
+) {
+    (*options).opt.max_open_files = n.max(0) as usize;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_block_size(
+    options: *mut leveldb_options_t,
+    size: usize,
+) {
+    (*options).opt.block_size = size;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_block_restart_interval(
+    options: *mut leveldb_options_t,
+    n: c_int,
+) {
+    (*options).opt.block_restart_interval = n.max(0) as usize;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_max_file_size(
+    options: *mut leveldb_options_t,
+    size: usize,
+) {
+    (*options).opt.target_file_size_base = size;
+}
+
+/// leveldb_options_set_compression selects `leveldb_no_compression` or
+/// `leveldb_snappy_compression`; other values are ignored.
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_options_set_compression(
+    options: *mut leveldb_options_t,
+    t: c_int,
+) {
+    match t {
+        leveldb_no_compression => (*options).opt.compressor = NoneCompressor::ID,
+        leveldb_snappy_compression => (*options).opt.compressor = SnappyCompressor::ID,
+        _ => {}
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn leveldb_readoptions_create() -> *mut leveldb_readoptions_t {
+    Box::into_raw(Box::new(leveldb_readoptions_t {
+        snapshot: None,
+        ropt: ReadOptions::default(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_readoptions_destroy(options: *mut leveldb_readoptions_t) {
+    drop(Box::from_raw(options));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_readoptions_set_verify_checksums(
+    options: *mut leveldb_readoptions_t,
+    v: c_uchar,
+) {
+    (*options).ropt.verify_checksums = v != 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_readoptions_set_fill_cache(
+    options: *mut leveldb_readoptions_t,
+    v: c_uchar,
+) {
+    (*options).ropt.fill_cache = v != 0;
+}
+
+/// leveldb_readoptions_set_snapshot makes reads use snapshot, or the current state if it is
+/// NULL.
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_readoptions_set_snapshot(
+    options: *mut leveldb_readoptions_t,
+    snapshot: *const leveldb_snapshot_t,
+) {
+    (*options).snapshot = snapshot.as_ref().map(|s| s.snapshot.clone());
+}
+
+#[no_mangle]
+pub extern "C" fn leveldb_writeoptions_create() -> *mut leveldb_writeoptions_t {
+    Box::into_raw(Box::new(leveldb_writeoptions_t { sync: false }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writeoptions_destroy(options: *mut leveldb_writeoptions_t) {
+    drop(Box::from_raw(options));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn leveldb_writeoptions_set_sync(
+    options: *mut leveldb_writeoptions_t,
+    v: c_uchar,
+) {
+    (*options).sync = v != 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+
+    unsafe fn get(
+        db: *mut leveldb_t,
+        ro: *const leveldb_readoptions_t,
+        k: &str,
+    ) -> Option<Vec<u8>> {
+        let mut err = ptr::null_mut();
+        let mut len = 0;
+        let v = leveldb_get(
+            db,
+            ro,
+            k.as_ptr() as *const c_char,
+            k.len(),
+            &mut len,
+            &mut err,
+        );
+        assert!(err.is_null());
+        if v.is_null() {
+            return None;
+        }
+        let r = bytes(v, len).to_vec();
+        leveldb_free(v as *mut c_void);
+        Some(r)
+    }
+
+    extern "C" fn count_put(
+        state: *mut c_void,
+        _: *const c_char,
+        _: usize,
+        _: *const c_char,
+        _: usize,
+    ) {
+        unsafe { (*(state as *mut (usize, usize))).0 += 1 };
+    }
+
+    extern "C" fn count_delete(state: *mut c_void, _: *const c_char, _: usize) {
+        unsafe { (*(state as *mut (usize, usize))).1 += 1 };
+    }
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let opt = leveldb_options_create();
+            (*opt).opt = options::for_test();
+            let name = CString::new("db").unwrap();
+            let mut err = ptr::null_mut();
+
+            leveldb_options_set_create_if_missing(opt, 0);
+            assert!(leveldb_open(opt, name.as_ptr(), &mut err).is_null());
+            assert!(!err.is_null());
+            leveldb_options_set_create_if_missing(opt, 1);
+            leveldb_options_set_compression(opt, leveldb_snappy_compression);
+            let db = leveldb_open(opt, name.as_ptr(), &mut err);
+            assert!(!db.is_null());
+            leveldb_free(err as *mut c_void);
+            err = ptr::null_mut();
+
+            let wo = leveldb_writeoptions_create();
+            let ro = leveldb_readoptions_create();
+            leveldb_put(
+                db,
+                wo,
+                "a".as_ptr() as *const c_char,
+                1,
+                "1".as_ptr() as *const c_char,
+                1,
+                &mut err,
+            );
+            assert!(err.is_null());
+            let snapshot = leveldb_create_snapshot(db);
+
+            let wb = leveldb_writebatch_create();
+            leveldb_writebatch_put(
+                wb,
+                "b".as_ptr() as *const c_char,
+                1,
+                "2".as_ptr() as *const c_char,
+                1,
+            );
+            leveldb_writebatch_put(
+                wb,
+                "c".as_ptr() as *const c_char,
+                1,
+                "3".as_ptr() as *const c_char,
+                1,
+            );
+            leveldb_writebatch_delete(wb, "a".as_ptr() as *const c_char, 1);
+            let mut counts = (0usize, 0usize);
+            leveldb_writebatch_iterate(
+                wb,
+                &mut counts as *mut _ as *mut c_void,
+                count_put,
+                count_delete,
+            );
+            assert_eq!((2, 1), counts);
+            leveldb_write(db, wo, wb, &mut err);
+            assert!(err.is_null());
+            leveldb_writebatch_destroy(wb);
+
+            assert_eq!(None, get(db, ro, "a"));
+            assert_eq!(Some(b"2".to_vec()), get(db, ro, "b"));
+            leveldb_readoptions_set_verify_checksums(ro, 1);
+            leveldb_readoptions_set_fill_cache(ro, 0);
+            assert_eq!(Some(b"2".to_vec()), get(db, ro, "b"));
+            assert!((*ro).ropt.verify_checksums && !(*ro).ropt.fill_cache);
+            leveldb_readoptions_set_snapshot(ro, snapshot);
+            assert_eq!(Some(b"1".to_vec()), get(db, ro, "a"));
+            assert_eq!(None, get(db, ro, "b"));
+            leveldb_readoptions_set_snapshot(ro, ptr::null());
+            leveldb_release_snapshot(db, snapshot);
+
+            let it = leveldb_create_iterator(db, ro);
+            let mut keys = vec![];
+            leveldb_iter_seek_to_first(it);
+            while leveldb_iter_valid(it) != 0 {
+                let mut len = 0;
+                let k = leveldb_iter_key(it, &mut len);
+                keys.push(bytes(k, len).to_vec());
+                leveldb_iter_next(it);
+            }
+            assert_eq!(vec![b"b".to_vec(), b"c".to_vec()], keys);
+            leveldb_iter_seek(it, "bb".as_ptr() as *const c_char, 2);
+            let mut len = 0;
+            assert_eq!(b"3", bytes(leveldb_iter_value(it, &mut len), len));
+            leveldb_iter_prev(it);
+            assert_eq!(b"b", bytes(leveldb_iter_key(it, &mut len), len));
+            leveldb_iter_seek_to_last(it);
+            assert_eq!(b"c", bytes(leveldb_iter_key(it, &mut len), len));
+            leveldb_iter_get_error(it, &mut err);
+            assert!(err.is_null());
+            leveldb_iter_destroy(it);
+
+            leveldb_delete(db, wo, "b".as_ptr() as *const c_char, 1, &mut err);
+            assert_eq!(None, get(db, ro, "b"));
+
+            let prop = CString::new("leveldb.num-files-at-level0").unwrap();
+            let v = leveldb_property_value(db, prop.as_ptr());
+            assert_eq!(b"0", CStr::from_ptr(v).to_bytes());
+            leveldb_free(v as *mut c_void);
+            let prop = CString::new("leveldb.unknown").unwrap();
+            assert!(leveldb_property_value(db, prop.as_ptr()).is_null());
+
+            (*db).db.compact_all().unwrap();
+            let (start, limit) = (["a".as_ptr(), "x".as_ptr()], ["z".as_ptr(), "y".as_ptr()]);
+            let mut sizes = [u64::MAX; 2];
+            leveldb_approximate_sizes(
+                db,
+                2,
+                start.as_ptr() as *const *const c_char,
+                [1, 1].as_ptr(),
+                limit.as_ptr() as *const *const c_char,
+                [1, 1].as_ptr(),
+                sizes.as_mut_ptr(),
+            );
+            assert!(sizes[0] > 0);
+            assert_eq!(0, sizes[1]);
+
+            leveldb_readoptions_destroy(ro);
+            leveldb_writeoptions_destroy(wo);
+            leveldb_close(db);
+            leveldb_options_destroy(opt);
+        }
+    }
+}
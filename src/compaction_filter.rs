@@ -0,0 +1,15 @@
+//! Compaction filters drop entries while compactions rewrite them (see
+//! `Options::compaction_filter`), e.g. values that have expired (see `TtlDB`).
+
+/// CompactionFilter decides which values are removed by compactions. It is only asked about
+/// values that no snapshot can see an older version of, and is called from within compactions,
+/// i.e. it must not use the database.
+///
+/// A removed value is replaced by a deletion marker, unless no older version of its key can
+/// exist in deeper levels, so that these don't become visible again. Values that are never
+/// compacted are not filtered, so reads have to apply the same condition if it matters that a
+/// value is gone at a given time.
+pub trait CompactionFilter {
+    /// filter returns true if the value of key is to be removed.
+    fn filter(&self, key: &[u8], value: &[u8]) -> bool;
+}
@@ -0,0 +1,67 @@
+use crate::cancellation::CancellationToken;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Default)]
+struct Progress {
+    processed: AtomicUsize,
+    total: AtomicUsize,
+    cancel: CancellationToken,
+}
+
+/// CompactionProgress follows a manual compaction started by `DB::compact_range_with_progress()`,
+/// and can cancel it. Clones refer to the same compaction; they can be sent to other threads,
+/// e.g. to cancel a compaction running in an `AsyncDB` or `MutexDB`.
+#[derive(Clone, Default)]
+pub struct CompactionProgress(Arc<Progress>);
+
+impl CompactionProgress {
+    pub fn new() -> CompactionProgress {
+        CompactionProgress::default()
+    }
+
+    /// with_cancellation creates a CompactionProgress that is cancelled together with token.
+    pub fn with_cancellation(token: CancellationToken) -> CompactionProgress {
+        CompactionProgress(Arc::new(Progress {
+            cancel: token,
+            ..Progress::default()
+        }))
+    }
+
+    /// processed_bytes returns the size of the data compacted so far: the memtable, and the
+    /// input files of the compactions that finished.
+    pub fn processed_bytes(&self) -> usize {
+        self.0.processed.load(Ordering::Relaxed)
+    }
+
+    /// total_bytes returns an estimate of the size of all data to be compacted, which is known
+    /// once the compaction has started. It is based on the files overlapping the range at that
+    /// time, and may be exceeded by `processed_bytes()`.
+    pub fn total_bytes(&self) -> usize {
+        self.0.total.load(Ordering::Relaxed)
+    }
+
+    /// cancel stops the compaction once the running step (compacting the memtable, or a set of
+    /// files into the next level) has finished; the database stays consistent, and the range
+    /// stays partially compacted. The compaction then returns `StatusCode::Incomplete`.
+    pub fn cancel(&self) {
+        self.0.cancel.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancel.is_cancelled()
+    }
+
+    pub(crate) fn token(&self) -> &CancellationToken {
+        &self.0.cancel
+    }
+
+    pub(crate) fn set_total(&self, bytes: usize) {
+        self.0.total.store(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_processed(&self, bytes: usize) {
+        self.0.processed.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
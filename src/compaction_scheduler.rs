@@ -0,0 +1,62 @@
+//! A `CompactionScheduler` lets the embedder decide when level compactions run, and which one of
+//! several pending compactions runs first. Memtable compactions are not scheduled, as writes wait
+//! for them.
+
+/// Why a level compaction is pending.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionReason {
+    /// The level is larger than its limit.
+    Size,
+    /// A file was read from too often without yielding a result (see `Options::seek_compaction`).
+    Seek,
+    /// A file contains many deletion markers (see `Options::deletion_compaction_ratio`).
+    Deletion,
+    /// The space amplification is too high (see `Options::max_space_amplification`).
+    SpaceAmplification,
+}
+
+/// CompactionJob describes a pending level compaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompactionJob {
+    pub reason: CompactionReason,
+    /// The level compacted into `level + 1`.
+    pub level: usize,
+    /// For size compactions, the ratio of the level's size to its limit (at least 1). 0 for other
+    /// compactions.
+    pub score: f64,
+    /// The key range of the file that starts the compaction. Overlapping files in both levels are
+    /// added to the compaction when it runs.
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    /// The size of the file that starts the compaction, in bytes.
+    pub file_size: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionDecision {
+    /// Run the job at this index of the pending jobs.
+    Run(usize),
+    /// Don't compact now. The database asks again after the next write, or when
+    /// `DB::compact_pending()` is called.
+    Defer,
+}
+
+/// CompactionScheduler is consulted every time the database is about to run a level compaction.
+pub trait CompactionScheduler {
+    /// schedule decides which of the pending jobs runs next. `jobs` is never empty, and is ordered
+    /// by urgency, as the database would run them: size compactions by descending score, then
+    /// seek and deletion compactions.
+    ///
+    /// Deferring lets level 0 grow and slows down reads; once level 0 contains so many files that
+    /// writes would stop in the original implementation, compactions run regardless.
+    fn schedule(&self, jobs: &[CompactionJob]) -> CompactionDecision;
+}
+
+/// DefaultCompactionScheduler always runs the most urgent job.
+pub struct DefaultCompactionScheduler;
+
+impl CompactionScheduler for DefaultCompactionScheduler {
+    fn schedule(&self, _: &[CompactionJob]) -> CompactionDecision {
+        CompactionDecision::Run(0)
+    }
+}
@@ -5,37 +5,83 @@
 
 use crate::db_iter::DBIterator;
 
-use crate::cmp::{Cmp, InternalKeyCmp};
-use crate::env::{Env, FileLock};
+use crate::blockhandle::BlockHandle;
+use crate::cancellation::{check_cancelled, CancellationToken};
+use crate::cmp::{Cmp, DefaultCmp, InternalKeyCmp};
+use crate::compaction_progress::CompactionProgress;
+use crate::compaction_scheduler::{CompactionDecision, CompactionReason};
+use crate::env::{Env, FileLock, IoPriority, RandomAccessReader};
+use crate::env_common::Instant;
 use crate::error::{err, Result, StatusCode};
+use crate::event_listener::{CompactionInfo, FlushInfo, TableFileInfo, TableFileReason};
 use crate::filter::{BoxedFilterPolicy, InternalFilterPolicy};
+use crate::hot_keys::{HotKey, HotKeySampler};
 use crate::infolog::Logger;
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, ValueType};
 use crate::log::{LogReader, LogWriter};
+use crate::memory_usage::{self, MemoryUsage};
 use crate::memtable::MemTable;
 use crate::merging_iter::MergingIter;
-use crate::options::Options;
-use crate::snapshot::{Snapshot, SnapshotList};
+use crate::metrics;
+use crate::options::{self, Durability, IngestOptions, Options, ReadOptions};
+use crate::pinned_slice::PinnedSlice;
+use crate::range_del::{covering_seq, RangeTombstone};
+use crate::rate_limiter::RateLimitedWriter;
+use crate::snapshot::{NamedSnapshot, Snapshot, SnapshotInfo, SnapshotList};
+use crate::statistics::{Operation, Statistics};
 use crate::table_builder::TableBuilder;
 use crate::table_cache::{table_file_name, TableCache};
+use crate::table_properties::TableProperties;
+use crate::table_reader::Table;
 use crate::types::{
     parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber, Shared,
     MAX_SEQUENCE_NUMBER, NUM_LEVELS,
 };
-use crate::version::Version;
+use crate::version::{total_size, Version};
 use crate::version_edit::VersionEdit;
 use crate::version_set::{
-    manifest_file_name, read_current_file, set_current_file, Compaction, VersionSet,
+    current_file_name, manifest_file_name, read_current_file, set_current_file, temp_file_name,
+    Compaction, VersionSet,
 };
 use crate::write_batch::WriteBatch;
+use crate::write_stall::{WriteStall, WriteStallReason};
 
 use std::cmp::Ordering;
-use std::io::{self, BufWriter, Write};
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter, Read, Write};
 use std::mem;
 use std::ops::Drop;
 use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
+
+use integer_encoding::{VarIntReader, VarIntWriter};
+
+/// When level compactions are deferred, they are nevertheless done synchronously once level 0
+/// has this many files, to bound read amplification.
+const L0_STOP_WRITES_TRIGGER: usize = 12;
+/// From this many level 0 files on, writes are considered delayed (see `WriteStall`).
+const L0_SLOWDOWN_WRITES_TRIGGER: usize = 8;
+
+/// The name prefix of snapshots taken by `DB::auto_snapshot_if_due()`.
+const AUTO_SNAPSHOT_PREFIX: &str = "auto-";
+
+/// DB::delete_prefix() deletes keys in batches of this size if it can't use a range deletion.
+const DELETE_PREFIX_BATCH_SIZE: u32 = 1000;
+/// DB::put_many() writes batches of about this many bytes of keys and values.
+const PUT_MANY_BATCH_BYTES: usize = 1 << 20;
+
+/// KeyPresence is what `DB::key_may_exist()` knows about a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyPresence {
+    /// The key certainly doesn't exist.
+    Absent,
+    /// The key may exist: A table's filter matches it, but the table wasn't read.
+    MayExist,
+    /// The key exists, in a memtable.
+    Present,
+}
 
 /// DB contains the actual database implemenation. As opposed to the original, this implementation
 /// is not concurrent (yet).
@@ -43,6 +89,14 @@ pub struct DB {
     name: PathBuf,
     path: PathBuf,
     lock: Option<FileLock>,
+    // Set by open_read_only(): Nothing is written to the database directory.
+    read_only: bool,
+    // Set by open_as_secondary(): The database can follow a primary instance.
+    secondary: bool,
+    // The log files that a read-only instance replayed into its memtable, and the offset up to
+    // which the last one was read.
+    replayed_logs: Vec<FileNum>,
+    replayed_off: usize,
 
     internal_cmp: Rc<Box<dyn Cmp>>,
     fpol: InternalFilterPolicy<BoxedFilterPolicy>,
@@ -53,11 +107,33 @@ pub struct DB {
 
     log: Option<LogWriter<BufWriter<Box<dyn Write>>>>,
     log_num: Option<FileNum>,
+    // Whether records were written to the log since it was last synced, and when that was.
+    log_dirty: bool,
+    log_synced: Instant,
+    // When the last write happened, and which steps of compact_idle() are done since then.
+    last_write: Instant,
+    idle_purged: bool,
+    idle_done: bool,
+    // Number of pause_background_work() calls not yet matched by continue_background_work().
+    paused: usize,
+    write_stall: WriteStall,
+    // Whether the compaction scheduler deferred the pending compactions.
+    compactions_deferred: bool,
+    // The level compaction that compact_step() is doing in steps, while it isn't finished.
+    running_compaction: Option<RunningCompaction>,
+    // Set while compact_step() runs, so that level compactions are only started, not finished.
+    step_compactions: bool,
     cache: Shared<TableCache>,
     vset: Shared<VersionSet>,
     snaps: SnapshotList,
+    named_snapshots: Vec<(NamedSnapshot, Snapshot)>,
+    meta: BTreeMap<String, Vec<u8>>,
 
     cstats: [CompactionStats; NUM_LEVELS],
+    stats: Option<Shared<Statistics>>,
+    hot_keys: Option<HotKeySampler>,
+    // Writes stalled by compactions since the rate limiter was last tuned.
+    stalled_writes: u64,
 }
 
 unsafe impl Send for DB {}
@@ -74,28 +150,66 @@ impl DB {
         }
         let path = name.canonicalize().unwrap_or(name.to_owned());
 
-        let cache = share(TableCache::new(&name, opt.clone(), opt.max_open_files - 10));
+        let cache = share(TableCache::new(
+            name,
+            opt.clone(),
+            opt.max_open_files
+                .saturating_sub(options::RESERVED_FILES)
+                .max(1),
+        ));
         let vset = VersionSet::new(&name, opt.clone(), cache.clone());
+        let stats = if opt.statistics {
+            Some(share(Statistics::new()))
+        } else {
+            None
+        };
+        let hot_keys = if opt.hot_key_sample_rate > 0 {
+            Some(HotKeySampler::new(
+                opt.hot_key_sample_rate,
+                opt.prefix_extractor.clone(),
+            ))
+        } else {
+            None
+        };
 
         DB {
             name: name.to_owned(),
             path,
             lock: None,
+            read_only: false,
+            secondary: false,
+            replayed_logs: vec![],
+            replayed_off: 0,
             internal_cmp: Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             fpol: InternalFilterPolicy::new(opt.filter_policy.clone()),
 
-            mem: MemTable::new(opt.cmp.clone()),
+            mem: MemTable::new_sharded(opt.cmp.clone(), opt.memtable_shards),
             imm: None,
 
             opt,
 
             log: None,
             log_num: None,
+            log_dirty: false,
+            log_synced: Instant::now(),
+            last_write: Instant::now(),
+            idle_purged: false,
+            idle_done: false,
+            paused: 0,
+            write_stall: WriteStall::Normal,
+            compactions_deferred: false,
+            running_compaction: None,
+            step_compactions: false,
             cache,
             vset: share(vset),
             snaps: SnapshotList::new(),
+            named_snapshots: vec![],
+            meta: BTreeMap::new(),
 
             cstats: Default::default(),
+            stats,
+            hot_keys,
+            stalled_writes: 0,
         }
     }
 
@@ -108,9 +222,16 @@ impl DB {
     ///
     /// Whether a new database is created and what happens if a database exists at the given path
     /// depends on the options set (`create_if_missing`, `error_if_exists`).
-    pub fn open<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+    pub fn open<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<DB> {
+        let warnings = opt.validate()?;
         let name = name.as_ref();
         let mut db = DB::new(name, opt);
+        for w in warnings {
+            log!(db.opt.log, "Option adjusted or questionable: {}", w);
+        }
+        // Named snapshots must be in place before recovery, which may compact the log.
+        db.load_named_snapshots()?;
+        db.load_meta()?;
         let mut ve = VersionEdit::new();
         let save_manifest = db.recover(&mut ve)?;
 
@@ -121,6 +242,7 @@ impl DB {
                 .opt
                 .env
                 .open_writable_file(Path::new(&log_file_name(&db.name, lognum)))?;
+            sync_new_file(&db.opt, &db.name, &log_file_name(&db.name, lognum))?;
             ve.set_log_num(lognum);
             db.log = Some(LogWriter::new(BufWriter::new(logfile)));
             db.log_num = Some(lognum);
@@ -128,14 +250,197 @@ impl DB {
 
         if save_manifest {
             ve.set_log_num(db.log_num.unwrap_or(0));
-            db.vset.borrow_mut().log_and_apply(ve)?;
+            db.log_and_apply_new_files(ve, TableFileReason::Flush)?;
         }
 
         db.delete_obsolete_files()?;
         db.maybe_do_compaction()?;
+        if db.opt.persist_hot_blocks {
+            if let Err(e) = db.load_hot_blocks() {
+                log!(db.opt.log, "Error loading hot blocks: {}", e);
+            }
+        }
+        Ok(db)
+    }
+
+    /// open_read_only opens an existing database for reading only, e.g. for inspection tools or
+    /// reports that run while the database is in use. It doesn't take the lock file and never
+    /// writes to the database directory: The log files are replayed into the memtable instead of
+    /// being compacted, no new log or manifest is created, and log messages are discarded unless
+    /// `Options::log` is set. Writes, compactions and other changes fail with
+    /// `StatusCode::NotSupported`.
+    ///
+    /// The database contains the state on disk at the time it was opened; writes made later by
+    /// another instance aren't visible (see `open_as_secondary()`).
+    pub fn open_read_only<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<DB> {
+        let warnings = opt.validate()?;
+        if opt.log.is_none() {
+            opt.log = Some(share(Logger(Box::new(io::sink()))));
+        }
+        opt.reuse_logs = false;
+        opt.reuse_manifest = false;
+        let mut db = DB::new(name.as_ref(), opt);
+        db.read_only = true;
+        for w in warnings {
+            log!(db.opt.log, "Option adjusted or questionable: {}", w);
+        }
+        db.load_named_snapshots()?;
+        db.load_meta()?;
+        db.recover(&mut VersionEdit::new())?;
+        if db.opt.persist_hot_blocks {
+            if let Err(e) = db.load_hot_blocks() {
+                log!(db.opt.log, "Error loading hot blocks: {}", e);
+            }
+        }
+        Ok(db)
+    }
+
+    /// open_as_secondary opens an existing database read-only like `open_read_only()`, as a
+    /// secondary instance of a primary instance that has it open for writing, e.g. in another
+    /// process on shared storage. Unlike a plain read-only database, a secondary instance can
+    /// pick up the primary's writes by calling `try_catch_up_with_primary()`.
+    pub fn open_as_secondary<P: AsRef<Path>>(name: P, opt: Options) -> Result<DB> {
+        let mut db = DB::open_read_only(name, opt)?;
+        db.secondary = true;
         Ok(db)
     }
 
+    /// try_catch_up_with_primary makes the changes of the primary instance visible in a
+    /// secondary instance (see `open_as_secondary()`): It applies the edits that the primary
+    /// appended to its manifest since the last call, replays what it appended to its log files,
+    /// and reloads the named snapshots and metadata. Only if the primary has switched to a new
+    /// manifest, e.g. after being reopened, the whole state is read anew. It returns how many
+    /// sequence numbers the instance advanced.
+    ///
+    /// On error, the instance may have caught up partially, but it stays consistent, and the
+    /// call can be retried. Writes become visible once the primary has flushed them to its log
+    /// (see `flush()`). Existing iterators keep reading the state they were created from. Table
+    /// files that the primary deletes after compactions can't be read anymore unless they are in
+    /// the table cache, so catch up regularly.
+    pub fn try_catch_up_with_primary(&mut self) -> Result<u64> {
+        if !self.secondary {
+            return err(
+                StatusCode::NotSupported,
+                "database is not opened as a secondary instance",
+            );
+        }
+        let last_seq = self.vset.borrow().last_seq;
+        let caught_up = self.vset.borrow_mut().catch_up()?;
+        if caught_up {
+            self.catch_up_logs()?;
+        } else {
+            self.reload_from_primary()?;
+        }
+
+        let old_named_snapshots = mem::take(&mut self.named_snapshots);
+        let old_meta = mem::take(&mut self.meta);
+        if let Err(e) = self.load_named_snapshots().and_then(|_| self.load_meta()) {
+            self.named_snapshots = old_named_snapshots;
+            self.meta = old_meta;
+            return Err(e);
+        }
+        let advanced = self.vset.borrow().last_seq.saturating_sub(last_seq);
+        log!(
+            self.opt.log,
+            "Caught up with primary: {} sequence numbers",
+            advanced
+        );
+        Ok(advanced)
+    }
+
+    /// catch_up_logs replays the records that the primary appended to its log files since they
+    /// were read last. If the primary has compacted some of the logs read so far into tables
+    /// meanwhile, the memtable is rebuilt from the remaining ones.
+    fn catch_up_logs(&mut self) -> Result<()> {
+        let (log_num, prev_log_num) = {
+            let vset = self.vset.borrow();
+            (vset.log_num, vset.prev_log_num)
+        };
+        let is_live = |num: FileNum| num >= log_num || num == prev_log_num;
+        let mut logs: Vec<FileNum> = self
+            .opt
+            .env
+            .children(&self.path)?
+            .iter()
+            .filter_map(|name| match parse_file_name(name) {
+                Ok((num, FileType::Log)) if is_live(num) => Some(num),
+                _ => None,
+            })
+            .collect();
+        logs.sort();
+
+        let rebuilt = if self.replayed_logs.iter().all(|&num| is_live(num)) {
+            None
+        } else {
+            let mem = MemTable::new_sharded(self.opt.cmp.clone(), self.opt.memtable_shards);
+            Some((
+                mem::replace(&mut self.mem, mem),
+                mem::take(&mut self.replayed_logs),
+                mem::take(&mut self.replayed_off),
+            ))
+        };
+        let last = self.replayed_logs.last().copied();
+        for num in logs {
+            if last.is_some_and(|last| num < last) {
+                continue;
+            }
+            let off = if Some(num) == last {
+                self.replayed_off
+            } else {
+                0
+            };
+            match self.recover_log_file(num, off, false, &mut VersionEdit::new()) {
+                Ok((_, max_seq, end)) => {
+                    if Some(num) != last {
+                        self.replayed_logs.push(num);
+                    }
+                    self.replayed_off = end;
+                    let mut vset = self.vset.borrow_mut();
+                    vset.last_seq = vset.last_seq.max(max_seq);
+                }
+                Err(e) => {
+                    if let Some((mem, logs, off)) = rebuilt {
+                        self.mem = mem;
+                        self.replayed_logs = logs;
+                        self.replayed_off = off;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// reload_from_primary replaces the state of a secondary instance by the one described by
+    /// the primary's current manifest and log files. On error, the previous state is kept.
+    fn reload_from_primary(&mut self) -> Result<()> {
+        let vset = VersionSet::new(&self.name, self.opt.clone(), self.cache.clone());
+        let old_vset = mem::replace(&mut *self.vset.borrow_mut(), vset);
+        let old_mem = mem::replace(
+            &mut self.mem,
+            MemTable::new_sharded(self.opt.cmp.clone(), self.opt.memtable_shards),
+        );
+        let old_logs = mem::take(&mut self.replayed_logs);
+        let old_off = mem::take(&mut self.replayed_off);
+        if let Err(e) = self.recover(&mut VersionEdit::new()) {
+            *self.vset.borrow_mut() = old_vset;
+            self.mem = old_mem;
+            self.replayed_logs = old_logs;
+            self.replayed_off = old_off;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// check_writable returns an error if the database was opened with `open_read_only()`.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            err(StatusCode::NotSupported, "database is opened read-only")
+        } else {
+            Ok(())
+        }
+    }
+
     /// initialize_db initializes a new database.
     fn initialize_db(&mut self) -> Result<()> {
         let mut ve = VersionEdit::new();
@@ -150,6 +455,7 @@ impl DB {
             let mut lw = LogWriter::new(manifest_file);
             lw.add_record(&ve.encode())?;
             lw.flush()?;
+            self.opt.env.sync_file(Path::new(&manifest))?;
         }
         set_current_file(&self.opt.env, &self.path, 1)
     }
@@ -157,15 +463,21 @@ impl DB {
     /// recover recovers from the existing state on disk. If the wrapped result is `true`, then
     /// log_and_apply() should be called after recovery has finished.
     fn recover(&mut self, ve: &mut VersionEdit) -> Result<bool> {
-        if self.opt.error_if_exists && self.opt.env.exists(&self.path.as_ref()).unwrap_or(false) {
+        // The directory itself already exists, as the info log is opened in it.
+        let current = current_file_name(&self.path);
+        if self.opt.error_if_exists && self.opt.env.exists(&current).unwrap_or(false) {
             return err(StatusCode::AlreadyExists, "database already exists");
         }
 
-        let _ = self.opt.env.mkdir(Path::new(&self.path));
-        self.acquire_lock()?;
+        if !self.read_only {
+            let _ = self.opt.env.mkdir(Path::new(&self.path));
+            self.acquire_lock()?;
+        }
 
         if let Err(e) = read_current_file(&self.opt.env, &self.path) {
-            if e.code == StatusCode::NotFound && self.opt.create_if_missing {
+            if self.read_only {
+                return err(StatusCode::InvalidArgument, "database does not exist");
+            } else if e.code == StatusCode::NotFound && self.opt.create_if_missing {
                 self.initialize_db()?;
             } else {
                 return err(
@@ -185,18 +497,15 @@ impl DB {
         let mut expected = self.vset.borrow().live_files();
         let mut log_files = vec![];
 
+        // Other files, like the `lost` directory of `repair_db()`, are left alone.
         for file in &filenames {
-            match parse_file_name(&file) {
-                Ok((num, typ)) => {
-                    expected.remove(&num);
-                    if typ == FileType::Log
-                        && (num >= self.vset.borrow().log_num
-                            || num == self.vset.borrow().prev_log_num)
-                    {
-                        log_files.push(num);
-                    }
+            if let Ok((num, typ)) = parse_file_name(file) {
+                expected.remove(&num);
+                if typ == FileType::Log
+                    && (num >= self.vset.borrow().log_num || num == self.vset.borrow().prev_log_num)
+                {
+                    log_files.push(num);
                 }
-                Err(e) => return Err(e.annotate(format!("While parsing {:?}", file))),
             }
         }
         if !expected.is_empty() {
@@ -206,8 +515,8 @@ impl DB {
 
         log_files.sort();
         for i in 0..log_files.len() {
-            let (save_manifest_, max_seq_) =
-                self.recover_log_file(log_files[i], i == log_files.len() - 1, ve)?;
+            let (save_manifest_, max_seq_, end) =
+                self.recover_log_file(log_files[i], 0, i == log_files.len() - 1, ve)?;
             if save_manifest_ {
                 save_manifest = true;
             }
@@ -215,6 +524,10 @@ impl DB {
                 max_seq = max_seq_;
             }
             self.vset.borrow_mut().mark_file_number_used(log_files[i]);
+            if self.read_only {
+                self.replayed_logs.push(log_files[i]);
+                self.replayed_off = end;
+            }
         }
 
         if self.vset.borrow().last_seq < max_seq {
@@ -224,27 +537,39 @@ impl DB {
         Ok(save_manifest)
     }
 
-    /// recover_log_file reads a single log file into a memtable, writing new L0 tables if
-    /// necessary. If is_last is true, it checks whether the log file can be reused, and sets up
-    /// the database's logging handles appropriately if that's the case.
+    /// recover_log_file reads a single log file from offset off on into a memtable, writing new
+    /// L0 tables if necessary. If is_last is true, it checks whether the log file can be reused,
+    /// and sets up the database's logging handles appropriately if that's the case. A read-only
+    /// database reads all log files into its memtable instead. Besides whether the manifest
+    /// needs to be saved and the largest sequence number, it returns the offset after the last
+    /// complete record.
     fn recover_log_file(
         &mut self,
         log_num: FileNum,
+        off: usize,
         is_last: bool,
         ve: &mut VersionEdit,
-    ) -> Result<(bool, SequenceNumber)> {
+    ) -> Result<(bool, SequenceNumber, usize)> {
         let filename = log_file_name(&self.path, log_num);
-        let logfile = self.opt.env.open_sequential_file(Path::new(&filename))?;
+        let logfile: Box<dyn Read> = if off == 0 {
+            self.opt.env.open_sequential_file(Path::new(&filename))?
+        } else {
+            let file = self.opt.env.open_random_access_file(Path::new(&filename))?;
+            Box::new(RandomAccessReader::new(file, off))
+        };
         // Use the user-supplied comparator; it will be wrapped inside a MemtableKeyCmp.
         let cmp: Rc<Box<dyn Cmp>> = self.opt.cmp.clone();
 
-        let mut logreader = LogReader::new(
+        let mut logreader = LogReader::new_with_off(
             logfile, // checksum=
-            true,
+            true, off,
         );
         log!(self.opt.log, "Recovering log file {:?}", filename);
         let mut scratch = vec![];
-        let mut mem = MemTable::new(cmp.clone());
+        let mut mem = MemTable::new_sharded(cmp.clone(), self.opt.memtable_shards);
+        if self.read_only {
+            std::mem::swap(&mut mem, &mut self.mem);
+        }
         let mut batch = WriteBatch::new();
 
         let mut compactions = 0;
@@ -271,17 +596,19 @@ impl DB {
             if last_seq > max_seq {
                 max_seq = last_seq
             }
-            if mem.approx_mem_usage() > self.opt.write_buffer_size {
+            if !self.read_only && mem.approx_mem_usage() > self.opt.write_buffer_size {
                 compactions += 1;
                 self.write_l0_table(&mem, ve, None)?;
                 save_manifest = true;
-                mem = MemTable::new(cmp.clone());
+                mem = MemTable::new_sharded(cmp.clone(), self.opt.memtable_shards);
             }
             batch.clear();
         }
 
         // Check if we can reuse the last log file.
-        if self.opt.reuse_logs && is_last && compactions == 0 {
+        if self.read_only {
+            self.mem = mem;
+        } else if self.opt.reuse_logs && is_last && compactions == 0 {
             assert!(self.log.is_none());
             log!(self.opt.log, "reusing log file {:?}", filename);
             let oldsize = self.opt.env.size_of(Path::new(&filename))?;
@@ -296,12 +623,16 @@ impl DB {
             self.write_l0_table(&mem, ve, None)?;
         }
 
-        Ok((save_manifest, max_seq))
+        Ok((save_manifest, max_seq, logreader.record_end()))
     }
 
     /// delete_obsolete_files removes files that are no longer needed from the file system.
     fn delete_obsolete_files(&mut self) -> Result<()> {
-        let files = self.vset.borrow().live_files();
+        let mut files = self.vset.borrow().live_files();
+        // The outputs of an unfinished compaction are not part of any version yet.
+        if let Some(ref running) = self.running_compaction {
+            files.extend(running.state.outputs.iter().map(|o| o.num));
+        }
         let filenames = self.opt.env.children(Path::new(&self.path))?;
         for name in filenames {
             if let Ok((num, typ)) = parse_file_name(&name) {
@@ -328,16 +659,31 @@ impl DB {
                             continue;
                         }
                     }
-                    FileType::Current | FileType::DBLock | FileType::InfoLog => continue,
+                    FileType::Current
+                    | FileType::DBLock
+                    | FileType::InfoLog
+                    | FileType::HotBlocks
+                    | FileType::NamedSnapshots
+                    | FileType::Metadata => continue,
                 }
 
                 // If we're here, delete this file.
+                let path = self.path.join(&name);
+                let mut size = 0;
                 if typ == FileType::Table {
                     let _ = self.cache.borrow_mut().evict(num);
+                    size = self.opt.env.size_of(&path).unwrap_or(0);
                 }
                 log!(self.opt.log, "Deleting file type={:?} num={}", typ, num);
-                if let Err(e) = self.opt.env.delete(&self.path.join(&name)) {
+                if let Err(e) = self.opt.env.delete(&path) {
                     log!(self.opt.log, "Deleting file num={} failed: {}", num, e);
+                } else if typ == FileType::Table {
+                    if let Some(ref listener) = self.opt.event_listener {
+                        listener.on_table_file_deleted(&TableFileInfo {
+                            file_num: num,
+                            size,
+                        });
+                    }
                 }
             }
         }
@@ -346,18 +692,8 @@ impl DB {
 
     /// acquire_lock acquires the lock file.
     fn acquire_lock(&mut self) -> Result<()> {
-        let lock_r = self.opt.env.lock(Path::new(&lock_file_name(&self.path)));
-        match lock_r {
-            Ok(lockfile) => {
-                self.lock = Some(lockfile);
-                Ok(())
-            }
-            Err(ref e) if e.code == StatusCode::LockError => err(
-                StatusCode::LockError,
-                "database lock is held by another instance",
-            ),
-            Err(e) => Err(e),
-        }
+        self.lock = Some(lock_db(self.opt.env.as_ref().as_ref(), &self.path)?);
+        Ok(())
     }
 
     /// release_lock releases the lock file, if it's currently held.
@@ -372,6 +708,9 @@ impl DB {
     /// Flush data to disk and release lock.
     pub fn close(&mut self) -> Result<()> {
         self.flush()?;
+        if self.opt.persist_hot_blocks && self.lock.is_some() {
+            self.save_hot_blocks()?;
+        }
         self.release_lock()?;
         Ok(())
     }
@@ -385,7 +724,10 @@ impl DB {
     pub fn put(&mut self, k: &[u8], v: &[u8]) -> Result<()> {
         let mut wb = WriteBatch::new();
         wb.put(k, v);
-        self.write(wb, false)
+        let start = self.latency_start();
+        let r = self.write_internal(wb, false);
+        self.record_latency(Operation::Put, start);
+        r
     }
 
     /// Deletes a single entry. Like with `put()`, you can call `flush()` to guarantee that
@@ -396,9 +738,51 @@ impl DB {
         self.write(wb, false)
     }
 
-    /// Writes an entire WriteBatch. `sync` determines whether the write should be flushed to
-    /// disk.
+    /// delete_range deletes all entries with a key in [from; to) by writing a single range
+    /// tombstone, instead of a deletion marker per key. Reads skip the entries covered by it, and
+    /// compactions drop them once no snapshot can observe them anymore.
+    ///
+    /// Range tombstones are cheap to write, but each one is checked by every read until the
+    /// entries it covers have been compacted away. LevelDB doesn't support them, so with
+    /// `Options::leveldb_compatible`, `StatusCode::InvalidArgument` is returned.
+    pub fn delete_range(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+        options::check_range_deletion(&self.opt)?;
+        match self.opt.cmp.cmp(from, to) {
+            Ordering::Less => {}
+            Ordering::Equal => return Ok(()),
+            Ordering::Greater => {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "delete_range: from is after to",
+                )
+            }
+        }
+        let mut wb = WriteBatch::new();
+        wb.delete_range(from, to);
+        self.write(wb, false)
+    }
+
+    /// Writes an entire WriteBatch. `sync` determines whether the write should be synced to disk
+    /// even if `Options::durability` is lower than `Durability::DataSync`.
     pub fn write(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
+        let start = self.latency_start();
+        let r = self.write_internal(batch, sync);
+        self.record_latency(
+            if sync {
+                Operation::SyncWrite
+            } else {
+                Operation::Write
+            },
+            start,
+        );
+        r
+    }
+
+    fn write_internal(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.check_writable()?;
+        if batch.has_range_deletions() {
+            options::check_range_deletion(&self.opt)?;
+        }
         assert!(self.log.is_some());
 
         self.make_room_for_write(false)?;
@@ -409,18 +793,150 @@ impl DB {
 
         batch.insert_into_memtable(next, &mut self.mem);
         log.add_record(&batch.encode(next))?;
+        self.vset.borrow_mut().last_seq += entries;
+        self.last_write = Instant::now();
+        self.idle_purged = false;
+        self.idle_done = false;
+        self.compactions_deferred = false;
+        self.update_write_stall();
         if sync {
+            self.sync_log(self.opt.durability.max(Durability::DataSync))
+        } else {
+            self.log_dirty = true;
+            self.sync_log(self.opt.durability)?;
+            self.sync_log_if_due()
+        }
+    }
+
+    /// commit_group syncs the log for a group of `writes` writes that were done without `sync`,
+    /// and records the group's size in the statistics. With `Options::group_commit_delay`,
+    /// `AsyncDB` does this for concurrent sync writes.
+    pub fn commit_group(&mut self, writes: usize) -> Result<()> {
+        self.flush()?;
+        if let Some(ref stats) = self.stats {
+            stats.borrow_mut().record_group_commit(writes as u64);
+        }
+        Ok(())
+    }
+
+    /// sync_log_if_due syncs the log if it has unsynced records and `Options::log_sync_interval`
+    /// has passed since the last sync. Call this periodically to bound the time that writes stay
+    /// unsynced while no further writes arrive.
+    pub fn sync_log_if_due(&mut self) -> Result<()> {
+        match self.opt.log_sync_interval {
+            Some(interval) if self.log_dirty && self.log_synced.elapsed() >= interval => {
+                self.sync_log(self.opt.durability.max(Durability::DataSync))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// sync_log persists the records written to the log so far according to the durability level
+    /// d.
+    fn sync_log(&mut self, d: Durability) -> Result<()> {
+        if d == Durability::None {
+            return Ok(());
+        }
+        if let Some(ref mut log) = self.log.as_mut() {
             log.flush()?;
         }
-        self.vset.borrow_mut().last_seq += entries;
+        if let Some(num) = self.log_num {
+            let name = log_file_name(&self.path, num);
+            match d {
+                Durability::DataSync => self.opt.env.sync_file_data(&name)?,
+                Durability::FullSync => self.opt.env.sync_file(&name)?,
+                Durability::None | Durability::Buffered => return Ok(()),
+            }
+        }
+        self.log_dirty = false;
+        self.log_synced = Instant::now();
+        Ok(())
+    }
+
+    /// try_write is like `write()`, but returns an error with `StatusCode::WouldBlock` instead of
+    /// writing if the write would first have to wait for a memtable compaction.
+    pub fn try_write(&mut self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.write_timeout(batch, sync, Duration::ZERO)
+    }
+
+    /// try_put is the non-blocking form of `put()` (see `try_write()`).
+    pub fn try_put(&mut self, k: &[u8], v: &[u8]) -> Result<()> {
+        self.put_timeout(k, v, Duration::ZERO)
+    }
+
+    /// write_timeout is like `write()`, but bounds the time spent on compactions before the
+    /// write. If the memtable is full, it is compacted, and the level compaction that this makes
+    /// due runs in steps (see `compact_step()`) until timeout has passed; the rest of it is left
+    /// to later calls of `compact_step()` or other compactions. The memtable compaction itself
+    /// isn't interrupted, so with a zero timeout, the write fails with `StatusCode::WouldBlock`
+    /// instead (see `try_write()`).
+    pub fn write_timeout(
+        &mut self,
+        batch: WriteBatch,
+        sync: bool,
+        timeout: Duration,
+    ) -> Result<()> {
+        if self.write_would_stall() {
+            if timeout.is_zero() {
+                return err(
+                    StatusCode::WouldBlock,
+                    "memtable is full and needs compaction",
+                );
+            }
+            let start = Instant::now();
+            self.step_compactions = true;
+            let mut r = self.make_room_for_write(false);
+            while r.is_ok() && self.running_compaction.is_some() && start.elapsed() < timeout {
+                r = self.continue_compaction(true).map(|_| ());
+            }
+            self.step_compactions = false;
+            r?;
+        }
+        self.write(batch, sync)
+    }
+
+    /// put_timeout is the bounded-wait form of `put()` (see `write_timeout()`).
+    pub fn put_timeout(&mut self, k: &[u8], v: &[u8], timeout: Duration) -> Result<()> {
+        let mut wb = WriteBatch::new();
+        wb.put(k, v);
+        self.write_timeout(wb, false, timeout)
+    }
+
+    /// put_many adds many entries, writing them in batches, which is much faster than calling
+    /// `put()` for each of them. The batches are applied atomically one by one, but not all
+    /// together. Like `put()`, the writes aren't synced; call `flush()` afterwards to sync them
+    /// all at once.
+    pub fn put_many<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(&mut self, pairs: I) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        let mut bytes = 0;
+        for (k, v) in pairs {
+            batch.put(&k, &v);
+            bytes += k.len() + v.len();
+            if bytes >= PUT_MANY_BATCH_BYTES {
+                self.write(std::mem::replace(&mut batch, WriteBatch::new()), false)?;
+                bytes = 0;
+            }
+        }
+        if batch.count() > 0 {
+            self.write(batch, false)?;
+        }
         Ok(())
     }
 
     /// flush makes sure that all pending changes (e.g. from put()) are stored on disk.
     pub fn flush(&mut self) -> Result<()> {
-        if let Some(ref mut log) = self.log.as_mut() {
-            log.flush()?;
+        self.sync_log(self.opt.durability.max(Durability::DataSync))
+    }
+
+    /// flush_memtable writes the memtable to a table file, so that its entries don't have to be
+    /// recovered from the log after a restart. It fails with `StatusCode::WouldBlock` while
+    /// background work is paused.
+    pub fn flush_memtable(&mut self) -> Result<()> {
+        if self.paused > 0 {
+            return err(StatusCode::WouldBlock, "background work is paused");
         }
+        self.make_room_for_write(true)?;
+        self.update_write_stall();
         Ok(())
     }
 }
@@ -428,27 +944,23 @@ impl DB {
 impl DB {
     // READ //
 
-    fn get_internal(&mut self, seq: SequenceNumber, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    fn get_internal(
+        &mut self,
+        seq: SequenceNumber,
+        key: &[u8],
+        ropt: &ReadOptions,
+    ) -> Result<Option<PinnedSlice>> {
+        if let Some(hot_keys) = self.hot_keys.as_mut() {
+            hot_keys.sample(key);
+        }
         // Using this lookup key will skip all entries with higher sequence numbers, because they
         // will compare "Lesser" using the InternalKeyCmp
         let lkey = LookupKey::new(key, seq);
+        // Entries up to this sequence number are covered by a range tombstone.
+        let deleted = self.range_deleted_seq(key, seq);
 
-        match self.mem.get(&lkey) {
-            (Some(v), _) => return Ok(Some(v)),
-            // deleted entry
-            (None, true) => return Ok(None),
-            // not found entry
-            (None, false) => {}
-        }
-
-        if let Some(imm) = self.imm.as_ref() {
-            match imm.get(&lkey) {
-                (Some(v), _) => return Ok(Some(v)),
-                // deleted entry
-                (None, true) => return Ok(None),
-                // not found entry
-                (None, false) => {}
-            }
+        if let Some(v) = self.memtables_get(&lkey, deleted) {
+            return Ok(v);
         }
 
         let mut do_compaction = false;
@@ -458,11 +970,13 @@ impl DB {
         {
             let current = self.current();
             let mut current = current.borrow_mut();
-            if let Ok(Some((v, st))) = current.get(lkey.internal_key()) {
+            if let Some((v, found, st)) = current.get(lkey.internal_key(), ropt)? {
                 if current.update_stats(st) {
                     do_compaction = true;
                 }
-                result = Some(v)
+                if found > deleted {
+                    result = Some(v)
+                }
             }
         }
 
@@ -474,21 +988,190 @@ impl DB {
         Ok(result)
     }
 
+    /// memtables_get looks up key in the memtables. A found entry is the newest one, and its
+    /// value is returned unless it is a deletion marker or covered by a range tombstone deleting
+    /// entries up to sequence number deleted.
+    fn memtables_get(
+        &self,
+        key: &LookupKey,
+        deleted: SequenceNumber,
+    ) -> Option<Option<PinnedSlice>> {
+        for mem in std::iter::once(&self.mem).chain(self.imm.as_ref()) {
+            if let Some((found, v)) = mem.get_entry_pinned(key) {
+                return Some(v.filter(|_| found > deleted));
+            }
+        }
+        None
+    }
+
+    /// multi_get_internal looks up keys as of sequence number seq, in sorted order: The tables
+    /// are searched for all keys not found in the memtables at once (see `Version::multi_get()`).
+    fn multi_get_internal<K: AsRef<[u8]>>(
+        &mut self,
+        seq: SequenceNumber,
+        keys: &[K],
+        ropt: &ReadOptions,
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        let cmp = self.opt.cmp.clone();
+        order.sort_by(|&a, &b| cmp.cmp(keys[a].as_ref(), keys[b].as_ref()));
+
+        let mut results = vec![None; keys.len()];
+        // The keys not found in the memtables, and the sequence numbers up to which range
+        // tombstones delete them.
+        let mut pending = vec![];
+        for i in order {
+            let key = keys[i].as_ref();
+            if let Some(hot_keys) = self.hot_keys.as_mut() {
+                hot_keys.sample(key);
+            }
+            let lkey = LookupKey::new(key, seq);
+            let deleted = self.range_deleted_seq(key, seq);
+            match self.memtables_get(&lkey, deleted) {
+                Some(v) => results[i] = v.map(PinnedSlice::into_vec),
+                None => pending.push((i, lkey, deleted)),
+            }
+        }
+        if pending.is_empty() {
+            return Ok(results);
+        }
+
+        let lookup: Vec<InternalKey> = pending.iter().map(|(_, k, _)| k.internal_key()).collect();
+        let found = self.current().borrow().multi_get(&lookup, ropt)?;
+        for ((i, _, deleted), entry) in pending.iter().zip(found) {
+            if let Some((v, found)) = entry {
+                if found > *deleted {
+                    results[*i] = Some(v.into_vec());
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// multi_get returns the current values of keys, in the same order. It's faster than calling
+    /// `get()` for each key: The keys are looked up in sorted order, each level of tables is
+    /// consulted once for all of them, and each table and data block is read once for all keys
+    /// that it holds.
+    pub fn multi_get<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        let seq = self.vset.borrow().last_seq;
+        self.multi_get_internal(seq, keys, &ReadOptions::default())
+    }
+
+    /// multi_get_at is like `multi_get()`, but reads the values at or before snapshot.
+    pub fn multi_get_at<K: AsRef<[u8]>>(
+        &mut self,
+        snapshot: &Snapshot,
+        keys: &[K],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        self.multi_get_internal(snapshot.sequence(), keys, &ReadOptions::default())
+    }
+
     /// get_at reads the value for a given key at or before snapshot. It returns Ok(None) if the
     /// entry wasn't found, and Err(_) if an error occurred.
     pub fn get_at(&mut self, snapshot: &Snapshot, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.get_internal(snapshot.sequence(), key)
+        self.get_at_with(snapshot, key, &ReadOptions::default())
     }
 
-    /// get is a simplified version of get_at(), translating errors to None.
-    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+    /// get_at_with is like `get_at()`, but reads table blocks as ropt says.
+    pub fn get_at_with(
+        &mut self,
+        snapshot: &Snapshot,
+        key: &[u8],
+        ropt: &ReadOptions,
+    ) -> Result<Option<Vec<u8>>> {
+        let start = self.latency_start();
+        let r = self.get_internal(snapshot.sequence(), key, ropt);
+        self.record_latency(Operation::Get, start);
+        Ok(r?.map(PinnedSlice::into_vec))
+    }
+
+    /// key_may_exist checks whether key exists without reading its value: The memtables are
+    /// searched, then the key ranges and filters of the tables (which may be opened, but no data
+    /// blocks are read). Without filters (see `Options::filter_policy`), a key within a table's
+    /// range may exist.
+    pub fn key_may_exist(&mut self, key: &[u8]) -> Result<KeyPresence> {
         let seq = self.vset.borrow().last_seq;
-        if let Ok(v) = self.get_internal(seq, key) {
-            v
+        let lkey = LookupKey::new(key, seq);
+        let deleted = self.range_deleted_seq(key, seq);
+        for mem in std::iter::once(&self.mem).chain(self.imm.as_ref()) {
+            match mem.get_entry_pinned(&lkey) {
+                Some((found, Some(_))) if found > deleted => return Ok(KeyPresence::Present),
+                Some(_) => return Ok(KeyPresence::Absent),
+                None => {}
+            }
+        }
+        if self.current().borrow().key_may_exist(lkey.internal_key())? {
+            Ok(KeyPresence::MayExist)
         } else {
-            None
+            Ok(KeyPresence::Absent)
         }
     }
+
+    /// get reads the current value for a given key. It returns Ok(None) if the entry wasn't
+    /// found, and Err(_) if it couldn't be read, e.g. because of an I/O error or a corrupted
+    /// block.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_with(key, &ReadOptions::default())
+    }
+
+    /// get_with is like `get()`, but reads table blocks as ropt says, e.g. without filling the
+    /// block cache.
+    pub fn get_with(&mut self, key: &[u8], ropt: &ReadOptions) -> Result<Option<Vec<u8>>> {
+        let seq = self.vset.borrow().last_seq;
+        let start = self.latency_start();
+        let r = self.get_internal(seq, key, ropt);
+        self.record_latency(Operation::Get, start);
+        Ok(r?.map(PinnedSlice::into_vec))
+    }
+
+    /// get_pinned is like `get()`, but returns the value without copying it: The returned
+    /// `PinnedSlice` points into the cached table block or the memtable entry that holds the
+    /// value, and keeps it in memory until dropped. This saves an allocation and a copy per read,
+    /// which matters for large values.
+    pub fn get_pinned(&mut self, key: &[u8]) -> Result<Option<PinnedSlice>> {
+        let seq = self.vset.borrow().last_seq;
+        let start = self.latency_start();
+        let r = self.get_internal(seq, key, &ReadOptions::default());
+        self.record_latency(Operation::Get, start);
+        r
+    }
+
+    /// range_deleted_seq returns the sequence number up to which entries for key are covered by
+    /// a range tombstone visible at seq, or 0.
+    fn range_deleted_seq(&self, key: &[u8], seq: SequenceNumber) -> SequenceNumber {
+        let current = self.current();
+        let current = current.borrow();
+        covering_seq(
+            self.mem
+                .range_tombstones()
+                .iter()
+                .chain(self.imm.iter().flat_map(|m| m.range_tombstones()))
+                .chain(current.range_tombstones.iter()),
+            self.opt.cmp.as_ref().as_ref(),
+            key,
+            seq,
+        )
+    }
+
+    /// range_tombstones returns the range tombstones of the memtables and the current version.
+    pub(crate) fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        let current = self.current();
+        let current = current.borrow();
+        self.mem
+            .range_tombstones()
+            .iter()
+            .chain(self.imm.iter().flat_map(|m| m.range_tombstones()))
+            .chain(current.range_tombstones.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// get_or_none is what `get()` used to be: It returns None both if the entry wasn't found and
+    /// if an error occurred.
+    #[deprecated(note = "use get(), which doesn't hide read errors")]
+    pub fn get_or_none(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get(key).ok().flatten()
+    }
 }
 
 impl DB {
@@ -498,22 +1181,90 @@ impl DB {
     /// return elements added to the database after its creation.
     pub fn new_iter(&mut self) -> Result<DBIterator> {
         let snapshot = self.get_snapshot();
-        self.new_iter_at(snapshot)
+        let mut iter = self.new_iter_at(snapshot)?;
+        iter.set_follow_latest();
+        Ok(iter)
     }
 
     /// new_iter_at returns a DBIterator at the supplied snapshot.
     pub fn new_iter_at(&mut self, ss: Snapshot) -> Result<DBIterator> {
-        Ok(DBIterator::new(
+        self.new_iter_at_with(ss, &ReadOptions::default())
+    }
+
+    /// Like `new_iter()`, but the iterator reads table blocks as ropt says (see `get_with()`), and
+    /// only covers the keys within the bounds set in ropt (see
+    /// `ReadOptions::iterate_lower_bound`).
+    pub fn new_iter_with(&mut self, ropt: &ReadOptions) -> Result<DBIterator> {
+        let snapshot = self.get_snapshot();
+        let mut iter = self.new_iter_at_with(snapshot, ropt)?;
+        iter.set_follow_latest();
+        Ok(iter)
+    }
+
+    /// Like `new_iter_at()`, but the iterator reads table blocks as ropt says (see `get_with()`),
+    /// and only covers the keys within the bounds set in ropt (see
+    /// `ReadOptions::iterate_lower_bound`).
+    pub fn new_iter_at_with(&mut self, ss: Snapshot, ropt: &ReadOptions) -> Result<DBIterator> {
+        let mut iter = DBIterator::new(
             self.opt.cmp.clone(),
             self.vset.clone(),
-            self.merge_iterators()?,
+            self.merge_iterators_with(ropt)?,
             ss,
-        ))
+            self.stats.clone(),
+        );
+        iter.set_range_tombstones(self.range_tombstones());
+        iter.set_read_options(ropt);
+        Ok(iter)
+    }
+
+    /// new_prefix_iter returns a DBIterator over the entries whose keys start with prefix, in the
+    /// current state of the database. Table files whose key range or prefix filter rule out the
+    /// prefix aren't read, so this is faster than seeking a `new_iter()` iterator if the tables
+    /// were written with prefix filtering (see `Options::prefix_extractor`) and prefix is a prefix
+    /// as returned by the extractor.
+    ///
+    /// The iterator becomes invalid at the first key without the prefix; starting from the
+    /// beginning (e.g. with `seek_to_first()`) seeks to prefix.
+    pub fn new_prefix_iter(&mut self, prefix: &[u8]) -> Result<DBIterator> {
+        let snapshot = self.get_snapshot();
+        let mut iter = DBIterator::new(
+            self.opt.cmp.clone(),
+            self.vset.clone(),
+            self.merge_prefix_iterators(prefix)?,
+            snapshot,
+            self.stats.clone(),
+        );
+        iter.set_range_tombstones(self.range_tombstones());
+        iter.set_follow_latest();
+        iter.set_prefix(prefix);
+        Ok(iter)
     }
 
     /// merge_iterators produces a MergingIter merging the entries in the memtable, the immutable
     /// memtable, and table files from all levels.
-    fn merge_iterators(&mut self) -> Result<MergingIter> {
+    pub(crate) fn merge_iterators(&mut self) -> Result<MergingIter> {
+        self.merge_iterators_with(&ReadOptions::default())
+    }
+
+    /// merge_iterators_with is like `merge_iterators()`, but table files are read as ropt says
+    /// (see `Version::new_iters_with()`).
+    pub(crate) fn merge_iterators_with(&mut self, ropt: &ReadOptions) -> Result<MergingIter> {
+        let mut iters = self.memtable_iters();
+        iters.extend(self.current().borrow().new_iters_with(ropt)?);
+        Ok(MergingIter::new(self.internal_cmp.clone(), iters))
+    }
+
+    /// merge_prefix_iterators is like `merge_iterators()`, but leaves out table files that
+    /// contain no key starting with prefix (see `Version::new_prefix_iters()`).
+    pub(crate) fn merge_prefix_iterators(&mut self, prefix: &[u8]) -> Result<MergingIter> {
+        let mut iters = self.memtable_iters();
+        iters.extend(self.current().borrow().new_prefix_iters(prefix)?);
+        Ok(MergingIter::new(self.internal_cmp.clone(), iters))
+    }
+
+    /// memtable_iters returns iterators over the memtable and the immutable memtable, if they
+    /// aren't empty.
+    fn memtable_iters(&self) -> Vec<Box<dyn LdbIterator>> {
         let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
         if self.mem.len() > 0 {
             iters.push(Box::new(self.mem.iter()));
@@ -523,1088 +1274,5003 @@ impl DB {
                 iters.push(Box::new(imm.iter()));
             }
         }
-
-        // Add iterators for table files.
-        let current = self.current();
-        let current = current.borrow();
-        iters.extend(current.new_iters()?);
-
-        Ok(MergingIter::new(self.internal_cmp.clone(), iters))
-    }
-}
-
-impl DB {
-    // SNAPSHOTS //
-
-    /// Returns a snapshot at the current state. It can be used to retrieve entries from the
-    /// database as they were at an earlier point in time.
-    pub fn get_snapshot(&mut self) -> Snapshot {
-        self.snaps.new_snapshot(self.vset.borrow().last_seq)
+        iters
     }
 }
 
 impl DB {
-    // STATISTICS //
-    fn add_stats(&mut self, level: usize, cs: CompactionStats) {
-        assert!(level < NUM_LEVELS);
-        self.cstats[level].add(cs);
-    }
-
-    /// Trigger a compaction based on where this key is located in the different levels.
-    fn record_read_sample<'a>(&mut self, k: InternalKey<'a>) {
+    // BLOCK CACHE //
+
+    /// prefetch_range loads the data blocks of all table files that may contain keys in
+    /// [from; to] into the block cache, and returns the number of blocks read. This is useful to
+    /// warm the cache after opening a database. A range larger than the cache only leaves its end
+    /// in the cache.
+    pub fn prefetch_range(&mut self, from: &[u8], to: &[u8]) -> Result<usize> {
+        let ifrom = LookupKey::new(from, MAX_SEQUENCE_NUMBER);
+        let ito = LookupKey::new_full(to, 0, ValueType::TypeDeletion);
         let current = self.current();
-        if current.borrow_mut().record_read_sample(k) {
-            if let Err(e) = self.maybe_do_compaction() {
-                log!(self.opt.log, "record_read_sample: compaction failed: {}", e);
+        let mut read = 0;
+        for l in 0..NUM_LEVELS {
+            let files =
+                current
+                    .borrow()
+                    .overlapping_inputs(l, ifrom.internal_key(), ito.internal_key());
+            for f in files {
+                let table = self.cache.borrow_mut().get_table(f.borrow().num)?;
+                read += table.prefetch(ifrom.internal_key(), ito.internal_key())?;
             }
         }
+        Ok(read)
     }
-}
 
-impl DB {
-    // COMPACTIONS //
+    /// checkpoint creates a copy of the database in dir, which must be empty or not exist yet,
+    /// e.g. for an online backup. The copy contains all writes made before the call, and can be
+    /// opened like any database. Table files are immutable and hard-linked if the `Env` supports
+    /// it (see `Env::link()`), or copied otherwise; the log, the named snapshots and the metadata
+    /// are copied, and a new manifest and CURRENT file are written. The database stays open.
+    /// Read-only instances (see `open_read_only()`) can be checkpointed, too; their copy contains
+    /// the log files they replayed.
+    pub fn checkpoint<P: AsRef<Path>>(&mut self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        if !self.opt.env.children(dir).unwrap_or_default().is_empty() {
+            return err(
+                StatusCode::AlreadyExists,
+                &format!("checkpoint directory {} is not empty", dir.display()),
+            );
+        }
+        // Afterwards, all writes are either in a table or in the current log. A read-only
+        // instance has neither an immutable memtable nor a log of its own.
+        if self.imm.is_some() {
+            self.compact_memtable()?;
+        }
+        if let Some(ref mut log) = self.log.as_mut() {
+            log.flush()?;
+        }
+        self.opt.env.mkdir(dir)?;
+        let r = self.write_checkpoint(dir);
+        if r.is_err() {
+            let _ = destroy_db(dir, &self.opt);
+        }
+        r
+    }
 
-    /// make_room_for_write checks if the memtable has become too large, and triggers a compaction
-    /// if it's the case.
-    fn make_room_for_write(&mut self, force: bool) -> Result<()> {
-        if !force && self.mem.approx_mem_usage() < self.opt.write_buffer_size || self.mem.len() == 0 {
-            Ok(())
-        } else {
-            // Create new memtable.
-            let logn = self.vset.borrow_mut().new_file_number();
-            let logf = self
-                .opt
-                .env
-                .open_writable_file(Path::new(&log_file_name(&self.path, logn)));
-            if logf.is_err() {
-                self.vset.borrow_mut().reuse_file_number(logn);
-                Err(logf.err().unwrap())
-            } else {
-                self.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
-                self.log_num = Some(logn);
-
-                let mut imm = MemTable::new(self.opt.cmp.clone());
-                mem::swap(&mut imm, &mut self.mem);
-                self.imm = Some(imm);
-                self.maybe_do_compaction()
+    fn write_checkpoint(&mut self, dir: &Path) -> Result<()> {
+        let env = self.opt.env.clone();
+        let mut edit = VersionEdit::new();
+        edit.set_comparator_name(self.opt.cmp.id());
+        {
+            let current = self.current();
+            let current = current.borrow();
+            for (level, files) in current.files.iter().enumerate() {
+                for f in files {
+                    let f = f.borrow();
+                    let (from, to) = (
+                        table_file_name(&self.path, f.num),
+                        table_file_name(dir, f.num),
+                    );
+                    if env.link(&from, &to).is_err() {
+                        copy_file(env.as_ref().as_ref(), &from, &to)?;
+                    }
+                    edit.add_file(level, f.clone());
+                }
+            }
+            for t in &current.range_tombstones {
+                edit.add_range_tombstone(t.clone());
             }
         }
-    }
 
-    /// maybe_do_compaction starts a blocking compaction if it makes sense.
-    fn maybe_do_compaction(&mut self) -> Result<()> {
-        if self.imm.is_some() {
-            self.compact_memtable()?;
+        let (log_num, prev_log_num, manifest_num, last_seq) = {
+            let vset = self.vset.borrow();
+            (
+                self.log_num.unwrap_or(vset.log_num),
+                if self.read_only { vset.prev_log_num } else { 0 },
+                vset.next_file_num,
+                vset.last_seq,
+            )
+        };
+        let logs = if self.read_only {
+            self.replayed_logs.clone()
+        } else {
+            self.log_num.into_iter().collect()
+        };
+        for num in logs {
+            copy_file(
+                env.as_ref().as_ref(),
+                &log_file_name(&self.path, num),
+                &log_file_name(dir, num),
+            )?;
         }
-        // Issue #34 PR #36: after compacting a memtable into an L0 file, it is possible that the
-        // L0 files need to be merged and promoted.
-        if self.vset.borrow().needs_compaction() {
-            let c = self.vset.borrow_mut().pick_compaction();
-            if let Some(c) = c {
-                self.start_compaction(c)
-            } else {
-                Ok(())
+        for name in [named_snapshots_file_name, meta_file_name] {
+            if env.exists(&name(&self.path))? {
+                copy_file(env.as_ref().as_ref(), &name(&self.path), &name(dir))?;
             }
-        } else {
-            Ok(())
         }
-    }
 
-    /// compact_range triggers an immediate compaction on the specified key range. Repeatedly
-    /// calling this without actually adding new keys is not useful.
-    ///
-    /// Compactions in general will cause the database to find entries more quickly, and take up
-    /// less space on disk.
-    pub fn compact_range(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
-        let mut max_level = 1;
+        edit.set_log_num(log_num);
+        edit.set_prev_log_num(prev_log_num);
+        edit.set_next_file(manifest_num + 1);
+        edit.set_last_seq(last_seq);
+        let manifest = manifest_file_name(dir, manifest_num);
         {
-            let v = self.vset.borrow().current();
-            let v = v.borrow();
-            for l in 1..NUM_LEVELS - 1 {
-                if v.overlap_in_level(l, from, to) {
-                    max_level = l;
+            let mut lw = LogWriter::new(env.open_writable_file(&manifest)?);
+            lw.add_record(&edit.encode())?;
+            lw.flush()?;
+        }
+        env.sync_file(&manifest)?;
+        set_current_file(&env, dir, manifest_num)
+    }
+
+    /// verify_integrity reads all table files of the database, and returns an error if one of
+    /// them has a size other than recorded in the manifest, or a block whose checksum doesn't
+    /// match. Blocks are read from the files even if they are cached. The token, if given, is
+    /// checked between blocks.
+    pub fn verify_integrity(&mut self, cancel: Option<&CancellationToken>) -> Result<()> {
+        let current = self.current();
+        for level in current.borrow().files.iter() {
+            for f in level {
+                let (num, size) = (f.borrow().num, f.borrow().size);
+                let name = table_file_name(&self.path, num);
+                let actual = self.opt.env.size_of(&name)?;
+                if actual != size {
+                    return err(
+                        StatusCode::Corruption,
+                        &format!(
+                            "table {} has {} bytes instead of {}",
+                            name.display(),
+                            actual,
+                            size
+                        ),
+                    );
                 }
+                let table = self.cache.borrow_mut().get_table(num)?;
+                table.verify_checksums(cancel)?;
             }
         }
+        check_cancelled(cancel, "integrity check")
+    }
 
-        // Compact memtable.
-        self.make_room_for_write(true)?;
-
-        let mut ifrom = LookupKey::new(from, MAX_SEQUENCE_NUMBER)
-            .internal_key()
-            .to_vec();
-        let iend = LookupKey::new_full(to, 0, ValueType::TypeDeletion);
-
-        for l in 0..max_level + 1 {
-            loop {
-                let c_ = self
-                    .vset
-                    .borrow_mut()
-                    .compact_range(l, &ifrom, iend.internal_key());
-                if let Some(c) = c_ {
-                    // Update ifrom to the largest key of the last file in this compaction.
-                    let ix = c.num_inputs(0) - 1;
-                    ifrom = c.input(0, ix).largest.clone();
-                    self.start_compaction(c)?;
-                } else {
-                    break;
+    /// save_hot_blocks writes the locations of the cached data blocks of all open tables to the
+    /// HOTBLOCKS file, as a sequence of varint triples (file number, offset, size).
+    fn save_hot_blocks(&mut self) -> Result<()> {
+        let mut buf = vec![];
+        let current = self.current();
+        for level in current.borrow().files.iter() {
+            for f in level {
+                let num = f.borrow().num;
+                let table = match self.cache.borrow_mut().cached_table(num) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                for (_, h) in table.index_entries()? {
+                    if table.is_cached(&h) {
+                        buf.write_varint(num)?;
+                        buf.write_varint(h.offset())?;
+                        buf.write_varint(h.size())?;
+                    }
                 }
             }
         }
+        let mut f = self
+            .opt
+            .env
+            .open_writable_file(&hot_blocks_file_name(&self.path))?;
+        f.write_all(&buf)?;
+        f.flush()?;
         Ok(())
     }
 
-    /// start_compaction dispatches the different kinds of compactions depending on the current
-    /// state of the database.
-    fn start_compaction(&mut self, mut compaction: Compaction) -> Result<()> {
-        if compaction.is_trivial_move() {
-            assert_eq!(1, compaction.num_inputs(0));
-            let f = compaction.input(0, 0);
-            let num = f.num;
-            let size = f.size;
-            let level = compaction.level();
-
-            compaction.edit().delete_file(level, num);
-            compaction.edit().add_file(level + 1, f);
-
-            let r = self.vset.borrow_mut().log_and_apply(compaction.into_edit());
-            if let Err(e) = r {
-                log!(self.opt.log, "trivial move failed: {}", e);
-                Err(e)
-            } else {
-                log!(
-                    self.opt.log,
-                    "Moved num={} bytes={} from L{} to L{}",
-                    num,
-                    size,
-                    level,
-                    level + 1
-                );
-                log!(
-                    self.opt.log,
-                    "Summary: {}",
-                    self.vset.borrow().current_summary()
-                );
-                Ok(())
+    /// load_hot_blocks reads the blocks listed in the HOTBLOCKS file into the block cache, as
+    /// long as they belong to live table files and fit into the cache. It returns the number of
+    /// blocks read.
+    fn load_hot_blocks(&mut self) -> Result<usize> {
+        let name = hot_blocks_file_name(&self.path);
+        if !self.opt.env.exists(&name)? {
+            return Ok(0);
+        }
+        let mut buf = vec![];
+        self.opt
+            .env
+            .open_sequential_file(&name)?
+            .read_to_end(&mut buf)?;
+
+        let live = self.vset.borrow().live_files();
+        let cap = self.opt.block_cache.borrow().cap();
+        let mut rd = &buf[..];
+        let mut read = 0;
+        while !rd.is_empty() && read < cap {
+            let num: FileNum = rd.read_varint()?;
+            let offset: usize = rd.read_varint()?;
+            let size: usize = rd.read_varint()?;
+            if !live.contains(&num) {
+                continue;
             }
-        } else {
-            let smallest = if self.snaps.empty() {
-                self.vset.borrow().last_seq
-            } else {
-                self.snaps.oldest()
-            };
-            let mut state = CompactionState::new(compaction, smallest);
-            if let Err(e) = self.do_compaction_work(&mut state) {
-                state.cleanup(&self.opt.env, &self.path);
-                log!(self.opt.log, "Compaction work failed: {}", e);
+            let table = self.cache.borrow_mut().get_table(num)?;
+            let h = BlockHandle::new(offset, size);
+            if !table.is_cached(&h) {
+                table.read_block(&h)?;
+                read += 1;
             }
-            self.install_compaction_results(state)?;
-            log!(
-                self.opt.log,
-                "Compaction finished: {}",
-                self.vset.borrow().current_summary()
-            );
-
-            self.delete_obsolete_files()
         }
+        log!(
+            self.opt.log,
+            "Loaded {} hot blocks into the block cache",
+            read
+        );
+        Ok(read)
     }
+}
 
-    fn compact_memtable(&mut self) -> Result<()> {
-        assert!(self.imm.is_some());
+impl DB {
+    // SNAPSHOTS //
 
-        let mut ve = VersionEdit::new();
-        let base = self.current();
+    /// Returns a snapshot at the current state. It can be used to retrieve entries from the
+    /// database as they were at an earlier point in time.
+    pub fn get_snapshot(&mut self) -> Snapshot {
+        self.snaps
+            .new_snapshot(self.vset.borrow().last_seq, self.opt.env.micros())
+    }
 
-        let imm = self.imm.take().unwrap();
-        if let Err(e) = self.write_l0_table(&imm, &mut ve, Some(&base.borrow())) {
-            self.imm = Some(imm);
-            return Err(e);
-        }
-        ve.set_log_num(self.log_num.unwrap_or(0));
-        self.vset.borrow_mut().log_and_apply(ve)?;
-        if let Err(e) = self.delete_obsolete_files() {
-            log!(self.opt.log, "Error deleting obsolete files: {}", e);
+    /// snapshots lists all snapshots that are still alive, ordered by sequence number. Old
+    /// snapshots prevent compactions from dropping overwritten and deleted entries.
+    pub fn snapshots(&self) -> Vec<SnapshotInfo> {
+        self.snaps.list(self.opt.env.micros())
+    }
+
+    /// create_named_snapshot creates a snapshot that is persisted in the database: It is still
+    /// available, under its name, after the database is reopened, until it is released with
+    /// `release_named_snapshot()`. Like other snapshots, it prevents compactions from dropping
+    /// the entries it can see, which makes it useful for consistent backups and exports.
+    pub fn create_named_snapshot(&mut self, name: &str) -> Result<Snapshot> {
+        self.check_writable()?;
+        if self.named_snapshot(name).is_some() {
+            return err(
+                StatusCode::AlreadyExists,
+                &format!("snapshot {} already exists", name),
+            );
         }
-        Ok(())
+        let snapshot = self.get_snapshot();
+        let info = NamedSnapshot {
+            name: name.to_string(),
+            sequence: snapshot.sequence(),
+            created_micros: self.opt.env.micros(),
+        };
+        self.named_snapshots.push((info, snapshot.clone()));
+        self.save_named_snapshots()?;
+        Ok(snapshot)
     }
 
-    /// write_l0_table writes the given memtable to a table file.
-    fn write_l0_table(
-        &mut self,
-        memt: &MemTable,
-        ve: &mut VersionEdit,
-        base: Option<&Version>,
-    ) -> Result<()> {
-        let start_ts = self.opt.env.micros();
-        let num = self.vset.borrow_mut().new_file_number();
-        log!(self.opt.log, "Start write of L0 table {:06}", num);
-        let fmd = build_table(&self.path, &self.opt, memt.iter(), num)?;
-        log!(self.opt.log, "L0 table {:06} has {} bytes", num, fmd.size);
+    /// named_snapshot returns the named snapshot with the given name.
+    pub fn named_snapshot(&self, name: &str) -> Option<Snapshot> {
+        self.named_snapshots
+            .iter()
+            .find(|(info, _)| info.name == name)
+            .map(|(_, s)| s.clone())
+    }
 
-        // Wrote empty table.
-        if fmd.size == 0 {
-            self.vset.borrow_mut().reuse_file_number(num);
-            return Ok(());
+    /// named_snapshots lists the named snapshots, oldest first.
+    pub fn named_snapshots(&self) -> Vec<NamedSnapshot> {
+        self.named_snapshots
+            .iter()
+            .map(|(info, _)| info.clone())
+            .collect()
+    }
+
+    /// release_named_snapshot removes a named snapshot. Copies of it returned earlier remain
+    /// valid until they are dropped.
+    pub fn release_named_snapshot(&mut self, name: &str) -> Result<()> {
+        self.check_writable()?;
+        let len = self.named_snapshots.len();
+        self.named_snapshots.retain(|(info, _)| info.name != name);
+        if self.named_snapshots.len() == len {
+            return err(StatusCode::NotFound, &format!("no snapshot named {}", name));
         }
+        self.save_named_snapshots()
+    }
 
-        let cache_result = self.cache.borrow_mut().get_table(num);
-        if let Err(e) = cache_result {
-            log!(
-                self.opt.log,
-                "L0 table {:06} not returned by cache: {}",
-                num,
-                e
-            );
-            let _ = self
-                .opt
-                .env
-                .delete(Path::new(&table_file_name(&self.path, num)));
-            return Err(e);
+    /// auto_snapshot_if_due creates a named snapshot if `Options::auto_snapshot_interval` has
+    /// passed since the last automatic one, and releases the automatic snapshots beyond
+    /// `Options::auto_snapshot_retain`. Automatic snapshots are named `auto-` followed by their
+    /// creation time in microseconds since the Unix epoch; the name of a new one is returned.
+    pub fn auto_snapshot_if_due(&mut self) -> Result<Option<String>> {
+        let interval = match self.opt.auto_snapshot_interval {
+            Some(i) => i.as_micros() as u64,
+            None => return Ok(None),
+        };
+        self.check_writable()?;
+        let now = self.opt.env.micros();
+        let is_auto = |info: &NamedSnapshot| info.name.starts_with(AUTO_SNAPSHOT_PREFIX);
+        let last = self
+            .named_snapshots
+            .iter()
+            .filter(|(info, _)| is_auto(info))
+            .map(|(info, _)| info.created_micros)
+            .max();
+        if last.is_some_and(|last| now.saturating_sub(last) < interval) {
+            return Ok(None);
         }
 
-        let mut stats = CompactionStats::default();
-        stats.micros = self.opt.env.micros() - start_ts;
-        stats.written = fmd.size;
+        let name = format!("{}{}", AUTO_SNAPSHOT_PREFIX, now);
+        self.create_named_snapshot(&name)?;
+        let mut excess = self
+            .named_snapshots
+            .iter()
+            .filter(|(info, _)| is_auto(info))
+            .count()
+            .saturating_sub(self.opt.auto_snapshot_retain.max(1));
+        self.named_snapshots.retain(|(info, _)| {
+            if excess > 0 && is_auto(info) {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+        self.save_named_snapshots()?;
+        Ok(Some(name))
+    }
 
-        let mut level = 0;
-        if let Some(b) = base {
-            level = b.pick_memtable_output_level(
-                parse_internal_key(&fmd.smallest).2,
-                parse_internal_key(&fmd.largest).2,
-            );
+    /// save_named_snapshots writes the named snapshots to the SNAPSHOTS file, as a sequence of
+    /// (name length, name, sequence, creation time) with varint integers. The file is replaced
+    /// atomically.
+    fn save_named_snapshots(&mut self) -> Result<()> {
+        let mut buf = vec![];
+        for (info, _) in &self.named_snapshots {
+            buf.write_varint(info.name.len())?;
+            buf.write_all(info.name.as_bytes())?;
+            buf.write_varint(info.sequence)?;
+            buf.write_varint(info.created_micros)?;
         }
+        self.replace_file(&named_snapshots_file_name(&self.path), &buf)
+    }
 
-        self.add_stats(level, stats);
-        ve.add_file(level, fmd);
+    /// replace_file atomically replaces the contents of a file in the database directory, by
+    /// writing a temporary file and renaming it.
+    fn replace_file(&mut self, name: &Path, contents: &[u8]) -> Result<()> {
+        let tmp = temp_file_name(&self.path, self.vset.borrow_mut().new_file_number());
+        {
+            let mut f = self.opt.env.open_writable_file(&tmp)?;
+            f.write_all(contents)?;
+            f.flush()?;
+        }
+        self.opt.env.sync_file(&tmp)?;
+        self.opt.env.rename(&tmp, name)
+    }
 
+    /// load_named_snapshots restores the named snapshots saved by `save_named_snapshots()`.
+    fn load_named_snapshots(&mut self) -> Result<()> {
+        let name = named_snapshots_file_name(&self.path);
+        if !self.opt.env.exists(&name)? {
+            return Ok(());
+        }
+        let mut buf = vec![];
+        self.opt
+            .env
+            .open_sequential_file(&name)?
+            .read_to_end(&mut buf)?;
+        let mut rd = &buf[..];
+        while !rd.is_empty() {
+            let len: usize = rd.read_varint()?;
+            if len > rd.len() {
+                return err(StatusCode::Corruption, "truncated SNAPSHOTS file");
+            }
+            let (name, rest) = rd.split_at(len);
+            rd = rest;
+            let info = NamedSnapshot {
+                name: String::from_utf8_lossy(name).into_owned(),
+                sequence: rd.read_varint()?,
+                created_micros: rd.read_varint()?,
+            };
+            let snapshot = self.snaps.new_snapshot(info.sequence, info.created_micros);
+            self.named_snapshots.push((info, snapshot));
+        }
         Ok(())
     }
 
-    fn do_compaction_work(&mut self, cs: &mut CompactionState) -> Result<()> {
-        {
-            let current = self.vset.borrow().current();
-            assert!(current.borrow().num_level_files(cs.compaction.level()) > 0);
-            assert!(cs.builder.is_none());
-        }
-        let start_ts = self.opt.env.micros();
-        log!(
-            self.opt.log,
-            "Compacting {} files at L{} and {} files at L{}",
-            cs.compaction.num_inputs(0),
-            cs.compaction.level(),
-            cs.compaction.num_inputs(1),
-            cs.compaction.level() + 1
-        );
+    /// oldest_snapshot_age returns the age in microseconds of the oldest live snapshot, if any.
+    pub fn oldest_snapshot_age(&self) -> Option<u64> {
+        self.snapshots().iter().map(|s| s.age_micros).max()
+    }
 
-        let mut input = self.vset.borrow().make_input_iterator(&cs.compaction);
-        input.seek_to_first();
+    /// read_txn returns a ReadTxn, which reads from a snapshot of the current state until it is
+    /// dropped.
+    pub fn read_txn(&mut self) -> ReadTxn<'_> {
+        let snapshot = self.get_snapshot();
+        ReadTxn { db: self, snapshot }
+    }
+}
 
-        let (mut key, mut val) = (vec![], vec![]);
-        let mut last_seq_for_key = MAX_SEQUENCE_NUMBER;
+/// ReadTxn reads several keys consistently: all reads see the database as it was when the
+/// transaction was started by `DB::read_txn()`.
+pub struct ReadTxn<'a> {
+    db: &'a mut DB,
+    snapshot: Snapshot,
+}
 
-        let mut have_ukey = false;
-        let mut current_ukey = vec![];
+impl ReadTxn<'_> {
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
 
-        while input.valid() {
-            // TODO: Do we need to do a memtable compaction here? Probably not, in the sequential
-            // case.
-            assert!(input.current(&mut key, &mut val));
-            if cs.compaction.should_stop_before(&key) && cs.builder.is_some() {
-                self.finish_compaction_output(cs)?;
-            }
-            let (ktyp, seq, ukey) = parse_internal_key(&key);
-            if seq == 0 {
-                // Parsing failed.
-                log!(self.opt.log, "Encountered seq=0 in key: {:?}", &key);
-                last_seq_for_key = MAX_SEQUENCE_NUMBER;
-                have_ukey = false;
-                current_ukey.clear();
-                input.advance();
-                continue;
-            }
+    /// get returns the value of key, or None if it doesn't exist.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.db.get_at(&self.snapshot, key)
+    }
 
-            if !have_ukey || self.opt.cmp.cmp(ukey, &current_ukey) != Ordering::Equal {
-                // First occurrence of this key.
-                current_ukey.clear();
-                current_ukey.extend_from_slice(ukey);
-                have_ukey = true;
-                last_seq_for_key = MAX_SEQUENCE_NUMBER;
-            }
+    /// multi_get returns the values of keys, in the same order (see `DB::multi_get()`).
+    pub fn multi_get<K: AsRef<[u8]>>(&mut self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.db.multi_get_at(&self.snapshot, keys)
+    }
 
-            // We can omit the key under the following conditions:
-            if last_seq_for_key <= cs.smallest_seq {
-                last_seq_for_key = seq;
-                input.advance();
-                continue;
-            }
-            // Entry is deletion; no older version is observable by any snapshot; and all entries
-            // in compacted levels with smaller sequence numbers will
-            if ktyp == ValueType::TypeDeletion
-                && seq <= cs.smallest_seq
-                && cs.compaction.is_base_level_for(ukey)
-            {
-                last_seq_for_key = seq;
-                input.advance();
-                continue;
-            }
-
-            last_seq_for_key = seq;
+    /// iter returns an iterator over the transaction's snapshot. It stays valid after the
+    /// transaction is dropped.
+    pub fn iter(&mut self) -> Result<DBIterator> {
+        self.db.new_iter_at(self.snapshot.clone())
+    }
+}
 
-            if cs.builder.is_none() {
-                let fnum = self.vset.borrow_mut().new_file_number();
-                let mut fmd = FileMetaData::default();
-                fmd.num = fnum;
+impl DB {
+    // METADATA //
+
+    /// put_meta stores a metadata entry, e.g. the version of the application's schema or a
+    /// marker for a completed migration. Metadata is kept in the METADATA file next to the data,
+    /// not in the keyspace: It is never returned by `get()` or iterators, isn't affected by
+    /// deletions or compactions, and isn't visible in snapshots. Each change is written
+    /// synchronously.
+    pub fn put_meta(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.meta.insert(key.to_string(), value.to_vec());
+        self.save_meta()
+    }
 
-                let fname = table_file_name(&self.path, fnum);
-                let f = self.opt.env.open_writable_file(Path::new(&fname))?;
-                let f = Box::new(BufWriter::new(f));
-                cs.builder = Some(TableBuilder::new(self.opt.clone(), f));
-                cs.outputs.push(fmd);
-            }
-            if cs.builder.as_ref().unwrap().entries() == 0 {
-                cs.current_output().smallest = key.clone();
-            }
-            cs.current_output().largest = key.clone();
-            cs.builder.as_mut().unwrap().add(&key, &val)?;
-            // NOTE: Adjust max file size based on level.
-            if cs.builder.as_ref().unwrap().size_estimate() > self.opt.max_file_size {
-                self.finish_compaction_output(cs)?;
-            }
+    /// get_meta returns the metadata entry stored under key.
+    pub fn get_meta(&self, key: &str) -> Option<Vec<u8>> {
+        self.meta.get(key).cloned()
+    }
 
-            input.advance();
+    /// delete_meta removes a metadata entry. Removing a missing entry is not an error.
+    pub fn delete_meta(&mut self, key: &str) -> Result<()> {
+        self.check_writable()?;
+        if self.meta.remove(key).is_some() {
+            self.save_meta()?;
         }
+        Ok(())
+    }
 
-        if cs.builder.is_some() {
-            self.finish_compaction_output(cs)?;
+    /// meta_keys lists the keys of all metadata entries, in order.
+    pub fn meta_keys(&self) -> Vec<String> {
+        self.meta.keys().cloned().collect()
+    }
+
+    /// save_meta writes the metadata to the METADATA file, as a sequence of (key length, key,
+    /// value length, value) with varint lengths.
+    fn save_meta(&mut self) -> Result<()> {
+        let mut buf = vec![];
+        for (k, v) in &self.meta {
+            buf.write_varint(k.len())?;
+            buf.write_all(k.as_bytes())?;
+            buf.write_varint(v.len())?;
+            buf.write_all(v)?;
         }
+        self.replace_file(&meta_file_name(&self.path), &buf)
+    }
 
-        let mut stats = CompactionStats::default();
-        stats.micros = self.opt.env.micros() - start_ts;
-        for parent in 0..2 {
-            for inp in 0..cs.compaction.num_inputs(parent) {
-                stats.read += cs.compaction.input(parent, inp).size;
-            }
+    /// load_meta reads the metadata saved by `save_meta()`.
+    fn load_meta(&mut self) -> Result<()> {
+        let name = meta_file_name(&self.path);
+        if !self.opt.env.exists(&name)? {
+            return Ok(());
         }
-        for output in &cs.outputs {
-            stats.written += output.size;
+        let mut buf = vec![];
+        self.opt
+            .env
+            .open_sequential_file(&name)?
+            .read_to_end(&mut buf)?;
+        let mut rd = &buf[..];
+        let next = |rd: &mut &[u8]| -> Result<Vec<u8>> {
+            let len: usize = rd.read_varint()?;
+            if len > rd.len() {
+                return err(StatusCode::Corruption, "truncated METADATA file");
+            }
+            let (field, rest) = rd.split_at(len);
+            *rd = rest;
+            Ok(field.to_vec())
+        };
+        while !rd.is_empty() {
+            let k = String::from_utf8_lossy(&next(&mut rd)?).into_owned();
+            let v = next(&mut rd)?;
+            self.meta.insert(k, v);
         }
-        self.cstats[cs.compaction.level()].add(stats);
         Ok(())
     }
 
-    fn finish_compaction_output(&mut self, cs: &mut CompactionState) -> Result<()> {
-        assert!(cs.builder.is_some());
-        let output_num = cs.current_output().num;
-        assert!(output_num > 0);
-
-        // The original checks if the input iterator has an OK status. For this, we'd need to
-        // extend the LdbIterator interface though -- let's see if we can without for now.
-        // (it's not good for corruptions, in any case)
-        let b = cs.builder.take().unwrap();
-        let entries = b.entries();
-        let bytes = b.finish()?;
-        cs.total_bytes += bytes;
-
-        cs.current_output().size = bytes;
+    // STATISTICS //
+    fn add_stats(&mut self, level: usize, cs: CompactionStats) {
+        assert!(level < NUM_LEVELS);
+        metrics::compaction(level, cs.read, cs.written);
+        self.cstats[level].add(cs);
+    }
 
-        if entries > 0 {
-            // Verify that table can be used. (Separating get_table() because borrowing in an if
-            // let expression is dangerous).
-            let r = self.cache.borrow_mut().get_table(output_num);
-            if let Err(e) = r {
-                log!(self.opt.log, "New table can't be read: {}", e);
-                return Err(e);
+    /// Trigger a compaction based on where this key is located in the different levels.
+    fn record_read_sample<'a>(&mut self, k: InternalKey<'a>) {
+        let current = self.current();
+        if current.borrow_mut().record_read_sample(k) {
+            if let Err(e) = self.maybe_do_compaction() {
+                log!(self.opt.log, "record_read_sample: compaction failed: {}", e);
             }
-            log!(
-                self.opt.log,
-                "New table num={}: keys={} size={}",
-                output_num,
-                entries,
-                bytes
-            );
         }
-        Ok(())
     }
 
-    fn install_compaction_results(&mut self, mut cs: CompactionState) -> Result<()> {
-        log!(
-            self.opt.log,
-            "Compacted {} L{} files + {} L{} files => {}B",
-            cs.compaction.num_inputs(0),
-            cs.compaction.level(),
-            cs.compaction.num_inputs(1),
-            cs.compaction.level() + 1,
-            cs.total_bytes
-        );
-        cs.compaction.add_input_deletions();
-        let level = cs.compaction.level();
-        for output in &cs.outputs {
-            cs.compaction.edit().add_file(level + 1, output.clone());
-        }
-        self.vset
-            .borrow_mut()
-            .log_and_apply(cs.compaction.into_edit())
+    /// space_amplification returns an estimate of the ratio of the size of all table files to the
+    /// size of the live data in them (see `Options::max_space_amplification`). It is at least 1;
+    /// the memtable isn't included.
+    pub fn space_amplification(&self) -> f64 {
+        self.current().borrow().space_amplification()
     }
-}
 
-impl Drop for DB {
-    fn drop(&mut self) {
-        self.flush().ok();
-        let _ = self.release_lock();
+    /// approximate_sizes returns an estimate of the bytes in table files taken by the keys of each
+    /// of the ranges from (inclusive) to to (exclusive), like `GetApproximateSizes()` in the C++
+    /// implementation. The estimate is based on the table files' indices; the memtable isn't
+    /// included.
+    pub fn approximate_sizes<K: AsRef<[u8]>>(&self, ranges: &[(K, K)]) -> Vec<u64> {
+        let v = self.current();
+        let vset = self.vset.borrow();
+        ranges
+            .iter()
+            .map(|(from, to)| {
+                let start = LookupKey::new(from.as_ref(), MAX_SEQUENCE_NUMBER);
+                let limit = LookupKey::new(to.as_ref(), MAX_SEQUENCE_NUMBER);
+                let start = vset.approximate_offset(&v, start.internal_key());
+                let limit = vset.approximate_offset(&v, limit.internal_key());
+                limit.saturating_sub(start) as u64
+            })
+            .collect()
     }
-}
-
-struct CompactionState {
-    compaction: Compaction,
-    smallest_seq: SequenceNumber,
-    outputs: Vec<FileMetaData>,
-    builder: Option<TableBuilder<Box<dyn Write>>>,
-    total_bytes: usize,
-}
 
-impl CompactionState {
-    fn new(c: Compaction, smallest: SequenceNumber) -> CompactionState {
-        CompactionState {
-            compaction: c,
-            smallest_seq: smallest,
-            outputs: vec![],
-            builder: None,
-            total_bytes: 0,
-        }
+    /// memory_usage returns the memory used by the database's memtables and open tables, and by
+    /// its block cache (see `memory_usage::approximate()`), e.g. to decide when to flush the
+    /// memtable. If the block cache is shared with other databases, it's counted in full.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let cache = self.opt.block_cache.borrow();
+        memory_usage::approximate(&[self], &[&cache])
     }
 
-    fn current_output(&mut self) -> &mut FileMetaData {
-        let len = self.outputs.len();
-        &mut self.outputs[len - 1]
+    /// memtable_memory_usage returns the approximate memory used by the memtable and the
+    /// immutable memtable awaiting compaction, if any.
+    pub(crate) fn memtable_memory_usage(&self) -> usize {
+        self.mem.approx_mem_usage() + self.imm.as_ref().map_or(0, |m| m.approx_mem_usage())
     }
 
-    /// cleanup cleans up after an aborted compaction.
-    fn cleanup<P: AsRef<Path>>(&mut self, env: &Box<dyn Env>, name: P) {
-        for o in self.outputs.drain(..) {
-            let name = table_file_name(name.as_ref(), o.num);
-            let _ = env.delete(&name);
-        }
+    /// table_reader_memory_usage returns the memory used by the table cache's open tables.
+    pub(crate) fn table_reader_memory_usage(&self) -> usize {
+        self.cache.borrow().memory_usage()
     }
-}
-
-#[derive(Debug, Default)]
-struct CompactionStats {
-    micros: u64,
-    read: usize,
-    written: usize,
-}
 
-impl CompactionStats {
-    fn add(&mut self, cs: CompactionStats) {
-        self.micros += cs.micros;
-        self.read += cs.read;
-        self.written += cs.written;
+    /// user_cmp returns the comparator of user keys.
+    pub(crate) fn user_cmp(&self) -> Rc<Box<dyn Cmp>> {
+        self.opt.cmp.clone()
     }
-}
-
-pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
-    dbname: P,
-    opt: &Options,
-    mut from: I,
-    num: FileNum,
-) -> Result<FileMetaData> {
-    from.reset();
-    let filename = table_file_name(dbname.as_ref(), num);
 
-    let (mut kbuf, mut vbuf) = (vec![], vec![]);
-    let mut firstkey = None;
-    // lastkey is what remains in kbuf.
+    /// statistics returns a copy of the statistics collected since opening the database or the
+    /// last call to `take_statistics()`. It returns None if `Options::statistics` is not set.
+    pub fn statistics(&self) -> Option<Statistics> {
+        self.stats.as_ref().map(|s| s.borrow().clone())
+    }
 
-    // Clean up file if write fails at any point.
-    //
-    // TODO: Replace with catch {} when available.
-    let r = (|| -> Result<()> {
-        let f = opt.env.open_writable_file(Path::new(&filename))?;
-        let f = BufWriter::new(f);
-        let mut builder = TableBuilder::new(opt.clone(), f);
-        while from.advance() {
-            assert!(from.current(&mut kbuf, &mut vbuf));
-            if firstkey.is_none() {
-                firstkey = Some(kbuf.clone());
+    /// get_property returns the value of a property of the database's internal state, like
+    /// `GetProperty()` in the C++ implementation, or None if the property is unknown. The
+    /// properties are:
+    ///
+    /// * `leveldb.num-files-at-level<N>`: The number of table files at level N.
+    /// * `leveldb.stats`: The files, bytes and compactions of each level, followed by the
+    ///   statistics if `Options::statistics` is set.
+    /// * `leveldb.sstables`: The table files of each level, with their key ranges.
+    /// * `leveldb.approximate-memory-usage`: The bytes used by memtables, open tables and the
+    ///   block cache (see `memory_usage()`).
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        let name = name.strip_prefix("leveldb.")?;
+        let current = self.current();
+        let v = current.borrow();
+        if let Some(level) = name.strip_prefix("num-files-at-level") {
+            let level: usize = level.parse().ok()?;
+            return v.files.get(level).map(|fs| fs.len().to_string());
+        }
+        match name {
+            "stats" => {
+                let mb = |b: usize| b as f64 / 1048576.;
+                let mut s = String::from(
+                    "                               Compactions\n\
+                     Level  Files Size(MB) Time(sec) Read(MB) Write(MB)\n\
+                     --------------------------------------------------\n",
+                );
+                for (level, cs) in self.cstats.iter().enumerate() {
+                    let files = &v.files[level];
+                    if files.is_empty() && cs.micros == 0 {
+                        continue;
+                    }
+                    s.push_str(&format!(
+                        "{:3} {:8} {:8.0} {:9.0} {:8.0} {:9.0}\n",
+                        level,
+                        files.len(),
+                        mb(total_size(files.iter())),
+                        cs.micros as f64 / 1e6,
+                        mb(cs.read),
+                        mb(cs.written)
+                    ));
+                }
+                if let Some(stats) = self.statistics() {
+                    s.push_str(&stats.to_string());
+                }
+                Some(s)
             }
-            builder.add(&kbuf, &vbuf)?;
+            "sstables" => {
+                let mut s = String::new();
+                for (level, files) in v.files.iter().enumerate() {
+                    s.push_str(&format!("--- level {} ---\n", level));
+                    for f in files {
+                        let f = f.borrow();
+                        let (styp, sseq, skey) = parse_internal_key(&f.smallest);
+                        let (ltyp, lseq, lkey) = parse_internal_key(&f.largest);
+                        s.push_str(&format!(
+                            " {}:{}['{}' @ {} : {} .. '{}' @ {} : {}]\n",
+                            f.num,
+                            f.size,
+                            skey.escape_ascii(),
+                            sseq,
+                            styp as u8,
+                            lkey.escape_ascii(),
+                            lseq,
+                            ltyp as u8
+                        ));
+                    }
+                }
+                Some(s)
+            }
+            "approximate-memory-usage" => Some(self.memory_usage().total().to_string()),
+            _ => None,
         }
-        builder.finish()?;
-        Ok(())
-    })();
+    }
 
-    if let Err(e) = r {
-        let _ = opt.env.delete(Path::new(&filename));
-        return Err(e);
+    /// hot_keys returns up to n of the most frequently read keys, most frequent first, if
+    /// `Options::hot_key_sample_rate` is set. The counts are estimated from the sampled reads
+    /// since the database was opened or `reset_hot_keys()` was called.
+    pub fn hot_keys(&self, n: usize) -> Vec<HotKey> {
+        self.hot_keys.as_ref().map_or(vec![], |h| h.hot_keys(n))
     }
 
-    let mut md = FileMetaData::default();
-    match firstkey {
-        None => {
-            let _ = opt.env.delete(Path::new(&filename));
-        }
-        Some(key) => {
-            md.num = num;
-            md.size = opt.env.size_of(Path::new(&filename))?;
-            md.smallest = key;
-            md.largest = kbuf;
-        }
+    /// hot_prefixes is like `hot_keys()`, but counts the prefixes of the read keys, as returned
+    /// by `Options::prefix_extractor`.
+    pub fn hot_prefixes(&self, n: usize) -> Vec<HotKey> {
+        self.hot_keys.as_ref().map_or(vec![], |h| h.hot_prefixes(n))
     }
-    Ok(md)
-}
 
-fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
-    db.join(format!("{:06}.log", num))
-}
+    pub fn reset_hot_keys(&mut self) {
+        if let Some(h) = self.hot_keys.as_mut() {
+            h.reset();
+        }
+    }
 
-fn lock_file_name(db: &Path) -> PathBuf {
-    db.join("LOCK")
-}
+    /// take_statistics is like `statistics()`, but resets the collected statistics afterwards.
+    /// This is useful for reporting latencies per time interval.
+    pub fn take_statistics(&mut self) -> Option<Statistics> {
+        self.stats.as_ref().map(|s| mem::take(&mut *s.borrow_mut()))
+    }
 
-/// open_info_log opens an info log file in the given database. It transparently returns a
-/// /dev/null logger in case the open fails.
-fn open_info_log<E: Env + ?Sized, P: AsRef<Path>>(env: &E, db: P) -> Logger {
-    let db = db.as_ref();
-    let logfilename = db.join("LOG");
-    let oldlogfilename = db.join("LOG.old");
-    let _ = env.mkdir(Path::new(db));
-    if let Ok(e) = env.exists(Path::new(&logfilename)) {
-        if e {
-            let _ = env.rename(Path::new(&logfilename), Path::new(&oldlogfilename));
+    fn latency_start(&self) -> Option<Instant> {
+        if self.stats.is_some() || cfg!(feature = "metrics") {
+            Some(Instant::now())
+        } else {
+            None
         }
     }
-    if let Ok(w) = env.open_writable_file(Path::new(&logfilename)) {
-        Logger(w)
-    } else {
-        Logger(Box::new(io::sink()))
+
+    fn record_latency(&self, op: Operation, start: Option<Instant>) {
+        if let Some(start) = start {
+            let d = start.elapsed();
+            if let Some(ref stats) = self.stats {
+                stats.borrow_mut().record(op, d);
+            }
+            metrics::operation(op, d);
+        }
     }
 }
 
-#[cfg(test)]
-pub mod testutil {
-    use super::*;
-
-    use crate::version::testutil::make_version;
-
-    /// build_db creates a database filled with the tables created by make_version().
-    pub fn build_db() -> (DB, Options) {
-        let name = "db";
-        let (v, mut opt) = make_version();
-        opt.reuse_logs = false;
-        opt.reuse_manifest = false;
-        let mut ve = VersionEdit::new();
-        ve.set_comparator_name(opt.cmp.id());
-        ve.set_log_num(0);
-        // 9 files + 1 manifest we write below.
-        ve.set_next_file(11);
-        // 30 entries in these tables.
-        ve.set_last_seq(30);
+impl DB {
+    // BULK LOAD //
 
-        for l in 0..NUM_LEVELS {
-            for f in &v.files[l] {
-                ve.add_file(l, f.borrow().clone());
+    /// bulk_load writes entries, sorted by key and without duplicates, directly into table files
+    /// in the bottom level, bypassing the log, the memtable and compactions. This turns loading
+    /// an initial dataset into a single sequential write. The entries become visible at once.
+    ///
+    /// The keys must not overlap with the key ranges already present in the database; if they do,
+    /// or if they aren't sorted, bulk_load fails with `StatusCode::InvalidArgument` and the
+    /// contents are left unchanged. The memtable is flushed first, so that its keys can be
+    /// checked. Returns the number of table files written.
+    pub fn bulk_load<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(
+        &mut self,
+        sorted: I,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        if self.mem.len() > 0 {
+            self.flush_memtable()?;
+        }
+        self.finish_compaction()?;
+        let seq = self.vset.borrow().last_seq + 1;
+        let mut outputs = vec![];
+        let r = self
+            .write_bulk_tables(sorted, seq, &mut outputs)
+            .and_then(|_| self.install_bulk_tables(&outputs, seq));
+        if let Err(e) = r {
+            for f in &outputs {
+                let _ = self.opt.env.delete(&table_file_name(&self.path, f.num));
             }
+            log!(self.opt.log, "Bulk load failed: {}", e);
+            return Err(e);
         }
+        Ok(outputs.len())
+    }
 
-        let manifest = manifest_file_name(name, 10);
-        let manifest_file = opt.env.open_writable_file(Path::new(&manifest)).unwrap();
-        let mut lw = LogWriter::new(manifest_file);
-        lw.add_record(&ve.encode()).unwrap();
-        lw.flush().unwrap();
-        set_current_file(&opt.env, name, 10).unwrap();
+    /// write_bulk_tables writes the entries to table files, all with sequence number seq, adding
+    /// the files to outputs as they are created.
+    fn write_bulk_tables<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(
+        &mut self,
+        sorted: I,
+        seq: SequenceNumber,
+        outputs: &mut Vec<FileMetaData>,
+    ) -> Result<()> {
+        let max_size = options::target_file_size(&self.opt, NUM_LEVELS - 1);
+        let mut builder: Option<TableBuilder<BufWriter<Box<dyn Write>>>> = None;
+        let mut last: Option<Vec<u8>> = None;
+        for (k, v) in sorted {
+            if let Some(ref last) = last {
+                if self.opt.cmp.cmp(last, &k) != Ordering::Less {
+                    return err(StatusCode::InvalidArgument, "bulk load keys are not sorted");
+                }
+            }
+            let ikey = LookupKey::new(&k, seq);
+            if builder.is_none() {
+                let num = self.vset.borrow_mut().new_file_number();
+                let f = self
+                    .opt
+                    .env
+                    .open_writable_file(&table_file_name(&self.path, num))?;
+                builder = Some(TableBuilder::new(self.opt.clone(), BufWriter::new(f)));
+                outputs.push(FileMetaData {
+                    num,
+                    smallest: ikey.internal_key().to_vec(),
+                    ..Default::default()
+                });
+            }
+            let b = builder.as_mut().unwrap();
+            b.add(ikey.internal_key(), &v)?;
+            let full = b.size_estimate() > max_size;
+            last = Some(k);
+            if full {
+                let fmd = outputs.last_mut().unwrap();
+                self.finish_bulk_table(builder.take().unwrap(), fmd, seq, last.as_ref().unwrap())?;
+            }
+        }
+        match (builder, last) {
+            (Some(b), Some(last)) => {
+                self.finish_bulk_table(b, outputs.last_mut().unwrap(), seq, &last)
+            }
+            _ => Ok(()),
+        }
+    }
 
-        (DB::open(name, opt.clone()).unwrap(), opt)
+    fn finish_bulk_table(
+        &self,
+        b: TableBuilder<BufWriter<Box<dyn Write>>>,
+        fmd: &mut FileMetaData,
+        seq: SequenceNumber,
+        last: &[u8],
+    ) -> Result<()> {
+        fmd.largest = LookupKey::new(last, seq).internal_key().to_vec();
+        fmd.num_entries = b.properties().num_entries;
+        fmd.size = b.finish()?;
+        sync_new_file(&self.opt, &self.path, &table_file_name(&self.path, fmd.num))?;
+        log!(
+            self.opt.log,
+            "Bulk loaded table num={}: keys={} size={}",
+            fmd.num,
+            fmd.num_entries,
+            fmd.size
+        );
+        Ok(())
     }
 
-    /// set_file_to_compact ensures that the specified table file will be compacted next.
-    pub fn set_file_to_compact(db: &mut DB, num: FileNum) {
-        let v = db.current();
-        let mut v = v.borrow_mut();
+    /// install_bulk_tables adds the written tables to the bottom level, after checking that they
+    /// don't overlap with any existing table file.
+    fn install_bulk_tables(&mut self, outputs: &[FileMetaData], seq: SequenceNumber) -> Result<()> {
+        let (first, last) = match (outputs.first(), outputs.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(()),
+        };
+        let smallest = parse_internal_key(&first.smallest).2;
+        let largest = parse_internal_key(&last.largest).2;
+        let overlaps = {
+            let current = self.current();
+            let current = current.borrow();
+            (0..NUM_LEVELS).any(|l| current.overlap_in_level(l, smallest, largest))
+        };
+        if overlaps {
+            return err(
+                StatusCode::InvalidArgument,
+                "bulk load keys overlap with the database's contents",
+            );
+        }
+        let mut ve = VersionEdit::new();
+        for f in outputs {
+            ve.add_file(NUM_LEVELS - 1, f.clone());
+        }
+        self.vset.borrow_mut().last_seq = seq;
+        self.log_and_apply_new_files(ve, TableFileReason::Import)
+    }
 
-        let mut ftc = None;
-        for l in 0..NUM_LEVELS {
-            for f in &v.files[l] {
-                if f.borrow().num == num {
-                    ftc = Some((f.clone(), l));
+    /// ingest_external_file adds table files written outside of the database (see
+    /// `table::TableFileWriter`) to it, all at once. The files' key ranges must not overlap each
+    /// other. A file whose key range overlaps no data in the database is copied into the bottom
+    /// level as it is. Otherwise its entries are rewritten with a new sequence number, so that
+    /// they replace the existing entries for their keys, into the deepest level above the
+    /// overlapping data. The memtable is flushed first, so that its keys can be checked.
+    ///
+    /// Entries that are added as they are are visible to existing snapshots, too. Invalid files
+    /// are rejected before the database is changed.
+    pub fn ingest_external_file<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+        iopt: &IngestOptions,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if self.mem.len() > 0 {
+            self.flush_memtable()?;
+        }
+        self.finish_compaction()?;
+        let mut files = vec![];
+        for path in paths {
+            files.push(self.scan_external_file(path.as_ref())?);
+        }
+        let cmp = self.internal_cmp.clone();
+        files.sort_by(|a, b| cmp.cmp(&a.smallest, &b.smallest));
+        for pair in files.windows(2) {
+            let (a, b) = (
+                parse_internal_key(&pair[0].largest).2,
+                parse_internal_key(&pair[1].smallest).2,
+            );
+            if self.opt.cmp.cmp(a, b) != Ordering::Less {
+                return err(StatusCode::InvalidArgument, "external files overlap");
+            }
+        }
+
+        let mut created = vec![];
+        if let Err(e) = self.install_external_files(&files, iopt, &mut created) {
+            for num in created {
+                let _ = self.opt.env.delete(&table_file_name(&self.path, num));
+            }
+            log!(self.opt.log, "Ingesting external files failed: {}", e);
+            return Err(e);
+        }
+        if iopt.move_files {
+            for f in &files {
+                let _ = self.opt.env.delete(&f.path);
+            }
+        }
+        Ok(())
+    }
+
+    /// scan_external_file checks that a file is an intact table with sorted, unique keys, and
+    /// collects the metadata needed to add it to the database.
+    fn scan_external_file(&self, path: &Path) -> Result<ExternalFile> {
+        let size = self.opt.env.size_of(path)?;
+        let file = self.opt.env.open_random_access_file(path)?;
+        let table = Table::new(self.opt.clone(), Rc::new(file), size)?;
+        table.verify_checksums(None)?;
+
+        let mut f = ExternalFile {
+            path: path.to_owned(),
+            table,
+            size,
+            smallest: vec![],
+            largest: vec![],
+            num_entries: 0,
+            num_deletions: 0,
+            max_seq: 0,
+        };
+        let mut iter = f.table.iter();
+        let (mut k, mut v) = (vec![], vec![]);
+        while iter.advance() {
+            iter.current(&mut k, &mut v);
+            let (typ, seq, ukey) = parse_internal_key(&k);
+            if seq == 0 {
+                return err(
+                    StatusCode::Corruption,
+                    &format!("external file {} has an invalid key", path.display()),
+                );
+            }
+            if !f.largest.is_empty()
+                && self.opt.cmp.cmp(parse_internal_key(&f.largest).2, ukey) != Ordering::Less
+            {
+                return err(
+                    StatusCode::InvalidArgument,
+                    &format!("keys of external file {} are not sorted", path.display()),
+                );
+            }
+            if f.smallest.is_empty() {
+                f.smallest = k.clone();
+            }
+            f.largest = k.clone();
+            f.max_seq = f.max_seq.max(seq);
+            f.num_entries += 1;
+            if typ == ValueType::TypeDeletion {
+                f.num_deletions += 1;
+            }
+        }
+        if f.num_entries == 0 {
+            return err(
+                StatusCode::InvalidArgument,
+                &format!("external file {} is empty", path.display()),
+            );
+        }
+        Ok(f)
+    }
+
+    /// install_external_files copies or rewrites the files into the database directory, adding
+    /// the new file numbers to created, and then adds them to the version.
+    fn install_external_files(
+        &mut self,
+        files: &[ExternalFile],
+        iopt: &IngestOptions,
+        created: &mut Vec<FileNum>,
+    ) -> Result<()> {
+        let seq = self.vset.borrow().last_seq + 1;
+        let mut rewritten = false;
+        let mut ve = VersionEdit::new();
+        for f in files {
+            let (smallest, largest) = (
+                parse_internal_key(&f.smallest).2,
+                parse_internal_key(&f.largest).2,
+            );
+            let (overlapping_level, covered) = {
+                let current = self.current();
+                let current = current.borrow();
+                (
+                    (0..NUM_LEVELS).find(|&l| current.overlap_in_level(l, smallest, largest)),
+                    current
+                        .range_tombstones
+                        .iter()
+                        .any(|t| t.overlaps(self.opt.cmp.as_ref().as_ref(), smallest, largest)),
+                )
+            };
+            let num = self.vset.borrow_mut().new_file_number();
+            created.push(num);
+            let name = table_file_name(&self.path, num);
+
+            if overlapping_level.is_none() && !covered && f.max_seq < seq {
+                if !iopt.move_files || self.opt.env.link(&f.path, &name).is_err() {
+                    copy_file(self.opt.env.as_ref().as_ref(), &f.path, &name)?;
                 }
+                let md = FileMetaData {
+                    num,
+                    size: f.size,
+                    smallest: f.smallest.clone(),
+                    largest: f.largest.clone(),
+                    num_entries: f.num_entries,
+                    num_deletions: f.num_deletions,
+                    ..Default::default()
+                };
+                log!(
+                    self.opt.log,
+                    "Ingested external table num={} at L{}",
+                    num,
+                    NUM_LEVELS - 1
+                );
+                ve.add_file(NUM_LEVELS - 1, md);
+                continue;
             }
+
+            let level = overlapping_level.map_or(NUM_LEVELS - 1, |l| l.saturating_sub(1));
+            let mut builder = TableBuilder::new(
+                self.opt.clone(),
+                BufWriter::new(self.opt.env.open_writable_file(&name)?),
+            );
+            let mut iter = f.table.iter();
+            let (mut k, mut v) = (vec![], vec![]);
+            while iter.advance() {
+                iter.current(&mut k, &mut v);
+                let (typ, _, ukey) = parse_internal_key(&k);
+                builder.add(LookupKey::new_full(ukey, seq, typ).internal_key(), &v)?;
+            }
+            let md = FileMetaData {
+                num,
+                size: builder.finish()?,
+                smallest: LookupKey::new_full(smallest, seq, ValueType::TypeValue)
+                    .internal_key()
+                    .to_vec(),
+                largest: LookupKey::new_full(largest, seq, ValueType::TypeValue)
+                    .internal_key()
+                    .to_vec(),
+                num_entries: f.num_entries,
+                num_deletions: f.num_deletions,
+                ..Default::default()
+            };
+            sync_new_file(&self.opt, &self.path, &name)?;
+            log!(
+                self.opt.log,
+                "Ingested external table num={} at L{}, with seq={}",
+                num,
+                level,
+                seq
+            );
+            ve.add_file(level, md);
+            rewritten = true;
         }
-        if let Some((f, l)) = ftc {
-            v.file_to_compact = Some(f);
-            v.file_to_compact_lvl = l;
+        if rewritten {
+            self.vset.borrow_mut().last_seq = seq;
+        }
+        self.log_and_apply_new_files(ve, TableFileReason::Import)
+    }
+}
+
+/// ExternalFile is a table file to be added by `DB::ingest_external_file()`.
+struct ExternalFile {
+    path: PathBuf,
+    table: Table,
+    size: usize,
+    smallest: Vec<u8>,
+    largest: Vec<u8>,
+    num_entries: u64,
+    num_deletions: u64,
+    max_seq: SequenceNumber,
+}
+
+impl DB {
+    // COMPACTIONS //
+
+    /// make_room_for_write checks if the memtable has become too large, and triggers a compaction
+    /// if it's the case.
+    fn make_room_for_write(&mut self, force: bool) -> Result<()> {
+        if !force && !self.write_would_stall() || self.mem.len() == 0 {
+            Ok(())
         } else {
-            panic!("file number not found");
+            self.check_writable()?;
+            let start = Instant::now();
+            if !force {
+                self.set_write_stall(WriteStall::Stopped(WriteStallReason::MemtableFull));
+            }
+            // Create new memtable.
+            let logn = self.vset.borrow_mut().new_file_number();
+            let logf = self
+                .opt
+                .env
+                .open_writable_file(Path::new(&log_file_name(&self.path, logn)));
+            if logf.is_err() {
+                self.vset.borrow_mut().reuse_file_number(logn);
+                Err(logf.err().unwrap())
+            } else {
+                // Records still buffered for the old log must reach it before it's replaced.
+                self.sync_log(self.opt.durability.max(Durability::Buffered))?;
+                sync_new_file(&self.opt, &self.path, &log_file_name(&self.path, logn))?;
+                self.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
+                self.log_num = Some(logn);
+
+                let mut imm = MemTable::new_sharded(self.opt.cmp.clone(), self.opt.memtable_shards);
+                mem::swap(&mut imm, &mut self.mem);
+                self.imm = Some(imm);
+                self.compact_memtable()?;
+                if self.level_compaction_due() {
+                    if !force
+                        && self.current().borrow().num_level_files(0) >= L0_STOP_WRITES_TRIGGER
+                    {
+                        self.set_write_stall(WriteStall::Stopped(WriteStallReason::Level0Files));
+                    }
+                    self.stalled_writes += 1;
+                    if let Some(ref stats) = self.stats {
+                        stats.borrow_mut().record_write_stall();
+                    }
+                }
+                let result = self.maybe_do_compaction();
+                if !force {
+                    metrics::write_stall(start.elapsed());
+                }
+                result
+            }
+        }
+    }
+
+    /// write_would_stall returns true if the next write has to wait for the memtable to be
+    /// compacted.
+    fn write_would_stall(&self) -> bool {
+        self.paused == 0
+            && !self.read_only
+            && self.mem.len() > 0
+            && self.mem.approx_mem_usage() >= self.opt.write_buffer_size
+    }
+
+    /// write_stall returns the current write stall condition.
+    pub fn write_stall(&self) -> WriteStall {
+        self.write_stall
+    }
+
+    /// update_write_stall determines the write stall condition after an operation has finished.
+    fn update_write_stall(&mut self) {
+        let l0_files = self.current().borrow().num_level_files(0);
+        let stall = if l0_files >= L0_STOP_WRITES_TRIGGER {
+            WriteStall::Stopped(WriteStallReason::Level0Files)
+        } else if l0_files >= L0_SLOWDOWN_WRITES_TRIGGER {
+            WriteStall::Delayed(WriteStallReason::Level0Files)
+        } else if self.write_would_stall() {
+            WriteStall::Delayed(WriteStallReason::MemtableFull)
+        } else {
+            WriteStall::Normal
+        };
+        self.set_write_stall(stall);
+    }
+
+    fn set_write_stall(&mut self, stall: WriteStall) {
+        if stall == self.write_stall {
+            return;
+        }
+        let previous = mem::replace(&mut self.write_stall, stall);
+        if let Some(ref listener) = self.opt.write_stall_listener {
+            listener.on_write_stall_change(previous, stall);
+        }
+    }
+
+    /// maybe_do_compaction starts a blocking compaction if it makes sense. Memtable compactions
+    /// always run immediately, level compactions only if they are not deferred (see
+    /// `Options::defer_compactions`).
+    fn maybe_do_compaction(&mut self) -> Result<()> {
+        if self.paused > 0 || self.read_only {
+            return Ok(());
+        }
+        if self.imm.is_some() {
+            self.compact_memtable()?;
+        }
+        if self.level_compaction_due() {
+            self.compact_pending()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// level_compaction_due returns true if a level needs to be compacted, and the compaction
+    /// may not be deferred.
+    fn level_compaction_due(&self) -> bool {
+        self.vset.borrow().needs_compaction()
+            && (!self.opt.defer_compactions
+                || self.current().borrow().num_level_files(0) >= L0_STOP_WRITES_TRIGGER)
+    }
+
+    /// needs_compaction returns true if there is pending compaction work, which can be done by
+    /// calling `compact_pending()`.
+    pub fn needs_compaction(&self) -> bool {
+        self.paused == 0
+            && !self.read_only
+            && (self.imm.is_some()
+                || self.running_compaction.is_some()
+                || !self.compactions_deferred && self.vset.borrow().needs_compaction())
+    }
+
+    /// pause_background_work stops all memtable and level compactions until
+    /// `continue_background_work()` is called (as often as this function). Table files and the
+    /// manifest stay unchanged meanwhile, e.g. for taking a file system snapshot; only the log
+    /// is written to, and the memtable grows beyond `Options::write_buffer_size` instead of being
+    /// compacted. A level compaction done in steps (see `compact_step()`) stays unfinished, and
+    /// its output files aren't part of the database yet. `compact_range()` fails with
+    /// `StatusCode::WouldBlock` while paused.
+    pub fn pause_background_work(&mut self) {
+        self.paused += 1;
+    }
+
+    /// continue_background_work resumes compactions stopped by `pause_background_work()`, and
+    /// runs the ones that became due while paused.
+    pub fn continue_background_work(&mut self) -> Result<()> {
+        if self.paused == 0 {
+            return err(StatusCode::InvalidArgument, "background work is not paused");
+        }
+        self.paused -= 1;
+        if self.paused > 0 {
+            return Ok(());
+        }
+        self.make_room_for_write(false)?;
+        let r = self.maybe_do_compaction();
+        self.update_write_stall();
+        r
+    }
+
+    /// compact_pending runs the most urgent pending compaction, if any. The memtable is always
+    /// compacted first, as writes may be waiting for it.
+    pub fn compact_pending(&mut self) -> Result<()> {
+        let r = self.compact_pending_internal();
+        self.update_write_stall();
+        r
+    }
+
+    fn compact_pending_internal(&mut self) -> Result<()> {
+        self.check_writable()?;
+        if self.paused > 0 {
+            return Ok(());
+        }
+        if self.imm.is_some() {
+            self.compact_memtable()?;
+        }
+        if self.running_compaction.is_some() {
+            if self.step_compactions {
+                return self.continue_compaction(true).map(|_| ());
+            }
+            return self.finish_compaction();
+        }
+        // Issue #34 PR #36: after compacting a memtable into an L0 file, it is possible that the
+        // L0 files need to be merged and promoted.
+        self.compactions_deferred = false;
+        let jobs = self.vset.borrow().pending_compactions();
+        if jobs.is_empty() {
+            return Ok(());
+        }
+        let ix = if self.current().borrow().num_level_files(0) >= L0_STOP_WRITES_TRIGGER {
+            0
+        } else {
+            match self.opt.compaction_scheduler.schedule(&jobs) {
+                CompactionDecision::Run(ix) if ix < jobs.len() => ix,
+                CompactionDecision::Run(ix) => {
+                    return err(
+                        StatusCode::InvalidArgument,
+                        &format!("compaction scheduler picked nonexistent job {}", ix),
+                    )
+                }
+                CompactionDecision::Defer => {
+                    self.compactions_deferred = true;
+                    return Ok(());
+                }
+            }
+        };
+        let c = self.vset.borrow_mut().compaction_for(&jobs[ix]);
+        if let Some(c) = c {
+            if let Some(ref stats) = self.stats {
+                match jobs[ix].reason {
+                    CompactionReason::Seek => stats.borrow_mut().record_seek_compaction(),
+                    CompactionReason::Deletion => stats.borrow_mut().record_deletion_compaction(),
+                    CompactionReason::SpaceAmplification => {
+                        stats.borrow_mut().record_space_amp_compaction()
+                    }
+                    CompactionReason::Size => {}
+                }
+            }
+            self.start_compaction(c)
+        } else {
+            Ok(())
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::testutil::{build_db, set_file_to_compact};
-    use super::*;
+    /// compact_step does the next part of the pending compaction work, or else of the idle
+    /// compactions if they're due (see `compact_idle()`), and returns false if there was nothing
+    /// to do. Unlike `compact_pending()`, it doesn't run a level compaction as a whole: Each call
+    /// processes about one output file's worth of its input, and the compaction pauses in between.
+    /// Memtable compactions, which writes wait for, take precedence over the next step. Other
+    /// compactions, `bulk_load()` and `ingest_external_file()` finish a paused compaction first;
+    /// one that is unfinished when the database is closed is discarded.
+    pub fn compact_step(&mut self) -> Result<bool> {
+        self.check_writable()?;
+        if self.paused > 0 {
+            return Ok(false);
+        }
+        let r = if self.imm.is_some() {
+            self.compact_memtable().map(|_| true)
+        } else if self.running_compaction.is_some() {
+            self.continue_compaction(true).map(|_| true)
+        } else {
+            self.step_compactions = true;
+            let r = if self.needs_compaction() {
+                self.compact_pending_internal().map(|_| true)
+            } else if self.idle_compaction_due() {
+                self.compact_idle()
+            } else {
+                Ok(false)
+            };
+            self.step_compactions = false;
+            r
+        };
+        self.update_write_stall();
+        r
+    }
+
+    /// idle_compaction_due returns true if no writes arrived for `Options::idle_compaction_delay`,
+    /// and `compact_idle()` has work left for this idle period.
+    pub fn idle_compaction_due(&self) -> bool {
+        match self.opt.idle_compaction_delay {
+            Some(delay) => !self.idle_done && self.last_write.elapsed() >= delay,
+            None => false,
+        }
+    }
+
+    /// compact_idle runs one step of the compactions done while the database is idle: First
+    /// pending compactions, then compactions of level 0 into level 1 even if level 0 isn't full.
+    /// With `Options::idle_tombstone_purge`, the key range of all files containing deletion
+    /// markers is then compacted into the bottommost level, once per idle period. It returns
+    /// false if there was nothing left to do.
+    pub fn compact_idle(&mut self) -> Result<bool> {
+        self.check_writable()?;
+        if self.paused > 0 {
+            return Ok(false);
+        }
+        if self.needs_compaction() {
+            self.compact_pending()?;
+            return Ok(true);
+        }
+        let current = self.current();
+        let l0_range = {
+            let v = current.borrow();
+            let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+            for f in &v.files[0] {
+                let f = f.borrow();
+                range = Some(match range {
+                    None => (f.smallest.clone(), f.largest.clone()),
+                    Some((from, to)) => (
+                        min_by_cmp(&**self.internal_cmp, from, f.smallest.clone()),
+                        max_by_cmp(&**self.internal_cmp, to, f.largest.clone()),
+                    ),
+                });
+            }
+            range
+        };
+        if let Some((from, to)) = l0_range {
+            let c = self.vset.borrow_mut().compact_range(0, &from, &to);
+            if let Some(c) = c {
+                self.start_compaction(c)?;
+                return Ok(true);
+            }
+        }
+        if self.opt.idle_tombstone_purge && !self.idle_purged {
+            self.idle_purged = true;
+            let range = {
+                let v = current.borrow();
+                let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+                for f in v.files.iter().flatten() {
+                    self.vset.borrow().load_table_properties(f);
+                    let f = f.borrow();
+                    if f.num_deletions == 0 {
+                        continue;
+                    }
+                    let (_, _, smallest) = parse_internal_key(&f.smallest);
+                    let (_, _, largest) = parse_internal_key(&f.largest);
+                    range = Some(match range {
+                        None => (smallest.to_vec(), largest.to_vec()),
+                        Some((from, to)) => (
+                            min_by_cmp(&**self.opt.cmp, from, smallest.to_vec()),
+                            max_by_cmp(&**self.opt.cmp, to, largest.to_vec()),
+                        ),
+                    });
+                }
+                range
+            };
+            if let Some((from, to)) = range {
+                self.compact_range(&from, &to)?;
+                return Ok(true);
+            }
+        }
+        self.idle_done = true;
+        Ok(false)
+    }
+
+    /// compact_range triggers an immediate compaction on the specified key range. Repeatedly
+    /// calling this without actually adding new keys is not useful.
+    ///
+    /// Compactions in general will cause the database to find entries more quickly, and take up
+    /// less space on disk.
+    pub fn compact_range(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+        self.compact_range_with_progress(from, to, &CompactionProgress::new())
+    }
+
+    /// compact_range_with_progress is like `compact_range()`, and reports its progress to
+    /// progress, which can also cancel it (see `CompactionProgress::cancel()`).
+    pub fn compact_range_with_progress(
+        &mut self,
+        from: &[u8],
+        to: &[u8],
+        progress: &CompactionProgress,
+    ) -> Result<()> {
+        self.check_writable()?;
+        if self.paused > 0 {
+            return err(StatusCode::WouldBlock, "background work is paused");
+        }
+        // The compactions of a range are picked one after the other, so none may be left
+        // unfinished.
+        self.step_compactions = false;
+        self.finish_compaction()?;
+        let mut ifrom = LookupKey::new(from, MAX_SEQUENCE_NUMBER)
+            .internal_key()
+            .to_vec();
+        let iend = LookupKey::new_full(to, 0, ValueType::TypeDeletion);
+
+        let mut max_level = 1;
+        let mem_size = self.mem.approx_mem_usage();
+        {
+            let v = self.vset.borrow().current();
+            let v = v.borrow();
+            for l in 1..NUM_LEVELS - 1 {
+                if v.overlap_in_level(l, from, to) {
+                    max_level = l;
+                }
+            }
+            // Each level's data is read again when compacting the next level.
+            let level_sizes: Vec<usize> = (0..max_level + 2)
+                .map(|l| total_size(v.overlapping_inputs(l, &ifrom, iend.internal_key()).iter()))
+                .collect();
+            let files: usize = level_sizes.windows(2).map(|w| w[0] + w[1]).sum();
+            progress.set_total(mem_size + files);
+        }
+
+        // Compact memtable.
+        self.make_room_for_write(true)?;
+        progress.add_processed(mem_size);
+
+        for l in 0..max_level + 1 {
+            loop {
+                if let Err(e) = progress.token().check("compaction") {
+                    self.update_write_stall();
+                    return Err(e);
+                }
+                let c_ = self
+                    .vset
+                    .borrow_mut()
+                    .compact_range(l, &ifrom, iend.internal_key());
+                if let Some(c) = c_ {
+                    // Update ifrom to the largest key of the last file in this compaction.
+                    let ix = c.num_inputs(0) - 1;
+                    ifrom = c.input(0, ix).largest.clone();
+                    let input_size = (0..2)
+                        .map(|p| {
+                            (0..c.num_inputs(p))
+                                .map(|i| c.input(p, i).size)
+                                .sum::<usize>()
+                        })
+                        .sum::<usize>();
+                    self.start_compaction(c)?;
+                    progress.add_processed(input_size);
+                } else {
+                    break;
+                }
+            }
+        }
+        self.update_write_stall();
+        Ok(())
+    }
+
+    /// compact_all compacts the whole key space, like `compact_range()` with the smallest and
+    /// largest keys stored. This reclaims the space of deleted and overwritten entries, e.g.
+    /// after large deletions.
+    pub fn compact_all(&mut self) -> Result<()> {
+        self.flush_memtable()?;
+        let range = {
+            let current = self.current();
+            let v = current.borrow();
+            let mut range: Option<(Vec<u8>, Vec<u8>)> = None;
+            for f in v.files.iter().flatten() {
+                let f = f.borrow();
+                let (_, _, smallest) = parse_internal_key(&f.smallest);
+                let (_, _, largest) = parse_internal_key(&f.largest);
+                range = Some(match range {
+                    None => (smallest.to_vec(), largest.to_vec()),
+                    Some((from, to)) => (
+                        min_by_cmp(&**self.opt.cmp, from, smallest.to_vec()),
+                        max_by_cmp(&**self.opt.cmp, to, largest.to_vec()),
+                    ),
+                });
+            }
+            range
+        };
+        match range {
+            Some((from, to)) => self.compact_range(&from, &to),
+            None => Ok(()),
+        }
+    }
+
+    /// delete_files_in_range removes all table files whose keys lie entirely within [from; to]
+    /// from the database, in one manifest edit and without rewriting any data. It returns the
+    /// number of removed files.
+    ///
+    /// This is the fastest way to drop a large key range, but only a partial one: Entries in the
+    /// memtable and in files that overlap the range only partially are kept (use `delete()` and
+    /// `compact_range()` for those), and older versions of keys in the range that are stored in
+    /// remaining files become visible again. Snapshots don't protect the removed entries.
+    pub fn delete_files_in_range(&mut self, from: &[u8], to: &[u8]) -> Result<usize> {
+        let cmp = self.opt.cmp.clone();
+        self.delete_files_where(|smallest, largest| {
+            cmp.cmp(smallest, from) >= Ordering::Equal && cmp.cmp(largest, to) <= Ordering::Equal
+        })
+    }
+
+    /// delete_prefix deletes all entries whose key starts with prefix. Table files that only
+    /// contain such keys are removed (see `delete_files_in_range()`); the remaining keys are
+    /// deleted by a single range tombstone (see `delete_range()`). Like with
+    /// `delete_files_in_range()`, the entries of removed files disappear for live snapshots, too.
+    ///
+    /// The comparator must order keys with a common prefix contiguously, as `DefaultCmp` does.
+    /// If it isn't `DefaultCmp`, if `Options::leveldb_compatible` is set, or if no key sorts
+    /// after all keys with the prefix (it is empty or consists of 0xff bytes), the remaining keys
+    /// are deleted by writing deletion markers instead, which are applied atomically in batches
+    /// of 1000 keys.
+    pub fn delete_prefix(&mut self, prefix: &[u8]) -> Result<()> {
+        self.delete_files_where(|smallest, largest| {
+            smallest.starts_with(prefix) && largest.starts_with(prefix)
+        })?;
+
+        if self.opt.cmp.id() == DefaultCmp.id() && !self.opt.leveldb_compatible {
+            if let Some(to) = prefix_successor(prefix) {
+                return self.delete_range(prefix, &to);
+            }
+        }
+        let mut from = prefix.to_vec();
+        loop {
+            // Collect a batch of keys first, so that the iterator doesn't outlive the tables
+            // and memtables that are changed by the write.
+            let mut batch = WriteBatch::new();
+            let mut last_key = None;
+            {
+                let mut iter = self.new_iter()?;
+                iter.seek(&from);
+                let (mut k, mut v) = (vec![], vec![]);
+                while iter.valid() && batch.count() < DELETE_PREFIX_BATCH_SIZE {
+                    iter.current(&mut k, &mut v);
+                    if !k.starts_with(prefix) {
+                        break;
+                    }
+                    batch.delete(&k);
+                    last_key = Some(k.clone());
+                    iter.advance();
+                }
+            }
+            match last_key {
+                None => return Ok(()),
+                Some(k) => {
+                    self.write(batch, false)?;
+                    from = k;
+                }
+            }
+        }
+    }
+
+    /// delete_files_where removes all table files for whose smallest and largest user keys
+    /// matches returns true, and returns the number of removed files.
+    fn delete_files_where<F: Fn(&[u8], &[u8]) -> bool>(&mut self, matches: F) -> Result<usize> {
+        self.check_writable()?;
+        self.finish_compaction()?;
+        let mut ve = VersionEdit::new();
+        {
+            let current = self.current();
+            let current = current.borrow();
+            for level in 0..NUM_LEVELS {
+                for f in &current.files[level] {
+                    let f = f.borrow();
+                    if matches(
+                        parse_internal_key(&f.smallest).2,
+                        parse_internal_key(&f.largest).2,
+                    ) {
+                        ve.delete_file(level, f.num);
+                    }
+                }
+            }
+        }
+        let n = ve.deleted.len();
+        if n == 0 {
+            return Ok(0);
+        }
+        log!(self.opt.log, "Deleting {} table files", n);
+        self.vset.borrow_mut().log_and_apply(ve)?;
+        if let Err(e) = self.delete_obsolete_files() {
+            log!(self.opt.log, "Error deleting obsolete files: {}", e);
+        }
+        Ok(n)
+    }
+
+    /// start_compaction dispatches the different kinds of compactions depending on the current
+    /// state of the database.
+    fn start_compaction(&mut self, mut compaction: Compaction) -> Result<()> {
+        let start_ts = self.opt.env.micros();
+        let mut info = CompactionInfo {
+            level: compaction.level(),
+            input_files: vec![],
+            output_files: vec![],
+            bytes_read: 0,
+            bytes_written: 0,
+            micros: 0,
+            trivial_move: compaction.is_trivial_move(),
+        };
+        for parent in 0..2 {
+            for inp in 0..compaction.num_inputs(parent) {
+                info.input_files.push(compaction.input(parent, inp).num);
+            }
+        }
+
+        if compaction.is_trivial_move() {
+            assert_eq!(1, compaction.num_inputs(0));
+            let f = compaction.input(0, 0);
+            let num = f.num;
+            let size = f.size;
+            let level = compaction.level();
+
+            compaction.edit().delete_file(level, num);
+            compaction.edit().add_file(level + 1, f);
+
+            let r = self.vset.borrow_mut().log_and_apply(compaction.into_edit());
+            if let Err(e) = r {
+                log!(self.opt.log, "trivial move failed: {}", e);
+                Err(e)
+            } else {
+                info.output_files.push(num);
+                self.compaction_completed(info, start_ts);
+                log!(
+                    self.opt.log,
+                    "Moved num={} bytes={} from L{} to L{}",
+                    num,
+                    size,
+                    level,
+                    level + 1
+                );
+                log!(
+                    self.opt.log,
+                    "Summary: {}",
+                    self.vset.borrow().current_summary()
+                );
+                Ok(())
+            }
+        } else {
+            assert!(self.running_compaction.is_none());
+            let smallest = self.smallest_snapshot_seq();
+            self.running_compaction = Some(RunningCompaction {
+                state: CompactionState::new(compaction, smallest),
+                info,
+                start_ts,
+            });
+            self.continue_compaction(self.step_compactions).map(|_| ())
+        }
+    }
+
+    /// continue_compaction does the work of the running level compaction, either all of it or,
+    /// if step is set, only the next part (see `compact_step()`). Once all input is processed, it
+    /// installs the results and returns true.
+    fn continue_compaction(&mut self, step: bool) -> Result<bool> {
+        let mut running = match self.running_compaction.take() {
+            Some(running) => running,
+            None => return Ok(true),
+        };
+        if self.opt.low_priority_compaction_io {
+            self.opt.env.set_io_priority(IoPriority::Low);
+        }
+        let r = self.do_compaction_work(&mut running.state, step);
+        if self.opt.low_priority_compaction_io {
+            self.opt.env.set_io_priority(IoPriority::Normal);
+        }
+        match r {
+            Ok(true) => {}
+            Ok(false) => {
+                self.running_compaction = Some(running);
+                return Ok(false);
+            }
+            Err(e) => {
+                // Installing the partial results would drop the input files' remaining entries.
+                running.state.cleanup(&self.opt.env, &self.path);
+                log!(self.opt.log, "Compaction work failed: {}", e);
+                return Err(e);
+            }
+        }
+        let RunningCompaction {
+            state,
+            mut info,
+            start_ts,
+        } = running;
+        for parent in 0..2 {
+            for inp in 0..state.compaction.num_inputs(parent) {
+                info.bytes_read += state.compaction.input(parent, inp).size;
+            }
+        }
+        info.output_files = state.outputs.iter().map(|o| o.num).collect();
+        info.bytes_written = state.outputs.iter().map(|o| o.size).sum();
+        self.install_compaction_results(state)?;
+        if let Some(ref rl) = self.opt.rate_limiter {
+            let pending = self.vset.borrow().needs_compaction();
+            rl.borrow_mut().tune(self.stalled_writes, pending);
+            self.stalled_writes = 0;
+        }
+        log!(
+            self.opt.log,
+            "Compaction finished: {}",
+            self.vset.borrow().current_summary()
+        );
+
+        let r = self.delete_obsolete_files();
+        self.compaction_completed(info, start_ts);
+        r.map(|_| true)
+    }
+
+    /// finish_compaction does the remaining work of a level compaction paused by
+    /// `compact_step()`, if there is one.
+    fn finish_compaction(&mut self) -> Result<()> {
+        self.continue_compaction(false).map(|_| ())
+    }
+
+    /// compaction_completed notifies the event listener of a compaction that started at
+    /// start_ts.
+    fn compaction_completed(&self, mut info: CompactionInfo, start_ts: u64) {
+        if let Some(ref listener) = self.opt.event_listener {
+            info.micros = self.opt.env.micros() - start_ts;
+            listener.on_compaction_completed(&info);
+        }
+    }
+
+    /// log_and_apply_new_files applies ve, which adds table files created for reason, and
+    /// notifies the event listener of them.
+    fn log_and_apply_new_files(&mut self, ve: VersionEdit, reason: TableFileReason) -> Result<()> {
+        let files: Vec<TableFileInfo> = ve
+            .new_files
+            .iter()
+            .map(|(_, f)| TableFileInfo {
+                file_num: f.num,
+                size: f.size,
+            })
+            .collect();
+        self.vset.borrow_mut().log_and_apply(ve)?;
+        if let Some(ref listener) = self.opt.event_listener {
+            for f in &files {
+                listener.on_table_file_created(f, reason);
+            }
+        }
+        Ok(())
+    }
+
+    fn compact_memtable(&mut self) -> Result<()> {
+        assert!(self.imm.is_some());
+
+        let mut ve = VersionEdit::new();
+        let base = self.current();
+
+        let imm = self.imm.take().unwrap();
+        let flush = match self.write_l0_table(&imm, &mut ve, Some(&base.borrow())) {
+            Ok(flush) => flush,
+            Err(e) => {
+                self.imm = Some(imm);
+                return Err(e);
+            }
+        };
+        ve.set_log_num(self.log_num.unwrap_or(0));
+        self.log_and_apply_new_files(ve, TableFileReason::Flush)?;
+        if let (Some(listener), Some(flush)) = (&self.opt.event_listener, flush) {
+            listener.on_flush_completed(&flush);
+        }
+        if let Err(e) = self.delete_obsolete_files() {
+            log!(self.opt.log, "Error deleting obsolete files: {}", e);
+        }
+        Ok(())
+    }
+
+    /// write_l0_table writes the given memtable to a table file, and adds it to ve. It returns
+    /// None if no file was written, as all entries were obsolete.
+    fn write_l0_table(
+        &mut self,
+        memt: &MemTable,
+        ve: &mut VersionEdit,
+        base: Option<&Version>,
+    ) -> Result<Option<FlushInfo>> {
+        let start_ts = self.opt.env.micros();
+        let num = self.vset.borrow_mut().new_file_number();
+        log!(self.opt.log, "Start write of L0 table {:06}", num);
+        // The memtable's range tombstones move to the manifest. Of the files that exist then,
+        // only the new table and older ones may contain entries they cover.
+        for t in memt.range_tombstones() {
+            ve.add_range_tombstone(RangeTombstone {
+                first_clean_file: num + 1,
+                ..t.clone()
+            });
+        }
+        let fmd = match base {
+            Some(b) => {
+                let mut filter =
+                    ObsoleteEntryFilter::new(self.opt.cmp.clone(), self.smallest_snapshot_seq());
+                build_table_filtered(&self.path, &self.opt, memt.iter(), num, |k| {
+                    !filter.is_obsolete(k, b)
+                })?
+            }
+            // During recovery, there is no version to check for older entries.
+            None => build_table(&self.path, &self.opt, memt.iter(), num)?,
+        };
+        log!(self.opt.log, "L0 table {:06} has {} bytes", num, fmd.size);
+
+        // Wrote empty table.
+        if fmd.size == 0 {
+            self.vset.borrow_mut().reuse_file_number(num);
+            return Ok(None);
+        }
+
+        let cache_result = self.cache.borrow_mut().get_table(num);
+        if let Err(e) = cache_result {
+            log!(
+                self.opt.log,
+                "L0 table {:06} not returned by cache: {}",
+                num,
+                e
+            );
+            let _ = self
+                .opt
+                .env
+                .delete(Path::new(&table_file_name(&self.path, num)));
+            return Err(e);
+        }
+
+        let mut stats = CompactionStats::default();
+        stats.micros = self.opt.env.micros() - start_ts;
+        stats.written = fmd.size;
+
+        let mut level = 0;
+        // A paused compaction's outputs may span gaps between its inputs, which the table
+        // could be moved into.
+        if let (Some(b), None) = (base, &self.running_compaction) {
+            level = b.pick_memtable_output_level(
+                parse_internal_key(&fmd.smallest).2,
+                parse_internal_key(&fmd.largest).2,
+            );
+        }
+
+        let flush = FlushInfo {
+            file_num: num,
+            level,
+            size: fmd.size,
+            num_entries: fmd.num_entries,
+            micros: stats.micros,
+        };
+        self.add_stats(level, stats);
+        ve.add_file(level, fmd);
+
+        Ok(Some(flush))
+    }
+
+    /// smallest_snapshot_seq returns the oldest sequence number that may still be observed by a
+    /// reader. Older versions of a key that are shadowed by a newer version before this sequence
+    /// number can be dropped.
+    fn smallest_snapshot_seq(&self) -> SequenceNumber {
+        if self.snaps.empty() {
+            self.vset.borrow().last_seq
+        } else {
+            self.snaps.oldest()
+        }
+    }
+
+    /// do_compaction_work writes the compaction's input to its output files. If step is set, it
+    /// returns false once about one output file's worth of input is processed, and continues from
+    /// there when called again. Memtable compactions may run in between, but nothing else that
+    /// changes the current version.
+    fn do_compaction_work(&mut self, cs: &mut CompactionState, step: bool) -> Result<bool> {
+        let start_ts = self.opt.env.micros();
+        if cs.input.is_none() {
+            {
+                let current = self.vset.borrow().current();
+                assert!(current.borrow().num_level_files(cs.compaction.level()) > 0);
+                assert!(cs.builder.is_none());
+            }
+            log!(
+                self.opt.log,
+                "Compacting {} files at L{} and {} files at L{}",
+                cs.compaction.num_inputs(0),
+                cs.compaction.level(),
+                cs.compaction.num_inputs(1),
+                cs.compaction.level() + 1
+            );
+
+            let mut input = self.vset.borrow().make_input_iterator(&cs.compaction);
+            input.seek_to_first();
+            cs.input = Some(input);
+            // Entries covered by these tombstones are invisible to all snapshots.
+            cs.range_tombstones = self
+                .current()
+                .borrow()
+                .range_tombstones
+                .iter()
+                .filter(|t| t.seq <= cs.smallest_seq)
+                .cloned()
+                .collect();
+        }
+        let mut input = cs.input.take().unwrap();
+        let range_tombstones = mem::take(&mut cs.range_tombstones);
+
+        let (mut key, mut val) = (vec![], vec![]);
+        let mut last_seq_for_key = cs.last_seq_for_key;
+
+        let mut have_ukey = cs.current_ukey.is_some();
+        let mut current_ukey = cs.current_ukey.take().unwrap_or_default();
+        let mut processed = 0;
+
+        while input.valid() {
+            if step && processed >= cs.compaction.max_output_file_size() {
+                // Pause here; memtable compactions may run before the next step.
+                cs.input = Some(input);
+                cs.range_tombstones = range_tombstones;
+                cs.last_seq_for_key = last_seq_for_key;
+                cs.current_ukey = if have_ukey { Some(current_ukey) } else { None };
+                cs.micros += self.opt.env.micros() - start_ts;
+                return Ok(false);
+            }
+            assert!(input.current(&mut key, &mut val));
+            processed += key.len() + val.len();
+            if cs.compaction.should_stop_before(&key) && cs.builder.is_some() {
+                self.finish_compaction_output(cs)?;
+            }
+            let (ktyp, seq, ukey) = parse_internal_key(&key);
+            if seq == 0 {
+                // Parsing failed.
+                log!(self.opt.log, "Encountered seq=0 in key: {:?}", &key);
+                last_seq_for_key = MAX_SEQUENCE_NUMBER;
+                have_ukey = false;
+                current_ukey.clear();
+                input.advance();
+                continue;
+            }
+
+            if !have_ukey || self.opt.cmp.cmp(ukey, &current_ukey) != Ordering::Equal {
+                // First occurrence of this key.
+                current_ukey.clear();
+                current_ukey.extend_from_slice(ukey);
+                have_ukey = true;
+                last_seq_for_key = MAX_SEQUENCE_NUMBER;
+            }
+
+            // We can omit the key under the following conditions:
+            if last_seq_for_key <= cs.smallest_seq {
+                last_seq_for_key = seq;
+                input.advance();
+                continue;
+            }
+            // Entry is covered by a range tombstone that all snapshots see.
+            if !range_tombstones.is_empty()
+                && covering_seq(
+                    &range_tombstones,
+                    self.opt.cmp.as_ref().as_ref(),
+                    ukey,
+                    MAX_SEQUENCE_NUMBER,
+                ) > seq
+            {
+                last_seq_for_key = seq;
+                input.advance();
+                continue;
+            }
+            // Entry is deletion; no older version is observable by any snapshot; and all entries
+            // in compacted levels with smaller sequence numbers will
+            if ktyp == ValueType::TypeDeletion
+                && seq <= cs.smallest_seq
+                && cs.compaction.is_base_level_for(ukey)
+            {
+                last_seq_for_key = seq;
+                input.advance();
+                continue;
+            }
+            // Value removed by the compaction filter; no snapshot sees an older version. It is
+            // written as a deletion if older versions may exist in deeper levels.
+            let filtered = ktyp == ValueType::TypeValue
+                && seq <= cs.smallest_seq
+                && self
+                    .opt
+                    .compaction_filter
+                    .as_ref()
+                    .is_some_and(|f| f.filter(ukey, &val));
+            if filtered && cs.compaction.is_base_level_for(ukey) {
+                last_seq_for_key = seq;
+                input.advance();
+                continue;
+            }
+
+            last_seq_for_key = seq;
+
+            if cs.builder.is_some() && self.crosses_prefix(&cs.current_output().largest, ukey) {
+                self.finish_compaction_output(cs)?;
+            }
+            if cs.builder.is_none() {
+                let fnum = self.vset.borrow_mut().new_file_number();
+                let mut fmd = FileMetaData::default();
+                fmd.num = fnum;
+
+                let fname = table_file_name(&self.path, fnum);
+                let prio = if self.opt.low_priority_compaction_io {
+                    IoPriority::Low
+                } else {
+                    IoPriority::Normal
+                };
+                let f = self
+                    .opt
+                    .env
+                    .open_writable_file_with_priority(Path::new(&fname), prio)?;
+                let f: Box<dyn Write> = match self.opt.rate_limiter {
+                    Some(ref rl) => Box::new(RateLimitedWriter::new(f, rl.clone())),
+                    None => f,
+                };
+                let f = Box::new(BufWriter::new(f));
+                cs.builder = Some(TableBuilder::new(self.opt.clone(), f));
+                cs.outputs.push(fmd);
+            }
+            if filtered {
+                key = LookupKey::new_full(&current_ukey, seq, ValueType::TypeDeletion)
+                    .internal_key()
+                    .to_vec();
+                val.clear();
+            }
+            if cs.builder.as_ref().unwrap().entries() == 0 {
+                cs.current_output().smallest = key.clone();
+            }
+            cs.current_output().largest = key.clone();
+            cs.builder.as_mut().unwrap().add(&key, &val)?;
+            if cs.builder.as_ref().unwrap().size_estimate() > cs.compaction.max_output_file_size() {
+                self.finish_compaction_output(cs)?;
+            }
+
+            input.advance();
+        }
+
+        if cs.builder.is_some() {
+            self.finish_compaction_output(cs)?;
+        }
+
+        let mut stats = CompactionStats {
+            micros: cs.micros + self.opt.env.micros() - start_ts,
+            ..Default::default()
+        };
+        for parent in 0..2 {
+            for inp in 0..cs.compaction.num_inputs(parent) {
+                stats.read += cs.compaction.input(parent, inp).size;
+            }
+        }
+        for output in &cs.outputs {
+            stats.written += output.size;
+        }
+        self.add_stats(cs.compaction.level(), stats);
+        Ok(true)
+    }
+
+    /// crosses_prefix returns true if output files are partitioned by prefix (see
+    /// `Options::partition_by_prefix`), and ukey has another prefix than the internal key last.
+    fn crosses_prefix(&self, last: &[u8], ukey: &[u8]) -> bool {
+        match self.opt.prefix_extractor {
+            Some(ref pe) if self.opt.partition_by_prefix => {
+                let (_, _, last) = parse_internal_key(last);
+                pe.prefix(last) != pe.prefix(ukey)
+            }
+            _ => false,
+        }
+    }
+
+    fn finish_compaction_output(&mut self, cs: &mut CompactionState) -> Result<()> {
+        assert!(cs.builder.is_some());
+        let output_num = cs.current_output().num;
+        assert!(output_num > 0);
+
+        // The original checks if the input iterator has an OK status. For this, we'd need to
+        // extend the LdbIterator interface though -- let's see if we can without for now.
+        // (it's not good for corruptions, in any case)
+        let b = cs.builder.take().unwrap();
+        let entries = b.entries();
+        let props = b.properties().clone();
+        let bytes = b.finish()?;
+        sync_new_file(
+            &self.opt,
+            &self.path,
+            &table_file_name(&self.path, output_num),
+        )?;
+        cs.total_bytes += bytes;
+
+        cs.current_output().size = bytes;
+        cs.current_output().num_entries = props.num_entries;
+        cs.current_output().num_deletions = props.num_deletions;
+
+        if entries > 0 {
+            // Verify that table can be used. (Separating get_table() because borrowing in an if
+            // let expression is dangerous).
+            let r = self.cache.borrow_mut().get_table(output_num);
+            if let Err(e) = r {
+                log!(self.opt.log, "New table can't be read: {}", e);
+                return Err(e);
+            }
+            log!(
+                self.opt.log,
+                "New table num={}: keys={} size={}",
+                output_num,
+                entries,
+                bytes
+            );
+        }
+        Ok(())
+    }
+
+    fn install_compaction_results(&mut self, mut cs: CompactionState) -> Result<()> {
+        log!(
+            self.opt.log,
+            "Compacted {} L{} files + {} L{} files => {}B",
+            cs.compaction.num_inputs(0),
+            cs.compaction.level(),
+            cs.compaction.num_inputs(1),
+            cs.compaction.level() + 1,
+            cs.total_bytes
+        );
+        cs.compaction.add_input_deletions();
+        let level = cs.compaction.level();
+        for output in &cs.outputs {
+            cs.compaction.edit().add_file(level + 1, output.clone());
+        }
+        // The outputs may keep entries covered by tombstones that some snapshot doesn't see yet.
+        let next_file = self.vset.borrow().next_file_num;
+        let current = self.current();
+        for t in current.borrow().range_tombstones.iter() {
+            if t.seq > cs.smallest_seq
+                && cs.outputs.iter().any(|o| {
+                    t.overlaps(
+                        self.opt.cmp.as_ref().as_ref(),
+                        parse_internal_key(&o.smallest).2,
+                        parse_internal_key(&o.largest).2,
+                    )
+                })
+            {
+                cs.compaction.edit().add_range_tombstone(RangeTombstone {
+                    first_clean_file: next_file,
+                    ..t.clone()
+                });
+            }
+        }
+        self.log_and_apply_new_files(cs.compaction.into_edit(), TableFileReason::Compaction)
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        self.flush().ok();
+        if self.opt.persist_hot_blocks && self.lock.is_some() {
+            if let Err(e) = self.save_hot_blocks() {
+                log!(self.opt.log, "Error saving hot blocks: {}", e);
+            }
+        }
+        let _ = self.release_lock();
+    }
+}
+
+/// RunningCompaction is a level compaction that `DB::compact_step()` does in steps.
+struct RunningCompaction {
+    state: CompactionState,
+    info: CompactionInfo,
+    start_ts: u64,
+}
+
+struct CompactionState {
+    compaction: Compaction,
+    smallest_seq: SequenceNumber,
+    outputs: Vec<FileMetaData>,
+    builder: Option<TableBuilder<Box<dyn Write>>>,
+    total_bytes: usize,
+
+    // Where do_compaction_work() continues after a step.
+    input: Option<Box<dyn LdbIterator>>,
+    range_tombstones: Vec<RangeTombstone>,
+    last_seq_for_key: SequenceNumber,
+    current_ukey: Option<Vec<u8>>,
+    micros: u64,
+}
+
+impl CompactionState {
+    fn new(c: Compaction, smallest: SequenceNumber) -> CompactionState {
+        CompactionState {
+            compaction: c,
+            smallest_seq: smallest,
+            outputs: vec![],
+            builder: None,
+            total_bytes: 0,
+            input: None,
+            range_tombstones: vec![],
+            last_seq_for_key: MAX_SEQUENCE_NUMBER,
+            current_ukey: None,
+            micros: 0,
+        }
+    }
+
+    fn current_output(&mut self) -> &mut FileMetaData {
+        let len = self.outputs.len();
+        &mut self.outputs[len - 1]
+    }
+
+    /// cleanup cleans up after an aborted compaction.
+    fn cleanup<P: AsRef<Path>>(&mut self, env: &Box<dyn Env>, name: P) {
+        for o in self.outputs.drain(..) {
+            let name = table_file_name(name.as_ref(), o.num);
+            let _ = env.delete(&name);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct CompactionStats {
+    micros: u64,
+    read: usize,
+    written: usize,
+}
+
+impl CompactionStats {
+    fn add(&mut self, cs: CompactionStats) {
+        self.micros += cs.micros;
+        self.read += cs.read;
+        self.written += cs.written;
+    }
+}
+
+/// ObsoleteEntryFilter decides which entries of a memtable are not needed anymore when it is
+/// written to a table: Versions shadowed by a newer version that is visible to all readers, and
+/// deletion markers visible to all readers for keys that are not stored in any table. This way,
+/// deletions of keys that never left the memtable don't have to be carried to the lowest level.
+struct ObsoleteEntryFilter {
+    cmp: Rc<Box<dyn Cmp>>,
+    smallest_seq: SequenceNumber,
+    current_ukey: Option<Vec<u8>>,
+    last_seq_for_key: SequenceNumber,
+}
+
+impl ObsoleteEntryFilter {
+    fn new(cmp: Rc<Box<dyn Cmp>>, smallest_seq: SequenceNumber) -> ObsoleteEntryFilter {
+        ObsoleteEntryFilter {
+            cmp,
+            smallest_seq,
+            current_ukey: None,
+            last_seq_for_key: MAX_SEQUENCE_NUMBER,
+        }
+    }
+
+    /// is_obsolete must be called for every entry, in order.
+    fn is_obsolete(&mut self, key: InternalKey, base: &Version) -> bool {
+        let (typ, seq, ukey) = parse_internal_key(key);
+        let same_key = match self.current_ukey {
+            Some(ref k) => self.cmp.cmp(ukey, k) == Ordering::Equal,
+            None => false,
+        };
+        if !same_key {
+            self.current_ukey = Some(ukey.to_vec());
+            self.last_seq_for_key = MAX_SEQUENCE_NUMBER;
+        }
+        let obsolete = self.last_seq_for_key <= self.smallest_seq
+            || (typ == ValueType::TypeDeletion
+                && seq <= self.smallest_seq
+                && (0..NUM_LEVELS).all(|l| !base.overlap_in_level(l, ukey, ukey)));
+        self.last_seq_for_key = seq;
+        obsolete
+    }
+}
+
+pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
+    dbname: P,
+    opt: &Options,
+    from: I,
+    num: FileNum,
+) -> Result<FileMetaData> {
+    build_table_filtered(dbname, opt, from, num, |_| true)
+}
+
+/// build_table_filtered writes the entries of from for which keep returns true to a new table.
+fn build_table_filtered<I: LdbIterator, P: AsRef<Path>, F: FnMut(&[u8]) -> bool>(
+    dbname: P,
+    opt: &Options,
+    mut from: I,
+    num: FileNum,
+    mut keep: F,
+) -> Result<FileMetaData> {
+    from.reset();
+    let filename = table_file_name(dbname.as_ref(), num);
+
+    let (mut kbuf, mut vbuf) = (vec![], vec![]);
+    let mut firstkey = None;
+    let mut props = TableProperties::default();
+    // lastkey is what remains in kbuf.
+
+    // Clean up file if write fails at any point.
+    //
+    // TODO: Replace with catch {} when available.
+    let r = (|| -> Result<()> {
+        let f = opt.env.open_writable_file(Path::new(&filename))?;
+        let f = BufWriter::new(f);
+        let mut builder = TableBuilder::new(opt.clone(), f);
+        while from.advance() {
+            assert!(from.current(&mut kbuf, &mut vbuf));
+            if !keep(&kbuf) {
+                continue;
+            }
+            if firstkey.is_none() {
+                firstkey = Some(kbuf.clone());
+            }
+            builder.add(&kbuf, &vbuf)?;
+        }
+        props = builder.properties().clone();
+        builder.finish()?;
+        sync_new_file(opt, dbname.as_ref(), &filename)
+    })();
+
+    if let Err(e) = r {
+        let _ = opt.env.delete(Path::new(&filename));
+        return Err(e);
+    }
+
+    let mut md = FileMetaData::default();
+    match firstkey {
+        None => {
+            let _ = opt.env.delete(Path::new(&filename));
+        }
+        Some(key) => {
+            md.num = num;
+            md.size = opt.env.size_of(Path::new(&filename))?;
+            md.smallest = key;
+            md.largest = kbuf;
+            md.num_entries = props.num_entries;
+            md.num_deletions = props.num_deletions;
+        }
+    }
+    Ok(md)
+}
+
+/// prefix_successor returns the smallest key that sorts after all keys starting with prefix in
+/// byte order, or None if there is no such key.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&b| b != 0xff)?;
+    let mut to = prefix[..=last].to_vec();
+    to[last] += 1;
+    Some(to)
+}
+
+fn min_by_cmp(cmp: &dyn Cmp, a: Vec<u8>, b: Vec<u8>) -> Vec<u8> {
+    if cmp.cmp(&a, &b) == Ordering::Greater {
+        b
+    } else {
+        a
+    }
+}
+
+fn max_by_cmp(cmp: &dyn Cmp, a: Vec<u8>, b: Vec<u8>) -> Vec<u8> {
+    if cmp.cmp(&a, &b) == Ordering::Less {
+        b
+    } else {
+        a
+    }
+}
+
+/// destroy_db deletes the database at name, like `leveldb::DestroyDB()`: Its files are deleted,
+/// and then its directory, if nothing else is left in it. Files that don't belong to the database
+/// are kept. The database must not be open; otherwise, `StatusCode::LockError` is returned. A
+/// database that doesn't exist is destroyed already.
+pub fn destroy_db<P: AsRef<Path>>(name: P, opt: &Options) -> Result<()> {
+    let env = &opt.env;
+    let path = name.as_ref();
+    let children = match env.children(path) {
+        Ok(children) => children,
+        Err(_) => return Ok(()),
+    };
+    let lock = lock_db(env.as_ref().as_ref(), path)?;
+    let mut result = Ok(());
+    for name in children {
+        match parse_file_name(&name) {
+            // The lock file is deleted once it is released.
+            Ok((_, FileType::DBLock)) | Err(_) => {}
+            Ok(_) => {
+                if let Err(e) = env.delete(&path.join(&name)) {
+                    result = result.and(Err(e));
+                }
+            }
+        }
+    }
+    env.unlock(lock)?;
+    let _ = env.delete(&lock_file_name(path));
+    // rmdir() may delete the directory's contents, too.
+    if env.children(path).is_ok_and(|c| c.is_empty()) {
+        let _ = env.rmdir(path);
+    }
+    result
+}
+
+/// sync_new_file persists a newly written file according to `opt.durability`.
+fn sync_new_file(opt: &Options, dir: &Path, name: &Path) -> Result<()> {
+    match opt.durability {
+        Durability::None | Durability::Buffered => Ok(()),
+        Durability::DataSync => opt.env.sync_file_data(name),
+        Durability::FullSync => {
+            opt.env.sync_file(name)?;
+            opt.env.sync_dir(dir)
+        }
+    }
+}
+
+/// copy_file copies a file's current contents to a new file, and syncs it.
+fn copy_file(env: &dyn Env, from: &Path, to: &Path) -> Result<()> {
+    {
+        let mut src = env.open_sequential_file(from)?;
+        let mut dst = env.open_writable_file(to)?;
+        io::copy(&mut src, &mut dst)?;
+        dst.flush()?;
+    }
+    env.sync_file(to)
+}
+
+pub(crate) fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
+    db.join(format!("{:06}.log", num))
+}
+
+fn lock_file_name(db: &Path) -> PathBuf {
+    db.join("LOCK")
+}
+
+/// lock_db acquires the lock file of the database at db.
+pub(crate) fn lock_db(env: &dyn Env, db: &Path) -> Result<FileLock> {
+    match env.lock(&lock_file_name(db)) {
+        Ok(lock) => Ok(lock),
+        Err(ref e) if e.code == StatusCode::LockError => err(
+            StatusCode::LockError,
+            "database lock is held by another instance",
+        ),
+        Err(e) => Err(e),
+    }
+}
+
+fn hot_blocks_file_name(db: &Path) -> PathBuf {
+    db.join("HOTBLOCKS")
+}
+
+fn named_snapshots_file_name(db: &Path) -> PathBuf {
+    db.join("SNAPSHOTS")
+}
+
+fn meta_file_name(db: &Path) -> PathBuf {
+    db.join("METADATA")
+}
+
+/// open_info_log opens an info log file in the given database. It transparently returns a
+/// /dev/null logger in case the open fails.
+fn open_info_log<E: Env + ?Sized, P: AsRef<Path>>(env: &E, db: P) -> Logger {
+    let db = db.as_ref();
+    let logfilename = db.join("LOG");
+    let oldlogfilename = db.join("LOG.old");
+    let _ = env.mkdir(Path::new(db));
+    if let Ok(e) = env.exists(Path::new(&logfilename)) {
+        if e {
+            let _ = env.rename(Path::new(&logfilename), Path::new(&oldlogfilename));
+        }
+    }
+    if let Ok(w) = env.open_writable_file(Path::new(&logfilename)) {
+        Logger(w)
+    } else {
+        Logger(Box::new(io::sink()))
+    }
+}
+
+#[cfg(test)]
+pub mod testutil {
+    use super::*;
+
+    use crate::version::testutil::make_version;
+
+    /// build_db creates a database filled with the tables created by make_version().
+    pub fn build_db() -> (DB, Options) {
+        let name = "db";
+        let (v, mut opt) = make_version();
+        opt.reuse_logs = false;
+        opt.reuse_manifest = false;
+        let mut ve = VersionEdit::new();
+        ve.set_comparator_name(opt.cmp.id());
+        ve.set_log_num(0);
+        // 9 files + 1 manifest we write below.
+        ve.set_next_file(11);
+        // 30 entries in these tables.
+        ve.set_last_seq(30);
+
+        for l in 0..NUM_LEVELS {
+            for f in &v.files[l] {
+                ve.add_file(l, f.borrow().clone());
+            }
+        }
+
+        let manifest = manifest_file_name(name, 10);
+        let manifest_file = opt.env.open_writable_file(Path::new(&manifest)).unwrap();
+        let mut lw = LogWriter::new(manifest_file);
+        lw.add_record(&ve.encode()).unwrap();
+        lw.flush().unwrap();
+        set_current_file(&opt.env, name, 10).unwrap();
+
+        (DB::open(name, opt.clone()).unwrap(), opt)
+    }
+
+    /// set_file_to_compact ensures that the specified table file will be compacted next.
+    pub fn set_file_to_compact(db: &mut DB, num: FileNum) {
+        let v = db.current();
+        let mut v = v.borrow_mut();
+
+        let mut ftc = None;
+        for l in 0..NUM_LEVELS {
+            for f in &v.files[l] {
+                if f.borrow().num == num {
+                    ftc = Some((f.clone(), l));
+                }
+            }
+        }
+        if let Some((f, l)) = ftc {
+            v.file_to_compact = Some(f);
+            v.file_to_compact_lvl = l;
+        } else {
+            panic!("file number not found");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testutil::{build_db, set_file_to_compact};
+    use super::*;
+
+    use crate::cache::Cache;
+    use crate::compaction_scheduler::{CompactionJob, CompactionScheduler};
+    use crate::env::{RandomAccess, WritableFile};
+    use crate::error::Status;
+    use crate::event_listener::EventListener;
+    use crate::key_types::LookupKey;
+    use crate::mem_env::MemEnv;
+    use crate::options;
+    use crate::rate_limiter::RateLimiter;
+    use crate::table::TableFileWriter;
+    use crate::test_util::LdbIteratorIter;
+    use crate::types::current_key_val;
+    use crate::version::testutil::make_version;
+    use crate::write_stall::WriteStallListener;
+
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::time::Duration;
+
+    #[test]
+    fn test_db_impl_open_info_log() {
+        let e = MemEnv::new();
+        {
+            let l = Some(share(open_info_log(&e, "abc")));
+            assert!(e.exists(&Path::new("abc").join("LOG")).unwrap());
+            log!(l, "hello {}", "world");
+            assert_eq!(12, e.size_of(&Path::new("abc").join("LOG")).unwrap());
+        }
+        {
+            let l = Some(share(open_info_log(&e, "abc")));
+            assert!(e.exists(&Path::new("abc").join("LOG.old")).unwrap());
+            assert!(e.exists(&Path::new("abc").join("LOG")).unwrap());
+            assert_eq!(12, e.size_of(&Path::new("abc").join("LOG.old")).unwrap());
+            assert_eq!(0, e.size_of(&Path::new("abc").join("LOG")).unwrap());
+            log!(l, "something else");
+            log!(l, "and another {}", 1);
+
+            let mut s = String::new();
+            let mut r = e
+                .open_sequential_file(&Path::new("abc").join("LOG"))
+                .unwrap();
+            r.read_to_string(&mut s).unwrap();
+            assert_eq!("something else\nand another 1\n", &s);
+        }
+    }
+
+    fn build_memtable() -> MemTable {
+        let mut mt = MemTable::new(options::for_test().cmp);
+        let mut i = 1;
+        for k in ["abc", "def", "ghi", "jkl", "mno", "aabc", "test123"].iter() {
+            mt.add(
+                i,
+                ValueType::TypeValue,
+                k.as_bytes(),
+                "looooongval".as_bytes(),
+            );
+            i += 1;
+        }
+        mt
+    }
+
+    #[test]
+    fn test_db_impl_init() {
+        // A sanity check for recovery and basic persistence.
+        let opt = options::for_test();
+        let env = opt.env.clone();
+
+        // Several test cases with different options follow. The printlns can eventually be
+        // removed.
+
+        {
+            let mut opt = opt.clone();
+            opt.reuse_manifest = false;
+            let _ = DB::open("otherdb", opt.clone()).unwrap();
+
+            eprintln!(
+                "children after: {:?}",
+                env.children(Path::new("otherdb")).unwrap()
+            );
+            assert!(env.exists(&Path::new("otherdb").join("CURRENT")).unwrap());
+            // Database is initialized and initial manifest reused.
+            assert!(!env
+                .exists(&Path::new("otherdb").join("MANIFEST-000001"))
+                .unwrap());
+            assert!(env
+                .exists(&Path::new("otherdb").join("MANIFEST-000002"))
+                .unwrap());
+            assert!(env
+                .exists(&Path::new("otherdb").join("000003.log"))
+                .unwrap());
+        }
+
+        {
+            let mut opt = opt.clone();
+            opt.reuse_manifest = true;
+            let mut db = DB::open("db", opt.clone()).unwrap();
+
+            eprintln!(
+                "children after: {:?}",
+                env.children(&Path::new("db").join("")).unwrap()
+            );
+            assert!(env.exists(&Path::new("db").join("CURRENT")).unwrap());
+            // Database is initialized and initial manifest reused.
+            assert!(env
+                .exists(&Path::new("db").join("MANIFEST-000001"))
+                .unwrap());
+            assert!(env.exists(&Path::new("db").join("LOCK")).unwrap());
+            assert!(env.exists(&Path::new("db").join("000003.log")).unwrap());
+
+            db.put("abc".as_bytes(), "def".as_bytes()).unwrap();
+            db.put("abd".as_bytes(), "def".as_bytes()).unwrap();
+        }
+
+        {
+            eprintln!(
+                "children before: {:?}",
+                env.children(&Path::new("db").join("")).unwrap()
+            );
+            let mut opt = opt.clone();
+            opt.reuse_manifest = false;
+            opt.reuse_logs = false;
+            let mut db = DB::open("db", opt.clone()).unwrap();
+
+            eprintln!(
+                "children after: {:?}",
+                env.children(&Path::new("db").join("")).unwrap()
+            );
+            // Obsolete manifest is deleted.
+            assert!(!env
+                .exists(&Path::new("db").join("MANIFEST-000001"))
+                .unwrap());
+            // New manifest is created.
+            assert!(env
+                .exists(&Path::new("db").join("MANIFEST-000002"))
+                .unwrap());
+            // Obsolete log file is deleted.
+            assert!(!env.exists(&Path::new("db").join("000003.log")).unwrap());
+            // New L0 table has been added.
+            assert!(env.exists(&Path::new("db").join("000003.ldb")).unwrap());
+            assert!(env.exists(&Path::new("db").join("000004.log")).unwrap());
+            // Check that entry exists and is correct. Phew, long call chain!
+            let current = db.current();
+            log!(opt.log, "files: {:?}", current.borrow().files);
+            assert_eq!(
+                "def".as_bytes(),
+                current
+                    .borrow_mut()
+                    .get(
+                        LookupKey::new("abc".as_bytes(), 1).internal_key(),
+                        &ReadOptions::default()
+                    )
+                    .unwrap()
+                    .unwrap()
+                    .0
+                    .as_ref()
+            );
+            db.put("abe".as_bytes(), "def".as_bytes()).unwrap();
+        }
+
+        {
+            eprintln!(
+                "children before: {:?}",
+                env.children(Path::new("db")).unwrap()
+            );
+            // reuse_manifest above causes the old manifest to be deleted as obsolete, but no new
+            // manifest is written. CURRENT becomes stale.
+            let mut opt = opt.clone();
+            opt.reuse_logs = true;
+            let db = DB::open("db", opt).unwrap();
+
+            eprintln!(
+                "children after: {:?}",
+                env.children(Path::new("db")).unwrap()
+            );
+            assert!(!env
+                .exists(&Path::new("db").join("MANIFEST-000001"))
+                .unwrap());
+            assert!(env
+                .exists(&Path::new("db").join("MANIFEST-000002"))
+                .unwrap());
+            assert!(!env
+                .exists(&Path::new("db").join("MANIFEST-000005"))
+                .unwrap());
+            assert!(env.exists(&Path::new("db").join("000004.log")).unwrap());
+            // 000004 should be reused, no new log file should be created.
+            assert!(!env.exists(&Path::new("db").join("000006.log")).unwrap());
+            // Log is reused, so memtable should contain last written entry from above.
+            assert_eq!(1, db.mem.len());
+            assert_eq!(
+                "def".as_bytes(),
+                db.mem
+                    .get(&LookupKey::new("abe".as_bytes(), 3))
+                    .0
+                    .unwrap()
+                    .as_slice()
+            );
+        }
+    }
+
+    #[test]
+    fn test_db_impl_compact_all() {
+        let mut db = DB::open("db2", options::for_test()).unwrap();
+        db.compact_all().unwrap();
+
+        let (mut db, _) = build_db();
+        db.put(b"zzz", b"new").unwrap();
+        db.delete(b"aaa").unwrap();
+        db.compact_all().unwrap();
+        let levels: Vec<usize> = db
+            .current()
+            .borrow()
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, fs)| !fs.is_empty())
+            .map(|(l, _)| l)
+            .collect();
+        assert_eq!(1, levels.len(), "{:?}", levels);
+        assert!(levels[0] > 0);
+        assert_eq!(None, db.get(b"aaa").unwrap());
+        assert_eq!(Some(b"new".to_vec()), db.get(b"zzz").unwrap());
+        assert_eq!(Some(b"val2".to_vec()), db.get(b"cab").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_compact_range() {
+        let (mut db, opt) = build_db();
+        let env = &opt.env;
+
+        eprintln!(
+            "children before: {:?}",
+            env.children(&Path::new("db").join("")).unwrap()
+        );
+        db.compact_range(b"aaa", b"dba").unwrap();
+        eprintln!(
+            "children after: {:?}",
+            env.children(&Path::new("db").join("")).unwrap()
+        );
+
+        assert_eq!(
+            320,
+            opt.env
+                .size_of(&Path::new("db").join("000007.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            200,
+            opt.env
+                .size_of(&Path::new("db").join("000008.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            200,
+            opt.env
+                .size_of(&Path::new("db").join("000009.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            435,
+            opt.env
+                .size_of(&Path::new("db").join("000015.ldb"))
+                .unwrap()
+        );
+
+        assert!(!opt.env.exists(&Path::new("db").join("000001.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000002.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000004.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000005.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000006.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000013.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000014.ldb")).unwrap());
+
+        assert_eq!(b"val1".to_vec(), db.get(b"aaa").unwrap().unwrap());
+        assert_eq!(b"val2".to_vec(), db.get(b"cab").unwrap().unwrap());
+        assert_eq!(b"val3".to_vec(), db.get(b"aba").unwrap().unwrap());
+        assert_eq!(b"val3".to_vec(), db.get(b"fab").unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_compact_range_with_progress() {
+        let (mut db, _) = build_db();
+        db.put(b"aab", b"val4").unwrap();
+        let progress = CompactionProgress::new();
+        db.compact_range_with_progress(b"aaa", b"dba", &progress)
+            .unwrap();
+        assert!(progress.total_bytes() > 0);
+        assert!(progress.processed_bytes() >= progress.total_bytes() / 2);
+
+        // A cancelled compaction stops after compacting the memtable.
+        let (mut db, _) = build_db();
+        db.put(b"aab", b"val4").unwrap();
+        let files = |db: &DB| {
+            let v = db.current();
+            let v = v.borrow();
+            (0..NUM_LEVELS)
+                .map(|l| v.num_level_files(l))
+                .collect::<Vec<_>>()
+        };
+        let before = files(&db);
+        let progress = CompactionProgress::new();
+        let remote = progress.clone();
+        std::thread::spawn(move || remote.cancel()).join().unwrap();
+        assert_eq!(
+            StatusCode::Incomplete,
+            db.compact_range_with_progress(b"aaa", b"dba", &progress)
+                .err()
+                .unwrap()
+                .code
+        );
+        assert_eq!(before.iter().sum::<usize>() + 1, files(&db).iter().sum());
+        assert!(progress.processed_bytes() > 0);
+        assert!(progress.processed_bytes() < progress.total_bytes());
+        assert_eq!(b"val4".to_vec(), db.get(b"aab").unwrap().unwrap());
+        assert_eq!(b"val3".to_vec(), db.get(b"fab").unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_verify_integrity() {
+        let (mut db, opt) = build_db();
+        db.verify_integrity(None).unwrap();
+
+        let token = CancellationToken::new();
+        db.verify_integrity(Some(&token)).unwrap();
+        token.cancel();
+        assert_eq!(
+            StatusCode::Incomplete,
+            db.verify_integrity(Some(&token)).err().unwrap().code
+        );
+        let progress = CompactionProgress::with_cancellation(token);
+        assert!(progress.is_cancelled());
+        assert!(db
+            .compact_range_with_progress(b"aaa", b"zzz", &progress)
+            .is_err());
+
+        // Flip a byte in the first block of a table.
+        let name = Path::new("db").join("000003.ldb");
+        let mut contents = vec![];
+        opt.env
+            .open_sequential_file(&name)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents[1] ^= 0xff;
+        opt.env
+            .open_writable_file(&name)
+            .unwrap()
+            .write_all(&contents)
+            .unwrap();
+        assert_eq!(
+            StatusCode::Corruption,
+            db.verify_integrity(None).err().unwrap().code
+        );
+    }
+
+    #[test]
+    fn test_db_impl_flush_memtable() {
+        let mut opt = options::for_test();
+        opt.reuse_logs = false;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"key", b"val").unwrap();
+        db.flush_memtable().unwrap();
+        assert_eq!(0, db.mem.len());
+        assert_eq!(
+            1,
+            db.current()
+                .borrow()
+                .files
+                .iter()
+                .map(|f| f.len())
+                .sum::<usize>()
+        );
+        // Flushing an empty memtable does nothing.
+        db.flush_memtable().unwrap();
+
+        db.pause_background_work();
+        assert_eq!(
+            StatusCode::WouldBlock,
+            db.flush_memtable().err().unwrap().code
+        );
+        db.continue_background_work().unwrap();
+        drop(db);
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(Some(b"val".to_vec()), db.get(b"key").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_hot_keys() {
+        let mut opt = options::for_test();
+        opt.hot_key_sample_rate = 1;
+        opt.prefix_extractor = Some(Rc::new(Box::new(crate::filter::FixedPrefix(1))));
+        let mut db = DB::open("db", opt).unwrap();
+        db.put(b"a1", b"").unwrap();
+        for _ in 0..10 {
+            db.get(b"a1").unwrap();
+            db.get(b"b2").unwrap();
+        }
+        db.get(b"a2").unwrap();
+        let snapshot = db.get_snapshot();
+        db.get_at(&snapshot, b"b2").unwrap();
+
+        let hot: Vec<_> = db
+            .hot_keys(2)
+            .into_iter()
+            .map(|h| (h.key, h.reads))
+            .collect();
+        assert_eq!(vec![(b"b2".to_vec(), 11), (b"a1".to_vec(), 10)], hot);
+        assert_eq!(b"a".to_vec(), db.hot_prefixes(1)[0].key);
+        db.reset_hot_keys();
+        assert!(db.hot_keys(2).is_empty());
+
+        let mut db = DB::open("db2", options::for_test()).unwrap();
+        db.get(b"a1").unwrap();
+        assert!(db.hot_keys(2).is_empty());
+    }
+
+    #[test]
+    fn test_db_impl_meta() {
+        let opt = options::for_test();
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"a", b"1").unwrap();
+            db.put_meta("schema_version", b"3").unwrap();
+            db.put_meta("migrated", b"").unwrap();
+            assert_eq!(Some(b"3".to_vec()), db.get_meta("schema_version"));
+            assert_eq!(None, db.get(b"schema_version").unwrap());
+            assert_eq!(None, db.get_meta("a"));
+            db.put_meta("schema_version", b"4").unwrap();
+            db.delete_meta("missing").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+        }
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"4".to_vec()), db.get_meta("schema_version"));
+        assert_eq!(Some(vec![]), db.get_meta("migrated"));
+        assert_eq!(vec!["migrated", "schema_version"], db.meta_keys());
+        let entries: Vec<_> = LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).collect();
+        assert_eq!(vec![(b"a".to_vec(), b"1".to_vec())], entries);
+
+        db.delete_meta("migrated").unwrap();
+        drop(db);
+        let db = DB::open("db", opt).unwrap();
+        assert_eq!(vec!["schema_version"], db.meta_keys());
+    }
+
+    #[test]
+    fn test_db_impl_named_snapshots() {
+        let mut opt = options::for_test();
+        opt.reuse_logs = false;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"key", b"old").unwrap();
+            db.create_named_snapshot("backup").unwrap();
+            assert_eq!(
+                StatusCode::AlreadyExists,
+                db.create_named_snapshot("backup").err().unwrap().code
+            );
+            db.put(b"key", b"new").unwrap();
+        }
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        let infos = db.named_snapshots();
+        assert_eq!(1, infos.len());
+        assert_eq!("backup", infos[0].name);
+        assert_eq!(1, db.snapshots().len());
+        db.compact_range(b"a", b"z").unwrap();
+        let snapshot = db.named_snapshot("backup").unwrap();
+        assert_eq!(Some(b"old".to_vec()), db.get_at(&snapshot, b"key").unwrap());
+        assert_eq!(Some(b"new".to_vec()), db.get(b"key").unwrap());
+
+        db.release_named_snapshot("backup").unwrap();
+        assert!(db.release_named_snapshot("backup").is_err());
+        drop(snapshot);
+        assert!(db.snapshots().is_empty());
+        drop(db);
+        let db = DB::open("db", opt).unwrap();
+        assert!(db.named_snapshots().is_empty());
+    }
+
+    #[test]
+    fn test_db_impl_auto_snapshots() {
+        let mut opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(None, db.auto_snapshot_if_due().unwrap());
+
+        opt.auto_snapshot_interval = Some(Duration::from_millis(2));
+        opt.auto_snapshot_retain = 2;
+        let mut db = DB::open("db2", opt).unwrap();
+        db.create_named_snapshot("manual").unwrap();
+        let first = db.auto_snapshot_if_due().unwrap().unwrap();
+        assert!(first.starts_with("auto-"));
+        assert_eq!(None, db.auto_snapshot_if_due().unwrap());
+        for _ in 0..2 {
+            std::thread::sleep(Duration::from_millis(3));
+            assert!(db.auto_snapshot_if_due().unwrap().is_some());
+        }
+        let names: Vec<_> = db.named_snapshots().into_iter().map(|s| s.name).collect();
+        assert_eq!(3, names.len());
+        assert_eq!("manual", names[0]);
+        assert!(!names.contains(&first));
+    }
+
+    #[test]
+    fn test_db_impl_compact_range_memtable() {
+        let (mut db, opt) = build_db();
+        let env = &opt.env;
+
+        db.put(b"xxx", b"123").unwrap();
+
+        eprintln!(
+            "children before: {:?}",
+            env.children(Path::new("db")).unwrap()
+        );
+        db.compact_range(b"aaa", b"dba").unwrap();
+        eprintln!(
+            "children after: {:?}",
+            env.children(Path::new("db")).unwrap()
+        );
+
+        assert_eq!(
+            320,
+            opt.env
+                .size_of(&Path::new("db").join("000007.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            200,
+            opt.env
+                .size_of(&Path::new("db").join("000008.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            200,
+            opt.env
+                .size_of(&Path::new("db").join("000009.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            182,
+            opt.env
+                .size_of(&Path::new("db").join("000014.ldb"))
+                .unwrap()
+        );
+        assert_eq!(
+            435,
+            opt.env
+                .size_of(&Path::new("db").join("000017.ldb"))
+                .unwrap()
+        );
+
+        assert!(!opt.env.exists(&Path::new("db").join("000001.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000002.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000003.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000004.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000005.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000006.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000015.ldb")).unwrap());
+        assert!(!opt.env.exists(&Path::new("db").join("000016.ldb")).unwrap());
+
+        assert_eq!(b"val1".to_vec(), db.get(b"aaa").unwrap().unwrap());
+        assert_eq!(b"val2".to_vec(), db.get(b"cab").unwrap().unwrap());
+        assert_eq!(b"val3".to_vec(), db.get(b"aba").unwrap().unwrap());
+        assert_eq!(b"val3".to_vec(), db.get(b"fab").unwrap().unwrap());
+        assert_eq!(b"123".to_vec(), db.get(b"xxx").unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_validate_options() {
+        struct ReverseCmp;
+        impl Cmp for ReverseCmp {
+            fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+                b.cmp(a)
+            }
+            fn find_shortest_sep(&self, from: &[u8], _: &[u8]) -> Vec<u8> {
+                from.to_vec()
+            }
+            fn find_short_succ(&self, key: &[u8]) -> Vec<u8> {
+                key.to_vec()
+            }
+            fn id(&self) -> &'static str {
+                "test.ReverseCmp"
+            }
+        }
+
+        let mut opt = options::for_test();
+        opt.max_open_files = 1;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"k", b"v").unwrap();
+            assert_eq!(20, db.opt.max_open_files);
+        }
+
+        let mut bad = opt.clone();
+        bad.block_size = 0;
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            DB::open("db", bad).err().unwrap().code
+        );
+
+        let mut other = opt.clone();
+        other.cmp = Rc::new(Box::new(ReverseCmp));
+        let e = DB::open("db", other).err().unwrap();
+        assert_eq!(StatusCode::InvalidArgument, e.code);
+        assert!(e.err.contains("test.ReverseCmp"));
+
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(Some(b"v".to_vec()), db.get(b"k").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_write_stall_listener() {
+        struct Listener(Rc<RefCell<Vec<WriteStall>>>);
+        impl WriteStallListener for Listener {
+            fn on_write_stall_change(&self, previous: WriteStall, current: WriteStall) {
+                let mut changes = self.0.borrow_mut();
+                assert_eq!(Some(&previous), changes.last());
+                changes.push(current);
+            }
+        }
+
+        let changes = Rc::new(RefCell::new(vec![WriteStall::Normal]));
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1000;
+        opt.defer_compactions = true;
+        opt.write_stall_listener = Some(Rc::new(Box::new(Listener(changes.clone()))));
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(WriteStall::Normal, db.write_stall());
+
+        // Every write fills the memtable, and the next one waits for its compaction.
+        db.put(b"key", &[0; 1000]).unwrap();
+        db.put(b"key", &[0; 1000]).unwrap();
+        assert_eq!(
+            vec![
+                WriteStall::Normal,
+                WriteStall::Delayed(WriteStallReason::MemtableFull),
+                WriteStall::Stopped(WriteStallReason::MemtableFull),
+                WriteStall::Delayed(WriteStallReason::MemtableFull),
+            ],
+            *changes.borrow()
+        );
+
+        // With deferred compactions, level 0 grows until writes have to compact it.
+        for _ in 0..20 {
+            db.put(b"key", &[0; 1000]).unwrap();
+        }
+        {
+            let changes = changes.borrow();
+            assert!(changes.contains(&WriteStall::Delayed(WriteStallReason::Level0Files)));
+            assert!(changes.contains(&WriteStall::Stopped(WriteStallReason::Level0Files)));
+        }
+        db.compact_range(b"a", b"z").unwrap();
+        assert_eq!(WriteStall::Normal, db.write_stall());
+        assert_eq!(Some(&WriteStall::Normal), changes.borrow().last());
+    }
+
+    #[test]
+    fn test_db_impl_event_listener() {
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Flush(FlushInfo),
+            Compaction(CompactionInfo),
+            Created(TableFileInfo, TableFileReason),
+            Deleted(TableFileInfo),
+        }
+        struct Listener(Rc<RefCell<Vec<Event>>>);
+        impl EventListener for Listener {
+            fn on_flush_completed(&self, info: &FlushInfo) {
+                self.0.borrow_mut().push(Event::Flush(info.clone()));
+            }
+            fn on_compaction_completed(&self, info: &CompactionInfo) {
+                self.0.borrow_mut().push(Event::Compaction(info.clone()));
+            }
+            fn on_table_file_created(&self, info: &TableFileInfo, reason: TableFileReason) {
+                self.0
+                    .borrow_mut()
+                    .push(Event::Created(info.clone(), reason));
+            }
+            fn on_table_file_deleted(&self, info: &TableFileInfo) {
+                self.0.borrow_mut().push(Event::Deleted(info.clone()));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut opt = options::for_test();
+        opt.event_listener = Some(Rc::new(Box::new(Listener(events.clone()))));
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(events.borrow().is_empty());
+
+        let mut flushed = vec![];
+        for i in 0..2u8 {
+            db.put(&[b'a' + i], b"1").unwrap();
+            db.put(&[b'x' + i], b"2").unwrap();
+            db.flush_memtable().unwrap();
+            let events: Vec<Event> = events.borrow_mut().drain(..).collect();
+            let Event::Flush(ref flush) = events[1] else {
+                panic!("unexpected events: {:?}", events);
+            };
+            assert_eq!(2, flush.num_entries);
+            let created = TableFileInfo {
+                file_num: flush.file_num,
+                size: flush.size,
+            };
+            assert_eq!(
+                flush.size,
+                db.opt
+                    .env
+                    .size_of(&table_file_name(&db.path, flush.file_num))
+                    .unwrap()
+            );
+            assert_eq!(
+                Event::Created(created.clone(), TableFileReason::Flush),
+                events[0]
+            );
+            flushed.push(created);
+        }
+
+        db.compact_all().unwrap();
+        let events: Vec<Event> = events.borrow_mut().drain(..).collect();
+        let compactions: Vec<&CompactionInfo> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Compaction(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        let compaction = compactions.iter().find(|c| !c.trivial_move).unwrap();
+        let mut inputs = compaction.input_files.clone();
+        inputs.sort();
+        assert_eq!(
+            flushed.iter().map(|f| f.file_num).collect::<Vec<_>>(),
+            inputs
+        );
+        assert_eq!(
+            flushed.iter().map(|f| f.size).sum::<usize>(),
+            compaction.bytes_read
+        );
+        assert_eq!(1, compaction.output_files.len());
+        let output = TableFileInfo {
+            file_num: compaction.output_files[0],
+            size: compaction.bytes_written,
+        };
+        assert!(events.contains(&Event::Created(output, TableFileReason::Compaction)));
+        for f in flushed {
+            assert!(events.contains(&Event::Deleted(f)));
+        }
+    }
+
+    #[test]
+    fn test_db_impl_try_write() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        let mut db = DB::open("db", opt).unwrap();
+
+        db.try_put(b"abc", b"def").unwrap();
+        while !db.write_would_stall() {
+            db.put(b"abd", b"0123456789").unwrap();
+        }
+        let e = db.try_put(b"abe", b"ghi").err().unwrap();
+        assert_eq!(StatusCode::WouldBlock, e.code);
+        assert!(db.get(b"abe").unwrap().is_none());
+
+        // A regular write makes room again.
+        db.put(b"abf", b"jkl").unwrap();
+        db.try_put(b"abe", b"ghi").unwrap();
+        assert_eq!(b"ghi".to_vec(), db.get(b"abe").unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_write_timeout() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 8192;
+        opt.target_file_size_base = 1024;
+        let mut db = DB::open("db", opt).unwrap();
+        let mut i = 0;
+        let mut fill = |db: &mut DB| {
+            while !db.write_would_stall() {
+                db.put(
+                    format!("key{:05}", i % 100).as_bytes(),
+                    format!("{:08}", i).as_bytes(),
+                )
+                .unwrap();
+                i += 1;
+            }
+        };
+
+        // With a short timeout, the level compaction that a full memtable leads to is left
+        // unfinished.
+        let mut rounds = 0;
+        while db.running_compaction.is_none() {
+            fill(&mut db);
+            db.put_timeout(b"abc", b"def", Duration::from_nanos(1))
+                .unwrap();
+            rounds += 1;
+            assert!(rounds < 100);
+        }
+        assert_eq!(Some(b"def".to_vec()), db.get(b"abc").unwrap());
+
+        // Given enough time, it is finished.
+        fill(&mut db);
+        db.put_timeout(b"abc", b"ghi", Duration::from_secs(60))
+            .unwrap();
+        assert!(db.running_compaction.is_none());
+        assert_eq!(Some(b"ghi".to_vec()), db.get(b"abc").unwrap());
+
+        fill(&mut db);
+        let e = db.put_timeout(b"abc", b"jkl", Duration::ZERO).unwrap_err();
+        assert_eq!(StatusCode::WouldBlock, e.code);
+    }
+
+    #[test]
+    fn test_db_impl_snapshots() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        assert!(db.snapshots().is_empty());
+        assert!(db.oldest_snapshot_age().is_none());
+
+        db.put(b"abc", b"def").unwrap();
+        let ss1 = db.get_snapshot();
+        db.put(b"abd", b"def").unwrap();
+        {
+            let ss2 = db.get_snapshot();
+            let snaps = db.snapshots();
+            assert_eq!(2, snaps.len());
+            assert_eq!(ss1.sequence(), snaps[0].sequence);
+            assert_eq!(ss2.sequence(), snaps[1].sequence);
+            assert!(snaps[0].age_micros >= snaps[1].age_micros);
+            assert!(db.oldest_snapshot_age().unwrap() >= snaps[0].age_micros);
+        }
+        assert_eq!(1, db.snapshots().len());
+        drop(ss1);
+        assert!(db.snapshots().is_empty());
+    }
+
+    #[test]
+    fn test_db_impl_statistics() {
+        let mut opt = options::for_test();
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"abc", b"def").unwrap();
+            assert!(db.statistics().is_none());
+        }
+
+        opt.statistics = true;
+        let mut db = DB::open("db2", opt).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.put(b"abd", b"def").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"abe", b"ghi");
+        db.write(wb, true).unwrap();
+        db.get(b"abc").unwrap();
+        {
+            let mut it = db.new_iter().unwrap();
+            it.seek(b"abd");
+            while it.advance() {}
+        }
+
+        let stats = db.take_statistics().unwrap();
+        assert_eq!(1, stats.latency(Operation::Get).count());
+        assert_eq!(2, stats.latency(Operation::Put).count());
+        assert_eq!(0, stats.latency(Operation::Write).count());
+        assert_eq!(1, stats.latency(Operation::SyncWrite).count());
+        assert_eq!(1, stats.latency(Operation::Seek).count());
+        assert_eq!(2, stats.latency(Operation::Next).count());
+
+        // take_statistics() resets the histograms.
+        let stats = db.statistics().unwrap();
+        assert_eq!(0, stats.latency(Operation::Get).count());
+        assert_eq!(0, stats.latency(Operation::Put).count());
+    }
+
+    #[allow(unused_variables)]
+    #[test]
+    fn test_db_impl_locking() {
+        let opt = options::for_test();
+        let db = DB::open("db", opt.clone()).unwrap();
+        let want_err = Status::new(
+            StatusCode::LockError,
+            "database lock is held by another instance",
+        );
+        assert_eq!(want_err, DB::open("db", opt.clone()).err().unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_open_read_only() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1 << 20;
+        let env = opt.env.clone();
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            DB::open_read_only("db", opt.clone()).err().unwrap().code
+        );
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"2").unwrap();
+        db.flush_memtable().unwrap();
+        db.put(b"b", b"3").unwrap();
+        db.delete(b"a").unwrap();
+        db.put_meta("schema", b"7").unwrap();
+        db.flush().unwrap();
+
+        let sizes = |env: &Box<dyn Env>| -> Vec<(String, usize)> {
+            let mut files = env.children(Path::new("db")).unwrap();
+            files.sort();
+            files
+                .into_iter()
+                .map(|f| {
+                    let size = env.size_of(&Path::new("db").join(&f)).unwrap();
+                    (f.to_string_lossy().into_owned(), size)
+                })
+                .collect()
+        };
+        let before = sizes(&env);
+
+        // The database can be opened while it's in use, and contains the logged writes.
+        let mut ro = DB::open_read_only("db", opt.clone()).unwrap();
+        assert_eq!(None, ro.get(b"a").unwrap());
+        assert_eq!(Some(b"3".to_vec()), ro.get(b"b").unwrap());
+        assert_eq!(Some(b"7".to_vec()), ro.get_meta("schema"));
+        let mut it = ro.new_iter().unwrap();
+        assert_eq!(
+            vec![(b"b".to_vec(), b"3".to_vec())],
+            LdbIteratorIter::wrap(&mut it).collect::<Vec<_>>()
+        );
+
+        for r in [
+            ro.put(b"c", b"4"),
+            ro.delete(b"b"),
+            ro.delete_range(b"a", b"c"),
+            ro.flush_memtable(),
+            ro.compact_range(b"a", b"c"),
+            ro.put_meta("schema", b"8"),
+        ] {
+            assert_eq!(StatusCode::NotSupported, r.unwrap_err().code);
+        }
+        assert!(ro.create_named_snapshot("s").is_err());
+        assert!(!ro.needs_compaction());
+        ro.close().unwrap();
+        drop(ro);
+        assert_eq!(before, sizes(&env));
+
+        // Later writes aren't visible to an open read-only instance.
+        let mut ro = DB::open_read_only("db", opt.clone()).unwrap();
+        db.put(b"c", b"5").unwrap();
+        db.flush().unwrap();
+        assert_eq!(None, ro.get(b"c").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_open_as_secondary() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.flush().unwrap();
+
+        let mut sec = DB::open_as_secondary("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"1".to_vec()), sec.get(b"a").unwrap());
+        assert_eq!(0, sec.try_catch_up_with_primary().unwrap());
+
+        db.put(b"b", b"2").unwrap();
+        db.flush_memtable().unwrap();
+        db.put(b"c", b"3").unwrap();
+        db.delete(b"a").unwrap();
+        db.put_meta("schema", b"7").unwrap();
+        db.create_named_snapshot("s").unwrap();
+        db.put(b"c", b"4").unwrap();
+        db.flush().unwrap();
+        let mut it = sec.new_iter().unwrap();
+        assert_eq!(None, sec.get(b"b").unwrap());
+
+        assert_eq!(4, sec.try_catch_up_with_primary().unwrap());
+        assert_eq!(None, sec.get(b"a").unwrap());
+        assert_eq!(Some(b"2".to_vec()), sec.get(b"b").unwrap());
+        assert_eq!(Some(b"4".to_vec()), sec.get(b"c").unwrap());
+        assert_eq!(Some(b"7".to_vec()), sec.get_meta("schema"));
+        let s = sec.named_snapshot("s").unwrap();
+        assert_eq!(Some(b"3".to_vec()), sec.get_at(&s, b"c").unwrap());
+        // The iterator created before still reads the previous state.
+        assert_eq!(1, LdbIteratorIter::wrap(&mut it).count());
+
+        // Compactions of the primary are picked up, too.
+        db.compact_all().unwrap();
+        assert_eq!(0, sec.try_catch_up_with_primary().unwrap());
+        let mut it = sec.new_iter().unwrap();
+        assert_eq!(
+            vec![
+                (b"b".to_vec(), b"2".to_vec()),
+                (b"c".to_vec(), b"4".to_vec())
+            ],
+            LdbIteratorIter::wrap(&mut it).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            StatusCode::NotSupported,
+            sec.put(b"d", b"5").unwrap_err().code
+        );
+        let mut ro = DB::open_read_only("db", opt.clone()).unwrap();
+        assert_eq!(
+            StatusCode::NotSupported,
+            ro.try_catch_up_with_primary().unwrap_err().code
+        );
+
+        // A failed catch-up leaves the state unchanged.
+        opt.env.delete(&Path::new("db").join("CURRENT")).unwrap();
+        assert!(sec.try_catch_up_with_primary().is_err());
+        assert_eq!(Some(b"4".to_vec()), sec.get(b"c").unwrap());
+        assert_eq!(Some(b"7".to_vec()), sec.get_meta("schema"));
+    }
+
+    #[test]
+    fn test_db_impl_catch_up_with_primary_incrementally() {
+        let mut opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.flush().unwrap();
+        let mut sec = DB::open_as_secondary("db", opt.clone()).unwrap();
+        let log = sec.replayed_logs.clone();
+        assert_eq!(1, log.len());
+        let off = sec.replayed_off;
+
+        // Only the new records of the log are replayed.
+        db.put(b"b", b"2").unwrap();
+        db.flush().unwrap();
+        assert_eq!(1, sec.try_catch_up_with_primary().unwrap());
+        assert_eq!(log, sec.replayed_logs);
+        assert!(sec.replayed_off > off);
+        assert_eq!(2, sec.mem.len());
+
+        // A torn record at the end of the log is read once it's complete.
+        let off = sec.replayed_off;
+        let logfile = log_file_name(&sec.path, log[0]);
+        db.put(b"c", b"3").unwrap();
+        db.flush().unwrap();
+        let mut content = vec![];
+        opt.env
+            .open_sequential_file(&logfile)
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        let torn = content.len() - 3;
+        let mut f = opt.env.open_writable_file(&logfile).unwrap();
+        f.write_all(&content[..torn]).unwrap();
+        assert_eq!(0, sec.try_catch_up_with_primary().unwrap());
+        assert_eq!(off, sec.replayed_off);
+        let mut f = opt.env.open_appendable_file(&logfile).unwrap();
+        f.write_all(&content[torn..]).unwrap();
+        assert_eq!(1, sec.try_catch_up_with_primary().unwrap());
+        assert_eq!(content.len(), sec.replayed_off);
+        assert_eq!(Some(b"3".to_vec()), sec.get(b"c").unwrap());
+
+        // Once the primary has compacted the log into a table, the memtable is rebuilt from the
+        // new log only.
+        db.flush_memtable().unwrap();
+        db.put(b"d", b"4").unwrap();
+        db.flush().unwrap();
+        assert_eq!(1, sec.try_catch_up_with_primary().unwrap());
+        assert_ne!(log, sec.replayed_logs);
+        assert_eq!(1, sec.mem.len());
+        db.compact_all().unwrap();
+        assert_eq!(0, sec.try_catch_up_with_primary().unwrap());
+        assert_eq!(0, sec.try_catch_up_with_primary().unwrap());
+        let mut it = sec.new_iter().unwrap();
+        assert_eq!(4, LdbIteratorIter::wrap(&mut it).count());
+
+        // If the primary writes a new manifest, it's read anew.
+        drop(db);
+        opt.reuse_manifest = false;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.delete(b"a").unwrap();
+        db.flush().unwrap();
+        assert_eq!(1, sec.try_catch_up_with_primary().unwrap());
+        assert_eq!(None, sec.get(b"a").unwrap());
+        assert_eq!(Some(b"4".to_vec()), sec.get(b"d").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_build_table() {
+        let mut opt = options::for_test();
+        opt.block_size = 128;
+        let mt = build_memtable();
+
+        let f = build_table("db", &opt, mt.iter(), 123).unwrap();
+        let path = &Path::new("db").join("000123.ldb");
+
+        assert_eq!(
+            LookupKey::new("aabc".as_bytes(), 6).internal_key(),
+            f.smallest.as_slice()
+        );
+        assert_eq!(
+            LookupKey::new("test123".as_bytes(), 7).internal_key(),
+            f.largest.as_slice()
+        );
+        assert_eq!(379, f.size);
+        assert_eq!(123, f.num);
+        assert!(opt.env.exists(path).unwrap());
+
+        {
+            // Read table back in.
+            let mut tc = TableCache::new("db", opt.clone(), 100);
+            let tbl = tc.get_table(123).unwrap();
+            assert_eq!(mt.len(), LdbIteratorIter::wrap(&mut tbl.iter()).count());
+        }
+
+        {
+            // Corrupt table; make sure it doesn't load fully.
+            let mut buf = vec![];
+            opt.env
+                .open_sequential_file(path)
+                .unwrap()
+                .read_to_end(&mut buf)
+                .unwrap();
+            buf[150] += 1;
+            opt.env
+                .open_writable_file(path)
+                .unwrap()
+                .write_all(&buf)
+                .unwrap();
+
+            let mut tc = TableCache::new("db", opt.clone(), 100);
+            let tbl = tc.get_table(123).unwrap();
+            // The last two entries are skipped due to the corruption above.
+            assert_eq!(
+                5,
+                LdbIteratorIter::wrap(&mut tbl.iter())
+                    .map(|v| eprintln!("{:?}", v))
+                    .count()
+            );
+        }
+    }
+
+    #[allow(unused_variables)]
+    #[test]
+    fn test_db_impl_build_db_sanity() {
+        let db = build_db().0;
+        let env = &db.opt.env;
+        let name = &db.name;
+
+        assert!(env.exists(Path::new(&log_file_name(name, 12))).unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_get_corrupted_block() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"key", b"value").unwrap();
+        db.make_room_for_write(true).unwrap();
+        drop(db);
 
-    use crate::error::Status;
-    use crate::key_types::LookupKey;
-    use crate::mem_env::MemEnv;
-    use crate::options;
-    use crate::test_util::LdbIteratorIter;
-    use crate::version::testutil::make_version;
+        let path = table_file_name("db", 5);
+        let mut buf = vec![];
+        opt.env
+            .open_sequential_file(&path)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        buf[5] += 1;
+        opt.env
+            .open_writable_file(&path)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(StatusCode::Corruption, db.get(b"key").unwrap_err().code);
+    }
 
     #[test]
-    fn test_db_impl_open_info_log() {
-        let e = MemEnv::new();
-        {
-            let l = Some(share(open_info_log(&e, "abc")));
-            assert!(e.exists(&Path::new("abc").join("LOG")).unwrap());
-            log!(l, "hello {}", "world");
-            assert_eq!(12, e.size_of(&Path::new("abc").join("LOG")).unwrap());
-        }
-        {
-            let l = Some(share(open_info_log(&e, "abc")));
-            assert!(e.exists(&Path::new("abc").join("LOG.old")).unwrap());
-            assert!(e.exists(&Path::new("abc").join("LOG")).unwrap());
-            assert_eq!(12, e.size_of(&Path::new("abc").join("LOG.old")).unwrap());
-            assert_eq!(0, e.size_of(&Path::new("abc").join("LOG")).unwrap());
-            log!(l, "something else");
-            log!(l, "and another {}", 1);
+    fn test_db_impl_multi_get() {
+        let mut opt = options::for_test();
+        // Without filters, tables are searched for keys they don't contain, too.
+        opt.whole_key_filtering = false;
+        let mut db = DB::open("db", opt).unwrap();
+        db.put(b"a5", b"old").unwrap();
+        db.put(b"d", b"old").unwrap();
+        db.compact_all().unwrap();
+        db.put(b"a0", b"1").unwrap();
+        db.delete(b"b").unwrap();
+        db.put(b"c", b"2").unwrap();
+        db.put(b"d", b"3").unwrap();
+        db.flush_memtable().unwrap();
+        let snapshot = db.get_snapshot();
+        db.put(b"c", b"4").unwrap();
+        db.delete_range(b"d", b"e").unwrap();
+        db.put(b"f", b"5").unwrap();
+
+        let keys: Vec<&[u8]> = vec![b"f", b"a5", b"b", b"c", b"x", b"a0", b"d", b"c", b""];
+        let want: Vec<Option<Vec<u8>>> = keys.iter().map(|k| db.get(k).unwrap()).collect();
+        assert_eq!(want, db.multi_get(&keys).unwrap());
+        // The deletion of b in the same table doesn't hide a5.
+        assert_eq!(Some(b"old".to_vec()), want[1]);
+        assert_eq!(
+            vec![Some(b"2".to_vec()), Some(b"3".to_vec()), None],
+            db.multi_get_at(&snapshot, &[b"c", b"d", b"f"]).unwrap()
+        );
+        assert!(db.multi_get::<&[u8]>(&[]).unwrap().is_empty());
+    }
 
-            let mut s = String::new();
-            let mut r = e
-                .open_sequential_file(&Path::new("abc").join("LOG"))
-                .unwrap();
-            r.read_to_string(&mut s).unwrap();
-            assert_eq!("something else\nand another 1\n", &s);
-        }
+    #[test]
+    fn test_db_impl_get_pinned() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        let big = vec![7; 5000];
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", &big).unwrap();
+        db.delete(b"c").unwrap();
+
+        let a = db.get_pinned(b"a").unwrap().unwrap();
+        let b = db.get_pinned(b"b").unwrap().unwrap();
+        assert_eq!(None, db.get_pinned(b"c").unwrap());
+        assert_eq!(None, db.get_pinned(b"d").unwrap());
+
+        // The values stay valid after the memtable has been compacted.
+        db.flush_memtable().unwrap();
+        assert_eq!(b"1", &*a);
+        assert_eq!(big, b.into_vec());
+
+        let b = db.get_pinned(b"b").unwrap().unwrap();
+        db.put(b"b", b"2").unwrap();
+        db.compact_all().unwrap();
+        assert_eq!(big, &*b);
+        assert_eq!(b"2", &*db.get_pinned(b"b").unwrap().unwrap());
+        assert_eq!(Some(b"1".to_vec()), db.get(b"a").unwrap());
     }
 
-    fn build_memtable() -> MemTable {
-        let mut mt = MemTable::new(options::for_test().cmp);
-        let mut i = 1;
-        for k in ["abc", "def", "ghi", "jkl", "mno", "aabc", "test123"].iter() {
-            mt.add(
-                i,
-                ValueType::TypeValue,
-                k.as_bytes(),
-                "looooongval".as_bytes(),
-            );
-            i += 1;
-        }
-        mt
+    #[test]
+    fn test_db_impl_get_with_read_options() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"key", b"value").unwrap();
+        db.make_room_for_write(true).unwrap();
+        let cached = || opt.block_cache.borrow().count();
+        let no_fill = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            Some(b"value".to_vec()),
+            db.get_with(b"key", &no_fill).unwrap()
+        );
+        assert_eq!(0, cached());
+        let ss = db.get_snapshot();
+        assert!(db.get_at_with(&ss, b"key", &no_fill).unwrap().is_some());
+        assert_eq!(0, cached());
+        assert!(db.new_iter_with(&no_fill).unwrap().next().is_some());
+        assert!(db.new_iter_at_with(ss, &no_fill).unwrap().next().is_some());
+        assert_eq!(0, cached());
+        assert!(db.get(b"key").unwrap().is_some());
+        assert_eq!(1, cached());
+
+        // Corrupt the table after its block was cached.
+        let path = table_file_name("db", 5);
+        let mut buf = vec![];
+        opt.env
+            .open_sequential_file(&path)
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        buf[5] += 1;
+        opt.env
+            .open_writable_file(&path)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+        assert!(db.get(b"key").unwrap().is_some());
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            StatusCode::Corruption,
+            db.get_with(b"key", &verify).unwrap_err().code
+        );
+        // Iterators skip corrupted blocks.
+        assert!(db.new_iter().unwrap().next().is_some());
+        assert!(db.new_iter_with(&verify).unwrap().next().is_none());
     }
 
     #[test]
-    fn test_db_impl_init() {
-        // A sanity check for recovery and basic persistence.
+    fn test_db_impl_destroy_db() {
         let opt = options::for_test();
         let env = opt.env.clone();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"key", b"value").unwrap();
+        db.flush_memtable().unwrap();
+        assert_eq!(
+            StatusCode::LockError,
+            destroy_db("db", &opt).unwrap_err().code
+        );
+        drop(db);
 
-        // Several test cases with different options follow. The printlns can eventually be
-        // removed.
+        let other = Path::new("db").join("notes.txt");
+        env.open_writable_file(&other)
+            .unwrap()
+            .write_all(b"keep me")
+            .unwrap();
+        destroy_db("db", &opt).unwrap();
+        assert_eq!(
+            vec![PathBuf::from("notes.txt")],
+            env.children(Path::new("db")).unwrap()
+        );
+        assert!(DB::open(
+            "db",
+            Options {
+                create_if_missing: false,
+                ..opt.clone()
+            }
+        )
+        .is_err());
 
-        {
-            let mut opt = opt.clone();
-            opt.reuse_manifest = false;
-            let _ = DB::open("otherdb", opt.clone()).unwrap();
+        env.delete(&other).unwrap();
+        destroy_db("db", &opt).unwrap();
+        assert!(env.children(Path::new("db")).unwrap_or_default().is_empty());
+        destroy_db("nonexistent", &opt).unwrap();
+    }
 
-            eprintln!(
-                "children after: {:?}",
-                env.children(Path::new("otherdb")).unwrap()
-            );
-            assert!(env.exists(&Path::new("otherdb").join("CURRENT")).unwrap());
-            // Database is initialized and initial manifest reused.
-            assert!(!env
-                .exists(&Path::new("otherdb").join("MANIFEST-000001"))
-                .unwrap());
-            assert!(env
-                .exists(&Path::new("otherdb").join("MANIFEST-000002"))
-                .unwrap());
-            assert!(env
-                .exists(&Path::new("otherdb").join("000003.log"))
-                .unwrap());
+    #[test]
+    fn test_db_impl_checkpoint() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"2").unwrap();
+        db.flush_memtable().unwrap();
+        db.delete_range(b"a", b"b").unwrap();
+        db.put(b"c", b"3").unwrap();
+        db.put_meta("schema", b"7").unwrap();
+        db.create_named_snapshot("s").unwrap();
+        db.put(b"b", b"4").unwrap();
+
+        db.checkpoint("cp").unwrap();
+        assert_eq!(
+            StatusCode::AlreadyExists,
+            db.checkpoint("cp").unwrap_err().code
+        );
+        // Later writes don't show up in the checkpoint.
+        db.put(b"d", b"5").unwrap();
+        db.compact_all().unwrap();
+        drop(db);
+        destroy_db("db", &opt).unwrap();
+
+        let mut cp = DB::open("cp", opt.clone()).unwrap();
+        assert_eq!(None, cp.get(b"a").unwrap());
+        assert_eq!(Some(b"4".to_vec()), cp.get(b"b").unwrap());
+        assert_eq!(Some(b"3".to_vec()), cp.get(b"c").unwrap());
+        assert_eq!(None, cp.get(b"d").unwrap());
+        assert_eq!(Some(b"7".to_vec()), cp.get_meta("schema"));
+        let s = cp.named_snapshot("s").unwrap();
+        assert_eq!(Some(b"2".to_vec()), cp.get_at(&s, b"b").unwrap());
+
+        // The checkpoint is a database of its own.
+        cp.put(b"e", b"6").unwrap();
+        cp.compact_all().unwrap();
+        drop(cp);
+        let mut cp = DB::open("cp", opt.clone()).unwrap();
+        assert_eq!(Some(b"6".to_vec()), cp.get(b"e").unwrap());
+        assert!(env.exists(&Path::new("cp").join("CURRENT")).unwrap());
+
+        // A read-only instance is checkpointed with the log files it replayed.
+        cp.put(b"f", b"7").unwrap();
+        drop(cp);
+        let mut ro = DB::open_read_only("cp", opt.clone()).unwrap();
+        ro.checkpoint("cp2").unwrap();
+        drop(ro);
+        let mut cp2 = DB::open("cp2", opt).unwrap();
+        assert_eq!(Some(b"6".to_vec()), cp2.get(b"e").unwrap());
+        assert_eq!(Some(b"7".to_vec()), cp2.get(b"f").unwrap());
+        assert_eq!(Some(b"7".to_vec()), cp2.get_meta("schema"));
+    }
+
+    #[test]
+    fn test_db_impl_key_may_exist() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        for k in [b"a", b"c", b"e"] {
+            db.put(k, b"value").unwrap();
         }
+        db.flush_memtable().unwrap();
+        db.put(b"b", b"value").unwrap();
+        db.delete(b"c").unwrap();
+
+        assert_eq!(KeyPresence::Present, db.key_may_exist(b"b").unwrap());
+        assert_eq!(KeyPresence::Absent, db.key_may_exist(b"c").unwrap());
+        assert_eq!(KeyPresence::MayExist, db.key_may_exist(b"a").unwrap());
+        assert_eq!(KeyPresence::MayExist, db.key_may_exist(b"e").unwrap());
+        // Rejected by the filter, or outside of the table's range.
+        assert_eq!(KeyPresence::Absent, db.key_may_exist(b"d").unwrap());
+        assert_eq!(KeyPresence::Absent, db.key_may_exist(b"f").unwrap());
+        assert_eq!(KeyPresence::Absent, db.key_may_exist(b"0").unwrap());
+    }
 
-        {
-            let mut opt = opt.clone();
-            opt.reuse_manifest = true;
-            let mut db = DB::open("db", opt.clone()).unwrap();
+    #[test]
+    fn test_db_impl_get_from_table_with_snapshot() {
+        let mut db = build_db().0;
 
-            eprintln!(
-                "children after: {:?}",
-                env.children(&Path::new("db").join("")).unwrap()
-            );
-            assert!(env.exists(&Path::new("db").join("CURRENT")).unwrap());
-            // Database is initialized and initial manifest reused.
-            assert!(env
-                .exists(&Path::new("db").join("MANIFEST-000001"))
-                .unwrap());
-            assert!(env.exists(&Path::new("db").join("LOCK")).unwrap());
-            assert!(env.exists(&Path::new("db").join("000003.log")).unwrap());
+        assert_eq!(30, db.vset.borrow().last_seq);
 
-            db.put("abc".as_bytes(), "def".as_bytes()).unwrap();
-            db.put("abd".as_bytes(), "def".as_bytes()).unwrap();
-        }
+        // seq = 31
+        db.put("xyy".as_bytes(), "123".as_bytes()).unwrap();
+        let old_ss = db.get_snapshot();
+        // seq = 32
+        db.put("xyz".as_bytes(), "123".as_bytes()).unwrap();
+        db.flush().unwrap();
+        assert!(db.get_at(&old_ss, "xyy".as_bytes()).unwrap().is_some());
+        assert!(db.get_at(&old_ss, "xyz".as_bytes()).unwrap().is_none());
 
-        {
-            eprintln!(
-                "children before: {:?}",
-                env.children(&Path::new("db").join("")).unwrap()
-            );
-            let mut opt = opt.clone();
-            opt.reuse_manifest = false;
-            opt.reuse_logs = false;
-            let mut db = DB::open("db", opt.clone()).unwrap();
+        // memtable get
+        assert_eq!(
+            "123".as_bytes(),
+            db.get("xyz".as_bytes()).unwrap().unwrap().as_slice()
+        );
+        assert!(db
+            .get_internal(31, "xyy".as_bytes(), &ReadOptions::default())
+            .unwrap()
+            .is_some());
+        assert!(db
+            .get_internal(32, "xyy".as_bytes(), &ReadOptions::default())
+            .unwrap()
+            .is_some());
 
-            eprintln!(
-                "children after: {:?}",
-                env.children(&Path::new("db").join("")).unwrap()
-            );
-            // Obsolete manifest is deleted.
-            assert!(!env
-                .exists(&Path::new("db").join("MANIFEST-000001"))
-                .unwrap());
-            // New manifest is created.
-            assert!(env
-                .exists(&Path::new("db").join("MANIFEST-000002"))
-                .unwrap());
-            // Obsolete log file is deleted.
-            assert!(!env.exists(&Path::new("db").join("000003.log")).unwrap());
-            // New L0 table has been added.
-            assert!(env.exists(&Path::new("db").join("000003.ldb")).unwrap());
-            assert!(env.exists(&Path::new("db").join("000004.log")).unwrap());
-            // Check that entry exists and is correct. Phew, long call chain!
-            let current = db.current();
-            log!(opt.log, "files: {:?}", current.borrow().files);
+        assert!(db
+            .get_internal(31, "xyz".as_bytes(), &ReadOptions::default())
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get_internal(32, "xyz".as_bytes(), &ReadOptions::default())
+            .unwrap()
+            .is_some());
+
+        // table get
+        assert_eq!(
+            "val2".as_bytes(),
+            db.get("eab".as_bytes()).unwrap().unwrap().as_slice()
+        );
+        assert!(db
+            .get_internal(3, "eab".as_bytes(), &ReadOptions::default())
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get_internal(32, "eab".as_bytes(), &ReadOptions::default())
+            .unwrap()
+            .is_some());
+
+        {
+            let ss = db.get_snapshot();
             assert_eq!(
-                "def".as_bytes(),
-                current
-                    .borrow_mut()
-                    .get(LookupKey::new("abc".as_bytes(), 1).internal_key())
+                "val2".as_bytes(),
+                db.get_at(&ss, "eab".as_bytes())
                     .unwrap()
                     .unwrap()
-                    .0
                     .as_slice()
             );
-            db.put("abe".as_bytes(), "def".as_bytes()).unwrap();
         }
 
-        {
-            eprintln!(
-                "children before: {:?}",
-                env.children(Path::new("db")).unwrap()
-            );
-            // reuse_manifest above causes the old manifest to be deleted as obsolete, but no new
-            // manifest is written. CURRENT becomes stale.
-            let mut opt = opt.clone();
-            opt.reuse_logs = true;
-            let db = DB::open("db", opt).unwrap();
+        // from table.
+        assert_eq!(
+            "val2".as_bytes(),
+            db.get("cab".as_bytes()).unwrap().unwrap().as_slice()
+        );
+    }
+
+    #[test]
+    fn test_db_impl_delete() {
+        let mut db = build_db().0;
+
+        db.put(b"xyy", b"123").unwrap();
+        db.put(b"xyz", b"123").unwrap();
+
+        assert!(db.get(b"xyy").unwrap().is_some());
+        assert!(db.get(b"gaa").unwrap().is_some());
+
+        // Delete one memtable entry and one table entry.
+        db.delete(b"xyy").unwrap();
+        db.delete(b"gaa").unwrap();
+
+        assert!(db.get(b"xyy").unwrap().is_none());
+        assert!(db.get(b"gaa").unwrap().is_none());
+        assert!(db.get(b"xyz").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_db_impl_memtable_drops_tombstones() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt).unwrap();
+        let table_entries = |db: &DB| {
+            let mut entries = vec![];
+            for mut it in db.current().borrow().new_iters().unwrap() {
+                let (mut k, mut v) = (vec![], vec![]);
+                while it.advance() {
+                    it.current(&mut k, &mut v);
+                    let (typ, _, ukey) = parse_internal_key(&k);
+                    entries.push((ukey.to_vec(), typ));
+                }
+            }
+            entries
+        };
+
+        db.put(b"aaa", b"val").unwrap();
+        db.make_room_for_write(true).unwrap();
+
+        // bbb only exists in the memtable, aaa also in a table.
+        db.put(b"bbb", b"val").unwrap();
+        db.delete(b"bbb").unwrap();
+        db.delete(b"aaa").unwrap();
+        db.put(b"ccc", b"val").unwrap();
+        db.make_room_for_write(true).unwrap();
+        let entries = table_entries(&db);
+        assert_eq!(3, entries.len());
+        assert!(entries.contains(&(b"aaa".to_vec(), ValueType::TypeDeletion)));
+        assert!(entries.contains(&(b"aaa".to_vec(), ValueType::TypeValue)));
+        assert!(entries.contains(&(b"ccc".to_vec(), ValueType::TypeValue)));
+
+        // The deletion marker is kept while a snapshot can observe the deleted value.
+        db.put(b"ddd", b"val").unwrap();
+        let snap = db.get_snapshot();
+        db.delete(b"ddd").unwrap();
+        db.make_room_for_write(true).unwrap();
+        let entries = table_entries(&db);
+        assert!(entries.contains(&(b"ddd".to_vec(), ValueType::TypeDeletion)));
+        assert!(entries.contains(&(b"ddd".to_vec(), ValueType::TypeValue)));
+        assert_eq!(b"val".to_vec(), db.get_at(&snap, b"ddd").unwrap().unwrap());
+        assert!(db.get(b"bbb").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_db_impl_deletion_compaction() {
+        let mut opt = options::for_test();
+        opt.statistics = true;
+        opt.deletion_compaction_ratio = 0.5;
+        let mut db = DB::open("db", opt).unwrap();
 
-            eprintln!(
-                "children after: {:?}",
-                env.children(Path::new("db")).unwrap()
-            );
-            assert!(!env
-                .exists(&Path::new("db").join("MANIFEST-000001"))
-                .unwrap());
-            assert!(env
-                .exists(&Path::new("db").join("MANIFEST-000002"))
-                .unwrap());
-            assert!(!env
-                .exists(&Path::new("db").join("MANIFEST-000005"))
-                .unwrap());
-            assert!(env.exists(&Path::new("db").join("000004.log")).unwrap());
-            // 000004 should be reused, no new log file should be created.
-            assert!(!env.exists(&Path::new("db").join("000006.log")).unwrap());
-            // Log is reused, so memtable should contain last written entry from above.
-            assert_eq!(1, db.mem.len());
-            assert_eq!(
-                "def".as_bytes(),
-                db.mem
-                    .get(&LookupKey::new("abe".as_bytes(), 3))
-                    .0
-                    .unwrap()
-                    .as_slice()
-            );
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), b"val").unwrap();
+        }
+        db.make_room_for_write(true).unwrap();
+        for i in 0..80 {
+            db.delete(format!("key{:03}", i).as_bytes()).unwrap();
         }
+        // The file containing the deletions is compacted right away, dropping the deletions and
+        // the deleted entries.
+        db.make_room_for_write(true).unwrap();
+
+        assert_eq!(1, db.statistics().unwrap().deletion_compactions());
+        let v = db.current();
+        let v = v.borrow();
+        let files: Vec<_> = v.files.iter().flatten().collect();
+        assert_eq!(1, files.len());
+        assert_eq!(20, files[0].borrow().num_entries);
+        assert_eq!(0, files[0].borrow().num_deletions);
+        assert!(v.deletion_file_to_compact.is_none());
+        drop(v);
+        assert!(db.get(b"key000").unwrap().is_none());
+        assert_eq!(b"val".to_vec(), db.get(b"key099").unwrap().unwrap());
     }
 
     #[test]
-    fn test_db_impl_compact_range() {
-        let (mut db, opt) = build_db();
-        let env = &opt.env;
+    fn test_db_impl_space_amp_compaction() {
+        let mut opt = options::for_test();
+        opt.statistics = true;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(1.0, db.space_amplification());
+        for round in 0..3u8 {
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), &[round; 100])
+                    .unwrap();
+            }
+            db.make_room_for_write(true).unwrap();
+        }
+        let amp = db.space_amplification();
+        // Each round's file has been stored in another level.
+        assert!(amp > 2.5, "{}", amp);
+        drop(db);
+
+        opt.max_space_amplification = 1.5;
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.needs_compaction());
+        while db.needs_compaction() {
+            db.compact_pending().unwrap();
+        }
+        assert!(db.space_amplification() <= 1.5);
+        assert!(!db.needs_compaction());
+        assert!(db.statistics().unwrap().space_amp_compactions() > 0);
+        assert_eq!(Some(vec![2; 100]), db.get(b"key050").unwrap());
+    }
 
-        eprintln!(
-            "children before: {:?}",
-            env.children(&Path::new("db").join("")).unwrap()
+    #[test]
+    fn test_db_impl_get_property() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b\x01", b"2").unwrap();
+        db.make_room_for_write(true).unwrap();
+        let level = db
+            .current()
+            .borrow()
+            .files
+            .iter()
+            .position(|fs| !fs.is_empty());
+        let level = level.unwrap();
+
+        let files =
+            |db: &DB, l: usize| db.get_property(&format!("leveldb.num-files-at-level{}", l));
+        assert_eq!(Some("1".to_string()), files(&db, level));
+        assert_eq!(Some("0".to_string()), files(&db, level + 1));
+        assert_eq!(None, files(&db, NUM_LEVELS));
+        assert_eq!(None, db.get_property("leveldb.num-files-at-levelx"));
+        assert_eq!(None, db.get_property("leveldb.unknown"));
+        assert_eq!(None, db.get_property("stats"));
+
+        let stats = db.get_property("leveldb.stats").unwrap();
+        assert!(stats.contains("Level  Files Size(MB)"), "{}", stats);
+        assert!(stats.contains(&format!("{:3} {:8}", level, 1)), "{}", stats);
+
+        let sstables = db.get_property("leveldb.sstables").unwrap();
+        assert!(
+            sstables.contains(&format!("--- level {} ---\n 5:", level)),
+            "{}",
+            sstables
         );
-        db.compact_range(b"aaa", b"dba").unwrap();
-        eprintln!(
-            "children after: {:?}",
-            env.children(&Path::new("db").join("")).unwrap()
+        assert!(
+            sstables.contains("['a' @ 1 : 1 .. 'b\\x01' @ 2 : 1]"),
+            "{}",
+            sstables
         );
 
+        let mem: usize = db
+            .get_property("leveldb.approximate-memory-usage")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(mem > 0);
+    }
+
+    #[test]
+    fn test_db_impl_approximate_sizes() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        for i in 0..1000 {
+            db.put(format!("key{:04}", i).as_bytes(), &[i as u8; 100])
+                .unwrap();
+        }
+        // The memtable isn't counted.
+        assert_eq!(vec![0], db.approximate_sizes(&[(b"key", b"kez")]));
+        db.make_room_for_write(true).unwrap();
+
+        let sizes = db.approximate_sizes(&[
+            ("key", "kez"),
+            ("key0000", "key0500"),
+            ("key0500", "key1000"),
+            ("a", "b"),
+            ("z", "a"),
+        ]);
+        assert!(sizes[0] > 100_000, "{:?}", sizes);
+        assert!(sizes[1] > sizes[0] / 3 && sizes[1] < sizes[0] * 2 / 3);
+        assert!(sizes[1] + sizes[2] <= sizes[0]);
+        assert_eq!(0, sizes[3]);
+        assert_eq!(0, sizes[4]);
+    }
+
+    #[test]
+    fn test_db_impl_delete_files_in_range() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        for prefix in &["a", "b", "c"] {
+            for i in 0..10 {
+                db.put(format!("{}{}", prefix, i).as_bytes(), b"val")
+                    .unwrap();
+            }
+            db.make_room_for_write(true).unwrap();
+        }
+        db.put(b"a5", b"new").unwrap();
+        let nfiles = |db: &DB| db.current().borrow().files.iter().flatten().count();
+        assert_eq!(3, nfiles(&db));
+        let files_before = opt.env.children(Path::new("db")).unwrap();
+
+        // The "b" file overlaps the range only partially.
+        assert_eq!(1, db.delete_files_in_range(b"a", b"b5").unwrap());
+        assert_eq!(2, nfiles(&db));
+        let files_after = opt.env.children(Path::new("db")).unwrap();
+        assert!(files_after.len() < files_before.len());
+
+        assert!(db.get(b"a0").unwrap().is_none());
+        // Memtable entries are kept.
+        assert_eq!(b"new".to_vec(), db.get(b"a5").unwrap().unwrap());
+        assert_eq!(b"val".to_vec(), db.get(b"b0").unwrap().unwrap());
+        assert_eq!(b"val".to_vec(), db.get(b"c9").unwrap().unwrap());
+        assert_eq!(0, db.delete_files_in_range(b"a", b"b5").unwrap());
+
+        // The removal survives reopening.
+        drop(db);
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.get(b"a0").unwrap().is_none());
+        assert_eq!(b"val".to_vec(), db.get(b"b0").unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_partition_by_prefix() {
+        let fill = |partition: bool| {
+            let mut opt = options::for_test();
+            opt.prefix_extractor = Some(Rc::new(Box::new(crate::filter::FixedPrefix(1))));
+            opt.partition_by_prefix = partition;
+            let mut db = DB::open("db", opt).unwrap();
+            for round in 0..2 {
+                for prefix in &["a", "b", "c"] {
+                    for i in 0..10 {
+                        db.put(format!("{}{}", prefix, i).as_bytes(), &[round])
+                            .unwrap();
+                    }
+                }
+                db.make_room_for_write(true).unwrap();
+            }
+            db.put(b"z", b"").unwrap();
+            db.compact_range(b"a", b"z").unwrap();
+            db
+        };
+
+        let db = fill(false);
+        assert_eq!(1, db.current().borrow().files.iter().flatten().count());
+
+        let mut db = fill(true);
+        let files: Vec<_> = db
+            .current()
+            .borrow()
+            .files
+            .iter()
+            .flatten()
+            .map(|f| {
+                let f = f.borrow();
+                (
+                    parse_internal_key(&f.smallest).2.to_vec(),
+                    parse_internal_key(&f.largest).2.to_vec(),
+                )
+            })
+            .collect();
         assert_eq!(
-            250,
-            opt.env
-                .size_of(&Path::new("db").join("000007.ldb"))
-                .unwrap()
-        );
-        assert_eq!(
-            200,
-            opt.env
-                .size_of(&Path::new("db").join("000008.ldb"))
-                .unwrap()
+            vec![
+                (b"a0".to_vec(), b"a9".to_vec()),
+                (b"b0".to_vec(), b"b9".to_vec()),
+                (b"c0".to_vec(), b"c9".to_vec()),
+                (b"z".to_vec(), b"z".to_vec()),
+            ],
+            files
         );
+        assert_eq!(Some(vec![1]), db.get(b"b5").unwrap());
+        assert_eq!(1, db.delete_files_in_range(b"b", b"b9").unwrap());
+        assert_eq!(None, db.get(b"b5").unwrap());
+        assert_eq!(Some(vec![1]), db.get(b"c5").unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_put_many() {
+        let mut opt = options::for_test();
+        opt.statistics = true;
+        let mut db = DB::open("db", opt).unwrap();
+        let val = vec![0xab; 1000];
+        let n = 3 * PUT_MANY_BATCH_BYTES / 1000;
+        db.put_many((0..n).map(|i| (format!("key{:06}", i).into_bytes(), val.clone())))
+            .unwrap();
+        db.put_many(vec![]).unwrap();
+
+        assert_eq!(n as u64, db.vset.borrow().last_seq);
         assert_eq!(
-            200,
-            opt.env
-                .size_of(&Path::new("db").join("000009.ldb"))
-                .unwrap()
+            4,
+            db.statistics().unwrap().latency(Operation::Write).count()
         );
+        assert_eq!(Some(val.clone()), db.get(b"key000000").unwrap());
         assert_eq!(
-            435,
-            opt.env
-                .size_of(&Path::new("db").join("000015.ldb"))
-                .unwrap()
+            Some(val),
+            db.get(format!("key{:06}", n - 1).as_bytes()).unwrap()
         );
-
-        assert!(!opt.env.exists(&Path::new("db").join("000001.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000002.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000004.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000005.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000006.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000013.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000014.ldb")).unwrap());
-
-        assert_eq!(b"val1".to_vec(), db.get(b"aaa").unwrap());
-        assert_eq!(b"val2".to_vec(), db.get(b"cab").unwrap());
-        assert_eq!(b"val3".to_vec(), db.get(b"aba").unwrap());
-        assert_eq!(b"val3".to_vec(), db.get(b"fab").unwrap());
     }
 
     #[test]
-    fn test_db_impl_compact_range_memtable() {
-        let (mut db, opt) = build_db();
-        let env = &opt.env;
-
-        db.put(b"xxx", b"123").unwrap();
-
-        eprintln!(
-            "children before: {:?}",
-            env.children(Path::new("db")).unwrap()
-        );
-        db.compact_range(b"aaa", b"dba").unwrap();
-        eprintln!(
-            "children after: {:?}",
-            env.children(Path::new("db")).unwrap()
+    fn test_db_impl_bulk_load() {
+        let mut opt = options::for_test();
+        opt.target_file_size_base = 8 << 10;
+        opt.target_file_size_multiplier = 1;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"a", b"before").unwrap();
+        let ss = db.get_snapshot();
+
+        let entries = |from, to| {
+            (from..to).map(|i: usize| {
+                (
+                    format!("key{:05}", i).into_bytes(),
+                    i.to_le_bytes().to_vec(),
+                )
+            })
+        };
+        let files = db.bulk_load(entries(0, 2000)).unwrap();
+        assert!(files > 1);
+        assert_eq!(files, db.current().borrow().files[NUM_LEVELS - 1].len());
+        assert_eq!(0, db.bulk_load(vec![]).unwrap());
+        assert_eq!(Some(b"before".to_vec()), db.get(b"a").unwrap());
+        assert_eq!(None, db.get_at(&ss, b"key00000").unwrap());
+        assert_eq!(
+            Some(1999usize.to_le_bytes().to_vec()),
+            db.get(b"key01999").unwrap()
         );
 
+        // Overlapping and unsorted entries are rejected without leaving files behind.
+        let children = db.opt.env.children(Path::new("db")).unwrap().len();
         assert_eq!(
-            250,
-            opt.env
-                .size_of(&Path::new("db").join("000007.ldb"))
-                .unwrap()
+            StatusCode::InvalidArgument,
+            db.bulk_load(entries(1500, 2500)).unwrap_err().code
         );
+        db.put(b"x1", b"").unwrap();
         assert_eq!(
-            200,
-            opt.env
-                .size_of(&Path::new("db").join("000008.ldb"))
-                .unwrap()
+            StatusCode::InvalidArgument,
+            db.bulk_load(vec![(b"x0".to_vec(), vec![]), (b"x2".to_vec(), vec![])])
+                .unwrap_err()
+                .code
         );
         assert_eq!(
-            200,
-            opt.env
-                .size_of(&Path::new("db").join("000009.ldb"))
-                .unwrap()
+            StatusCode::InvalidArgument,
+            db.bulk_load(entries(3000, 4000).rev()).unwrap_err().code
         );
+        // The memtable holding x1 has been flushed to a new table.
         assert_eq!(
-            182,
-            opt.env
-                .size_of(&Path::new("db").join("000014.ldb"))
-                .unwrap()
+            children + 1,
+            db.opt.env.children(Path::new("db")).unwrap().len()
         );
+
+        db.bulk_load(entries(5000, 5001)).unwrap();
+        drop(db);
+        let mut db = DB::open("db", opt).unwrap();
         assert_eq!(
-            435,
-            opt.env
-                .size_of(&Path::new("db").join("000017.ldb"))
-                .unwrap()
+            Some(0usize.to_le_bytes().to_vec()),
+            db.get(b"key00000").unwrap()
         );
+        assert!(db.get(b"key05000").unwrap().is_some());
+        let mut iter = db.new_iter().unwrap();
+        assert_eq!(2003, LdbIteratorIter::wrap(&mut iter).count());
+    }
 
-        assert!(!opt.env.exists(&Path::new("db").join("000001.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000002.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000003.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000004.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000005.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000006.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000015.ldb")).unwrap());
-        assert!(!opt.env.exists(&Path::new("db").join("000016.ldb")).unwrap());
+    #[test]
+    fn test_db_impl_ingest_external_file() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        let write = |name: &str, entries: &[(&[u8], Option<&[u8]>)]| {
+            let mut w = TableFileWriter::create(opt.clone(), name).unwrap();
+            for (k, v) in entries {
+                match v {
+                    Some(v) => w.put(k, v).unwrap(),
+                    None => w.delete(k).unwrap(),
+                }
+            }
+            w.finish().unwrap();
+        };
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"c", b"3").unwrap();
+        db.flush_memtable().unwrap();
+        db.put(b"d", b"4").unwrap();
+        let ss = db.get_snapshot();
+
+        // No overlap: added as it is, in the bottom level.
+        write("x.ldb", &[(b"x1", Some(b"10")), (b"x2", Some(b"11"))]);
+        // Overlaps the table with a and c: rewritten into the level above it.
+        write("b.ldb", &[(b"b", Some(b"2")), (b"c", None)]);
+        let iopt = IngestOptions { move_files: true };
+        db.ingest_external_file(&["x.ldb", "b.ldb"], &iopt).unwrap();
+        assert!(!env.exists(Path::new("x.ldb")).unwrap());
+        assert_eq!(1, db.current().borrow().files[NUM_LEVELS - 1].len());
+
+        assert_eq!(Some(b"2".to_vec()), db.get(b"b").unwrap());
+        assert_eq!(None, db.get(b"c").unwrap());
+        assert_eq!(Some(b"4".to_vec()), db.get(b"d").unwrap());
+        assert_eq!(Some(b"11".to_vec()), db.get(b"x2").unwrap());
+        assert_eq!(Some(b"3".to_vec()), db.get_at(&ss, b"c").unwrap());
+        assert_eq!(None, db.get_at(&ss, b"b").unwrap());
+
+        // Overlapping and empty files are rejected.
+        write("e1.ldb", &[(b"e", Some(b"5")), (b"g", Some(b"7"))]);
+        write("e2.ldb", &[(b"f", Some(b"6"))]);
+        write("empty.ldb", &[]);
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.ingest_external_file(&["e1.ldb", "e2.ldb"], &IngestOptions::default())
+                .unwrap_err()
+                .code
+        );
+        assert!(db
+            .ingest_external_file(&["empty.ldb"], &IngestOptions::default())
+            .is_err());
+        assert_eq!(None, db.get(b"f").unwrap());
+        db.ingest_external_file(&["e2.ldb"], &IngestOptions::default())
+            .unwrap();
+        assert!(env.exists(Path::new("e2.ldb")).unwrap());
 
-        assert_eq!(b"val1".to_vec(), db.get(b"aaa").unwrap());
-        assert_eq!(b"val2".to_vec(), db.get(b"cab").unwrap());
-        assert_eq!(b"val3".to_vec(), db.get(b"aba").unwrap());
-        assert_eq!(b"val3".to_vec(), db.get(b"fab").unwrap());
-        assert_eq!(b"123".to_vec(), db.get(b"xxx").unwrap());
+        let mut w = TableFileWriter::create(opt.clone(), "bad.ldb").unwrap();
+        w.put(b"b", b"").unwrap();
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            w.put(b"a", b"").unwrap_err().code
+        );
+        assert_eq!(1, w.entries());
+
+        drop(db);
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(Some(b"2".to_vec()), db.get(b"b").unwrap());
+        assert_eq!(Some(b"6".to_vec()), db.get(b"f").unwrap());
+        db.compact_all().unwrap();
+        let mut iter = db.new_iter().unwrap();
+        assert_eq!(6, LdbIteratorIter::wrap(&mut iter).count());
     }
 
-    #[allow(unused_variables)]
     #[test]
-    fn test_db_impl_locking() {
+    fn test_db_impl_delete_prefix() {
         let opt = options::for_test();
-        let db = DB::open("db", opt.clone()).unwrap();
-        let want_err = Status::new(
-            StatusCode::LockError,
-            "database lock is held by another instance",
-        );
-        assert_eq!(want_err, DB::open("db", opt.clone()).err().unwrap());
+        let mut db = DB::open("db", opt).unwrap();
+        for i in 0..10 {
+            db.put(format!("a{}", i).as_bytes(), b"val").unwrap();
+        }
+        db.make_room_for_write(true).unwrap();
+        // b* is spread over a file with other keys, and the memtable.
+        for prefix in &["b", "c"] {
+            for i in 0..10 {
+                db.put(format!("{}{}", prefix, i).as_bytes(), b"val")
+                    .unwrap();
+            }
+        }
+        db.make_room_for_write(true).unwrap();
+        for i in 0..(DELETE_PREFIX_BATCH_SIZE + 10) {
+            db.put(format!("b{}", i).as_bytes(), b"val").unwrap();
+        }
+
+        db.delete_prefix(b"a").unwrap();
+        // The remaining keys are deleted by a single range tombstone.
+        let seq = db.vset.borrow().last_seq;
+        db.delete_prefix(b"b").unwrap();
+        assert_eq!(seq + 1, db.vset.borrow().last_seq);
+        assert_eq!(1, db.current().borrow().files.iter().flatten().count());
+        let mut iter = db.new_iter().unwrap();
+        let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(10, keys.len());
+        assert!(keys.iter().all(|k| k.starts_with(b"c")));
+
+        // Without range deletions, a deletion marker is written per key.
+        let mut opt = options::for_test();
+        opt.leveldb_compatible = true;
+        let mut db = DB::open("db2", opt).unwrap();
+        for k in [&b"a"[..], b"b", b"b\xff", b"b\xff\x00", b"c"] {
+            db.put(k, b"val").unwrap();
+        }
+        db.delete_prefix(b"b").unwrap();
+        let mut iter = db.new_iter().unwrap();
+        let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(vec![b"a".to_vec(), b"c".to_vec()], keys);
     }
 
     #[test]
-    fn test_db_impl_build_table() {
+    fn test_db_impl_prefix_successor() {
+        assert_eq!(Some(b"b".to_vec()), prefix_successor(b"a"));
+        assert_eq!(Some(b"b".to_vec()), prefix_successor(b"a\xff\xff"));
+        assert_eq!(Some(b"a\x01".to_vec()), prefix_successor(b"a\x00"));
+        assert_eq!(None, prefix_successor(b"\xff"));
+        assert_eq!(None, prefix_successor(b""));
+    }
+
+    #[test]
+    fn test_db_impl_new_prefix_iter() {
         let mut opt = options::for_test();
-        opt.block_size = 128;
-        let mt = build_memtable();
+        opt.prefix_extractor = Some(Rc::new(Box::new(crate::filter::FixedPrefix(2))));
+        let mut db = DB::open("db", opt).unwrap();
+        // Two tables with overlapping key ranges, but only the second contains "ab".
+        for prefixes in &[["aa", "ac"], ["ab", "ad"]] {
+            for prefix in prefixes {
+                for i in 0..10 {
+                    db.put(format!("{}{}", prefix, i).as_bytes(), b"val")
+                        .unwrap();
+                }
+            }
+            db.make_room_for_write(true).unwrap();
+        }
+        db.put(b"ab5x", b"val").unwrap();
+        db.delete(b"ab9").unwrap();
+        let ntables = |db: &DB, prefix: &[u8]| {
+            let v = db.current();
+            let n = v.borrow().new_prefix_iters(prefix).unwrap().len();
+            n
+        };
+        assert_eq!(1, ntables(&db, b"ab"));
+        assert_eq!(0, ntables(&db, b"ae"));
+        // Not a prefix as returned by the extractor, so the filters can't be used.
+        assert_eq!(2, ntables(&db, b"a"));
+
+        let mut iter = db.new_prefix_iter(b"ab").unwrap();
+        let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(10, keys.len());
+        assert_eq!(b"ab0".to_vec(), keys[0]);
+        assert_eq!(b"ab5x".to_vec(), keys[6]);
+        assert_eq!(b"ab8".to_vec(), keys[9]);
+
+        iter.seek(b"ab5");
+        assert_eq!(b"ab5".to_vec(), current_key_val(&iter).unwrap().0);
+        iter.seek_to_first();
+        assert_eq!(b"ab0".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(!iter.prev());
+        assert!(iter.seek_for_prev(b"ab~"));
+        assert_eq!(b"ab8".to_vec(), current_key_val(&iter).unwrap().0);
+
+        assert!(db.new_prefix_iter(b"ae").unwrap().next().is_none());
+    }
 
-        let f = build_table("db", &opt, mt.iter(), 123).unwrap();
-        let path = &Path::new("db").join("000123.ldb");
+    #[test]
+    fn test_db_impl_new_iter_with_bounds() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        for prefix in &["a", "b", "c"] {
+            for i in 0..10 {
+                db.put(format!("{}{}", prefix, i).as_bytes(), b"val")
+                    .unwrap();
+            }
+            if *prefix != "c" {
+                db.make_room_for_write(true).unwrap();
+            }
+        }
+        db.delete(b"b5").unwrap();
+        let ntables = |db: &DB, lower: Option<&[u8]>, upper: Option<&[u8]>| {
+            let ropt = ReadOptions {
+                iterate_lower_bound: lower.map(|l| l.to_vec()),
+                iterate_upper_bound: upper.map(|u| u.to_vec()),
+                ..ReadOptions::default()
+            };
+            let v = db.current();
+            let n = v.borrow().new_iters_with(&ropt).unwrap().len();
+            n
+        };
+        assert!(ntables(&db, Some(b"a"), None) > 0);
+        assert_eq!(0, ntables(&db, Some(b"c"), None));
+        assert_eq!(0, ntables(&db, None, Some(b"a")));
+
+        let ropt = ReadOptions {
+            iterate_lower_bound: Some(b"a5".to_vec()),
+            iterate_upper_bound: Some(b"b7".to_vec()),
+            ..ReadOptions::default()
+        };
+        let mut iter = db.new_iter_with(&ropt).unwrap();
+        let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(11, keys.len());
+        assert_eq!(b"a5".to_vec(), keys[0]);
+        assert_eq!(b"b6".to_vec(), keys[10]);
+
+        iter.seek(b"a");
+        assert_eq!(b"a5".to_vec(), current_key_val(&iter).unwrap().0);
+        iter.seek(b"b7");
+        assert!(!iter.valid());
+        assert!(iter.seek_for_prev(b"z"));
+        assert_eq!(b"b6".to_vec(), current_key_val(&iter).unwrap().0);
+        let mut n = 1;
+        while iter.prev() {
+            n += 1;
+        }
+        assert_eq!(11, n);
+
+        // Without bounds, all keys are returned.
+        let snapshot = db.get_snapshot();
+        let mut iter = db
+            .new_iter_at_with(snapshot, &ReadOptions::default())
+            .unwrap();
+        assert_eq!(29, LdbIteratorIter::wrap(&mut iter).count());
+    }
 
+    #[test]
+    fn test_db_impl_delete_range() {
+        let opt = options::for_test();
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        for k in &["a", "b", "c", "d", "e"] {
+            db.put(k.as_bytes(), b"old").unwrap();
+        }
+        db.flush_memtable().unwrap();
+        db.put(b"bb", b"old").unwrap();
+        let ss = db.get_snapshot();
+        db.delete_range(b"b", b"d").unwrap();
+        db.put(b"c", b"new").unwrap();
+        db.put(b"f", b"new").unwrap();
         assert_eq!(
-            LookupKey::new("aabc".as_bytes(), 6).internal_key(),
-            f.smallest.as_slice()
+            StatusCode::InvalidArgument,
+            db.delete_range(b"d", b"b").unwrap_err().code
+        );
+
+        let check = |db: &mut DB| {
+            for (k, v) in &[
+                ("a", Some("old")),
+                ("b", None),
+                ("bb", None),
+                ("c", Some("new")),
+            ] {
+                assert_eq!(
+                    v.map(|v| v.as_bytes().to_vec()),
+                    db.get(k.as_bytes()).unwrap()
+                );
+            }
+            let mut iter = db.new_iter().unwrap();
+            let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+            assert_eq!(
+                vec![
+                    b"a".to_vec(),
+                    b"c".to_vec(),
+                    b"d".to_vec(),
+                    b"e".to_vec(),
+                    b"f".to_vec()
+                ],
+                keys
+            );
+            let mut keys = vec![];
+            let (mut k, mut v) = (vec![], vec![]);
+            iter.seek(b"e");
+            while iter.current(&mut k, &mut v) {
+                keys.push(String::from_utf8(k.clone()).unwrap());
+                iter.prev();
+            }
+            assert_eq!(vec!["e", "d", "c", "a"], keys);
+        };
+        check(&mut db);
+        assert_eq!(Some(b"old".to_vec()), db.get_at(&ss, b"bb").unwrap());
+        assert_eq!(Some(b"old".to_vec()), db.get_at(&ss, b"c").unwrap());
+
+        // The tombstone is in the log, then in the manifest.
+        drop(ss);
+        drop(db);
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        check(&mut db);
+        db.flush_memtable().unwrap();
+        assert_eq!(1, db.current().borrow().range_tombstones.len());
+        drop(db);
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        check(&mut db);
+
+        // Compactions drop the covered entries, and then the tombstone.
+        db.compact_all().unwrap();
+        check(&mut db);
+        assert!(db.current().borrow().range_tombstones.is_empty());
+        let entries = db
+            .current()
+            .borrow()
+            .files
+            .iter()
+            .flatten()
+            .map(|f| f.borrow().num_entries)
+            .sum::<u64>();
+        assert_eq!(5, entries);
+    }
+
+    #[test]
+    fn test_db_impl_delete_range_leveldb_compatible() {
+        let mut opt = options::for_test();
+        opt.leveldb_compatible = true;
+        let mut db = DB::open("db", opt).unwrap();
+        db.put(b"a", b"1").unwrap();
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.delete_range(b"a", b"b").unwrap_err().code
         );
+        let mut wb = WriteBatch::new();
+        wb.put(b"b", b"2");
+        wb.delete_range(b"a", b"b");
         assert_eq!(
-            LookupKey::new("test123".as_bytes(), 7).internal_key(),
-            f.largest.as_slice()
+            StatusCode::InvalidArgument,
+            db.write(wb, false).unwrap_err().code
         );
-        assert_eq!(379, f.size);
-        assert_eq!(123, f.num);
-        assert!(opt.env.exists(path).unwrap());
+        assert_eq!(Some(b"1".to_vec()), db.get(b"a").unwrap());
+        assert_eq!(None, db.get(b"b").unwrap());
+        db.flush_memtable().unwrap();
+        assert!(db.current().borrow().range_tombstones.is_empty());
+    }
+
+    #[test]
+    fn test_db_impl_compact_single_file() {
+        let mut db = build_db().0;
+        set_file_to_compact(&mut db, 4);
+        db.maybe_do_compaction().unwrap();
+
+        let env = &db.opt.env;
+        let name = &db.name;
+        assert!(!env.exists(Path::new(&table_file_name(name, 3))).unwrap());
+        assert!(!env.exists(Path::new(&table_file_name(name, 4))).unwrap());
+        assert!(!env.exists(Path::new(&table_file_name(name, 5))).unwrap());
+        assert!(env.exists(Path::new(&table_file_name(name, 13))).unwrap());
+    }
+
+    #[test]
+    fn test_db_impl_defer_compactions() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        opt.defer_compactions = true;
+        let mut db = DB::open("db", opt).unwrap();
+
+        // Memtables are flushed, but level 0 is left to grow. Keys are overwritten so that
+        // flushed files overlap and stay in level 0.
+        let mut i = 0;
+        while db.current().borrow().num_level_files(0) < 6 {
+            assert!(i < 100000);
+            db.put(format!("key{:05}", i % 100).as_bytes(), b"0123456789")
+                .unwrap();
+            i += 1;
+        }
+        assert!(db.needs_compaction());
+
+        while db.needs_compaction() {
+            db.compact_pending().unwrap();
+        }
+        assert!(db.current().borrow().num_level_files(0) < 4);
+        assert_eq!(
+            b"0123456789".to_vec(),
+            db.get(format!("key{:05}", (i - 1) % 100).as_bytes())
+                .unwrap()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_db_impl_compact_step() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 8192;
+        opt.target_file_size_base = 1024;
+        opt.defer_compactions = true;
+        let env = opt.env.clone();
+        let mut db = DB::open("db", opt).unwrap();
+
+        let mut i = 0;
+        while db.current().borrow().num_level_files(0) < 6 {
+            db.put(
+                format!("key{:05}", i % 100).as_bytes(),
+                format!("{:08}", i).as_bytes(),
+            )
+            .unwrap();
+            i += 1;
+        }
 
-        {
-            // Read table back in.
-            let mut tc = TableCache::new("db", opt.clone(), 100);
-            let tbl = tc.get_table(123).unwrap();
-            assert_eq!(mt.len(), LdbIteratorIter::wrap(&mut tbl.iter()).count());
+        // The level compaction pauses after the first step...
+        assert!(db.compact_step().unwrap());
+        assert!(db.running_compaction.is_some());
+        let l0 = db.current().borrow().num_level_files(0);
+        assert!(l0 >= 6);
+
+        // ...and memtable compactions proceed meanwhile, into level 0.
+        while db.current().borrow().num_level_files(0) == l0 {
+            db.put(
+                format!("key{:05}", i % 100).as_bytes(),
+                format!("{:08}", i).as_bytes(),
+            )
+            .unwrap();
+            i += 1;
         }
+        assert_eq!(l0 + 1, db.current().borrow().num_level_files(0));
+        assert!(db.running_compaction.is_some());
 
-        {
-            // Corrupt table; make sure it doesn't load fully.
-            let mut buf = vec![];
-            opt.env
-                .open_sequential_file(path)
-                .unwrap()
-                .read_to_end(&mut buf)
-                .unwrap();
-            buf[150] += 1;
-            opt.env
-                .open_writable_file(path)
-                .unwrap()
-                .write_all(&buf)
-                .unwrap();
+        let mut steps = 1;
+        while db.compact_step().unwrap() {
+            steps += 1;
+            assert!(steps < 1000);
+        }
+        assert!(steps > 2);
+        assert!(db.running_compaction.is_none());
+        assert!(!db.needs_compaction());
+        assert!(db.current().borrow().num_level_files(0) < 4);
 
-            let mut tc = TableCache::new("db", opt.clone(), 100);
-            let tbl = tc.get_table(123).unwrap();
-            // The last two entries are skipped due to the corruption above.
+        for k in 0..100 {
+            let last = (0..i).filter(|j| j % 100 == k).max().unwrap();
             assert_eq!(
-                5,
-                LdbIteratorIter::wrap(&mut tbl.iter())
-                    .map(|v| eprintln!("{:?}", v))
-                    .count()
+                Some(format!("{:08}", last).into_bytes()),
+                db.get(format!("key{:05}", k).as_bytes()).unwrap()
             );
         }
+        // No output of the paused compaction was lost or left behind.
+        let tables = env
+            .children(Path::new("db"))
+            .unwrap()
+            .iter()
+            .filter(|n| matches!(parse_file_name(n), Ok((_, FileType::Table))))
+            .count();
+        assert_eq!(db.vset.borrow().live_files().len(), tables);
     }
 
-    #[allow(unused_variables)]
     #[test]
-    fn test_db_impl_build_db_sanity() {
-        let db = build_db().0;
-        let env = &db.opt.env;
-        let name = &db.name;
+    fn test_db_impl_compact_step_finished_first() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 8192;
+        opt.target_file_size_base = 1024;
+        opt.defer_compactions = true;
+        let mut db = DB::open("db", opt).unwrap();
+
+        let mut i = 0;
+        while db.current().borrow().num_level_files(0) < 6 {
+            db.put(format!("key{:05}", i % 100).as_bytes(), b"0123456789")
+                .unwrap();
+            i += 1;
+        }
+        assert!(db.compact_step().unwrap());
+        assert!(db.running_compaction.is_some());
 
-        assert!(env.exists(Path::new(&log_file_name(name, 12))).unwrap());
+        // Other compactions finish the paused one before picking their inputs.
+        db.compact_range(b"key00000", b"key00099").unwrap();
+        assert!(db.running_compaction.is_none());
+        assert_eq!(0, db.current().borrow().num_level_files(0));
+        assert_eq!(
+            b"0123456789".to_vec(),
+            db.get(b"key00042").unwrap().unwrap()
+        );
     }
 
     #[test]
-    fn test_db_impl_get_from_table_with_snapshot() {
-        let mut db = build_db().0;
+    fn test_db_impl_rate_limiter() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        opt.statistics = true;
+        let rl = share(RateLimiter::new_auto_tuned(opt.env.clone(), 1 << 30));
+        opt.rate_limiter = Some(rl.clone());
+        let mut db = DB::open("db", opt).unwrap();
+
+        let mut i = 0;
+        while rl.borrow().total_bytes() == 0 {
+            assert!(i < 100000);
+            db.put(format!("key{:05}", i % 100).as_bytes(), b"0123456789")
+                .unwrap();
+            i += 1;
+        }
+        assert!(db.statistics().unwrap().write_stalls() > 0);
+        // Stalls speed up the limiter.
+        assert!(rl.borrow().bytes_per_sec() > 1 << 29);
+    }
 
-        assert_eq!(30, db.vset.borrow().last_seq);
+    #[test]
+    fn test_db_impl_seek_compaction_stats() {
+        let (mut db, _) = build_db();
+        db.stats = Some(share(Statistics::new()));
 
-        // seq = 31
-        db.put("xyy".as_bytes(), "123".as_bytes()).unwrap();
-        let old_ss = db.get_snapshot();
-        // seq = 32
-        db.put("xyz".as_bytes(), "123".as_bytes()).unwrap();
-        db.flush().unwrap();
-        assert!(db.get_at(&old_ss, "xyy".as_bytes()).unwrap().is_some());
-        assert!(db.get_at(&old_ss, "xyz".as_bytes()).unwrap().is_none());
+        set_file_to_compact(&mut db, 4);
+        db.maybe_do_compaction().unwrap();
+        assert_eq!(1, db.statistics().unwrap().seek_compactions());
+    }
 
-        // memtable get
-        assert_eq!(
-            "123".as_bytes(),
-            db.get("xyz".as_bytes()).unwrap().as_slice()
-        );
-        assert!(db.get_internal(31, "xyy".as_bytes()).unwrap().is_some());
-        assert!(db.get_internal(32, "xyy".as_bytes()).unwrap().is_some());
+    /// TestScheduler records the jobs it is offered, and defers them while `defer` is set.
+    struct TestScheduler {
+        defer: Rc<Cell<bool>>,
+        jobs: Rc<RefCell<Vec<CompactionJob>>>,
+    }
 
-        assert!(db.get_internal(31, "xyz".as_bytes()).unwrap().is_none());
-        assert!(db.get_internal(32, "xyz".as_bytes()).unwrap().is_some());
+    impl CompactionScheduler for TestScheduler {
+        fn schedule(&self, jobs: &[CompactionJob]) -> CompactionDecision {
+            *self.jobs.borrow_mut() = jobs.to_vec();
+            if self.defer.get() {
+                CompactionDecision::Defer
+            } else {
+                CompactionDecision::Run(jobs.len() - 1)
+            }
+        }
+    }
 
-        // table get
-        assert_eq!(
-            "val2".as_bytes(),
-            db.get("eab".as_bytes()).unwrap().as_slice()
-        );
-        assert!(db.get_internal(3, "eab".as_bytes()).unwrap().is_none());
-        assert!(db.get_internal(32, "eab".as_bytes()).unwrap().is_some());
+    #[test]
+    fn test_db_impl_prefetch_range() {
+        let mut opt = options::for_test();
+        opt.block_size = 128;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..500 {
+                db.put(format!("key{:03}", i).as_bytes(), &[7; 20]).unwrap();
+            }
+            db.compact_range(b"key000", b"key999").unwrap();
+        }
 
+        opt.persist_hot_blocks = true;
+        opt.block_cache = share(Cache::new(1000));
         {
-            let ss = db.get_snapshot();
-            assert_eq!(
-                "val2".as_bytes(),
-                db.get_at(&ss, "eab".as_bytes())
-                    .unwrap()
-                    .unwrap()
-                    .as_slice()
-            );
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            assert_eq!(0, opt.block_cache.borrow().count());
+            let n = db.prefetch_range(b"key100", b"key199").unwrap();
+            assert!(n > 10);
+            assert_eq!(n, opt.block_cache.borrow().count());
+            assert_eq!(0, db.prefetch_range(b"key150", b"key160").unwrap());
+            assert!(db.prefetch_range(b"key000", b"key099").unwrap() > 10);
+            db.close().unwrap();
         }
 
-        // from table.
-        assert_eq!(
-            "val2".as_bytes(),
-            db.get("cab".as_bytes()).unwrap().as_slice()
-        );
+        // The cached blocks are loaded again when the database is opened.
+        let cached = opt.block_cache.borrow().count();
+        opt.block_cache = share(Cache::new(1000));
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(cached, opt.block_cache.borrow().count());
+        assert_eq!(0, db.prefetch_range(b"key000", b"key199").unwrap());
+        assert!(db.prefetch_range(b"key200", b"key201").unwrap() > 0);
     }
 
     #[test]
-    fn test_db_impl_delete() {
-        let mut db = build_db().0;
-
-        db.put(b"xyy", b"123").unwrap();
-        db.put(b"xyz", b"123").unwrap();
+    fn test_db_impl_read_txn() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put(b"b", b"2").unwrap();
 
-        assert!(db.get(b"xyy").is_some());
-        assert!(db.get(b"gaa").is_some());
+        let mut txn = db.read_txn();
+        let seq = txn.snapshot().sequence();
+        assert_eq!(
+            vec![Some(b"1".to_vec()), None, Some(b"2".to_vec())],
+            txn.multi_get(&[b"a", b"c", b"b"]).unwrap()
+        );
+        assert_eq!(2, LdbIteratorIter::wrap(&mut txn.iter().unwrap()).count());
+        drop(txn);
+
+        db.put(b"c", b"3").unwrap();
+        db.delete(b"a").unwrap();
+        let mut txn = db.read_txn();
+        assert!(txn.snapshot().sequence() > seq);
+        assert_eq!(None, txn.get(b"a").unwrap());
+        assert_eq!(
+            vec![None, Some(b"3".to_vec())],
+            txn.multi_get(&[b"a".to_vec(), b"c".to_vec()]).unwrap()
+        );
+        let mut iter = txn.iter().unwrap();
+        drop(txn);
+        db.put(b"d", b"4").unwrap();
+        assert_eq!(2, LdbIteratorIter::wrap(&mut iter).count());
+        assert_eq!(1, db.snapshots().len());
+    }
 
-        // Delete one memtable entry and one table entry.
-        db.delete(b"xyy").unwrap();
-        db.delete(b"gaa").unwrap();
+    #[test]
+    fn test_db_impl_memtable_shards() {
+        let mut opt = options::for_test();
+        opt.memtable_shards = 3;
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..50 {
+                db.put(format!("key{:02}", i).as_bytes(), b"val").unwrap();
+            }
+            db.delete(b"key10").unwrap();
+            assert_eq!(None, db.get(b"key10").unwrap());
+            assert_eq!(Some(b"val".to_vec()), db.get(b"key11").unwrap());
+            let keys: Vec<_> = LdbIteratorIter::wrap(&mut db.new_iter().unwrap())
+                .map(|(k, _)| k)
+                .collect();
+            assert_eq!(49, keys.len());
+            assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        }
 
-        assert!(db.get(b"xyy").is_none());
-        assert!(db.get(b"gaa").is_none());
-        assert!(db.get(b"xyz").is_some());
+        // Recovery writes a level-0 table from the sharded memtable.
+        opt.memtable_shards = 2;
+        let mut db = DB::open("db", opt).unwrap();
+        assert_eq!(None, db.get(b"key10").unwrap());
+        assert_eq!(Some(b"val".to_vec()), db.get(b"key49").unwrap());
+        db.compact_range(b"key00", b"key99").unwrap();
+        assert_eq!(Some(b"val".to_vec()), db.get(b"key00").unwrap());
     }
 
     #[test]
-    fn test_db_impl_compact_single_file() {
-        let mut db = build_db().0;
+    fn test_db_impl_compaction_scheduler() {
+        let (mut db, _) = build_db();
+        db.stats = Some(share(Statistics::new()));
+        let defer = Rc::new(Cell::new(true));
+        let jobs = Rc::new(RefCell::new(vec![]));
+        db.opt.compaction_scheduler = Rc::new(Box::new(TestScheduler {
+            defer: defer.clone(),
+            jobs: jobs.clone(),
+        }));
+        let table_exists = |db: &DB, num| {
+            db.opt
+                .env
+                .exists(Path::new(&table_file_name(&db.name, num)))
+                .unwrap()
+        };
+
         set_file_to_compact(&mut db, 4);
+        assert!(db.needs_compaction());
         db.maybe_do_compaction().unwrap();
+        assert!(table_exists(&db, 4));
+        assert!(!db.needs_compaction());
+        {
+            let jobs = jobs.borrow();
+            let job = jobs.last().unwrap();
+            assert_eq!(CompactionReason::Seek, job.reason);
+            assert_eq!(1, job.level);
+            assert_eq!(0.0, job.score);
+        }
 
-        let env = &db.opt.env;
-        let name = &db.name;
-        assert!(!env.exists(Path::new(&table_file_name(name, 3))).unwrap());
-        assert!(!env.exists(Path::new(&table_file_name(name, 4))).unwrap());
-        assert!(!env.exists(Path::new(&table_file_name(name, 5))).unwrap());
-        assert!(env.exists(Path::new(&table_file_name(name, 13))).unwrap());
+        // Writes and explicit calls ask the scheduler again.
+        db.put(b"zzz", b"val").unwrap();
+        assert!(db.needs_compaction());
+        defer.set(false);
+        db.compact_pending().unwrap();
+        assert!(!table_exists(&db, 4));
+        assert!(table_exists(&db, 13));
+        assert_eq!(1, db.statistics().unwrap().seek_compactions());
     }
 
     #[test]
@@ -1763,6 +6429,447 @@ mod tests {
         assert!(!env.exists(&Path::new("db").join("000001.ldb")).unwrap());
     }
 
+    /// CrashEnv is a MemEnv whose mutating operations fail after a budget of them is used up.
+    /// crash() then simulates a power loss: CURRENT and manifest files lose everything written to
+    /// them since they were last synced. Independently, opening a single table file can fail.
+    struct CrashEnv(Rc<CrashState>);
+
+    struct CrashState {
+        env: MemEnv,
+        ops_left: Cell<Option<usize>>,
+        // How many table files are opened before opening one fails.
+        table_opens_left: Cell<Option<usize>>,
+        // Files with unsynced writes, and their durable sizes.
+        unsynced: RefCell<HashMap<PathBuf, usize>>,
+        dir_syncs: Cell<usize>,
+        // Files synced by name, rather than through their handle.
+        synced_by_name: RefCell<Vec<PathBuf>>,
+    }
+
+    impl CrashState {
+        fn new() -> Rc<CrashState> {
+            Rc::new(CrashState {
+                env: MemEnv::new(),
+                ops_left: Cell::new(None),
+                table_opens_left: Cell::new(None),
+                unsynced: RefCell::new(HashMap::new()),
+                dir_syncs: Cell::new(0),
+                synced_by_name: RefCell::new(vec![]),
+            })
+        }
+
+        fn mutate(&self) -> Result<()> {
+            match self.ops_left.get() {
+                Some(0) => err(StatusCode::IOError, "simulated crash"),
+                Some(n) => {
+                    self.ops_left.set(Some(n - 1));
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+
+        fn crash(&self) {
+            self.ops_left.set(None);
+            for (p, size) in self.unsynced.borrow_mut().drain() {
+                let name = p.file_name().unwrap().to_str().unwrap();
+                let lossy =
+                    name == "CURRENT" || name.starts_with("MANIFEST-") || name.ends_with(".dbtmp");
+                if lossy && self.env.exists(&p).unwrap() {
+                    let mut contents = vec![];
+                    let mut f = self.env.open_sequential_file(&p).unwrap();
+                    f.read_to_end(&mut contents).unwrap();
+                    let mut f = self.env.open_writable_file(&p).unwrap();
+                    f.write_all(&contents[..size]).unwrap();
+                }
+            }
+        }
+    }
+
+    impl Env for CrashEnv {
+        fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+            self.0.env.open_sequential_file(p)
+        }
+        fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
+            self.0.env.open_random_access_file(p)
+        }
+        fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+            self.0.mutate()?;
+            if p.extension().is_some_and(|e| e == "ldb") {
+                match self.0.table_opens_left.get() {
+                    Some(0) => {
+                        self.0.table_opens_left.set(None);
+                        return err(StatusCode::IOError, "simulated failure");
+                    }
+                    Some(n) => self.0.table_opens_left.set(Some(n - 1)),
+                    None => {}
+                }
+            }
+            self.0.unsynced.borrow_mut().insert(p.to_owned(), 0);
+            self.0.env.open_writable_file(p)
+        }
+        fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+            self.0.mutate()?;
+            let size = self.0.env.size_of(p).unwrap_or(0);
+            self.0
+                .unsynced
+                .borrow_mut()
+                .entry(p.to_owned())
+                .or_insert(size);
+            self.0.env.open_appendable_file(p)
+        }
+        fn exists(&self, p: &Path) -> Result<bool> {
+            self.0.env.exists(p)
+        }
+        fn children(&self, p: &Path) -> Result<Vec<PathBuf>> {
+            self.0.env.children(p)
+        }
+        fn size_of(&self, p: &Path) -> Result<usize> {
+            self.0.env.size_of(p)
+        }
+        fn delete(&self, p: &Path) -> Result<()> {
+            self.0.mutate()?;
+            self.0.unsynced.borrow_mut().remove(p);
+            self.0.env.delete(p)
+        }
+        fn mkdir(&self, p: &Path) -> Result<()> {
+            self.0.mutate()?;
+            self.0.env.mkdir(p)
+        }
+        fn rmdir(&self, p: &Path) -> Result<()> {
+            self.0.mutate()?;
+            self.0.env.rmdir(p)
+        }
+        fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+            self.0.mutate()?;
+            self.0.env.rename(old, new)?;
+            let mut unsynced = self.0.unsynced.borrow_mut();
+            match unsynced.remove(old) {
+                Some(size) => unsynced.insert(new.to_owned(), size),
+                None => unsynced.remove(new),
+            };
+            Ok(())
+        }
+        fn open_syncable_file(&self, p: &Path, append: bool) -> Result<Box<dyn WritableFile>> {
+            let file = if append {
+                self.open_appendable_file(p)?
+            } else {
+                self.open_writable_file(p)?
+            };
+            Ok(Box::new(CrashFile {
+                file,
+                path: p.to_owned(),
+                state: self.0.clone(),
+            }))
+        }
+        fn sync_file(&self, p: &Path) -> Result<()> {
+            self.0.mutate()?;
+            self.0.unsynced.borrow_mut().remove(p);
+            self.0.synced_by_name.borrow_mut().push(p.to_owned());
+            Ok(())
+        }
+        fn sync_dir(&self, _: &Path) -> Result<()> {
+            self.0.mutate()?;
+            self.0.dir_syncs.set(self.0.dir_syncs.get() + 1);
+            Ok(())
+        }
+        fn lock(&self, p: &Path) -> Result<FileLock> {
+            self.0.env.lock(p)
+        }
+        fn unlock(&self, l: FileLock) -> Result<()> {
+            self.0.env.unlock(l)
+        }
+        fn new_logger(&self, p: &Path) -> Result<crate::env::Logger> {
+            self.0.env.new_logger(p)
+        }
+        fn micros(&self) -> u64 {
+            self.0.env.micros()
+        }
+        fn sleep_for(&self, micros: u32) {
+            self.0.env.sleep_for(micros)
+        }
+    }
+
+    /// CrashFile is a file of a CrashEnv that can be synced through its handle.
+    struct CrashFile {
+        file: Box<dyn Write>,
+        path: PathBuf,
+        state: Rc<CrashState>,
+    }
+
+    impl Write for CrashFile {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.file.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl WritableFile for CrashFile {
+        fn sync(&mut self) -> Result<()> {
+            self.state.mutate()?;
+            self.state.unsynced.borrow_mut().remove(&self.path);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_db_impl_manifest_update_syncs() {
+        let state = CrashState::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(CrashEnv(state.clone())));
+        let mut db = DB::open("db", opt).unwrap();
+        let manifest = manifest_file_name(Path::new("db"), db.vset.borrow().manifest_num);
+        let dir_syncs = state.dir_syncs.get();
+        state.synced_by_name.borrow_mut().clear();
+
+        for i in 0..3 {
+            db.put(format!("key{}", i).as_bytes(), b"value").unwrap();
+            db.flush_memtable().unwrap();
+        }
+        db.compact_range(b"a", b"z").unwrap();
+
+        // The manifest is synced through its handle, and CURRENT is left alone as it still
+        // refers to the same manifest.
+        assert!(!state.unsynced.borrow().contains_key(&manifest));
+        assert!(!state.synced_by_name.borrow().contains(&manifest));
+        assert!(!state
+            .unsynced
+            .borrow()
+            .contains_key(&current_file_name(Path::new("db"))));
+        assert_eq!(dir_syncs, state.dir_syncs.get());
+    }
+
+    #[test]
+    fn test_db_impl_crash_during_manifest_update() {
+        for crash_at in 0.. {
+            let state = CrashState::new();
+            let mut opt = options::for_test();
+            opt.env = Rc::new(Box::new(CrashEnv(state.clone())));
+            {
+                let mut db = DB::open("db", opt.clone()).unwrap();
+                db.put(b"abc", b"def").unwrap();
+                db.compact_range(b"a", b"z").unwrap();
+            }
+
+            // Reopening writes a new manifest; compacting updates it.
+            state.ops_left.set(Some(crash_at));
+            let completed = DB::open("db", opt.clone()).and_then(|mut db| {
+                db.put(b"xyz", b"uvw")?;
+                db.compact_range(b"a", b"z")
+            });
+            let crashed = state.ops_left.get() == Some(0);
+            state.crash();
+
+            let mut db = DB::open("db", opt.clone())
+                .unwrap_or_else(|e| panic!("reopen after crash at {}: {}", crash_at, e));
+            assert_eq!(
+                Some(b"def".to_vec()),
+                db.get(b"abc").unwrap(),
+                "crash at {}",
+                crash_at
+            );
+            if !crashed {
+                assert!(completed.is_ok());
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_db_impl_failed_compaction_keeps_inputs() {
+        let state = CrashState::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(CrashEnv(state.clone())));
+        opt.target_file_size_base = 1000;
+        let mut db = DB::open("db", opt).unwrap();
+        let key = |i: usize| format!("key{:03}", i).into_bytes();
+        for round in 0..2 {
+            for i in 0..50 {
+                db.put(&key(i), &[round; 100]).unwrap();
+            }
+            db.flush_memtable().unwrap();
+        }
+
+        // Creating the second output file fails.
+        state.table_opens_left.set(Some(1));
+        assert!(db.compact_range(b"a", b"z").is_err());
+        for i in 0..50 {
+            assert_eq!(Some(vec![1; 100]), db.get(&key(i)).unwrap());
+        }
+        db.compact_range(b"a", b"z").unwrap();
+        for i in 0..50 {
+            assert_eq!(Some(vec![1; 100]), db.get(&key(i)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_db_impl_durability() {
+        let mut dir_syncs = vec![];
+        for &d in &[
+            Durability::None,
+            Durability::Buffered,
+            Durability::DataSync,
+            Durability::FullSync,
+        ] {
+            let state = CrashState::new();
+            let mut opt = options::for_test();
+            opt.env = Rc::new(Box::new(CrashEnv(state.clone())));
+            opt.durability = d;
+            let mut db = DB::open("db", opt).unwrap();
+            dir_syncs.push(state.dir_syncs.get());
+
+            let log = log_file_name(Path::new("db"), db.log_num.unwrap());
+            db.put(b"abc", b"def").unwrap();
+            assert_eq!(
+                d >= Durability::Buffered,
+                state.env.size_of(&log).unwrap() > 0
+            );
+            assert_eq!(
+                d >= Durability::DataSync,
+                !state.unsynced.borrow().contains_key(&log)
+            );
+
+            // Synchronous writes are synced at every level.
+            let mut wb = WriteBatch::new();
+            wb.put(b"xyz", b"uvw");
+            state.unsynced.borrow_mut().insert(log.clone(), 0);
+            db.write(wb, true).unwrap();
+            assert!(!state.unsynced.borrow().contains_key(&log));
+        }
+        // Only FullSync syncs the directory after creating the log file.
+        assert_eq!(dir_syncs[0], dir_syncs[2]);
+        assert!(dir_syncs[3] > dir_syncs[2]);
+    }
+
+    #[test]
+    fn test_db_impl_log_sync_interval() {
+        let state = CrashState::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(CrashEnv(state.clone())));
+        opt.log_sync_interval = Some(Duration::from_millis(50));
+        let mut db = DB::open("db", opt).unwrap();
+        let log = log_file_name(Path::new("db"), db.log_num.unwrap());
+        let synced = || !state.unsynced.borrow().contains_key(&log);
+
+        db.put(b"abc", b"def").unwrap();
+        assert!(!synced());
+        db.sync_log_if_due().unwrap();
+        assert!(!synced());
+
+        std::thread::sleep(Duration::from_millis(60));
+        db.sync_log_if_due().unwrap();
+        assert!(synced());
+        // Nothing to sync.
+        state.unsynced.borrow_mut().insert(log.clone(), 0);
+        std::thread::sleep(Duration::from_millis(60));
+        db.sync_log_if_due().unwrap();
+        assert!(!synced());
+
+        // A write after the interval is synced immediately.
+        db.put(b"xyz", b"uvw").unwrap();
+        assert!(synced());
+    }
+
+    #[test]
+    fn test_db_impl_commit_group() {
+        let state = CrashState::new();
+        let mut opt = options::for_test();
+        opt.env = Rc::new(Box::new(CrashEnv(state.clone())));
+        opt.statistics = true;
+        let mut db = DB::open("db", opt).unwrap();
+        let log = log_file_name(Path::new("db"), db.log_num.unwrap());
+
+        for i in 0..3u8 {
+            db.put(&[i], b"val").unwrap();
+        }
+        assert!(state.unsynced.borrow().contains_key(&log));
+        db.commit_group(3).unwrap();
+        assert!(!state.unsynced.borrow().contains_key(&log));
+        db.put(b"x", b"val").unwrap();
+        db.commit_group(1).unwrap();
+
+        let stats = db.statistics().unwrap();
+        assert_eq!(2, stats.group_commit_sizes().count());
+        assert_eq!(2.0, stats.group_commit_sizes().average());
+    }
+
+    #[test]
+    fn test_db_impl_compact_idle() {
+        let mut opt = options::for_test();
+        opt.idle_compaction_delay = Some(Duration::from_millis(0));
+        opt.idle_tombstone_purge = true;
+        let mut db = DB::open("db", opt).unwrap();
+        assert!(db.idle_compaction_due());
+        assert!(!db.compact_idle().unwrap());
+        assert!(!db.idle_compaction_due());
+
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), b"val").unwrap();
+        }
+        db.make_room_for_write(true).unwrap();
+        for i in 0..50 {
+            db.delete(format!("key{:03}", i).as_bytes()).unwrap();
+        }
+        db.make_room_for_write(true).unwrap();
+        db.put(b"key100", b"val").unwrap();
+        db.make_room_for_write(true).unwrap();
+        assert!(db.idle_compaction_due());
+
+        let mut steps = 0;
+        while db.compact_idle().unwrap() {
+            steps += 1;
+            assert!(steps < 10);
+        }
+        assert!(!db.idle_compaction_due());
+        let v = db.current();
+        let v = v.borrow();
+        assert!(v.files[0].is_empty());
+        let files: Vec<_> = v.files.iter().flatten().collect();
+        assert_eq!(
+            51,
+            files.iter().map(|f| f.borrow().num_entries).sum::<u64>()
+        );
+        assert!(files.iter().all(|f| f.borrow().num_deletions == 0));
+        drop(v);
+        assert!(db.get(b"key000").unwrap().is_none());
+
+        db.put(b"abc", b"def").unwrap();
+        assert!(db.idle_compaction_due());
+    }
+
+    #[test]
+    fn test_db_impl_pause_background_work() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1000;
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        let files = |db: &DB| db.current().borrow().files.iter().flatten().count();
+
+        db.pause_background_work();
+        db.pause_background_work();
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        assert_eq!(0, files(&db));
+        assert!(!db.needs_compaction());
+        assert_eq!(
+            StatusCode::WouldBlock,
+            db.compact_range(b"a", b"z").err().unwrap().code
+        );
+        assert_eq!(b"value".to_vec(), db.get(b"key050").unwrap().unwrap());
+
+        db.continue_background_work().unwrap();
+        assert_eq!(0, files(&db));
+        db.continue_background_work().unwrap();
+        assert_eq!(1, files(&db));
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            db.continue_background_work().err().unwrap().code
+        );
+        assert_eq!(b"value".to_vec(), db.get(b"key050").unwrap().unwrap());
+    }
+
     #[test]
     fn test_db_impl_open_close_reopen() {
         let opt;
@@ -1785,7 +6892,7 @@ mod tests {
         {
             let mut db = DB::open("db", opt.clone()).unwrap();
 
-            assert_eq!(None, db.get(b"xx5"));
+            assert_eq!(None, db.get(b"xx5").unwrap());
 
             let ss = db.get_snapshot();
             db.put(b"xx4", b"222").unwrap();
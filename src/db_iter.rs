@@ -1,8 +1,14 @@
 use crate::cmp::Cmp;
+use crate::db_impl::DB;
+use crate::env_common::Instant;
+use crate::error::{Result, Status, StatusCode};
 use crate::key_types::{parse_internal_key, truncate_to_userkey, LookupKey, ValueType};
 use crate::merging_iter::MergingIter;
+use crate::options::ReadOptions;
+use crate::range_del::{covering_seq, RangeTombstone};
 use crate::snapshot::Snapshot;
-use crate::types::{Direction, LdbIterator, Shared};
+use crate::statistics::{Operation, Statistics};
+use crate::types::{Direction, LdbIterator, Shared, MAX_SEQUENCE_NUMBER};
 use crate::version_set::VersionSet;
 
 use std::cmp::Ordering;
@@ -22,9 +28,29 @@ pub struct DBIterator {
     // By holding onto a snapshot, we make sure that the iterator iterates over the state at the
     // point of its creation.
     ss: Snapshot,
+    // The range tombstones of the database when the iterator was created or refreshed.
+    range_tombstones: Vec<RangeTombstone>,
+    // Whether refresh() moves the iterator to the latest state of the database, or keeps ss.
+    follow_latest: bool,
+    // If set, the iterator only returns keys starting with this prefix (see DB::new_prefix_iter).
+    prefix: Option<Vec<u8>>,
+    // How table blocks are read, and the bounds of the keys returned by the iterator.
+    ropt: ReadOptions,
+    // Latencies of seek() and advance() are recorded here, if set.
+    stats: Option<Shared<Statistics>>,
     dir: Direction,
     byte_count: isize,
 
+    // Internal entries skipped by the current operation, and in total.
+    skipped: usize,
+    total_skipped: u64,
+    // Deletion markers skipped since the last visible entry.
+    tombstones: usize,
+    // If non-zero, stop after skipping this many consecutive deletion markers.
+    max_skipped_tombstones: usize,
+    // Set if the iterator stopped early in the middle of a deleted range.
+    incomplete: bool,
+
     valid: bool,
     // temporarily stored user key.
     savedkey: Vec<u8>,
@@ -40,15 +66,27 @@ impl DBIterator {
         vset: Shared<VersionSet>,
         iter: MergingIter,
         ss: Snapshot,
+        stats: Option<Shared<Statistics>>,
     ) -> DBIterator {
         DBIterator {
             cmp,
             vset,
             iter,
             ss,
+            range_tombstones: vec![],
+            follow_latest: false,
+            prefix: None,
+            ropt: ReadOptions::default(),
+            stats,
             dir: Direction::Forward,
             byte_count: random_period(),
 
+            skipped: 0,
+            total_skipped: 0,
+            tombstones: 0,
+            max_skipped_tombstones: 0,
+            incomplete: false,
+
             valid: false,
             savedkey: vec![],
             keybuf: vec![],
@@ -57,6 +95,161 @@ impl DBIterator {
         }
     }
 
+    pub(crate) fn set_range_tombstones(&mut self, range_tombstones: Vec<RangeTombstone>) {
+        self.range_tombstones = range_tombstones;
+    }
+
+    /// is_range_deleted returns true if the entry for ukey at seq is covered by a range tombstone
+    /// in the iterator's snapshot.
+    fn is_range_deleted(&self, ukey: &[u8], seq: u64) -> bool {
+        !self.range_tombstones.is_empty()
+            && covering_seq(
+                &self.range_tombstones,
+                self.cmp.as_ref().as_ref(),
+                ukey,
+                self.ss.sequence(),
+            ) > seq
+    }
+
+    /// set_follow_latest makes refresh() move the iterator to the latest database state instead of
+    /// its snapshot.
+    pub(crate) fn set_follow_latest(&mut self) {
+        self.follow_latest = true;
+    }
+
+    /// set_prefix restricts the iterator to keys starting with prefix.
+    pub(crate) fn set_prefix(&mut self, prefix: &[u8]) {
+        self.prefix = Some(prefix.to_vec());
+    }
+
+    /// set_read_options sets the options the iterator's table iterators were created with,
+    /// restricting the iterator to the bounds set in them.
+    pub(crate) fn set_read_options(&mut self, ropt: &ReadOptions) {
+        self.ropt = ropt.clone();
+    }
+
+    /// in_bounds returns whether ukey has the iterator's prefix and is within its bounds.
+    fn in_bounds(&self, ukey: &[u8]) -> bool {
+        self.prefix.as_ref().is_none_or(|p| ukey.starts_with(p))
+            && self
+                .ropt
+                .iterate_lower_bound
+                .as_ref()
+                .is_none_or(|l| self.cmp.cmp(ukey, l) != Ordering::Less)
+            && self
+                .ropt
+                .iterate_upper_bound
+                .as_ref()
+                .is_none_or(|u| self.cmp.cmp(ukey, u) == Ordering::Less)
+    }
+
+    /// current_in_bounds returns whether the current entry's key is within the iterator's prefix
+    /// and bounds. It must only be called on a valid iterator.
+    fn current_in_bounds(&self) -> bool {
+        // A valid forward iterator has just read its current entry into keybuf.
+        let ukey = if self.dir == Direction::Forward {
+            parse_internal_key(&self.keybuf).2
+        } else {
+            &self.savedkey[..]
+        };
+        self.in_bounds(ukey)
+    }
+
+    /// check_bounds invalidates the iterator if it moved to a key outside its prefix or bounds.
+    fn check_bounds(&mut self) {
+        if self.valid && !self.current_in_bounds() {
+            self.valid = false;
+        }
+    }
+
+    /// start returns the smallest key the iterator may return according to its lower bound and
+    /// prefix, if it has either.
+    fn start(&self) -> Option<&[u8]> {
+        match (
+            self.ropt.iterate_lower_bound.as_deref(),
+            self.prefix.as_deref(),
+        ) {
+            (Some(l), Some(p)) if self.cmp.cmp(l, p) == Ordering::Less => Some(p),
+            (l, p) => l.or(p),
+        }
+    }
+
+    /// refresh re-creates the iterator's view of the database from the database's current files
+    /// and memtables, releasing tables and memtables that it was holding on to. Iterators created
+    /// by `DB::new_iter()` advance to the latest state of the database, while iterators created
+    /// by `DB::new_iter_at()` keep their snapshot.
+    ///
+    /// A valid iterator is positioned at the first entry at or after its current key afterwards.
+    pub fn refresh(&mut self, db: &mut DB) -> Result<()> {
+        let mut key = vec![];
+        let valid = self.valid() && self.current(&mut key, &mut vec![]);
+        let iter = match self.prefix {
+            Some(ref prefix) => db.merge_prefix_iterators(prefix)?,
+            None => db.merge_iterators_with(&self.ropt)?,
+        };
+        if self.follow_latest {
+            self.ss = db.get_snapshot();
+        }
+        self.iter = iter;
+        self.range_tombstones = db.range_tombstones();
+        self.reset();
+        if valid {
+            self.seek_internal(&key);
+        }
+        Ok(())
+    }
+
+    /// set_max_skipped_tombstones makes forward iteration stop after skipping n consecutive
+    /// deletion markers (0 means no limit), instead of scanning through an arbitrarily large
+    /// deleted range. The iterator becomes invalid and `status()` returns an error with
+    /// `StatusCode::Incomplete`; calling `advance()` continues the scan where it stopped.
+    pub fn set_max_skipped_tombstones(&mut self, n: usize) {
+        self.max_skipped_tombstones = n;
+    }
+
+    /// seek_for_prev moves the iterator to the last entry with a key at or before to, and returns
+    /// whether there is one.
+    pub fn seek_for_prev(&mut self, to: &[u8]) -> bool {
+        let start = self.stats.as_ref().map(|_| Instant::now());
+        let to = match self.ropt.iterate_upper_bound {
+            // The entry before the upper bound is the last one in bounds.
+            Some(ref u) if self.cmp.cmp(to, u) != Ordering::Less => u.clone(),
+            _ => to.to_vec(),
+        };
+        self.seek_internal(&to);
+        if self.valid() {
+            let mut key = vec![];
+            self.current(&mut key, &mut vec![]);
+            if self.cmp.cmp(&key, &to) == Ordering::Greater || !self.current_in_bounds() {
+                self.prev();
+            }
+            self.check_bounds();
+        } else if !self.incomplete {
+            // All keys are before to, so the last entry is the one.
+            self.seek_to_last_internal();
+        }
+        self.finish_op(Operation::Seek, start);
+        self.valid()
+    }
+
+    /// status returns an error if the iterator was stopped early.
+    pub fn status(&self) -> Result<()> {
+        if self.incomplete {
+            Err(Status::new(
+                StatusCode::Incomplete,
+                "too many deleted entries skipped",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// skipped_internal_keys returns the number of internal entries (deleted entries, deletion
+    /// markers and older versions) that this iterator has skipped so far.
+    pub fn skipped_internal_keys(&self) -> u64 {
+        self.total_skipped
+    }
+
     /// record_read_sample records a read sample using the current contents of self.keybuf, which
     /// should be an InternalKey.
     fn record_read_sample(&mut self, len: usize) {
@@ -79,7 +272,14 @@ impl DBIterator {
             self.iter.current(&mut self.keybuf, &mut self.savedval);
             let len = self.keybuf.len() + self.savedval.len();
             self.record_read_sample(len);
-            let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            let (mut typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            if !self.in_bounds(ukey) {
+                // Moving forward, all further keys are past the upper bound or prefix, too.
+                break;
+            }
+            if typ == ValueType::TypeValue && self.is_range_deleted(ukey, seq) {
+                typ = ValueType::TypeDeletion;
+            }
 
             // Skip keys with a sequence number after our snapshot.
             if seq <= self.ss.sequence() {
@@ -88,16 +288,27 @@ impl DBIterator {
                     self.savedkey.clear();
                     self.savedkey.extend_from_slice(ukey);
                     skipping = true;
+                    self.tombstones += 1;
+                    if self.max_skipped_tombstones > 0
+                        && self.tombstones >= self.max_skipped_tombstones
+                    {
+                        // Keep savedkey and the position of self.iter for resuming.
+                        self.incomplete = true;
+                        self.valid = false;
+                        return false;
+                    }
                 } else if typ == ValueType::TypeValue {
                     if skipping && self.cmp.cmp(ukey, &self.savedkey) <= Ordering::Equal {
                         // Entry hidden, because it's smaller than the key to be skipped.
                     } else {
                         self.valid = true;
+                        self.tombstones = 0;
                         self.savedkey.clear();
                         return true;
                     }
                 }
             }
+            self.skipped += 1;
             self.iter.advance();
         }
         self.savedkey.clear();
@@ -127,7 +338,14 @@ impl DBIterator {
             self.iter.current(&mut self.keybuf, &mut self.valbuf);
             let len = self.keybuf.len() + self.valbuf.len();
             self.record_read_sample(len);
-            let (typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            let (mut typ, seq, ukey) = parse_internal_key(&self.keybuf);
+            if !self.in_bounds(ukey) {
+                // Moving backward, all further keys are before the lower bound or prefix, too.
+                break;
+            }
+            if typ == ValueType::TypeValue && self.is_range_deleted(ukey, seq) {
+                typ = ValueType::TypeDeletion;
+            }
 
             if seq > 0 && seq <= self.ss.sequence() {
                 if value_type != ValueType::TypeDeletion
@@ -162,8 +380,24 @@ impl DBIterator {
     }
 }
 
-impl LdbIterator for DBIterator {
-    fn advance(&mut self) -> bool {
+impl DBIterator {
+    fn advance_internal(&mut self) -> bool {
+        if self.incomplete {
+            // Resume skipping the deleted range.
+            self.incomplete = false;
+            self.tombstones = 0;
+            // The deletion marker at which the scan stopped is skipped now.
+            self.skipped += 1;
+            self.iter.advance();
+            if !self.iter.valid() {
+                self.savedkey.clear();
+                return false;
+            }
+            return self.find_next_user_entry(
+                // skipping=
+                true,
+            );
+        }
         if !self.valid() {
             self.seek_to_first();
             return self.valid();
@@ -185,12 +419,105 @@ impl LdbIterator for DBIterator {
             // Save current user key.
             assert!(self.iter.current(&mut self.savedkey, &mut self.savedval));
             truncate_to_userkey(&mut self.savedkey);
+            let r = self.find_next_user_entry(
+                // skipping=
+                true,
+            );
+            // The entry that was current has been passed over first; it wasn't skipped.
+            self.skipped -= 1;
+            return r;
         }
         self.find_next_user_entry(
             // skipping=
             true,
         )
     }
+
+    fn seek_internal(&mut self, to: &[u8]) {
+        self.incomplete = false;
+        self.tombstones = 0;
+        self.dir = Direction::Forward;
+        self.savedkey.clear();
+        self.savedval.clear();
+        self.savedkey
+            .extend_from_slice(LookupKey::new(to, self.ss.sequence()).internal_key());
+        self.iter.seek(&self.savedkey);
+        if self.iter.valid() {
+            self.find_next_user_entry(
+                // skipping=
+                false,
+            );
+        } else {
+            self.valid = false;
+        }
+    }
+
+    /// seek_to_last_internal moves the iterator to the last entry within its bounds.
+    fn seek_to_last_internal(&mut self) {
+        self.incomplete = false;
+        self.tombstones = 0;
+        self.savedkey.clear();
+        self.savedval.clear();
+        let upper = self
+            .ropt
+            .iterate_upper_bound
+            .as_ref()
+            .map(|u| LookupKey::new(u, MAX_SEQUENCE_NUMBER));
+        match upper {
+            // The entry before the first one at or after the upper bound.
+            Some(u) => {
+                self.iter.seek(u.internal_key());
+                if self.iter.valid() {
+                    self.iter.prev();
+                } else {
+                    self.iter.seek_to_last();
+                }
+            }
+            None => self.iter.seek_to_last(),
+        }
+        // Step over the entries after the prefix, if the iterator has one.
+        while self.iter.valid() {
+            self.iter.current(&mut self.keybuf, &mut self.valbuf);
+            let ukey = parse_internal_key(&self.keybuf).2;
+            if self.in_bounds(ukey)
+                || self
+                    .start()
+                    .is_some_and(|s| self.cmp.cmp(ukey, s) == Ordering::Less)
+            {
+                break;
+            }
+            self.iter.prev();
+        }
+        if !self.iter.valid() {
+            self.valid = false;
+            self.dir = Direction::Forward;
+            return;
+        }
+        self.dir = Direction::Reverse;
+        self.find_prev_user_entry();
+        self.check_bounds();
+    }
+
+    /// finish_op records statistics about the operation that has just finished.
+    fn finish_op(&mut self, op: Operation, start: Option<Instant>) {
+        if let (Some(stats), Some(start)) = (self.stats.as_ref(), start) {
+            let mut stats = stats.borrow_mut();
+            stats.record_since(op, start);
+            stats.record_skipped_internal_keys(self.skipped as u64);
+        }
+        self.total_skipped += self.skipped as u64;
+        self.skipped = 0;
+    }
+}
+
+impl LdbIterator for DBIterator {
+    fn advance(&mut self) -> bool {
+        let start = self.stats.as_ref().map(|_| Instant::now());
+        self.advance_internal();
+        self.check_bounds();
+        self.finish_op(Operation::Next, start);
+        self.valid()
+    }
     fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
         if !self.valid() {
             return false;
@@ -237,28 +564,33 @@ impl LdbIterator for DBIterator {
             }
             self.dir = Direction::Reverse;
         }
-        self.find_prev_user_entry()
+        self.find_prev_user_entry();
+        self.check_bounds();
+        self.valid()
     }
     fn valid(&self) -> bool {
         self.valid
     }
     fn seek(&mut self, to: &[u8]) {
-        self.dir = Direction::Forward;
-        self.savedkey.clear();
-        self.savedval.clear();
-        self.savedkey
-            .extend_from_slice(LookupKey::new(to, self.ss.sequence()).internal_key());
-        self.iter.seek(&self.savedkey);
-        if self.iter.valid() {
-            self.find_next_user_entry(
-                // skipping=
-                false,
-            );
-        } else {
-            self.valid = false;
+        let start = self.stats.as_ref().map(|_| Instant::now());
+        match self.start() {
+            Some(first) if self.cmp.cmp(to, first) == Ordering::Less => {
+                let first = first.to_vec();
+                self.seek_internal(&first);
+            }
+            _ => self.seek_internal(to),
         }
+        self.check_bounds();
+        self.finish_op(Operation::Seek, start);
     }
     fn seek_to_first(&mut self) {
+        if let Some(first) = self.start().map(<[u8]>::to_vec) {
+            self.seek_internal(&first);
+            self.check_bounds();
+            return;
+        }
+        self.incomplete = false;
+        self.tombstones = 0;
         self.dir = Direction::Forward;
         self.savedval.clear();
         self.iter.seek_to_first();
@@ -271,8 +603,13 @@ impl LdbIterator for DBIterator {
             self.valid = false;
         }
     }
+    fn seek_to_last(&mut self) {
+        self.seek_to_last_internal();
+    }
     fn reset(&mut self) {
         self.iter.reset();
+        self.incomplete = false;
+        self.tombstones = 0;
         self.valid = false;
         self.savedkey.clear();
         self.savedval.clear();
@@ -412,6 +749,93 @@ mod tests {
         );
     }
 
+    #[test]
+    fn db_iter_seek_for_prev() {
+        let mut db = build_db().0;
+        let mut iter = db.new_iter().unwrap();
+
+        // Exact matches, keys between entries, and the deleted entry gca.
+        let cases: &[(&[u8], &[u8])] = &[
+            (b"aaa", b"aaa"),
+            (b"aab", b"aab"),
+            (b"aac", b"aab"),
+            (b"cab", b"cab"),
+            (b"gca", b"gba"),
+            (b"gcz", b"gba"),
+            (b"gda", b"gda"),
+        ];
+        for (to, want) in cases {
+            assert!(iter.seek_for_prev(to));
+            assert_eq!(want.to_vec(), current_key_val(&iter).unwrap().0);
+        }
+
+        // Before the first entry.
+        assert!(!iter.seek_for_prev(b"a"));
+        assert!(!iter.valid());
+
+        // After the last entry.
+        let last = LdbIteratorIter::wrap(&mut db.new_iter().unwrap())
+            .last()
+            .unwrap();
+        assert!(iter.seek_for_prev(b"zzz"));
+        assert_eq!(last, current_key_val(&iter).unwrap());
+
+        // The iterator can move on in both directions.
+        assert!(iter.seek_for_prev(b"cac"));
+        assert!(iter.advance());
+        assert_eq!(b"cba".to_vec(), current_key_val(&iter).unwrap().0);
+        assert!(iter.seek_for_prev(b"cac"));
+        assert!(iter.prev());
+        assert_eq!(b"bba".to_vec(), current_key_val(&iter).unwrap().0);
+    }
+
+    #[test]
+    fn db_iter_seek_to_last() {
+        let mut db = build_db().0;
+        // A deleted entry after all others.
+        db.put(b"zzz", b"val").unwrap();
+        db.delete(b"zzz").unwrap();
+        let all: Vec<_> = LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).collect();
+        let last_where = |f: &dyn Fn(&[u8]) -> bool| all.iter().rev().find(|(k, _)| f(k)).cloned();
+
+        let mut iter = db.new_iter().unwrap();
+        iter.seek_to_last();
+        assert_eq!(all.last().cloned(), current_key_val(&iter));
+        assert!(iter.prev());
+        assert_eq!(all.get(all.len() - 2).cloned(), current_key_val(&iter));
+        assert!(iter.advance());
+        assert_eq!(all.last().cloned(), current_key_val(&iter));
+        assert!(!iter.advance());
+
+        let mut ropt = ReadOptions::default();
+        ropt.iterate_upper_bound = Some(b"cab".to_vec());
+        let mut iter = db.new_iter_with(&ropt).unwrap();
+        iter.seek_to_last();
+        assert_eq!(
+            last_where(&|k| k < b"cab".as_slice()),
+            current_key_val(&iter)
+        );
+        assert!(iter.seek_for_prev(b"zzz"));
+        assert_eq!(
+            last_where(&|k| k < b"cab".as_slice()),
+            current_key_val(&iter)
+        );
+
+        ropt.iterate_lower_bound = Some(b"zz".to_vec());
+        ropt.iterate_upper_bound = None;
+        let mut iter = db.new_iter_with(&ropt).unwrap();
+        iter.seek_to_last();
+        assert!(!iter.valid());
+
+        // Entries after the prefix are stepped over.
+        let mut iter = db.new_prefix_iter(b"b").unwrap();
+        iter.seek_to_last();
+        assert_eq!(last_where(&|k| k.starts_with(b"b")), current_key_val(&iter));
+        let mut iter = db.new_prefix_iter(b"x").unwrap();
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
     #[test]
     fn db_iter_deleted_entry_not_returned() {
         let mut db = build_db().0;
@@ -487,6 +911,89 @@ mod tests {
         }
     }
 
+    #[test]
+    fn db_iter_refresh() {
+        let mut db = build_db().0;
+        let mut iter = db.new_iter().unwrap();
+        let ss = db.get_snapshot();
+        let mut iter_at = db.new_iter_at(ss).unwrap();
+        assert!(iter.advance());
+        assert!(iter.advance());
+        assert!(iter_at.advance());
+        assert_eq!(b"aab".to_vec(), current_key_val(&iter).unwrap().0);
+
+        db.put(b"aab", b"new").unwrap();
+        db.put(b"aac", b"new").unwrap();
+        db.compact_range(b"aaa", b"zzz").unwrap();
+
+        iter.refresh(&mut db).unwrap();
+        assert_eq!(
+            (b"aab".to_vec(), b"new".to_vec()),
+            current_key_val(&iter).unwrap()
+        );
+        assert!(iter.advance());
+        assert_eq!(b"aac".to_vec(), current_key_val(&iter).unwrap().0);
+
+        // The iterator with an explicit snapshot keeps seeing the old state.
+        iter_at.refresh(&mut db).unwrap();
+        assert_eq!(b"aaa".to_vec(), current_key_val(&iter_at).unwrap().0);
+        assert!(iter_at.advance());
+        assert_eq!(
+            (b"aab".to_vec(), b"val2".to_vec()),
+            current_key_val(&iter_at).unwrap()
+        );
+        assert!(iter_at.advance());
+        assert_eq!(b"aax".to_vec(), current_key_val(&iter_at).unwrap().0);
+
+        // An invalid iterator stays invalid.
+        iter.reset();
+        iter.refresh(&mut db).unwrap();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn db_iter_max_skipped_tombstones() {
+        let mut opt = options::for_test();
+        opt.statistics = true;
+        let mut db = DB::open("db", opt).unwrap();
+        for i in 0..100 {
+            db.put(format!("key{:03}", i).as_bytes(), b"val").unwrap();
+        }
+        for i in 20..70 {
+            db.delete(format!("key{:03}", i).as_bytes()).unwrap();
+        }
+
+        let mut iter = db.new_iter().unwrap();
+        iter.set_max_skipped_tombstones(10);
+        let mut keys = vec![];
+        let mut stops = 0;
+        loop {
+            if iter.advance() {
+                keys.push(current_key_val(&iter).unwrap().0);
+            } else if iter.status().is_err() {
+                assert_eq!(StatusCode::Incomplete, iter.status().err().unwrap().code);
+                stops += 1;
+            } else {
+                break;
+            }
+        }
+        assert_eq!(5, stops);
+        assert!(iter.status().is_ok());
+        assert_eq!(50, keys.len());
+        assert_eq!(b"key019".to_vec(), keys[19]);
+        assert_eq!(b"key070".to_vec(), keys[20]);
+        // Each deleted key has a deletion marker and an older value.
+        assert_eq!(100, iter.skipped_internal_keys());
+        let stats = db.statistics().unwrap();
+        assert_eq!(100, stats.skipped_internal_keys().sum());
+
+        // Without limit, the range is skipped in one step.
+        let mut iter = db.new_iter().unwrap();
+        iter.seek(b"key020");
+        assert!(iter.valid());
+        assert_eq!(b"key070".to_vec(), current_key_val(&iter).unwrap().0);
+    }
+
     #[test]
     fn db_iter_allow_empty_key() {
         let opt = options::for_test();
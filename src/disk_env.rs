@@ -1,4 +1,4 @@
-use crate::env::{path_to_str, Env, FileLock, Logger, RandomAccess};
+use crate::env::{path_to_str, Env, FileLock, IoPriority, Logger, RandomAccess, WritableFile};
 use crate::env_common::{micros, sleep_for};
 use crate::error::{err, Result, Status, StatusCode};
 use fs2::FileExt;
@@ -32,6 +32,124 @@ fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
     s
 }
 
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn ReplaceFileW(
+        replaced: *const u16,
+        replacement: *const u16,
+        backup: *const u16,
+        flags: u32,
+        exclude: *mut std::ffi::c_void,
+        reserved: *mut std::ffi::c_void,
+    ) -> i32;
+}
+
+/// replace_file replaces the existing file `new` with `old`. ReplaceFileW is the way to update a
+/// file in place recommended by Windows; unlike MoveFileEx, it keeps the attributes of `new`.
+#[cfg(windows)]
+fn replace_file(old: &Path, new: &Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let wide = |p: &Path| -> Vec<u16> { p.as_os_str().encode_wide().chain(Some(0)).collect() };
+    let (old, new) = (wide(old), wide(new));
+    let ok = unsafe {
+        ReplaceFileW(
+            new.as_ptr(),
+            old.as_ptr(),
+            std::ptr::null(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// LowPriorityFile is a file written by background work. Once it is closed, its contents are
+/// dropped from the page cache, as they are unlikely to be read again soon. Pages that haven't
+/// been written back yet stay cached; callers sync the file themselves.
+struct LowPriorityFile(File);
+
+impl Write for LowPriorityFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Drop for LowPriorityFile {
+    fn drop(&mut self) {
+        drop_from_page_cache(&self.0);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn drop_from_page_cache(f: &File) {
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        libc::posix_fadvise(f.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn drop_from_page_cache(_: &File) {}
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+#[cfg(target_os = "linux")]
+thread_local! {
+    // The I/O priority of the thread before it was lowered.
+    static PREVIOUS_IO_PRIORITY: std::cell::Cell<Option<libc::c_long>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// thread_io_priority returns the I/O priority of the calling thread (who=0).
+#[cfg(target_os = "linux")]
+fn thread_io_priority() -> Option<libc::c_long> {
+    let ioprio = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0) };
+    if ioprio < 0 {
+        None
+    } else {
+        Some(ioprio)
+    }
+}
+
+/// set_thread_io_priority lowers the I/O priority of the calling thread, or restores the one it
+/// had before.
+#[cfg(target_os = "linux")]
+fn set_thread_io_priority(prio: IoPriority) {
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    const IOPRIO_CLASS_BE: libc::c_long = 2;
+    let ioprio = match prio {
+        IoPriority::Normal => match PREVIOUS_IO_PRIORITY.with(|p| p.take()) {
+            Some(ioprio) => ioprio,
+            None => return,
+        },
+        IoPriority::Low => {
+            if PREVIOUS_IO_PRIORITY.with(|p| p.get()).is_none() {
+                PREVIOUS_IO_PRIORITY.with(|p| p.set(thread_io_priority()));
+            }
+            // Lowest priority of the best-effort class.
+            IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | 7
+        }
+    };
+    // Who=0 refers to the calling thread.
+    unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_thread_io_priority(_: IoPriority) {}
+
 // Note: We're using Ok(f()?) in several locations below in order to benefit from the automatic
 // error conversion using std::convert::From.
 impl Env for PosixDiskEnv {
@@ -63,6 +181,23 @@ impl Env for PosixDiskEnv {
                 .map_err(|e| map_err_with_name("open (write)", p, e))?,
         ))
     }
+    fn open_writable_file_with_priority(
+        &self,
+        p: &Path,
+        prio: IoPriority,
+    ) -> Result<Box<dyn Write>> {
+        match prio {
+            IoPriority::Normal => self.open_writable_file(p),
+            IoPriority::Low => Ok(Box::new(LowPriorityFile(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(p)
+                    .map_err(|e| map_err_with_name("open (write)", p, e))?,
+            ))),
+        }
+    }
     fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
         Ok(Box::new(
             fs::OpenOptions::new()
@@ -73,6 +208,17 @@ impl Env for PosixDiskEnv {
                 .map_err(|e| map_err_with_name("open (append)", p, e))?,
         ))
     }
+    fn open_syncable_file(&self, p: &Path, append: bool) -> Result<Box<dyn WritableFile>> {
+        Ok(Box::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(p)
+                .map_err(|e| map_err_with_name("open (syncable)", p, e))?,
+        ))
+    }
 
     fn exists(&self, p: &Path) -> Result<bool> {
         Ok(p.exists())
@@ -105,8 +251,41 @@ impl Env for PosixDiskEnv {
         Ok(fs::remove_dir_all(p).map_err(|e| map_err_with_name("rmdir", p, e))?)
     }
     fn rename(&self, old: &Path, new: &Path) -> Result<()> {
+        #[cfg(windows)]
+        {
+            if new.exists() {
+                return replace_file(old, new).map_err(|e| map_err_with_name("rename", old, e));
+            }
+        }
         Ok(fs::rename(old, new).map_err(|e| map_err_with_name("rename", old, e))?)
     }
+    fn link(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::hard_link(from, to).map_err(|e| map_err_with_name("link", from, e))
+    }
+    fn sync_file(&self, p: &Path) -> Result<()> {
+        // Windows requires write access for flushing a file.
+        let f = fs::OpenOptions::new()
+            .write(true)
+            .open(p)
+            .map_err(|e| map_err_with_name("sync_file", p, e))?;
+        f.sync_all()
+            .map_err(|e| map_err_with_name("sync_file", p, e))
+    }
+    fn sync_file_data(&self, p: &Path) -> Result<()> {
+        let f = fs::OpenOptions::new()
+            .write(true)
+            .open(p)
+            .map_err(|e| map_err_with_name("sync_file_data", p, e))?;
+        f.sync_data()
+            .map_err(|e| map_err_with_name("sync_file_data", p, e))
+    }
+    #[cfg(unix)]
+    fn sync_dir(&self, p: &Path) -> Result<()> {
+        let d = File::open(p).map_err(|e| map_err_with_name("sync_dir", p, e))?;
+        d.sync_all()
+            .map_err(|e| map_err_with_name("sync_dir", p, e))
+    }
+    // On Windows, directories can't be synced; NTFS journals changes to them.
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         let mut locks = self.locks.lock().unwrap();
@@ -171,6 +350,10 @@ impl Env for PosixDiskEnv {
     fn sleep_for(&self, micros: u32) {
         sleep_for(micros);
     }
+
+    fn set_io_priority(&self, prio: IoPriority) {
+        set_thread_io_priority(prio);
+    }
 }
 
 #[cfg(test)]
@@ -211,6 +394,12 @@ mod tests {
             assert!(!env.exists(name).unwrap());
             // rename back so that the remaining tests can use the file.
             assert!(env.rename(newname, name).is_ok());
+
+            // link
+            assert!(env.link(name, newname).is_ok());
+            assert_eq!(6, env.size_of(newname).unwrap());
+            assert!(env.link(name, newname).is_err());
+            assert!(env.delete(newname).is_ok());
         }
 
         assert!(env.open_sequential_file(name).is_ok());
@@ -219,6 +408,40 @@ mod tests {
         assert!(env.delete(name).is_ok());
     }
 
+    #[test]
+    fn test_low_priority_io() {
+        let name = Path::new("testfile.lowprio");
+        let env = PosixDiskEnv::new();
+        // A priority of the best-effort class other than the default one.
+        #[cfg(target_os = "linux")]
+        let ioprio = {
+            let ioprio: libc::c_long = 2 << 13 | 3;
+            unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+            Some(ioprio)
+        };
+
+        env.set_io_priority(IoPriority::Low);
+        env.set_io_priority(IoPriority::Low);
+        {
+            let mut f = env
+                .open_writable_file_with_priority(name, IoPriority::Low)
+                .unwrap();
+            f.write_all("123xyz".as_bytes()).unwrap();
+        }
+        env.set_io_priority(IoPriority::Normal);
+        // The previous priority is restored, not the default one.
+        #[cfg(target_os = "linux")]
+        assert_eq!(ioprio, thread_io_priority());
+
+        let mut contents = String::new();
+        env.open_sequential_file(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!("123xyz", contents);
+        assert!(env.delete(name).is_ok());
+    }
+
     #[test]
     fn test_locking() {
         let env = PosixDiskEnv::new();
@@ -1,7 +1,7 @@
 //! An `env` is an abstraction layer that allows the database to run both on different platforms as
 //! well as persisting data on disk or in memory.
 
-use crate::error::Result;
+use crate::error::{err, Result, StatusCode};
 
 use std::fs::File;
 use std::io::prelude::*;
@@ -10,6 +10,7 @@ use std::os::unix::fs::FileExt;
 #[cfg(windows)]
 use std::os::windows::fs::FileExt;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 pub trait RandomAccess {
     fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize>;
@@ -29,15 +30,116 @@ impl RandomAccess for File {
     }
 }
 
+/// RandomAccessReader reads a random access file sequentially from some offset on, without
+/// reading what comes before it like a sequential file would have to.
+pub(crate) struct RandomAccessReader {
+    file: Box<dyn RandomAccess>,
+    off: usize,
+}
+
+impl RandomAccessReader {
+    pub(crate) fn new(file: Box<dyn RandomAccess>, off: usize) -> RandomAccessReader {
+        RandomAccessReader { file, off }
+    }
+}
+
+impl Read for RandomAccessReader {
+    fn read(&mut self, dst: &mut [u8]) -> std::io::Result<usize> {
+        let n = self
+            .file
+            .read_at(self.off, dst)
+            .map_err(std::io::Error::other)?;
+        self.off += n;
+        Ok(n)
+    }
+}
+
+/// WritableFile is a file opened for writing that can be synced through its handle, instead of
+/// opening it again by name like `Env::sync_file()` does.
+pub trait WritableFile: Write {
+    /// sync makes sure that everything written to the file so far is stored durably.
+    fn sync(&mut self) -> Result<()>;
+}
+
+impl WritableFile for File {
+    fn sync(&mut self) -> Result<()> {
+        Ok(self.sync_all()?)
+    }
+}
+
+/// open_syncable_file opens a file with `Env::open_syncable_file()` or, if the Env doesn't
+/// support that, opens it normally and syncs it by name.
+pub(crate) fn open_syncable_file(
+    env: &Rc<Box<dyn Env>>,
+    p: &Path,
+    append: bool,
+) -> Result<Box<dyn WritableFile>> {
+    match env.open_syncable_file(p, append) {
+        Err(e) if e.code == StatusCode::NotSupported => {}
+        r => return r,
+    }
+    let file = if append {
+        env.open_appendable_file(p)?
+    } else {
+        env.open_writable_file(p)?
+    };
+    Ok(Box::new(SyncedByName {
+        file,
+        env: env.clone(),
+        path: p.to_owned(),
+    }))
+}
+
+struct SyncedByName {
+    file: Box<dyn Write>,
+    env: Rc<Box<dyn Env>>,
+    path: PathBuf,
+}
+
+impl Write for SyncedByName {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WritableFile for SyncedByName {
+    fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.env.sync_file(&self.path)
+    }
+}
+
 pub struct FileLock {
     pub id: String,
 }
 
+/// IoPriority is a hint about the urgency of I/O. Envs are free to ignore it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoPriority {
+    Normal,
+    /// Background work, like compactions, that should interfere as little as possible with other
+    /// I/O.
+    Low,
+}
+
 pub trait Env {
     fn open_sequential_file(&self, _: &Path) -> Result<Box<dyn Read>>;
     fn open_random_access_file(&self, _: &Path) -> Result<Box<dyn RandomAccess>>;
     fn open_writable_file(&self, _: &Path) -> Result<Box<dyn Write>>;
+    /// Like `open_writable_file()`, with a priority hint for the written data.
+    fn open_writable_file_with_priority(&self, p: &Path, _: IoPriority) -> Result<Box<dyn Write>> {
+        self.open_writable_file(p)
+    }
     fn open_appendable_file(&self, _: &Path) -> Result<Box<dyn Write>>;
+    /// Like `open_writable_file()`, or `open_appendable_file()` if append is set, but the file
+    /// can be synced through the returned handle. Envs that can't do this return
+    /// `StatusCode::NotSupported`, and the file is synced with `sync_file()` instead.
+    fn open_syncable_file(&self, _: &Path, _append: bool) -> Result<Box<dyn WritableFile>> {
+        err(StatusCode::NotSupported, "syncable files are not supported")
+    }
 
     fn exists(&self, _: &Path) -> Result<bool>;
     fn children(&self, _: &Path) -> Result<Vec<PathBuf>>;
@@ -46,7 +148,29 @@ pub trait Env {
     fn delete(&self, _: &Path) -> Result<()>;
     fn mkdir(&self, _: &Path) -> Result<()>;
     fn rmdir(&self, _: &Path) -> Result<()>;
+    /// Renames a file, replacing the destination if it exists. The replacement should be atomic:
+    /// after a crash, the destination refers either to the old or to the new file.
     fn rename(&self, _: &Path, _: &Path) -> Result<()>;
+    /// Creates a hard link to a file, i.e. a second name for the same contents. The destination
+    /// must not exist. Envs that can't link files return `StatusCode::NotSupported`.
+    fn link(&self, _: &Path, _: &Path) -> Result<()> {
+        err(StatusCode::NotSupported, "hard links are not supported")
+    }
+    /// Makes sure that the contents of a (closed) file are stored durably. Envs without
+    /// persistent storage don't need to implement this.
+    fn sync_file(&self, _: &Path) -> Result<()> {
+        Ok(())
+    }
+    /// Like `sync_file()`, but metadata (like the modification time) that isn't needed for
+    /// reading the file may remain unsynced.
+    fn sync_file_data(&self, p: &Path) -> Result<()> {
+        self.sync_file(p)
+    }
+    /// Makes sure that changes to a directory's entries, like created or renamed files, are
+    /// stored durably.
+    fn sync_dir(&self, _: &Path) -> Result<()> {
+        Ok(())
+    }
 
     fn lock(&self, _: &Path) -> Result<FileLock>;
     fn unlock(&self, l: FileLock) -> Result<()>;
@@ -55,6 +179,9 @@ pub trait Env {
 
     fn micros(&self) -> u64;
     fn sleep_for(&self, micros: u32);
+
+    /// Sets the I/O priority of the current thread.
+    fn set_io_priority(&self, _: IoPriority) {}
 }
 
 pub struct Logger {
@@ -1,5 +1,11 @@
 use std::thread;
+// std has no clock on WebAssembly.
+#[cfg(not(feature = "wasm"))]
 use std::time;
+#[cfg(feature = "wasm")]
+use web_time as time;
+
+pub use time::Instant;
 
 pub fn micros() -> u64 {
     loop {
@@ -27,6 +27,12 @@ pub enum StatusCode {
     NotSupported,
     PermissionDenied,
     AsyncError,
+    /// The operation could not be carried out without waiting, e.g. because a queue is full or a
+    /// write would stall on a memtable compaction.
+    WouldBlock,
+    /// The operation was stopped before completion, e.g. because an iterator skipped too many
+    /// deleted entries.
+    Incomplete,
     Unknown,
     #[cfg(feature = "fs")]
     Errno(errno::Errno),
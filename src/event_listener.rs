@@ -0,0 +1,65 @@
+//! Notifications about the database's background work: memtable flushes, compactions, and the
+//! table files they create and delete. An `EventListener` (see `Options::event_listener`) can
+//! use them e.g. for metrics, or to schedule other work around compactions.
+
+use crate::types::FileNum;
+
+/// FlushInfo describes a memtable flush, which wrote the memtable to a table file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlushInfo {
+    pub file_num: FileNum,
+    /// The level that the table file was added to.
+    pub level: usize,
+    /// The size of the table file in bytes.
+    pub size: usize,
+    pub num_entries: u64,
+    /// How long writing the table file took.
+    pub micros: u64,
+}
+
+/// CompactionInfo describes a compaction of files at `level` and `level + 1` into new files at
+/// `level + 1`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompactionInfo {
+    pub level: usize,
+    pub input_files: Vec<FileNum>,
+    pub output_files: Vec<FileNum>,
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+    /// How long the compaction took.
+    pub micros: u64,
+    /// Whether a single file was moved to the next level, without reading or writing it.
+    pub trivial_move: bool,
+}
+
+/// Why a table file was created.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableFileReason {
+    /// A memtable flush, including the flushes of recovered log files when opening the database.
+    Flush,
+    Compaction,
+    /// `DB::bulk_load()` or `DB::ingest_external_file()`.
+    Import,
+}
+
+/// TableFileInfo describes a table file that was added to or deleted from the database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableFileInfo {
+    pub file_num: FileNum,
+    /// The size of the file in bytes.
+    pub size: usize,
+}
+
+/// EventListener is notified after flushes and compactions have completed, and when table files
+/// are added to the database or deleted. A table file is reported as created once it is part
+/// of the database, and as deleted once it was removed from the file system. All methods do
+/// nothing by default.
+///
+/// The methods are called from within the database's operations, which must not be used from
+/// them.
+pub trait EventListener {
+    fn on_flush_completed(&self, _info: &FlushInfo) {}
+    fn on_compaction_completed(&self, _info: &CompactionInfo) {}
+    fn on_table_file_created(&self, _info: &TableFileInfo, _reason: TableFileReason) {}
+    fn on_table_file_deleted(&self, _info: &TableFileInfo) {}
+}
@@ -178,6 +178,45 @@ impl FilterPolicy for BloomPolicy {
     }
 }
 
+/// A PrefixExtractor maps keys to prefixes. If one is set in the options, filters can contain the
+/// prefixes of keys (see `Options::prefix_filtering`), which allows finding out whether a table
+/// contains any key with a given prefix.
+pub trait PrefixExtractor {
+    /// Returns a string identifying this extractor, including its parameters. Prefix filters of
+    /// tables written with a differently named extractor are ignored.
+    fn name(&self) -> String;
+    /// Returns the prefix of key, or None if the key has no prefix; such keys are only filtered
+    /// as whole keys. Keys with the same prefix must be ordered contiguously by the comparator.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+/// FixedPrefix uses the first n bytes of a key as prefix. Shorter keys have no prefix.
+#[derive(Clone)]
+pub struct FixedPrefix(pub usize);
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> String {
+        format!("rusty-leveldb.FixedPrefix.{}", self.0)
+    }
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        if key.len() >= self.0 {
+            Some(&key[..self.0])
+        } else {
+            None
+        }
+    }
+}
+
+/// prefix_filter_key returns the key under which a prefix is stored in a filter. For tables of
+/// internal keys, it has an (empty) tag in order to pass through InternalFilterPolicy.
+pub fn prefix_filter_key(prefix: &[u8], internal_keys: bool) -> Vec<u8> {
+    let mut k = prefix.to_vec();
+    if internal_keys {
+        k.extend_from_slice(&[0; 8]);
+    }
+    k
+}
+
 /// A filter policy wrapping another policy; extracting the user key from internal keys for all
 /// operations.
 /// A User Key is u8*.
@@ -5,8 +5,11 @@ use std::rc::Rc;
 
 use integer_encoding::FixedInt;
 
-const FILTER_BASE_LOG2: u32 = 11;
+pub const FILTER_BASE_LOG2: u32 = 11;
 const FILTER_BASE: u32 = 1 << FILTER_BASE_LOG2; // 2 KiB
+/// The range that `Options::filter_base_lg2` is clamped to.
+const MIN_FILTER_BASE_LOG2: u32 = 8;
+const MAX_FILTER_BASE_LOG2: u32 = 30;
 
 /// For a given byte offset, returns the index of the filter that includes the key at that offset.
 #[inline]
@@ -24,6 +27,7 @@ fn get_filter_index(offset: usize, base_lg2: u32) -> u32 {
 /// Two consecutive filter offsets may be the same.
 pub struct FilterBlockBuilder {
     policy: BoxedFilterPolicy,
+    base_lg2: u32,
     // filters, concatenated
     filters: Vec<u8>,
     filter_offsets: Vec<usize>,
@@ -35,8 +39,15 @@ pub struct FilterBlockBuilder {
 
 impl FilterBlockBuilder {
     pub fn new(fp: BoxedFilterPolicy) -> FilterBlockBuilder {
+        FilterBlockBuilder::new_with_base_lg2(fp, FILTER_BASE_LOG2)
+    }
+
+    /// new_with_base_lg2 returns a builder that generates one filter per `2^base_lg2` bytes of
+    /// table data. Readers take the granularity from the filter block.
+    pub fn new_with_base_lg2(fp: BoxedFilterPolicy, base_lg2: u32) -> FilterBlockBuilder {
         FilterBlockBuilder {
             policy: fp,
+            base_lg2: base_lg2.clamp(MIN_FILTER_BASE_LOG2, MAX_FILTER_BASE_LOG2),
             // some pre-allocation
             filters: Vec::with_capacity(1024),
             filter_offsets: Vec::with_capacity(1024),
@@ -59,7 +70,7 @@ impl FilterBlockBuilder {
     }
 
     pub fn start_block(&mut self, offset: usize) {
-        let filter_ix = get_filter_index(offset, FILTER_BASE_LOG2);
+        let filter_ix = get_filter_index(offset, self.base_lg2);
         assert!(filter_ix >= self.filter_offsets.len() as u32);
 
         while filter_ix > self.filter_offsets.len() as u32 {
@@ -98,7 +109,7 @@ impl FilterBlockBuilder {
 
         (offsets_offset as u32).encode_fixed(&mut result[ix..ix + 4]);
         ix += 4;
-        result[ix] = FILTER_BASE_LOG2 as u8;
+        result[ix] = self.base_lg2 as u8;
 
         result
     }
@@ -132,6 +143,11 @@ impl FilterBlockReader {
         }
     }
 
+    /// size returns the size of the filter block in bytes.
+    pub fn size(&self) -> usize {
+        self.block.len()
+    }
+
     /// Returns number of filters
     pub fn num(&self) -> u32 {
         ((self.block.len() - self.offsets_offset - 5) / 4) as u32
@@ -219,6 +235,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_filter_block_base_lg2() {
+        let keys = get_keys();
+        let mut bld =
+            FilterBlockBuilder::new_with_base_lg2(Rc::new(Box::new(BloomPolicy::new(32))), 9);
+        for (i, k) in keys.iter().enumerate() {
+            bld.start_block(i * 600);
+            bld.add_key(k);
+        }
+        let reader =
+            FilterBlockReader::new_owned(Rc::new(Box::new(BloomPolicy::new(32))), bld.finish());
+        assert_eq!(9, reader.filter_base_lg2);
+        // Each block got a filter of its own.
+        for (i, k) in keys.iter().enumerate() {
+            for j in 0..keys.len() {
+                assert_eq!(i == j, reader.key_may_match(j * 600, k));
+            }
+        }
+
+        let bld = FilterBlockBuilder::new_with_base_lg2(Rc::new(Box::new(BloomPolicy::new(32))), 0);
+        assert_eq!(MIN_FILTER_BASE_LOG2, bld.base_lg2);
+    }
+
     #[test]
     fn test_filter_block_build_read() {
         let result = produce_filter_block();
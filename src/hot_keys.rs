@@ -0,0 +1,167 @@
+//! Sampling of read keys, in order to find skew in the workload. See
+//! `Options::hot_key_sample_rate` and `DB::hot_keys()`.
+
+use crate::filter::PrefixExtractor;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use rand::Rng;
+
+/// The number of keys (and prefixes) tracked. Keys outside of the most frequently
+/// sampled ones are eventually forgotten.
+pub(crate) const HOT_KEY_CAPACITY: usize = 128;
+
+/// HotKey is a frequently read key or prefix, as reported by `DB::hot_keys()` and
+/// `DB::hot_prefixes()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotKey {
+    pub key: Vec<u8>,
+    /// The estimated number of reads: the number of samples, times the sample rate.
+    pub reads: u64,
+    /// By how much `reads` may overestimate the true number, as the key may have replaced a less
+    /// frequent one in the tracked set.
+    pub error: u64,
+}
+
+/// TopK counts samples with the Space-Saving algorithm: It tracks a fixed number of keys; a new
+/// key replaces the least frequent one, inheriting its count as error.
+struct TopK {
+    counts: HashMap<Vec<u8>, (u64, u64)>,
+}
+
+impl TopK {
+    fn new() -> TopK {
+        TopK {
+            counts: HashMap::with_capacity(HOT_KEY_CAPACITY),
+        }
+    }
+
+    fn add(&mut self, key: &[u8]) {
+        if let Some((count, _)) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        let mut error = 0;
+        if self.counts.len() >= HOT_KEY_CAPACITY {
+            let (min_key, (min, _)) = self
+                .counts
+                .iter()
+                .min_by_key(|(_, (count, _))| *count)
+                .map(|(k, c)| (k.clone(), *c))
+                .unwrap();
+            self.counts.remove(&min_key);
+            error = min;
+        }
+        self.counts.insert(key.to_vec(), (error + 1, error));
+    }
+
+    fn top(&self, n: usize, rate: u64) -> Vec<HotKey> {
+        let mut keys: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(k, (count, error))| HotKey {
+                key: k.clone(),
+                reads: count * rate,
+                error: error * rate,
+            })
+            .collect();
+        keys.sort_by(|a, b| b.reads.cmp(&a.reads).then_with(|| a.key.cmp(&b.key)));
+        keys.truncate(n);
+        keys
+    }
+}
+
+/// HotKeySampler samples one in `rate` of the keys passed to `sample()`, at random.
+pub(crate) struct HotKeySampler {
+    rate: usize,
+    prefix_extractor: Option<Rc<Box<dyn PrefixExtractor>>>,
+    keys: TopK,
+    prefixes: TopK,
+}
+
+impl HotKeySampler {
+    pub fn new(
+        rate: usize,
+        prefix_extractor: Option<Rc<Box<dyn PrefixExtractor>>>,
+    ) -> HotKeySampler {
+        HotKeySampler {
+            rate: rate.max(1),
+            prefix_extractor,
+            keys: TopK::new(),
+            prefixes: TopK::new(),
+        }
+    }
+
+    pub fn sample(&mut self, key: &[u8]) {
+        if self.rate > 1 && rand::thread_rng().gen_range(0..self.rate) != 0 {
+            return;
+        }
+        self.keys.add(key);
+        if let Some(prefix) = self.prefix_extractor.as_ref().and_then(|p| p.prefix(key)) {
+            self.prefixes.add(prefix);
+        }
+    }
+
+    pub fn hot_keys(&self, n: usize) -> Vec<HotKey> {
+        self.keys.top(n, self.rate as u64)
+    }
+
+    pub fn hot_prefixes(&self, n: usize) -> Vec<HotKey> {
+        self.prefixes.top(n, self.rate as u64)
+    }
+
+    pub fn reset(&mut self) {
+        self.keys = TopK::new();
+        self.prefixes = TopK::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FixedPrefix;
+
+    #[test]
+    fn test_hot_key_sampler() {
+        let mut s = HotKeySampler::new(1, Some(Rc::new(Box::new(FixedPrefix(2)))));
+        for i in 0..1000 {
+            s.sample(b"aa-hot");
+            if i % 2 == 0 {
+                s.sample(b"bb-warm");
+            }
+            s.sample(format!("cc-{}", i).as_bytes());
+        }
+
+        let hot = s.hot_keys(2);
+        assert_eq!(b"aa-hot".to_vec(), hot[0].key);
+        assert_eq!(b"bb-warm".to_vec(), hot[1].key);
+        assert!(hot[0].reads - hot[0].error <= 1000 && hot[0].reads >= 1000);
+        assert!(hot[1].reads - hot[1].error <= 500 && hot[1].reads >= 500);
+        assert!(s.hot_keys(1000).len() <= HOT_KEY_CAPACITY);
+
+        let prefixes = s.hot_prefixes(3);
+        assert_eq!(
+            vec![
+                (b"aa".to_vec(), 1000),
+                (b"cc".to_vec(), 1000),
+                (b"bb".to_vec(), 500)
+            ],
+            prefixes
+                .into_iter()
+                .map(|p| (p.key, p.reads))
+                .collect::<Vec<_>>()
+        );
+
+        s.reset();
+        assert!(s.hot_keys(10).is_empty());
+
+        let mut s = HotKeySampler::new(10, None);
+        for _ in 0..10000 {
+            s.sample(b"key");
+        }
+        let reads = s.hot_keys(1)[0].reads;
+        assert!(reads > 5000 && reads < 15000, "{}", reads);
+        assert!(s.hot_prefixes(1).is_empty());
+    }
+}
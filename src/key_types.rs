@@ -13,9 +13,13 @@ use integer_encoding::{FixedInt, FixedIntWriter, VarInt, VarIntWriter};
 // of boilerplate, but increases type safety.
 
 #[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum ValueType {
     TypeDeletion = 0,
     TypeValue = 1,
+    /// Only used in write batches (see `WriteBatch::delete_range()`); range tombstones aren't
+    /// stored as internal keys.
+    TypeRangeDeletion = 2,
 }
 
 /// A MemtableKey consists of the following elements: [keylen, key, tag, (vallen, value)] where
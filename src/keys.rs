@@ -0,0 +1,426 @@
+//! Order-preserving key encodings.
+//!
+//! LevelDB orders keys by comparing their bytes (when using the `DefaultCmp`). Encoding numbers
+//! in native or little-endian byte order, or concatenating strings of varying length, breaks that
+//! order: `2u32.to_le_bytes()` sorts after `256u32.to_le_bytes()`. The encodings in this module
+//! guarantee that the encoded keys sort like the original values.
+//!
+//! ```
+//! use rusty_leveldb::keys::{self, Reverse};
+//!
+//! assert!(keys::encode(&2u32) < keys::encode(&256u32));
+//! assert!(keys::encode(&-1i64) < keys::encode(&0i64));
+//! assert!(keys::encode(&"a".to_string()) < keys::encode(&"ab".to_string()));
+//! assert!(keys::encode(&Reverse(2u32)) > keys::encode(&Reverse(256u32)));
+//!
+//! let k = keys::encode(&-12345i64);
+//! assert_eq!(-12345i64, keys::decode::<i64>(&k).unwrap());
+//! ```
+
+use crate::error::{err, Result, StatusCode};
+
+use std::convert::TryInto;
+
+/// Escapes a zero byte in a variable-length encoding.
+const ESCAPE: u8 = 0xff;
+/// Terminates a variable-length encoding (after a zero byte).
+const TERMINATOR: u8 = 0x01;
+
+/// KeyPart is implemented by types that have an order-preserving encoding: For values `a < b`,
+/// the encoding of `a` sorts before the encoding of `b`. All encodings are self-delimiting, so
+/// that encoded values can be concatenated.
+pub trait KeyPart: Sized {
+    /// encode_to appends the encoded value to dst.
+    fn encode_to(&self, dst: &mut Vec<u8>);
+    /// decode_from decodes a value from the beginning of src, and returns it together with the
+    /// number of bytes read.
+    fn decode_from(src: &[u8]) -> Result<(Self, usize)>;
+}
+
+/// encode returns the order-preserving encoding of v.
+pub fn encode<T: KeyPart>(v: &T) -> Vec<u8> {
+    let mut dst = vec![];
+    v.encode_to(&mut dst);
+    dst
+}
+
+/// decode decodes a value that was encoded with `encode()`. All of src must be used.
+pub fn decode<T: KeyPart>(src: &[u8]) -> Result<T> {
+    let (v, n) = T::decode_from(src)?;
+    if n != src.len() {
+        return err(StatusCode::InvalidData, "trailing bytes after encoded key");
+    }
+    Ok(v)
+}
+
+fn fixed<const N: usize>(src: &[u8]) -> Result<[u8; N]> {
+    match src.get(..N) {
+        Some(b) => Ok(b.try_into().unwrap()),
+        None => err(StatusCode::InvalidData, "encoded key is too short"),
+    }
+}
+
+/// Unsigned integers are encoded in big-endian byte order.
+impl KeyPart for u32 {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&self.to_be_bytes());
+    }
+    fn decode_from(src: &[u8]) -> Result<(u32, usize)> {
+        Ok((u32::from_be_bytes(fixed(src)?), 4))
+    }
+}
+
+impl KeyPart for u64 {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&self.to_be_bytes());
+    }
+    fn decode_from(src: &[u8]) -> Result<(u64, usize)> {
+        Ok((u64::from_be_bytes(fixed(src)?), 8))
+    }
+}
+
+/// Signed integers are encoded in big-endian byte order with the sign bit flipped, so that
+/// negative numbers sort before positive ones.
+impl KeyPart for i64 {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&((*self as u64) ^ (1 << 63)).to_be_bytes());
+    }
+    fn decode_from(src: &[u8]) -> Result<(i64, usize)> {
+        Ok(((u64::from_be_bytes(fixed(src)?) ^ (1 << 63)) as i64, 8))
+    }
+}
+
+/// Byte strings of variable length are encoded with every zero byte escaped as `00 ff`, and
+/// terminated by `00 01`. This way, a string sorts before all strings it is a prefix of, also if
+/// followed by other encoded values.
+impl KeyPart for Vec<u8> {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        encode_bytes(self, dst)
+    }
+    fn decode_from(src: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let mut v = vec![];
+        let mut i = 0;
+        while i < src.len() {
+            if src[i] != 0 {
+                v.push(src[i]);
+                i += 1;
+                continue;
+            }
+            match src.get(i + 1) {
+                Some(&ESCAPE) => v.push(0),
+                Some(&TERMINATOR) => return Ok((v, i + 2)),
+                _ => return err(StatusCode::InvalidData, "invalid escape in encoded key"),
+            }
+            i += 2;
+        }
+        err(
+            StatusCode::InvalidData,
+            "unterminated string in encoded key",
+        )
+    }
+}
+
+fn encode_bytes(s: &[u8], dst: &mut Vec<u8>) {
+    for &b in s {
+        dst.push(b);
+        if b == 0 {
+            dst.push(ESCAPE);
+        }
+    }
+    dst.push(0);
+    dst.push(TERMINATOR);
+}
+
+/// Strings are encoded like byte strings.
+impl KeyPart for String {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), dst)
+    }
+    fn decode_from(src: &[u8]) -> Result<(String, usize)> {
+        let (v, n) = Vec::<u8>::decode_from(src)?;
+        match String::from_utf8(v) {
+            Ok(s) => Ok((s, n)),
+            Err(_) => err(StatusCode::InvalidData, "invalid UTF-8 in encoded key"),
+        }
+    }
+}
+
+/// FixedBytes is a byte string of a fixed length, which is encoded as-is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedBytes<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> FixedBytes<N> {
+    /// pad creates a FixedBytes from a string of at most N bytes, padded with zero bytes. Note
+    /// that trailing zero bytes of the original string can't be distinguished from padding.
+    pub fn pad(s: &[u8]) -> Result<FixedBytes<N>> {
+        if s.len() > N {
+            return err(
+                StatusCode::InvalidArgument,
+                "string is too long for fixed key",
+            );
+        }
+        let mut b = [0; N];
+        b[..s.len()].copy_from_slice(s);
+        Ok(FixedBytes(b))
+    }
+}
+
+impl<const N: usize> KeyPart for FixedBytes<N> {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        dst.extend_from_slice(&self.0);
+    }
+    fn decode_from(src: &[u8]) -> Result<(FixedBytes<N>, usize)> {
+        Ok((FixedBytes(fixed(src)?), N))
+    }
+}
+
+/// Reverse wraps a value so that it sorts in descending order, e.g. for "newest first"
+/// timestamps. The inner value's encoding is inverted bitwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Reverse<T>(pub T);
+
+impl<T: KeyPart> KeyPart for Reverse<T> {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        let start = dst.len();
+        self.0.encode_to(dst);
+        for b in &mut dst[start..] {
+            *b = !*b;
+        }
+    }
+    fn decode_from(src: &[u8]) -> Result<(Reverse<T>, usize)> {
+        let inverted: Vec<u8> = src.iter().map(|b| !b).collect();
+        let (v, n) = T::decode_from(&inverted)?;
+        Ok((Reverse(v), n))
+    }
+}
+
+/// Tuples are encoded by concatenating the encodings of their components, which orders them
+/// lexicographically.
+macro_rules! tuple_key_part {
+    ($($t:ident),+) => {
+        impl<$($t: KeyPart),+> KeyPart for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn encode_to(&self, dst: &mut Vec<u8>) {
+                let ($(ref $t,)+) = *self;
+                $($t.encode_to(dst);)+
+            }
+            #[allow(non_snake_case)]
+            fn decode_from(src: &[u8]) -> Result<(Self, usize)> {
+                let mut n = 0;
+                $(
+                    let ($t, len) = $t::decode_from(&src[n..])?;
+                    n += len;
+                )+
+                Ok((($($t,)+), n))
+            }
+        }
+    };
+}
+
+tuple_key_part!(A);
+tuple_key_part!(A, B);
+tuple_key_part!(A, B, C);
+tuple_key_part!(A, B, C, D);
+tuple_key_part!(A, B, C, D, E);
+
+/// CompositeKey builds a key from a sequence of components of different types. Keys compare by
+/// their first component, then by their second, and so on; a key built from the first
+/// components of another key is a (byte) prefix of it, and can be used to seek to or scan all
+/// keys starting with these components.
+///
+/// ```
+/// use rusty_leveldb::keys::{CompositeKey, CompositeKeyReader, Reverse};
+///
+/// // (user, newest timestamp first)
+/// let k = CompositeKey::new().push_str("alice").push(&Reverse(1700000000u64));
+/// let prefix = CompositeKey::new().push_str("alice");
+/// assert!(k.as_bytes().starts_with(prefix.as_bytes()));
+///
+/// let mut r = CompositeKeyReader::new(k.as_bytes());
+/// assert_eq!("alice", r.next::<String>().unwrap());
+/// assert_eq!(1700000000, r.next::<Reverse<u64>>().unwrap().0);
+/// assert!(r.is_empty());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CompositeKey {
+    buf: Vec<u8>,
+}
+
+impl CompositeKey {
+    pub fn new() -> CompositeKey {
+        CompositeKey::default()
+    }
+
+    /// push appends a component.
+    pub fn push<T: KeyPart>(mut self, v: &T) -> CompositeKey {
+        v.encode_to(&mut self.buf);
+        self
+    }
+
+    /// push_bytes appends a variable-length byte string component (decoded as `Vec<u8>`).
+    pub fn push_bytes(mut self, s: &[u8]) -> CompositeKey {
+        encode_bytes(s, &mut self.buf);
+        self
+    }
+
+    /// push_str appends a string component (decoded as `String`).
+    pub fn push_str(self, s: &str) -> CompositeKey {
+        self.push_bytes(s.as_bytes())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// CompositeKeyReader decodes the components of a key built by `CompositeKey`, in order.
+pub struct CompositeKeyReader<'a> {
+    src: &'a [u8],
+}
+
+impl<'a> CompositeKeyReader<'a> {
+    pub fn new(src: &'a [u8]) -> CompositeKeyReader<'a> {
+        CompositeKeyReader { src }
+    }
+
+    /// next decodes the next component, which must have been encoded as a T.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next<T: KeyPart>(&mut self) -> Result<T> {
+        let (v, n) = T::decode_from(self.src)?;
+        self.src = &self.src[n..];
+        Ok(v)
+    }
+
+    /// is_empty returns true if all components have been read.
+    pub fn is_empty(&self) -> bool {
+        self.src.is_empty()
+    }
+
+    /// remaining returns the components that have not been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.src
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ordered<T: KeyPart + Clone + PartialEq + std::fmt::Debug>(sorted: &[T]) {
+        for w in sorted.windows(2) {
+            assert!(encode(&w[0]) < encode(&w[1]), "{:?} {:?}", w[0], w[1]);
+        }
+        for v in sorted {
+            assert_eq!(*v, decode::<T>(&encode(v)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_keys_integers() {
+        assert_ordered(&[0u32, 1, 2, 255, 256, 65536, u32::MAX]);
+        assert_ordered(&[0u64, 1, 256, 1 << 40, u64::MAX]);
+        assert_ordered(&[i64::MIN, -65536, -256, -1, 0, 1, 255, 256, i64::MAX]);
+        assert!(decode::<u64>(&[1, 2, 3]).is_err());
+        assert!(decode::<u32>(&[1, 2, 3, 4, 5]).is_err());
+    }
+
+    #[test]
+    fn test_keys_strings() {
+        let sorted: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0],
+            vec![0, 0],
+            vec![0, 1],
+            vec![1],
+            b"a".to_vec(),
+            b"a\x00".to_vec(),
+            b"a\x00b".to_vec(),
+            b"a\x01".to_vec(),
+            b"ab".to_vec(),
+            vec![0xff],
+            vec![0xff, 0xff],
+        ];
+        assert_ordered(&sorted);
+        assert_ordered(&[
+            "".to_string(),
+            "a".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+
+        // Strings are self-delimiting.
+        let mut k = encode(&b"a\x00c".to_vec());
+        7u32.encode_to(&mut k);
+        let (s, n) = Vec::<u8>::decode_from(&k).unwrap();
+        assert_eq!(b"a\x00c".to_vec(), s);
+        assert_eq!(7, decode::<u32>(&k[n..]).unwrap());
+
+        assert!(decode::<Vec<u8>>(b"abc").is_err());
+        assert!(decode::<Vec<u8>>(&[b'a', 0, 2]).is_err());
+        assert!(decode::<String>(&[0xff, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_keys_fixed_and_reverse() {
+        assert_ordered(&[
+            FixedBytes::<3>::pad(b"").unwrap(),
+            FixedBytes::pad(b"a").unwrap(),
+            FixedBytes::pad(b"ab").unwrap(),
+            FixedBytes::pad(b"abc").unwrap(),
+            FixedBytes::pad(b"b").unwrap(),
+        ]);
+        assert!(FixedBytes::<2>::pad(b"abc").is_err());
+
+        assert_ordered(&[Reverse(10u64), Reverse(9), Reverse(0)]);
+        assert_ordered(&[Reverse(1i64), Reverse(0), Reverse(-1)]);
+        assert_ordered(&[
+            Reverse(b"b".to_vec()),
+            Reverse(b"ab".to_vec()),
+            Reverse(b"a".to_vec()),
+            Reverse(vec![]),
+        ]);
+    }
+
+    #[test]
+    fn test_keys_tuples() {
+        // A short first component sorts before a longer one, whatever follows it.
+        assert_ordered(&[
+            (b"a".to_vec(), u32::MAX),
+            (b"a\x00".to_vec(), 0),
+            (b"ab".to_vec(), 0),
+            (b"ab".to_vec(), 1),
+            (b"b".to_vec(), 0),
+        ]);
+        assert_ordered(&[
+            (1u32, -1i64, "x".to_string()),
+            (1, 0, "".to_string()),
+            (1, 0, "a".to_string()),
+            (2, i64::MIN, "".to_string()),
+        ]);
+        assert_ordered(&[(Reverse(2u64), 0u32), (Reverse(1), 5), (Reverse(1), 6)]);
+        assert!(decode::<(u32, u32)>(&encode(&(1u32,))).is_err());
+    }
+
+    #[test]
+    fn test_keys_composite_key() {
+        let k1 = CompositeKey::new().push_str("a").push(&7u32);
+        let k2 = CompositeKey::new().push_str("a\x00").push(&0u32);
+        let k3 = CompositeKey::new().push_bytes(b"ab");
+        assert!(k1 < k2);
+        assert!(k2 < k3);
+        assert!(k1.as_bytes() < k2.as_bytes());
+        assert_eq!(encode(&("a".to_string(), 7u32)), k1.clone().into_bytes());
+
+        let mut r = CompositeKeyReader::new(k1.as_bytes());
+        assert_eq!("a", r.next::<String>().unwrap());
+        assert!(!r.is_empty());
+        assert_eq!(4, r.remaining().len());
+        assert_eq!(7, r.next::<u32>().unwrap());
+        assert!(r.is_empty());
+        assert!(r.next::<u32>().is_err());
+    }
+}
@@ -9,7 +9,7 @@
 //! let mut db = DB::open("mydatabase", opt).unwrap();
 //!
 //! db.put(b"Hello", b"World").unwrap();
-//! assert_eq!(b"World", db.get(b"Hello").unwrap().as_slice());
+//! assert_eq!(b"World", db.get(b"Hello").unwrap().unwrap().as_slice());
 //!
 //! let mut iter = db.new_iter().unwrap();
 //! // Note: For efficiency reasons, it's recommended to use advance() and current() instead of
@@ -31,6 +31,9 @@ extern crate errno;
 #[cfg(feature = "fs")]
 extern crate fs2;
 
+#[cfg(feature = "fs")]
+extern crate libc;
+
 extern crate integer_encoding;
 extern crate rand;
 extern crate snap;
@@ -49,55 +52,116 @@ mod block;
 mod block_builder;
 mod blockhandle;
 mod cache;
+mod cancellation;
 mod cmp;
+mod compaction_filter;
+mod compaction_progress;
+mod compaction_scheduler;
 
 #[cfg(feature = "fs")]
 mod disk_env;
 
 mod env_common;
 mod error;
+mod event_listener;
 mod filter;
 mod filter_block;
+mod hot_keys;
 mod key_types;
 mod log;
 mod mem_env;
 mod memtable;
 mod merging_iter;
+mod metrics;
+mod migrate;
+mod mutex_db;
 mod options;
+mod pinned_slice;
+mod range_del;
+mod rate_limiter;
+mod repair;
+mod rocksdb_table;
+mod secondary_cache;
 mod skipmap;
 mod snapshot;
+mod statistics;
 mod table_block;
 mod table_builder;
 mod table_cache;
+mod table_properties;
 mod table_reader;
 mod test_util;
+mod ttl;
 mod types;
 mod version;
 mod version_edit;
 mod version_set;
 mod write_batch;
+mod write_stall;
 
 mod db_impl;
 mod db_iter;
 
-pub mod env;
+#[cfg(feature = "capi")]
+pub mod capi;
+
+pub mod cache_sim;
 pub mod compressor;
+pub mod env;
+pub mod keys;
+pub mod memory_usage;
+pub mod table;
+
+#[cfg(feature = "testutil")]
+pub mod testutil;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 #[cfg(feature = "async")]
-pub use asyncdb::AsyncDB;
+pub use asyncdb::{
+    AsyncDB, AsyncDbOptions, AsyncMetrics, AsyncSnapshot, AsyncWriteBatch, BlockingSpawner,
+    IteratorRef, Scan, Sleep, SnapshotRef, Subscription, ThreadSpawner, TokioSpawner, WriteEvent,
+};
 
+pub use cancellation::CancellationToken;
 pub use cmp::{Cmp, DefaultCmp};
+pub use compaction_filter::CompactionFilter;
+pub use compaction_progress::CompactionProgress;
+pub use compaction_scheduler::{
+    CompactionDecision, CompactionJob, CompactionReason, CompactionScheduler,
+    DefaultCompactionScheduler,
+};
 pub use compressor::{Compressor, CompressorId};
-pub use db_impl::DB;
+pub use db_impl::{destroy_db, KeyPresence, ReadTxn, DB};
 pub use db_iter::DBIterator;
 
 #[cfg(feature = "fs")]
 pub use disk_env::PosixDiskEnv;
 
 pub use error::{Result, Status, StatusCode};
-pub use filter::{BloomPolicy, FilterPolicy};
+pub use event_listener::{
+    CompactionInfo, EventListener, FlushInfo, TableFileInfo, TableFileReason,
+};
+pub use filter::{BloomPolicy, FilterPolicy, FixedPrefix, PrefixExtractor};
+pub use hot_keys::HotKey;
+pub use log::{verify_wal, WalDamage, WalReport};
 pub use mem_env::MemEnv;
-pub use options::{in_memory, CompressorList, Options};
-pub use skipmap::SkipMap;
+pub use memtable::{MemTable, MemtableIterator};
+pub use migrate::migrate_comparator;
+pub use mutex_db::MutexDB;
+pub use options::{
+    in_memory, CompactionPri, CompressorList, Durability, IngestOptions, Options, OptionsBuilder,
+    OptionsWarning, ReadOptions,
+};
+pub use pinned_slice::PinnedSlice;
+pub use rate_limiter::RateLimiter;
+pub use repair::{repair_db, RepairReport};
+pub use rocksdb_table::{RocksDBEntry, RocksDBEntryType, RocksDBTable, RocksDBTableIter};
+pub use secondary_cache::SecondaryCache;
+pub use skipmap::{CmpRef, SkipMap, SkipMapIter};
+pub use snapshot::{NamedSnapshot, Snapshot, SnapshotInfo};
+pub use statistics::{Histogram, Operation, Statistics};
+pub use ttl::{TtlDB, TtlIterator};
 pub use types::LdbIterator;
 pub use write_batch::WriteBatch;
+pub use write_stall::{WriteStall, WriteStallListener, WriteStallReason};
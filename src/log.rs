@@ -3,9 +3,12 @@
 //! A record is a bytestring: [checksum: uint32, length: uint16, type: uint8, data: [u8]]
 //! checksum is the crc32 sum of type and data; type is one of RecordType::{Full/First/Middle/Last}
 
+use crate::env::WritableFile;
 use crate::error::{err, Result, StatusCode};
+use crate::options::Options;
 
 use std::io::{Read, Write};
+use std::path::Path;
 
 use crc::crc32;
 use crc::Hasher32;
@@ -116,6 +119,14 @@ impl<W: Write> LogWriter<W> {
     }
 }
 
+impl LogWriter<Box<dyn WritableFile>> {
+    /// sync flushes the log and makes sure that its records are stored durably.
+    pub fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.dst.sync()
+    }
+}
+
 pub struct LogReader<R: Read> {
     // TODO: Wrap src in a buffer to enhance read performance.
     src: R,
@@ -124,6 +135,9 @@ pub struct LogReader<R: Read> {
     blocksize: usize,
     head_scratch: [u8; 7],
     checksums: bool,
+    // The offset in the file, and the offset after the last complete record.
+    off: usize,
+    record_end: usize,
 }
 
 impl<R: Read> LogReader<R> {
@@ -135,9 +149,28 @@ impl<R: Read> LogReader<R> {
             checksums: chksum,
             head_scratch: [0; 7],
             digest: crc32::Digest::new(crc32::CASTAGNOLI),
+            off: 0,
+            record_end: 0,
         }
     }
 
+    /// new_with_off opens a reader starting at some offset of an existing log file, which src
+    /// is positioned at. The offset must be at a record boundary, e.g. a previous reader's
+    /// `record_end()`, and the file must have the default block size.
+    pub fn new_with_off(src: R, chksum: bool, off: usize) -> LogReader<R> {
+        let mut r = LogReader::new(src, chksum);
+        r.blk_off = off % BLOCK_SIZE;
+        r.off = off;
+        r.record_end = off;
+        r
+    }
+
+    /// record_end returns the offset in the file after the last record that was read
+    /// completely. A log that is still being written can be read further from there later.
+    pub fn record_end(&self) -> usize {
+        self.record_end
+    }
+
     /// EOF is signalled by Ok(0)
     pub fn read(&mut self, dst: &mut Vec<u8>) -> Result<usize> {
         let mut checksum: u32;
@@ -152,6 +185,7 @@ impl<R: Read> LogReader<R> {
                 // skip to next block
                 self.src
                     .read_exact(&mut self.head_scratch[0..self.blocksize - self.blk_off])?;
+                self.off += self.blocksize - self.blk_off;
                 self.blk_off = 0;
             }
 
@@ -163,6 +197,10 @@ impl<R: Read> LogReader<R> {
             }
 
             self.blk_off += bytes_read;
+            self.off += bytes_read;
+            if bytes_read < HEADER_SIZE {
+                return err(StatusCode::Corruption, "truncated record header");
+            }
 
             checksum = u32::decode_fixed(&self.head_scratch[0..4]);
             length = u16::decode_fixed(&self.head_scratch[4..6]);
@@ -173,6 +211,10 @@ impl<R: Read> LogReader<R> {
                 .src
                 .read(&mut dst[dst_offset..dst_offset + length as usize])?;
             self.blk_off += bytes_read;
+            self.off += bytes_read;
+            if bytes_read < length as usize {
+                return err(StatusCode::Corruption, "truncated record");
+            }
 
             if self.checksums
                 && !self.check_integrity(typ, &dst[dst_offset..dst_offset + bytes_read], checksum)
@@ -183,12 +225,14 @@ impl<R: Read> LogReader<R> {
             dst_offset += length as usize;
 
             if typ == RecordType::Full as u8 {
+                self.record_end = self.off;
                 return Ok(dst_offset);
             } else if typ == RecordType::First as u8 {
                 continue;
             } else if typ == RecordType::Middle as u8 {
                 continue;
             } else if typ == RecordType::Last as u8 {
+                self.record_end = self.off;
                 return Ok(dst_offset);
             }
         }
@@ -202,6 +246,162 @@ impl<R: Read> LogReader<R> {
     }
 }
 
+/// WalDamage describes a damaged range of a log file, whose bytes are dropped when the log is
+/// read during recovery.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalDamage {
+    /// Offset of the damaged range within the file.
+    pub offset: usize,
+    /// Number of bytes dropped by a reader.
+    pub bytes: usize,
+    pub reason: String,
+}
+
+/// WalReport is the result of verify_wal().
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WalReport {
+    pub file_size: usize,
+    /// Number of complete, intact records.
+    pub records: usize,
+    /// Total size of the intact records.
+    pub record_bytes: usize,
+    /// Number of fragments of intact records; a record spanning several blocks consists of
+    /// several fragments.
+    pub fragments: usize,
+    /// Damaged ranges, in file order.
+    pub damaged: Vec<WalDamage>,
+    /// Whether the file ends in the middle of a record, as after a crash during a write. This is
+    /// not counted as damage: recovery drops such a record silently.
+    pub truncated_tail: bool,
+}
+
+impl WalReport {
+    /// is_ok returns true if no damaged ranges were found.
+    pub fn is_ok(&self) -> bool {
+        self.damaged.is_empty()
+    }
+}
+
+/// verify_wal scans the log file at path, validating the checksums of records and their
+/// fragmentation structure. Unlike recovery, which stops at or skips damaged records depending on
+/// `Options::paranoid_checks`, it reports all damaged ranges. Manifest files share the log format
+/// and can be verified, too.
+pub fn verify_wal<P: AsRef<Path>>(path: P, opt: &Options) -> Result<WalReport> {
+    let path = path.as_ref();
+    let mut data = Vec::with_capacity(opt.env.size_of(path)?);
+    opt.env.open_sequential_file(path)?.read_to_end(&mut data)?;
+    Ok(verify_records(&data, BLOCK_SIZE))
+}
+
+fn verify_records(data: &[u8], block_size: usize) -> WalReport {
+    let mut report = WalReport {
+        file_size: data.len(),
+        ..Default::default()
+    };
+    let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
+    // Start offset, size and number of fragments of the record being assembled.
+    let mut partial: Option<(usize, usize, usize)> = None;
+    let damage = |report: &mut WalReport, offset, bytes, reason: &str| {
+        report.damaged.push(WalDamage {
+            offset,
+            bytes,
+            reason: reason.to_string(),
+        })
+    };
+    // Records the dropped partial record when a fragment at off can't continue it.
+    let drop_partial = |report: &mut WalReport, partial: Option<(usize, _, _)>, off: usize| {
+        if let Some((start, _, _)) = partial {
+            damage(report, start, off - start, "record without last fragment");
+        }
+    };
+
+    let mut off = 0;
+    while off < data.len() {
+        let block_end = (off / block_size + 1) * block_size;
+        if block_end - off < HEADER_SIZE {
+            // Block trailer.
+            off = block_end;
+            continue;
+        }
+        if data.len() - off < HEADER_SIZE {
+            report.truncated_tail = true;
+            break;
+        }
+        let checksum = u32::decode_fixed(&data[off..off + 4]);
+        let length = u16::decode_fixed(&data[off + 4..off + 6]) as usize;
+        let typ = data[off + 6];
+        let end = off + HEADER_SIZE + length;
+
+        if typ == 0 && length == 0 {
+            // Zeroed space, e.g. preallocated by the file system; readers skip the block.
+            off = block_end;
+            continue;
+        }
+        if end > data.len() && block_end >= data.len() {
+            report.truncated_tail = true;
+            break;
+        }
+        if end > block_end {
+            drop_partial(&mut report, partial.take(), off);
+            damage(&mut report, off, block_end - off, "bad record length");
+            off = block_end;
+            continue;
+        }
+        digest.reset();
+        digest.write(&data[off + 6..end]);
+        if unmask_crc(checksum) != digest.sum32() {
+            // The length may be corrupted, too; like recovery, drop the rest of the block.
+            drop_partial(&mut report, partial.take(), off);
+            damage(
+                &mut report,
+                off,
+                block_end.min(data.len()) - off,
+                "checksum mismatch",
+            );
+            off = block_end;
+            continue;
+        }
+
+        if typ == RecordType::Full as u8 || typ == RecordType::First as u8 {
+            drop_partial(&mut report, partial.take(), off);
+        }
+        if typ == RecordType::Full as u8 {
+            report.records += 1;
+            report.record_bytes += length;
+            report.fragments += 1;
+        } else if typ == RecordType::First as u8 {
+            partial = Some((off, length, 1));
+        } else if typ == RecordType::Middle as u8 || typ == RecordType::Last as u8 {
+            match partial {
+                Some((_, ref mut size, ref mut frags)) => {
+                    *size += length;
+                    *frags += 1;
+                    if typ == RecordType::Last as u8 {
+                        report.records += 1;
+                        report.record_bytes += *size;
+                        report.fragments += *frags;
+                        partial = None;
+                    }
+                }
+                None => damage(
+                    &mut report,
+                    off,
+                    end - off,
+                    "fragment without first fragment",
+                ),
+            }
+        } else {
+            drop_partial(&mut report, partial.take(), off);
+            damage(&mut report, off, end - off, "unknown record type");
+        }
+        off = end;
+    }
+    if partial.is_some() {
+        report.truncated_tail = true;
+    }
+    report
+}
+
 const MASK_DELTA: u32 = 0xa282ead8;
 
 pub fn mask_crc(c: u32) -> u32 {
@@ -281,6 +481,66 @@ mod tests {
         assert_eq!(old, dst);
     }
 
+    #[test]
+    fn test_verify_records() {
+        let block_size = super::HEADER_SIZE + 10;
+        let data = vec![
+            "abcdefghi".as_bytes().to_vec(),              // one fragment
+            "123456789012".as_bytes().to_vec(),           // two fragments
+            "0101010101010101010101".as_bytes().to_vec(), // three fragments
+            "xyz".as_bytes().to_vec(),
+        ];
+        let mut lw = LogWriter::new(Vec::new());
+        lw.block_size = block_size;
+        for e in data.iter() {
+            assert!(lw.add_record(e).is_ok());
+        }
+        let log = lw.dst;
+
+        let report = verify_records(&log, block_size);
+        assert!(report.is_ok());
+        assert_eq!(4, report.records);
+        assert_eq!(9, report.fragments);
+        assert_eq!(
+            data.iter().map(|d| d.len()).sum::<usize>(),
+            report.record_bytes
+        );
+        assert!(!report.truncated_tail);
+
+        // A corrupted middle fragment damages its block, and the record's other fragments.
+        let mut damaged = log.clone();
+        damaged[4 * block_size + 8] ^= 1;
+        let report = verify_records(&damaged, block_size);
+        assert_eq!(3, report.records);
+        let damage = |offset, bytes, reason: &str| WalDamage {
+            offset,
+            bytes,
+            reason: reason.to_string(),
+        };
+        assert_eq!(
+            vec![
+                damage(43, 25, "record without last fragment"),
+                damage(68, 17, "checksum mismatch"),
+                damage(85, 8, "fragment without first fragment"),
+            ],
+            report.damaged
+        );
+
+        // A missing first fragment.
+        let report = verify_records(&log[2 * block_size..], block_size);
+        assert_eq!(2, report.records);
+        assert_eq!(
+            vec![damage(0, 9, "fragment without first fragment")],
+            report.damaged
+        );
+
+        // A torn write at the end isn't damage.
+        let report = verify_records(&log[..log.len() - 2], block_size);
+        assert!(report.is_ok());
+        assert!(report.truncated_tail);
+        assert_eq!(3, report.records);
+    }
+
     #[test]
     fn test_reader() {
         let data = vec![
@@ -324,4 +584,30 @@ mod tests {
         }
         assert_eq!(i, data.len());
     }
+
+    #[test]
+    fn test_reader_with_off() {
+        let mut lw = LogWriter::new(Vec::new());
+        lw.add_record(b"first").unwrap();
+        let first_end = lw.dst.len();
+        // Spans two blocks.
+        lw.add_record(&[7; 40000]).unwrap();
+        let log = lw.dst;
+
+        // A log that is still being written is read up to its last complete record.
+        for torn in [first_end + 3, first_end + 100, BLOCK_SIZE + 3] {
+            let mut lr = LogReader::new(&log[..torn], true);
+            let mut dst = vec![];
+            assert_eq!(5, lr.read(&mut dst).unwrap());
+            assert!(lr.read(&mut dst).is_err());
+            assert_eq!(first_end, lr.record_end());
+        }
+
+        let mut lr = LogReader::new_with_off(&log[first_end..], true, first_end);
+        let mut dst = vec![];
+        assert_eq!(40000, lr.read(&mut dst).unwrap());
+        assert_eq!(vec![7; 40000], dst);
+        assert_eq!(0, lr.read(&mut dst).unwrap());
+        assert_eq!(log.len(), lr.record_end());
+    }
 }
@@ -208,6 +208,29 @@ impl MemFS {
             ),
         }
     }
+    fn link_(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut fs = self.store.lock()?;
+        if fs.contains_key(path_to_str(to)) {
+            return err(
+                StatusCode::AlreadyExists,
+                &format!("link: file exists: {}", path_to_str(to)),
+            );
+        }
+        match fs.get(path_to_str(from)) {
+            Some(v) => {
+                let f = MemFSEntry {
+                    f: v.f.clone(),
+                    locked: false,
+                };
+                fs.insert(path_to_string(to), f);
+                Ok(())
+            }
+            _ => err(
+                StatusCode::NotFound,
+                &format!("link: file not found: {}", path_to_str(from)),
+            ),
+        }
+    }
     fn rename_(&self, from: &Path, to: &Path) -> Result<()> {
         let mut fs = self.store.lock()?;
         match fs.remove(path_to_str(from)) {
@@ -329,6 +352,9 @@ impl Env for MemEnv {
     fn rename(&self, old: &Path, new: &Path) -> Result<()> {
         self.0.rename_(old, new)
     }
+    fn link(&self, from: &Path, to: &Path) -> Result<()> {
+        self.0.link_(from, to)
+    }
 
     fn lock(&self, p: &Path) -> Result<FileLock> {
         self.0.lock_(p)
@@ -502,6 +528,13 @@ mod tests {
         assert!(fs.size_of_(&path).is_err());
 
         assert!(fs.rename_(&nonexist, &path).is_err());
+
+        // link_ file.
+        fs.link_(&newpath, &path).unwrap();
+        assert!(fs.link_(&newpath, &path).is_err());
+        assert!(fs.link_(&nonexist, &Path::new("/c")).is_err());
+        fs.delete_(&newpath).unwrap();
+        assert_eq!(fs.size_of_(&path).unwrap(), 5);
     }
 
     fn s2p(x: &str) -> PathBuf {
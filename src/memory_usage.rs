@@ -0,0 +1,108 @@
+//! Approximate memory usage of databases and block caches, e.g. for processes that open many
+//! databases and need to stay within a memory limit.
+//!
+//! ```
+//! use rusty_leveldb::{memory_usage, DB};
+//!
+//! let opt = rusty_leveldb::in_memory();
+//! let mut db1 = DB::open("db1", opt.clone()).unwrap();
+//! let mut db2 = DB::open("db2", opt.clone()).unwrap();
+//! db1.put(b"key", b"value").unwrap();
+//!
+//! // Both databases share the block cache of opt.
+//! let usage = memory_usage::approximate(&[&db1, &db2], &[&*opt.block_cache.borrow()]);
+//! assert!(usage.memtables > 0);
+//! assert_eq!(usage.total(), usage.memtables + usage.table_readers + usage.block_cache);
+//! ```
+
+use crate::block::Block;
+use crate::cache::Cache;
+use crate::DB;
+
+/// MemoryUsage is a breakdown of the memory used by databases and block caches, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    /// Memtables, including immutable memtables awaiting compaction.
+    pub memtables: usize,
+    /// Index and filter blocks of the tables kept open by the databases' table caches.
+    pub table_readers: usize,
+    /// Blocks held by the block caches.
+    pub block_cache: usize,
+    /// The part of `block_cache` that is also used by iterators, and so can't be freed by
+    /// evicting the blocks.
+    pub block_cache_pinned: usize,
+}
+
+impl MemoryUsage {
+    /// total returns the memory used by memtables, table readers and block caches.
+    pub fn total(&self) -> usize {
+        self.memtables + self.table_readers + self.block_cache
+    }
+}
+
+/// approximate returns the memory used by the databases and block caches. A block cache may be
+/// shared by several databases (see `Options::block_cache`), and is counted once even if it's
+/// passed more than once.
+///
+/// Blocks read by iterators after they were evicted from the cache aren't accounted for, and
+/// neither are memtables kept alive by iterators after they were compacted.
+pub fn approximate(dbs: &[&DB], caches: &[&Cache<Block>]) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    for db in dbs {
+        usage.memtables += db.memtable_memory_usage();
+        usage.table_readers += db.table_reader_memory_usage();
+    }
+    for (i, cache) in caches.iter().enumerate() {
+        if caches[..i].iter().any(|c| std::ptr::eq(*c, *cache)) {
+            continue;
+        }
+        for block in cache.values() {
+            usage.block_cache += block.size();
+            if block.pinned() {
+                usage.block_cache_pinned += block.size();
+            }
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::types::LdbIterator;
+
+    #[test]
+    fn test_memory_usage_approximate() {
+        let opt = options::for_test();
+        let mut db1 = DB::open("db1", opt.clone()).unwrap();
+        let mut db2 = DB::open("db2", opt.clone()).unwrap();
+        assert_eq!(0, approximate(&[], &[]).total());
+
+        let empty = approximate(&[&db1, &db2], &[]);
+        for i in 0..1000 {
+            let k = format!("key{:04}", i);
+            db1.put(k.as_bytes(), &[0; 100]).unwrap();
+            db2.put(k.as_bytes(), &[0; 100]).unwrap();
+        }
+        let filled = approximate(&[&db1, &db2], &[]);
+        assert!(filled.memtables > empty.memtables + 2 * 100_000);
+
+        db1.flush_memtable().unwrap();
+        db1.get(b"key0000").unwrap();
+        let cache = opt.block_cache.borrow();
+        let usage = approximate(&[&db1], &[&cache, &cache]);
+        assert!(usage.memtables < filled.memtables / 2);
+        assert!(usage.table_readers > 0);
+        assert!(usage.block_cache > 0);
+        assert_eq!(0, usage.block_cache_pinned);
+        assert_eq!(usage.block_cache, approximate(&[], &[&cache]).block_cache);
+        drop(cache);
+        assert_eq!(usage, db1.memory_usage());
+
+        let mut iter = db1.new_iter().unwrap();
+        iter.seek(b"key0000");
+        let cache = opt.block_cache.borrow();
+        assert!(approximate(&[&db1], &[&cache]).block_cache_pinned > 0);
+    }
+}
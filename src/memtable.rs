@@ -1,73 +1,195 @@
-use crate::cmp::{Cmp, MemtableKeyCmp};
+use crate::cmp::{Cmp, InternalKeyCmp, MemtableKeyCmp};
 use crate::key_types::{build_memtable_key, parse_internal_key, parse_memtable_key, ValueType};
 use crate::key_types::{LookupKey, UserKey};
+use crate::merging_iter::MergingIter;
+use crate::pinned_slice::PinnedSlice;
+use crate::range_del::{covering_seq, RangeTombstone};
 use crate::skipmap::{SkipMap, SkipMapIter};
-use crate::types::{current_key_val, LdbIterator, SequenceNumber};
+use crate::types::{LdbIterator, SequenceNumber};
 
 use std::rc::Rc;
 
+use crc::crc32;
 use integer_encoding::FixedInt;
 
 /// Provides Insert/Get/Iterate, based on the SkipMap implementation.
 /// MemTable uses MemtableKeys internally, that is, it stores key and value in the [Skipmap] key.
+///
+/// A MemTable can be used on its own, as an ordered in-memory map with versions: every write is
+/// tagged with a sequence number, and reads at a sequence number only see writes with an equal
+/// or lower one. Like in the database, sequence numbers must be unique, and entries are never
+/// removed; a deletion adds a marker that hides older values.
+///
+/// ```
+/// use rusty_leveldb::{DefaultCmp, MemTable};
+/// use std::rc::Rc;
+///
+/// let mut mt = MemTable::new(Rc::new(Box::new(DefaultCmp)));
+/// mt.put(1, b"key", b"v1");
+/// mt.put(2, b"key", b"v2");
+/// mt.delete(3, b"key");
+///
+/// assert_eq!(None, mt.get_at(b"key", 0));
+/// assert_eq!(Some(Some(b"v1".to_vec())), mt.get_at(b"key", 1));
+/// assert_eq!(Some(Some(b"v2".to_vec())), mt.get_at(b"key", 2));
+/// assert_eq!(Some(None), mt.get_at(b"key", 3));
+///
+/// mt.put(4, b"key", b"v4");
+/// mt.delete_range(5, b"a", b"z");
+/// assert_eq!(Some(None), mt.get_at(b"key", 5));
+/// ```
+///
+/// A MemTable can be split into shards (see `Options::memtable_shards`): each user key is assigned
+/// to one shard by its hash, so that all versions of a key live in the same skip map. Iterators
+/// merge the shards.
 pub struct MemTable {
-    map: SkipMap,
+    shards: Vec<SkipMap>,
+    range_tombstones: Vec<RangeTombstone>,
+    cmp: Rc<Box<dyn Cmp>>,
 }
 
 impl MemTable {
     /// Returns a new MemTable.
     /// This wraps opt.cmp inside a MemtableKey-specific comparator.
     pub fn new(cmp: Rc<Box<dyn Cmp>>) -> MemTable {
-        MemTable::new_raw(Rc::new(Box::new(MemtableKeyCmp(cmp))))
+        MemTable::new_sharded(cmp, 1)
     }
 
-    /// Doesn't wrap the comparator in a MemtableKeyCmp.
-    fn new_raw(cmp: Rc<Box<dyn Cmp>>) -> MemTable {
+    /// Returns a new MemTable consisting of `shards` skip maps (at least one). Keys that compare
+    /// as equal must be equal bytewise, as they are otherwise stored in different shards.
+    pub fn new_sharded(cmp: Rc<Box<dyn Cmp>>, shards: usize) -> MemTable {
+        let mcmp: Rc<Box<dyn Cmp>> = Rc::new(Box::new(MemtableKeyCmp(cmp.clone())));
         MemTable {
-            map: SkipMap::new(cmp),
+            shards: (0..shards.max(1))
+                .map(|_| SkipMap::new(mcmp.clone()))
+                .collect(),
+            range_tombstones: vec![],
+            cmp,
         }
     }
 
+    /// len returns the number of entries, including range deletions.
     pub fn len(&self) -> usize {
-        self.map.len()
+        self.shards.iter().map(SkipMap::len).sum::<usize>() + self.range_tombstones.len()
     }
 
     pub fn approx_mem_usage(&self) -> usize {
-        self.map.approx_memory()
+        self.shards
+            .iter()
+            .map(SkipMap::approx_memory)
+            .sum::<usize>()
+            + self
+                .range_tombstones
+                .iter()
+                .map(|t| t.from.len() + t.to.len() + 32)
+                .sum::<usize>()
+    }
+
+    /// shard returns the index of the shard storing key.
+    fn shard(&self, key: UserKey) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        crc32::checksum_castagnoli(key) as usize % self.shards.len()
     }
 
     pub fn add<'a>(&mut self, seq: SequenceNumber, t: ValueType, key: UserKey<'a>, value: &[u8]) {
-        self.map
-            .insert(build_memtable_key(key, value, t, seq), Vec::new())
+        let ix = self.shard(key);
+        self.shards[ix].insert(build_memtable_key(key, value, t, seq), Vec::new())
+    }
+
+    /// put stores a value for key, visible to reads at seq or later.
+    pub fn put(&mut self, seq: SequenceNumber, key: &[u8], value: &[u8]) {
+        self.add(seq, ValueType::TypeValue, key, value)
+    }
+
+    /// delete hides older values of key from reads at seq or later.
+    pub fn delete(&mut self, seq: SequenceNumber, key: &[u8]) {
+        self.add(seq, ValueType::TypeDeletion, key, b"")
+    }
+
+    /// delete_range hides older values of all keys in [from; to) from reads at seq or later.
+    pub fn delete_range(&mut self, seq: SequenceNumber, from: &[u8], to: &[u8]) {
+        self.range_tombstones
+            .push(RangeTombstone::new(from, to, seq));
+    }
+
+    /// range_tombstones returns the range deletions added to the memtable.
+    pub(crate) fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// get_at returns the newest entry for key with a sequence number of at most seq: None if
+    /// there is none, `Some(None)` if it is a deletion, and the value otherwise.
+    pub fn get_at(&self, key: &[u8], seq: SequenceNumber) -> Option<Option<Vec<u8>>> {
+        let deleted = covering_seq(&self.range_tombstones, self.cmp.as_ref().as_ref(), key, seq);
+        match self.get_entry(&LookupKey::new(key, seq)) {
+            Some((found, v)) if found > deleted => Some(v),
+            _ if deleted > 0 => Some(None),
+            _ => None,
+        }
     }
 
     /// get returns the value for the given entry and whether the entry is marked as deleted. This
-    /// is to distinguish between not-found and found-deleted.
-    #[allow(unused_variables)]
+    /// is to distinguish between not-found and found-deleted. Range deletions are not considered.
     pub fn get(&self, key: &LookupKey) -> (Option<Vec<u8>>, bool) {
-        let mut iter = self.map.iter();
+        match self.get_entry(key) {
+            Some((_, Some(v))) => (Some(v), false),
+            Some((_, None)) => (None, true),
+            None => (None, false),
+        }
+    }
+
+    /// get_entry returns the sequence number and value (None for a deletion) of the entry found
+    /// by get().
+    pub(crate) fn get_entry(&self, key: &LookupKey) -> Option<(SequenceNumber, Option<Vec<u8>>)> {
+        self.get_entry_pinned(key)
+            .map(|(seq, v)| (seq, v.map(PinnedSlice::into_vec)))
+    }
+
+    /// get_entry_pinned is like get_entry(), but returns the value without copying it.
+    pub(crate) fn get_entry_pinned(
+        &self,
+        key: &LookupKey,
+    ) -> Option<(SequenceNumber, Option<PinnedSlice>)> {
+        let mut iter = self.shards[self.shard(key.user_key())].iter();
         iter.seek(key.memtable_key());
 
-        if let Some((foundkey, _)) = current_key_val(&iter) {
-            let (fkeylen, fkeyoff, tag, vallen, valoff) = parse_memtable_key(&foundkey);
-
-            // Compare user key -- if equal, proceed
-            // We only care about user key equality here
-            if key.user_key() == &foundkey[fkeyoff..fkeyoff + fkeylen] {
-                if tag & 0xff == ValueType::TypeValue as u64 {
-                    return (Some(foundkey[valoff..valoff + vallen].to_vec()), false);
-                } else {
-                    return (None, true);
-                }
-            }
+        let foundkey = iter.current_key()?;
+        let (fkeylen, fkeyoff, tag, vallen, valoff) = parse_memtable_key(foundkey);
+
+        // Compare user key -- if equal, proceed
+        // We only care about user key equality here
+        if key.user_key() != &foundkey[fkeyoff..fkeyoff + fkeylen] {
+            return None;
+        }
+        if tag & 0xff == ValueType::TypeValue as u64 {
+            let val = PinnedSlice::from_memtable(iter, valoff..valoff + vallen);
+            Some((tag >> 8, Some(val)))
+        } else {
+            Some((tag >> 8, None))
         }
-        (None, false)
     }
 
     pub fn iter(&self) -> MemtableIterator {
-        MemtableIterator {
-            skipmapiter: self.map.iter(),
+        if self.shards.len() == 1 {
+            return MemtableIterator(Iter::Shard(ShardIterator {
+                skipmapiter: self.shards[0].iter(),
+            }));
         }
+        let iters = self
+            .shards
+            .iter()
+            .map(|m| {
+                Box::new(ShardIterator {
+                    skipmapiter: m.iter(),
+                }) as Box<dyn LdbIterator>
+            })
+            .collect();
+        MemtableIterator(Iter::Merged(MergingIter::new(
+            Rc::new(Box::new(InternalKeyCmp(self.cmp.clone()))),
+            iters,
+        )))
     }
 }
 
@@ -76,11 +198,64 @@ impl MemTable {
 /// with InternalKeys.
 ///
 /// This iterator does not skip deleted entries.
-pub struct MemtableIterator {
-    skipmapiter: SkipMapIter,
+pub struct MemtableIterator(Iter);
+
+enum Iter {
+    Shard(ShardIterator),
+    Merged(MergingIter),
 }
 
 impl LdbIterator for MemtableIterator {
+    fn advance(&mut self) -> bool {
+        match &mut self.0 {
+            Iter::Shard(it) => it.advance(),
+            Iter::Merged(it) => it.advance(),
+        }
+    }
+    fn reset(&mut self) {
+        match &mut self.0 {
+            Iter::Shard(it) => it.reset(),
+            Iter::Merged(it) => it.reset(),
+        }
+    }
+    fn prev(&mut self) -> bool {
+        match &mut self.0 {
+            Iter::Shard(it) => it.prev(),
+            Iter::Merged(it) => it.prev(),
+        }
+    }
+    fn valid(&self) -> bool {
+        match &self.0 {
+            Iter::Shard(it) => it.valid(),
+            Iter::Merged(it) => it.valid(),
+        }
+    }
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        match &self.0 {
+            Iter::Shard(it) => it.current(key, val),
+            Iter::Merged(it) => it.current(key, val),
+        }
+    }
+    fn seek(&mut self, to: &[u8]) {
+        match &mut self.0 {
+            Iter::Shard(it) => it.seek(to),
+            Iter::Merged(it) => it.seek(to),
+        }
+    }
+    fn seek_to_last(&mut self) {
+        match &mut self.0 {
+            Iter::Shard(it) => it.seek_to_last(),
+            Iter::Merged(it) => it.seek_to_last(),
+        }
+    }
+}
+
+/// ShardIterator iterates over a single shard of a MemTable.
+struct ShardIterator {
+    skipmapiter: SkipMapIter,
+}
+
+impl LdbIterator for ShardIterator {
     fn advance(&mut self) -> bool {
         if !self.skipmapiter.advance() {
             return false;
@@ -91,24 +266,9 @@ impl LdbIterator for MemtableIterator {
         self.skipmapiter.reset();
     }
     fn prev(&mut self) -> bool {
-        // Make sure this is actually needed (skipping deleted values?).
-        let (mut key, mut val) = (vec![], vec![]);
-        loop {
-            if !self.skipmapiter.prev() {
-                return false;
-            }
-            if self.skipmapiter.current(&mut key, &mut val) {
-                let (_, _, tag, _, _) = parse_memtable_key(&key);
-
-                if tag & 0xff == ValueType::TypeValue as u64 {
-                    return true;
-                } else {
-                    continue;
-                }
-            } else {
-                return false;
-            }
-        }
+        // Like advance(), this doesn't skip deletion markers: DBIterator needs them to hide older
+        // values when moving backwards.
+        self.skipmapiter.prev()
     }
     fn valid(&self) -> bool {
         self.skipmapiter.valid()
@@ -139,6 +299,9 @@ impl LdbIterator for MemtableIterator {
         self.skipmapiter
             .seek(LookupKey::new(ukey, seq).memtable_key());
     }
+    fn seek_to_last(&mut self) {
+        self.skipmapiter.seek_to_last();
+    }
 }
 
 /// shift_left moves s[mid..] to s[0..s.len()-mid]. The new size is s.len()-mid.
@@ -157,6 +320,7 @@ mod tests {
     use crate::key_types::{parse_tag, truncate_to_userkey};
     use crate::options;
     use crate::test_util::{test_iterator_properties, LdbIteratorIter};
+    use crate::types::current_key_val;
 
     #[test]
     fn test_shift_left() {
@@ -202,7 +366,7 @@ mod tests {
         );
 
         assert_eq!(
-            mt.map.iter().next().unwrap().0,
+            mt.shards[0].iter().next().unwrap().0,
             &[11, 97, 98, 99, 1, 123, 0, 0, 0, 0, 0, 0, 3, 49, 50, 51]
         );
         assert_eq!(
@@ -362,6 +526,16 @@ mod tests {
 
         iter.prev();
         assert!(!iter.valid());
+
+        // Deletion markers aren't skipped in either direction.
+        iter.seek(LookupKey::new(b"abf", 200).internal_key());
+        assert!(iter.prev());
+        let key = current_key_val(&iter).unwrap().0;
+        let (typ, seq, ukey) = parse_internal_key(&key);
+        assert_eq!(
+            (ValueType::TypeDeletion, 122, b"abe".as_ref()),
+            (typ, seq, ukey)
+        );
     }
 
     #[test]
@@ -391,4 +565,42 @@ mod tests {
 
         test_iterator_properties(mt.iter());
     }
+
+    #[test]
+    fn test_memtable_sharded() {
+        let mut plain = MemTable::new(options::for_test().cmp);
+        let mut mt = MemTable::new_sharded(options::for_test().cmp, 4);
+        for i in 0..100u64 {
+            let k = format!("key{:03}", i % 40);
+            if i % 7 == 0 {
+                plain.delete(i + 1, k.as_bytes());
+                mt.delete(i + 1, k.as_bytes());
+            } else {
+                plain.put(i + 1, k.as_bytes(), &i.to_le_bytes());
+                mt.put(i + 1, k.as_bytes(), &i.to_le_bytes());
+            }
+        }
+        assert_eq!(100, mt.len());
+        assert!(mt.shards.iter().all(|m| m.len() > 0));
+
+        for i in 0..40 {
+            let k = format!("key{:03}", i);
+            for seq in &[0, 30, 60, 100] {
+                assert_eq!(
+                    plain.get_at(k.as_bytes(), *seq),
+                    mt.get_at(k.as_bytes(), *seq)
+                );
+            }
+        }
+        assert_eq!(
+            LdbIteratorIter::wrap(&mut plain.iter()).collect::<Vec<_>>(),
+            LdbIteratorIter::wrap(&mut mt.iter()).collect::<Vec<_>>()
+        );
+
+        let mut iter = mt.iter();
+        iter.seek(LookupKey::new(b"key020", 100).internal_key());
+        let mut k = current_key_val(&iter).unwrap().0;
+        truncate_to_userkey(&mut k);
+        assert_eq!(b"key020".to_vec(), k);
+    }
 }
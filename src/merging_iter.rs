@@ -83,8 +83,8 @@ impl MergingIter {
                                 if self.iters[i].valid() {
                                     self.iters[i].prev();
                                 } else {
-                                    // seek to last.
-                                    while self.iters[i].advance() {}
+                                    // All entries are before key.
+                                    self.iters[i].seek_to_last();
                                 }
                             }
                         }
@@ -162,12 +162,21 @@ impl LdbIterator for MergingIter {
         for i in 0..self.iters.len() {
             self.iters[i].seek(key);
         }
+        self.direction = Direction::Forward;
         self.find_smallest();
     }
+    fn seek_to_last(&mut self) {
+        for i in 0..self.iters.len() {
+            self.iters[i].seek_to_last();
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
     fn reset(&mut self) {
         for i in 0..self.iters.len() {
             self.iters[i].reset();
         }
+        self.direction = Direction::Forward;
         self.current = None;
     }
     fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
@@ -309,6 +318,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_merging_seek_to_last() {
+        let val = "def".as_bytes();
+        let iter = TestLdbIter::new(vec![(b("aba"), val), (b("abc"), val)]);
+        let iter2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val), (b("abe"), val)]);
+        let mut miter = MergingIter::new(
+            Rc::new(Box::new(DefaultCmp)),
+            vec![Box::new(iter), Box::new(iter2)],
+        );
+
+        miter.seek_to_last();
+        assert_eq!(
+            Some((b("abe").to_vec(), val.to_vec())),
+            current_key_val(&miter)
+        );
+        assert!(miter.prev());
+        assert_eq!(
+            Some((b("abd").to_vec(), val.to_vec())),
+            current_key_val(&miter)
+        );
+        assert!(miter.advance());
+        assert_eq!(
+            Some((b("abe").to_vec(), val.to_vec())),
+            current_key_val(&miter)
+        );
+
+        // Going back from abe, the first iterator has no entry at or after it and continues at
+        // its last entry.
+        miter.seek(b("abe"));
+        assert!(miter.prev());
+        assert_eq!(
+            Some((b("abd").to_vec(), val.to_vec())),
+            current_key_val(&miter)
+        );
+        assert!(miter.prev());
+        assert_eq!(
+            Some((b("abc").to_vec(), val.to_vec())),
+            current_key_val(&miter)
+        );
+    }
+
     fn b(s: &'static str) -> &'static [u8] {
         s.as_bytes()
     }
@@ -371,4 +421,30 @@ mod tests {
             Some((b("aba").to_vec(), val.to_vec()))
         );
     }
+
+    #[test]
+    fn test_merging_seek_prev() {
+        let val = "def".as_bytes();
+
+        let it1 = TestLdbIter::new(vec![(b("aba"), val), (b("abb"), val)]);
+        let it2 = TestLdbIter::new(vec![(b("abc"), val), (b("abd"), val), (b("abe"), val)]);
+
+        let mut iter = MergingIter::new(
+            Rc::new(Box::new(DefaultCmp)),
+            vec![Box::new(it1), Box::new(it2)],
+        );
+
+        // it1 has no entry at or after abd, but must take part in backward iteration.
+        iter.seek("abd".as_bytes());
+        let mut keys = vec![];
+        while iter.prev() {
+            keys.push(current_key_val(&iter).unwrap().0);
+        }
+        assert_eq!(vec![b("abc"), b("abb"), b("aba")], keys);
+
+        // Seeking after prev() moves forward again.
+        iter.seek("abb".as_bytes());
+        assert!(iter.prev());
+        assert_eq!(b("aba").to_vec(), current_key_val(&iter).unwrap().0);
+    }
 }
@@ -0,0 +1,171 @@
+//! Metrics emitted through the facade of the `metrics` crate, if the `metrics` feature is
+//! enabled; otherwise, the functions in this module do nothing. The host application installs a
+//! recorder (e.g. a Prometheus exporter) to collect them. The metrics are:
+//!
+//! * `leveldb_block_cache_hits_total`, `leveldb_block_cache_misses_total` (counters)
+//! * `leveldb_operation_seconds` (histogram, label `operation`: `get`, `put`, `write`, `sync_write`)
+//! * `leveldb_write_stalls_total` (counter), `leveldb_write_stall_seconds` (histogram): time a
+//!   write waited for the memtable to be compacted.
+//! * `leveldb_compaction_read_bytes_total`, `leveldb_compaction_written_bytes_total` (counters,
+//!   label `level`)
+//! * `leveldb_async_queue_depth` (gauge): requests waiting for the `AsyncDB` worker.
+
+pub(crate) use imp::*;
+
+#[cfg(feature = "metrics")]
+mod imp {
+    use crate::statistics::Operation;
+
+    use std::time::Duration;
+
+    pub fn block_cache_access(hit: bool) {
+        if hit {
+            ::metrics::counter!("leveldb_block_cache_hits_total").increment(1);
+        } else {
+            ::metrics::counter!("leveldb_block_cache_misses_total").increment(1);
+        }
+    }
+
+    pub fn operation(op: Operation, d: Duration) {
+        let name = match op {
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::Write => "write",
+            Operation::SyncWrite => "sync_write",
+            Operation::Seek => "seek",
+            Operation::Next => "next",
+        };
+        ::metrics::histogram!("leveldb_operation_seconds", "operation" => name)
+            .record(d.as_secs_f64());
+    }
+
+    pub fn write_stall(d: Duration) {
+        ::metrics::counter!("leveldb_write_stalls_total").increment(1);
+        ::metrics::histogram!("leveldb_write_stall_seconds").record(d.as_secs_f64());
+    }
+
+    pub fn compaction(level: usize, read: usize, written: usize) {
+        let level = level.to_string();
+        ::metrics::counter!("leveldb_compaction_read_bytes_total", "level" => level.clone())
+            .increment(read as u64);
+        ::metrics::counter!("leveldb_compaction_written_bytes_total", "level" => level)
+            .increment(written as u64);
+    }
+
+    #[cfg(feature = "async")]
+    pub fn async_queue_depth(n: usize) {
+        ::metrics::gauge!("leveldb_async_queue_depth").set(n as f64);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+    use crate::statistics::Operation;
+
+    use std::time::Duration;
+
+    pub fn block_cache_access(_: bool) {}
+    pub fn operation(_: Operation, _: Duration) {}
+    pub fn write_stall(_: Duration) {}
+    pub fn compaction(_: usize, _: usize, _: usize) {}
+    #[cfg(feature = "async")]
+    pub fn async_queue_depth(_: usize) {}
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use crate::options;
+    use crate::DB;
+
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use ::metrics::{
+        Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+        Unit,
+    };
+
+    /// Counts histogram samples.
+    struct Samples(AtomicU64);
+
+    impl HistogramFn for Samples {
+        fn record(&self, _: f64) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// TestRecorder sums counters, and counts histogram samples, by metric name.
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<HashMap<String, Arc<AtomicU64>>>,
+        histograms: Mutex<HashMap<String, Arc<Samples>>>,
+    }
+
+    impl TestRecorder {
+        fn counter(&self, name: &str) -> u64 {
+            self.counters
+                .lock()
+                .unwrap()
+                .get(name)
+                .map_or(0, |c| c.load(Ordering::Relaxed))
+        }
+        fn samples(&self, name: &str) -> u64 {
+            self.histograms
+                .lock()
+                .unwrap()
+                .get(name)
+                .map_or(0, |h| h.0.load(Ordering::Relaxed))
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let mut counters = self.counters.lock().unwrap();
+            Counter::from_arc(counters.entry(key.name().to_string()).or_default().clone())
+        }
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            let mut histograms = self.histograms.lock().unwrap();
+            Histogram::from_arc(
+                histograms
+                    .entry(key.name().to_string())
+                    .or_insert_with(|| Arc::new(Samples(AtomicU64::new(0))))
+                    .clone(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_metrics_emitted() {
+        let rec = TestRecorder::default();
+        ::metrics::with_local_recorder(&rec, || {
+            let mut opt = options::for_test();
+            opt.write_buffer_size = 1000;
+            let mut db = DB::open("db", opt).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), &[0; 100])
+                    .unwrap();
+            }
+            db.compact_range(b"key000", b"key999").unwrap();
+            assert!(db.get(b"key050").unwrap().is_some());
+        });
+
+        assert!(rec.counter("leveldb_write_stalls_total") > 0);
+        assert!(rec.samples("leveldb_write_stall_seconds") > 0);
+        assert!(rec.counter("leveldb_compaction_written_bytes_total") > 0);
+        assert!(rec.counter("leveldb_compaction_read_bytes_total") > 0);
+        assert!(
+            rec.counter("leveldb_block_cache_hits_total")
+                + rec.counter("leveldb_block_cache_misses_total")
+                > 0
+        );
+        assert_eq!(101, rec.samples("leveldb_operation_seconds"));
+    }
+}
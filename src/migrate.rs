@@ -0,0 +1,155 @@
+//! Rewriting a database for another comparator.
+
+use crate::cmp::Cmp;
+use crate::error::{err, Result, StatusCode};
+use crate::options::Options;
+use crate::types::LdbIterator;
+use crate::write_batch::WriteBatch;
+use crate::DB;
+
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// Entries are written to the new database in batches of this many.
+const MIGRATE_BATCH_SIZE: usize = 1000;
+
+/// migrate_comparator copies all entries of the database at `from`, opened with `from_opt`, into
+/// a new database at `to`, created with `to_opt` and its comparator. It returns the number of
+/// copied entries.
+///
+/// A database can't be opened with a comparator other than the one it was created with; this is
+/// the way to change it. Keys that are distinct under the old comparator but equal under the new
+/// one would overwrite each other, so the migration fails with `StatusCode::InvalidData` if it
+/// encounters such keys; the partially written new database is left in place.
+pub fn migrate_comparator<P: AsRef<Path>, Q: AsRef<Path>>(
+    from: P,
+    from_opt: Options,
+    to: Q,
+    mut to_opt: Options,
+) -> Result<usize> {
+    let mut src = DB::open(from, from_opt)?;
+    to_opt.create_if_missing = true;
+    to_opt.error_if_exists = true;
+    let cmp = to_opt.cmp.clone();
+    let mut dst = DB::open(to, to_opt)?;
+
+    let mut iter = src.new_iter()?;
+    let mut pending: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(MIGRATE_BATCH_SIZE);
+    let mut copied = 0;
+    while let Some((k, v)) = iter.next() {
+        pending.push((k, v));
+        if pending.len() >= MIGRATE_BATCH_SIZE {
+            copied += write_unique(&mut dst, cmp.as_ref().as_ref(), &mut pending)?;
+        }
+    }
+    copied += write_unique(&mut dst, cmp.as_ref().as_ref(), &mut pending)?;
+    dst.flush()?;
+    dst.close()?;
+    Ok(copied)
+}
+
+/// write_unique writes the entries to db after checking that none of their keys is equal to
+/// another one or to a key already in db, under cmp.
+fn write_unique(
+    db: &mut DB,
+    cmp: &dyn Cmp,
+    entries: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) -> Result<usize> {
+    entries.sort_by(|a, b| cmp.cmp(&a.0, &b.0));
+    // Lookups compare keys bytewise, seeking uses the comparator.
+    let mut existing = db.new_iter()?;
+    let (mut found, mut val) = (vec![], vec![]);
+    let mut batch = WriteBatch::new();
+    for (i, (k, v)) in entries.iter().enumerate() {
+        existing.seek(k);
+        let duplicate = i > 0 && cmp.cmp(&entries[i - 1].0, k) == Ordering::Equal
+            || existing.current(&mut found, &mut val) && cmp.cmp(&found, k) == Ordering::Equal;
+        if duplicate {
+            return err(
+                StatusCode::InvalidData,
+                &format!(
+                    "key {:?} is not unique under comparator {}",
+                    String::from_utf8_lossy(k),
+                    cmp.id()
+                ),
+            );
+        }
+        batch.put(k, v);
+    }
+    let n = entries.len();
+    db.write(batch, false)?;
+    entries.clear();
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::test_util::LdbIteratorIter;
+
+    use std::rc::Rc;
+
+    /// Orders keys case-insensitively.
+    struct CaseInsensitiveCmp;
+
+    impl Cmp for CaseInsensitiveCmp {
+        fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+            a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+        }
+        fn find_shortest_sep(&self, from: &[u8], _: &[u8]) -> Vec<u8> {
+            from.to_vec()
+        }
+        fn find_short_succ(&self, key: &[u8]) -> Vec<u8> {
+            key.to_vec()
+        }
+        fn id(&self) -> &'static str {
+            "test.CaseInsensitiveCmp"
+        }
+    }
+
+    #[test]
+    fn test_migrate_comparator() {
+        let opt = options::for_test();
+        {
+            let mut db = DB::open("olddb", opt.clone()).unwrap();
+            for k in &["b", "C", "a", "D"] {
+                db.put(k.as_bytes(), k.as_bytes()).unwrap();
+            }
+        }
+        let mut to_opt = opt.clone();
+        to_opt.cmp = Rc::new(Box::new(CaseInsensitiveCmp));
+        assert_eq!(
+            4,
+            migrate_comparator("olddb", opt.clone(), "newdb", to_opt.clone()).unwrap()
+        );
+        assert!(DB::open("newdb", opt.clone()).is_err());
+        let mut db = DB::open("newdb", to_opt.clone()).unwrap();
+        let mut iter = db.new_iter().unwrap();
+        let keys: Vec<_> = LdbIteratorIter::wrap(&mut iter).map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![b"a".to_vec(), b"b".to_vec(), b"C".to_vec(), b"D".to_vec()],
+            keys
+        );
+        drop(db);
+
+        // The destination must not exist yet.
+        assert!(migrate_comparator("olddb", opt.clone(), "newdb", to_opt.clone()).is_err());
+
+        {
+            let mut db = DB::open("olddb", opt.clone()).unwrap();
+            db.put(b"A", b"A").unwrap();
+            // Puts "A" and "a" into different batches.
+            for i in 0..MIGRATE_BATCH_SIZE {
+                db.put(format!("B{:04}", i).as_bytes(), b"").unwrap();
+            }
+        }
+        assert_eq!(
+            StatusCode::InvalidData,
+            migrate_comparator("olddb", opt, "newdb2", to_opt)
+                .err()
+                .unwrap()
+                .code
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! A convenience wrapper for sharing a database between threads behind a mutex.
+
+use crate::error::{err, Result, StatusCode};
+use crate::options::{Options, ReadOptions};
+use crate::write_batch::WriteBatch;
+use crate::DB;
+
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// MutexDB is a convenience wrapper putting a `DB` behind a mutex: a `Send + Sync` handle, which
+/// can be cloned and used from several threads without going through a channel like `AsyncDB`
+/// does. It is no more than `Arc<Mutex<DB>>` with the most common methods forwarded.
+///
+/// `DB` itself is not thread-safe: it is built on single-threaded reference counting (`Rc`), and
+/// has none of the internal locking of the original implementation, which lets reads run
+/// concurrently with each other and with writes. Here, operations are serialized instead: each
+/// one holds the mutex while it runs, including compactions triggered by writes, which block
+/// reads meanwhile. Iterators and snapshots refer to the database's internals, and can only be
+/// used within `with()`.
+///
+/// The `Options` used to open the database must not be used elsewhere while the database is
+/// open, as they share state (e.g. the block cache) with it.
+#[derive(Clone)]
+pub struct MutexDB {
+    db: Arc<Mutex<DB>>,
+}
+
+impl MutexDB {
+    /// Create a new or open an existing database.
+    pub fn open<P: AsRef<Path>>(name: P, opt: Options) -> Result<MutexDB> {
+        Ok(MutexDB {
+            db: Arc::new(Mutex::new(DB::open(name, opt)?)),
+        })
+    }
+
+    pub(crate) fn lock(&self) -> Result<MutexGuard<'_, DB>> {
+        match self.db.lock() {
+            Ok(db) => Ok(db),
+            Err(_) => err(
+                StatusCode::LockError,
+                "a thread panicked while using the database",
+            ),
+        }
+    }
+
+    /// with runs f with exclusive access to the database, e.g. in order to iterate over it or to
+    /// read several keys from one snapshot. Other threads wait until f returns.
+    pub fn with<R: Send, F: FnOnce(&mut DB) -> R>(&self, f: F) -> Result<R> {
+        Ok(f(&mut *self.lock()?))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.lock()?.get(key)
+    }
+    pub fn get_with(&self, key: &[u8], ropt: &ReadOptions) -> Result<Option<Vec<u8>>> {
+        self.lock()?.get_with(key, ropt)
+    }
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.lock()?.multi_get(keys)
+    }
+    pub fn put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.lock()?.put(key, val)
+    }
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.lock()?.delete(key)
+    }
+    pub fn write(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        self.lock()?.write(batch, sync)
+    }
+    pub fn flush(&self) -> Result<()> {
+        self.lock()?.flush()
+    }
+    pub fn compact_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        self.lock()?.compact_range(from, to)
+    }
+    pub fn delete_prefix(&self, prefix: &[u8]) -> Result<()> {
+        self.lock()?.delete_prefix(prefix)
+    }
+    pub fn delete_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        self.lock()?.delete_range(from, to)
+    }
+    /// checkpoint creates a copy of the database in dir (see `DB::checkpoint()`).
+    pub fn checkpoint<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        self.lock()?.checkpoint(dir)
+    }
+    /// close flushes the database and releases its lock file. Other handles fail afterwards.
+    pub fn close(&self) -> Result<()> {
+        self.lock()?.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::test_util::LdbIteratorIter;
+
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_mutex_db_threads() {
+        assert_send_sync::<MutexDB>();
+        let db = MutexDB::open("mutexdb", options::for_test()).unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        let k = format!("{}-{:03}", t, i);
+                        db.put(k.as_bytes(), b"val").unwrap();
+                        assert_eq!(Some(b"val".to_vec()), db.get(k.as_bytes()).unwrap());
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        db.delete(b"0-000").unwrap();
+        let n = db
+            .with(|db| LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).count())
+            .unwrap();
+        assert_eq!(399, n);
+        db.close().unwrap();
+    }
+}
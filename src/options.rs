@@ -1,16 +1,27 @@
 use crate::block::Block;
 use crate::cache::Cache;
+use crate::cache_sim::BlockTrace;
 use crate::cmp::{Cmp, DefaultCmp};
+use crate::compaction_filter::CompactionFilter;
+use crate::compaction_scheduler::{CompactionScheduler, DefaultCompactionScheduler};
 use crate::compressor::{self, Compressor, CompressorId};
 use crate::env::Env;
+use crate::event_listener::EventListener;
+use crate::filter::{self, FilterPolicy, PrefixExtractor};
+use crate::filter_block::FILTER_BASE_LOG2;
 use crate::infolog::{self, Logger};
 use crate::mem_env::MemEnv;
+use crate::rate_limiter::RateLimiter;
+use crate::secondary_cache::SecondaryCache;
 use crate::types::{share, Shared};
+use crate::write_stall::WriteStallListener;
 use crate::Result;
-use crate::{filter, Status, StatusCode};
+use crate::{Status, StatusCode};
 
 use std::default::Default;
+use std::fmt;
 use std::rc::Rc;
+use std::time::Duration;
 
 const KB: usize = 1 << 10;
 const MB: usize = KB * KB;
@@ -19,6 +30,44 @@ const BLOCK_MAX_SIZE: usize = 4 * KB;
 const BLOCK_CACHE_CAPACITY: usize = 8 * MB;
 const WRITE_BUFFER_SIZE: usize = 4 * MB;
 const DEFAULT_BITS_PER_KEY: u32 = 10; // NOTE: This may need to be optimized.
+/// The table cache keeps this many fewer files open than `max_open_files`, leaving room for logs
+/// and the manifest.
+pub(crate) const RESERVED_FILES: usize = 10;
+const MIN_OPEN_FILES: usize = RESERVED_FILES + 10;
+
+/// CompactionPri determines which file of a level is chosen as input for a size compaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionPri {
+    /// Pick files in key order, continuing after the key range compacted last on the level. This
+    /// is the original LevelDB behavior.
+    RoundRobin,
+    /// Pick the file with the smallest ratio of overlapping bytes in the next level to its own
+    /// size. This minimizes the data rewritten per compaction, and thus write amplification, e.g.
+    /// with skewed or sequential key distributions.
+    MinOverlappingRatio,
+}
+
+/// Durability determines how far the log is persisted after each write, and whether new files
+/// are synced. Stronger levels make writes safe against more kinds of crashes, at the cost of
+/// latency. Writes with `sync` set and `DB::flush()` use at least `DataSync`.
+///
+/// Independent of this, the manifest and CURRENT files are always synced when they change, so
+/// that a crash never leaves the database unopenable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// Log records are buffered in memory until the buffer is full. This is the fastest level;
+    /// recent writes are lost if the process crashes.
+    None,
+    /// Log records are handed to the OS after each write. Writes survive a crash of the process,
+    /// but not of the OS or a power loss.
+    Buffered,
+    /// The log's data is synced (`fdatasync`) after each write, and new table files are synced
+    /// before they're used.
+    DataSync,
+    /// Like `DataSync`, but with a full `fsync`, and the database directory is synced after
+    /// creating a log file.
+    FullSync,
+}
 
 /// Options contains general parameters for a LevelDB instance. Most of the names are
 /// self-explanatory; the defaults are defined in the `Default` implementation.
@@ -31,11 +80,85 @@ pub struct Options {
     pub error_if_exists: bool,
     pub paranoid_checks: bool,
     pub write_buffer_size: usize,
+    /// The number of skip maps the memtable is split into, by hash of the key. Iterators merge
+    /// the shards, which makes scans of the memtable slower; point lookups and writes only touch
+    /// one shard. As keys that compare as equal must hash alike, this is only supported with the
+    /// default bytewise comparator; `validate()` rejects more than one shard with other ones.
+    pub memtable_shards: usize,
     pub max_open_files: usize,
+    /// If not zero, used instead of `target_file_size_base`. Zero by default.
+    #[deprecated(note = "use target_file_size_base")]
     pub max_file_size: usize,
+    /// The size at which table files written by compactions into level 1 are split.
+    pub target_file_size_base: usize,
+    /// Table files in level `l > 1` are split at `target_file_size_base *
+    /// target_file_size_multiplier^(l-1)` bytes, allowing lower levels to consist of fewer,
+    /// larger files.
+    pub target_file_size_multiplier: usize,
+    /// A compaction output file is closed early once it overlaps with more than
+    /// `max_grandparent_overlap_factor` times the target file size of data in the level below its
+    /// output level. This bounds the cost of compacting that file later.
+    pub max_grandparent_overlap_factor: usize,
+    /// Upper limit for the input size of a compaction when adding more files from the input
+    /// level to it. 0 means 25 times the target file size of the input level. Note that a
+    /// compaction always includes all overlapping files and may thus exceed this limit.
+    pub max_compaction_bytes: usize,
+    /// If set, writes only wait for memtable compactions ("flushes"). Level compactions are left
+    /// to `DB::compact_pending()`, or to `DB::compact_step()` (which `AsyncDB` calls when idle),
+    /// unless level 0 gets too large. This keeps long-running level compactions out of the write
+    /// path.
+    pub defer_compactions: bool,
+    /// If set, level compactions are done at a low I/O priority, and the table files written by
+    /// them are evicted from the OS page cache (see `env::IoPriority`).
+    pub low_priority_compaction_io: bool,
+    /// Limits the rate at which compactions write table files.
+    pub rate_limiter: Option<Shared<RateLimiter>>,
+    /// If non-zero, compactions read their input tables in chunks of this size instead of block
+    /// by block. This speeds up compactions on spinning disks and network file systems.
+    pub compaction_readahead_size: usize,
     pub block_cache: Shared<Cache<Block>>,
+    /// Blocks evicted from the block cache are kept in this cache on disk. It should only be used
+    /// together with one block cache.
+    pub secondary_cache: Option<Shared<SecondaryCache>>,
+    /// If set, reads of data blocks through the block cache are recorded here, e.g. to find a
+    /// good block cache size with `cache_sim::simulate()`.
+    pub block_trace: Option<Shared<BlockTrace>>,
+    /// If set, the database records which data blocks are in the block cache when it is closed,
+    /// and loads them again when it is opened (see `DB::prefetch_range()`). This avoids a slow,
+    /// cold cache after restarts.
+    pub persist_hot_blocks: bool,
     pub block_size: usize,
     pub block_restart_interval: usize,
+    /// Whether index blocks store a short separator between two data blocks instead of the last
+    /// key of a block (see `Cmp::find_shortest_sep()`). Shortening makes index blocks smaller,
+    /// but a lookup of a key between a block's last key and the separator reads that block in
+    /// vain.
+    pub shorten_index_keys: bool,
+    /// A filter is generated per `2^filter_base_lg2` bytes of table data (clamped to 8..=30).
+    /// Smaller values yield more precise filters for small blocks, at the cost of a larger
+    /// filter block. Tables remain readable by the original LevelDB.
+    pub filter_base_lg2: u32,
+    /// If set, values in data blocks are prefix-encoded against the previous value, like keys
+    /// are. This shrinks tables whose consecutive values are similar (versioned rows, counters),
+    /// at a small cost for other values. Blocks are flagged, so tables can mix both formats;
+    /// the original LevelDB can't read such blocks.
+    pub value_delta_encoding: bool,
+    /// Whether the filter policy is applied to keys. If unset (and no prefixes are filtered),
+    /// tables are written without a filter block, which saves space when most lookups are for
+    /// existing keys.
+    pub whole_key_filtering: bool,
+    /// Determines the prefixes of keys for prefix filtering.
+    pub prefix_extractor: Option<Rc<Box<dyn PrefixExtractor>>>,
+    /// If set and a prefix extractor is configured, the filter policy is also applied to the
+    /// prefixes of keys. Lookups use whole keys where the filter contains them, and prefixes
+    /// otherwise; prefix seeks need prefixes. Filtering only prefixes yields smaller filters for
+    /// keyspaces with many keys per prefix. The original LevelDB can't read prefix filters.
+    pub prefix_filtering: bool,
+    /// If set and a prefix extractor is configured, compactions start a new output file where
+    /// the prefix of the keys changes, so that no table file contains keys of two prefixes. This
+    /// lets `DB::delete_prefix()` and `DB::delete_files_in_range()` remove whole files, at the
+    /// cost of more (and smaller) files if prefixes have few keys.
+    pub partition_by_prefix: bool,
     /// Compressor id in compressor list
     ///
     /// Note: you have to open a database with the same compression type as it was written to, in
@@ -46,6 +169,68 @@ pub struct Options {
     pub reuse_logs: bool,
     pub reuse_manifest: bool,
     pub filter_policy: filter::BoxedFilterPolicy,
+    /// Whether to record latency histograms of common operations. See `DB::statistics()`.
+    pub statistics: bool,
+    /// If not zero, one in this many keys read by `DB::get()` is sampled at random, in order to
+    /// find the most frequently read keys and prefixes. See `DB::hot_keys()`.
+    pub hot_key_sample_rate: usize,
+    /// How input files for size compactions are chosen. See `CompactionPri`.
+    pub compaction_pri: CompactionPri,
+    /// Whether files that are read from often without yielding a result are compacted
+    /// ("seek compaction"). Disable this for read-heavy workloads on rarely-written ranges.
+    pub seek_compaction: bool,
+    /// A new table file allows one unproductive seek per `bytes_per_seek` bytes (but at least
+    /// 100) before it is scheduled for a seek compaction.
+    pub bytes_per_seek: usize,
+    /// If greater than 0, a table file (outside of the last level) in which at least this share
+    /// (0 to 1) of entries are deletion markers is compacted even if its level isn't full. This
+    /// reclaims space after bulk deletions. Files written by older versions of this library are
+    /// only considered after their properties have been read once.
+    pub deletion_compaction_ratio: f64,
+    /// If greater than 0, files are compacted towards the last level while the estimated space
+    /// amplification (see `DB::space_amplification()`) exceeds this value, even if their levels
+    /// aren't full. This reclaims the space of overwritten and deleted entries. Values below 1
+    /// have the same effect as 1: compacting until all files are in one level.
+    pub max_space_amplification: f64,
+    /// If set, only files that the original C++ LevelDB can read are written: Tables are
+    /// compressed with Snappy or not at all, use the builtin bloom filter (or none), and don't
+    /// contain extensions like table properties. Opening a database fails with
+    /// `StatusCode::InvalidArgument` if other options are incompatible with this, and so do range
+    /// deletions (see `DB::delete_range()`). Use this for databases that must remain usable with
+    /// other LevelDB implementations and tools.
+    pub leveldb_compatible: bool,
+    /// How far writes and new files are persisted. See `Durability`.
+    pub durability: Durability,
+    /// If set, log records written without `sync` are synced at most this long after they were
+    /// written. This bounds the writes lost in a crash to a time window, without syncing every
+    /// write. `AsyncDB` syncs from its worker even while no writes arrive; with `DB`, the sync
+    /// happens on the next write or the next call of `DB::sync_log_if_due()`.
+    pub log_sync_interval: Option<Duration>,
+    /// If set, `AsyncDB` acknowledges sync writes only after a shared sync of the log, which
+    /// waits up to this long for further writes. Many concurrent sync writers then share one
+    /// sync instead of each paying for their own. See `Statistics::group_commit_sizes()`.
+    pub group_commit_delay: Option<Duration>,
+    /// If set, the database is considered idle once no writes arrived for this long. `AsyncDB`
+    /// then compacts it beyond what's required (see `DB::compact_idle()`), so that quiet periods
+    /// leave it fully compacted.
+    pub idle_compaction_delay: Option<Duration>,
+    /// If set, compactions during idle periods also compact files containing deletion markers
+    /// into the bottommost level, where the markers can be dropped.
+    pub idle_tombstone_purge: bool,
+    /// If set, `DB::auto_snapshot_if_due()` takes a named snapshot this often; `AsyncDB` calls it
+    /// periodically.
+    pub auto_snapshot_interval: Option<Duration>,
+    /// The number of automatic snapshots that are retained; older ones are released.
+    pub auto_snapshot_retain: usize,
+    /// Decides when level compactions run, and in which order. See `CompactionScheduler`.
+    pub compaction_scheduler: Rc<Box<dyn CompactionScheduler>>,
+    /// Notified when writes become delayed or stopped, and when they return to normal.
+    pub write_stall_listener: Option<Rc<Box<dyn WriteStallListener>>>,
+    /// Notified of completed flushes and compactions, and of table files being created and
+    /// deleted. See `EventListener`.
+    pub event_listener: Option<Rc<Box<dyn EventListener>>>,
+    /// If set, compactions remove the values it selects. See `CompactionFilter`.
+    pub compaction_filter: Option<Rc<Box<dyn CompactionFilter>>>,
 }
 
 #[cfg(feature = "fs")]
@@ -55,6 +240,7 @@ type DefaultEnv = crate::disk_env::PosixDiskEnv;
 type DefaultEnv = crate::mem_env::MemEnv;
 
 impl Default for Options {
+    #[allow(deprecated)]
     fn default() -> Options {
         Options {
             cmp: Rc::new(Box::new(DefaultCmp)),
@@ -64,21 +250,99 @@ impl Default for Options {
             error_if_exists: false,
             paranoid_checks: false,
             write_buffer_size: WRITE_BUFFER_SIZE,
+            memtable_shards: 1,
             max_open_files: 1 << 10,
-            max_file_size: 2 << 20,
+            max_file_size: 0,
+            target_file_size_base: 2 << 20,
+            target_file_size_multiplier: 1,
+            max_grandparent_overlap_factor: 10,
+            max_compaction_bytes: 0,
+            defer_compactions: false,
+            low_priority_compaction_io: false,
+            rate_limiter: None,
+            compaction_readahead_size: 0,
             // 2000 elements by default
             block_cache: share(Cache::new(BLOCK_CACHE_CAPACITY / BLOCK_MAX_SIZE)),
+            secondary_cache: None,
+            block_trace: None,
+            persist_hot_blocks: false,
             block_size: BLOCK_MAX_SIZE,
             block_restart_interval: 16,
+            shorten_index_keys: true,
+            filter_base_lg2: FILTER_BASE_LOG2,
+            value_delta_encoding: false,
+            whole_key_filtering: true,
+            prefix_extractor: None,
+            prefix_filtering: true,
+            partition_by_prefix: false,
             reuse_logs: true,
             reuse_manifest: true,
             compressor: 0,
             compressor_list: Rc::new(CompressorList::default()),
             filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            statistics: false,
+            hot_key_sample_rate: 0,
+            compaction_pri: CompactionPri::RoundRobin,
+            seek_compaction: true,
+            bytes_per_seek: 16 * KB,
+            deletion_compaction_ratio: 0.0,
+            max_space_amplification: 0.0,
+            leveldb_compatible: false,
+            durability: Durability::None,
+            log_sync_interval: None,
+            group_commit_delay: None,
+            idle_compaction_delay: None,
+            idle_tombstone_purge: false,
+            auto_snapshot_interval: None,
+            auto_snapshot_retain: 10,
+            compaction_scheduler: Rc::new(Box::new(DefaultCompactionScheduler)),
+            write_stall_listener: None,
+            event_listener: None,
+            compaction_filter: None,
+        }
+    }
+}
+
+/// ReadOptions controls how a single read or iterator uses the block cache (see
+/// `DB::get_with()` and `DB::new_iter_with()`), and which keys an iterator covers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadOptions {
+    /// Read blocks from the table files even if they're cached, verifying their checksums
+    /// (blocks read from files are always verified). This detects corruption of files whose
+    /// blocks were cached before the corruption occurred.
+    pub verify_checksums: bool,
+    /// Keep blocks read from the table files in the block cache. Reads that touch data of no
+    /// further interest, like bulk scans, can leave this unset in order not to evict more useful
+    /// blocks.
+    pub fill_cache: bool,
+    /// If set, iterators only return keys at or after this one. Table files with only smaller
+    /// keys aren't read. Point reads ignore it.
+    pub iterate_lower_bound: Option<Vec<u8>>,
+    /// If set, iterators only return keys before this one. Table files with only greater or
+    /// equal keys aren't read, and iterators stop at the bound instead of reading on to the next
+    /// entry. Point reads ignore it.
+    pub iterate_upper_bound: Option<Vec<u8>>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            verify_checksums: false,
+            fill_cache: true,
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
         }
     }
 }
 
+/// IngestOptions controls how `DB::ingest_external_file()` adds files to the database.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IngestOptions {
+    /// Remove the files once they were added. Files that are added as they are are hard-linked
+    /// into the database rather than copied, if the `Env` supports it.
+    pub move_files: bool,
+}
+
 /// Customize compressor method for leveldb
 ///
 /// `Default` value is like the code below
@@ -131,6 +395,363 @@ impl Default for CompressorList {
     }
 }
 
+/// OptionsWarning describes an option that `Options::validate()` adjusted, or a combination of
+/// options that is likely not intended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptionsWarning {
+    /// The name of the field the warning is about.
+    pub option: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for OptionsWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.option, self.message)
+    }
+}
+
+impl Options {
+    /// validate checks the options before a database is opened with them; `DB::open()` calls it
+    /// and logs the warnings. Values that can't work are rejected (a `block_size` or
+    /// `target_file_size_base` of zero, or `memtable_shards` with a custom comparator); values out
+    /// of range are adjusted, which is reported as a warning, as are combinations that work but
+    /// are probably not intended. Finally, the checks of `check_compatibility()` are applied.
+    pub fn validate(&mut self) -> Result<Vec<OptionsWarning>> {
+        if self.block_size == 0 {
+            return Err(Status::new(
+                StatusCode::InvalidArgument,
+                "block_size must not be zero",
+            ));
+        }
+        if self.target_file_size_base == 0 {
+            return Err(Status::new(
+                StatusCode::InvalidArgument,
+                "target_file_size_base must not be zero",
+            ));
+        }
+        if self.memtable_shards > 1 && self.cmp.id() != DefaultCmp.id() {
+            return Err(Status::new(
+                StatusCode::InvalidArgument,
+                "memtable_shards requires the bytewise comparator",
+            ));
+        }
+
+        let mut warnings = vec![];
+        let mut warn = |option, message: String| warnings.push(OptionsWarning { option, message });
+        if self.max_open_files < MIN_OPEN_FILES {
+            warn(
+                "max_open_files",
+                format!(
+                    "{} is below the minimum, using {}",
+                    self.max_open_files, MIN_OPEN_FILES
+                ),
+            );
+            self.max_open_files = MIN_OPEN_FILES;
+        }
+        if self.block_restart_interval == 0 {
+            warn(
+                "block_restart_interval",
+                "0 is invalid, using 1".to_string(),
+            );
+            self.block_restart_interval = 1;
+        }
+        if self.memtable_shards == 0 {
+            warn("memtable_shards", "0 is invalid, using 1".to_string());
+            self.memtable_shards = 1;
+        }
+        if !(8..=30).contains(&self.filter_base_lg2) {
+            let lg2 = self.filter_base_lg2.clamp(8, 30);
+            warn(
+                "filter_base_lg2",
+                format!("{} is out of range, using {}", self.filter_base_lg2, lg2),
+            );
+            self.filter_base_lg2 = lg2;
+        }
+        let cache_size = self
+            .block_cache
+            .borrow()
+            .cap()
+            .saturating_mul(self.block_size);
+        if self.write_buffer_size > cache_size {
+            warn(
+                "write_buffer_size",
+                format!(
+                    "{} bytes is larger than the block cache (about {} bytes); recently flushed \
+                     tables won't fit into it",
+                    self.write_buffer_size, cache_size
+                ),
+            );
+        }
+
+        check_compatibility(self)?;
+        Ok(warnings)
+    }
+}
+
+/// OptionsBuilder builds `Options` with typed setters, optionally starting from a preset tuned for
+/// a workload. `build()` validates the result; fields without a setter can be changed with
+/// `with()`.
+///
+/// ```
+/// use rusty_leveldb::{compressor::SnappyCompressor, OptionsBuilder};
+///
+/// let opt = OptionsBuilder::new()
+///     .point_lookup()
+///     .compressor(SnappyCompressor)
+///     .with(|o| o.seek_compaction = false)
+///     .build()
+///     .unwrap();
+/// assert_eq!(1, opt.compressor);
+/// assert!(OptionsBuilder::new().block_size(0).build().is_err());
+/// ```
+pub struct OptionsBuilder {
+    opt: Options,
+    block_cache_size: Option<usize>,
+}
+
+impl Default for OptionsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OptionsBuilder {
+    /// new starts from the default options.
+    pub fn new() -> OptionsBuilder {
+        OptionsBuilder::from_options(Options::default())
+    }
+
+    /// from_options starts from existing options.
+    pub fn from_options(opt: Options) -> OptionsBuilder {
+        OptionsBuilder {
+            opt,
+            block_cache_size: None,
+        }
+    }
+
+    /// point_lookup tunes the options for reads of single keys: a large block cache, more precise
+    /// filters and small blocks, at the cost of more memory and slower scans.
+    pub fn point_lookup(self) -> Self {
+        self.block_cache_size(64 * MB)
+            .block_size(BLOCK_MAX_SIZE / 2)
+            .bits_per_key(16)
+            .with(|o| o.shorten_index_keys = false)
+    }
+
+    /// bulk_load tunes the options for loading a large amount of data: a large write buffer and
+    /// large files, and no compactions until `DB::compact_pending()` or `DB::compact_range()` is
+    /// called. Reads during the load get slower as level 0 grows.
+    pub fn bulk_load(self) -> Self {
+        self.write_buffer_size(64 * MB).with(|o| {
+            o.target_file_size_base = 16 * MB;
+            o.defer_compactions = true;
+            o.seek_compaction = false;
+            o.durability = Durability::None;
+        })
+    }
+
+    /// small_footprint tunes the options for little memory and few open files, e.g. on embedded
+    /// devices, at the cost of throughput.
+    pub fn small_footprint(self) -> Self {
+        self.write_buffer_size(256 * KB)
+            .block_cache_size(256 * KB)
+            .max_open_files(64)
+            .compressor(compressor::SnappyCompressor)
+            .with(|o| o.target_file_size_base = MB)
+    }
+
+    pub fn env<E: Env + 'static>(mut self, env: E) -> Self {
+        self.opt.env = Rc::new(Box::new(env));
+        self
+    }
+
+    pub fn comparator<C: Cmp + 'static>(mut self, cmp: C) -> Self {
+        self.opt.cmp = Rc::new(Box::new(cmp));
+        self
+    }
+
+    pub fn create_if_missing(mut self, create: bool) -> Self {
+        self.opt.create_if_missing = create;
+        self
+    }
+
+    pub fn error_if_exists(mut self, error: bool) -> Self {
+        self.opt.error_if_exists = error;
+        self
+    }
+
+    pub fn paranoid_checks(mut self, paranoid: bool) -> Self {
+        self.opt.paranoid_checks = paranoid;
+        self
+    }
+
+    pub fn write_buffer_size(mut self, bytes: usize) -> Self {
+        self.opt.write_buffer_size = bytes;
+        self
+    }
+
+    pub fn max_open_files(mut self, n: usize) -> Self {
+        self.opt.max_open_files = n;
+        self
+    }
+
+    pub fn block_size(mut self, bytes: usize) -> Self {
+        self.opt.block_size = bytes;
+        self
+    }
+
+    pub fn block_restart_interval(mut self, n: usize) -> Self {
+        self.opt.block_restart_interval = n;
+        self
+    }
+
+    /// block_cache_size replaces the block cache with one holding about `bytes` of blocks of
+    /// the final `block_size`.
+    pub fn block_cache_size(mut self, bytes: usize) -> Self {
+        self.block_cache_size = Some(bytes);
+        self
+    }
+
+    /// compressor compresses new blocks with c, which is added to the compressor list.
+    pub fn compressor<C: Compressor + CompressorId + 'static>(mut self, c: C) -> Self {
+        self.opt.compressor = C::ID;
+        match Rc::get_mut(&mut self.opt.compressor_list) {
+            Some(list) => list.set(c),
+            None => {
+                let mut list = CompressorList::default();
+                list.set(c);
+                self.opt.compressor_list = Rc::new(list);
+            }
+        }
+        self
+    }
+
+    pub fn filter_policy<F: FilterPolicy + 'static>(mut self, policy: F) -> Self {
+        self.opt.filter_policy = Rc::new(Box::new(policy));
+        self
+    }
+
+    /// bits_per_key uses a bloom filter with the given precision.
+    pub fn bits_per_key(self, bits: u32) -> Self {
+        self.filter_policy(filter::BloomPolicy::new(bits))
+    }
+
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.opt.durability = durability;
+        self
+    }
+
+    pub fn statistics(mut self, statistics: bool) -> Self {
+        self.opt.statistics = statistics;
+        self
+    }
+
+    pub fn leveldb_compatible(mut self, compatible: bool) -> Self {
+        self.opt.leveldb_compatible = compatible;
+        self
+    }
+
+    /// with applies f to the options, for fields without a setter.
+    pub fn with<F: FnOnce(&mut Options)>(mut self, f: F) -> Self {
+        f(&mut self.opt);
+        self
+    }
+
+    /// build validates the options (see `Options::validate()`), discarding warnings.
+    pub fn build(self) -> Result<Options> {
+        self.build_with_warnings().map(|(opt, _)| opt)
+    }
+
+    /// build_with_warnings validates the options, returning them together with the warnings.
+    pub fn build_with_warnings(mut self) -> Result<(Options, Vec<OptionsWarning>)> {
+        if let Some(bytes) = self.block_cache_size {
+            let entries = bytes / self.opt.block_size.max(1);
+            self.opt.block_cache = share(Cache::new(entries.max(1)));
+        }
+        let warnings = self.opt.validate()?;
+        Ok((self.opt, warnings))
+    }
+}
+
+/// target_file_size returns the size at which table files in the given level are split.
+pub fn target_file_size(opt: &Options, level: usize) -> usize {
+    #[allow(deprecated)]
+    let mut size = if opt.max_file_size > 0 {
+        opt.max_file_size
+    } else {
+        opt.target_file_size_base
+    };
+    for _ in 1..level {
+        size = size.saturating_mul(opt.target_file_size_multiplier.max(1));
+    }
+    size
+}
+
+/// max_compaction_bytes returns the limit for expanding a compaction from the given level.
+pub fn max_compaction_bytes(opt: &Options, level: usize) -> usize {
+    if opt.max_compaction_bytes > 0 {
+        opt.max_compaction_bytes
+    } else {
+        target_file_size(opt, level).saturating_mul(25)
+    }
+}
+
+/// check_range_deletion returns an error if opt.leveldb_compatible is set: LevelDB can't read the
+/// log records and manifest edits of range deletions (see `DB::delete_range()`).
+pub fn check_range_deletion(opt: &Options) -> Result<()> {
+    if opt.leveldb_compatible {
+        return Err(Status::new(
+            StatusCode::InvalidArgument,
+            "range deletions are not supported by LevelDB (leveldb_compatible is set)",
+        ));
+    }
+    Ok(())
+}
+
+/// check_compatibility returns an error if opt.leveldb_compatible is set, but other options
+/// would result in files that the original LevelDB can't read.
+pub fn check_compatibility(opt: &Options) -> Result<()> {
+    if !opt.leveldb_compatible {
+        return Ok(());
+    }
+    if opt.compressor != compressor::NoneCompressor::ID
+        && opt.compressor != compressor::SnappyCompressor::ID
+    {
+        return Err(Status::new(
+            StatusCode::InvalidArgument,
+            &format!(
+                "compressor {} is not supported by LevelDB (leveldb_compatible is set)",
+                opt.compressor
+            ),
+        ));
+    }
+    if opt.value_delta_encoding {
+        return Err(Status::new(
+            StatusCode::InvalidArgument,
+            "value_delta_encoding is not supported by LevelDB (leveldb_compatible is set)",
+        ));
+    }
+    if opt.prefix_extractor.is_some() && opt.prefix_filtering {
+        return Err(Status::new(
+            StatusCode::InvalidArgument,
+            "prefix filters are not supported by LevelDB (leveldb_compatible is set)",
+        ));
+    }
+    let filter = opt.filter_policy.name();
+    if filter != filter::BloomPolicy::new(1).name()
+        && filter != filter::NoFilterPolicy::new().name()
+    {
+        return Err(Status::new(
+            StatusCode::InvalidArgument,
+            &format!(
+                "filter policy {} is not supported by LevelDB (leveldb_compatible is set)",
+                filter
+            ),
+        ));
+    }
+    Ok(())
+}
+
 /// Returns Options that will cause a database to exist purely in-memory instead of being stored on
 /// disk. This is useful for testing or ephemeral databases.
 pub fn in_memory() -> Options {
@@ -145,3 +766,179 @@ pub fn for_test() -> Options {
     o.log = Some(share(infolog::stderr()));
     o
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_target_file_size() {
+        let mut opt = Options::default();
+        assert_eq!(2 << 20, target_file_size(&opt, 0));
+        assert_eq!(2 << 20, target_file_size(&opt, 6));
+
+        opt.target_file_size_base = 1000;
+        opt.target_file_size_multiplier = 10;
+        assert_eq!(1000, target_file_size(&opt, 0));
+        assert_eq!(1000, target_file_size(&opt, 1));
+        assert_eq!(10000, target_file_size(&opt, 2));
+        assert_eq!(100_000_000, target_file_size(&opt, 6));
+
+        #[allow(deprecated)]
+        {
+            opt.max_file_size = 2000;
+        }
+        assert_eq!(2000, target_file_size(&opt, 1));
+        assert_eq!(20000, target_file_size(&opt, 2));
+    }
+
+    #[test]
+    fn test_options_check_compatibility() {
+        let mut opt = Options::default();
+        opt.compressor = 42;
+        assert!(check_compatibility(&opt).is_ok());
+
+        opt.leveldb_compatible = true;
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            check_compatibility(&opt).err().unwrap().code
+        );
+        opt.compressor = compressor::SnappyCompressor::ID;
+        assert!(check_compatibility(&opt).is_ok());
+        opt.filter_policy = Rc::new(Box::new(filter::NoFilterPolicy::new()));
+        assert!(check_compatibility(&opt).is_ok());
+        opt.value_delta_encoding = true;
+        assert!(check_compatibility(&opt).is_err());
+        opt.value_delta_encoding = false;
+        opt.prefix_extractor = Some(Rc::new(Box::new(filter::FixedPrefix(2))));
+        assert!(check_compatibility(&opt).is_err());
+        opt.prefix_filtering = false;
+        assert!(check_compatibility(&opt).is_ok());
+    }
+
+    #[test]
+    fn test_options_validate() {
+        let mut opt = Options::default();
+        assert_eq!(Vec::<OptionsWarning>::new(), opt.validate().unwrap());
+
+        opt.max_open_files = 3;
+        opt.memtable_shards = 0;
+        opt.filter_base_lg2 = 40;
+        opt.write_buffer_size = 64 * MB;
+        let warnings = opt.validate().unwrap();
+        assert_eq!(
+            vec![
+                "max_open_files",
+                "memtable_shards",
+                "filter_base_lg2",
+                "write_buffer_size"
+            ],
+            warnings.iter().map(|w| w.option).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "max_open_files: 3 is below the minimum, using 20",
+            warnings[0].to_string()
+        );
+        assert_eq!(
+            (20, 1, 30),
+            (opt.max_open_files, opt.memtable_shards, opt.filter_base_lg2)
+        );
+        assert_eq!(1, opt.validate().unwrap().len());
+
+        opt.block_size = 0;
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            opt.validate().err().unwrap().code
+        );
+        opt.block_size = 1024;
+        opt.leveldb_compatible = true;
+        opt.value_delta_encoding = true;
+        assert!(opt.validate().is_err());
+    }
+
+    #[test]
+    fn test_options_validate_memtable_shards() {
+        /// Orders keys case-insensitively, so that keys differing in case are equal, but would
+        /// be put into different shards.
+        struct CaseInsensitiveCmp;
+
+        impl Cmp for CaseInsensitiveCmp {
+            fn cmp(&self, a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+                a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+            }
+            fn find_shortest_sep(&self, from: &[u8], _: &[u8]) -> Vec<u8> {
+                from.to_vec()
+            }
+            fn find_short_succ(&self, key: &[u8]) -> Vec<u8> {
+                key.to_vec()
+            }
+            fn id(&self) -> &'static str {
+                "test.CaseInsensitiveCmp"
+            }
+        }
+
+        let mut opt = Options {
+            memtable_shards: 4,
+            ..Options::default()
+        };
+        assert!(opt.validate().is_ok());
+        opt.cmp = Rc::new(Box::new(CaseInsensitiveCmp));
+        assert_eq!(
+            StatusCode::InvalidArgument,
+            opt.validate().err().unwrap().code
+        );
+        opt.memtable_shards = 1;
+        assert!(opt.validate().is_ok());
+    }
+
+    #[test]
+    fn test_options_builder() {
+        let opt = OptionsBuilder::new()
+            .env(MemEnv::new())
+            .block_size(1024)
+            .block_cache_size(MB)
+            .max_open_files(100)
+            .build()
+            .unwrap();
+        assert_eq!(1024, opt.block_cache.borrow().cap());
+        assert_eq!(100, opt.max_open_files);
+
+        let opt = OptionsBuilder::new().point_lookup().build().unwrap();
+        assert_eq!(BLOCK_MAX_SIZE / 2, opt.block_size);
+        assert_eq!(32 * KB, opt.block_cache.borrow().cap());
+        let opt = OptionsBuilder::new().small_footprint().build().unwrap();
+        assert_eq!(compressor::SnappyCompressor::ID, opt.compressor);
+        assert!(opt.compressor_list.is_set(compressor::SnappyCompressor::ID));
+        assert_eq!(64, opt.block_cache.borrow().cap());
+
+        // The large write buffer of bulk_load() doesn't fit into the default block cache.
+        let (opt, warnings) = OptionsBuilder::new()
+            .bulk_load()
+            .max_open_files(1)
+            .build_with_warnings()
+            .unwrap();
+        assert!(opt.defer_compactions);
+        assert_eq!(
+            vec!["max_open_files", "write_buffer_size"],
+            warnings.iter().map(|w| w.option).collect::<Vec<_>>()
+        );
+
+        assert!(OptionsBuilder::new().block_size(0).build().is_err());
+        assert!(OptionsBuilder::new()
+            .leveldb_compatible(true)
+            .with(|o| o.value_delta_encoding = true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_options_max_compaction_bytes() {
+        let mut opt = Options::default();
+        opt.target_file_size_base = 1000;
+        opt.target_file_size_multiplier = 2;
+        assert_eq!(25000, max_compaction_bytes(&opt, 1));
+        assert_eq!(50000, max_compaction_bytes(&opt, 2));
+        opt.max_compaction_bytes = 1234;
+        assert_eq!(1234, max_compaction_bytes(&opt, 2));
+    }
+}
@@ -0,0 +1,127 @@
+//! Values returned by `DB::get_pinned()` without copying them.
+
+use crate::block::BlockContents;
+use crate::skipmap::SkipMapIter;
+
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::rc::Rc;
+
+/// Pinned is the memory a PinnedSlice points into.
+enum Pinned {
+    /// A data block, usually held by the block cache, too.
+    Block(Rc<BlockContents>),
+    /// The key of the memtable entry the iterator is positioned at. Memtable entries contain
+    /// their value, and the iterator keeps the memtable's memory alive.
+    Memtable(SkipMapIter),
+    /// A value that isn't stored contiguously, e.g. in a block with prefix-encoded values.
+    Owned(Vec<u8>),
+}
+
+/// PinnedSlice is a value that was looked up without copying it: It points into the table block
+/// or memtable entry that the value is stored in, and dereferences to `[u8]`. The block or
+/// memtable is kept in memory as long as the PinnedSlice exists, even if the block is evicted
+/// from the block cache or the memtable is compacted meanwhile; so don't hold on to it for
+/// longer than needed.
+pub struct PinnedSlice {
+    pinned: Pinned,
+    range: Range<usize>,
+}
+
+impl PinnedSlice {
+    pub(crate) fn from_block(block: Rc<BlockContents>, range: Range<usize>) -> PinnedSlice {
+        PinnedSlice {
+            pinned: Pinned::Block(block),
+            range,
+        }
+    }
+
+    /// from_memtable pins the range of the key of the entry that iter is positioned at.
+    pub(crate) fn from_memtable(iter: SkipMapIter, range: Range<usize>) -> PinnedSlice {
+        assert!(iter.current_key().is_some());
+        PinnedSlice {
+            pinned: Pinned::Memtable(iter),
+            range,
+        }
+    }
+
+    pub(crate) fn from_vec(v: Vec<u8>) -> PinnedSlice {
+        PinnedSlice {
+            range: 0..v.len(),
+            pinned: Pinned::Owned(v),
+        }
+    }
+
+    /// into_vec returns the value as vector. It's only copied if it is pinned.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.pinned {
+            Pinned::Owned(v) => v,
+            _ => self.to_vec(),
+        }
+    }
+}
+
+impl Deref for PinnedSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.pinned {
+            Pinned::Block(ref block) => &block[self.range.clone()],
+            Pinned::Memtable(ref iter) => &iter.current_key().unwrap()[self.range.clone()],
+            Pinned::Owned(ref v) => &v[self.range.clone()],
+        }
+    }
+}
+
+impl AsRef<[u8]> for PinnedSlice {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl PartialEq for PinnedSlice {
+    fn eq(&self, other: &PinnedSlice) -> bool {
+        **self == **other
+    }
+}
+
+impl fmt::Debug for PinnedSlice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PinnedSlice").field(&&**self).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::options;
+    use crate::skipmap::SkipMap;
+    use crate::types::LdbIterator;
+
+    #[test]
+    fn test_pinned_slice() {
+        let block = Rc::new(b"abcdef".to_vec());
+        let s = PinnedSlice::from_block(block.clone(), 1..4);
+        assert_eq!(b"bcd", &*s);
+        assert_eq!(2, Rc::strong_count(&block));
+        assert_eq!(b"bcd".to_vec(), s.into_vec());
+        assert_eq!(1, Rc::strong_count(&block));
+
+        let mut map = SkipMap::new(options::for_test().cmp);
+        map.insert(b"key".to_vec(), b"value".to_vec());
+        let mut iter = map.iter();
+        iter.seek(b"key");
+        let s = PinnedSlice::from_memtable(iter, 1..3);
+        drop(map);
+        assert_eq!(b"ey", &*s);
+        assert_eq!("PinnedSlice([101, 121])", format!("{:?}", s));
+
+        let s = PinnedSlice::from_vec(b"value".to_vec());
+        assert_eq!(
+            s,
+            PinnedSlice::from_block(Rc::new(b"values".to_vec()), 0..5)
+        );
+        assert_eq!(b"value".to_vec(), s.into_vec());
+    }
+}
@@ -0,0 +1,85 @@
+//! Range tombstones delete all keys in a range with a single marker (see `DB::delete_range()`).
+//!
+//! A tombstone is kept by the memtable it was written to. Table files can't store it, so it moves
+//! to the manifest when the memtable is written to a table, and stays there until no table file
+//! contains entries that it covers anymore. Reads and compactions consult the tombstones of the
+//! memtables and of the current version.
+
+use crate::cmp::Cmp;
+use crate::types::{FileNum, SequenceNumber};
+
+use std::cmp::Ordering;
+
+/// RangeTombstone hides all entries with a key in [from; to) that are older than the tombstone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeTombstone {
+    pub from: Vec<u8>,
+    pub to: Vec<u8>,
+    pub seq: SequenceNumber,
+    /// Table files numbered from here on don't contain entries covered by the tombstone; older
+    /// ones may. 0 while the tombstone is in a memtable.
+    pub first_clean_file: FileNum,
+}
+
+impl RangeTombstone {
+    pub fn new(from: &[u8], to: &[u8], seq: SequenceNumber) -> RangeTombstone {
+        RangeTombstone {
+            from: from.to_vec(),
+            to: to.to_vec(),
+            seq,
+            first_clean_file: 0,
+        }
+    }
+
+    pub fn contains(&self, cmp: &dyn Cmp, key: &[u8]) -> bool {
+        cmp.cmp(&self.from, key) <= Ordering::Equal && cmp.cmp(key, &self.to) == Ordering::Less
+    }
+
+    /// overlaps returns true if any user key in [smallest; largest] is in the tombstone's range.
+    pub fn overlaps(&self, cmp: &dyn Cmp, smallest: &[u8], largest: &[u8]) -> bool {
+        cmp.cmp(smallest, &self.to) == Ordering::Less
+            && cmp.cmp(&self.from, largest) <= Ordering::Equal
+    }
+}
+
+/// covering_seq returns the sequence number of the newest tombstone visible at seq that covers
+/// key, or 0 if there is none. Entries for key with a lower sequence number are deleted.
+pub fn covering_seq<'a, I: IntoIterator<Item = &'a RangeTombstone>>(
+    tombstones: I,
+    cmp: &dyn Cmp,
+    key: &[u8],
+    seq: SequenceNumber,
+) -> SequenceNumber {
+    tombstones
+        .into_iter()
+        .filter(|t| t.seq <= seq && t.contains(cmp, key))
+        .map(|t| t.seq)
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmp::DefaultCmp;
+
+    #[test]
+    fn test_range_del_covering_seq() {
+        let tombstones = vec![
+            RangeTombstone::new(b"b", b"d", 10),
+            RangeTombstone::new(b"c", b"f", 20),
+        ];
+        let cmp = DefaultCmp;
+        assert_eq!(0, covering_seq(&tombstones, &cmp, b"a", 30));
+        assert_eq!(10, covering_seq(&tombstones, &cmp, b"b", 30));
+        assert_eq!(20, covering_seq(&tombstones, &cmp, b"c", 30));
+        assert_eq!(10, covering_seq(&tombstones, &cmp, b"c", 19));
+        assert_eq!(0, covering_seq(&tombstones, &cmp, b"c", 9));
+        assert_eq!(0, covering_seq(&tombstones, &cmp, b"f", 30));
+
+        assert!(tombstones[0].overlaps(&cmp, b"a", b"b"));
+        assert!(tombstones[0].overlaps(&cmp, b"c", b"z"));
+        assert!(!tombstones[0].overlaps(&cmp, b"d", b"z"));
+        assert!(!tombstones[0].overlaps(&cmp, b"a", b"aa"));
+    }
+}
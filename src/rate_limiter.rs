@@ -0,0 +1,197 @@
+//! A token-bucket rate limiter for the I/O done by compactions.
+
+use crate::env::Env;
+use crate::types::Shared;
+
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// The budget is refilled in intervals of this length.
+const REFILL_PERIOD_MICROS: u64 = 100 * 1000;
+/// The minimum rate of an auto-tuned limiter is this fraction of its maximum rate.
+const AUTO_TUNE_MIN_DIVISOR: usize = 20;
+
+/// RateLimiter limits the number of bytes written by compactions per second. As compactions run
+/// synchronously, being throttled slows down the operation that triggered the compaction;
+/// combine with `Options::defer_compactions` to move throttled compactions out of the write path.
+///
+/// An auto-tuned limiter adjusts its rate between a twentieth of the maximum and the maximum
+/// rate: It speeds up when writes are stalled by compactions or compactions are pending, and
+/// slows down otherwise.
+pub struct RateLimiter {
+    env: Rc<Box<dyn Env>>,
+    bytes_per_sec: usize,
+    // Bytes that may be written in the current refill period.
+    available: usize,
+    last_refill: u64,
+
+    auto_tune: bool,
+    min_bytes_per_sec: usize,
+    max_bytes_per_sec: usize,
+    total_bytes: u64,
+}
+
+impl RateLimiter {
+    /// new returns a limiter allowing a fixed number of bytes per second.
+    pub fn new(env: Rc<Box<dyn Env>>, bytes_per_sec: usize) -> RateLimiter {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        let now = env.micros();
+        let mut rl = RateLimiter {
+            env,
+            bytes_per_sec,
+            available: 0,
+            last_refill: now,
+            auto_tune: false,
+            min_bytes_per_sec: bytes_per_sec,
+            max_bytes_per_sec: bytes_per_sec,
+            total_bytes: 0,
+        };
+        rl.available = rl.budget_per_period();
+        rl
+    }
+
+    /// new_auto_tuned returns a limiter that tunes its rate based on the database's load, never
+    /// exceeding max_bytes_per_sec.
+    pub fn new_auto_tuned(env: Rc<Box<dyn Env>>, max_bytes_per_sec: usize) -> RateLimiter {
+        let mut rl = RateLimiter::new(env, max_bytes_per_sec);
+        rl.auto_tune = true;
+        rl.min_bytes_per_sec = (rl.max_bytes_per_sec / AUTO_TUNE_MIN_DIVISOR).max(1);
+        rl.bytes_per_sec = rl.max_bytes_per_sec / 2;
+        rl
+    }
+
+    pub fn bytes_per_sec(&self) -> usize {
+        self.bytes_per_sec
+    }
+
+    pub fn set_bytes_per_sec(&mut self, bytes_per_sec: usize) {
+        self.bytes_per_sec = bytes_per_sec.max(1);
+        self.min_bytes_per_sec = self.min_bytes_per_sec.min(self.bytes_per_sec);
+        self.max_bytes_per_sec = self.max_bytes_per_sec.max(self.bytes_per_sec);
+    }
+
+    /// total_bytes returns the number of bytes requested through this limiter.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    fn budget_per_period(&self) -> usize {
+        (self.bytes_per_sec as u64 * REFILL_PERIOD_MICROS / 1000000).max(1) as usize
+    }
+
+    fn refill(&mut self) {
+        let now = self.env.micros();
+        if now >= self.last_refill + REFILL_PERIOD_MICROS {
+            self.available = self.budget_per_period();
+            self.last_refill = now;
+        }
+    }
+
+    /// request blocks until n bytes may be written.
+    pub fn request(&mut self, mut n: usize) {
+        self.total_bytes += n as u64;
+        while n > 0 {
+            self.refill();
+            if self.available == 0 {
+                let next_refill = self.last_refill + REFILL_PERIOD_MICROS;
+                let wait = next_refill.saturating_sub(self.env.micros());
+                self.env.sleep_for(wait.max(1) as u32);
+                continue;
+            }
+            let granted = n.min(self.available);
+            self.available -= granted;
+            n -= granted;
+        }
+    }
+
+    /// tune adjusts the rate of an auto-tuned limiter. It is called after every compaction with
+    /// the number of writes stalled by compactions since the last call, and whether more
+    /// compactions are pending.
+    pub fn tune(&mut self, stalled_writes: u64, compaction_pending: bool) {
+        if !self.auto_tune {
+            return;
+        }
+        if stalled_writes > 0 || compaction_pending {
+            self.bytes_per_sec = (self.bytes_per_sec + self.bytes_per_sec / 4)
+                .max(self.bytes_per_sec + 1)
+                .min(self.max_bytes_per_sec);
+        } else {
+            self.bytes_per_sec =
+                (self.bytes_per_sec - self.bytes_per_sec / 20).max(self.min_bytes_per_sec);
+        }
+    }
+}
+
+/// RateLimitedWriter requests every write from a RateLimiter before passing it on.
+pub struct RateLimitedWriter<W: Write> {
+    w: W,
+    limiter: Shared<RateLimiter>,
+}
+
+impl<W: Write> RateLimitedWriter<W> {
+    pub fn new(w: W, limiter: Shared<RateLimiter>) -> RateLimitedWriter<W> {
+        RateLimitedWriter { w, limiter }
+    }
+}
+
+impl<W: Write> Write for RateLimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.limiter.borrow_mut().request(buf.len());
+        self.w.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_env::MemEnv;
+    use crate::types::share;
+
+    #[test]
+    fn test_rate_limiter_request() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        // 1000 bytes per refill period.
+        let mut rl = RateLimiter::new(env.clone(), 10000);
+        let start = env.micros();
+        rl.request(2500);
+        // 1000 bytes are available immediately, the rest in the next two periods.
+        assert!(env.micros() - start >= 2 * REFILL_PERIOD_MICROS);
+        assert_eq!(2500, rl.total_bytes());
+    }
+
+    #[test]
+    fn test_rate_limiter_writer() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let rl = share(RateLimiter::new(env, 1 << 30));
+        let mut w = RateLimitedWriter::new(vec![], rl.clone());
+        w.write_all(b"abcdef").unwrap();
+        assert_eq!(b"abcdef".to_vec(), w.w);
+        assert_eq!(6, rl.borrow().total_bytes());
+    }
+
+    #[test]
+    fn test_rate_limiter_auto_tune() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let mut rl = RateLimiter::new_auto_tuned(env.clone(), 20000);
+        assert_eq!(10000, rl.bytes_per_sec());
+
+        rl.tune(1, false);
+        assert_eq!(12500, rl.bytes_per_sec());
+        for _ in 0..10 {
+            rl.tune(0, true);
+        }
+        assert_eq!(20000, rl.bytes_per_sec());
+        for _ in 0..1000 {
+            rl.tune(0, false);
+        }
+        assert_eq!(1000, rl.bytes_per_sec());
+
+        // A fixed limiter is not tuned.
+        let mut rl = RateLimiter::new(env, 20000);
+        rl.tune(1, true);
+        assert_eq!(20000, rl.bytes_per_sec());
+    }
+}
@@ -0,0 +1,329 @@
+//! Salvaging a database whose MANIFEST is lost or damaged, like `leveldb::RepairDB()`.
+
+use crate::db_impl::{build_table, lock_db, log_file_name};
+use crate::error::{err, Result, StatusCode};
+use crate::key_types::{parse_internal_key, ValueType};
+use crate::log::{LogReader, LogWriter};
+use crate::memtable::MemTable;
+use crate::options::Options;
+use crate::table_cache::table_file_name;
+use crate::table_reader::Table;
+use crate::types::{parse_file_name, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber};
+use crate::version_edit::VersionEdit;
+use crate::version_set::{manifest_file_name, set_current_file};
+use crate::write_batch::WriteBatch;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The directory below the database's, into which files that are no longer used are moved.
+const LOST_DIR: &str = "lost";
+
+/// RepairReport is the result of `repair_db()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepairReport {
+    /// The number of tables in the repaired database.
+    pub tables: usize,
+    /// The number of log files whose records were written to tables.
+    pub logs: usize,
+    /// The number of damaged tables whose readable entries were copied to new tables.
+    pub salvaged_tables: usize,
+    /// The files moved to the `lost` directory, i.e. the old manifests, the converted logs, and
+    /// the damaged or unreadable tables.
+    pub lost: Vec<PathBuf>,
+    pub last_sequence: SequenceNumber,
+}
+
+/// repair_db rebuilds the MANIFEST of the database at name from the files found in its
+/// directory, when the database can't be opened anymore: The records of its log files are written
+/// to new tables, the readable entries of damaged tables are copied to new tables, and all tables
+/// are placed in level 0 (compactions move them back down). The files that are replaced are
+/// moved to the `lost` subdirectory, rather than deleted.
+///
+/// Data may be lost: Damaged log records and blocks of tables are dropped, and entries that were
+/// deleted may reappear if the deletion was in a dropped block. Range deletions (see
+/// `DB::delete_range()`) are not recovered either. The database must not be open.
+pub fn repair_db<P: AsRef<Path>>(name: P, opt: Options) -> Result<RepairReport> {
+    let path = name.as_ref();
+    let env = opt.env.clone();
+    let lock = lock_db(env.as_ref().as_ref(), path)?;
+    let mut repairer = Repairer {
+        path,
+        opt,
+        next_file: 1,
+        report: RepairReport::default(),
+    };
+    let r = repairer.run();
+    env.unlock(lock)?;
+    r.map(|_| repairer.report)
+}
+
+struct Repairer<'a> {
+    path: &'a Path,
+    opt: Options,
+    next_file: FileNum,
+    report: RepairReport,
+}
+
+impl<'a> Repairer<'a> {
+    fn run(&mut self) -> Result<()> {
+        let (mut manifests, mut logs, mut tables) = (vec![], vec![], vec![]);
+        for name in self.opt.env.children(self.path)? {
+            if let Ok((num, typ)) = parse_file_name(&name) {
+                match typ {
+                    FileType::Descriptor => manifests.push(name),
+                    FileType::Log => logs.push(num),
+                    FileType::Table => tables.push(num),
+                    _ => {}
+                }
+                self.next_file = self.next_file.max(num + 1);
+            }
+        }
+        if manifests.is_empty() && logs.is_empty() && tables.is_empty() {
+            return err(StatusCode::NotFound, "repair found no database files");
+        }
+
+        logs.sort_unstable();
+        for num in logs {
+            match self.convert_log(num) {
+                Ok(table) => {
+                    tables.extend(table);
+                    self.report.logs += 1;
+                }
+                Err(e) => {
+                    log!(self.opt.log, "Repair: log {:06} ignored: {}", num, e);
+                }
+            }
+            self.archive(&log_file_name(self.path, num));
+        }
+
+        let mut edit = VersionEdit::new();
+        let mut last_seq = 0;
+        for num in tables {
+            match self.scan_table(num) {
+                Ok(Some((md, max_seq))) => {
+                    last_seq = last_seq.max(max_seq);
+                    edit.add_file(0, md);
+                    self.report.tables += 1;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log!(self.opt.log, "Repair: table {:06} ignored: {}", num, e);
+                    self.archive(&table_file_name(self.path, num));
+                }
+            }
+        }
+        self.report.last_sequence = last_seq;
+
+        let manifest_num = self.next_file;
+        self.next_file += 1;
+        edit.set_comparator_name(self.opt.cmp.id());
+        edit.set_log_num(0);
+        edit.set_next_file(self.next_file);
+        edit.set_last_seq(last_seq);
+        self.write_manifest(manifest_num, &edit)?;
+        for name in manifests {
+            self.archive(&self.path.join(name));
+        }
+        set_current_file(&self.opt.env, self.path, manifest_num)
+    }
+
+    /// convert_log writes the intact records of a log file to a new table, and returns its
+    /// number, if there were any.
+    fn convert_log(&mut self, num: FileNum) -> Result<Option<FileNum>> {
+        let file = self
+            .opt
+            .env
+            .open_sequential_file(&log_file_name(self.path, num))?;
+        let mut reader = LogReader::new(file, true);
+        let mut mem = MemTable::new(self.opt.cmp.clone());
+        let (mut scratch, mut batch) = (vec![], WriteBatch::new());
+        loop {
+            match reader.read(&mut scratch) {
+                Ok(0) => break,
+                Ok(len) if len < 12 => {
+                    log!(self.opt.log, "Repair: log {:06}: record too short", num);
+                }
+                Ok(_) => {
+                    batch.set_contents(&scratch);
+                    batch.insert_into_memtable(batch.sequence(), &mut mem);
+                }
+                // The reader continues after the damaged record.
+                Err(ref e) if e.code == StatusCode::Corruption => {
+                    log!(self.opt.log, "Repair: log {:06}: {}", num, e);
+                }
+                // A truncated record at the end of the file.
+                Err(_) => break,
+            }
+        }
+        if mem.len() == 0 {
+            return Ok(None);
+        }
+        let table = self.new_file_number();
+        build_table(self.path, &self.opt, mem.iter(), table)?;
+        Ok(Some(table))
+    }
+
+    /// scan_table returns the metadata of a table and its highest sequence number. A damaged
+    /// table is replaced by a new one with its readable entries first, and an empty table is
+    /// dropped (None).
+    fn scan_table(&mut self, num: FileNum) -> Result<Option<(FileMetaData, SequenceNumber)>> {
+        let mut num = num;
+        let mut table = self.open_table(num)?;
+        if let Err(e) = table.verify_checksums(None) {
+            log!(self.opt.log, "Repair: table {:06} is damaged: {}", num, e);
+            let copy = self.new_file_number();
+            let copied = build_table(self.path, &self.opt, table.iter(), copy)?;
+            self.archive(&table_file_name(self.path, num));
+            self.report.salvaged_tables += 1;
+            if copied.size == 0 {
+                return Ok(None);
+            }
+            num = copy;
+            table = self.open_table(num)?;
+        }
+
+        let mut md = FileMetaData {
+            num,
+            size: self.opt.env.size_of(&table_file_name(self.path, num))?,
+            ..FileMetaData::default()
+        };
+        let mut max_seq = 0;
+        let mut iter = table.iter();
+        let (mut k, mut v) = (vec![], vec![]);
+        while iter.advance() {
+            iter.current(&mut k, &mut v);
+            let (typ, seq, _) = parse_internal_key(&k);
+            max_seq = max_seq.max(seq);
+            md.num_entries += 1;
+            if typ == ValueType::TypeDeletion {
+                md.num_deletions += 1;
+            }
+            if md.smallest.is_empty() {
+                md.smallest = k.clone();
+            }
+        }
+        if md.num_entries == 0 {
+            self.archive(&table_file_name(self.path, num));
+            return Ok(None);
+        }
+        md.largest = k;
+        Ok(Some((md, max_seq)))
+    }
+
+    fn open_table(&self, num: FileNum) -> Result<Table> {
+        let name = table_file_name(self.path, num);
+        let size = self.opt.env.size_of(&name)?;
+        let file = self.opt.env.open_random_access_file(&name)?;
+        Table::new(self.opt.clone(), Rc::new(file), size)
+    }
+
+    fn write_manifest(&self, num: FileNum, edit: &VersionEdit) -> Result<()> {
+        let name = manifest_file_name(self.path, num);
+        let r = (|| {
+            let mut lw = LogWriter::new(self.opt.env.open_writable_file(&name)?);
+            lw.add_record(&edit.encode())?;
+            lw.flush()?;
+            self.opt.env.sync_file(&name)
+        })();
+        if r.is_err() {
+            let _ = self.opt.env.delete(&name);
+        }
+        r
+    }
+
+    fn new_file_number(&mut self) -> FileNum {
+        self.next_file += 1;
+        self.next_file - 1
+    }
+
+    /// archive moves a file into the lost directory; failures are only logged.
+    fn archive(&mut self, file: &Path) {
+        let dir = self.path.join(LOST_DIR);
+        let _ = self.opt.env.mkdir(&dir);
+        let dest = dir.join(file.file_name().unwrap());
+        match self.opt.env.rename(file, &dest) {
+            Ok(()) => self.report.lost.push(dest),
+            Err(e) => {
+                log!(self.opt.log, "Repair: moving {:?} failed: {}", file, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::DB;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_repair_db() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        assert_eq!(
+            StatusCode::NotFound,
+            repair_db("db", opt.clone()).unwrap_err().code
+        );
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"a", b"1").unwrap();
+            db.put(b"b", b"2").unwrap();
+            db.flush_memtable().unwrap();
+            db.delete(b"a").unwrap();
+            db.put(b"c", b"3").unwrap();
+            assert_eq!(
+                StatusCode::LockError,
+                repair_db("db", opt.clone()).unwrap_err().code
+            );
+        }
+        for name in env.children(Path::new("db")).unwrap() {
+            if let Ok((_, FileType::Descriptor)) = parse_file_name(&name) {
+                env.delete(&Path::new("db").join(name)).unwrap();
+            }
+        }
+        assert!(DB::open("db", opt.clone()).is_err());
+
+        let report = repair_db("db", opt.clone()).unwrap();
+        assert_eq!(2, report.tables);
+        assert_eq!(1, report.logs);
+        assert_eq!(0, report.salvaged_tables);
+        assert_eq!(4, report.last_sequence);
+        assert!(report
+            .lost
+            .contains(&Path::new("db").join(LOST_DIR).join("000004.log")));
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            assert_eq!(None, db.get(b"a").unwrap());
+            assert_eq!(Some(b"2".to_vec()), db.get(b"b").unwrap());
+            assert_eq!(Some(b"3".to_vec()), db.get(b"c").unwrap());
+            db.put(b"d", b"4").unwrap();
+        }
+
+        // Flip a byte in the first block of each table.
+        for name in env.children(Path::new("db")).unwrap() {
+            if let Ok((_, FileType::Table)) = parse_file_name(&name) {
+                let name = Path::new("db").join(name);
+                let mut contents = vec![];
+                env.open_sequential_file(&name)
+                    .unwrap()
+                    .read_to_end(&mut contents)
+                    .unwrap();
+                contents[1] ^= 0xff;
+                env.open_writable_file(&name)
+                    .unwrap()
+                    .write_all(&contents)
+                    .unwrap();
+            }
+        }
+        let report = repair_db("db", opt.clone()).unwrap();
+        assert_eq!(2, report.salvaged_tables);
+        assert_eq!(1, report.tables);
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(Some(b"4".to_vec()), db.get(b"d").unwrap());
+        assert_eq!(None, db.get(b"b").unwrap());
+    }
+}
@@ -0,0 +1,783 @@
+//! A reader for table files written by RocksDB ("BlockBasedTable" format), so that data exported
+//! from RocksDB can be converted without external tools.
+//!
+//! The reader supports the table format versions 0 to 5, all index types (binary search, hash,
+//! two-level/partitioned, and binary search with first key), delta-encoded index values and data
+//! blocks with hash index. Blocks must be uncompressed or compressed with Snappy. Only CRC32c
+//! block checksums are verified. Range deletions (stored in a separate block) are not read.
+
+use crate::blockhandle::BlockHandle;
+use crate::compressor::{Compressor, SnappyCompressor};
+use crate::db_impl::DB;
+use crate::env::RandomAccess;
+use crate::error::{err, Result, StatusCode};
+use crate::log::unmask_crc;
+use crate::write_batch::WriteBatch;
+
+use std::collections::HashMap;
+
+use crc::crc32;
+use crc::Hasher32;
+use integer_encoding::{FixedInt, VarInt};
+
+/// Magic number of tables with the original LevelDB footer (RocksDB format version 0).
+const LEGACY_MAGIC: u64 = 0xdb4775248b80fb57;
+/// Magic number of tables with format version 1 or newer.
+const BLOCK_BASED_MAGIC: u64 = 0x88e241b785f4cff7;
+const LEGACY_FOOTER_LENGTH: usize = 48;
+const FOOTER_LENGTH: usize = 53;
+const MAX_FORMAT_VERSION: u32 = 5;
+/// Compression type and checksum following every block.
+const BLOCK_TRAILER_LENGTH: usize = 5;
+
+/// Other checksum types (xxHash variants) are not verified.
+const CRC32C_CHECKSUM: u8 = 1;
+
+const NO_COMPRESSION: u8 = 0;
+const SNAPPY_COMPRESSION: u8 = 1;
+
+const INDEX_TWO_LEVEL: u32 = 2;
+const INDEX_WITH_FIRST_KEY: u32 = 3;
+
+/// Set in the restart count of data blocks that have a hash index.
+const DATA_BLOCK_HASH_INDEX_FLAG: u32 = 1 << 31;
+
+const PROPERTIES_BLOCK: &[u8] = b"rocksdb.properties";
+const PROP_INDEX_TYPE: &str = "rocksdb.block.based.table.index.type";
+const PROP_DELTA_ENCODED: &str = "rocksdb.index.value.is.delta.encoded";
+const PROP_NUM_RANGE_DELETIONS: &str = "rocksdb.num.range-deletions";
+
+/// The type of an entry in a RocksDB table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RocksDBEntryType {
+    Deletion,
+    Value,
+    Merge,
+    SingleDeletion,
+    /// Other types, e.g. references to blob files.
+    Other(u8),
+}
+
+impl RocksDBEntryType {
+    fn from_tag(t: u8) -> RocksDBEntryType {
+        match t {
+            0 => RocksDBEntryType::Deletion,
+            1 => RocksDBEntryType::Value,
+            2 => RocksDBEntryType::Merge,
+            7 => RocksDBEntryType::SingleDeletion,
+            t => RocksDBEntryType::Other(t),
+        }
+    }
+}
+
+/// An entry of a RocksDB table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RocksDBEntry {
+    pub key: Vec<u8>,
+    pub seq: u64,
+    pub typ: RocksDBEntryType,
+    pub value: Vec<u8>,
+}
+
+/// RocksDBTable reads a table file written by RocksDB.
+pub struct RocksDBTable {
+    file: Box<dyn RandomAccess>,
+    size: usize,
+    format_version: u32,
+    checksum_type: u8,
+    index: BlockHandle,
+    index_type: u32,
+    delta_encoded: bool,
+    props: HashMap<String, Vec<u8>>,
+}
+
+impl RocksDBTable {
+    /// open reads the footer, meta index and properties of the table file of the given size.
+    pub fn open(file: Box<dyn RandomAccess>, size: usize) -> Result<RocksDBTable> {
+        if size < LEGACY_FOOTER_LENGTH {
+            return err(StatusCode::Corruption, "file is too short for a table");
+        }
+        let mut magic = [0; 8];
+        file.read_at(size - 8, &mut magic)?;
+        let (format_version, checksum_type, handles) = match u64::decode_fixed(&magic) {
+            LEGACY_MAGIC => {
+                let mut footer = vec![0; LEGACY_FOOTER_LENGTH];
+                file.read_at(size - LEGACY_FOOTER_LENGTH, &mut footer)?;
+                (0, CRC32C_CHECKSUM, footer)
+            }
+            BLOCK_BASED_MAGIC => {
+                if size < FOOTER_LENGTH {
+                    return err(StatusCode::Corruption, "file is too short for a table");
+                }
+                let mut footer = vec![0; FOOTER_LENGTH];
+                file.read_at(size - FOOTER_LENGTH, &mut footer)?;
+                let version = u32::decode_fixed(&footer[FOOTER_LENGTH - 12..FOOTER_LENGTH - 8]);
+                if version > MAX_FORMAT_VERSION {
+                    return err(
+                        StatusCode::NotSupported,
+                        &format!("RocksDB table format version {} is not supported", version),
+                    );
+                }
+                (version, footer[0], footer[1..].to_vec())
+            }
+            m => {
+                return err(
+                    StatusCode::NotSupported,
+                    &format!("not a RocksDB block-based table (magic number {:x})", m),
+                )
+            }
+        };
+        let (metaindex, n) = decode_handle(&handles)?;
+        let (index, _) = decode_handle(&handles[n..])?;
+
+        let mut table = RocksDBTable {
+            file,
+            size,
+            format_version,
+            checksum_type,
+            index,
+            index_type: 0,
+            delta_encoded: false,
+            props: HashMap::new(),
+        };
+        let metaindex = decode_entries(&table.read_block(&metaindex)?)?;
+        if let Some((_, h)) = metaindex.iter().find(|(k, _)| k == PROPERTIES_BLOCK) {
+            let (h, _) = decode_handle(h)?;
+            for (k, v) in decode_entries(&table.read_block(&h)?)? {
+                table
+                    .props
+                    .insert(String::from_utf8_lossy(&k).into_owned(), v);
+            }
+        }
+        if let Some(t) = table.props.get(PROP_INDEX_TYPE) {
+            if t.len() != 4 {
+                return err(StatusCode::Corruption, "invalid index type property");
+            }
+            table.index_type = u32::decode_fixed(t);
+        }
+        table.delta_encoded = table.property_u64(PROP_DELTA_ENCODED).unwrap_or(0) != 0;
+        Ok(table)
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// properties returns the table's properties, e.g. "rocksdb.num.entries" or
+    /// "rocksdb.comparator".
+    pub fn properties(&self) -> &HashMap<String, Vec<u8>> {
+        &self.props
+    }
+
+    /// property_u64 returns the value of a numeric (varint-encoded) property.
+    pub fn property_u64(&self, name: &str) -> Option<u64> {
+        self.props
+            .get(name)
+            .and_then(|v| u64::decode_var(v))
+            .map(|(n, _)| n)
+    }
+
+    /// iter returns an iterator over all entries, in the order they are stored (ascending user
+    /// keys, and descending sequence numbers for equal user keys).
+    pub fn iter(&self) -> Result<RocksDBTableIter<'_>> {
+        let mut blocks = self.decode_index(&self.read_block(&self.index)?)?;
+        if self.index_type == INDEX_TWO_LEVEL {
+            let mut data_blocks = vec![];
+            for partition in &blocks {
+                data_blocks.extend(self.decode_index(&self.read_block(partition)?)?);
+            }
+            blocks = data_blocks;
+        }
+        blocks.reverse();
+        Ok(RocksDBTableIter {
+            table: self,
+            blocks,
+            entries: vec![].into_iter(),
+        })
+    }
+
+    /// write_to writes the newest version of every key in this table to db: values are put, and
+    /// deleted keys are deleted. It returns the number of keys written. Tables containing merge
+    /// operands, range deletions or other unsupported entries are rejected, whereas entries
+    /// written before the failure stay in db.
+    pub fn write_to(&self, db: &mut DB) -> Result<usize> {
+        if self.property_u64(PROP_NUM_RANGE_DELETIONS).unwrap_or(0) > 0 {
+            return err(
+                StatusCode::NotSupported,
+                "table contains range deletions, which can't be converted",
+            );
+        }
+        let mut n = 0;
+        let mut batch = WriteBatch::new();
+        let mut last_key: Option<Vec<u8>> = None;
+        for e in self.iter()? {
+            let e = e?;
+            if last_key.as_ref() == Some(&e.key) {
+                // Older version of the same key.
+                continue;
+            }
+            match e.typ {
+                RocksDBEntryType::Value => batch.put(&e.key, &e.value),
+                RocksDBEntryType::Deletion | RocksDBEntryType::SingleDeletion => {
+                    batch.delete(&e.key)
+                }
+                t => {
+                    return err(
+                        StatusCode::NotSupported,
+                        &format!("can't convert entry of type {:?}", t),
+                    )
+                }
+            }
+            n += 1;
+            last_key = Some(e.key);
+            if batch.count() >= 1000 {
+                db.write(std::mem::replace(&mut batch, WriteBatch::new()), false)?;
+            }
+        }
+        if batch.count() > 0 {
+            db.write(batch, false)?;
+        }
+        Ok(n)
+    }
+
+    /// read_block reads, verifies and decompresses the block at h.
+    fn read_block(&self, h: &BlockHandle) -> Result<Vec<u8>> {
+        let end = h.offset().checked_add(h.size() + BLOCK_TRAILER_LENGTH);
+        if end.is_none_or(|end| end > self.size) {
+            return err(
+                StatusCode::Corruption,
+                &format!("block at {} exceeds the file", h.offset()),
+            );
+        }
+        let mut buf = vec![0; h.size() + BLOCK_TRAILER_LENGTH];
+        if self.file.read_at(h.offset(), &mut buf)? < buf.len() {
+            return err(StatusCode::Corruption, "short read of block");
+        }
+        let compression = buf[h.size()];
+        if self.checksum_type == CRC32C_CHECKSUM {
+            let want = unmask_crc(u32::decode_fixed(&buf[h.size() + 1..]));
+            let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
+            digest.write(&buf[..h.size() + 1]);
+            if digest.sum32() != want {
+                return err(
+                    StatusCode::Corruption,
+                    &format!("checksum verification failed for block at {}", h.offset()),
+                );
+            }
+        }
+        buf.truncate(h.size());
+        match compression {
+            NO_COMPRESSION => Ok(buf),
+            SNAPPY_COMPRESSION => SnappyCompressor.decode(buf),
+            c => err(
+                StatusCode::NotSupported,
+                &format!("compression type {} is not supported", c),
+            ),
+        }
+    }
+
+    /// decode_index returns the block handles stored in an index block.
+    fn decode_index(&self, block: &[u8]) -> Result<Vec<BlockHandle>> {
+        let first_key = self.index_type == INDEX_WITH_FIRST_KEY;
+        let mut handles: Vec<BlockHandle> = vec![];
+        let mut r = BlockReader::new(block, !self.delta_encoded)?;
+        while let Some((shared, value)) = r.next_entry()? {
+            let mut value = value;
+            let (h, n) = if !self.delta_encoded || shared == 0 {
+                decode_handle(value)?
+            } else {
+                // Only the size difference to the previous (adjacent) block is stored.
+                let prev = match handles.last() {
+                    Some(prev) => prev,
+                    None => return err(StatusCode::Corruption, "delta-encoded first handle"),
+                };
+                let (delta, n) = varint::<i64>(value)?;
+                let size = prev.size() as i64 + delta;
+                if size < 0 {
+                    return err(StatusCode::Corruption, "invalid delta-encoded handle");
+                }
+                (
+                    BlockHandle::new(
+                        prev.offset() + prev.size() + BLOCK_TRAILER_LENGTH,
+                        size as usize,
+                    ),
+                    n,
+                )
+            };
+            value = &value[n..];
+            if first_key {
+                let (len, n) = varint::<usize>(value)?;
+                if n + len > value.len() {
+                    return err(StatusCode::Corruption, "truncated index entry");
+                }
+                value = &value[n + len..];
+            }
+            if self.delta_encoded {
+                // Without explicit value lengths, the reader continues after the handle.
+                r.consume(value.len());
+            }
+            handles.push(h);
+        }
+        Ok(handles)
+    }
+}
+
+/// RocksDBTableIter iterates over the entries of a RocksDB table, reading one data block at a
+/// time.
+pub struct RocksDBTableIter<'a> {
+    table: &'a RocksDBTable,
+    // Remaining data blocks, in reverse order.
+    blocks: Vec<BlockHandle>,
+    entries: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> Iterator for RocksDBTableIter<'a> {
+    type Item = Result<RocksDBEntry>;
+
+    fn next(&mut self) -> Option<Result<RocksDBEntry>> {
+        loop {
+            if let Some((mut k, v)) = self.entries.next() {
+                if k.len() < 8 {
+                    self.blocks.clear();
+                    return Some(err(StatusCode::Corruption, "invalid internal key"));
+                }
+                let tag = u64::decode_fixed(&k[k.len() - 8..]);
+                k.truncate(k.len() - 8);
+                return Some(Ok(RocksDBEntry {
+                    key: k,
+                    seq: tag >> 8,
+                    typ: RocksDBEntryType::from_tag(tag as u8),
+                    value: v,
+                }));
+            }
+            let h = self.blocks.pop()?;
+            let r = self
+                .table
+                .read_block(&h)
+                .and_then(|b| decode_entries(&strip_hash_index(b)?));
+            match r {
+                Ok(entries) => self.entries = entries.into_iter(),
+                Err(e) => {
+                    self.blocks.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+fn varint<T: VarInt>(src: &[u8]) -> Result<(T, usize)> {
+    match T::decode_var(src) {
+        Some(r) => Ok(r),
+        None => err(StatusCode::Corruption, "truncated varint in block"),
+    }
+}
+
+fn decode_handle(src: &[u8]) -> Result<(BlockHandle, usize)> {
+    match BlockHandle::decode(src) {
+        Some(r) => Ok(r),
+        None => err(StatusCode::Corruption, "couldn't decode block handle"),
+    }
+}
+
+/// strip_hash_index removes the hash index from a data block, if it has one.
+fn strip_hash_index(mut block: Vec<u8>) -> Result<Vec<u8>> {
+    if block.len() < 4 {
+        return err(StatusCode::Corruption, "block is too short");
+    }
+    let packed = u32::decode_fixed(&block[block.len() - 4..]);
+    if packed & DATA_BLOCK_HASH_INDEX_FLAG == 0 {
+        return Ok(block);
+    }
+    // [entries][restarts][buckets: u8 each][num buckets: u16][packed restart count: u32]
+    if block.len() < 6 {
+        return err(StatusCode::Corruption, "block is too short");
+    }
+    let num_buckets = u16::decode_fixed(&block[block.len() - 6..block.len() - 4]) as usize;
+    if block.len() < 6 + num_buckets {
+        return err(StatusCode::Corruption, "invalid data block hash index");
+    }
+    let restarts_end = block.len() - 6 - num_buckets;
+    block.truncate(restarts_end);
+    block.extend_from_slice(&(packed & !DATA_BLOCK_HASH_INDEX_FLAG).encode_fixed_vec());
+    Ok(block)
+}
+
+/// decode_entries returns all key/value pairs of a block in the original LevelDB format.
+fn decode_entries(block: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut r = BlockReader::new(block, true)?;
+    let mut entries = vec![];
+    while let Some((_, v)) = r.next_entry()? {
+        let v = v.to_vec();
+        entries.push((r.key.clone(), v));
+    }
+    Ok(entries)
+}
+
+/// BlockReader decodes the entries of a block sequentially, checking all bounds.
+struct BlockReader<'a> {
+    block: &'a [u8],
+    // The end of the entries (beginning of the restart array).
+    end: usize,
+    off: usize,
+    has_value_length: bool,
+    key: Vec<u8>,
+}
+
+impl<'a> BlockReader<'a> {
+    fn new(block: &'a [u8], has_value_length: bool) -> Result<BlockReader<'a>> {
+        if block.len() < 4 {
+            return err(StatusCode::Corruption, "block is too short");
+        }
+        let restarts = u32::decode_fixed(&block[block.len() - 4..]) as usize;
+        let end = restarts
+            .checked_mul(4)
+            .and_then(|r| block.len().checked_sub(4 + r));
+        match end {
+            Some(end) => Ok(BlockReader {
+                block,
+                end,
+                off: 0,
+                has_value_length,
+                key: vec![],
+            }),
+            None => err(StatusCode::Corruption, "invalid restart count in block"),
+        }
+    }
+
+    /// next_entry decodes the next entry into self.key, and returns the number of shared key
+    /// bytes and the value. Without value lengths, all remaining bytes are returned as value, and
+    /// consume() must be called with the number of bytes not belonging to the value.
+    fn next_entry(&mut self) -> Result<Option<(usize, &'a [u8])>> {
+        if self.off >= self.end {
+            return Ok(None);
+        }
+        let src = &self.block[self.off..self.end];
+        let (shared, n1) = varint::<usize>(src)?;
+        let (non_shared, n2) = varint::<usize>(&src[n1..])?;
+        let (val_len, n3) = if self.has_value_length {
+            varint::<usize>(&src[n1 + n2..])?
+        } else {
+            (0, 0)
+        };
+        let key_off = n1 + n2 + n3;
+        if shared > self.key.len() || key_off + non_shared > src.len() {
+            return err(StatusCode::Corruption, "invalid block entry");
+        }
+        self.key.truncate(shared);
+        self.key
+            .extend_from_slice(&src[key_off..key_off + non_shared]);
+        let val_off = key_off + non_shared;
+        if self.has_value_length {
+            if val_off + val_len > src.len() {
+                return err(StatusCode::Corruption, "invalid block entry");
+            }
+            self.off += val_off + val_len;
+            Ok(Some((shared, &src[val_off..val_off + val_len])))
+        } else {
+            self.off += val_off;
+            Ok(Some((shared, &src[val_off..])))
+        }
+    }
+
+    /// consume is called after next_entry() without value lengths, with the number of bytes of
+    /// the returned value that don't belong to the entry.
+    fn consume(&mut self, unused: usize) {
+        self.off = self.end - unused;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_builder::BlockBuilder;
+    use crate::cmp::InternalKeyCmp;
+    use crate::key_types::{LookupKey, ValueType};
+    use crate::log::mask_crc;
+    use crate::options::{self, Options};
+
+    use std::rc::Rc;
+
+    const RESTART_INTERVAL: usize = 4;
+
+    /// TableWriter writes tables like RocksDB's BlockBasedTableBuilder, for the variants
+    /// supported by the reader.
+    struct TableWriter {
+        buf: Vec<u8>,
+        format_version: u32,
+        compression: u8,
+        delta_encoded: bool,
+        first_key: bool,
+        hash_index: bool,
+    }
+
+    impl TableWriter {
+        fn new(format_version: u32) -> TableWriter {
+            TableWriter {
+                buf: vec![],
+                format_version,
+                compression: NO_COMPRESSION,
+                delta_encoded: false,
+                first_key: false,
+                hash_index: false,
+            }
+        }
+
+        fn write_block(&mut self, mut contents: Vec<u8>) -> BlockHandle {
+            if self.compression == SNAPPY_COMPRESSION {
+                contents = SnappyCompressor.encode(contents).unwrap();
+            }
+            let h = BlockHandle::new(self.buf.len(), contents.len());
+            self.buf.extend_from_slice(&contents);
+            self.buf.push(self.compression);
+            let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
+            digest.write(&contents);
+            digest.write(&[self.compression]);
+            self.buf
+                .extend_from_slice(&mask_crc(digest.sum32()).encode_fixed_vec());
+            h
+        }
+
+        fn plain_block(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+            TableWriter::build_block(options::for_test(), entries)
+        }
+
+        fn build_block(mut o: Options, entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+            o.block_restart_interval = RESTART_INTERVAL;
+            let mut b = BlockBuilder::new(o);
+            for (k, v) in entries {
+                b.add(k, v);
+            }
+            b.finish()
+        }
+
+        fn data_block(&self, entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+            let mut o = options::for_test();
+            o.cmp = Rc::new(Box::new(InternalKeyCmp(o.cmp.clone())));
+            let mut b = TableWriter::build_block(o, entries);
+            if self.hash_index {
+                let packed = u32::decode_fixed(&b[b.len() - 4..]) | DATA_BLOCK_HASH_INDEX_FLAG;
+                b.truncate(b.len() - 4);
+                // Three buckets.
+                b.extend_from_slice(&[0, 0xff, 1]);
+                b.extend_from_slice(&3u16.encode_fixed_vec());
+                b.extend_from_slice(&packed.encode_fixed_vec());
+            }
+            b
+        }
+
+        /// index_block encodes an index block like RocksDB's IndexBlockBuilder.
+        fn index_block(&self, handles: &[BlockHandle]) -> Vec<u8> {
+            let mut buf = vec![];
+            let mut restarts = vec![];
+            let mut prev: Option<&BlockHandle> = None;
+            for (i, h) in handles.iter().enumerate() {
+                let key = format!("k{:04}", i).into_bytes();
+                let restart = i % RESTART_INTERVAL == 0;
+                let shared = if restart { 0 } else { 1 };
+                if restart {
+                    restarts.push(buf.len() as u32);
+                }
+                let mut value = vec![];
+                if self.delta_encoded && shared > 0 {
+                    let prev = prev.unwrap();
+                    value.extend((h.size() as i64 - prev.size() as i64).encode_var_vec());
+                } else {
+                    value.extend(h.offset().encode_var_vec());
+                    value.extend(h.size().encode_var_vec());
+                }
+                if self.first_key {
+                    value.extend(3usize.encode_var_vec());
+                    value.extend_from_slice(b"abc");
+                }
+                buf.extend(shared.encode_var_vec());
+                buf.extend((key.len() - shared).encode_var_vec());
+                if !self.delta_encoded {
+                    buf.extend(value.len().encode_var_vec());
+                }
+                buf.extend_from_slice(&key[shared..]);
+                buf.extend_from_slice(&value);
+                prev = Some(h);
+            }
+            for r in &restarts {
+                buf.extend(r.encode_fixed_vec());
+            }
+            buf.extend((restarts.len() as u32).encode_fixed_vec());
+            buf
+        }
+
+        fn finish(mut self, data: &[Vec<(Vec<u8>, Vec<u8>)>], index_type: u32) -> Vec<u8> {
+            let mut handles = vec![];
+            for block in data {
+                let b = self.data_block(block);
+                handles.push(self.write_block(b));
+            }
+            let mut props = vec![(
+                PROP_INDEX_TYPE.as_bytes().to_vec(),
+                index_type.encode_fixed_vec(),
+            )];
+            if self.delta_encoded {
+                props.push((PROP_DELTA_ENCODED.as_bytes().to_vec(), vec![1]));
+            }
+            props.push((b"rocksdb.num.entries".to_vec(), 123u64.encode_var_vec()));
+            props.sort();
+            let props = TableWriter::plain_block(&props);
+            let props_handle = self.write_block(props);
+            let mut enc = [0; 20];
+            let n = props_handle.encode_to(&mut enc);
+            let metaindex =
+                TableWriter::plain_block(&[(b"rocksdb.properties".to_vec(), enc[..n].to_vec())]);
+            let metaindex_handle = self.write_block(metaindex);
+
+            let index_handle = if index_type == INDEX_TWO_LEVEL {
+                // Two partitions.
+                let (a, b) = handles.split_at(handles.len() / 2);
+                let a = self.index_block(a);
+                let b = self.index_block(b);
+                let partitions = vec![self.write_block(a), self.write_block(b)];
+                let top = self.index_block(&partitions);
+                self.write_block(top)
+            } else {
+                let index = self.index_block(&handles);
+                self.write_block(index)
+            };
+
+            let mut footer = vec![];
+            if self.format_version > 0 {
+                footer.push(CRC32C_CHECKSUM);
+            }
+            footer.extend(metaindex_handle.offset().encode_var_vec());
+            footer.extend(metaindex_handle.size().encode_var_vec());
+            footer.extend(index_handle.offset().encode_var_vec());
+            footer.extend(index_handle.size().encode_var_vec());
+            if self.format_version > 0 {
+                footer.resize(FOOTER_LENGTH - 12, 0);
+                footer.extend(self.format_version.encode_fixed_vec());
+                footer.extend(BLOCK_BASED_MAGIC.encode_fixed_vec());
+            } else {
+                footer.resize(LEGACY_FOOTER_LENGTH - 8, 0);
+                footer.extend(LEGACY_MAGIC.encode_fixed_vec());
+            }
+            self.buf.extend(footer);
+            self.buf
+        }
+    }
+
+    fn internal_key(k: &[u8], seq: u64, t: ValueType) -> Vec<u8> {
+        LookupKey::new_full(k, seq, t).internal_key().to_vec()
+    }
+
+    /// test_data returns 5 blocks with 10 entries each.
+    fn test_data() -> Vec<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut blocks = vec![];
+        for b in 0..5 {
+            let mut entries = vec![];
+            for i in 0..10 {
+                let k = format!("key{:03}", b * 10 + i);
+                entries.push((
+                    internal_key(k.as_bytes(), 100 + i, ValueType::TypeValue),
+                    format!("val{}", b * 10 + i).into_bytes(),
+                ));
+            }
+            blocks.push(entries);
+        }
+        blocks
+    }
+
+    fn open(buf: Vec<u8>) -> Result<RocksDBTable> {
+        let size = buf.len();
+        RocksDBTable::open(Box::new(buf), size)
+    }
+
+    fn check_contents(t: &RocksDBTable) {
+        let entries: Vec<_> = t.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(50, entries.len());
+        for (i, e) in entries.iter().enumerate() {
+            assert_eq!(format!("key{:03}", i).into_bytes(), e.key);
+            assert_eq!(format!("val{}", i).into_bytes(), e.value);
+            assert_eq!(100 + i as u64 % 10, e.seq);
+            assert_eq!(RocksDBEntryType::Value, e.typ);
+        }
+    }
+
+    #[test]
+    fn test_rocksdb_table_formats() {
+        for &(version, index_type, delta, first_key, hash, compression) in &[
+            (0, 0, false, false, false, NO_COMPRESSION),
+            (2, 0, false, false, false, SNAPPY_COMPRESSION),
+            (4, 0, true, false, false, NO_COMPRESSION),
+            (4, 1, true, false, true, NO_COMPRESSION),
+            (5, INDEX_TWO_LEVEL, true, false, false, SNAPPY_COMPRESSION),
+            (5, INDEX_TWO_LEVEL, false, false, true, NO_COMPRESSION),
+            (5, INDEX_WITH_FIRST_KEY, true, true, false, NO_COMPRESSION),
+            (3, INDEX_WITH_FIRST_KEY, false, true, false, NO_COMPRESSION),
+        ] {
+            let mut w = TableWriter::new(version);
+            w.delta_encoded = delta;
+            w.first_key = first_key;
+            w.hash_index = hash;
+            w.compression = compression;
+            let t = open(w.finish(&test_data(), index_type)).unwrap();
+            assert_eq!(version, t.format_version());
+            assert_eq!(Some(123), t.property_u64("rocksdb.num.entries"));
+            check_contents(&t);
+        }
+    }
+
+    #[test]
+    fn test_rocksdb_table_errors() {
+        let mut buf = TableWriter::new(5).finish(&test_data(), 0);
+        // Corrupt a data block.
+        buf[10] ^= 1;
+        let t = open(buf).unwrap();
+        let r: Result<Vec<_>> = t.iter().unwrap().collect();
+        assert_eq!(StatusCode::Corruption, r.err().unwrap().code);
+
+        let mut buf = TableWriter::new(5).finish(&test_data(), 0);
+        let l = buf.len();
+        buf[l - 12] = 6;
+        assert_eq!(StatusCode::NotSupported, open(buf).err().unwrap().code);
+        assert_eq!(
+            StatusCode::NotSupported,
+            open(vec![0; 100]).err().unwrap().code
+        );
+        assert_eq!(
+            StatusCode::Corruption,
+            open(vec![0; 10]).err().unwrap().code
+        );
+
+        // A LevelDB table is read like a RocksDB table of format version 0.
+        let mut buf = vec![];
+        {
+            let mut b = crate::table_builder::TableBuilder::new(options::for_test(), &mut buf);
+            b.add(&internal_key(b"abc", 1, ValueType::TypeValue), b"def")
+                .unwrap();
+            b.finish().unwrap();
+        }
+        let t = open(buf).unwrap();
+        let entries: Vec<_> = t.iter().unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(1, entries.len());
+        assert_eq!(b"abc".to_vec(), entries[0].key);
+    }
+
+    #[test]
+    fn test_rocksdb_table_write_to() {
+        let blocks = vec![vec![
+            (internal_key(b"a", 5, ValueType::TypeValue), b"new".to_vec()),
+            (internal_key(b"a", 3, ValueType::TypeValue), b"old".to_vec()),
+            (internal_key(b"b", 4, ValueType::TypeDeletion), vec![]),
+            (
+                internal_key(b"b", 2, ValueType::TypeValue),
+                b"deleted".to_vec(),
+            ),
+            (internal_key(b"c", 1, ValueType::TypeValue), b"c".to_vec()),
+        ]];
+        let t = open(TableWriter::new(5).finish(&blocks, 0)).unwrap();
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        db.put(b"b", b"existing").unwrap();
+        assert_eq!(3, t.write_to(&mut db).unwrap());
+        assert_eq!(b"new".to_vec(), db.get(b"a").unwrap().unwrap());
+        assert!(db.get(b"b").unwrap().is_none());
+        assert_eq!(b"c".to_vec(), db.get(b"c").unwrap().unwrap());
+    }
+}
@@ -0,0 +1,203 @@
+//! A disk-backed second tier for the block cache.
+
+use crate::cache::CacheKey;
+use crate::env::{Env, RandomAccess};
+use crate::error::Result;
+use crate::log::{mask_crc, unmask_crc};
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crc::crc32;
+use integer_encoding::FixedInt;
+
+/// The cache file is split into this many segments, which are overwritten in turn.
+const NUM_SEGMENTS: usize = 4;
+/// Each entry is followed by a checksum of its data.
+const CKSUM_LEN: usize = 4;
+
+struct Segment {
+    writer: Box<dyn Write>,
+    reader: Box<dyn RandomAccess>,
+    size: usize,
+    keys: Vec<CacheKey>,
+}
+
+/// SecondaryCache stores blocks evicted from the block cache (see `Options::secondary_cache`) in
+/// files on a local disk, so that they can be read back without going to the database's storage.
+/// This is useful if the database lives on a network file system or a slow disk.
+///
+/// The cache uses up to `capacity` bytes in four segment files, which are written sequentially;
+/// once all are full, the oldest segment is discarded. Entries are checksummed,
+/// and entries that fail verification are treated as missing. The cache's contents don't
+/// survive a restart.
+pub struct SecondaryCache {
+    env: Rc<Box<dyn Env>>,
+    dir: PathBuf,
+    segment_size: usize,
+    segments: Vec<Segment>,
+    current: usize,
+    // Maps keys to (segment, offset, length) of the stored block.
+    index: HashMap<CacheKey, (usize, usize, usize)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl SecondaryCache {
+    /// new creates a cache in the directory dir, which is created if necessary. Cache files left
+    /// in it are overwritten.
+    pub fn new<P: AsRef<Path>>(
+        env: Rc<Box<dyn Env>>,
+        dir: P,
+        capacity: usize,
+    ) -> Result<SecondaryCache> {
+        let dir = dir.as_ref().to_owned();
+        if !env.exists(&dir)? {
+            env.mkdir(&dir)?;
+        }
+        let mut c = SecondaryCache {
+            env,
+            dir,
+            segment_size: (capacity / NUM_SEGMENTS).max(1),
+            segments: vec![],
+            current: 0,
+            index: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        };
+        for i in 0..NUM_SEGMENTS {
+            let s = c.open_segment(i)?;
+            c.segments.push(s);
+        }
+        Ok(c)
+    }
+
+    fn segment_name(&self, i: usize) -> PathBuf {
+        self.dir.join(format!("blockcache-{}", i))
+    }
+
+    /// open_segment creates an empty segment file.
+    fn open_segment(&self, i: usize) -> Result<Segment> {
+        let name = self.segment_name(i);
+        let writer = self.env.open_writable_file(&name)?;
+        let reader = self.env.open_random_access_file(&name)?;
+        Ok(Segment {
+            writer,
+            reader,
+            size: 0,
+            keys: vec![],
+        })
+    }
+
+    /// insert stores a block's contents. Blocks larger than a segment aren't stored.
+    pub fn insert(&mut self, key: &CacheKey, data: &[u8]) -> Result<()> {
+        let len = data.len() + CKSUM_LEN;
+        if len > self.segment_size || self.index.contains_key(key) {
+            return Ok(());
+        }
+        if self.segments[self.current].size + len > self.segment_size {
+            self.current = (self.current + 1) % NUM_SEGMENTS;
+            for k in &self.segments[self.current].keys {
+                self.index.remove(k);
+            }
+            self.segments[self.current] = self.open_segment(self.current)?;
+        }
+
+        let seg = &mut self.segments[self.current];
+        let cksum = mask_crc(crc32::checksum_castagnoli(data));
+        seg.writer.write_all(data)?;
+        seg.writer.write_all(&cksum.encode_fixed_vec())?;
+        seg.writer.flush()?;
+        self.index
+            .insert(*key, (self.current, seg.size, data.len()));
+        seg.keys.push(*key);
+        seg.size += len;
+        Ok(())
+    }
+
+    /// get returns the stored contents of a block. Read errors are treated as misses.
+    pub fn get(&mut self, key: &CacheKey) -> Option<Vec<u8>> {
+        let (seg, offset, len) = match self.index.get(key) {
+            Some(e) => *e,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        let mut buf = vec![0; len + CKSUM_LEN];
+        let ok = match self.segments[seg].reader.read_at(offset, &mut buf) {
+            Ok(n) if n == buf.len() => {
+                let cksum = unmask_crc(u32::decode_fixed(&buf[len..]));
+                crc32::checksum_castagnoli(&buf[..len]) == cksum
+            }
+            _ => false,
+        };
+        if !ok {
+            self.index.remove(key);
+            self.misses += 1;
+            return None;
+        }
+        self.hits += 1;
+        buf.truncate(len);
+        Some(buf)
+    }
+
+    /// count returns the number of stored blocks.
+    pub fn count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// usage returns the size of the cache files in bytes.
+    pub fn usage(&self) -> usize {
+        self.segments.iter().map(|s| s.size).sum()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_env::MemEnv;
+
+    fn key(i: u64) -> CacheKey {
+        let mut k = [0; 16];
+        k[..8].copy_from_slice(&i.encode_fixed_vec());
+        k
+    }
+
+    #[test]
+    fn test_secondary_cache() {
+        let env: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+        let mut c = SecondaryCache::new(env.clone(), "cache", 4 * 100).unwrap();
+        assert!(env.exists(Path::new("cache/blockcache-3")).unwrap());
+
+        c.insert(&key(1), &[1; 40]).unwrap();
+        c.insert(&key(2), &[2; 40]).unwrap();
+        c.insert(&key(3), &[3; 200]).unwrap();
+        assert_eq!(2, c.count());
+        assert_eq!(Some(vec![1; 40]), c.get(&key(1)));
+        assert_eq!(Some(vec![2; 40]), c.get(&key(2)));
+        assert_eq!(None, c.get(&key(3)));
+        assert_eq!((2, 1), (c.hits(), c.misses()));
+
+        // Filling all segments discards the oldest one.
+        for i in 10..20 {
+            c.insert(&key(i), &[i as u8; 40]).unwrap();
+        }
+        assert_eq!(None, c.get(&key(1)));
+        assert_eq!(None, c.get(&key(11)));
+        assert_eq!(Some(vec![12; 40]), c.get(&key(12)));
+        assert_eq!(Some(vec![19; 40]), c.get(&key(19)));
+        assert_eq!(8, c.count());
+        assert!(c.usage() <= 400);
+    }
+}
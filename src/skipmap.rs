@@ -3,48 +3,204 @@ use crate::rand::rngs::StdRng;
 use crate::rand::{RngCore, SeedableRng};
 use crate::types::LdbIterator;
 
-use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::mem::{replace, size_of};
+use std::ptr;
 use std::rc::Rc;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
+use std::sync::{Arc, Mutex};
 
 const MAX_HEIGHT: usize = 12;
 const BRANCHING_FACTOR: u32 = 4;
+/// Keys and values are allocated from chunks of this size. Entries larger than a quarter chunk
+/// get a chunk of their own, so that little space is wasted at the end of chunks.
+const ARENA_CHUNK_SIZE: usize = 4096;
+
+/// ArenaSlice points to bytes allocated in an Arena. It is valid as long as the arena is.
+#[derive(Clone, Copy)]
+struct ArenaSlice {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl ArenaSlice {
+    const EMPTY: ArenaSlice = ArenaSlice {
+        ptr: std::ptr::null(),
+        len: 0,
+    };
+
+    fn get<'a>(&self) -> &'a [u8] {
+        if self.len == 0 {
+            &[]
+        } else {
+            // The arena never moves or frees chunks before it's dropped.
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+}
+
+// The bytes are never modified after they're allocated, so they can be read from any thread.
+unsafe impl Send for ArenaSlice {}
+unsafe impl Sync for ArenaSlice {}
+
+/// Arena allocates the keys and values of a skipmap from large chunks, instead of allocating
+/// every single one. Like in the original memtable, memory is only released when the whole map
+/// is dropped.
+struct Arena {
+    current: Vec<u8>,
+    chunks: Vec<Vec<u8>>,
+    // Bytes allocated, including the unused ends of full chunks.
+    usage: usize,
+}
+
+impl Arena {
+    fn new() -> Arena {
+        Arena {
+            current: Vec::new(),
+            chunks: Vec::new(),
+            usage: 0,
+        }
+    }
+
+    fn alloc(&mut self, data: &[u8]) -> ArenaSlice {
+        if data.is_empty() {
+            return ArenaSlice::EMPTY;
+        }
+        self.usage += data.len();
+        if data.len() > ARENA_CHUNK_SIZE / 4 {
+            let chunk = data.to_vec();
+            let slice = ArenaSlice {
+                ptr: chunk.as_ptr(),
+                len: chunk.len(),
+            };
+            self.chunks.push(chunk);
+            return slice;
+        }
+        if self.current.capacity() - self.current.len() < data.len() {
+            self.usage += self.current.capacity() - self.current.len();
+            // Moving the vector doesn't move its contents.
+            let full = replace(&mut self.current, Vec::with_capacity(ARENA_CHUNK_SIZE));
+            if !full.is_empty() {
+                self.chunks.push(full);
+            }
+        }
+        let start = self.current.len();
+        // Never exceeds the capacity, so the chunk isn't reallocated.
+        self.current.extend_from_slice(data);
+        ArenaSlice {
+            ptr: self.current[start..].as_ptr(),
+            len: data.len(),
+        }
+    }
+}
 
 /// A node in a skipmap contains links to the next node and others that are further away (skips);
-/// `skips[0]` is the immediate element after, that is, the element contained in `next`.
+/// `skips[0]` is the immediate element after. Links are atomic, so that readers can traverse the
+/// map while it's being written to: a node is completely initialized before it's linked (with
+/// release ordering), and readers load links with acquire ordering.
+///
+/// Nodes are never unlinked, and are only freed when the map is dropped, so readers need neither a
+/// lock nor any form of (epoch-based) reclamation.
 struct Node {
-    skips: Vec<Option<*mut Node>>,
-    next: Option<Box<Node>>,
-    key: Vec<u8>,
-    value: Vec<u8>,
+    skips: Box<[AtomicPtr<Node>]>,
+    key: ArenaSlice,
+    value: ArenaSlice,
+}
+
+impl Node {
+    fn new(height: usize, key: ArenaSlice, value: ArenaSlice) -> Node {
+        Node {
+            skips: (0..height)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+            key,
+            value,
+        }
+    }
+
+    fn key(&self) -> &[u8] {
+        self.key.get()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.value.get()
+    }
+
+    /// next returns the next node at level, or None at the end of the map.
+    fn next(&self, level: usize) -> Option<&Node> {
+        // Nodes live as long as the map.
+        unsafe { self.skips[level].load(atomic::Ordering::Acquire).as_ref() }
+    }
+
+    fn set_next(&self, level: usize, node: *mut Node) {
+        self.skips[level].store(node, atomic::Ordering::Release)
+    }
+}
+
+/// State only used by the writer.
+struct Writer {
+    rand: StdRng,
+    // approximation of memory used by nodes; the arena accounts for keys and values.
+    approx_mem: usize,
+    arena: Arena,
+}
+
+/// CmpRef is the comparator of a `SkipMap`: `Rc<Box<dyn Cmp>>` for a map used by a single
+/// thread, like the memtable's, or `Arc<dyn Cmp + Send + Sync>` for one shared between threads
+/// (see `SkipMap::new_shared()`).
+pub trait CmpRef {
+    fn as_cmp(&self) -> &dyn Cmp;
+}
+
+impl CmpRef for Rc<Box<dyn Cmp>> {
+    fn as_cmp(&self) -> &dyn Cmp {
+        self.as_ref().as_ref()
+    }
+}
+
+impl CmpRef for Arc<dyn Cmp + Send + Sync> {
+    fn as_cmp(&self) -> &dyn Cmp {
+        self.as_ref()
+    }
 }
 
 /// Implements the backing store for a `MemTable`. The important methods are `insert()` and
 /// `contains()`; in order to get full key and value for an entry, use a `SkipMapIter` instance,
 /// `seek()` to the key to look up (this is as fast as any lookup in a skip map), and then call
 /// `current()`.
-struct InnerSkipMap {
+///
+/// Reads only follow atomic links; the writer state is behind a lock of its own, which only
+/// writers take.
+struct InnerSkipMap<C> {
     head: Box<Node>,
-    rand: StdRng,
-    len: usize,
-    // approximation of memory used.
-    approx_mem: usize,
-    cmp: Rc<Box<dyn Cmp>>,
+    len: AtomicUsize,
+    cmp: C,
+    // Declared after head, so that the nodes are dropped before the arena holding their keys.
+    writer: Mutex<Writer>,
 }
 
-impl Drop for InnerSkipMap {
-    // Avoid possible stack overflow caused by dropping many nodes.
+impl<C> Drop for InnerSkipMap<C> {
+    // Nodes other than the head are owned by the map through raw pointers. Dropping them one by
+    // one also avoids deep recursion.
     fn drop(&mut self) {
-        let mut next_node = self.head.next.take();
-        while let Some(mut boxed_node) = next_node {
-            next_node = boxed_node.next.take();
+        let mut next = self.head.skips[0].load(atomic::Ordering::Acquire);
+        while !next.is_null() {
+            let node = unsafe { Box::from_raw(next) };
+            next = node.skips[0].load(atomic::Ordering::Acquire);
         }
     }
 }
 
-pub struct SkipMap {
-    map: Rc<RefCell<InnerSkipMap>>,
+/// SkipMap is an ordered map of byte strings, ordered by a `Cmp`. Keys and values are stored in an
+/// arena, which makes inserts cheap, but memory is only released when the map is dropped. Keys
+/// must be unique. Iterators (`SkipMapIter`) remain valid while the map is modified, and reading
+/// doesn't lock the map.
+///
+/// Clones are handles to the same map. With a thread-safe comparator (see
+/// `SkipMap::new_shared()`), they can be used from different threads: Inserts are serialized by a
+/// lock, while readers run concurrently with them and each other, without locking.
+pub struct SkipMap<C = Rc<Box<dyn Cmp>>> {
+    map: Arc<InnerSkipMap<C>>,
 }
 
 impl SkipMap {
@@ -55,54 +211,73 @@ impl SkipMap {
 
     /// Returns a SkipMap that uses the specified comparator.
     pub fn new(cmp: Rc<Box<dyn Cmp>>) -> SkipMap {
-        let mut s = Vec::new();
-        s.resize(MAX_HEIGHT, None);
+        SkipMap::with_cmp(cmp)
+    }
+}
+
+impl SkipMap<Arc<dyn Cmp + Send + Sync>> {
+    /// Returns a SkipMap that can be shared between threads.
+    pub fn new_shared(cmp: Arc<dyn Cmp + Send + Sync>) -> SkipMap<Arc<dyn Cmp + Send + Sync>> {
+        SkipMap::with_cmp(cmp)
+    }
+}
 
+impl<C> Clone for SkipMap<C> {
+    fn clone(&self) -> SkipMap<C> {
         SkipMap {
-            map: Rc::new(RefCell::new(InnerSkipMap {
-                head: Box::new(Node {
-                    skips: s,
-                    next: None,
-                    key: Vec::new(),
-                    value: Vec::new(),
-                }),
-                rand: StdRng::seed_from_u64(0xdeadbeef),
-                len: 0,
-                approx_mem: size_of::<Self>() + MAX_HEIGHT * size_of::<Option<*mut Node>>(),
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<C: CmpRef> SkipMap<C> {
+    fn with_cmp(cmp: C) -> SkipMap<C> {
+        SkipMap {
+            map: Arc::new(InnerSkipMap {
+                head: Box::new(Node::new(MAX_HEIGHT, ArenaSlice::EMPTY, ArenaSlice::EMPTY)),
+                len: AtomicUsize::new(0),
                 cmp,
-            })),
+                writer: Mutex::new(Writer {
+                    rand: StdRng::seed_from_u64(0xdeadbeef),
+                    approx_mem: size_of::<Self>()
+                        + size_of::<Node>()
+                        + MAX_HEIGHT * size_of::<AtomicPtr<Node>>(),
+                    arena: Arena::new(),
+                }),
+            }),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.map.borrow().len
+        self.map.len.load(atomic::Ordering::Acquire)
     }
     pub fn approx_memory(&self) -> usize {
-        self.map.borrow().approx_mem
+        let writer = self.map.writer.lock().unwrap();
+        writer.approx_mem + writer.arena.usage
     }
     pub fn contains(&self, key: &[u8]) -> bool {
-        self.map.borrow().contains(key)
+        self.map.contains(key)
     }
 
     /// inserts a key into the table. key may not be empty.
     pub fn insert(&mut self, key: Vec<u8>, val: Vec<u8>) {
         assert!(!key.is_empty());
-        self.map.borrow_mut().insert(key, val);
+        self.map.insert(&key, &val);
     }
 
-    pub fn iter(&self) -> SkipMapIter {
+    pub fn iter(&self) -> SkipMapIter<C> {
         SkipMapIter {
             map: self.map.clone(),
-            current: self.map.borrow().head.as_ref() as *const Node,
+            current: self.map.head.as_ref() as *const Node,
         }
     }
 }
 
-impl InnerSkipMap {
-    fn random_height(&mut self) -> usize {
+impl<C: CmpRef> InnerSkipMap<C> {
+    fn random_height(writer: &mut Writer) -> usize {
         let mut height = 1;
 
-        while height < MAX_HEIGHT && self.rand.next_u32() % BRANCHING_FACTOR == 0 {
+        while height < MAX_HEIGHT && writer.rand.next_u32().is_multiple_of(BRANCHING_FACTOR) {
             height += 1;
         }
 
@@ -111,7 +286,7 @@ impl InnerSkipMap {
 
     fn contains(&self, key: &[u8]) -> bool {
         if let Some(n) = self.get_greater_or_equal(key) {
-            n.key.starts_with(&key)
+            n.key().starts_with(key)
         } else {
             false
         }
@@ -121,24 +296,20 @@ impl InnerSkipMap {
     /// Returns None if the given key lies past the greatest key in the table.
     fn get_greater_or_equal<'a>(&'a self, key: &[u8]) -> Option<&'a Node> {
         // Start at the highest skip link of the head node, and work down from there
-        let mut current = self.head.as_ref() as *const Node;
-        let mut level = self.head.skips.len() - 1;
+        let mut current = self.head.as_ref();
+        let mut level = MAX_HEIGHT - 1;
 
         loop {
-            unsafe {
-                if let Some(next) = (*current).skips[level] {
-                    let ord = self.cmp.cmp((*next).key.as_slice(), key);
-
-                    match ord {
-                        Ordering::Less => {
-                            current = next;
-                            continue;
-                        }
-                        Ordering::Equal => return Some(&(*next)),
-                        Ordering::Greater => {
-                            if level == 0 {
-                                return Some(&(*next));
-                            }
+            if let Some(next) = current.next(level) {
+                match self.cmp.as_cmp().cmp(next.key(), key) {
+                    Ordering::Less => {
+                        current = next;
+                        continue;
+                    }
+                    Ordering::Equal => return Some(next),
+                    Ordering::Greater => {
+                        if level == 0 {
+                            return Some(next);
                         }
                     }
                 }
@@ -149,14 +320,12 @@ impl InnerSkipMap {
             level -= 1;
         }
 
-        unsafe {
-            if current.is_null() || current == self.head.as_ref() {
-                None
-            } else if self.cmp.cmp(&(*current).key, key) == Ordering::Less {
-                None
-            } else {
-                Some(&(*current))
-            }
+        if ptr::eq(current, self.head.as_ref())
+            || self.cmp.as_cmp().cmp(current.key(), key) == Ordering::Less
+        {
+            None
+        } else {
+            Some(current)
         }
     }
 
@@ -164,21 +333,14 @@ impl InnerSkipMap {
     /// Returns None if no smaller key was found.
     fn get_next_smaller<'a>(&'a self, key: &[u8]) -> Option<&'a Node> {
         // Start at the highest skip link of the head node, and work down from there
-        let mut current = self.head.as_ref() as *const Node;
-        let mut level = self.head.skips.len() - 1;
+        let mut current = self.head.as_ref();
+        let mut level = MAX_HEIGHT - 1;
 
         loop {
-            unsafe {
-                if let Some(next) = (*current).skips[level] {
-                    let ord = self.cmp.cmp((*next).key.as_slice(), key);
-
-                    match ord {
-                        Ordering::Less => {
-                            current = next;
-                            continue;
-                        }
-                        _ => (),
-                    }
+            if let Some(next) = current.next(level) {
+                if self.cmp.as_cmp().cmp(next.key(), key) == Ordering::Less {
+                    current = next;
+                    continue;
                 }
             }
             if level == 0 {
@@ -187,53 +349,68 @@ impl InnerSkipMap {
             level -= 1;
         }
 
-        unsafe {
-            if current.is_null() || current == self.head.as_ref() {
-                // If we're past the end for some reason or at the head
-                None
-            } else if self.cmp.cmp(&(*current).key, key) != Ordering::Less {
-                None
-            } else {
-                Some(&(*current))
+        if ptr::eq(current, self.head.as_ref())
+            || self.cmp.as_cmp().cmp(current.key(), key) != Ordering::Less
+        {
+            // If we're at the head
+            None
+        } else {
+            Some(current)
+        }
+    }
+
+    /// get_last returns the last node, or None if the map is empty.
+    fn get_last(&self) -> Option<&Node> {
+        let mut current = self.head.as_ref();
+        let mut level = MAX_HEIGHT - 1;
+
+        loop {
+            if let Some(next) = current.next(level) {
+                current = next;
+                continue;
+            }
+            if level == 0 {
+                break;
             }
+            level -= 1;
+        }
+
+        if ptr::eq(current, self.head.as_ref()) {
+            None
+        } else {
+            Some(current)
         }
     }
 
-    fn insert(&mut self, key: Vec<u8>, val: Vec<u8>) {
+    /// insert adds a node. Writers are serialized by the writer lock; readers may run
+    /// concurrently.
+    fn insert(&self, key: &[u8], val: &[u8]) {
         assert!(!key.is_empty());
+        let mut writer = self.writer.lock().unwrap();
 
         // Keeping track of skip entries that will need to be updated
-        let mut prevs: [Option<*mut Node>; MAX_HEIGHT] = [None; MAX_HEIGHT];
-        let new_height = self.random_height();
-        let prevs = &mut prevs[0..new_height];
+        let mut prevs: [&Node; MAX_HEIGHT] = [self.head.as_ref(); MAX_HEIGHT];
+        let new_height = InnerSkipMap::<C>::random_height(&mut writer);
 
         let mut level = MAX_HEIGHT - 1;
-        let mut current = self.head.as_mut() as *mut Node;
-        // Set previous node for all levels to current node.
-        for i in 0..prevs.len() {
-            prevs[i] = Some(current);
-        }
+        let mut current = self.head.as_ref();
 
         // Find the node after which we want to insert the new node; this is the node with the key
         // immediately smaller than the key to be inserted.
         loop {
-            unsafe {
-                if let Some(next) = (*current).skips[level] {
-                    // If the wanted position is after the current node
-                    let ord = self.cmp.cmp(&(*next).key, &key);
+            if let Some(next) = current.next(level) {
+                // If the wanted position is after the current node
+                let ord = self.cmp.as_cmp().cmp(next.key(), key);
 
-                    assert!(ord != Ordering::Equal, "No duplicates allowed");
+                assert!(ord != Ordering::Equal, "No duplicates allowed");
 
-                    if ord == Ordering::Less {
-                        current = next;
-                        continue;
-                    }
+                if ord == Ordering::Less {
+                    current = next;
+                    continue;
                 }
             }
 
-            if level < new_height {
-                prevs[level] = Some(current);
-            }
+            prevs[level] = current;
 
             if level == 0 {
                 break;
@@ -242,105 +419,98 @@ impl InnerSkipMap {
             }
         }
 
-        // Construct new node
-        let mut new_skips = Vec::new();
-        new_skips.resize(new_height, None);
-        let mut new = Box::new(Node {
-            skips: new_skips,
-            next: None,
-            key,
-            value: val,
-        });
-        let newp = new.as_mut() as *mut Node;
-
-        for i in 0..new_height {
-            if let Some(prev) = prevs[i] {
-                unsafe {
-                    new.skips[i] = (*prev).skips[i];
-                    (*prev).skips[i] = Some(newp);
-                }
-            }
+        // Construct new node. Its links are set before it becomes reachable.
+        let key = writer.arena.alloc(key);
+        let value = writer.arena.alloc(val);
+        let new = Box::into_raw(Box::new(Node::new(new_height, key, value)));
+        for (i, prev) in prevs.iter().enumerate().take(new_height) {
+            let next = prev.skips[i].load(atomic::Ordering::Acquire);
+            unsafe { (*new).set_next(i, next) };
+        }
+        // Link the node from the bottom up; a reader finding it on a higher level is guaranteed
+        // to find it on the levels below, too.
+        for (i, prev) in prevs.iter().enumerate().take(new_height) {
+            prev.set_next(i, new);
         }
 
-        let added_mem = size_of::<Node>()
-            + size_of::<Option<*mut Node>>() * new.skips.len()
-            + new.key.len()
-            + new.value.len();
-        self.approx_mem += added_mem;
-        self.len += 1;
-
-        // Insert new node by first replacing the previous element's next field with None and
-        // assigning its value to new.next...
-        new.next = unsafe { replace(&mut (*current).next, None) };
-        // ...and then setting the previous element's next field to the new node
-        unsafe { replace(&mut (*current).next, Some(new)) };
+        writer.approx_mem += size_of::<Node>() + size_of::<AtomicPtr<Node>>() * new_height;
+        self.len.fetch_add(1, atomic::Ordering::Release);
     }
+
     /// Runs through the skipmap and prints everything including addresses
     fn dbg_print(&self) {
-        let mut current = self.head.as_ref() as *const Node;
+        let mut current = self.head.as_ref();
         loop {
-            unsafe {
-                eprintln!(
-                    "{:?} {:?}/{:?} - {:?}",
-                    current,
-                    (*current).key,
-                    (*current).value,
-                    (*current).skips
-                );
-                if let Some(next) = (*current).skips[0].clone() {
-                    current = next;
-                } else {
-                    break;
-                }
+            eprintln!(
+                "{:?} {:?}/{:?} - {:?}",
+                current as *const Node,
+                current.key(),
+                current.value(),
+                current.skips
+            );
+            if let Some(next) = current.next(0) {
+                current = next;
+            } else {
+                break;
             }
         }
     }
 }
 
-pub struct SkipMapIter {
-    map: Rc<RefCell<InnerSkipMap>>,
+pub struct SkipMapIter<C = Rc<Box<dyn Cmp>>> {
+    map: Arc<InnerSkipMap<C>>,
     current: *const Node,
 }
 
-impl LdbIterator for SkipMapIter {
+impl<C: CmpRef> SkipMapIter<C> {
+    fn current_node(&self) -> &Node {
+        // Nodes live as long as the map, which the iterator holds on to.
+        unsafe { &*self.current }
+    }
+
+    /// current_key returns the key of the current entry without copying it.
+    pub(crate) fn current_key(&self) -> Option<&[u8]> {
+        if self.valid() {
+            Some(self.current_node().key())
+        } else {
+            None
+        }
+    }
+}
+
+impl<C: CmpRef> LdbIterator for SkipMapIter<C> {
     fn advance(&mut self) -> bool {
         // we first go to the next element, then return that -- in order to skip the head node
-        let r = unsafe {
-            (*self.current)
-                .next
-                .as_ref()
-                .map(|next| {
-                    self.current = next.as_ref() as *const Node;
-                    true
-                })
-                .unwrap_or(false)
-        };
-        if !r {
-            self.reset();
+        match self.current_node().next(0) {
+            Some(next) => {
+                self.current = next;
+                true
+            }
+            None => {
+                self.reset();
+                false
+            }
         }
-        r
     }
     fn reset(&mut self) {
-        self.current = self.map.borrow().head.as_ref();
+        self.current = self.map.head.as_ref();
     }
     fn seek(&mut self, key: &[u8]) {
-        if let Some(node) = self.map.borrow().get_greater_or_equal(key) {
+        if let Some(node) = self.map.get_greater_or_equal(key) {
             self.current = node as *const Node;
             return;
         }
         self.reset();
     }
     fn valid(&self) -> bool {
-        self.current != self.map.borrow().head.as_ref()
+        !ptr::eq(self.current, self.map.head.as_ref())
     }
     fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
         if self.valid() {
             key.clear();
             val.clear();
-            unsafe {
-                key.extend_from_slice(&(*self.current).key);
-                val.extend_from_slice(&(*self.current).value);
-            }
+            key.extend_from_slice(self.current_node().key());
+            val.extend_from_slice(self.current_node().value());
             true
         } else {
             false
@@ -349,20 +519,20 @@ impl LdbIterator for SkipMapIter {
     fn prev(&mut self) -> bool {
         // Going after the original implementation here; we just seek to the node before current().
         if self.valid() {
-            if let Some(prev) = self
-                .map
-                .borrow()
-                .get_next_smaller(unsafe { &(*self.current).key })
-            {
+            if let Some(prev) = self.map.get_next_smaller(self.current_node().key()) {
                 self.current = prev as *const Node;
-                if !prev.key.is_empty() {
-                    return true;
-                }
+                return true;
             }
         }
         self.reset();
         false
     }
+    fn seek_to_last(&mut self) {
+        match self.map.get_last() {
+            Some(node) => self.current = node as *const Node,
+            None => self.reset(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -391,7 +561,7 @@ pub mod tests {
     fn test_insert() {
         let skm = make_skipmap();
         assert_eq!(skm.len(), 26);
-        skm.map.borrow().dbg_print();
+        skm.map.dbg_print();
     }
 
     #[test]
@@ -420,68 +590,144 @@ pub mod tests {
         let skm = make_skipmap();
         assert_eq!(
             skm.map
-                .borrow()
                 .get_greater_or_equal(&"abf".as_bytes().to_vec())
                 .unwrap()
-                .key,
+                .key()
+                .to_vec(),
             "abf".as_bytes().to_vec()
         );
         assert!(skm
             .map
-            .borrow()
             .get_greater_or_equal(&"ab{".as_bytes().to_vec())
             .is_none());
         assert_eq!(
             skm.map
-                .borrow()
                 .get_greater_or_equal(&"aaa".as_bytes().to_vec())
                 .unwrap()
-                .key,
+                .key()
+                .to_vec(),
             "aba".as_bytes().to_vec()
         );
         assert_eq!(
             skm.map
-                .borrow()
                 .get_greater_or_equal(&"ab".as_bytes())
                 .unwrap()
-                .key
-                .as_slice(),
+                .key(),
             "aba".as_bytes()
         );
         assert_eq!(
             skm.map
-                .borrow()
                 .get_greater_or_equal(&"abc".as_bytes())
                 .unwrap()
-                .key
-                .as_slice(),
+                .key(),
             "abc".as_bytes()
         );
-        assert!(skm
-            .map
-            .borrow()
-            .get_next_smaller(&"ab0".as_bytes())
-            .is_none());
+        assert!(skm.map.get_next_smaller(&"ab0".as_bytes()).is_none());
         assert_eq!(
-            skm.map
-                .borrow()
-                .get_next_smaller(&"abd".as_bytes())
-                .unwrap()
-                .key
-                .as_slice(),
+            skm.map.get_next_smaller(&"abd".as_bytes()).unwrap().key(),
             "abc".as_bytes()
         );
         assert_eq!(
-            skm.map
-                .borrow()
-                .get_next_smaller(&"ab{".as_bytes())
-                .unwrap()
-                .key
-                .as_slice(),
+            skm.map.get_next_smaller(&"ab{".as_bytes()).unwrap().key(),
             "abz".as_bytes()
         );
     }
 
+    #[test]
+    fn test_skipmap_arena() {
+        let mut skm = SkipMap::new(options::for_test().cmp);
+        let empty = skm.approx_memory();
+        let big = vec![7; ARENA_CHUNK_SIZE * 2];
+        for i in 0..1000 {
+            let val = if i % 100 == 0 {
+                big.clone()
+            } else {
+                format!("value{}", i).into_bytes()
+            };
+            skm.insert(format!("key{:04}", i).into_bytes(), val);
+        }
+        assert!(skm.map.writer.lock().unwrap().arena.chunks.len() > 10);
+        assert!(skm.approx_memory() - empty > 10 * big.len() + 1000 * 14);
+
+        let mut i = 0;
+        for (k, v) in LdbIteratorIter::wrap(&mut skm.iter()) {
+            assert_eq!(format!("key{:04}", i).into_bytes(), k);
+            if i % 100 == 0 {
+                assert_eq!(big, v);
+            } else {
+                assert_eq!(format!("value{}", i).into_bytes(), v);
+            }
+            i += 1;
+        }
+        assert_eq!(1000, i);
+    }
+
+    #[test]
+    fn test_skipmap_read_while_writing() {
+        let mut skm = SkipMap::new(options::for_test().cmp);
+        skm.insert(b"b".to_vec(), b"1".to_vec());
+        skm.insert(b"d".to_vec(), b"2".to_vec());
+
+        let mut it = skm.iter();
+        it.seek(b"b");
+        assert!(it.valid());
+        skm.insert(b"c".to_vec(), b"3".to_vec());
+        skm.insert(b"a".to_vec(), b"4".to_vec());
+
+        // The iterator sees entries inserted after it was positioned.
+        assert_eq!((b"c".to_vec(), b"3".to_vec()), it.next().unwrap());
+        assert_eq!((b"d".to_vec(), b"2".to_vec()), it.next().unwrap());
+        assert!(it.prev());
+        assert!(it.prev());
+        assert!(it.prev());
+        assert_eq!(b"a", current_key_val(&it).unwrap().0.as_slice());
+        assert_eq!(4, skm.len());
+    }
+
+    #[test]
+    fn test_skipmap_shared() {
+        use crate::cmp::DefaultCmp;
+        use std::thread;
+
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let skm = SkipMap::new_shared(Arc::new(DefaultCmp));
+        assert_send_sync(&skm);
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let skm = skm.clone();
+                thread::spawn(move || loop {
+                    // Entries are counted once they're linked, so they can be seen.
+                    let len = skm.len();
+                    let keys: Vec<_> = LdbIteratorIter::wrap(&mut skm.iter())
+                        .map(|(k, _)| k)
+                        .collect();
+                    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                    assert!(keys.len() >= len);
+                    if keys.len() == 1000 {
+                        return;
+                    }
+                })
+            })
+            .collect();
+        let writers: Vec<_> = (0..2)
+            .map(|w| {
+                let mut skm = skm.clone();
+                thread::spawn(move || {
+                    for i in (w..1000).step_by(2) {
+                        let k = format!("key{:04}", i).into_bytes();
+                        skm.insert(k.clone(), k);
+                    }
+                })
+            })
+            .collect();
+        for t in writers.into_iter().chain(readers) {
+            t.join().unwrap();
+        }
+        assert_eq!(1000, skm.len());
+        assert!(skm.contains(b"key0999"));
+    }
+
     #[test]
     fn test_empty_skipmap_find_memtable_cmp() {
         // Regression test: Make sure comparator isn't called with empty key.
@@ -33,9 +33,27 @@ impl Snapshot {
     }
 }
 
+/// SnapshotInfo describes a live snapshot, as returned by `DB::snapshots()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotInfo {
+    pub sequence: SequenceNumber,
+    /// Time since the snapshot was created.
+    pub age_micros: u64,
+}
+
+/// NamedSnapshot describes a persistent snapshot (see `DB::create_named_snapshot()`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamedSnapshot {
+    pub name: String,
+    pub sequence: SequenceNumber,
+    /// The creation time in microseconds since the Unix epoch.
+    pub created_micros: u64,
+}
+
 /// A list of all snapshots is kept in the DB.
 struct InnerSnapshotList {
-    map: HashMap<SnapshotHandle, SequenceNumber>,
+    // handle -> (sequence number, creation time in micros)
+    map: HashMap<SnapshotHandle, (SequenceNumber, u64)>,
     newest: SnapshotHandle,
     oldest: SnapshotHandle,
 }
@@ -55,13 +73,15 @@ impl SnapshotList {
         }
     }
 
-    pub fn new_snapshot(&mut self, seq: SequenceNumber) -> Snapshot {
+    /// new_snapshot creates a snapshot at seq. now is the current time in microseconds, used for
+    /// calculating the snapshot's age.
+    pub fn new_snapshot(&mut self, seq: SequenceNumber, now: u64) -> Snapshot {
         let inner = self.inner.clone();
         let mut sl = self.inner.borrow_mut();
 
         sl.newest += 1;
         let newest = sl.newest;
-        sl.map.insert(newest, seq);
+        sl.map.insert(newest, (seq, now));
 
         if sl.oldest == 0 {
             sl.oldest = sl.newest;
@@ -83,7 +103,7 @@ impl SnapshotList {
             .inner
             .borrow()
             .map
-            .iter()
+            .values()
             .fold(
                 MAX_SEQUENCE_NUMBER,
                 |s, (seq, _)| if *seq < s { *seq } else { s },
@@ -101,12 +121,32 @@ impl SnapshotList {
         self.inner
             .borrow()
             .map
-            .iter()
+            .values()
             .fold(0, |s, (seq, _)| if *seq > s { *seq } else { s })
     }
 
     pub fn empty(&self) -> bool {
-        self.inner.borrow().oldest == 0
+        self.inner.borrow().map.is_empty()
+    }
+
+    /// list returns all live snapshots, ordered from oldest to newest sequence number.
+    pub fn list(&self, now: u64) -> Vec<SnapshotInfo> {
+        let mut infos: Vec<SnapshotInfo> = self
+            .inner
+            .borrow()
+            .map
+            .values()
+            .map(|(seq, created)| SnapshotInfo {
+                sequence: *seq,
+                age_micros: now.saturating_sub(*created),
+            })
+            .collect();
+        infos.sort_by(|a, b| {
+            a.sequence
+                .cmp(&b.sequence)
+                .then(b.age_micros.cmp(&a.age_micros))
+        });
+        infos
     }
 }
 
@@ -127,13 +167,13 @@ mod tests {
 
         {
             assert!(l.empty());
-            let a = l.new_snapshot(1);
+            let a = l.new_snapshot(1, 0);
 
             {
-                let b = l.new_snapshot(2);
+                let b = l.new_snapshot(2, 0);
 
                 {
-                    let c = l.new_snapshot(3);
+                    let c = l.new_snapshot(3, 0);
 
                     assert!(!l.empty());
                     assert_eq!(l.oldest(), 1);
@@ -147,5 +187,38 @@ mod tests {
             assert_eq!(l.oldest(), 1);
         }
         assert_eq!(l.oldest(), 0);
+        assert!(l.empty());
+    }
+
+    #[allow(unused_variables)]
+    #[test]
+    fn test_snapshot_list_info() {
+        let mut l = SnapshotList::new();
+        let a = l.new_snapshot(20, 100);
+        let b = l.new_snapshot(10, 150);
+        {
+            let c = l.new_snapshot(30, 200);
+            assert_eq!(10, l.oldest());
+            assert_eq!(30, l.newest());
+            assert_eq!(
+                vec![
+                    SnapshotInfo {
+                        sequence: 10,
+                        age_micros: 150
+                    },
+                    SnapshotInfo {
+                        sequence: 20,
+                        age_micros: 200
+                    },
+                    SnapshotInfo {
+                        sequence: 30,
+                        age_micros: 100
+                    },
+                ],
+                l.list(300)
+            );
+        }
+        assert_eq!(2, l.list(300).len());
+        assert_eq!(20, l.newest());
     }
 }
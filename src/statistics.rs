@@ -0,0 +1,371 @@
+//! statistics collects optional runtime metrics of a database, most importantly latency
+//! histograms for the common operations. Collection is enabled with `Options::statistics`.
+
+use crate::env_common::Instant;
+
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+/// Values below this are counted in their own bucket.
+const LINEAR_BUCKETS: usize = 16;
+/// Each power of two above LINEAR_BUCKETS is split into this many sub-buckets, which bounds the
+/// relative error of a recorded value to 1/SUB_BUCKETS.
+const SUB_BUCKETS: usize = 8;
+const SUB_BUCKET_BITS: u32 = 3;
+const NUM_BUCKETS: usize = LINEAR_BUCKETS + (64 - 4) * SUB_BUCKETS;
+
+/// The operations for which latencies are recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Get = 0,
+    Put = 1,
+    Write = 2,
+    SyncWrite = 3,
+    Seek = 4,
+    Next = 5,
+}
+
+const NUM_OPERATIONS: usize = 6;
+
+/// Histogram is a log-linear ("HDR-style") histogram of values, typically latencies in
+/// microseconds. Buckets are exact for small values and have a relative width of 1/8 above that.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: vec![0; NUM_BUCKETS],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// bucket_of returns the index of the bucket that v is counted in.
+    fn bucket_of(v: u64) -> usize {
+        if v < LINEAR_BUCKETS as u64 {
+            return v as usize;
+        }
+        let exp = 63 - v.leading_zeros();
+        let sub = (v >> (exp - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+        LINEAR_BUCKETS + (exp as usize - 4) * SUB_BUCKETS + sub
+    }
+
+    /// bucket_bounds returns the [lower; upper) range of values counted in bucket ix.
+    fn bucket_bounds(ix: usize) -> (u64, u64) {
+        if ix < LINEAR_BUCKETS {
+            return (ix as u64, ix as u64 + 1);
+        }
+        let exp = ((ix - LINEAR_BUCKETS) / SUB_BUCKETS + 4) as u32;
+        let sub = ((ix - LINEAR_BUCKETS) % SUB_BUCKETS) as u64;
+        let width = 1u64 << (exp - SUB_BUCKET_BITS);
+        let lower = (SUB_BUCKETS as u64 + sub) * width;
+        (lower, lower.saturating_add(width))
+    }
+
+    pub fn add(&mut self, v: u64) {
+        self.buckets[Histogram::bucket_of(v)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(v);
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+
+    /// merge adds all values recorded in other to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Histogram::new();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// percentile returns an estimate of the value below which p percent (0-100) of all recorded
+    /// values fall. The value is interpolated linearly within the matching bucket.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let threshold = self.count as f64 * (p / 100.0);
+        let mut sum = 0.0;
+        for (ix, &n) in self.buckets.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+            sum += n as f64;
+            if sum >= threshold {
+                let (lower, upper) = Histogram::bucket_bounds(ix);
+                let left = sum - n as f64;
+                let pos = (threshold - left) / n as f64;
+                let r = lower as f64 + (upper - lower) as f64 * pos;
+                return r.max(self.min() as f64).min(self.max as f64);
+            }
+        }
+        self.max as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        self.percentile(50.0)
+    }
+}
+
+impl Display for Histogram {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "count: {} avg: {:.2} min: {} median: {:.2} p99: {:.2} p99.9: {:.2} max: {}",
+            self.count,
+            self.average(),
+            self.min(),
+            self.median(),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.max
+        )
+    }
+}
+
+/// Statistics holds the metrics collected by a database instance.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Statistics {
+    latencies: [Histogram; NUM_OPERATIONS],
+    skipped_internal_keys: Histogram,
+    group_commit_sizes: Histogram,
+    seek_compactions: u64,
+    deletion_compactions: u64,
+    space_amp_compactions: u64,
+    write_stalls: u64,
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics::default()
+    }
+
+    /// record adds a latency sample (in microseconds) for the given operation.
+    pub fn record(&mut self, op: Operation, d: Duration) {
+        self.latencies[op as usize].add(d.as_micros() as u64);
+    }
+
+    /// record_since records the time elapsed since start for the given operation.
+    pub fn record_since(&mut self, op: Operation, start: Instant) {
+        self.record(op, start.elapsed())
+    }
+
+    /// latency returns the latency histogram (in microseconds) for the given operation.
+    pub fn latency(&self, op: Operation) -> &Histogram {
+        &self.latencies[op as usize]
+    }
+
+    /// record_skipped_internal_keys records how many internal entries (deleted or overwritten
+    /// entries) an iterator operation skipped.
+    pub fn record_skipped_internal_keys(&mut self, n: u64) {
+        self.skipped_internal_keys.add(n);
+    }
+
+    /// skipped_internal_keys returns the histogram of internal entries skipped per iterator
+    /// operation.
+    pub fn skipped_internal_keys(&self) -> &Histogram {
+        &self.skipped_internal_keys
+    }
+
+    /// record_group_commit records the number of writes that shared one sync of the log.
+    pub fn record_group_commit(&mut self, writes: u64) {
+        self.group_commit_sizes.add(writes);
+    }
+
+    /// group_commit_sizes returns the histogram of writes per group commit; its average is the
+    /// achieved batching factor.
+    pub fn group_commit_sizes(&self) -> &Histogram {
+        &self.group_commit_sizes
+    }
+
+    /// record_seek_compaction counts a compaction that was triggered by too many seeks on a file.
+    pub fn record_seek_compaction(&mut self) {
+        self.seek_compactions += 1;
+    }
+
+    pub fn seek_compactions(&self) -> u64 {
+        self.seek_compactions
+    }
+
+    /// record_deletion_compaction counts a compaction that was triggered by a file with many
+    /// deletion markers.
+    pub fn record_deletion_compaction(&mut self) {
+        self.deletion_compactions += 1;
+    }
+
+    pub fn deletion_compactions(&self) -> u64 {
+        self.deletion_compactions
+    }
+
+    /// record_space_amp_compaction counts a compaction that was triggered by a too high space
+    /// amplification.
+    pub fn record_space_amp_compaction(&mut self) {
+        self.space_amp_compactions += 1;
+    }
+
+    pub fn space_amp_compactions(&self) -> u64 {
+        self.space_amp_compactions
+    }
+
+    /// record_write_stall counts a write that had to wait for a level compaction.
+    pub fn record_write_stall(&mut self) {
+        self.write_stalls += 1;
+    }
+
+    pub fn write_stalls(&self) -> u64 {
+        self.write_stalls
+    }
+
+    pub fn reset(&mut self) {
+        *self = Statistics::default();
+    }
+}
+
+impl Display for Statistics {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for op in &[
+            Operation::Get,
+            Operation::Put,
+            Operation::Write,
+            Operation::SyncWrite,
+            Operation::Seek,
+            Operation::Next,
+        ] {
+            writeln!(f, "{:?} (us): {}", op, self.latency(*op))?;
+        }
+        writeln!(
+            f,
+            "Skipped internal keys per seek/next: {}",
+            self.skipped_internal_keys
+        )?;
+        writeln!(f, "Writes per group commit: {}", self.group_commit_sizes)?;
+        writeln!(f, "Seek compactions: {}", self.seek_compactions)?;
+        writeln!(f, "Deletion compactions: {}", self.deletion_compactions)?;
+        writeln!(
+            f,
+            "Space amplification compactions: {}",
+            self.space_amp_compactions
+        )?;
+        writeln!(f, "Write stalls: {}", self.write_stalls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics_histogram_buckets() {
+        for v in &[
+            0,
+            1,
+            15,
+            16,
+            17,
+            31,
+            32,
+            100,
+            1000,
+            123456,
+            u64::MAX / 3,
+            u64::MAX,
+        ] {
+            let ix = Histogram::bucket_of(*v);
+            let (lower, upper) = Histogram::bucket_bounds(ix);
+            assert!(lower <= *v, "{} {} {}", v, lower, upper);
+            assert!(*v < upper || upper == u64::MAX, "{} {} {}", v, lower, upper);
+        }
+        assert_eq!(NUM_BUCKETS - 1, Histogram::bucket_of(u64::MAX));
+    }
+
+    #[test]
+    fn test_statistics_histogram_percentiles() {
+        let mut h = Histogram::new();
+        assert_eq!(0.0, h.percentile(99.0));
+        for i in 1..1001 {
+            h.add(i);
+        }
+        assert_eq!(1000, h.count());
+        assert_eq!(1, h.min());
+        assert_eq!(1000, h.max());
+        assert_eq!(500.5, h.average());
+        // Relative error is bounded by the sub-bucket width.
+        assert!((h.median() - 500.0).abs() < 500.0 / 8.0);
+        assert!((h.percentile(99.0) - 990.0).abs() < 990.0 / 8.0);
+        assert!(h.percentile(100.0) <= 1000.0);
+
+        let mut h2 = Histogram::new();
+        h2.add(5000);
+        h2.merge(&h);
+        assert_eq!(1001, h2.count());
+        assert_eq!(5000, h2.max());
+        h2.clear();
+        assert_eq!(0, h2.count());
+    }
+
+    #[test]
+    fn test_statistics_record_reset() {
+        let mut s = Statistics::new();
+        s.record(Operation::Get, Duration::from_micros(12));
+        s.record(Operation::Get, Duration::from_micros(14));
+        s.record(Operation::Next, Duration::from_micros(1));
+        assert_eq!(2, s.latency(Operation::Get).count());
+        assert_eq!(1, s.latency(Operation::Next).count());
+        assert_eq!(0, s.latency(Operation::Put).count());
+        s.record_seek_compaction();
+        assert_eq!(1, s.seek_compactions());
+        s.reset();
+        assert_eq!(0, s.latency(Operation::Get).count());
+        assert_eq!(0, s.seek_compactions());
+    }
+}
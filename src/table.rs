@@ -0,0 +1,311 @@
+//! Inspection of single table files, independent of a database.
+//!
+//! ```
+//! use rusty_leveldb::table::TableFile;
+//! use rusty_leveldb::{LdbIterator, DB};
+//!
+//! let opt = rusty_leveldb::in_memory();
+//! let mut db = DB::open("inspected", opt.clone()).unwrap();
+//! db.put(b"key", b"value").unwrap();
+//! db.compact_range(b"a", b"z").unwrap();
+//!
+//! let name = opt.env.children("inspected".as_ref()).unwrap().into_iter()
+//!     .find(|f| f.extension().is_some_and(|e| e == "ldb"))
+//!     .unwrap();
+//! let table = TableFile::open(opt.env.clone(), &std::path::Path::new("inspected").join(name))
+//!     .unwrap();
+//! let mut iter = table.iter();
+//! let (k, v) = iter.next().unwrap();
+//! let key = rusty_leveldb::table::parse_key(&k).unwrap();
+//! assert_eq!((&b"key"[..], false), (key.user_key, key.deletion));
+//! assert_eq!(b"value".to_vec(), v);
+//! ```
+
+use crate::cmp::Cmp;
+use crate::env::Env;
+use crate::error::{err, Result, StatusCode};
+use crate::key_types::{parse_tag, LookupKey, ValueType};
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+use crate::table_reader::{Table, TableIterator};
+use crate::types::{LdbIterator, SequenceNumber};
+
+pub use crate::table_properties::TableProperties;
+
+use std::cmp::Ordering;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use integer_encoding::FixedInt;
+
+/// The location of a data block and its index key, which is greater than or equal to the last
+/// key in the block and smaller than the first key of the next block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableIndexEntry {
+    pub key: Vec<u8>,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// TableFile is a read-only view of a table (`.ldb`) file.
+pub struct TableFile {
+    table: Table,
+    index: Vec<TableIndexEntry>,
+    meta_blocks: Vec<String>,
+}
+
+impl TableFile {
+    /// open opens the table file at path. Keys are ordered bytewise; use `open_with_options()`
+    /// for tables written with another comparator.
+    pub fn open<P: AsRef<Path>>(env: Rc<Box<dyn Env>>, path: P) -> Result<TableFile> {
+        let opt = Options {
+            env,
+            ..Options::default()
+        };
+        TableFile::open_with_options(opt, path)
+    }
+
+    /// open_with_options opens the table file at path, using the environment, comparator, filter
+    /// policy and compressors of opt.
+    pub fn open_with_options<P: AsRef<Path>>(opt: Options, path: P) -> Result<TableFile> {
+        let path = path.as_ref();
+        let size = opt.env.size_of(path)?;
+        let file = opt.env.open_random_access_file(path)?;
+        let table = Table::new(opt, Rc::new(file), size)?;
+        let index = table
+            .index_entries()?
+            .into_iter()
+            .map(|(key, h)| TableIndexEntry {
+                key,
+                offset: h.offset(),
+                size: h.size(),
+            })
+            .collect();
+        let meta_blocks = table
+            .meta_index_entries()?
+            .into_iter()
+            .map(|(name, _)| String::from_utf8_lossy(&name).into_owned())
+            .collect();
+        Ok(TableFile {
+            table,
+            index,
+            meta_blocks,
+        })
+    }
+
+    pub fn file_size(&self) -> usize {
+        self.table.file_size()
+    }
+
+    /// properties returns the properties stored in the table. Tables written by other LevelDB
+    /// implementations, or without deletion markers, don't have any.
+    pub fn properties(&self) -> Option<&TableProperties> {
+        self.table.properties()
+    }
+
+    /// index returns the index entries, one per data block.
+    pub fn index(&self) -> &[TableIndexEntry] {
+        &self.index
+    }
+
+    /// meta_blocks returns the names of the table's meta blocks, e.g.
+    /// `filter.leveldb.BuiltinBloomFilter2`.
+    pub fn meta_blocks(&self) -> &[String] {
+        &self.meta_blocks
+    }
+
+    /// prefix_may_match returns false if the table contains no key with the given user key prefix.
+    /// This is only known for tables written with prefix filtering (see
+    /// `Options::prefix_filtering`) and the prefix extractor passed to `open_with_options()`.
+    pub fn prefix_may_match(&self, prefix: &[u8]) -> bool {
+        self.table.prefix_may_match(prefix)
+    }
+
+    /// iter returns an iterator over the raw entries of the table, whose keys are internal keys
+    /// (see `parse_key()`). The iterator skips blocks that can't be read; use
+    /// `verify_checksums()` to detect them.
+    pub fn iter(&self) -> TableFileIter {
+        TableFileIter(self.table.iter())
+    }
+
+    /// verify_checksums reads all data blocks, returning the first error.
+    pub fn verify_checksums(&self) -> Result<()> {
+        self.table.verify_checksums(None)
+    }
+}
+
+/// TableFileIter iterates over the raw entries of a table file.
+pub struct TableFileIter(TableIterator);
+
+impl LdbIterator for TableFileIter {
+    fn advance(&mut self) -> bool {
+        self.0.advance()
+    }
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        self.0.current(key, val)
+    }
+    /// seek moves to the first entry at or after an internal key.
+    fn seek(&mut self, key: &[u8]) {
+        self.0.seek(key)
+    }
+    fn reset(&mut self) {
+        self.0.reset()
+    }
+    fn valid(&self) -> bool {
+        self.0.valid()
+    }
+    fn prev(&mut self) -> bool {
+        self.0.prev()
+    }
+    fn seek_to_last(&mut self) {
+        self.0.seek_to_last()
+    }
+}
+
+/// The sequence number of the entries written by `TableFileWriter`, the oldest there is.
+const EXTERNAL_SEQ: SequenceNumber = 1;
+
+/// TableFileWriter writes a table file outside of a database, e.g. in order to add it to one
+/// with `DB::ingest_external_file()`. Keys must be added in increasing order, each at most once.
+pub struct TableFileWriter {
+    builder: TableBuilder<BufWriter<Box<dyn Write>>>,
+    cmp: Rc<Box<dyn Cmp>>,
+    last: Option<Vec<u8>>,
+}
+
+impl TableFileWriter {
+    /// create creates the table file at path, using the environment, comparator, filter policy
+    /// and compression settings of opt.
+    pub fn create<P: AsRef<Path>>(opt: Options, path: P) -> Result<TableFileWriter> {
+        let file = opt.env.open_writable_file(path.as_ref())?;
+        Ok(TableFileWriter {
+            cmp: opt.cmp.clone(),
+            builder: TableBuilder::new(opt, BufWriter::new(file)),
+            last: None,
+        })
+    }
+
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.add(key, val, ValueType::TypeValue)
+    }
+
+    /// delete writes a deletion marker, which hides the key's entries that are already in the
+    /// database the table is added to.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.add(key, &[], ValueType::TypeDeletion)
+    }
+
+    fn add(&mut self, key: &[u8], val: &[u8], typ: ValueType) -> Result<()> {
+        if let Some(ref last) = self.last {
+            if self.cmp.cmp(last, key) != Ordering::Less {
+                return err(
+                    StatusCode::InvalidArgument,
+                    "keys must be added in increasing order",
+                );
+            }
+        }
+        let ikey = LookupKey::new_full(key, EXTERNAL_SEQ, typ);
+        self.builder.add(ikey.internal_key(), val)?;
+        self.last = Some(key.to_vec());
+        Ok(())
+    }
+
+    /// entries returns the number of entries added so far.
+    pub fn entries(&self) -> usize {
+        self.builder.entries()
+    }
+
+    /// finish writes the rest of the table, and returns the file's size.
+    pub fn finish(self) -> Result<usize> {
+        self.builder.finish()
+    }
+}
+
+/// ParsedKey is the decoded form of an internal key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParsedKey<'a> {
+    pub user_key: &'a [u8],
+    pub seq: u64,
+    /// Whether the entry is a deletion marker.
+    pub deletion: bool,
+}
+
+/// parse_key decodes an internal key as returned by `TableFileIter`.
+pub fn parse_key(key: &[u8]) -> Result<ParsedKey<'_>> {
+    if key.len() < 8 {
+        return err(StatusCode::Corruption, "internal key too short");
+    }
+    let tag = u64::decode_fixed(&key[key.len() - 8..]);
+    if tag & 0xff > ValueType::TypeValue as u64 {
+        return err(
+            StatusCode::Corruption,
+            &format!("unknown value type {}", tag & 0xff),
+        );
+    }
+    let (typ, seq) = parse_tag(tag);
+    Ok(ParsedKey {
+        user_key: &key[..key.len() - 8],
+        seq,
+        deletion: typ == ValueType::TypeDeletion,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_types::LookupKey;
+    use crate::options;
+    use crate::table_builder::TableBuilder;
+
+    #[test]
+    fn test_table_file() {
+        let opt = options::for_test();
+        let mut opt2 = opt.clone();
+        opt2.block_size = 32;
+        {
+            let f = opt.env.open_writable_file(Path::new("t.ldb")).unwrap();
+            let mut b = TableBuilder::new(opt2, f);
+            for i in 0..10u64 {
+                let k = format!("key{}", i);
+                let lk = if i == 5 {
+                    LookupKey::new_full(k.as_bytes(), 100 + i, ValueType::TypeDeletion)
+                } else {
+                    LookupKey::new(k.as_bytes(), 100 + i)
+                };
+                b.add(lk.internal_key(), b"value").unwrap();
+            }
+            b.finish().unwrap();
+        }
+
+        let t = TableFile::open(opt.env.clone(), "t.ldb").unwrap();
+        assert_eq!(opt.env.size_of(Path::new("t.ldb")).unwrap(), t.file_size());
+        assert_eq!(1, t.properties().unwrap().num_deletions);
+        assert_eq!(10, t.properties().unwrap().num_entries);
+        assert_eq!(
+            vec![
+                "filter.leveldb.BuiltinBloomFilter2".to_string(),
+                "rusty-leveldb.properties".to_string()
+            ],
+            t.meta_blocks()
+        );
+        assert!(t.index().len() > 1);
+        assert_eq!(0, t.index()[0].offset);
+        t.verify_checksums().unwrap();
+
+        let mut iter = t.iter();
+        let mut n = 0;
+        while let Some((k, v)) = iter.next() {
+            let k = parse_key(&k).unwrap();
+            assert_eq!(format!("key{}", n).as_bytes(), k.user_key);
+            assert_eq!(100 + n, k.seq);
+            assert_eq!(n == 5, k.deletion);
+            assert_eq!(b"value".to_vec(), v);
+            n += 1;
+        }
+        assert_eq!(10, n);
+
+        assert!(parse_key(b"short").is_err());
+        assert!(TableFile::open(opt.env.clone(), "missing.ldb").is_err());
+    }
+}
@@ -4,11 +4,12 @@ use crate::blockhandle::BlockHandle;
 use crate::cmp::InternalKeyCmp;
 use crate::compressor::{self, Compressor, CompressorId};
 use crate::error::Result;
-use crate::filter::{InternalFilterPolicy, NoFilterPolicy};
+use crate::filter::{prefix_filter_key, InternalFilterPolicy, NoFilterPolicy, PrefixExtractor};
 use crate::filter_block::FilterBlockBuilder;
-use crate::key_types::InternalKey;
+use crate::key_types::{parse_internal_key, InternalKey, ValueType};
 use crate::log::mask_crc;
 use crate::options::Options;
+use crate::table_properties::{meta_block_options, TableProperties, PROPERTIES_BLOCK_NAME};
 
 use std::cmp::Ordering;
 use std::io::Write;
@@ -87,7 +88,13 @@ pub struct TableBuilder<Dst: Write> {
 
     offset: usize,
     num_entries: usize,
+    // Whether keys are InternalKeys, i.e. whether deletion markers can be counted.
+    internal_keys: bool,
+    props: TableProperties,
     prev_block_last_key: Vec<u8>,
+    // Set if prefixes are added to the filter.
+    prefix_extractor: Option<Rc<Box<dyn PrefixExtractor>>>,
+    last_prefix: Option<Vec<u8>>,
 
     data_block: Option<BlockBuilder>,
     index_block: Option<BlockBuilder>,
@@ -110,20 +117,45 @@ impl<Dst: Write> TableBuilder<Dst> {
     pub fn new(mut opt: Options, dst: Dst) -> TableBuilder<Dst> {
         opt.cmp = Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
         opt.filter_policy = Rc::new(Box::new(InternalFilterPolicy::new(opt.filter_policy)));
-        TableBuilder::new_raw(opt, dst)
+        let mut b = TableBuilder::new_raw(opt, dst);
+        b.internal_keys = true;
+        b
     }
 
     /// Like new(), but doesn't wrap the comparator in an InternalKeyCmp (for testing)
     pub fn new_raw(opt: Options, dst: Dst) -> TableBuilder<Dst> {
+        let prefix_extractor = opt
+            .prefix_extractor
+            .clone()
+            .filter(|_| opt.prefix_filtering);
+        let props = TableProperties {
+            whole_key_filter: opt.whole_key_filtering,
+            prefix_extractor: prefix_extractor
+                .as_ref()
+                .map(|e| e.name())
+                .unwrap_or_default(),
+            ..TableProperties::default()
+        };
         TableBuilder {
             opt: opt.clone(),
             dst,
             offset: 0,
             prev_block_last_key: vec![],
             num_entries: 0,
-            data_block: Some(BlockBuilder::new(opt.clone())),
-            filter_block: Some(FilterBlockBuilder::new(opt.filter_policy.clone())),
+            internal_keys: false,
+            props,
+            data_block: Some(BlockBuilder::new_data_block(opt.clone())),
+            filter_block: if opt.whole_key_filtering || prefix_extractor.is_some() {
+                Some(FilterBlockBuilder::new_with_base_lg2(
+                    opt.filter_policy.clone(),
+                    opt.filter_base_lg2,
+                ))
+            } else {
+                None
+            },
             index_block: Some(BlockBuilder::new(opt)),
+            prefix_extractor,
+            last_prefix: None,
         }
     }
 
@@ -131,6 +163,11 @@ impl<Dst: Write> TableBuilder<Dst> {
         self.num_entries
     }
 
+    /// properties returns the properties of the entries added so far.
+    pub fn properties(&self) -> &TableProperties {
+        &self.props
+    }
+
     pub fn size_estimate(&self) -> usize {
         let mut size = 0;
         if let Some(ref b) = self.data_block {
@@ -160,10 +197,29 @@ impl<Dst: Write> TableBuilder<Dst> {
         let dblock = &mut self.data_block.as_mut().unwrap();
 
         if let Some(ref mut fblock) = self.filter_block {
-            fblock.add_key(key);
+            if self.opt.whole_key_filtering {
+                fblock.add_key(key);
+            }
+            if let Some(ref extractor) = self.prefix_extractor {
+                let ukey = if self.internal_keys {
+                    &key[..key.len() - 8]
+                } else {
+                    key
+                };
+                if let Some(prefix) = extractor.prefix(ukey) {
+                    if self.last_prefix.as_deref() != Some(prefix) {
+                        fblock.add_key(&prefix_filter_key(prefix, self.internal_keys));
+                        self.last_prefix = Some(prefix.to_vec());
+                    }
+                }
+            }
         }
 
         self.num_entries += 1;
+        self.props.num_entries += 1;
+        if self.internal_keys && parse_internal_key(key).0 == ValueType::TypeDeletion {
+            self.props.num_deletions += 1;
+        }
         dblock.add(key, val);
         Ok(())
     }
@@ -175,7 +231,11 @@ impl<Dst: Write> TableBuilder<Dst> {
         assert!(self.data_block.is_some());
 
         let block = self.data_block.take().unwrap();
-        let sep = self.opt.cmp.find_shortest_sep(&block.last_key(), next_key);
+        let sep = if self.opt.shorten_index_keys {
+            self.opt.cmp.find_shortest_sep(block.last_key(), next_key)
+        } else {
+            block.last_key().to_vec()
+        };
         self.prev_block_last_key = Vec::from(block.last_key());
         let contents = block.finish();
 
@@ -191,11 +251,13 @@ impl<Dst: Write> TableBuilder<Dst> {
             .as_mut()
             .unwrap()
             .add(&sep, &handle_enc[0..enc_len]);
-        self.data_block = Some(BlockBuilder::new(self.opt.clone()));
+        self.data_block = Some(BlockBuilder::new_data_block(self.opt.clone()));
 
         if let Some(ref mut fblock) = self.filter_block {
             fblock.start_block(self.offset);
         }
+        // Filters are per range of blocks, each of which needs the prefix.
+        self.last_prefix = None;
 
         Ok(())
     }
@@ -236,15 +298,17 @@ impl<Dst: Write> TableBuilder<Dst> {
         // If there's a pending data block, write it
         if self.data_block.as_ref().unwrap().entries() > 0 {
             // Find a key reliably past the last key
-            let key_past_last = self
-                .opt
-                .cmp
-                .find_short_succ(self.data_block.as_ref().unwrap().last_key());
+            let last_key = self.data_block.as_ref().unwrap().last_key();
+            let key_past_last = if self.opt.shorten_index_keys {
+                self.opt.cmp.find_short_succ(last_key)
+            } else {
+                last_key.to_vec()
+            };
             self.write_data_block(&key_past_last)?;
         }
 
         // Create metaindex block
-        let mut meta_ix_block = BlockBuilder::new(self.opt.clone());
+        let mut meta_ix_block = BlockBuilder::new(meta_block_options(&self.opt));
 
         if self.filter_block.is_some() {
             // if there's a filter block, write the filter block and add it to the metaindex block.
@@ -265,6 +329,19 @@ impl<Dst: Write> TableBuilder<Dst> {
             meta_ix_block.add(filter_key.as_bytes(), &handle_enc[0..enc_len]);
         }
 
+        // Properties are only written for tables with deletion markers or prefix filters; other
+        // tables stay identical to the ones written by the original implementation. "filter.*"
+        // sorts before the properties block's name.
+        if (self.props.num_deletions > 0 || self.prefix_extractor.is_some())
+            && !self.opt.leveldb_compatible
+        {
+            let props_data = self.props.encode(&self.opt);
+            let props_handle = self.write_block(props_data, compressor_id_pair)?;
+            let mut handle_enc = [0; 16];
+            let enc_len = props_handle.encode_to(&mut handle_enc);
+            meta_ix_block.add(PROPERTIES_BLOCK_NAME.as_bytes(), &handle_enc[0..enc_len]);
+        }
+
         // write metaindex block
         let meta_ix = meta_ix_block.finish();
         let meta_ix_handle = self.write_block(meta_ix, compressor_id_pair)?;
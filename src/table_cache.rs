@@ -3,14 +3,16 @@
 //! returned.
 
 use crate::cache::{self, Cache};
+use crate::env::RandomAccess;
 use crate::error::{err, Result, StatusCode};
 use crate::key_types::InternalKey;
-use crate::options::Options;
-use crate::table_reader::Table;
+use crate::options::{Options, ReadOptions};
+use crate::table_reader::{PinnedEntry, Table};
 use crate::types::FileNum;
 
 use integer_encoding::FixedIntWriter;
 
+use std::cell::RefCell;
 use std::convert::AsRef;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
@@ -48,9 +50,21 @@ impl TableCache {
         &mut self,
         file_num: FileNum,
         key: InternalKey<'a>,
-    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        ropt: &ReadOptions,
+    ) -> Result<Option<PinnedEntry>> {
         let tbl = self.get_table(file_num)?;
-        tbl.get(key)
+        tbl.get_pinned(key, ropt)
+    }
+
+    /// multi_get looks up several sorted keys in a table (see `Table::multi_get_pinned()`).
+    pub fn multi_get<'a>(
+        &mut self,
+        file_num: FileNum,
+        keys: &[InternalKey<'a>],
+        ropt: &ReadOptions,
+    ) -> Result<Vec<Option<PinnedEntry>>> {
+        let tbl = self.get_table(file_num)?;
+        tbl.multi_get_pinned(keys, ropt)
     }
 
     /// Return a table from cache, or open the backing file, then cache and return it.
@@ -62,6 +76,11 @@ impl TableCache {
         self.open_table(file_num)
     }
 
+    /// cached_table returns a table if it is open already.
+    pub fn cached_table(&mut self, file_num: FileNum) -> Option<Table> {
+        self.cache.get(&filenum_to_key(file_num)).cloned()
+    }
+
     /// Open a table on the file system and read it.
     fn open_table(&mut self, file_num: FileNum) -> Result<Table> {
         let name = table_file_name(&self.dbname, file_num);
@@ -77,6 +96,25 @@ impl TableCache {
         Ok(table)
     }
 
+    /// open_table_with_readahead opens a table without caching it. Its file is read in chunks of
+    /// at least `readahead` bytes, which speeds up sequential scans like compactions.
+    pub fn open_table_with_readahead(&self, file_num: FileNum, readahead: usize) -> Result<Table> {
+        let name = table_file_name(&self.dbname, file_num);
+        let path = Path::new(&name);
+        let file_size = self.opts.env.size_of(path)?;
+        if file_size == 0 {
+            return err(StatusCode::InvalidData, "file is empty");
+        }
+        let file = self.opts.env.open_random_access_file(path)?;
+        let file: Box<dyn RandomAccess> = Box::new(ReadaheadFile::new(file, file_size, readahead));
+        Table::new(self.opts.clone(), Rc::new(file), file_size)
+    }
+
+    /// memory_usage returns the memory used by the open tables (see `Table::memory_usage()`).
+    pub fn memory_usage(&self) -> usize {
+        self.cache.values().map(Table::memory_usage).sum()
+    }
+
     pub fn evict(&mut self, file_num: FileNum) -> Result<()> {
         if self.cache.remove(&filenum_to_key(file_num)).is_some() {
             Ok(())
@@ -86,6 +124,47 @@ impl TableCache {
     }
 }
 
+/// ReadaheadFile serves reads from a buffer filled by large sequential reads.
+struct ReadaheadFile {
+    f: Box<dyn RandomAccess>,
+    size: usize,
+    readahead: usize,
+    // (offset, contents) of the buffered range.
+    buf: RefCell<(usize, Vec<u8>)>,
+}
+
+impl ReadaheadFile {
+    fn new(f: Box<dyn RandomAccess>, size: usize, readahead: usize) -> ReadaheadFile {
+        ReadaheadFile {
+            f,
+            size,
+            readahead,
+            buf: RefCell::new((0, vec![])),
+        }
+    }
+}
+
+impl RandomAccess for ReadaheadFile {
+    fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize> {
+        let mut buf = self.buf.borrow_mut();
+        let (ref mut buf_off, ref mut contents) = *buf;
+        if off < *buf_off || off + dst.len() > *buf_off + contents.len() {
+            let len = self
+                .readahead
+                .max(dst.len())
+                .min(self.size.saturating_sub(off));
+            contents.resize(len, 0);
+            let n = self.f.read_at(off, contents)?;
+            contents.truncate(n);
+            *buf_off = off;
+        }
+        let start = off - *buf_off;
+        let n = dst.len().min(contents.len() - start);
+        dst[..n].copy_from_slice(&contents[start..start + n]);
+        Ok(n)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,5 +241,31 @@ mod tests {
         assert!(cache.evict(123).is_ok());
         assert!(cache.evict(123).is_err());
         assert!(cache.cache.get(&filenum_to_key(123)).is_none());
+
+        // Uncached table with readahead.
+        for readahead in &[1, 7, 4096] {
+            let tbl = cache.open_table_with_readahead(123, *readahead).unwrap();
+            assert_eq!(LdbIteratorIter::wrap(&mut tbl.iter()).count(), 4);
+        }
+        assert!(cache.cache.get(&filenum_to_key(123)).is_none());
+    }
+
+    #[test]
+    fn test_table_cache_readahead_file() {
+        let contents: Vec<u8> = (0..100).collect();
+        let f = ReadaheadFile::new(Box::new(contents.clone()), contents.len(), 16);
+        let mut dst = [0; 10];
+        assert_eq!(10, f.read_at(5, &mut dst).unwrap());
+        assert_eq!(&contents[5..15], &dst);
+        assert_eq!((5, 16), (f.buf.borrow().0, f.buf.borrow().1.len()));
+        // Served from the buffer.
+        assert_eq!(5, f.read_at(16, &mut dst[..5]).unwrap());
+        assert_eq!(5, f.buf.borrow().0);
+        // Reads larger than the readahead and at the end of the file.
+        let mut dst = [0; 40];
+        assert_eq!(40, f.read_at(50, &mut dst).unwrap());
+        assert_eq!(&contents[50..90], &dst[..]);
+        assert_eq!(10, f.read_at(90, &mut dst).unwrap());
+        assert_eq!(&contents[90..], &dst[..10]);
     }
 }
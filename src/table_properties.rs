@@ -0,0 +1,130 @@
+//! Table properties are statistics about a table's contents, stored in a meta block of the table
+//! file. Tables written by other LevelDB implementations don't contain this block; readers ignore
+//! unknown meta blocks, so the file format stays compatible.
+
+use crate::block::{Block, BlockContents};
+use crate::block_builder::BlockBuilder;
+use crate::cmp::DefaultCmp;
+use crate::options::Options;
+use crate::types::LdbIterator;
+
+use std::rc::Rc;
+
+use integer_encoding::VarInt;
+
+/// The key of the properties block in a table's meta index block.
+pub const PROPERTIES_BLOCK_NAME: &str = "rusty-leveldb.properties";
+
+const FILTER_PREFIX_EXTRACTOR: &[u8] = b"filter.prefix_extractor";
+const FILTER_WHOLE_KEYS: &[u8] = b"filter.whole_keys";
+const NUM_DELETIONS: &[u8] = b"num_deletions";
+const NUM_ENTRIES: &[u8] = b"num_entries";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableProperties {
+    /// Number of entries in the table.
+    pub num_entries: u64,
+    /// Number of deletion markers in the table.
+    pub num_deletions: u64,
+    /// Whether the filter block contains whole keys.
+    pub whole_key_filter: bool,
+    /// The name of the prefix extractor whose prefixes the filter block contains, or empty.
+    pub prefix_extractor: String,
+}
+
+impl Default for TableProperties {
+    fn default() -> TableProperties {
+        TableProperties {
+            num_entries: 0,
+            num_deletions: 0,
+            whole_key_filter: true,
+            prefix_extractor: String::new(),
+        }
+    }
+}
+
+impl TableProperties {
+    /// deletion_ratio returns the share of deletion markers in all entries (0 to 1).
+    pub fn deletion_ratio(&self) -> f64 {
+        deletion_ratio(self.num_entries, self.num_deletions)
+    }
+
+    /// encode returns the contents of a properties block.
+    pub fn encode(&self, opt: &Options) -> BlockContents {
+        let mut b = BlockBuilder::new(meta_block_options(opt));
+        // Keys must be added in order. Filter properties are only stored if they differ from the
+        // defaults.
+        if !self.prefix_extractor.is_empty() {
+            b.add(FILTER_PREFIX_EXTRACTOR, self.prefix_extractor.as_bytes());
+        }
+        if !self.whole_key_filter {
+            b.add(FILTER_WHOLE_KEYS, &0u64.encode_var_vec());
+        }
+        b.add(NUM_DELETIONS, &self.num_deletions.encode_var_vec());
+        b.add(NUM_ENTRIES, &self.num_entries.encode_var_vec());
+        b.finish()
+    }
+
+    /// decode reads properties from a properties block. Unknown properties are ignored.
+    pub fn decode(block: &Block) -> TableProperties {
+        let mut props = TableProperties::default();
+        let mut it = block.iter();
+        let (mut k, mut v) = (vec![], vec![]);
+        while it.advance() {
+            it.current(&mut k, &mut v);
+            let n = u64::decode_var(&v).map(|(n, _)| n).unwrap_or(0);
+            if k == FILTER_PREFIX_EXTRACTOR {
+                props.prefix_extractor = String::from_utf8_lossy(&v).into_owned();
+            } else if k == FILTER_WHOLE_KEYS {
+                props.whole_key_filter = n != 0;
+            } else if k == NUM_DELETIONS {
+                props.num_deletions = n;
+            } else if k == NUM_ENTRIES {
+                props.num_entries = n;
+            }
+        }
+        props
+    }
+}
+
+pub fn deletion_ratio(num_entries: u64, num_deletions: u64) -> f64 {
+    if num_entries == 0 {
+        0.0
+    } else {
+        num_deletions as f64 / num_entries as f64
+    }
+}
+
+/// meta_block_options returns options for building and reading meta blocks. Like in the original,
+/// their keys are ordered bytewise, independent of the comparator used for the table's keys.
+pub fn meta_block_options(opt: &Options) -> Options {
+    let mut opt = opt.clone();
+    opt.cmp = Rc::new(Box::new(DefaultCmp));
+    opt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+
+    #[test]
+    fn test_table_properties_encode_decode() {
+        let props = TableProperties {
+            num_entries: 1000,
+            num_deletions: 250,
+            whole_key_filter: false,
+            prefix_extractor: "prefix".to_string(),
+        };
+        let opt = meta_block_options(&options::for_test());
+        let block = Block::new(opt.clone(), props.encode(&opt));
+        let decoded = TableProperties::decode(&block);
+        assert_eq!(props, decoded);
+        assert_eq!(0.25, decoded.deletion_ratio());
+        assert_eq!(0.0, TableProperties::default().deletion_ratio());
+
+        let props = TableProperties::default();
+        let block = Block::new(opt.clone(), props.encode(&opt));
+        assert_eq!(props, TableProperties::decode(&block));
+    }
+}
@@ -1,16 +1,21 @@
 use crate::block::{Block, BlockIter};
 use crate::blockhandle::BlockHandle;
 use crate::cache;
+use crate::cache_sim::BlockAccess;
+use crate::cancellation::{check_cancelled, CancellationToken};
 use crate::cmp::InternalKeyCmp;
 use crate::env::RandomAccess;
 use crate::error::{self, err, Result};
-use crate::filter;
+use crate::filter::{self, prefix_filter_key, PrefixExtractor};
 use crate::filter_block::FilterBlockReader;
-use crate::key_types::InternalKey;
-use crate::options::Options;
+use crate::key_types::{InternalKey, LookupKey};
+use crate::metrics;
+use crate::options::{Options, ReadOptions};
+use crate::pinned_slice::PinnedSlice;
 use crate::table_block;
 use crate::table_builder::{self, Footer};
-use crate::types::{current_key_val, LdbIterator};
+use crate::table_properties::{meta_block_options, TableProperties, PROPERTIES_BLOCK_NAME};
+use crate::types::{current_key_val, LdbIterator, MAX_SEQUENCE_NUMBER};
 
 use std::cmp::Ordering;
 use std::rc::Rc;
@@ -41,6 +46,9 @@ pub struct Table {
     footer: Footer,
     indexblock: Block,
     filters: Option<FilterBlockReader>,
+    props: Option<TableProperties>,
+    // Whether keys are InternalKeys.
+    internal_keys: bool,
 }
 
 impl Table {
@@ -49,11 +57,15 @@ impl Table {
         let footer = read_footer(file.as_ref().as_ref(), size)?;
         let indexblock =
             table_block::read_table_block(opt.clone(), file.as_ref().as_ref(), &footer.index)?;
-        let metaindexblock =
-            table_block::read_table_block(opt.clone(), file.as_ref().as_ref(), &footer.meta_index)?;
+        let metaindexblock = table_block::read_table_block(
+            meta_block_options(&opt),
+            file.as_ref().as_ref(),
+            &footer.meta_index,
+        )?;
 
         let filter_block_reader =
             Table::read_filter_block(&metaindexblock, file.as_ref().as_ref(), &opt)?;
+        let props = Table::read_properties(&metaindexblock, file.as_ref().as_ref(), &opt)?;
         let cache_id = opt.block_cache.borrow_mut().new_cache_id();
 
         Ok(Table {
@@ -63,7 +75,9 @@ impl Table {
             opt,
             footer,
             filters: filter_block_reader,
+            props,
             indexblock,
+            internal_keys: false,
         })
     }
 
@@ -80,7 +94,11 @@ impl Table {
         let mut metaindexiter = metaix.iter();
         metaindexiter.seek(&filter_name);
 
-        if let Some((_key, val)) = current_key_val(&metaindexiter) {
+        if let Some((key, val)) = current_key_val(&metaindexiter) {
+            if key != filter_name {
+                // The table was written with a different filter policy, or none.
+                return Ok(None);
+            }
             let fbl = BlockHandle::decode(&val);
             let filter_block_location = match fbl {
                 None => {
@@ -102,6 +120,67 @@ impl Table {
         Ok(None)
     }
 
+    /// read_properties reads the table's properties block, if there is one.
+    fn read_properties(
+        metaix: &Block,
+        file: &dyn RandomAccess,
+        options: &Options,
+    ) -> Result<Option<TableProperties>> {
+        let mut metaindexiter = metaix.iter();
+        metaindexiter.seek(PROPERTIES_BLOCK_NAME.as_bytes());
+
+        if let Some((key, val)) = current_key_val(&metaindexiter) {
+            if key != PROPERTIES_BLOCK_NAME.as_bytes() {
+                return Ok(None);
+            }
+            let location = match BlockHandle::decode(&val) {
+                None => {
+                    return err(
+                        error::StatusCode::Corruption,
+                        &format!("Couldn't decode corrupt blockhandle {:?}", &val),
+                    )
+                }
+                Some(ok) => ok.0,
+            };
+            let block =
+                table_block::read_table_block(meta_block_options(options), file, &location)?;
+            return Ok(Some(TableProperties::decode(&block)));
+        }
+        Ok(None)
+    }
+
+    /// properties returns the table's properties. Tables written by other implementations may
+    /// not have any.
+    pub fn properties(&self) -> Option<&TableProperties> {
+        self.props.as_ref()
+    }
+
+    pub fn file_size(&self) -> usize {
+        self.file_size
+    }
+
+    /// memory_usage returns the size of the index and filter blocks, which are kept in memory as
+    /// long as the table is open.
+    pub fn memory_usage(&self) -> usize {
+        self.indexblock.size() + self.filters.as_ref().map_or(0, |f| f.size())
+    }
+
+    /// index_entries returns the entries of the index block: for each data block a key that is
+    /// greater than or equal to the block's last key, and the block's location.
+    pub fn index_entries(&self) -> Result<Vec<(Vec<u8>, BlockHandle)>> {
+        block_handles(&self.indexblock)
+    }
+
+    /// meta_index_entries returns the names and locations of the table's meta blocks.
+    pub fn meta_index_entries(&self) -> Result<Vec<(Vec<u8>, BlockHandle)>> {
+        let metaindexblock = table_block::read_table_block(
+            meta_block_options(&self.opt),
+            self.file.as_ref().as_ref(),
+            &self.footer.meta_index,
+        )?;
+        block_handles(&metaindexblock)
+    }
+
     /// Creates a new table reader operating on internal keys (i.e., InternalKey). This means that
     /// a different comparator (internal_key_cmp) and a different filter policy
     /// (InternalFilterPolicy) are used.
@@ -110,7 +189,9 @@ impl Table {
         opt.filter_policy = Rc::new(Box::new(filter::InternalFilterPolicy::new(
             opt.filter_policy,
         )));
-        Table::new_raw(opt, file, size)
+        let mut t = Table::new_raw(opt, file, size)?;
+        t.internal_keys = true;
+        Ok(t)
     }
 
     /// block_cache_handle creates a CacheKey for a block with a given offset to be used in the
@@ -128,25 +209,176 @@ impl Table {
 
     /// Read a block from the current table at `location`, and cache it in the options' block
     /// cache.
-    fn read_block(&self, location: &BlockHandle) -> Result<Block> {
+    pub fn read_block(&self, location: &BlockHandle) -> Result<Block> {
+        self.read_block_with(location, &ReadOptions::default())
+    }
+
+    /// read_block_with is like `read_block()`, but uses the block cache as ropt says.
+    pub fn read_block_with(&self, location: &BlockHandle, ropt: &ReadOptions) -> Result<Block> {
         let cachekey = self.block_cache_handle(location.offset());
-        if let Some(block) = self.opt.block_cache.borrow_mut().get(&cachekey) {
-            return Ok(block.clone());
+        if !ropt.verify_checksums {
+            if let Some(block) = self.opt.block_cache.borrow_mut().get(&cachekey) {
+                metrics::block_cache_access(true);
+                self.trace_access(location, block);
+                return Ok(block.clone());
+            }
+            metrics::block_cache_access(false);
         }
 
-        // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
-        let b =
-            table_block::read_table_block(self.opt.clone(), self.file.as_ref().as_ref(), location)?;
+        let secondary = self
+            .opt
+            .secondary_cache
+            .as_ref()
+            .filter(|_| !ropt.verify_checksums)
+            .and_then(|c| c.borrow_mut().get(&cachekey));
+        let b = match secondary {
+            Some(contents) => Block::new(self.opt.clone(), contents),
+            // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
+            None => table_block::read_table_block(
+                self.opt.clone(),
+                self.file.as_ref().as_ref(),
+                location,
+            )?,
+        };
 
+        if !ropt.fill_cache {
+            self.trace_access(location, &b);
+            return Ok(b);
+        }
         // insert a cheap copy (Rc).
-        self.opt
+        let evicted = self
+            .opt
             .block_cache
             .borrow_mut()
             .insert(&cachekey, b.clone());
+        if let (Some(c), Some((key, block))) = (self.opt.secondary_cache.as_ref(), evicted) {
+            // The block can always be read from the table again.
+            c.borrow_mut().insert(&key, &block.contents()).ok();
+        }
+        self.trace_access(location, &b);
 
         Ok(b)
     }
 
+    fn trace_access(&self, location: &BlockHandle, block: &Block) {
+        if let Some(ref trace) = self.opt.block_trace {
+            trace.borrow_mut().record(BlockAccess {
+                table: self.cache_id,
+                offset: location.offset() as u64,
+                size: block.size() as u32,
+            });
+        }
+    }
+
+    /// verify_checksums reads all data blocks from the file, bypassing the block cache, and
+    /// returns the first error. The token, if given, is checked before each block.
+    pub fn verify_checksums(&self, cancel: Option<&CancellationToken>) -> Result<()> {
+        for (_, h) in self.index_entries()? {
+            check_cancelled(cancel, "integrity check")?;
+            table_block::read_table_block(self.opt.clone(), self.file.as_ref().as_ref(), &h)?;
+        }
+        Ok(())
+    }
+
+    /// is_cached returns whether the block at location is in the block cache.
+    pub fn is_cached(&self, location: &BlockHandle) -> bool {
+        let cachekey = self.block_cache_handle(location.offset());
+        self.opt.block_cache.borrow().contains(&cachekey)
+    }
+
+    /// prefetch loads the data blocks that may contain keys in [from; to] into the block cache,
+    /// and returns how many of them had to be read from the file.
+    pub fn prefetch(&self, from: &[u8], to: &[u8]) -> Result<usize> {
+        let mut iter = self.indexblock.iter();
+        iter.seek(from);
+        let (mut key, mut val) = (vec![], vec![]);
+        let mut read = 0;
+        while iter.valid() && iter.current(&mut key, &mut val) {
+            if let Some((handle, _)) = BlockHandle::decode(&val) {
+                if !self.is_cached(&handle) {
+                    self.read_block(&handle)?;
+                    read += 1;
+                }
+            }
+            // The index key is at least the block's last key.
+            if self.opt.cmp.cmp(&key, to) != Ordering::Less || !iter.advance() {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    /// prefix_extractor returns the configured prefix extractor if the table's filter contains
+    /// its prefixes.
+    fn prefix_extractor(&self) -> Option<&Rc<Box<dyn PrefixExtractor>>> {
+        let extractor = self.opt.prefix_extractor.as_ref()?;
+        let props = self.props.as_ref()?;
+        if props.prefix_extractor.is_empty() || props.prefix_extractor != extractor.name() {
+            return None;
+        }
+        Some(extractor)
+    }
+
+    /// key_may_match checks the filter of the block at offset for key, using the whole key if the
+    /// filter contains whole keys, and its prefix otherwise.
+    fn key_may_match(&self, offset: usize, key: &[u8]) -> bool {
+        let filters = match self.filters {
+            Some(ref f) => f,
+            None => return true,
+        };
+        // Tables without properties were written with whole-key filters.
+        if self.props.as_ref().is_none_or(|p| p.whole_key_filter) {
+            return filters.key_may_match(offset, key);
+        }
+        let ukey = if self.internal_keys {
+            &key[..key.len() - 8]
+        } else {
+            key
+        };
+        match self.prefix_extractor().and_then(|e| e.prefix(ukey)) {
+            Some(prefix) => {
+                filters.key_may_match(offset, &prefix_filter_key(prefix, self.internal_keys))
+            }
+            None => true,
+        }
+    }
+
+    /// prefix_may_match returns false if the table is known to contain no key starting with
+    /// prefix, which must be a prefix as returned by the prefix extractor. This requires the
+    /// table to be written with prefix filtering and the same extractor.
+    pub fn prefix_may_match(&self, prefix: &[u8]) -> bool {
+        let filters = match (&self.filters, self.prefix_extractor()) {
+            (Some(f), Some(e)) if e.prefix(prefix) == Some(prefix) => f,
+            _ => return true,
+        };
+        let target = if self.internal_keys {
+            LookupKey::new(prefix, MAX_SEQUENCE_NUMBER)
+                .internal_key()
+                .to_vec()
+        } else {
+            prefix.to_vec()
+        };
+        let fkey = prefix_filter_key(prefix, self.internal_keys);
+
+        // The first key starting with prefix is in the block whose index key is the first one
+        // after it, or -- as index keys may be greater than a block's last key -- in the next
+        // block.
+        let mut iter = self.indexblock.iter();
+        iter.seek(&target);
+        let (mut key, mut val) = (vec![], vec![]);
+        for _ in 0..2 {
+            if !iter.valid() || !iter.current(&mut key, &mut val) {
+                break;
+            }
+            match BlockHandle::decode(&val) {
+                Some((h, _)) if !filters.key_may_match(h.offset(), &fkey) => {}
+                _ => return true,
+            }
+            iter.advance();
+        }
+        false
+    }
+
     /// Returns the offset of the block that contains `key`.
     pub fn approx_offset_of(&self, key: &[u8]) -> usize {
         let mut iter = self.indexblock.iter();
@@ -163,11 +395,22 @@ impl Table {
 
     /// Iterators read from the file; thus only one iterator can be borrowed (mutably) per scope
     pub fn iter(&self) -> TableIterator {
+        self.iter_with(&ReadOptions::default())
+    }
+
+    /// iter_with is like `iter()`, but the iterator reads blocks as ropt says (see
+    /// `read_block_with()`).
+    pub fn iter_with(&self, ropt: &ReadOptions) -> TableIterator {
         TableIterator {
             current_block: None,
             current_block_off: 0,
             index_block: self.indexblock.iter(),
             table: self.clone(),
+            ropt: ReadOptions {
+                verify_checksums: ropt.verify_checksums,
+                fill_cache: ropt.fill_cache,
+                ..ReadOptions::default()
+            },
         }
     }
 
@@ -183,42 +426,120 @@ impl Table {
     /// exact match; it depends on other comparators than the one that the table reader knows
     /// whether a match is acceptable.
     pub fn get<'a>(&self, key: InternalKey<'a>) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.get_with(key, &ReadOptions::default())
+    }
+
+    /// may_contain returns false if the table certainly contains no entry at or after key and
+    /// before the next user key, judging by the index and filter blocks only.
+    pub fn may_contain<'a>(&self, key: InternalKey<'a>) -> bool {
         let mut index_iter = self.indexblock.iter();
         index_iter.seek(key);
-
-        let handle;
-        if let Some((last_in_block, h)) = current_key_val(&index_iter) {
-            if self.opt.cmp.cmp(key, &last_in_block) == Ordering::Less {
-                handle = BlockHandle::decode(&h).unwrap().0;
-            } else {
-                return Ok(None);
+        match current_key_val(&index_iter) {
+            Some((last_in_block, h))
+                if self.opt.cmp.cmp(key, &last_in_block) != Ordering::Greater =>
+            {
+                match BlockHandle::decode(&h) {
+                    Some((handle, _)) => self.key_may_match(handle.offset(), key),
+                    None => true,
+                }
             }
-        } else {
-            return Ok(None);
+            _ => false,
         }
+    }
 
-        // found correct block.
+    /// get_with is like `get()`, but reads the data block as ropt says.
+    pub fn get_with<'a>(
+        &self,
+        key: InternalKey<'a>,
+        ropt: &ReadOptions,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.get_pinned(key, ropt)?.map(|(k, v)| (k, v.into_vec())))
+    }
 
-        // Check bloom (or whatever) filter
-        if let Some(ref filters) = self.filters {
-            if !filters.key_may_match(handle.offset(), key) {
-                return Ok(None);
+    /// get_pinned is like `get_with()`, but returns the value without copying it out of the
+    /// data block.
+    pub(crate) fn get_pinned<'a>(
+        &self,
+        key: InternalKey<'a>,
+        ropt: &ReadOptions,
+    ) -> Result<Option<PinnedEntry>> {
+        Ok(self.multi_get_pinned(&[key], ropt)?.pop().flatten())
+    }
+
+    /// multi_get_pinned is like calling `get_pinned()` for each of keys, which must be sorted,
+    /// but reads a data block only once for adjacent keys stored in it.
+    pub(crate) fn multi_get_pinned<'a>(
+        &self,
+        keys: &[InternalKey<'a>],
+        ropt: &ReadOptions,
+    ) -> Result<Vec<Option<PinnedEntry>>> {
+        let mut index_iter = self.indexblock.iter();
+        // The data block read last, and its offset.
+        let mut block: Option<(usize, Block)> = None;
+        let mut results = Vec::with_capacity(keys.len());
+
+        for &key in keys {
+            index_iter.seek(key);
+            let handle = match current_key_val(&index_iter) {
+                // The index key may equal the block's last key if it wasn't shortened.
+                Some((last_in_block, h))
+                    if self.opt.cmp.cmp(key, &last_in_block) != Ordering::Greater =>
+                {
+                    BlockHandle::decode(&h).unwrap().0
+                }
+                _ => {
+                    results.push(None);
+                    continue;
+                }
+            };
+
+            // Check bloom (or whatever) filter
+            if !self.key_may_match(handle.offset(), key) {
+                results.push(None);
+                continue;
             }
-        }
 
-        // Read block (potentially from cache)
-        let tb = self.read_block(&handle)?;
-        let mut iter = tb.iter();
+            // Read block (potentially from cache), unless it's the previous key's block.
+            if block
+                .as_ref()
+                .is_none_or(|(offset, _)| *offset != handle.offset())
+            {
+                block = Some((handle.offset(), self.read_block_with(&handle, ropt)?));
+            }
+            let mut iter = block.as_ref().unwrap().1.iter();
 
-        // Go to entry and check if it's the wanted entry.
-        iter.seek(key);
-        if let Some((k, v)) = current_key_val(&iter) {
-            if self.opt.cmp.cmp(&k, key) >= Ordering::Equal {
-                return Ok(Some((k, v)));
+            // Go to entry and check if it's the wanted entry.
+            iter.seek(key);
+            results.push(
+                iter.current_pinned()
+                    .filter(|(k, _)| self.opt.cmp.cmp(k, key) >= Ordering::Equal),
+            );
+        }
+        Ok(results)
+    }
+}
+
+/// PinnedEntry is an entry found by `Table::get_pinned()`: the internal key, and the value
+/// pinned in its data block.
+pub(crate) type PinnedEntry = (Vec<u8>, PinnedSlice);
+
+/// block_handles returns the keys of a block whose values are block handles, and the decoded
+/// handles.
+fn block_handles(block: &Block) -> Result<Vec<(Vec<u8>, BlockHandle)>> {
+    let mut entries = vec![];
+    let mut iter = block.iter();
+    while let Some((key, val)) = iter.next() {
+        match BlockHandle::decode(&val) {
+            Some((handle, _)) => entries.push((key, handle)),
+            None => {
+                return err(
+                    error::StatusCode::Corruption,
+                    &format!("Couldn't decode corrupt blockhandle {:?}", &val),
+                )
             }
         }
-        Ok(None)
     }
+    Ok(entries)
 }
 
 /// This iterator is a "TwoLevelIterator"; it uses an index block in order to get an offset hint
@@ -234,6 +555,8 @@ pub struct TableIterator {
     current_block: Option<BlockIter>,
     current_block_off: usize,
     index_block: BlockIter,
+    // How data blocks are read; the iteration bounds aren't used.
+    ropt: ReadOptions,
 }
 
 impl TableIterator {
@@ -260,7 +583,7 @@ impl TableIterator {
             }
             Some(ok) => ok,
         };
-        let block = self.table.read_block(&new_block_handle)?;
+        let block = self.table.read_block_with(&new_block_handle, &self.ropt)?;
 
         self.current_block = Some(block.iter());
         self.current_block_off = new_block_handle.offset();
@@ -352,6 +675,18 @@ impl LdbIterator for TableIterator {
         }
     }
 
+    fn seek_to_last(&mut self) {
+        // The last entry is in the block referenced by the last index entry.
+        self.index_block.seek_to_last();
+        if let Some((_, handle)) = current_key_val(&self.index_block) {
+            if self.load_block(&handle).is_ok() {
+                self.current_block.as_mut().unwrap().seek_to_last();
+                return;
+            }
+        }
+        self.reset();
+    }
+
     fn reset(&mut self) {
         self.index_block.reset();
         self.current_block = None;
@@ -374,12 +709,14 @@ impl LdbIterator for TableIterator {
 
 #[cfg(test)]
 mod tests {
+    use crate::cache::Cache;
     use crate::compressor::CompressorId;
     use crate::filter::BloomPolicy;
     use crate::key_types::LookupKey;
+    use crate::secondary_cache::SecondaryCache;
     use crate::table_builder::TableBuilder;
     use crate::test_util::{test_iterator_properties, LdbIteratorIter};
-    use crate::types::{current_key_val, LdbIterator};
+    use crate::types::{current_key_val, share, LdbIterator};
     use crate::{compressor, options};
 
     use super::*;
@@ -402,8 +739,14 @@ mod tests {
     // Build a table containing raw keys (no format). It returns (vector, length) for convenience
     // reason, a call f(v, v.len()) doesn't work for borrowing reasons.
     fn build_table(data: Vec<(&'static str, &'static str)>) -> (Vec<u8>, usize) {
+        build_table_opt(options::for_test(), data)
+    }
+
+    fn build_table_opt(
+        mut opt: Options,
+        data: Vec<(&'static str, &'static str)>,
+    ) -> (Vec<u8>, usize) {
         let mut d = Vec::with_capacity(512);
-        let mut opt = options::for_test();
         opt.block_restart_interval = 2;
         opt.block_size = 32;
         opt.compressor = compressor::SnappyCompressor::ID;
@@ -500,6 +843,45 @@ mod tests {
         assert_eq!(opt.block_cache.borrow().count(), 2);
     }
 
+    #[test]
+    fn test_table_secondary_cache() {
+        let (src, size) = build_table(build_data());
+        let mut opt = options::for_test();
+        opt.block_cache = share(Cache::new(1));
+        let secondary = share(SecondaryCache::new(opt.env.clone(), "cache", 1 << 16).unwrap());
+        opt.secondary_cache = Some(secondary.clone());
+
+        let table = Table::new_raw(opt.clone(), wrap_buffer(src), size).unwrap();
+        let handles: Vec<_> = table
+            .index_entries()
+            .unwrap()
+            .into_iter()
+            .map(|(_, h)| h)
+            .collect();
+        assert!(handles.len() > 2);
+        for h in &handles {
+            table.read_block(h).unwrap();
+        }
+        // All but the last block were evicted to the secondary cache.
+        assert_eq!(handles.len() - 1, secondary.borrow().count());
+        assert_eq!(0, secondary.borrow().hits());
+
+        let first = table.read_block(&handles[0]).unwrap();
+        assert_eq!(1, secondary.borrow().hits());
+        assert_eq!(
+            LdbIteratorIter::wrap(&mut first.iter()).collect::<Vec<_>>(),
+            LdbIteratorIter::wrap(&mut table.read_block(&handles[0]).unwrap().iter())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            build_data()
+                .into_iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+                .collect::<Vec<_>>(),
+            LdbIteratorIter::wrap(&mut table.iter()).collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn test_table_iterator_fwd_bwd() {
         let (src, size) = build_table(build_data());
@@ -671,6 +1053,31 @@ mod tests {
         assert!(iter.valid());
     }
 
+    #[test]
+    fn test_table_multi_get() {
+        let (src, size) = build_table(build_data());
+        let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
+
+        let mut keys: Vec<Vec<u8>> = LdbIteratorIter::wrap(&mut table.iter())
+            .map(|(k, _)| k)
+            .collect();
+        keys.extend([
+            b"aa".to_vec(),
+            b"abb".to_vec(),
+            b"zzy".to_vec(),
+            b"zz{".to_vec(),
+        ]);
+        keys.sort();
+        let lookup: Vec<&[u8]> = keys.iter().map(|k| k.as_slice()).collect();
+        let found = table
+            .multi_get_pinned(&lookup, &ReadOptions::default())
+            .unwrap();
+        assert_eq!(keys.len(), found.len());
+        for (k, entry) in keys.iter().zip(found) {
+            assert_eq!(table.get(k).unwrap(), entry.map(|(k, v)| (k, v.into_vec())));
+        }
+    }
+
     #[test]
     fn test_table_get() {
         let (src, size) = build_table(build_data());
@@ -698,6 +1105,83 @@ mod tests {
         assert!(table.get("zz{".as_bytes()).unwrap().is_none());
     }
 
+    #[test]
+    fn test_table_builder_tuning() {
+        let mut opt = options::for_test();
+        opt.shorten_index_keys = false;
+        opt.whole_key_filtering = false;
+        let (src, size) = build_table_opt(opt.clone(), build_data());
+        let table = Table::new_raw(opt, wrap_buffer(src), size).unwrap();
+        assert!(table.filters.is_none());
+
+        // Index keys are the last keys of their blocks.
+        let mut ix = table.indexblock.iter();
+        let index_keys: Vec<_> = LdbIteratorIter::wrap(&mut ix).map(|(k, _)| k).collect();
+        assert_eq!(
+            vec![b"bcd".to_vec(), b"xzz".to_vec(), b"zzz".to_vec()],
+            index_keys
+        );
+
+        let mut iter = table.iter();
+        for (k, v) in LdbIteratorIter::wrap(&mut iter) {
+            assert_eq!(Ok(Some((k.clone(), v))), table.get(&k));
+        }
+        assert_eq!(b"bsr".to_vec(), table.get(b"bce").unwrap().unwrap().0);
+        assert!(table.get(b"zzzz").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_table_prefix_filtering() {
+        let data = vec![
+            ("aa1", "v"),
+            ("aa2", "v"),
+            ("ab1", "v"),
+            ("ab2", "v"),
+            ("ab3", "v"),
+            ("cc1", "v"),
+            ("cc2", "v"),
+            ("x", "v"),
+        ];
+        for &whole in &[true, false] {
+            let mut opt = options::for_test();
+            opt.whole_key_filtering = whole;
+            opt.prefix_extractor = Some(Rc::new(Box::new(filter::FixedPrefix(2))));
+            let (src, size) = build_table_opt(opt.clone(), data.clone());
+            let table = Table::new_raw(opt.clone(), wrap_buffer(src), size).unwrap();
+            let props = table.properties().unwrap();
+            assert_eq!(whole, props.whole_key_filter);
+            assert_eq!("rusty-leveldb.FixedPrefix.2", props.prefix_extractor);
+
+            for p in &["aa", "ab", "cc"] {
+                assert!(table.prefix_may_match(p.as_bytes()), "{}", p);
+            }
+            for p in &["ac", "bb", "zz"] {
+                assert!(!table.prefix_may_match(p.as_bytes()), "{}", p);
+            }
+            // Not a prefix as returned by the extractor.
+            assert!(table.prefix_may_match(b"a"));
+            for (k, v) in &data {
+                assert_eq!(
+                    Some((k.as_bytes().to_vec(), v.as_bytes().to_vec())),
+                    table.get(k.as_bytes()).unwrap()
+                );
+            }
+            // The block containing "ac1" isn't read, as its filter doesn't contain the key (or
+            // its prefix).
+            let cached = opt.block_cache.borrow().count();
+            assert_eq!(None, table.get(b"ac1").unwrap());
+            assert_eq!(cached, opt.block_cache.borrow().count());
+
+            // Other extractors can't use the prefix filter.
+            let mut opt2 = opt.clone();
+            opt2.prefix_extractor = Some(Rc::new(Box::new(filter::FixedPrefix(1))));
+            let (src, size) = build_table_opt(opt.clone(), data.clone());
+            let table = Table::new_raw(opt2, wrap_buffer(src), size).unwrap();
+            assert!(table.prefix_may_match(b"b"));
+            assert!(table.get(b"aa2").unwrap().is_some());
+        }
+    }
+
     // This test verifies that the table and filters work with internal keys. This means:
     // The table contains keys in InternalKey format and it uses a filter wrapped by
     // InternalFilterPolicy.
@@ -739,6 +1223,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_table_properties() {
+        use crate::key_types::{LookupKey, ValueType};
+
+        // Tables without deletion markers have no properties block.
+        let (src, size) = build_internal_table();
+        let table = Table::new(options::for_test(), wrap_buffer(src), size).unwrap();
+        assert!(table.properties().is_none());
+
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(options::for_test(), &mut d);
+            b.add(LookupKey::new(b"abc", 3).internal_key(), b"")
+                .unwrap();
+            b.add(
+                LookupKey::new_full(b"abd", 2, ValueType::TypeDeletion).internal_key(),
+                b"",
+            )
+            .unwrap();
+            b.add(LookupKey::new(b"abd", 1).internal_key(), b"def")
+                .unwrap();
+            assert_eq!(1, b.properties().num_deletions);
+            b.finish().unwrap();
+        }
+        let size = d.len();
+        let table = Table::new(options::for_test(), wrap_buffer(d), size).unwrap();
+        let props = table.properties().unwrap();
+        assert_eq!(3, props.num_entries);
+        assert_eq!(1, props.num_deletions);
+        // The filter is still found in the meta index.
+        assert!(table.filters.is_some());
+
+        // Properties are not written for LevelDB compatibility.
+        let mut opt = options::for_test();
+        opt.leveldb_compatible = true;
+        let mut d = vec![];
+        {
+            let mut b = TableBuilder::new(opt.clone(), &mut d);
+            b.add(
+                LookupKey::new_full(b"abd", 2, ValueType::TypeDeletion).internal_key(),
+                b"",
+            )
+            .unwrap();
+            b.finish().unwrap();
+        }
+        let size = d.len();
+        let table = Table::new(opt, wrap_buffer(d), size).unwrap();
+        assert!(table.properties().is_none());
+    }
+
     #[test]
     fn test_table_reader_checksum() {
         let (mut src, size) = build_table(build_data());
@@ -128,6 +128,15 @@ pub fn test_iterator_properties<It: LdbIterator>(mut it: It) {
     assert_eq!(first, current_key_val(&it));
     assert!(!it.prev());
     assert!(!it.valid());
+
+    it.seek_to_last();
+    assert!(it.valid());
+    assert_eq!(fourth, current_key_val(&it));
+    assert!(it.prev());
+    assert_eq!(third, current_key_val(&it));
+    assert!(it.advance());
+    assert_eq!(fourth, current_key_val(&it));
+    assert!(!it.advance());
 }
 
 #[cfg(test)]
@@ -0,0 +1,157 @@
+//! Helpers for tests of code using a database (enabled by the `testutil` feature).
+//!
+//! ```
+//! use rusty_leveldb::testutil::TempDB;
+//!
+//! let mut db = TempDB::new().unwrap();
+//! db.put(b"key", b"value").unwrap();
+//! assert_eq!(Some(b"value".to_vec()), db.get(b"key").unwrap());
+//! // The database is deleted here.
+//! ```
+
+use crate::env::Env;
+use crate::error::Result;
+use crate::options::{self, Options};
+use crate::DB;
+
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the databases created by one process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// TempDB is a database in a directory of its own, which is deleted with all its files when the
+/// TempDB is dropped. It dereferences to the `DB`.
+pub struct TempDB {
+    db: Option<DB>,
+    path: PathBuf,
+    opt: Options,
+}
+
+impl TempDB {
+    /// new creates a database in memory, with default options.
+    pub fn new() -> Result<TempDB> {
+        TempDB::with_options(options::in_memory())
+    }
+
+    /// with_options creates a database in a new directory of `opt.env`, named `tempdb-` followed
+    /// by a number unique in this process.
+    pub fn with_options(opt: Options) -> Result<TempDB> {
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        TempDB::create(PathBuf::from(format!("tempdb-{}", n)), opt)
+    }
+
+    /// on_disk creates a database on disk, in a new directory below the system's temporary
+    /// directory, with default options.
+    #[cfg(feature = "fs")]
+    pub fn on_disk() -> Result<TempDB> {
+        TempDB::on_disk_with_options(Options::default())
+    }
+
+    /// on_disk_with_options is like `on_disk()`, but opens the database with opt, using a
+    /// `PosixDiskEnv` regardless of `opt.env`.
+    #[cfg(feature = "fs")]
+    pub fn on_disk_with_options(mut opt: Options) -> Result<TempDB> {
+        opt.env = Rc::new(Box::new(crate::PosixDiskEnv::new()));
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = format!(
+            "rusty-leveldb-{}-{}-{}",
+            std::process::id(),
+            opt.env.micros(),
+            n
+        );
+        TempDB::create(std::env::temp_dir().join(name), opt)
+    }
+
+    fn create(path: PathBuf, mut opt: Options) -> Result<TempDB> {
+        opt.create_if_missing = true;
+        opt.error_if_exists = true;
+        let db = DB::open(&path, opt.clone())?;
+        opt.error_if_exists = false;
+        Ok(TempDB {
+            db: Some(db),
+            path,
+            opt,
+        })
+    }
+
+    /// path returns the database's directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// env returns the environment the database is stored in.
+    pub fn env(&self) -> &Rc<Box<dyn Env>> {
+        &self.opt.env
+    }
+
+    /// reopen closes the database and opens it again, e.g. to test recovery.
+    pub fn reopen(&mut self) -> Result<&mut DB> {
+        if let Some(mut db) = self.db.take() {
+            db.close()?;
+        }
+        self.db = Some(DB::open(&self.path, self.opt.clone())?);
+        Ok(self.db.as_mut().unwrap())
+    }
+}
+
+impl Deref for TempDB {
+    type Target = DB;
+    fn deref(&self) -> &DB {
+        self.db.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for TempDB {
+    fn deref_mut(&mut self) -> &mut DB {
+        self.db.as_mut().unwrap()
+    }
+}
+
+impl Drop for TempDB {
+    fn drop(&mut self) {
+        drop(self.db.take());
+        let env = &self.opt.env;
+        if let Ok(children) = env.children(&self.path) {
+            for c in children {
+                let _ = env.delete(&self.path.join(c));
+            }
+        }
+        let _ = env.rmdir(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tempdb_in_memory() {
+        let mut db = TempDB::new().unwrap();
+        let env = db.env().clone();
+        let path = db.path().to_owned();
+        db.put(b"a", b"1").unwrap();
+        assert_eq!(Some(b"1".to_vec()), db.reopen().unwrap().get(b"a").unwrap());
+        assert!(env.exists(&path.join("CURRENT")).unwrap());
+
+        let other = TempDB::new().unwrap();
+        assert_ne!(path, other.path());
+        drop(db);
+        assert!(env.children(&path).unwrap_or_default().is_empty());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_tempdb_on_disk() {
+        let mut db = TempDB::on_disk().unwrap();
+        let path = db.path().to_owned();
+        assert!(path.starts_with(std::env::temp_dir()));
+        db.put(b"a", b"1").unwrap();
+        db.flush().unwrap();
+        assert_eq!(Some(b"1".to_vec()), db.reopen().unwrap().get(b"a").unwrap());
+        drop(db);
+        assert!(!path.exists());
+    }
+}
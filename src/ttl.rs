@@ -0,0 +1,241 @@
+//! A database whose values may expire, for cache-like workloads.
+
+use crate::compaction_filter::CompactionFilter;
+use crate::db_iter::DBIterator;
+use crate::env::Env;
+use crate::error::{err, Result, StatusCode};
+use crate::options::Options;
+use crate::types::LdbIterator;
+use crate::DB;
+
+use std::convert::TryInto;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The size of the expiry timestamp appended to each value.
+const EXPIRY_SIZE: usize = 8;
+
+/// TtlDB is a `DB` whose values can be written with a time to live. Expired values are not
+/// returned by reads anymore, and are removed by the compactions that rewrite them.
+///
+/// Each stored value is followed by its expiry time, as 8 bytes of little-endian microseconds
+/// since the UNIX epoch (`Env::micros()`), or 0 if it never expires. A database must always be
+/// opened as a `TtlDB` therefore. `TtlDB` installs its own `Options::compaction_filter`.
+pub struct TtlDB {
+    db: DB,
+    env: Rc<Box<dyn Env>>,
+}
+
+impl TtlDB {
+    /// Create a new or open an existing database.
+    pub fn open<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<TtlDB> {
+        let env = opt.env.clone();
+        opt.compaction_filter = Some(Rc::new(Box::new(TtlFilter { env: env.clone() })));
+        Ok(TtlDB {
+            db: DB::open(name, opt)?,
+            env,
+        })
+    }
+
+    /// put stores a value that never expires.
+    pub fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.db.put(key, &encode_value(val, 0))
+    }
+
+    /// put_with_ttl stores a value that expires once ttl has passed.
+    pub fn put_with_ttl(&mut self, key: &[u8], val: &[u8], ttl: Duration) -> Result<()> {
+        let expiry = self
+            .env
+            .micros()
+            .saturating_add(ttl.as_micros().try_into().unwrap_or(u64::MAX));
+        self.db.put(key, &encode_value(val, expiry.max(1)))
+    }
+
+    /// get returns the value stored for key, unless it has expired.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut val = match self.db.get(key)? {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let expiry = match decode_expiry(&val) {
+            Some(expiry) => expiry,
+            None => return err(StatusCode::Corruption, "value has no expiry timestamp"),
+        };
+        if is_expired(expiry, self.env.micros()) {
+            return Ok(None);
+        }
+        val.truncate(val.len() - EXPIRY_SIZE);
+        Ok(Some(val))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.db.delete(key)
+    }
+
+    /// new_iter returns an iterator over the values that haven't expired when it is created.
+    pub fn new_iter(&mut self) -> Result<TtlIterator> {
+        Ok(TtlIterator {
+            iter: self.db.new_iter()?,
+            now: self.env.micros(),
+        })
+    }
+
+    /// db returns the underlying database, e.g. to flush or compact it. Values read from or
+    /// written to it directly carry the expiry timestamp.
+    pub fn db(&mut self) -> &mut DB {
+        &mut self.db
+    }
+}
+
+fn encode_value(val: &[u8], expiry: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(val.len() + EXPIRY_SIZE);
+    buf.extend_from_slice(val);
+    buf.extend_from_slice(&expiry.to_le_bytes());
+    buf
+}
+
+fn decode_expiry(val: &[u8]) -> Option<u64> {
+    let pos = val.len().checked_sub(EXPIRY_SIZE)?;
+    Some(u64::from_le_bytes(val[pos..].try_into().unwrap()))
+}
+
+fn is_expired(expiry: u64, now: u64) -> bool {
+    expiry != 0 && expiry <= now
+}
+
+/// TtlFilter makes compactions remove expired values.
+struct TtlFilter {
+    env: Rc<Box<dyn Env>>,
+}
+
+impl CompactionFilter for TtlFilter {
+    fn filter(&self, _: &[u8], value: &[u8]) -> bool {
+        decode_expiry(value).is_some_and(|expiry| is_expired(expiry, self.env.micros()))
+    }
+}
+
+/// TtlIterator iterates over the values of a `TtlDB` without their expiry timestamps, skipping
+/// the ones that had expired when it was created.
+pub struct TtlIterator {
+    iter: DBIterator,
+    now: u64,
+}
+
+impl TtlIterator {
+    fn current_expired(&self) -> bool {
+        let (mut key, mut val) = (vec![], vec![]);
+        self.iter.current(&mut key, &mut val)
+            && decode_expiry(&val).is_some_and(|expiry| is_expired(expiry, self.now))
+    }
+
+    fn skip_forward(&mut self) -> bool {
+        while self.current_expired() {
+            if !self.iter.advance() {
+                return false;
+            }
+        }
+        self.iter.valid()
+    }
+
+    fn skip_backward(&mut self) -> bool {
+        while self.current_expired() {
+            if !self.iter.prev() {
+                return false;
+            }
+        }
+        self.iter.valid()
+    }
+}
+
+impl LdbIterator for TtlIterator {
+    fn advance(&mut self) -> bool {
+        self.iter.advance() && self.skip_forward()
+    }
+    fn current(&self, key: &mut Vec<u8>, val: &mut Vec<u8>) -> bool {
+        if !self.iter.current(key, val) {
+            return false;
+        }
+        if val.len() >= EXPIRY_SIZE {
+            val.truncate(val.len() - EXPIRY_SIZE);
+        }
+        true
+    }
+    fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(key);
+        self.skip_forward();
+    }
+    fn reset(&mut self) {
+        self.iter.reset();
+    }
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+    fn prev(&mut self) -> bool {
+        self.iter.prev() && self.skip_backward()
+    }
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+        self.skip_backward();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use crate::types::current_key_val;
+
+    #[test]
+    fn test_ttl_db() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        let mut db = TtlDB::open("ttl", opt.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        db.put_with_ttl(b"b", b"2", Duration::from_millis(1))
+            .unwrap();
+        db.put_with_ttl(b"c", b"3", Duration::from_secs(3600))
+            .unwrap();
+        db.put_with_ttl(b"d", b"4", Duration::from_millis(1))
+            .unwrap();
+        env.sleep_for(5000);
+
+        assert_eq!(Some(b"1".to_vec()), db.get(b"a").unwrap());
+        assert_eq!(None, db.get(b"b").unwrap());
+        assert_eq!(Some(b"3".to_vec()), db.get(b"c").unwrap());
+        assert_eq!(None, db.get(b"x").unwrap());
+        db.db().put(b"x", b"short").unwrap();
+        assert_eq!(StatusCode::Corruption, db.get(b"x").unwrap_err().code);
+        db.db().delete(b"x").unwrap();
+
+        let mut it = db.new_iter().unwrap();
+        let mut keys = vec![];
+        while let Some((k, v)) = it.next() {
+            keys.push((k, v));
+        }
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"c".to_vec(), b"3".to_vec())
+            ],
+            keys
+        );
+        it.seek(b"b");
+        assert_eq!(Some((b"c".to_vec(), b"3".to_vec())), current_key_val(&it));
+        assert!(it.prev());
+        assert_eq!(Some((b"a".to_vec(), b"1".to_vec())), current_key_val(&it));
+        assert!(!it.prev());
+        drop(it);
+
+        // Compactions remove the expired values from the tables.
+        db.db().flush_memtable().unwrap();
+        db.db().compact_all().unwrap();
+        assert_eq!(None, db.db().get(b"b").unwrap());
+        assert_eq!(None, db.db().get(b"d").unwrap());
+        assert!(db.db().get(b"c").unwrap().is_some());
+        drop(db);
+
+        let mut db = TtlDB::open("ttl", opt).unwrap();
+        assert_eq!(Some(b"3".to_vec()), db.get(b"c").unwrap());
+    }
+}
@@ -80,6 +80,20 @@ pub trait LdbIterator {
         self.reset();
         self.advance();
     }
+
+    /// seek_to_last seeks to the last element. This implementation scans over all elements;
+    /// iterators that can find the last element directly override it.
+    fn seek_to_last(&mut self) {
+        self.reset();
+        let mut last = None;
+        while let Some((k, _)) = self.next() {
+            last = Some(k);
+        }
+        match last {
+            Some(k) => self.seek(&k),
+            None => self.reset(),
+        }
+    }
 }
 
 /// current_key_val is a helper allocating two vectors and filling them with the current key/value
@@ -112,6 +126,9 @@ impl LdbIterator for Box<dyn LdbIterator> {
     fn prev(&mut self) -> bool {
         self.as_mut().prev()
     }
+    fn seek_to_last(&mut self) {
+        self.as_mut().seek_to_last()
+    }
 }
 
 /// The unique (sequential) number of a file.
@@ -127,6 +144,9 @@ pub struct FileMetaData {
     // these are in InternalKey format:
     pub smallest: Vec<u8>,
     pub largest: Vec<u8>,
+    // From the table's properties; not stored in the manifest. 0 if not known (yet).
+    pub num_entries: u64,
+    pub num_deletions: u64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -138,6 +158,12 @@ pub enum FileType {
     Current,
     Temp,
     InfoLog,
+    /// The list of cached blocks written if `Options::persist_hot_blocks` is set.
+    HotBlocks,
+    /// The list of named snapshots (see `DB::create_named_snapshot()`).
+    NamedSnapshots,
+    /// The application metadata (see `DB::put_meta()`).
+    Metadata,
 }
 
 pub fn parse_file_name<P: AsRef<Path>>(ff: P) -> Result<(FileNum, FileType)> {
@@ -148,6 +174,12 @@ pub fn parse_file_name<P: AsRef<Path>>(ff: P) -> Result<(FileNum, FileType)> {
         return Ok((0, FileType::DBLock));
     } else if f == "LOG" || f == "LOG.old" {
         return Ok((0, FileType::InfoLog));
+    } else if f == "HOTBLOCKS" {
+        return Ok((0, FileType::HotBlocks));
+    } else if f == "SNAPSHOTS" {
+        return Ok((0, FileType::NamedSnapshots));
+    } else if f == "METADATA" {
+        return Ok((0, FileType::Metadata));
     } else if f.starts_with("MANIFEST-") {
         if let Some(ix) = f.find('-') {
             if let Ok(num) = FileNum::from_str_radix(&f[ix + 1..], 10) {
@@ -1,11 +1,17 @@
 use crate::cmp::{Cmp, InternalKeyCmp};
 use crate::error::Result;
 use crate::key_types::{parse_internal_key, InternalKey, LookupKey, UserKey, ValueType};
+use crate::options::ReadOptions;
+use crate::pinned_slice::PinnedSlice;
+use crate::range_del::RangeTombstone;
 use crate::table_cache::TableCache;
-use crate::table_reader::TableIterator;
-use crate::types::{FileMetaData, FileNum, LdbIterator, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS};
+use crate::table_reader::{Table, TableIterator};
+use crate::types::{
+    FileMetaData, FileNum, LdbIterator, SequenceNumber, Shared, MAX_SEQUENCE_NUMBER, NUM_LEVELS,
+};
 
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::rc::Rc;
 
@@ -24,9 +30,17 @@ pub struct Version {
     table_cache: Shared<TableCache>,
     user_cmp: Rc<Box<dyn Cmp>>,
     pub files: [Vec<FileMetaHandle>; NUM_LEVELS],
+    // The range tombstones that were written to tables, by sequence number.
+    pub range_tombstones: Vec<RangeTombstone>,
 
     pub file_to_compact: Option<FileMetaHandle>,
     pub file_to_compact_lvl: usize,
+    // A file dominated by deletion markers (see Options::deletion_compaction_ratio).
+    pub deletion_file_to_compact: Option<FileMetaHandle>,
+    pub deletion_file_to_compact_lvl: usize,
+    // A file whose compaction reclaims space (see Options::max_space_amplification).
+    pub space_amp_file_to_compact: Option<FileMetaHandle>,
+    pub space_amp_file_to_compact_lvl: usize,
     pub compaction_score: Option<f64>,
     pub compaction_level: Option<usize>,
 }
@@ -37,8 +51,13 @@ impl Version {
             table_cache: cache,
             user_cmp: ucmp,
             files: Default::default(),
+            range_tombstones: vec![],
             file_to_compact: None,
             file_to_compact_lvl: 0,
+            deletion_file_to_compact: None,
+            deletion_file_to_compact_lvl: 0,
+            space_amp_file_to_compact: None,
+            space_amp_file_to_compact_lvl: 0,
             compaction_score: None,
             compaction_level: None,
         }
@@ -55,9 +74,13 @@ impl Version {
     }
 
     /// get returns the value for the specified key using the persistent tables contained in this
-    /// Version.
+    /// Version, and the sequence number of the entry. Range tombstones are not considered.
     #[allow(unused_assignments)]
-    pub fn get<'a>(&self, key: InternalKey<'a>) -> Result<Option<(Vec<u8>, GetStats)>> {
+    pub fn get<'a>(
+        &self,
+        key: InternalKey<'a>,
+        ropt: &ReadOptions,
+    ) -> Result<Option<(PinnedSlice, SequenceNumber, GetStats)>> {
         let levels = self.get_overlapping(key);
         let ikey = key;
         let ukey = parse_internal_key(ikey).2;
@@ -82,15 +105,18 @@ impl Version {
                 // We receive both key and value from the table. Because we're using InternalKey
                 // keys, we now need to check whether the found entry's user key is equal to the
                 // one we're looking for (get() just returns the next-bigger key).
-                if let Ok(Some((k, v))) = self.table_cache.borrow_mut().get(f.borrow().num, ikey) {
+                let found = self
+                    .table_cache
+                    .borrow_mut()
+                    .get(f.borrow().num, ikey, ropt)?;
+                if let Some((k, v)) = found {
                     // We don't need to check the sequence number; get() will not return an entry
                     // with a higher sequence number than the one in the supplied key.
-                    let (typ, _, foundkey) = parse_internal_key(&k);
-                    if typ == ValueType::TypeValue
-                        && self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal
-                    {
-                        return Ok(Some((v, stats)));
-                    } else if typ == ValueType::TypeDeletion {
+                    let (typ, seq, foundkey) = parse_internal_key(&k);
+                    if self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal {
+                        if typ == ValueType::TypeValue {
+                            return Ok(Some((v, seq, stats)));
+                        }
                         // Skip looking once we have found a deletion.
                         return Ok(None);
                     }
@@ -100,6 +126,73 @@ impl Version {
         Ok(None)
     }
 
+    /// multi_get is like calling `get()` for each of keys, which must be sorted: Each level is
+    /// consulted once for all keys, and each table once for all keys in its range (see
+    /// `Table::multi_get_pinned()`). Unlike `get()`, it doesn't collect statistics for seek
+    /// compactions.
+    pub fn multi_get<'a>(
+        &self,
+        keys: &[InternalKey<'a>],
+        ropt: &ReadOptions,
+    ) -> Result<Vec<Option<(PinnedSlice, SequenceNumber)>>> {
+        let overlapping: Vec<_> = keys.iter().map(|k| self.get_overlapping(k)).collect();
+        let mut results: Vec<Option<(PinnedSlice, SequenceNumber)>> =
+            keys.iter().map(|_| None).collect();
+        // Whether an entry, possibly a deletion, was found for a key.
+        let mut found = vec![false; keys.len()];
+
+        for level in 0..NUM_LEVELS {
+            // The keys to look up in each file, with the files in the order in which get() reads
+            // them: In level 0, newest first.
+            let mut files: BTreeMap<Reverse<FileNum>, Vec<usize>> = BTreeMap::new();
+            for (i, levels) in overlapping.iter().enumerate() {
+                for f in &levels[level] {
+                    files.entry(Reverse(f.borrow().num)).or_default().push(i);
+                }
+            }
+
+            for (Reverse(num), ixs) in files {
+                let ixs: Vec<usize> = ixs.into_iter().filter(|&i| !found[i]).collect();
+                if ixs.is_empty() {
+                    continue;
+                }
+                let lookup: Vec<InternalKey> = ixs.iter().map(|&i| keys[i]).collect();
+                let entries = self
+                    .table_cache
+                    .borrow_mut()
+                    .multi_get(num, &lookup, ropt)?;
+                for (i, entry) in ixs.into_iter().zip(entries) {
+                    if let Some((k, v)) = entry {
+                        let (typ, seq, foundkey) = parse_internal_key(&k);
+                        let ukey = parse_internal_key(keys[i]).2;
+                        if self.user_cmp.cmp(foundkey, ukey) == Ordering::Equal {
+                            found[i] = true;
+                            if typ == ValueType::TypeValue {
+                                results[i] = Some((v, seq));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// key_may_exist returns true if any table may contain an entry for key, judging by the
+    /// key ranges of the files and the tables' filters. Tables are opened if necessary, but no
+    /// data blocks are read.
+    pub fn key_may_exist<'a>(&self, key: InternalKey<'a>) -> Result<bool> {
+        for files in self.get_overlapping(key).iter() {
+            for f in files {
+                let table = self.table_cache.borrow_mut().get_table(f.borrow().num)?;
+                if table.may_contain(key) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
     /// get_overlapping returns the files overlapping key in each level.
     fn get_overlapping<'a>(&self, key: InternalKey<'a>) -> [Vec<FileMetaHandle>; NUM_LEVELS] {
         let mut levels: [Vec<FileMetaHandle>; NUM_LEVELS] = Default::default();
@@ -252,6 +345,17 @@ impl Version {
         max
     }
 
+    /// space_amplification estimates the ratio of the size of all table files to the size of the
+    /// live data. The live data is taken to be the last non-empty level, as the levels above
+    /// mostly hold newer versions of its entries. It is 1 for an empty database.
+    pub fn space_amplification(&self) -> f64 {
+        let total = total_size(self.files.iter().flatten());
+        match self.files.iter().rev().find(|files| !files.is_empty()) {
+            Some(last) => total as f64 / total_size(last.iter()).max(1) as f64,
+            None => 1.0,
+        }
+    }
+
     /// overlap_in_level returns true if the specified level's files overlap the range [smallest;
     /// largest].
     pub fn overlap_in_level<'a>(
@@ -359,19 +463,72 @@ impl Version {
     /// new_iters returns a set of iterators that can be merged to yield all entries in this
     /// version.
     pub fn new_iters(&self) -> Result<Vec<Box<dyn LdbIterator>>> {
+        self.new_iters_with(&ReadOptions::default())
+    }
+
+    /// new_iters_with is like `new_iters()`, but the iterators read blocks as ropt says, and the
+    /// table files without keys within the iteration bounds of ropt are left out.
+    pub fn new_iters_with(&self, ropt: &ReadOptions) -> Result<Vec<Box<dyn LdbIterator>>> {
+        let (lower, upper) = (
+            ropt.iterate_lower_bound.as_deref(),
+            ropt.iterate_upper_bound.as_deref(),
+        );
+        self.new_filtered_iters(ropt, |f| {
+            let (usmallest, ulargest) = (
+                parse_internal_key(&f.smallest).2,
+                parse_internal_key(&f.largest).2,
+            );
+            let before = lower.is_some_and(|l| self.user_cmp.cmp(ulargest, l) == Ordering::Less);
+            let after = upper.is_some_and(|u| self.user_cmp.cmp(usmallest, u) != Ordering::Less);
+            Ok(!before && !after)
+        })
+    }
+
+    /// new_prefix_iters is like `new_iters()`, but leaves out the table files that contain no key
+    /// starting with prefix according to their key range or prefix filter (see
+    /// `Table::prefix_may_match()`).
+    pub fn new_prefix_iters(&self, prefix: &[u8]) -> Result<Vec<Box<dyn LdbIterator>>> {
+        self.new_filtered_iters(&ReadOptions::default(), |f| {
+            let ulargest = parse_internal_key(&f.largest).2;
+            if self.user_cmp.cmp(ulargest, prefix) == Ordering::Less {
+                return Ok(false);
+            }
+            let table = self.table_cache.borrow_mut().get_table(f.num)?;
+            Ok(table.prefix_may_match(prefix))
+        })
+    }
+
+    /// new_filtered_iters returns iterators reading blocks as ropt says over the table files for
+    /// which keep returns true: one per file on level 0, and one per level otherwise.
+    fn new_filtered_iters<F: FnMut(&FileMetaData) -> Result<bool>>(
+        &self,
+        ropt: &ReadOptions,
+        mut keep: F,
+    ) -> Result<Vec<Box<dyn LdbIterator>>> {
         let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
         for f in &self.files[0] {
-            iters.push(Box::new(
-                self.table_cache
-                    .borrow_mut()
-                    .get_table(f.borrow().num)?
-                    .iter(),
-            ));
+            if keep(&f.borrow())? {
+                iters.push(Box::new(
+                    self.table_cache
+                        .borrow_mut()
+                        .get_table(f.borrow().num)?
+                        .iter_with(ropt),
+                ));
+            }
         }
 
         for l in 1..NUM_LEVELS {
-            if !self.files[l].is_empty() {
-                iters.push(Box::new(self.new_concat_iter(l)));
+            let mut files = vec![];
+            for f in &self.files[l] {
+                if keep(&f.borrow())? {
+                    files.push(f.clone());
+                }
+            }
+            if !files.is_empty() {
+                iters.push(Box::new(
+                    new_version_iter(files, self.table_cache.clone(), self.user_cmp.clone())
+                        .with_read_options(ropt),
+                ));
             }
         }
 
@@ -392,6 +549,8 @@ pub fn new_version_iter(
         cmp: InternalKeyCmp(ucmp),
         current: None,
         current_ix: 0,
+        readahead: 0,
+        ropt: ReadOptions::default(),
     }
 }
 
@@ -408,6 +567,36 @@ pub struct VersionIter {
 
     current: Option<TableIterator>,
     current_ix: usize,
+    // If non-zero, tables are opened uncached with this readahead size.
+    readahead: usize,
+    ropt: ReadOptions,
+}
+
+impl VersionIter {
+    /// with_readahead makes the iterator read table files in chunks of the given size, bypassing
+    /// the table cache. This is meant for compactions.
+    pub fn with_readahead(mut self, readahead: usize) -> VersionIter {
+        self.readahead = readahead;
+        self
+    }
+
+    /// with_read_options makes the iterator read blocks as ropt says (see
+    /// `Table::read_block_with()`).
+    pub fn with_read_options(mut self, ropt: &ReadOptions) -> VersionIter {
+        self.ropt = ropt.clone();
+        self
+    }
+
+    fn open_table(&self, ix: usize) -> Result<Table> {
+        let num = self.files[ix].borrow().num;
+        if self.readahead > 0 {
+            self.cache
+                .borrow()
+                .open_table_with_readahead(num, self.readahead)
+        } else {
+            self.cache.borrow_mut().get_table(num)
+        }
+    }
 }
 
 impl LdbIterator for VersionIter {
@@ -427,12 +616,8 @@ impl LdbIterator for VersionIter {
         }
 
         // Initialize iterator or load next table.
-        if let Ok(tbl) = self
-            .cache
-            .borrow_mut()
-            .get_table(self.files[self.current_ix].borrow().num)
-        {
-            self.current = Some(tbl.iter());
+        if let Ok(tbl) = self.open_table(self.current_ix) {
+            self.current = Some(tbl.iter_with(&self.ropt));
         } else {
             return false;
         }
@@ -447,12 +632,8 @@ impl LdbIterator for VersionIter {
     }
     fn seek(&mut self, key: &[u8]) {
         if let Some(ix) = find_file(&self.cmp, &self.files, key) {
-            if let Ok(tbl) = self
-                .cache
-                .borrow_mut()
-                .get_table(self.files[ix].borrow().num)
-            {
-                let mut iter = tbl.iter();
+            if let Ok(tbl) = self.open_table(ix) {
+                let mut iter = tbl.iter_with(&self.ropt);
                 iter.seek(key);
                 if iter.valid() {
                     self.current_ix = ix;
@@ -463,6 +644,20 @@ impl LdbIterator for VersionIter {
         }
         self.reset();
     }
+    fn seek_to_last(&mut self) {
+        if let Some(ix) = self.files.len().checked_sub(1) {
+            if let Ok(tbl) = self.open_table(ix) {
+                let mut iter = tbl.iter_with(&self.ropt);
+                iter.seek_to_last();
+                if iter.valid() {
+                    self.current_ix = ix;
+                    self.current = Some(iter);
+                    return;
+                }
+            }
+        }
+        self.reset();
+    }
     fn reset(&mut self) {
         self.current = None;
         self.current_ix = 0;
@@ -478,7 +673,7 @@ impl LdbIterator for VersionIter {
                 let f = &self.files[self.current_ix - 1];
                 // Find previous table, seek to last entry.
                 if let Ok(tbl) = self.cache.borrow_mut().get_table(f.borrow().num) {
-                    let mut iter = tbl.iter();
+                    let mut iter = tbl.iter_with(&self.ropt);
                     iter.seek(&f.borrow().largest);
                     // The saved largest key must be in the table.
                     assert!(iter.valid());
@@ -594,6 +789,7 @@ pub mod testutil {
             num,
             smallest: LookupKey::new(smallest, smallestix).internal_key().to_vec(),
             largest: LookupKey::new(largest, largestix).internal_key().to_vec(),
+            ..Default::default()
         })
     }
 
@@ -773,9 +969,9 @@ mod tests {
     #[test]
     fn test_version_summary() {
         let v = make_version().0;
-        let expected = "level 0: 2 files, 483 bytes ([(1, 232), (2, 251)]); level 1: 3 files, 651 \
-                        bytes ([(3, 218), (4, 216), (5, 217)]); level 2: 2 files, 468 bytes ([(6, \
-                        218), (7, 250)]); level 3: 2 files, 400 bytes ([(8, 200), (9, 200)]); ";
+        let expected = "level 0: 2 files, 553 bytes ([(1, 232), (2, 321)]); level 1: 3 files, 651 \
+                        bytes ([(3, 218), (4, 216), (5, 217)]); level 2: 2 files, 538 bytes ([(6, \
+                        218), (7, 320)]); level 3: 2 files, 400 bytes ([(8, 200), (9, 200)]); ";
         assert_eq!(expected, &v.level_summary());
     }
 
@@ -802,8 +998,11 @@ mod tests {
         ];
 
         for ref c in cases {
-            match v.get(LookupKey::new(c.0, c.1).internal_key()) {
-                Ok(Some((val, _))) => assert_eq!(c.2.as_ref().unwrap().as_ref().unwrap(), &val),
+            match v.get(
+                LookupKey::new(c.0, c.1).internal_key(),
+                &ReadOptions::default(),
+            ) {
+                Ok(Some((val, _, _))) => assert_eq!(c.2.as_ref().unwrap().as_ref().unwrap(), &*val),
                 Ok(None) => assert!(c.2.as_ref().unwrap().as_ref().is_none()),
                 Err(_) => assert!(c.2.is_err()),
             }
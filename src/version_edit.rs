@@ -1,5 +1,6 @@
 use crate::error::{err, Result, StatusCode};
 use crate::key_types::InternalKey;
+use crate::range_del::RangeTombstone;
 use crate::types::{FileMetaData, FileNum, SequenceNumber};
 
 use integer_encoding::{VarIntReader, VarIntWriter};
@@ -23,6 +24,7 @@ enum EditTag {
     DeletedFile = 6,
     NewFile = 7,
     PrevLogNumber = 9, // sic!
+    RangeTombstone = 20,
 }
 
 fn tag_to_enum(t: u32) -> Option<EditTag> {
@@ -35,6 +37,7 @@ fn tag_to_enum(t: u32) -> Option<EditTag> {
         6 => Some(EditTag::DeletedFile),
         7 => Some(EditTag::NewFile),
         9 => Some(EditTag::PrevLogNumber),
+        20 => Some(EditTag::RangeTombstone),
         _ => None,
     }
 }
@@ -68,6 +71,8 @@ pub struct VersionEdit {
     pub compaction_ptrs: Vec<CompactionPointer>,
     pub deleted: HashSet<(usize, FileNum)>,
     pub new_files: Vec<(usize, FileMetaData)>,
+    // Added range tombstones, replacing those with the same sequence number.
+    pub range_tombstones: Vec<RangeTombstone>,
 }
 
 impl VersionEdit {
@@ -81,6 +86,7 @@ impl VersionEdit {
             compaction_ptrs: Vec::with_capacity(8),
             deleted: HashSet::with_capacity(8),
             new_files: Vec::with_capacity(8),
+            range_tombstones: vec![],
         }
     }
 
@@ -96,10 +102,18 @@ impl VersionEdit {
         self.deleted.insert((level, file_num));
     }
 
+    pub fn add_range_tombstone(&mut self, t: RangeTombstone) {
+        self.range_tombstones.push(t)
+    }
+
     pub fn set_comparator_name(&mut self, name: &str) {
         self.comparator = Some(name.to_string())
     }
 
+    pub fn comparator_name(&self) -> Option<&str> {
+        self.comparator.as_deref()
+    }
+
     pub fn set_log_num(&mut self, num: u64) {
         self.log_number = Some(num)
     }
@@ -180,6 +194,16 @@ impl VersionEdit {
             buf.write_all(nf.1.largest.as_ref()).unwrap();
         }
 
+        for t in self.range_tombstones.iter() {
+            buf.write_varint(EditTag::RangeTombstone as u32).unwrap();
+            buf.write_varint(t.from.len()).unwrap();
+            buf.write_all(&t.from).unwrap();
+            buf.write_varint(t.to.len()).unwrap();
+            buf.write_all(&t.to).unwrap();
+            buf.write_varint(t.seq).unwrap();
+            buf.write_varint(t.first_clean_file).unwrap();
+        }
+
         buf
     }
 
@@ -269,6 +293,7 @@ impl VersionEdit {
                                             smallest,
                                             largest,
                                             allowed_seeks: 0,
+                                            ..Default::default()
                                         },
                                     ))
                                 } else {
@@ -281,6 +306,22 @@ impl VersionEdit {
                             return err(StatusCode::IOError, "Couldn't read file level");
                         }
                     }
+
+                    EditTag::RangeTombstone => {
+                        let from = read_length_prefixed(&mut reader)?;
+                        let to = read_length_prefixed(&mut reader)?;
+                        match (reader.read_varint(), reader.read_varint()) {
+                            (Ok(seq), Ok(first_clean_file)) => {
+                                ve.range_tombstones.push(RangeTombstone {
+                                    from,
+                                    to,
+                                    seq,
+                                    first_clean_file,
+                                })
+                            }
+                            _ => return err(StatusCode::IOError, "Couldn't read range tombstone"),
+                        }
+                    }
                 }
             } else {
                 return err(
@@ -299,6 +340,8 @@ mod tests {
     use super::CompactionPointer;
     use super::VersionEdit;
 
+    use crate::range_del::RangeTombstone;
+
     use crate::cmp::{Cmp, DefaultCmp};
     use crate::types::FileMetaData;
 
@@ -320,9 +363,14 @@ mod tests {
                 size: 234,
                 smallest: vec![5, 6, 7],
                 largest: vec![8, 9, 0],
+                ..Default::default()
             },
         );
         ve.delete_file(1, 132);
+        ve.add_range_tombstone(RangeTombstone {
+            first_clean_file: 900,
+            ..RangeTombstone::new(b"abc", b"abd", 77)
+        });
 
         let encoded = ve.encode();
 
@@ -364,10 +412,12 @@ mod tests {
                     size: 234,
                     smallest: vec![5, 6, 7],
                     largest: vec![8, 9, 0],
+                    ..Default::default()
                 }
             )
         );
         assert_eq!(decoded.deleted.len(), 1);
         assert!(decoded.deleted.contains(&(1, 132)));
+        assert_eq!(ve.range_tombstones, decoded.range_tombstones);
     }
 }
@@ -1,11 +1,14 @@
 use crate::cmp::{Cmp, InternalKeyCmp};
-use crate::env::Env;
-use crate::error::{err, Result, Status, StatusCode};
+use crate::compaction_scheduler::{CompactionJob, CompactionReason};
+use crate::env::{open_syncable_file, Env, RandomAccessReader, WritableFile};
+use crate::error::{err, Result, StatusCode};
 use crate::key_types::{parse_internal_key, InternalKey, UserKey};
 use crate::log::{LogReader, LogWriter};
 use crate::merging_iter::MergingIter;
-use crate::options::Options;
+use crate::options::{max_compaction_bytes, target_file_size, CompactionPri, Options};
+use crate::range_del::RangeTombstone;
 use crate::table_cache::TableCache;
+use crate::table_properties::deletion_ratio;
 use crate::types::{
     parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, NUM_LEVELS,
 };
@@ -20,13 +23,15 @@ use std::rc::Rc;
 
 pub struct Compaction {
     level: usize,
-    max_file_size: usize,
+    opt: Options,
     input_version: Option<Shared<Version>>,
     level_ixs: [usize; NUM_LEVELS],
     cmp: Rc<Box<dyn Cmp>>,
     icmp: InternalKeyCmp,
 
     manual: bool,
+    // Whether this compaction rewrites a file dominated by deletion markers.
+    deletion_triggered: bool,
 
     // "parent" inputs from level and level+1.
     inputs: [Vec<FileMetaHandle>; 2],
@@ -43,12 +48,13 @@ impl Compaction {
     pub fn new(opt: &Options, level: usize, input: Option<Shared<Version>>) -> Compaction {
         Compaction {
             level,
-            max_file_size: opt.max_file_size,
+            opt: opt.clone(),
             input_version: input,
             level_ixs: Default::default(),
             cmp: opt.cmp.clone(),
             icmp: InternalKeyCmp(opt.cmp.clone()),
             manual: false,
+            deletion_triggered: false,
 
             inputs: Default::default(),
             grandparent_ix: 0,
@@ -121,7 +127,7 @@ impl Compaction {
     }
 
     pub fn is_trivial_move(&self) -> bool {
-        if self.manual {
+        if self.manual || self.deletion_triggered {
             return false;
         }
 
@@ -131,7 +137,21 @@ impl Compaction {
         } else {
             inputs_size = 0;
         }
-        self.num_inputs(0) == 1 && self.num_inputs(1) == 0 && inputs_size < 10 * self.max_file_size
+        self.num_inputs(0) == 1
+            && self.num_inputs(1) == 0
+            && inputs_size < self.max_grandparent_overlap_bytes()
+    }
+
+    /// max_output_file_size returns the size at which output files of this compaction are split.
+    pub fn max_output_file_size(&self) -> usize {
+        target_file_size(&self.opt, self.level + 1)
+    }
+
+    /// max_grandparent_overlap_bytes returns how many bytes of the grandparent level a single
+    /// output file may overlap with.
+    fn max_grandparent_overlap_bytes(&self) -> usize {
+        self.max_output_file_size()
+            .saturating_mul(self.opt.max_grandparent_overlap_factor)
     }
 
     pub fn should_stop_before<'a>(&mut self, k: InternalKey<'a>) -> bool {
@@ -153,7 +173,7 @@ impl Compaction {
         }
         self.seen_key = true;
 
-        if self.overlapped_bytes > 10 * self.max_file_size {
+        if self.overlapped_bytes > self.max_grandparent_overlap_bytes() {
             self.overlapped_bytes = 0;
             true
         } else {
@@ -179,7 +199,9 @@ pub struct VersionSet {
     current: Option<Shared<Version>>,
     compaction_ptrs: [Vec<u8>; NUM_LEVELS],
 
-    descriptor_log: Option<LogWriter<Box<dyn Write>>>,
+    descriptor_log: Option<LogWriter<Box<dyn WritableFile>>>,
+    // The manifest that recover() read, and the offset up to which it was read.
+    recovered_manifest: Option<(PathBuf, usize)>,
 }
 
 impl VersionSet {
@@ -202,6 +224,7 @@ impl VersionSet {
             current: Some(v),
             compaction_ptrs: Default::default(),
             descriptor_log: None,
+            recovered_manifest: None,
         }
     }
 
@@ -255,10 +278,19 @@ impl VersionSet {
         assert!(self.current.is_some());
         let v = self.current.as_ref().unwrap();
         let v = v.borrow();
-        v.compaction_score.unwrap_or(0.0) >= 1.0 || v.file_to_compact.is_some()
+        v.compaction_score.unwrap_or(0.0) >= 1.0
+            || v.file_to_compact.is_some()
+            || v.deletion_file_to_compact.is_some()
+            || v.space_amp_file_to_compact.is_some()
     }
 
-    fn approximate_offset<'a>(&self, v: &Shared<Version>, key: InternalKey<'a>) -> usize {
+    /// approximate_offset returns the approximate position of key in the table files of v, as
+    /// if they were concatenated in level and key order.
+    pub(crate) fn approximate_offset<'a>(
+        &self,
+        v: &Shared<Version>,
+        key: InternalKey<'a>,
+    ) -> usize {
         let mut offset = 0;
         for level in 0..NUM_LEVELS {
             for f in &v.borrow().files[level] {
@@ -278,45 +310,119 @@ impl VersionSet {
     }
 
     pub fn pick_compaction(&mut self) -> Option<Compaction> {
+        let jobs = self.pending_compactions();
+        jobs.first().and_then(|job| self.compaction_for(job))
+    }
+
+    /// pending_compactions returns the pending level compactions, the most urgent first.
+    pub fn pending_compactions(&self) -> Vec<CompactionJob> {
         assert!(self.current.is_some());
         let current = self.current();
         let current = current.borrow();
+        let job = |reason, level, score, f: &FileMetaHandle| {
+            let f = f.borrow();
+            CompactionJob {
+                reason,
+                level,
+                score,
+                smallest: parse_internal_key(&f.smallest).2.to_vec(),
+                largest: parse_internal_key(&f.largest).2.to_vec(),
+                file_size: f.size,
+            }
+        };
+
+        // The level picked by finalize() comes first; other oversized levels follow.
+        let mut jobs = vec![];
+        let best = current.compaction_level;
+        if let (Some(l), Some(score)) = (best, current.compaction_score) {
+            if score >= 1.0 {
+                if let Some(f) = self.size_compaction_file(&current, l) {
+                    jobs.push(job(CompactionReason::Size, l, score, &f));
+                }
+            }
+        }
+        let mut others = vec![];
+        for l in (0..NUM_LEVELS - 1).filter(|l| Some(*l) != best) {
+            let score = level_score(&current, l);
+            if score >= 1.0 {
+                if let Some(f) = self.size_compaction_file(&current, l) {
+                    others.push(job(CompactionReason::Size, l, score, &f));
+                }
+            }
+        }
+        // Stable, so that lower levels come first among equal scores.
+        others.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        jobs.extend(others);
+        if let Some(ref f) = current.file_to_compact {
+            jobs.push(job(
+                CompactionReason::Seek,
+                current.file_to_compact_lvl,
+                0.0,
+                f,
+            ));
+        }
+        if let Some(ref f) = current.deletion_file_to_compact {
+            jobs.push(job(
+                CompactionReason::Deletion,
+                current.deletion_file_to_compact_lvl,
+                0.0,
+                f,
+            ));
+        }
+        if let Some(ref f) = current.space_amp_file_to_compact {
+            jobs.push(job(
+                CompactionReason::SpaceAmplification,
+                current.space_amp_file_to_compact_lvl,
+                0.0,
+                f,
+            ));
+        }
+        jobs
+    }
 
-        let mut c = Compaction::new(&self.opt, 0, self.current.clone());
-        let level;
+    /// size_compaction_file returns the file that a size compaction of level starts with.
+    fn size_compaction_file(&self, current: &Version, level: usize) -> Option<FileMetaHandle> {
+        let f = match self.opt.compaction_pri {
+            CompactionPri::RoundRobin => current.files[level]
+                .iter()
+                .find(|f| {
+                    self.compaction_ptrs[level].is_empty()
+                        || self
+                            .cmp
+                            .cmp(&f.borrow().largest, &self.compaction_ptrs[level])
+                            == Ordering::Greater
+                })
+                .cloned(),
+            CompactionPri::MinOverlappingRatio => min_overlapping_file(current, level),
+        };
+        // Otherwise start with the first file in level. This will also reset the compaction
+        // pointers.
+        f.or_else(|| current.files[level].first().cloned())
+    }
 
-        // Size compaction?
-        if current.compaction_score.unwrap_or(0.0) >= 1.0 {
-            level = current.compaction_level.unwrap();
-            assert!(level < NUM_LEVELS - 1);
+    /// compaction_for sets up the compaction described by job, which must have been returned by
+    /// pending_compactions() for the current version.
+    pub fn compaction_for(&mut self, job: &CompactionJob) -> Option<Compaction> {
+        assert!(self.current.is_some());
+        let current = self.current();
+        let current = current.borrow();
 
-            for f in &current.files[level] {
-                if self.compaction_ptrs[level].is_empty()
-                    || self
-                        .cmp
-                        .cmp(&f.borrow().largest, &self.compaction_ptrs[level])
-                        == Ordering::Greater
-                {
-                    c.add_input(0, f.clone());
-                    break;
-                }
+        let level = job.level;
+        assert!(level < NUM_LEVELS - 1);
+        let mut c = Compaction::new(&self.opt, level, self.current.clone());
+        match job.reason {
+            CompactionReason::Size => c.add_input(0, self.size_compaction_file(&current, level)?),
+            CompactionReason::Seek => c.add_input(0, current.file_to_compact.clone()?),
+            CompactionReason::Deletion => {
+                // Moving the file wouldn't drop any deletion markers.
+                c.add_input(0, current.deletion_file_to_compact.clone()?);
+                c.deletion_triggered = true;
             }
-
-            if c.num_inputs(0) == 0 {
-                // Add first file in level. This will also reset the compaction pointers.
-                c.add_input(0, current.files[level][0].clone());
+            CompactionReason::SpaceAmplification => {
+                c.add_input(0, current.space_amp_file_to_compact.clone()?)
             }
-        } else if let Some(ref ftc) = current.file_to_compact {
-            // Seek compaction?
-            level = current.file_to_compact_lvl;
-            c.add_input(0, ftc.clone());
-        } else {
-            return None;
         }
 
-        c.level = level;
-        c.input_version = self.current.clone();
-
         if level == 0 {
             let (smallest, largest) = get_range(&self.cmp, c.inputs[0].iter());
             // This call intentionally overwrites the file previously put into c.inputs[0].
@@ -349,7 +455,7 @@ impl VersionSet {
             let mut total = 0;
             for i in 0..inputs.len() {
                 total += inputs[i].borrow().size;
-                if total > self.opt.max_file_size {
+                if total > target_file_size(&self.opt, level) {
                     inputs.truncate(i + 1);
                     break;
                 }
@@ -389,7 +495,7 @@ impl VersionSet {
             let expanded0_size = total_size(expanded0.iter());
             // ...if we picked up more files in the current level, and the total size is acceptable
             if expanded0.len() > compaction.num_inputs(0)
-                && (inputs1_size + expanded0_size) < 25 * self.opt.max_file_size
+                && (inputs1_size + expanded0_size) < max_compaction_bytes(&self.opt, level)
             {
                 let (new_start, new_limit) = get_range(&self.cmp, expanded0.iter());
                 let expanded1 = current.overlapping_inputs(level + 1, &new_start, &new_limit);
@@ -469,6 +575,9 @@ impl VersionSet {
                 edit.add_file(level, f.borrow().clone());
             }
         }
+        for t in &current.range_tombstones {
+            edit.add_range_tombstone(t.clone());
+        }
         self.descriptor_log
             .as_mut()
             .unwrap()
@@ -494,27 +603,41 @@ impl VersionSet {
 
         let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
         {
-            let mut builder = Builder::new();
+            let mut builder = Builder::new(&self.opt);
             builder.apply(&edit, &mut self.compaction_ptrs);
             builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
         }
         self.finalize(&mut v);
 
-        if self.descriptor_log.is_none() {
+        let new_manifest = self.descriptor_log.is_none();
+        let mut r = Ok(());
+        if new_manifest {
             let descname = manifest_file_name(&self.dbname, self.manifest_num);
             edit.set_next_file(self.next_file_num);
-            self.descriptor_log = Some(LogWriter::new(
-                self.opt.env.open_writable_file(Path::new(&descname))?,
-            ));
-            self.write_snapshot()?;
+            self.descriptor_log = Some(LogWriter::new(open_syncable_file(
+                &self.opt.env,
+                Path::new(&descname),
+                false,
+            )?));
+            r = self.write_snapshot().map(|_| ());
         }
 
         let encoded = edit.encode();
-        if let Some(ref mut lw) = self.descriptor_log {
-            lw.add_record(&encoded)?;
-            lw.flush()?;
+        if let (Ok(()), Some(lw)) = (&r, self.descriptor_log.as_mut()) {
+            // The manifest refers to the new version's files.
+            r = lw.add_record(&encoded).and_then(|_| lw.sync());
+        }
+        // CURRENT only changes when it has to refer to a new manifest, once that is durable.
+        if r.is_ok() && new_manifest {
+            r = set_current_file(&self.opt.env, &self.dbname, self.manifest_num);
+        }
+        if let Err(e) = r {
+            if new_manifest {
+                // CURRENT still refers to the previous manifest; the next call starts over.
+                self.descriptor_log = None;
+            }
+            return Err(e);
         }
-        set_current_file(&self.opt.env, &self.dbname, self.manifest_num)?;
 
         self.add_version(v);
         // log_number was set above.
@@ -529,16 +652,7 @@ impl VersionSet {
         let mut best_score = None;
 
         for l in 0..NUM_LEVELS - 1 {
-            let score: f64;
-            if l == 0 {
-                score = v.files[l].len() as f64 / 4.0;
-            } else {
-                let mut max_bytes = 10.0 * f64::from(1 << 20);
-                for _ in 0..l - 1 {
-                    max_bytes *= 10.0;
-                }
-                score = total_size(v.files[l].iter()) as f64 / max_bytes;
-            }
+            let score = level_score(v, l);
             if let Some(ref mut b) = best_score {
                 if *b < score {
                     *b = score;
@@ -551,6 +665,49 @@ impl VersionSet {
         }
         v.compaction_score = best_score;
         v.compaction_level = best_lvl;
+
+        if self.opt.deletion_compaction_ratio > 0.0 {
+            self.pick_deletion_compaction_file(v);
+        }
+        if self.opt.max_space_amplification > 0.0
+            && v.space_amplification() > self.opt.max_space_amplification
+        {
+            pick_space_amp_compaction_file(v);
+        }
+    }
+
+    /// pick_deletion_compaction_file finds the file outside of the last level with the highest
+    /// share of deletion markers, if it reaches Options::deletion_compaction_ratio.
+    fn pick_deletion_compaction_file(&self, v: &mut Version) {
+        let mut best_ratio = self.opt.deletion_compaction_ratio;
+        for l in 0..NUM_LEVELS - 1 {
+            for f in &v.files[l] {
+                self.load_table_properties(f);
+                let ratio = deletion_ratio(f.borrow().num_entries, f.borrow().num_deletions);
+                if ratio >= best_ratio
+                    && (v.deletion_file_to_compact.is_none() || ratio > best_ratio)
+                {
+                    best_ratio = ratio;
+                    v.deletion_file_to_compact = Some(f.clone());
+                    v.deletion_file_to_compact_lvl = l;
+                }
+            }
+        }
+    }
+
+    /// load_table_properties fills in the entry counts of f from its table, if they are unknown.
+    pub fn load_table_properties(&self, f: &FileMetaHandle) {
+        if f.borrow().num_entries > 0 {
+            return;
+        }
+        let num = f.borrow().num;
+        if let Ok(t) = self.cache.borrow_mut().get_table(num) {
+            if let Some(props) = t.properties() {
+                let mut f = f.borrow_mut();
+                f.num_entries = props.num_entries;
+                f.num_deletions = props.num_deletions;
+            }
+        }
     }
 
     /// recover recovers the state of a LevelDB instance from the files on disk. If recover()
@@ -564,29 +721,14 @@ impl VersionSet {
         let current = Path::new(&current);
 
         let descfilename = self.dbname.join(current);
-        let mut builder = Builder::new();
+        let mut builder = Builder::new(&self.opt);
         {
-            let mut descfile = self
-                .opt
-                .env
-                .open_sequential_file(Path::new(&descfilename))?;
-            let mut logreader = LogReader::new(
-                &mut descfile,
-                // checksum=
-                true,
-            );
-
+            let (edits, end) = self.read_manifest(&descfilename, 0)?;
             let mut log_number = None;
             let mut prev_log_number = None;
             let mut next_file_number = None;
             let mut last_seq = None;
-
-            let mut buf = Vec::new();
-            while let Ok(size) = logreader.read(&mut buf) {
-                if size == 0 {
-                    break;
-                }
-                let edit = VersionEdit::decode_from(&buf)?;
+            for edit in edits {
                 builder.apply(&edit, &mut self.compaction_ptrs);
                 if let Some(ln) = edit.log_number {
                     log_number = Some(ln);
@@ -601,6 +743,7 @@ impl VersionSet {
                     prev_log_number = Some(pln);
                 }
             }
+            self.recovered_manifest = Some((descfilename.clone(), end));
 
             if let Some(ln) = log_number {
                 self.log_num = ln;
@@ -655,6 +798,82 @@ impl VersionSet {
         Ok(!self.reuse_manifest(&descfilename, &current))
     }
 
+    /// read_manifest reads the edits in a manifest from offset off on, up to its last complete
+    /// record. It returns them and the offset after them.
+    fn read_manifest(&self, path: &Path, off: usize) -> Result<(Vec<VersionEdit>, usize)> {
+        let file = self.opt.env.open_random_access_file(path)?;
+        let mut logreader = LogReader::new_with_off(
+            RandomAccessReader::new(file, off),
+            // checksum=
+            true,
+            off,
+        );
+        let mut edits = vec![];
+        let mut buf = Vec::new();
+        while let Ok(size) = logreader.read(&mut buf) {
+            if size == 0 {
+                break;
+            }
+            let edit = VersionEdit::decode_from(&buf)?;
+            if let Some(name) = edit.comparator_name() {
+                if name != self.opt.cmp.id() {
+                    return err(
+                        StatusCode::InvalidArgument,
+                        &format!(
+                            "comparator {} does not match the database's comparator {}",
+                            self.opt.cmp.id(),
+                            name
+                        ),
+                    );
+                }
+            }
+            edits.push(edit);
+        }
+        Ok((edits, logreader.record_end()))
+    }
+
+    /// catch_up applies the edits that another instance appended to the manifest since
+    /// recover() or the previous catch_up() read it, e.g. for a secondary instance. It returns
+    /// false, without changing anything, if CURRENT names another manifest by now, which then
+    /// has to be recovered anew.
+    pub fn catch_up(&mut self) -> Result<bool> {
+        let Some((path, off)) = self.recovered_manifest.clone() else {
+            return Ok(false);
+        };
+        let mut current = read_current_file(&self.opt.env, &self.dbname)?;
+        current.truncate(current.len() - 1);
+        if self.dbname.join(current) != path {
+            return Ok(false);
+        }
+        let (edits, end) = self.read_manifest(&path, off)?;
+        if edits.is_empty() {
+            return Ok(true);
+        }
+
+        let mut builder = Builder::new(&self.opt);
+        for edit in edits {
+            builder.apply(&edit, &mut self.compaction_ptrs);
+            if let Some(ln) = edit.log_number {
+                self.log_num = ln;
+            }
+            if let Some(pln) = edit.prev_log_number {
+                self.prev_log_num = pln;
+            }
+            if let Some(nfn) = edit.next_file_number {
+                self.next_file_num = self.next_file_num.max(nfn + 1);
+            }
+            if let Some(ls) = edit.last_seq {
+                self.last_seq = self.last_seq.max(ls);
+            }
+        }
+        let mut v = Version::new(self.cache.clone(), self.opt.cmp.clone());
+        builder.save_to(&self.cmp, self.current.as_ref().unwrap(), &mut v);
+        self.finalize(&mut v);
+        self.add_version(v);
+        self.recovered_manifest = Some((path, end));
+        Ok(true)
+    }
+
     /// reuse_manifest checks whether the current manifest can be reused.
     fn reuse_manifest(
         &mut self,
@@ -676,15 +895,12 @@ impl VersionSet {
                 return false;
             }
             if let Ok(size) = self.opt.env.size_of(Path::new(current_manifest_path)) {
-                if size >= self.opt.max_file_size {
+                if size >= target_file_size(&self.opt, 0) {
                     return false;
                 }
 
                 assert!(self.descriptor_log.is_none());
-                let s = self
-                    .opt
-                    .env
-                    .open_appendable_file(Path::new(current_manifest_path));
+                let s = open_syncable_file(&self.opt.env, Path::new(current_manifest_path), true);
                 if let Ok(f) = s {
                     log!(self.opt.log, "reusing manifest {:?}", current_manifest_path);
                     self.descriptor_log = Some(LogWriter::new_with_off(f, size));
@@ -710,7 +926,14 @@ impl VersionSet {
                 // Add individual iterators for L0 tables.
                 for fi in 0..c.num_inputs(i) {
                     let f = &c.inputs[i][fi];
-                    let s = self.cache.borrow_mut().get_table(f.borrow().num);
+                    let num = f.borrow().num;
+                    let s = if self.opt.compaction_readahead_size > 0 {
+                        self.cache
+                            .borrow()
+                            .open_table_with_readahead(num, self.opt.compaction_readahead_size)
+                    } else {
+                        self.cache.borrow_mut().get_table(num)
+                    };
                     if let Ok(tbl) = s {
                         iters.push(Box::new(tbl.iter()));
                     } else {
@@ -724,11 +947,14 @@ impl VersionSet {
                 }
             } else {
                 // Create concatenating iterator higher levels.
-                iters.push(Box::new(new_version_iter(
-                    c.inputs[i].clone(),
-                    self.cache.clone(),
-                    self.opt.cmp.clone(),
-                )));
+                iters.push(Box::new(
+                    new_version_iter(
+                        c.inputs[i].clone(),
+                        self.cache.clone(),
+                        self.opt.cmp.clone(),
+                    )
+                    .with_readahead(self.opt.compaction_readahead_size),
+                ));
             }
         }
         assert!(iters.len() <= cap);
@@ -741,13 +967,22 @@ struct Builder {
     // (added, deleted) files per level.
     deleted: [Vec<FileNum>; NUM_LEVELS],
     added: [Vec<FileMetaHandle>; NUM_LEVELS],
+    range_tombstones: Vec<RangeTombstone>,
+    // Number of bytes per allowed seek of a new file; 0 disables seek compactions.
+    bytes_per_seek: usize,
 }
 
 impl Builder {
-    fn new() -> Builder {
+    fn new(opt: &Options) -> Builder {
         Builder {
             deleted: Default::default(),
             added: Default::default(),
+            range_tombstones: vec![],
+            bytes_per_seek: if opt.seek_compaction {
+                opt.bytes_per_seek.max(1)
+            } else {
+                0
+            },
         }
     }
 
@@ -762,10 +997,12 @@ impl Builder {
         }
         for &(level, ref f) in edit.new_files.iter() {
             let mut f = f.clone();
-            f.allowed_seeks = f.size / 16384;
-            if f.allowed_seeks < 100 {
-                f.allowed_seeks = 100;
-            }
+            // A zero bytes_per_seek means that seek compactions are disabled.
+            f.allowed_seeks = f
+                .size
+                .checked_div(self.bytes_per_seek)
+                .map(|n| n.max(100))
+                .unwrap_or(usize::MAX);
             // Remove this file from the list of deleted files.
             self.deleted[level] = self.deleted[level]
                 .iter()
@@ -773,6 +1010,10 @@ impl Builder {
                 .collect();
             self.added[level].push(share(f));
         }
+        for t in edit.range_tombstones.iter() {
+            self.range_tombstones.retain(|a| a.seq != t.seq);
+            self.range_tombstones.push(t.clone());
+        }
     }
 
     /// maybe_add_file adds a file f at level to version v, if it's not already marked as deleted
@@ -837,6 +1078,32 @@ impl Builder {
                 assert!(cmp.cmp(prev_end, this_begin) < Ordering::Equal);
             }
         }
+
+        // A tombstone is dropped once no file that may contain entries covered by it overlaps
+        // its range anymore.
+        let ucmp = cmp.0.as_ref().as_ref();
+        let added = &self.range_tombstones;
+        let mut tombstones: Vec<_> = base
+            .borrow()
+            .range_tombstones
+            .iter()
+            .filter(|t| !added.iter().any(|a| a.seq == t.seq))
+            .chain(added.iter())
+            .cloned()
+            .collect();
+        tombstones.retain(|t| {
+            v.files.iter().flatten().any(|f| {
+                let f = f.borrow();
+                f.num < t.first_clean_file
+                    && t.overlaps(
+                        ucmp,
+                        parse_internal_key(&f.smallest).2,
+                        parse_internal_key(&f.largest).2,
+                    )
+            })
+        });
+        tombstones.sort_by_key(|t| t.seq);
+        v.range_tombstones = tombstones;
     }
 }
 
@@ -848,11 +1115,11 @@ pub fn manifest_file_name<P: AsRef<Path>>(dbname: P, file_num: FileNum) -> PathB
     dbname.as_ref().join(manifest_name(file_num))
 }
 
-fn temp_file_name<P: AsRef<Path>>(dbname: P, file_num: FileNum) -> PathBuf {
+pub fn temp_file_name<P: AsRef<Path>>(dbname: P, file_num: FileNum) -> PathBuf {
     dbname.as_ref().join(format!("{:06}.dbtmp", file_num))
 }
 
-fn current_file_name<P: AsRef<Path>>(dbname: P) -> PathBuf {
+pub fn current_file_name<P: AsRef<Path>>(dbname: P) -> PathBuf {
     dbname.as_ref().join("CURRENT").to_owned()
 }
 
@@ -877,18 +1144,39 @@ pub fn set_current_file<P: AsRef<Path>>(
     let dbname = dbname.as_ref();
     let manifest_base = manifest_name(manifest_file_num);
     let tempfile = temp_file_name(dbname, manifest_file_num);
-    {
-        let mut f = env.open_writable_file(Path::new(&tempfile))?;
-        f.write_all(manifest_base.display().to_string().as_bytes())?;
-        f.write_all(b"\n")?;
-    }
     let currentfile = current_file_name(dbname);
-    if let Err(e) = env.rename(Path::new(&tempfile), Path::new(&currentfile)) {
+    // The new contents must be on disk before the rename, so that CURRENT never refers to an
+    // empty or partially written file after a crash.
+    let r = (|| {
+        {
+            let mut f = env.open_writable_file(Path::new(&tempfile))?;
+            f.write_all(manifest_base.display().to_string().as_bytes())?;
+            f.write_all(b"\n")?;
+            f.flush()?;
+        }
+        env.sync_file(Path::new(&tempfile))?;
+        env.rename(Path::new(&tempfile), Path::new(&currentfile))
+    })();
+    if let Err(e) = r {
         // ignore error.
         let _ = env.delete(Path::new(&tempfile));
-        return Err(Status::from(e));
+        return Err(e);
+    }
+    env.sync_dir(dbname)
+}
+
+/// level_score returns the ratio of a level's size to its limit. For level 0, the number of
+/// files is counted instead of their size.
+fn level_score(v: &Version, l: usize) -> f64 {
+    if l == 0 {
+        v.files[l].len() as f64 / 4.0
+    } else {
+        let mut max_bytes = 10.0 * f64::from(1 << 20);
+        for _ in 0..l - 1 {
+            max_bytes *= 10.0;
+        }
+        total_size(v.files[l].iter()) as f64 / max_bytes
     }
-    Ok(())
 }
 
 /// sort_files_by_smallest sorts the list of files by the smallest keys of the files.
@@ -939,6 +1227,51 @@ fn merge_iters<
     out
 }
 
+/// min_overlapping_file returns the file in level whose overlap with level+1 is smallest relative
+/// to its own size. Ties are broken by key order.
+fn min_overlapping_file(v: &Version, level: usize) -> Option<FileMetaHandle> {
+    let mut best: Option<(u64, FileMetaHandle)> = None;
+    for f in &v.files[level] {
+        let (size, overlap) = {
+            let fb = f.borrow();
+            let overlapping = v.overlapping_inputs(level + 1, &fb.smallest, &fb.largest);
+            (fb.size, total_size(overlapping.iter()))
+        };
+        // Scaled to avoid floating point comparisons.
+        let ratio = overlap as u64 * 1024 / size.max(1) as u64;
+        if best.as_ref().map(|(r, _)| ratio < *r).unwrap_or(true) {
+            best = Some((ratio, f.clone()));
+        }
+    }
+    best.map(|(_, f)| f)
+}
+
+/// pick_space_amp_compaction_file picks the file of the level just above the data in the last
+/// non-empty level that overlaps most with the next level, as compacting it likely drops the most
+/// obsolete entries. Nothing is picked once all files are in one level.
+fn pick_space_amp_compaction_file(v: &mut Version) {
+    let mut levels = (0..NUM_LEVELS).rev().filter(|l| !v.files[*l].is_empty());
+    let level = match (levels.next(), levels.next()) {
+        (Some(_), Some(level)) => level,
+        _ => return,
+    };
+    let mut best: Option<(usize, FileMetaHandle)> = None;
+    for f in &v.files[level] {
+        let overlap = {
+            let fb = f.borrow();
+            total_size(
+                v.overlapping_inputs(level + 1, &fb.smallest, &fb.largest)
+                    .iter(),
+            )
+        };
+        if best.as_ref().map(|(o, _)| overlap > *o).unwrap_or(true) {
+            best = Some((overlap, f.clone()));
+        }
+    }
+    v.space_amp_file_to_compact = best.map(|(_, f)| f);
+    v.space_amp_file_to_compact_lvl = level;
+}
+
 /// get_range returns the indices of the files within files that have the smallest lower bound
 /// respectively the largest upper bound.
 fn get_range<'a, C: Cmp, I: Iterator<Item = &'a FileMetaHandle>>(
@@ -1045,7 +1378,7 @@ mod tests {
         ve.delete_file(0, 2);
         ve.set_compact_pointer(2, LookupKey::new("xxx".as_bytes(), 123).internal_key());
 
-        let mut b = Builder::new();
+        let mut b = Builder::new(&opt);
         let mut ptrs: [Vec<u8>; NUM_LEVELS] = Default::default();
         b.apply(&ve, &mut ptrs);
 
@@ -1055,6 +1388,14 @@ mod tests {
         );
         assert_eq!(2, b.deleted[0][0]);
         assert_eq!(1, b.added[1].len());
+        assert_eq!(100, b.added[1][0].borrow().allowed_seeks);
+
+        // With seek compactions disabled, files never run out of allowed seeks.
+        let mut opt2 = opt.clone();
+        opt2.seek_compaction = false;
+        let mut b2 = Builder::new(&opt2);
+        b2.apply(&ve, &mut ptrs);
+        assert_eq!(usize::MAX, b2.added[1][0].borrow().allowed_seeks);
 
         let mut v2 = Version::new(
             share(TableCache::new("db", opt.clone(), 100)),
@@ -1142,6 +1483,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_version_set_catch_up() {
+        let (_, opt) = make_version();
+        let new_vset = || {
+            VersionSet::new(
+                "db",
+                opt.clone(),
+                share(TableCache::new("db", opt.clone(), 100)),
+            )
+        };
+        let add_file = |vs: &mut VersionSet, num: FileNum, from: &[u8], to: &[u8]| {
+            let mut ve = VersionEdit::new();
+            let mut fmd = FileMetaData::default();
+            fmd.num = num;
+            fmd.size = 123;
+            fmd.smallest = LookupKey::new(from, 100).internal_key().to_vec();
+            fmd.largest = LookupKey::new(to, 100).internal_key().to_vec();
+            ve.add_file(1, fmd);
+            vs.log_and_apply(ve).unwrap();
+        };
+        {
+            let mut ve = VersionEdit::new();
+            ve.set_comparator_name("leveldb.BytewiseComparator");
+            ve.set_log_num(10);
+            ve.set_next_file(20);
+            ve.set_last_seq(30);
+            let manifest = manifest_file_name("db", 19);
+            let mffile = opt.env.open_writable_file(Path::new(&manifest)).unwrap();
+            let mut lw = LogWriter::new(mffile);
+            lw.add_record(&ve.encode()).unwrap();
+            lw.flush().unwrap();
+            set_current_file(&opt.env.as_ref(), "db", 19).unwrap();
+        }
+
+        let mut primary = new_vset();
+        primary.recover().unwrap();
+        add_file(&mut primary, 21, b"abc", b"def");
+
+        let mut secondary = new_vset();
+        secondary.recover().unwrap();
+        assert_eq!(1, secondary.current().borrow().files[1].len());
+        // Nothing new yet.
+        assert!(secondary.catch_up().unwrap());
+        assert_eq!(1, secondary.current().borrow().files[1].len());
+
+        primary.last_seq = 40;
+        add_file(&mut primary, 22, b"ghi", b"jkl");
+        assert!(secondary.catch_up().unwrap());
+        assert_eq!(2, secondary.current().borrow().files[1].len());
+        assert_eq!(40, secondary.last_seq);
+
+        // Once CURRENT names another manifest, it has to be recovered anew.
+        let mut reopened = new_vset();
+        reopened.recover().unwrap();
+        reopened.manifest_num = reopened.new_file_number();
+        reopened.descriptor_log = None;
+        add_file(&mut reopened, 23, b"mno", b"pqr");
+        assert!(!secondary.catch_up().unwrap());
+        assert_eq!(2, secondary.current().borrow().files[1].len());
+    }
+
     #[test]
     fn test_version_set_utils() {
         let (v, opt) = make_version();
@@ -1154,9 +1556,9 @@ mod tests {
         let v = vs.current();
         let v = v.borrow();
         // num_level_bytes()
-        assert_eq!(483, v.num_level_bytes(0));
+        assert_eq!(553, v.num_level_bytes(0));
         assert_eq!(651, v.num_level_bytes(1));
-        assert_eq!(468, v.num_level_bytes(2));
+        assert_eq!(538, v.num_level_bytes(2));
         // num_level_files()
         assert_eq!(2, v.num_level_files(0));
         assert_eq!(3, v.num_level_files(1));
@@ -1201,6 +1603,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_version_set_pick_compaction_min_overlapping_ratio() {
+        let (mut v, mut opt) = make_version();
+        opt.compaction_pri = CompactionPri::MinOverlappingRatio;
+        let mut vs = VersionSet::new("db", opt.clone(), share(TableCache::new("db", opt, 100)));
+
+        // Rearrange levels: L1 holds [cab..fba] and [gaa..gda], L2 holds [daa..dba] and
+        // [eaa..fab], so that only the first L1 file overlaps with L2.
+        let l1 = v.files[1].clone();
+        v.files[1] = v.files[2].clone();
+        v.files[2] = vec![l1[1].clone(), l1[2].clone()];
+        v.compaction_score = Some(2.0);
+        v.compaction_level = Some(1);
+        vs.add_version(v);
+
+        let c = vs.pick_compaction().unwrap();
+        assert_eq!(1, c.level);
+        assert_eq!(1, c.inputs[0].len());
+        assert_eq!(7, c.inputs[0][0].borrow().num);
+        assert!(c.inputs[1].is_empty());
+
+        // Round-robin picks the first file of the level.
+        vs.opt.compaction_pri = CompactionPri::RoundRobin;
+        let c = vs.pick_compaction().unwrap();
+        assert_eq!(6, c.inputs[0][0].borrow().num);
+        assert_eq!(2, c.inputs[1].len());
+    }
+
     /// iterator_properties tests that it contains len elements and that they are ordered in
     /// ascending order by cmp.
     fn iterator_properties<It: LdbIterator>(mut it: It, len: usize, cmp: Rc<Box<dyn Cmp>>) {
@@ -1234,7 +1664,7 @@ mod tests {
                 vs.approximate_offset(&v, LookupKey::new("bab".as_bytes(), 9000).internal_key())
             );
             assert_eq!(
-                1134,
+                1204,
                 vs.approximate_offset(&v, LookupKey::new("fab".as_bytes(), 9000).internal_key())
             );
         }
@@ -1282,6 +1712,15 @@ mod tests {
                 Rc::new(Box::new(vs.cmp.clone())),
             );
 
+            // No expansion beyond max_compaction_bytes.
+            vs.opt.max_compaction_bytes = 1;
+            let c = vs
+                .compact_range(1, from.internal_key(), to.internal_key())
+                .unwrap();
+            assert_eq!(2, c.inputs[0].len());
+            assert_eq!(1, c.inputs[1].len());
+            vs.opt.max_compaction_bytes = 0;
+
             // is_trivial_move
             let from = LookupKey::new("fab".as_bytes(), 1000);
             let to = LookupKey::new("fba".as_bytes(), 1010);
@@ -1303,6 +1742,14 @@ mod tests {
             assert!(!c.should_stop_before(mid.internal_key()));
             assert!(!c.should_stop_before(to.internal_key()));
 
+            // Stop as soon as any grandparent data is overlapped.
+            let mut c = vs
+                .compact_range(0, from.internal_key(), to.internal_key())
+                .unwrap();
+            c.opt.max_grandparent_overlap_factor = 0;
+            assert!(!c.should_stop_before(from.internal_key()));
+            assert!(c.should_stop_before(to.internal_key()));
+
             // is_base_level_for
             let from = LookupKey::new("000".as_bytes(), 1000);
             let to = LookupKey::new("zzz".as_bytes(), 1010);
@@ -0,0 +1,134 @@
+//! JavaScript bindings for WebAssembly (enabled by the `wasm` feature; build without the default
+//! `fs` feature). Databases are kept in memory; all databases opened on a thread share one
+//! `MemEnv`, so that a database can be closed and opened again by name.
+//!
+//! ```js
+//! const db = await LevelDB.open("mydb");
+//! await db.put(new TextEncoder().encode("key"), new TextEncoder().encode("value"));
+//! const value = await db.get(new TextEncoder().encode("key")); // Uint8Array or undefined
+//! for (const [k, v] of await db.iterate()) { ... }
+//! db.close();
+//! ```
+//!
+//! The methods return Promises, which are rejected with an `Error` if the operation fails. The
+//! operations themselves run synchronously.
+
+use crate::env::Env;
+use crate::error::{Result, Status, StatusCode};
+use crate::mem_env::MemEnv;
+use crate::options::Options;
+use crate::{LdbIterator, DB};
+
+use std::rc::Rc;
+
+use js_sys::{Array, Error, Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static ENV: Rc<Box<dyn Env>> = Rc::new(Box::new(MemEnv::new()));
+}
+
+fn to_promise(r: Result<JsValue>) -> Promise {
+    match r {
+        Ok(v) => Promise::resolve(&v),
+        Err(e) => Promise::reject(&js_error(e)),
+    }
+}
+
+fn js_error(e: Status) -> JsValue {
+    Error::new(&e.err).into()
+}
+
+/// A database, exported to JavaScript as `LevelDB`.
+#[wasm_bindgen(js_name = LevelDB)]
+pub struct WasmDB {
+    db: Option<DB>,
+}
+
+#[wasm_bindgen(js_class = LevelDB)]
+impl WasmDB {
+    /// open opens the database called name, creating it if it doesn't exist yet.
+    pub fn open(name: &str) -> Promise {
+        let opt = Options {
+            env: ENV.with(|env| env.clone()),
+            create_if_missing: true,
+            ..Options::default()
+        };
+        to_promise(DB::open(name, opt).map(|db| WasmDB { db: Some(db) }.into()))
+    }
+
+    /// get resolves to the value of key as a `Uint8Array`, or to `undefined` if it isn't found.
+    pub fn get(&mut self, key: &[u8]) -> Promise {
+        to_promise(
+            self.db()
+                .and_then(|db| db.get(key))
+                .map(|v| v.map_or(JsValue::UNDEFINED, |v| bytes(&v))),
+        )
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Promise {
+        to_promise(
+            self.db()
+                .and_then(|db| db.put(key, value))
+                .map(|_| JsValue::UNDEFINED),
+        )
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> Promise {
+        to_promise(
+            self.db()
+                .and_then(|db| db.delete(key))
+                .map(|_| JsValue::UNDEFINED),
+        )
+    }
+
+    /// iterate resolves to an array of `[key, value]` pairs, in key order: those with keys from
+    /// `from` (inclusive) to `to` (exclusive), if given, and at most `limit` of them.
+    pub fn iterate(
+        &mut self,
+        from: Option<Vec<u8>>,
+        to: Option<Vec<u8>>,
+        limit: Option<u32>,
+    ) -> Promise {
+        to_promise(self.db().and_then(|db| {
+            let mut iter = db.new_iter()?;
+            let entries = Array::new();
+            let (mut k, mut v) = (vec![], vec![]);
+            match from {
+                Some(from) => iter.seek(&from),
+                None => iter.seek_to_first(),
+            }
+            while iter.current(&mut k, &mut v) {
+                if to.as_ref().is_some_and(|to| k.as_slice() >= &to[..])
+                    || limit.is_some_and(|l| entries.length() >= l)
+                {
+                    break;
+                }
+                entries.push(&Array::of2(&bytes(&k), &bytes(&v)));
+                iter.advance();
+            }
+            Ok(entries.into())
+        }))
+    }
+
+    /// close closes the database. Its contents are kept until the page or process exits.
+    pub fn close(&mut self) {
+        self.db = None;
+    }
+}
+
+impl WasmDB {
+    fn db(&mut self) -> Result<&mut DB> {
+        match self.db.as_mut() {
+            Some(db) => Ok(db),
+            None => Err(Status::new(
+                StatusCode::InvalidArgument,
+                "database is closed",
+            )),
+        }
+    }
+}
+
+fn bytes(b: &[u8]) -> JsValue {
+    Uint8Array::from(b).into()
+}
@@ -14,6 +14,9 @@ const HEADER_SIZE: usize = 12;
 /// The storage format is (with the respective length in bytes)
 ///
 /// [tag: 1, keylen: ~var, key: keylen, vallen: ~var, val: vallen]
+///
+/// A range deletion is stored like a put, with the start of the range as key and its end as
+/// value.
 pub struct WriteBatch {
     entries: Vec<u8>,
 }
@@ -60,6 +63,22 @@ impl WriteBatch {
         self.set_count(c + 1);
     }
 
+    /// Marks all entries with a key in [from; to) to be deleted from the database (see
+    /// `DB::delete_range()`). Range deletions are not returned by `iter()`. A database with
+    /// `Options::leveldb_compatible` refuses to write a batch containing them.
+    pub fn delete_range(&mut self, from: &[u8], to: &[u8]) {
+        self.entries
+            .write_all(&[ValueType::TypeRangeDeletion as u8])
+            .unwrap();
+        self.entries.write_varint(from.len()).unwrap();
+        self.entries.write_all(from).unwrap();
+        self.entries.write_varint(to.len()).unwrap();
+        self.entries.write_all(to).unwrap();
+
+        let c = self.count();
+        self.set_count(c + 1);
+    }
+
     /// Clear the contents of a WriteBatch.
     pub fn clear(&mut self) {
         self.entries.clear()
@@ -86,18 +105,31 @@ impl WriteBatch {
         u64::decode_fixed(&self.entries[SEQNUM_OFFSET..SEQNUM_OFFSET + 8])
     }
 
+    /// iter returns the puts (with a value) and deletes (without) in the batch.
     pub fn iter(&self) -> WriteBatchIter {
-        WriteBatchIter {
+        WriteBatchIter(self.records())
+    }
+
+    /// has_range_deletions returns whether the batch contains a range deletion.
+    pub(crate) fn has_range_deletions(&self) -> bool {
+        self.records()
+            .any(|(typ, _, _)| typ == ValueType::TypeRangeDeletion)
+    }
+
+    /// records returns all operations in the batch, including range deletions, whose value is
+    /// the end of the range.
+    pub(crate) fn records(&self) -> Records<'_> {
+        Records {
             batch: self,
             ix: HEADER_SIZE,
         }
     }
 
     pub fn insert_into_memtable(&self, mut seq: SequenceNumber, mt: &mut MemTable) {
-        for (k, v) in self.iter() {
-            match v {
-                Some(v_) => mt.add(seq, ValueType::TypeValue, k, v_),
-                None => mt.add(seq, ValueType::TypeDeletion, k, b""),
+        for (typ, k, v) in self.records() {
+            match typ {
+                ValueType::TypeRangeDeletion => mt.delete_range(seq, k, v),
+                _ => mt.add(seq, typ, k, v),
             }
             seq += 1;
         }
@@ -109,14 +141,29 @@ impl WriteBatch {
     }
 }
 
-pub struct WriteBatchIter<'a> {
+pub struct WriteBatchIter<'a>(Records<'a>);
+
+impl<'a> Iterator for WriteBatchIter<'a> {
+    type Item = (&'a [u8], Option<&'a [u8]>);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next()? {
+                (ValueType::TypeValue, k, v) => return Some((k, Some(v))),
+                (ValueType::TypeDeletion, k, _) => return Some((k, None)),
+                (ValueType::TypeRangeDeletion, _, _) => {}
+            }
+        }
+    }
+}
+
+pub(crate) struct Records<'a> {
     batch: &'a WriteBatch,
     ix: usize,
 }
 
-/// The iterator also plays the role of the decoder.
-impl<'a> Iterator for WriteBatchIter<'a> {
-    type Item = (&'a [u8], Option<&'a [u8]>);
+/// The iterator also plays the role of the decoder. Deletions have an empty value.
+impl<'a> Iterator for Records<'a> {
+    type Item = (ValueType, &'a [u8], &'a [u8]);
     fn next(&mut self) -> Option<Self::Item> {
         if self.ix >= self.batch.entries.len() {
             return None;
@@ -130,16 +177,17 @@ impl<'a> Iterator for WriteBatchIter<'a> {
         let k = &self.batch.entries[self.ix..self.ix + klen];
         self.ix += klen;
 
-        if tag == ValueType::TypeValue as u8 {
-            let (vlen, m) = usize::decode_var(&self.batch.entries[self.ix..])?;
-            self.ix += m;
-            let v = &self.batch.entries[self.ix..self.ix + vlen];
-            self.ix += vlen;
-
-            Some((k, Some(v)))
-        } else {
-            Some((k, None))
-        }
+        let typ = match tag {
+            1 => ValueType::TypeValue,
+            2 => ValueType::TypeRangeDeletion,
+            _ => return Some((ValueType::TypeDeletion, k, &[])),
+        };
+        let (vlen, m) = usize::decode_var(&self.batch.entries[self.ix..])?;
+        self.ix += m;
+        let v = &self.batch.entries[self.ix..self.ix + vlen];
+        self.ix += vlen;
+
+        Some((typ, k, v))
     }
 }
 
@@ -187,4 +235,27 @@ mod tests {
         assert_eq!(i, 5);
         assert_eq!(b.encode(1).len(), 49);
     }
+
+    #[test]
+    fn test_write_batch_delete_range() {
+        let mut b = WriteBatch::new();
+        b.put(b"b", b"1");
+        b.delete_range(b"a", b"c");
+        b.put(b"c", b"2");
+        assert_eq!(3, b.count());
+        assert_eq!(
+            vec![(&b"b"[..], Some(&b"1"[..])), (&b"c"[..], Some(&b"2"[..]))],
+            b.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            (ValueType::TypeRangeDeletion, &b"a"[..], &b"c"[..]),
+            b.records().nth(1).unwrap()
+        );
+
+        let mut mt = MemTable::new(crate::options::for_test().cmp);
+        b.insert_into_memtable(1, &mut mt);
+        assert_eq!(Some(None), mt.get_at(b"b", 3));
+        assert_eq!(Some(Some(b"2".to_vec())), mt.get_at(b"c", 3));
+        assert_eq!(Some(Some(b"1".to_vec())), mt.get_at(b"b", 1));
+    }
 }
@@ -0,0 +1,33 @@
+//! Write stalls: conditions in which writes wait for compactions. As compactions run
+//! synchronously, a stalled write does the compaction itself before returning; a
+//! `WriteStallListener` (see `Options::write_stall_listener`) learns when the database enters or
+//! leaves such conditions, e.g. in order to shed load, and `DB::write_stall()` returns the
+//! current one.
+
+/// Why writes are delayed or stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteStallReason {
+    /// The memtable reached `Options::write_buffer_size`, and has to be compacted.
+    MemtableFull,
+    /// Level 0 contains many files, which have to be compacted into level 1.
+    Level0Files,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteStall {
+    Normal,
+    /// Writes still proceed, but the next ones will have to wait: For `MemtableFull`, the next
+    /// write compacts the memtable first. For `Level0Files`, level 0 has 8 or more files, and
+    /// level compactions will soon run regardless of `Options::defer_compactions`.
+    Delayed(WriteStallReason),
+    /// A write is waiting: For `MemtableFull`, until the memtable is compacted. For
+    /// `Level0Files`, level 0 has 12 or more files, and writes that fill the memtable wait until
+    /// it is compacted.
+    Stopped(WriteStallReason),
+}
+
+/// WriteStallListener is notified when the write stall condition changes. It is called from
+/// within the database's operations, which must not be used from it.
+pub trait WriteStallListener {
+    fn on_write_stall_change(&self, previous: WriteStall, current: WriteStall);
+}